@@ -0,0 +1,183 @@
+//!
+//! Throughput Benchmarks
+//!
+//! Measures the hot paths performance work is judged against: a single CPU's
+//! cycles per second, ticks per second of a thousand CPU cluster, Block
+//! access through the World and ECS component iteration. Each benchmark is
+//! warmed up and then run for about a second; pass names to run only the
+//! benchmarks containing them, `cargo bench -- vcpu`.
+//!
+extern crate hivemind;
+
+use hivemind::math::{Fixed, Vector2};
+use hivemind::model::component::{Position, Velocity};
+use hivemind::model::entity::EntityManager;
+use hivemind::model::material::MaterialId;
+use hivemind::model::world::{Block, Chunk, World, CHUNK_SIZE};
+use hivemind::vcpu::cluster::HiveCluster;
+use hivemind::vcpu::cpu::VCPU16;
+use std::env;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Time spent measuring each benchmark
+const MEASURE: Duration = Duration::from_secs(1);
+/// Time spent warming up each benchmark
+const WARM_UP: Duration = Duration::from_millis(200);
+
+/// Counts A up through every u16 multiplying it into B, looping forever
+const LOOP: [u16; 5] = [
+    0x8401, // SET A, 0
+    0x8802, // ADD A, 1
+    0x0024, // MUL B, A
+    0x8413, // IFN A, 0
+    0x8B81, // SET PC, 1
+];
+
+///
+/// Named benchmark, `run` is called once per iteration and returns the units
+/// of work it did
+///
+struct Bench {
+    name: &'static str,
+    unit: &'static str,
+    run: Box<dyn FnMut() -> u64>,
+}
+
+fn main() {
+    let filters: Vec<String> = env::args().skip(1).filter(|arg| !arg.starts_with("--")).collect();
+    #[allow(unused_mut)]
+    let mut benches = vec![vcpu_step(false), vcpu_step(true), cluster_tick(), block_access(), ecs_iteration()];
+    #[cfg(feature = "jit")]
+    benches.insert(2, vcpu_blocks());
+    for mut bench in benches {
+        if filters.is_empty() || filters.iter().any(|filter| bench.name.contains(filter.as_str())) {
+            measure(&mut bench);
+        }
+    }
+}
+
+fn measure(bench: &mut Bench) {
+    let started = Instant::now();
+    while started.elapsed() < WARM_UP {
+        black_box((bench.run)());
+    }
+    let mut iterations = 0u64;
+    let mut units = 0u64;
+    let started = Instant::now();
+    while started.elapsed() < MEASURE {
+        units += black_box((bench.run)());
+        iterations += 1;
+    }
+    let elapsed = started.elapsed();
+    println!(
+        "{:<16} {:>12.0} ns/iter {:>16.0} {}/s",
+        bench.name,
+        elapsed.as_nanos() as f64 / iterations as f64,
+        units as f64 / elapsed.as_secs_f64(),
+        bench.unit,
+    );
+}
+
+/// One CPU running LOOP, 1000 cycles per iteration
+fn vcpu_step(cache: bool) -> Bench {
+    let mut cpu = VCPU16::new();
+    cpu.set_decode_cache(cache);
+    cpu.load_rom(&LOOP).unwrap();
+    Bench {
+        name: if cache { "vcpu_step_cached" } else { "vcpu_step" },
+        unit: "cycles",
+        run: Box::new(move || {
+            cpu.run(1000);
+            1000
+        }),
+    }
+}
+
+/// One CPU running LOOP from translated blocks, 1000 cycles per iteration
+#[cfg(feature = "jit")]
+fn vcpu_blocks() -> Bench {
+    let mut cpu = VCPU16::new();
+    cpu.set_block_cache(true);
+    cpu.load_rom(&LOOP).unwrap();
+    Bench {
+        name: "vcpu_step_blocks",
+        unit: "cycles",
+        run: Box::new(move || {
+            cpu.run(1000);
+            1000
+        }),
+    }
+}
+
+/// A thousand free standing CPUs running LOOP, one tick per iteration
+fn cluster_tick() -> Bench {
+    let mut world = World::new();
+    let mut entities = EntityManager::new();
+    let mut cluster = HiveCluster::new();
+    for _ in 0..1000 {
+        cluster.spawn(&LOOP).unwrap();
+    }
+    Bench {
+        name: "cluster_tick",
+        unit: "ticks",
+        run: Box::new(move || {
+            cluster.tick(&mut world, &mut entities);
+            black_box(cluster.cycles());
+            1
+        }),
+    }
+}
+
+/// Every Block of a loaded Chunk set and read back through the World
+fn block_access() -> Bench {
+    let mut world = World::new();
+    world.insert_chunk(Vector2::new(0, 0), Box::new(Chunk::new()));
+    let size = CHUNK_SIZE as u64;
+    let mut material = 0;
+    Bench {
+        name: "block_access",
+        unit: "blocks",
+        run: Box::new(move || {
+            material = (material + 1) % 4;
+            let block = Block::new(MaterialId::new(material));
+            for x in 0..size {
+                for y in 0..CHUNK_SIZE {
+                    for z in 0..size {
+                        world.set_block(x, y, z, block);
+                        black_box(world.get_block(x, y, z));
+                    }
+                }
+            }
+            2 * (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as u64
+        }),
+    }
+}
+
+/// Positions of ten thousand entities advanced by their Velocities
+fn ecs_iteration() -> Bench {
+    let mut entities = EntityManager::new();
+    for index in 0..10_000 {
+        let entity = entities.create_entity();
+        entities.add_component(entity, Position::from_f64(index as f64, 0.0, 0.0));
+        if index % 2 == 0 {
+            entities.add_component(entity, Velocity::from_f64(0.0, 1.0, 0.0));
+        }
+    }
+    Bench {
+        name: "ecs_iteration",
+        unit: "entities",
+        run: Box::new(move || {
+            let moves: Vec<_> = entities.iter::<Velocity>().map(|(entity, velocity)| (entity, velocity.y)).collect();
+            let mut sum = Fixed::ZERO;
+            for &(entity, dy) in moves.iter() {
+                if let Some(position) = entities.get_component_mut::<Position>(entity) {
+                    position.y += dy;
+                    sum += position.y;
+                }
+            }
+            black_box(sum);
+            moves.len() as u64
+        }),
+    }
+}
@@ -0,0 +1,24 @@
+//!
+//! Packet Decoding
+//!
+//! The input is decoded as a single datagram and as a stream of length
+//! prefixed frames, as the server reads them from clients. Chunk packets
+//! are decoded into a Chunk as a client would.
+//!
+#![no_main]
+
+use hivemind::model::world::Chunk;
+use hivemind::net::packet::Packet;
+use hivemind::net::read_frame;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut chunk = Chunk::new();
+    if let Ok(packet) = Packet::from_bytes(data) {
+        let _ = packet.read_chunk(&mut chunk);
+    }
+    let mut stream = data;
+    while let Ok(Some(packet)) = read_frame(&mut stream) {
+        let _ = packet.read_chunk(&mut chunk);
+    }
+});
@@ -0,0 +1,31 @@
+//!
+//! Region File Reading
+//!
+//! The input is a whole region file. Its Chunks are decoded and its
+//! entities restored with the default component codecs, as a World does
+//! when their Chunk loads.
+//!
+#![no_main]
+
+use hivemind::model::entity::EntityManager;
+use hivemind::model::persist::{read_entities, ComponentCodecs};
+use hivemind::model::storage::{decode_chunk, read_region};
+use hivemind::model::world::Chunk;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let (chunks, entities) = match read_region(data.to_vec()) {
+        Ok(payloads) => payloads,
+        Err(_) => return,
+    };
+    let mut chunk = Chunk::new();
+    for payload in chunks.iter().flatten() {
+        let _ = decode_chunk(&mut &payload[..], &mut chunk);
+    }
+    let (codecs, mut manager) = (ComponentCodecs::default(), EntityManager::new());
+    for payload in entities.iter().flatten() {
+        for stored in read_entities(&mut &payload[..]).unwrap_or_default() {
+            let _ = codecs.restore(&mut manager, &stored);
+        }
+    }
+});
@@ -0,0 +1,24 @@
+//!
+//! ROM Loading
+//!
+//! The first byte picks the image format, the rest is the image. Images
+//! which parse are loaded into a CPU, and the whole input is also offered
+//! as a memory image.
+//!
+#![no_main]
+
+use hivemind::vcpu::cpu::VCPU16;
+use hivemind::vcpu::image::{Format, Image};
+use libfuzzer_sys::fuzz_target;
+
+const FORMATS: [Format; 4] = [Format::RawBigEndian, Format::RawLittleEndian, Format::IntelHex, Format::Hive];
+
+fuzz_target!(|data: &[u8]| {
+    let mut cpu = VCPU16::new();
+    let _ = cpu.load_memory(&mut &data[..]);
+    if let Some((&format, image)) = data.split_first() {
+        if let Ok(image) = Image::read(&mut &image[..], FORMATS[format as usize % FORMATS.len()]) {
+            let _ = cpu.load_image(&image);
+        }
+    }
+});
@@ -0,0 +1,96 @@
+/*
+ * Hivemind C API
+ *
+ * Declarations for the functions of src/capi.rs, built with the `capi` feature
+ * into the hivemind static and dynamic libraries. Functions return HM_OK or a
+ * negative error code, and hm_last_error gives the message of the last error
+ * on the calling thread. Keep in step with src/capi.rs; `cargo test --features
+ * capi capi` checks every function there is declared here.
+ */
+#ifndef HIVEMIND_H
+#define HIVEMIND_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define HM_OK 0
+#define HM_ERR_NULL -1
+#define HM_ERR_NOT_FOUND -2
+#define HM_ERR_FAILED -3
+
+#define HM_EVENT_BLOCK_CHANGED 0
+#define HM_EVENT_CHUNK_LOADED 1
+#define HM_EVENT_CHUNK_GENERATED 2
+#define HM_EVENT_CHUNK_UNLOADED 3
+
+typedef struct VCPU16 HmCpu;
+typedef struct HmCluster HmCluster;
+typedef struct HmWorld HmWorld;
+
+typedef struct HmCpuId {
+    uint32_t slot;
+    uint32_t generation;
+} HmCpuId;
+
+typedef struct HmEntity {
+    uint32_t slot;
+    uint32_t suffix;
+} HmEntity;
+
+/* Chunk events give the Chunk in x and z, Block changes the Block and its materials */
+typedef struct HmEvent {
+    uint32_t kind;
+    uint64_t x;
+    uint64_t y;
+    uint64_t z;
+    uint16_t before;
+    uint16_t after;
+} HmEvent;
+
+const char *hm_last_error(void);
+
+/* Lone CPUs */
+HmCpu *hm_cpu_new(void);
+void hm_cpu_free(HmCpu *cpu);
+int32_t hm_cpu_load_rom(HmCpu *cpu, const uint16_t *rom, size_t length);
+int32_t hm_cpu_run(HmCpu *cpu, uint64_t cycles);
+int32_t hm_cpu_read_memory(const HmCpu *cpu, uint16_t address, uint16_t *buffer, size_t length);
+int32_t hm_cpu_write_memory(HmCpu *cpu, uint16_t address, const uint16_t *buffer, size_t length);
+int32_t hm_cpu_registers(const HmCpu *cpu, uint16_t registers[12]);
+int32_t hm_cpu_set_register(HmCpu *cpu, uint32_t index, uint16_t value);
+bool hm_cpu_is_halted(const HmCpu *cpu);
+
+/* Clusters of CPUs */
+HmCluster *hm_cluster_new(void);
+void hm_cluster_free(HmCluster *cluster);
+int32_t hm_cluster_spawn(HmCluster *cluster, const uint16_t *rom, size_t length, HmCpuId *id);
+int32_t hm_cluster_release(HmCluster *cluster, HmCpuId id);
+int32_t hm_cluster_tick(HmCluster *cluster);
+int32_t hm_cluster_read_memory(const HmCluster *cluster, HmCpuId id, uint16_t address, uint16_t *buffer, size_t length);
+int32_t hm_cluster_write_memory(HmCluster *cluster, HmCpuId id, uint16_t address, const uint16_t *buffer, size_t length);
+
+/* Worlds and their drones */
+HmWorld *hm_world_new(void);
+void hm_world_free(HmWorld *world);
+int32_t hm_world_insert_chunk(HmWorld *world, uint64_t x, uint64_t z);
+int32_t hm_world_get_block(const HmWorld *world, uint64_t x, uint64_t y, uint64_t z, uint16_t *material);
+int32_t hm_world_set_block(HmWorld *world, uint64_t x, uint64_t y, uint64_t z, uint16_t material);
+int32_t hm_world_spawn(HmWorld *world, double x, double y, double z, const uint16_t *rom, size_t length, HmEntity *entity);
+size_t hm_world_entities(const HmWorld *world, HmEntity *entities, size_t capacity);
+int32_t hm_world_destroy(HmWorld *world, HmEntity entity);
+int32_t hm_world_position(const HmWorld *world, HmEntity entity, double position[3]);
+int32_t hm_world_read_memory(HmWorld *world, HmEntity entity, uint16_t address, uint16_t *buffer, size_t length);
+int32_t hm_world_write_memory(HmWorld *world, HmEntity entity, uint16_t address, const uint16_t *buffer, size_t length);
+uint64_t hm_world_step(HmWorld *world, uint32_t ticks);
+size_t hm_world_poll_events(HmWorld *world, HmEvent *events, size_t capacity);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,302 @@
+"""
+Hivemind for Python
+
+Wraps the C API of the hivemind library (src/capi.rs, include/hivemind.h)
+with ctypes for scripting swarm experiments and looking at their results in
+notebooks. Build the library first with
+
+    cargo build --release --features capi
+
+and point HIVEMIND_LIB at it if it isn't in target/release beside this
+directory. Failing calls raise HivemindError with the library's message.
+
+    from hivemind import Simulation, VCPU
+
+    sim = Simulation()
+    sim.world.insert_chunk(0, 0)
+    drone = sim.spawn((1.5, 1.0, 1.5), [0x8401, 0x8802])
+    sim.step(10)
+    print(sim.entities.position(drone), sim.read_memory(drone, 0, 2))
+"""
+import ctypes
+import os
+import sys
+
+HM_OK = 0
+HM_ERR_NULL = -1
+HM_ERR_NOT_FOUND = -2
+HM_ERR_FAILED = -3
+
+EVENT_KINDS = ("block_changed", "chunk_loaded", "chunk_generated", "chunk_unloaded")
+REGISTERS = ("A", "B", "C", "X", "Y", "Z", "I", "J", "PC", "SP", "EX", "IA")
+
+
+class HivemindError(Exception):
+    def __init__(self, code, message):
+        Exception.__init__(self, message)
+        self.code = code
+
+
+class CpuId(ctypes.Structure):
+    _fields_ = [("slot", ctypes.c_uint32), ("generation", ctypes.c_uint32)]
+
+
+class Entity(ctypes.Structure):
+    _fields_ = [("slot", ctypes.c_uint32), ("suffix", ctypes.c_uint32)]
+
+    def __eq__(self, other):
+        return isinstance(other, Entity) and (self.slot, self.suffix) == (other.slot, other.suffix)
+
+    def __hash__(self):
+        return hash((self.slot, self.suffix))
+
+    def __repr__(self):
+        return "Entity(%d, %d)" % (self.slot, self.suffix)
+
+
+class Event(ctypes.Structure):
+    _fields_ = [
+        ("kind", ctypes.c_uint32),
+        ("x", ctypes.c_uint64),
+        ("y", ctypes.c_uint64),
+        ("z", ctypes.c_uint64),
+        ("before", ctypes.c_uint16),
+        ("after", ctypes.c_uint16),
+    ]
+
+    def as_dict(self):
+        return {"kind": EVENT_KINDS[self.kind], "x": self.x, "y": self.y, "z": self.z,
+                "before": self.before, "after": self.after}
+
+
+def _library_path():
+    if "HIVEMIND_LIB" in os.environ:
+        return os.environ["HIVEMIND_LIB"]
+    name = {"darwin": "libhivemind.dylib", "win32": "hivemind.dll"}.get(sys.platform, "libhivemind.so")
+    return os.path.join(os.path.dirname(os.path.abspath(__file__)), "..", "target", "release", name)
+
+
+_lib = ctypes.CDLL(_library_path())
+
+_words = ctypes.POINTER(ctypes.c_uint16)
+_size = ctypes.c_size_t
+_handle = ctypes.c_void_p
+_signatures = {
+    "hm_last_error": ([], ctypes.c_char_p),
+    "hm_cpu_new": ([], _handle),
+    "hm_cpu_free": ([_handle], None),
+    "hm_cpu_load_rom": ([_handle, _words, _size], ctypes.c_int32),
+    "hm_cpu_run": ([_handle, ctypes.c_uint64], ctypes.c_int32),
+    "hm_cpu_read_memory": ([_handle, ctypes.c_uint16, _words, _size], ctypes.c_int32),
+    "hm_cpu_write_memory": ([_handle, ctypes.c_uint16, _words, _size], ctypes.c_int32),
+    "hm_cpu_registers": ([_handle, _words], ctypes.c_int32),
+    "hm_cpu_set_register": ([_handle, ctypes.c_uint32, ctypes.c_uint16], ctypes.c_int32),
+    "hm_cpu_is_halted": ([_handle], ctypes.c_bool),
+    "hm_cluster_new": ([], _handle),
+    "hm_cluster_free": ([_handle], None),
+    "hm_cluster_spawn": ([_handle, _words, _size, ctypes.POINTER(CpuId)], ctypes.c_int32),
+    "hm_cluster_release": ([_handle, CpuId], ctypes.c_int32),
+    "hm_cluster_tick": ([_handle], ctypes.c_int32),
+    "hm_cluster_read_memory": ([_handle, CpuId, ctypes.c_uint16, _words, _size], ctypes.c_int32),
+    "hm_cluster_write_memory": ([_handle, CpuId, ctypes.c_uint16, _words, _size], ctypes.c_int32),
+    "hm_world_new": ([], _handle),
+    "hm_world_free": ([_handle], None),
+    "hm_world_insert_chunk": ([_handle, ctypes.c_uint64, ctypes.c_uint64], ctypes.c_int32),
+    "hm_world_get_block": ([_handle, ctypes.c_uint64, ctypes.c_uint64, ctypes.c_uint64, _words], ctypes.c_int32),
+    "hm_world_set_block": ([_handle, ctypes.c_uint64, ctypes.c_uint64, ctypes.c_uint64, ctypes.c_uint16], ctypes.c_int32),
+    "hm_world_spawn": ([_handle, ctypes.c_double, ctypes.c_double, ctypes.c_double, _words, _size,
+                        ctypes.POINTER(Entity)], ctypes.c_int32),
+    "hm_world_entities": ([_handle, ctypes.POINTER(Entity), _size], _size),
+    "hm_world_destroy": ([_handle, Entity], ctypes.c_int32),
+    "hm_world_position": ([_handle, Entity, ctypes.POINTER(ctypes.c_double)], ctypes.c_int32),
+    "hm_world_read_memory": ([_handle, Entity, ctypes.c_uint16, _words, _size], ctypes.c_int32),
+    "hm_world_write_memory": ([_handle, Entity, ctypes.c_uint16, _words, _size], ctypes.c_int32),
+    "hm_world_step": ([_handle, ctypes.c_uint32], ctypes.c_uint64),
+    "hm_world_poll_events": ([_handle, ctypes.POINTER(Event), _size], _size),
+}
+for _name, (_arguments, _result) in _signatures.items():
+    _function = getattr(_lib, _name)
+    _function.argtypes = _arguments
+    _function.restype = _result
+
+
+def _check(code):
+    if code != HM_OK:
+        raise HivemindError(code, _lib.hm_last_error().decode("utf-8", "replace"))
+
+
+def _buffer(words):
+    words = list(words)
+    return (ctypes.c_uint16 * len(words))(*words), len(words)
+
+
+def _read(read, *arguments):
+    length = arguments[-1]
+    buffer = (ctypes.c_uint16 * length)()
+    _check(read(*(arguments[:-1] + (buffer, length))))
+    return list(buffer)
+
+
+class VCPU(object):
+    """Lone VCPU16 with no devices attached"""
+
+    def __init__(self, rom=None):
+        self._handle = _lib.hm_cpu_new()
+        if rom is not None:
+            self.load(rom)
+
+    def __del__(self):
+        if getattr(self, "_handle", None):
+            _lib.hm_cpu_free(self._handle)
+            self._handle = None
+
+    def load(self, rom):
+        """Reset and load `rom` at address 0."""
+        buffer, length = _buffer(rom)
+        _check(_lib.hm_cpu_load_rom(self._handle, buffer, length))
+
+    def run(self, cycles):
+        _check(_lib.hm_cpu_run(self._handle, cycles))
+
+    def read_memory(self, address, length):
+        return _read(_lib.hm_cpu_read_memory, self._handle, address, length)
+
+    def write_memory(self, address, words):
+        buffer, length = _buffer(words)
+        _check(_lib.hm_cpu_write_memory(self._handle, address, buffer, length))
+
+    @property
+    def registers(self):
+        """Registers by name, A to IA."""
+        buffer = (ctypes.c_uint16 * 12)()
+        _check(_lib.hm_cpu_registers(self._handle, buffer))
+        return dict(zip(REGISTERS, buffer))
+
+    def set_register(self, name, value):
+        _check(_lib.hm_cpu_set_register(self._handle, REGISTERS.index(name), value))
+
+    @property
+    def halted(self):
+        return _lib.hm_cpu_is_halted(self._handle)
+
+
+class Cluster(object):
+    """CPUs ticked together without a world"""
+
+    def __init__(self):
+        self._handle = _lib.hm_cluster_new()
+
+    def __del__(self):
+        if getattr(self, "_handle", None):
+            _lib.hm_cluster_free(self._handle)
+            self._handle = None
+
+    def spawn(self, rom):
+        buffer, length = _buffer(rom)
+        id = CpuId()
+        _check(_lib.hm_cluster_spawn(self._handle, buffer, length, ctypes.byref(id)))
+        return id
+
+    def release(self, id):
+        _check(_lib.hm_cluster_release(self._handle, id))
+
+    def tick(self, ticks=1):
+        for _ in range(ticks):
+            _check(_lib.hm_cluster_tick(self._handle))
+
+    def read_memory(self, id, address, length):
+        return _read(_lib.hm_cluster_read_memory, self._handle, id, address, length)
+
+    def write_memory(self, id, address, words):
+        buffer, length = _buffer(words)
+        _check(_lib.hm_cluster_write_memory(self._handle, id, address, buffer, length))
+
+
+class World(object):
+    """Blocks of a Simulation's World"""
+
+    def __init__(self, simulation):
+        self._simulation = simulation
+
+    def insert_chunk(self, x, z):
+        """Put an empty Chunk at Chunk position x, z."""
+        _check(_lib.hm_world_insert_chunk(self._simulation._handle, x, z))
+
+    def get_block(self, x, y, z):
+        """Material id of a Block, None if its Chunk isn't loaded."""
+        material = ctypes.c_uint16()
+        code = _lib.hm_world_get_block(self._simulation._handle, x, y, z, ctypes.byref(material))
+        if code == HM_ERR_NOT_FOUND:
+            return None
+        _check(code)
+        return material.value
+
+    def set_block(self, x, y, z, material):
+        _check(_lib.hm_world_set_block(self._simulation._handle, x, y, z, material))
+
+
+class EntityManager(object):
+    """Entities of a Simulation"""
+
+    def __init__(self, simulation):
+        self._simulation = simulation
+
+    def __iter__(self):
+        return iter(self.list())
+
+    def __len__(self):
+        return _lib.hm_world_entities(self._simulation._handle, None, 0)
+
+    def list(self):
+        """Live entities in slot order."""
+        count = len(self)
+        entities = (Entity * count)()
+        _lib.hm_world_entities(self._simulation._handle, entities, count)
+        return list(entities)
+
+    def destroy(self, entity):
+        _check(_lib.hm_world_destroy(self._simulation._handle, entity))
+
+    def position(self, entity):
+        position = (ctypes.c_double * 3)()
+        _check(_lib.hm_world_position(self._simulation._handle, entity, position))
+        return tuple(position)
+
+
+class Simulation(object):
+    """Simulation of an initially empty World"""
+
+    def __init__(self):
+        self._handle = _lib.hm_world_new()
+        self.world = World(self)
+        self.entities = EntityManager(self)
+
+    def __del__(self):
+        if getattr(self, "_handle", None):
+            _lib.hm_world_free(self._handle)
+            self._handle = None
+
+    def spawn(self, position, rom):
+        """Place a drone at an (x, y, z) position running `rom`."""
+        buffer, length = _buffer(rom)
+        entity = Entity()
+        x, y, z = position
+        _check(_lib.hm_world_spawn(self._handle, x, y, z, buffer, length, ctypes.byref(entity)))
+        return entity
+
+    def step(self, ticks=1):
+        """Run `ticks` ticks, returning the tick reached."""
+        return _lib.hm_world_step(self._handle, ticks)
+
+    def read_memory(self, entity, address, length):
+        return _read(_lib.hm_world_read_memory, self._handle, entity, address, length)
+
+    def write_memory(self, entity, address, words):
+        buffer, length = _buffer(words)
+        _check(_lib.hm_world_write_memory(self._handle, entity, address, buffer, length))
+
+    def events(self, limit=1024):
+        """World events since the last call, as dicts, oldest first."""
+        buffer = (Event * limit)()
+        count = _lib.hm_world_poll_events(self._handle, buffer, limit)
+        return [buffer[index].as_dict() for index in range(count)]
@@ -0,0 +1,566 @@
+///
+/// Remote Administration
+///
+/// Admin commands let a server operator step into a misbehaving hive: list
+/// CPUs, pause or interrupt one, edit a Block, paste a blueprint or dump a
+/// CPU's state. Commands are plain text lines so the same parser serves the
+/// server console, scripts and network sessions, which must log in with the
+/// server's admin secret before any command is run.
+///
+/// ```text
+/// cpus
+/// pause <entity>              resume <entity>
+/// interrupt <entity> <message>
+/// set <x> <y> <z> <material>     block <x> <y> <z>
+/// spawn <blueprint> <x> <y> <z>
+/// trace <entity>
+/// at <tick> cpus|trace <entity>|block <x> <y> <z>
+/// entities [<component>|#<label>]...
+/// inspect <entity>
+/// edit <entity> <component>.<field> <value>
+/// watch <entity> <component>     unwatch <entity> <component>
+/// ```
+///
+/// Entities are written `<slot>:<suffix>`. `at` runs an inspection command
+/// against the Simulation rebuilt as it was at a past tick, which needs a
+/// History set on it, see `history`.
+///
+/// The entity inspector reaches Components by the names they are registered
+/// under in the Simulation's ComponentRegistry, see `model::reflect`:
+/// `entities` lists those having every Component and label given, `inspect`
+/// dumps one, and `edit` sets a field from text. A watched Component is
+/// reported by `poll`, which the server calls after each tick, whenever it
+/// has changed since it was last reported.
+///
+use model::entity::EntityID;
+use model::reflect::ReflectError;
+use model::structure::{Placement, Structure, StructureError};
+use model::update::BlockPosition;
+use model::world::Block;
+use net::packet::Packet;
+use simulation::Simulation;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::Path;
+use vcpu::cluster::{CpuComponent, CpuId, PowerState};
+use vcpu::cpu::VCPU16;
+
+/// Memory words shown after PC by `trace`
+pub const TRACE_WORDS: u16 = 8;
+
+///
+/// Admin Command
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AdminCommand {
+    ListCpus,
+    Pause(EntityID),
+    Resume(EntityID),
+    Interrupt { entity: EntityID, message: u16 },
+    SetBlock { position: BlockPosition, material: String },
+    Spawn { blueprint: String, origin: BlockPosition },
+    Trace(EntityID),
+    GetBlock(BlockPosition),
+    /// Inspection at a past tick
+    At { tick: u64, command: Box<AdminCommand> },
+    /// Entities with every Component and `#label` listed
+    Entities(Vec<String>),
+    Inspect(EntityID),
+    Edit { entity: EntityID, component: String, field: String, value: String },
+    Watch { entity: EntityID, component: String },
+    Unwatch { entity: EntityID, component: String },
+}
+
+///
+/// Admin Command Failure
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AdminError {
+    /// The session hasn't logged in
+    Denied,
+    Usage(&'static str),
+    UnknownCommand(String),
+    NoCpu(EntityID),
+    UnknownMaterial(String),
+    UnknownBlueprint(String),
+    Unloaded(BlockPosition),
+    Structure(StructureError),
+    /// The tick is outside the Simulation's History
+    NotInHistory(u64),
+    Rewind(String),
+    NoEntity(EntityID),
+    Reflect(ReflectError),
+    NotWatched { entity: EntityID, component: String },
+}
+
+impl fmt::Display for AdminError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AdminError::Denied => write!(f, "not logged in"),
+            AdminError::Usage(usage) => write!(f, "usage: {}", usage),
+            AdminError::UnknownCommand(ref name) => write!(f, "unknown command {}", name),
+            AdminError::NoCpu(entity) => write!(f, "entity {} has no cpu", format_entity(entity)),
+            AdminError::UnknownMaterial(ref name) => write!(f, "unknown material {}", name),
+            AdminError::UnknownBlueprint(ref name) => write!(f, "unknown blueprint {}", name),
+            AdminError::Unloaded(position) => write!(f, "block {:?} is not loaded", position),
+            AdminError::Structure(ref error) => write!(f, "{}", error),
+            AdminError::NotInHistory(tick) => write!(f, "tick {} is not in history", tick),
+            AdminError::Rewind(ref error) => write!(f, "unable to rewind: {}", error),
+            AdminError::NoEntity(entity) => write!(f, "entity {} does not exist", format_entity(entity)),
+            AdminError::Reflect(ref error) => write!(f, "{}", error),
+            AdminError::NotWatched { entity, ref component } => write!(f, "{} of {} is not watched", component, format_entity(entity)),
+        }
+    }
+}
+
+impl AdminCommand {
+    ///
+    /// Parse one command line.
+    ///
+    pub fn parse(line: &str) -> Result<AdminCommand, AdminError> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let entity = |usage| words.get(1).and_then(|word| parse_entity(word)).ok_or(AdminError::Usage(usage));
+        let number = |index: usize, usage| words.get(index).and_then(|word| word.parse().ok()).ok_or(AdminError::Usage(usage));
+        Ok(match words.first().cloned() {
+            Some("cpus") => AdminCommand::ListCpus,
+            Some("pause") => AdminCommand::Pause(entity("pause <entity>")?),
+            Some("resume") => AdminCommand::Resume(entity("resume <entity>")?),
+            Some("interrupt") => {
+                let usage = "interrupt <entity> <message>";
+                AdminCommand::Interrupt { entity: entity(usage)?, message: number(2, usage)? as u16 }
+            }
+            Some("set") => {
+                let usage = "set <x> <y> <z> <material>";
+                let position = (number(1, usage)?, number(2, usage)? as usize, number(3, usage)?);
+                let material = words.get(4).ok_or(AdminError::Usage(usage))?.to_string();
+                AdminCommand::SetBlock { position, material }
+            }
+            Some("spawn") => {
+                let usage = "spawn <blueprint> <x> <y> <z>";
+                let blueprint = words.get(1).ok_or(AdminError::Usage(usage))?.to_string();
+                AdminCommand::Spawn { blueprint, origin: (number(2, usage)?, number(3, usage)? as usize, number(4, usage)?) }
+            }
+            Some("trace") => AdminCommand::Trace(entity("trace <entity>")?),
+            Some("block") => {
+                let usage = "block <x> <y> <z>";
+                AdminCommand::GetBlock((number(1, usage)?, number(2, usage)? as usize, number(3, usage)?))
+            }
+            Some("at") => {
+                let usage = "at <tick> cpus|trace <entity>|block <x> <y> <z>";
+                let tick = number(1, usage)?;
+                let rest = words.get(2..).map(|rest| rest.join(" ")).unwrap_or_default();
+                match AdminCommand::parse(&rest) {
+                    Ok(command @ AdminCommand::ListCpus) | Ok(command @ AdminCommand::Trace(_)) | Ok(command @ AdminCommand::GetBlock(_)) => {
+                        AdminCommand::At { tick, command: Box::new(command) }
+                    }
+                    _ => return Err(AdminError::Usage(usage)),
+                }
+            }
+            Some("entities") => AdminCommand::Entities(words[1..].iter().map(|word| word.to_string()).collect()),
+            Some("inspect") => AdminCommand::Inspect(entity("inspect <entity>")?),
+            Some("edit") => {
+                let usage = "edit <entity> <component>.<field> <value>";
+                let mut path = words.get(2).ok_or(AdminError::Usage(usage))?.splitn(2, '.');
+                let (component, field) = match (path.next(), path.next()) {
+                    (Some(component), Some(field)) if !component.is_empty() && !field.is_empty() => (component.to_string(), field.to_string()),
+                    _ => return Err(AdminError::Usage(usage)),
+                };
+                let value = words.get(3..).filter(|value| !value.is_empty()).ok_or(AdminError::Usage(usage))?.join(" ");
+                AdminCommand::Edit { entity: entity(usage)?, component, field, value }
+            }
+            Some(name @ "watch") | Some(name @ "unwatch") => {
+                let usage = if name == "watch" { "watch <entity> <component>" } else { "unwatch <entity> <component>" };
+                let (entity, component) = (entity(usage)?, words.get(2).ok_or(AdminError::Usage(usage))?.to_string());
+                if name == "watch" { AdminCommand::Watch { entity, component } } else { AdminCommand::Unwatch { entity, component } }
+            }
+            Some(other) => return Err(AdminError::UnknownCommand(other.to_string())),
+            None => return Err(AdminError::Usage("cpus, pause, resume, interrupt, set, spawn, trace, block, at, entities, inspect, edit, watch or unwatch")),
+        })
+    }
+}
+
+pub fn format_entity(entity: EntityID) -> String { format!("{}:{}", entity.slot(), entity.suffix()) }
+
+pub fn parse_entity(text: &str) -> Option<EntityID> {
+    let mut parts = text.splitn(2, ':');
+    let slot = parts.next()?.parse().ok()?;
+    let suffix = parts.next()?.parse().ok()?;
+    Some(EntityID::new(slot, suffix))
+}
+
+///
+/// Admin Command Handler
+///
+#[derive(Clone, Default, Debug)]
+pub struct Admin {
+    /// Network sessions are refused while empty
+    secret: String,
+    blueprints: HashMap<String, Structure>,
+    /// Watched Components, as last reported
+    watches: BTreeMap<(EntityID, String), String>,
+}
+
+impl Admin {
+    pub fn new(secret: &str) -> Admin { Admin { secret: secret.to_string(), ..Admin::default() } }
+    /// Check a login secret without bailing out at the first wrong byte.
+    pub fn authenticate(&self, secret: &str) -> bool {
+        !self.secret.is_empty() && self.secret.len() == secret.len()
+            && self.secret.bytes().zip(secret.bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+    }
+    pub fn add_blueprint(&mut self, name: &str, blueprint: Structure) -> Option<Structure> {
+        self.blueprints.insert(name.to_string(), blueprint)
+    }
+    pub fn blueprint(&self, name: &str) -> Option<&Structure> { self.blueprints.get(name) }
+    ///
+    /// Load every structure file in a directory as a blueprint named by its
+    /// file stem, returns the number loaded.
+    ///
+    pub fn load_blueprints<P: AsRef<Path>>(&mut self, directory: P) -> io::Result<usize> {
+        let mut loaded = 0;
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) if path.is_file() => name.to_string(),
+                _ => continue,
+            };
+            let blueprint = Structure::load(&mut BufReader::new(File::open(&path)?))?;
+            self.add_blueprint(&name, blueprint);
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+    ///
+    /// Run a command, returning the text to show the operator.
+    ///
+    pub fn execute(&mut self, simulation: &mut Simulation, command: &AdminCommand) -> Result<String, AdminError> {
+        match *command {
+            AdminCommand::ListCpus => {
+                let cluster = simulation.cluster();
+                let lines: Vec<String> = cluster.ids().into_iter().map(|id| {
+                    let owner = cluster.owner(id).map_or("-".to_string(), format_entity);
+                    let cpu = cluster.get(id).unwrap();
+                    let state = match cluster.power_state(id) {
+                        _ if cluster.is_paused(id) => "paused",
+                        Some(PowerState::Off) => "off",
+                        Some(PowerState::Booting) => "booting",
+                        _ if cluster.is_hibernated(id) => "hibernated",
+                        _ => cpu_state(cpu),
+                    };
+                    format!("cpu {}.{} entity {} pc {:#06x} {}", id.slot(), id.generation(), owner, cpu.get_pc(), state)
+                }).collect();
+                Ok(if lines.is_empty() { "no cpus".to_string() } else { lines.join("\n") })
+            }
+            AdminCommand::Pause(entity) => {
+                let cpu = cpu_of(simulation, entity)?;
+                simulation.cluster_mut().pause(cpu);
+                Ok(format!("paused {}", format_entity(entity)))
+            }
+            AdminCommand::Resume(entity) => {
+                let cpu = cpu_of(simulation, entity)?;
+                simulation.cluster_mut().resume(cpu);
+                Ok(format!("resumed {}", format_entity(entity)))
+            }
+            AdminCommand::Interrupt { entity, message } => {
+                let cpu = cpu_of(simulation, entity)?;
+                simulation.cluster_mut().get_mut(cpu).unwrap().interrupt(message);
+                Ok(format!("interrupted {} with {:#06x}", format_entity(entity), message))
+            }
+            AdminCommand::SetBlock { position, ref material } => {
+                let world = simulation.world_mut();
+                let id = world.materials().id(material).ok_or_else(|| AdminError::UnknownMaterial(material.clone()))?;
+                let (x, y, z) = position;
+                if !world.set_block(x, y, z, Block::new(id)) {
+                    return Err(AdminError::Unloaded(position));
+                }
+                Ok(format!("set {} {} {} to {}", x, y, z, material))
+            }
+            AdminCommand::Spawn { ref blueprint, origin } => {
+                let structure = self.blueprint(blueprint).ok_or_else(|| AdminError::UnknownBlueprint(blueprint.clone()))?;
+                let undo = structure.paste(simulation.world_mut(), origin, &Placement::default()).map_err(AdminError::Structure)?;
+                Ok(format!("spawned {} at {:?}, {} blocks", blueprint, origin, undo.len()))
+            }
+            AdminCommand::Trace(entity) => {
+                let cpu = cpu_of(simulation, entity)?;
+                let cpu = simulation.cluster().get(cpu).unwrap();
+                let pc = cpu.get_pc();
+                let words: Vec<String> = (0..TRACE_WORDS).map(|offset| format!("{:04x}", cpu.get_memory(pc.wrapping_add(offset)))).collect();
+                Ok(format!(
+                    "{} {}, {} interrupts queued\n\
+                     a {:04x} b {:04x} c {:04x} x {:04x} y {:04x} z {:04x} i {:04x} j {:04x}\n\
+                     pc {:04x} sp {:04x} ex {:04x} ia {:04x}\n\
+                     [pc] {}",
+                    format_entity(entity), cpu_state(cpu), cpu.pending_interrupts(),
+                    cpu.get_a(), cpu.get_b(), cpu.get_c(), cpu.get_x(), cpu.get_y(), cpu.get_z(), cpu.get_i(), cpu.get_j(),
+                    pc, cpu.get_sp(), cpu.get_ex(), cpu.get_ia(),
+                    words.join(" "),
+                ))
+            }
+            AdminCommand::GetBlock(position) => {
+                let world = simulation.world();
+                let (x, y, z) = position;
+                let block = world.get_block(x, y, z).ok_or(AdminError::Unloaded(position))?;
+                let name = world.materials().get(block.material()).map_or("unknown", |material| material.name());
+                Ok(format!("block {} {} {} is {}", x, y, z, name))
+            }
+            AdminCommand::At { tick, ref command } => {
+                let mut past = simulation.rewind(tick).map_err(|error| AdminError::Rewind(error.to_string()))?.ok_or(AdminError::NotInHistory(tick))?;
+                Ok(format!("at tick {}: {}", tick, self.execute(&mut past, command)?))
+            }
+            AdminCommand::Entities(ref terms) => {
+                let (entities, registry) = (simulation.entities(), simulation.reflection());
+                if let Some(term) = terms.iter().find(|term| !term.starts_with('#') && !registry.is_registered(term)) {
+                    return Err(AdminError::Reflect(ReflectError::UnknownComponent(term.clone())));
+                }
+                let lines: Vec<String> = entities.entities().iter().filter_map(|entity| {
+                    let components = registry.components_of(entities, entity);
+                    let matches = terms.iter().all(|term| match term.strip_prefix('#') {
+                        Some(label) => entities.has_label(entity, label),
+                        None => components.contains(&&term[..]),
+                    });
+                    if !matches {
+                        return None;
+                    }
+                    let mut line = vec![format_entity(entity)];
+                    line.extend(components.iter().map(|name| name.to_string()));
+                    line.extend(entities.labels(entity).map(|label| format!("#{}", label)));
+                    if entities.is_disabled(entity) {
+                        line.push("(disabled)".to_string());
+                    }
+                    Some(line.join(" "))
+                }).collect();
+                Ok(if lines.is_empty() { "no entities".to_string() } else { lines.join("\n") })
+            }
+            AdminCommand::Inspect(entity) => {
+                let entities = simulation.entities();
+                if !entities.is_alive(entity) {
+                    return Err(AdminError::NoEntity(entity));
+                }
+                let mut header = vec![format_entity(entity)];
+                header.extend(entities.labels(entity).map(|label| format!("#{}", label)));
+                if entities.is_disabled(entity) {
+                    header.push("(disabled)".to_string());
+                }
+                let components = simulation.reflection().describe(entities, entity);
+                Ok(if components.is_empty() { header.join(" ") } else { format!("{}\n{}", header.join(" "), components) })
+            }
+            AdminCommand::Edit { entity, ref component, ref field, ref value } => {
+                simulation.set_field(entity, component, field, value).map_err(AdminError::Reflect)?;
+                let value = simulation.reflection().get(simulation.entities(), entity, component, field).map_err(AdminError::Reflect)?;
+                Ok(format!("{} {}.{} = {}", format_entity(entity), component, field, value))
+            }
+            AdminCommand::Watch { entity, ref component } => {
+                let current = simulation.reflection().describe_component(simulation.entities(), entity, component).map_err(AdminError::Reflect)?;
+                self.watches.insert((entity, component.clone()), current.clone());
+                Ok(format!("watching {} {}", format_entity(entity), current))
+            }
+            AdminCommand::Unwatch { entity, ref component } => {
+                match self.watches.remove(&(entity, component.clone())) {
+                    Some(_) => Ok(format!("stopped watching {} of {}", component, format_entity(entity))),
+                    None => Err(AdminError::NotWatched { entity, component: component.clone() }),
+                }
+            }
+        }
+    }
+    ///
+    /// Lines reporting each watched Component which has changed since it
+    /// was last reported. Watches of Components which are gone report that
+    /// once and end.
+    ///
+    pub fn poll(&mut self, simulation: &Simulation) -> Vec<String> {
+        let mut lines = Vec::new();
+        let tick = simulation.tick();
+        self.watches.retain(|&(entity, ref component), last| {
+            match simulation.reflection().describe_component(simulation.entities(), entity, component) {
+                Ok(ref current) if current == last => true,
+                Ok(current) => {
+                    lines.push(format!("tick {}: {} {}", tick, format_entity(entity), current));
+                    *last = current;
+                    true
+                }
+                Err(error) => {
+                    lines.push(format!("tick {}: {}, no longer watching", tick, error));
+                    false
+                }
+            }
+        });
+        lines
+    }
+    pub fn watching(&self) -> usize { self.watches.len() }
+    /// Parse and run one command line.
+    pub fn run(&mut self, simulation: &mut Simulation, line: &str) -> Result<String, AdminError> {
+        self.execute(simulation, &AdminCommand::parse(line)?)
+    }
+    ///
+    /// Run a script of command lines, skipping blanks and `#` comments.
+    /// Stops at the first failure, returning its line number.
+    ///
+    pub fn script(&mut self, simulation: &mut Simulation, script: &str) -> Result<Vec<String>, (usize, AdminError)> {
+        let mut replies = Vec::new();
+        for (number, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            replies.push(self.run(simulation, line).map_err(|error| (number + 1, error))?);
+        }
+        Ok(replies)
+    }
+}
+
+fn cpu_of(simulation: &Simulation, entity: EntityID) -> Result<CpuId, AdminError> {
+    simulation.entities().get_component::<CpuComponent>(entity)
+        .map(|component| component.cpu)
+        .filter(|&cpu| simulation.cluster().contains(cpu))
+        .ok_or(AdminError::NoCpu(entity))
+}
+
+fn cpu_state(cpu: &VCPU16) -> &'static str {
+    if cpu.is_halted() {
+        "halted"
+    } else if cpu.is_hibernating() {
+        "hibernating"
+    } else if cpu.is_sleeping() {
+        "sleeping"
+    } else {
+        "running"
+    }
+}
+
+///
+/// Admin State of one network connection
+///
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct AdminSession {
+    authenticated: bool,
+}
+
+impl AdminSession {
+    pub fn new() -> AdminSession { AdminSession::default() }
+    pub fn is_authenticated(&self) -> bool { self.authenticated }
+    ///
+    /// Answer an AdminLogin or Admin packet, None for any other packet.
+    ///
+    pub fn handle(&mut self, admin: &mut Admin, simulation: &mut Simulation, packet: &Packet) -> Option<Packet> {
+        let result = match *packet {
+            Packet::AdminLogin { ref secret } => {
+                self.authenticated = admin.authenticate(secret);
+                if self.authenticated { Ok("logged in".to_string()) } else { Err(AdminError::Denied) }
+            }
+            Packet::Admin { .. } if !self.authenticated => Err(AdminError::Denied),
+            Packet::Admin { ref line } => admin.run(simulation, line),
+            _ => return None,
+        };
+        Some(match result {
+            Ok(text) => Packet::AdminReply { ok: true, text },
+            Err(error) => Packet::AdminReply { ok: false, text: error.to_string() },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Admin, AdminCommand, AdminError, AdminSession};
+    use history::History;
+    use model::component::{Position, Velocity};
+    use model::entity::{EntityID, EntityManager};
+    use model::material::Material;
+    use model::structure::Structure;
+    use model::world::{Chunk, Vector2, World};
+    use net::packet::Packet;
+    use pool::Poolable;
+    use simulation::Simulation;
+
+    #[test]
+    pub fn test_admin_commands() {
+        let mut world = World::new();
+        let stone = world.materials_mut().register(Material::new("stone", 1.0, 1.0));
+        world.insert_chunk(Vector2::new(0, 0), Chunk::allocate());
+        let mut simulation = Simulation::new(world, EntityManager::new());
+        let drone = simulation.entities_mut().create_entity();
+        let cpu = simulation.attach(drone, &[]).unwrap();
+
+        let mut admin = Admin::new("hunter2");
+        assert_eq!(AdminCommand::parse("interrupt 0:0 7"), Ok(AdminCommand::Interrupt { entity: drone, message: 7 }));
+        assert_eq!(AdminCommand::parse("pause"), Err(AdminError::Usage("pause <entity>")));
+
+        // A paused CPU stays put until resumed
+        assert_eq!(admin.run(&mut simulation, "pause 0:0").unwrap(), "paused 0:0");
+        simulation.step();
+        assert_eq!(simulation.cluster().get(cpu).unwrap().get_pc(), 0);
+        assert!(admin.run(&mut simulation, "cpus").unwrap().ends_with("pc 0x0000 paused"));
+        assert_eq!(admin.run(&mut simulation, "pause 4:0"), Err(AdminError::NoCpu(EntityID::new(4, 0))));
+
+        // Scripts stop at the first failing line
+        let script = "# restore the hive\nset 1 2 3 stone\nresume 0:0\n\ntrace 0:0\nspawn tower 0 0 0\ncpus";
+        assert_eq!(admin.script(&mut simulation, script).unwrap_err(), (6, AdminError::UnknownBlueprint("tower".to_string())));
+        assert_eq!(simulation.world().get_block(1, 2, 3).unwrap().material(), stone);
+        assert!(!simulation.cluster().is_paused(cpu));
+
+        let tower = Structure::copy(simulation.world(), (1, 2, 3), (1, 3, 3)).unwrap();
+        admin.add_blueprint("tower", tower);
+        let replies = admin.script(&mut simulation, "spawn tower 5 0 5\ntrace 0:0").unwrap();
+        assert_eq!(replies[0], "spawned tower at (5, 0, 5), 2 blocks");
+        assert!(replies[1].starts_with("0:0 running, 0 interrupts queued"));
+        assert_eq!(simulation.world().get_block(5, 0, 5).unwrap().material(), stone);
+
+        // Past Blocks come from the History
+        assert_eq!(admin.run(&mut simulation, "at 0 block 5 0 5"), Err(AdminError::NotInHistory(0)));
+        simulation.set_history(Some(History::new(4, 2)));
+        let before = simulation.tick();
+        admin.run(&mut simulation, "set 5 0 5 air").unwrap();
+        simulation.step();
+        assert_eq!(admin.run(&mut simulation, &format!("at {} block 5 0 5", before)).unwrap(), format!("at tick {}: block 5 0 5 is stone", before));
+        assert_eq!(admin.run(&mut simulation, "block 5 0 5").unwrap(), "block 5 0 5 is air");
+        assert_eq!(AdminCommand::parse("at 3 set 1 2 3 stone"), Err(AdminError::Usage("at <tick> cpus|trace <entity>|block <x> <y> <z>")));
+
+        // Network sessions must log in first
+        let mut session = AdminSession::new();
+        let command = Packet::Admin { line: "cpus".to_string() };
+        assert_eq!(session.handle(&mut admin, &mut simulation, &command), Some(Packet::AdminReply { ok: false, text: "not logged in".to_string() }));
+        let login = |secret: &str| Packet::AdminLogin { secret: secret.to_string() };
+        assert!(matches!(session.handle(&mut admin, &mut simulation, &login("hunter3")), Some(Packet::AdminReply { ok: false, .. })));
+        assert!(matches!(session.handle(&mut admin, &mut simulation, &login("hunter2")), Some(Packet::AdminReply { ok: true, .. })));
+        assert!(matches!(session.handle(&mut admin, &mut simulation, &command), Some(Packet::AdminReply { ok: true, .. })));
+        assert_eq!(session.handle(&mut admin, &mut simulation, &Packet::Ack { tick: 1 }), None);
+        assert!(!Admin::new("").authenticate(""));
+    }
+
+    #[test]
+    pub fn test_entity_inspector() {
+        let mut simulation = Simulation::new(World::new(), EntityManager::new());
+        let drone = simulation.entities_mut().create_entity();
+        simulation.entities_mut().add_component(drone, Position::from_f64(1.0, 2.0, 3.0));
+        simulation.entities_mut().add_component(drone, Velocity::from_f64(0.0, 0.0, 0.0));
+        simulation.entities_mut().add_label(drone, "scout");
+        let rock = simulation.entities_mut().create_entity();
+        simulation.entities_mut().add_component(rock, Position::from_f64(9.0, 0.0, 9.0));
+        simulation.entities_mut().disable(rock);
+        let mut admin = Admin::default();
+
+        assert_eq!(admin.run(&mut simulation, "entities position").unwrap(), "0:0 position velocity #scout\n1:1 position (disabled)");
+        assert_eq!(admin.run(&mut simulation, "entities velocity #scout").unwrap(), "0:0 position velocity #scout");
+        assert_eq!(admin.run(&mut simulation, "entities #queen").unwrap(), "no entities");
+        assert_eq!(admin.run(&mut simulation, "entities heat").unwrap_err().to_string(), "unknown component heat");
+        assert_eq!(admin.run(&mut simulation, "inspect 0:0").unwrap(), "0:0 #scout\nposition { x: 1, y: 2, z: 3 }\nvelocity { x: 0, y: 0, z: 0 }");
+        assert_eq!(admin.run(&mut simulation, "inspect 5:0"), Err(AdminError::NoEntity(EntityID::new(5, 0))));
+
+        // Fields are set by path and checked against their kind
+        assert_eq!(admin.run(&mut simulation, "edit 0:0 position.y 4.5").unwrap(), "0:0 position.y = 4.5");
+        assert_eq!(admin.run(&mut simulation, "edit 0:0 position.y high").unwrap_err().to_string(), "y takes a fixed");
+        assert_eq!(AdminCommand::parse("edit 0:0 position 4"), Err(AdminError::Usage("edit <entity> <component>.<field> <value>")));
+
+        // Watched components are reported once per change, until they go
+        assert_eq!(admin.run(&mut simulation, "watch 0:0 velocity").unwrap(), "watching 0:0 velocity { x: 0, y: 0, z: 0 }");
+        assert!(admin.poll(&simulation).is_empty());
+        admin.run(&mut simulation, "edit 0:0 velocity.x 2").unwrap();
+        simulation.step();
+        assert_eq!(admin.poll(&simulation), vec![format!("tick {}: 0:0 velocity {{ x: 2, y: 0, z: 0 }}", simulation.tick())]);
+        assert!(admin.poll(&simulation).is_empty());
+        simulation.entities_mut().destroy_entity(drone);
+        assert_eq!(admin.poll(&simulation), vec![format!("tick {}: entity 0:0 has no velocity, no longer watching", simulation.tick())]);
+        assert_eq!(admin.watching(), 0);
+        assert_eq!(admin.run(&mut simulation, "unwatch 0:0 velocity"), Err(AdminError::NotWatched { entity: drone, component: "velocity".to_string() }));
+    }
+}
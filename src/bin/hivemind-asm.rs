@@ -0,0 +1,411 @@
+//!
+//! Hivemind Toolchain
+//!
+//! The assembler, disassembler and VCPU16 from the command line. Programs are
+//! HiveC (`.hc`), assembly (`.asm`, `.s`), `.hive` images, Intel HEX (`.hex`)
+//! or raw big-endian words.
+//!
+//! ```text
+//! hivemind-asm build <source> [-o <image>] [--map <file>]
+//! hivemind-asm dump <program> [--from <address>] [--count <instructions>]
+//! hivemind-asm run <program> [--cycles <count>] [--trace] [--serial]
+//! hivemind-asm test <program> <expected state>...
+//! ```
+//!
+//! `build` writes the image in the format of the output's extension, `.hive`
+//! by default. `run` stops when the CPU halts or hibernates, tracing every
+//! instruction before it runs with `--trace` and wiring a serial device to
+//! stdin and stdout with `--serial`. `test` runs the program afresh for each
+//! expected state file and compares the CPU with it; each line of one is
+//!
+//! ```text
+//! cycles 5000        ; cycles to run, default 10000
+//! state halted       ; running, sleeping, hibernating or halted
+//! A 3                ; a register A to J, PC, SP, EX or IA
+//! [0x8000] 1 2 'c'   ; words of memory from an address
+//! ```
+//!
+
+extern crate hivemind;
+
+use hivemind::devices::serial::Serial;
+use hivemind::devices::Device;
+use hivemind::vcpu::asm::{assemble, number, SourceMap, REGISTERS};
+use hivemind::vcpu::cpu::VCPU16;
+use hivemind::vcpu::disasm::disassemble;
+use hivemind::vcpu::hivec;
+use hivemind::vcpu::image::{Format, Image};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Write};
+use std::path::Path;
+use std::process;
+
+/// Cycles run when none are given
+const DEFAULT_CYCLES: u64 = 10_000;
+
+const USAGE: &str = "usage: hivemind-asm build <source> [-o <image>] [--map <file>]
+       hivemind-asm dump <program> [--from <address>] [--count <instructions>]
+       hivemind-asm run <program> [--cycles <count>] [--trace] [--serial]
+       hivemind-asm test <program> <expected state>...";
+
+///
+/// Subcommand and its options
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Command {
+    Build { source: String, output: Option<String>, map: Option<String> },
+    Dump { program: String, from: Option<u16>, count: Option<usize> },
+    Run { program: String, cycles: u64, trace: bool, serial: bool },
+    Test { program: String, expected: Vec<String> },
+}
+
+fn parse_command(args: &[String]) -> Result<Command, String> {
+    let subcommand = args.first().ok_or("missing command")?;
+    let mut files = Vec::new();
+    let (mut output, mut map, mut from, mut count) = (None, None, None, None);
+    let (mut cycles, mut trace, mut serial) = (DEFAULT_CYCLES, false, false);
+    let mut args = args[1..].iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().ok_or("-o needs a file")?.to_string()),
+            "--map" => map = Some(args.next().ok_or("--map needs a file")?.to_string()),
+            "--from" => from = Some(args.next().and_then(|value| number(value)).ok_or("--from needs an address")?),
+            "--count" => count = Some(integer(args.next(), "--count")?),
+            "--cycles" => cycles = integer(args.next(), "--cycles")?,
+            "--trace" => trace = true,
+            "--serial" => serial = true,
+            flag if flag.starts_with('-') => return Err(format!("unknown option {}", flag)),
+            file => files.push(file.to_string()),
+        }
+    }
+    let mut files = files.into_iter();
+    let first = files.next().ok_or("missing program")?;
+    let command = match subcommand.as_str() {
+        "build" => Command::Build { source: first, output, map },
+        "dump" => Command::Dump { program: first, from, count },
+        "run" => Command::Run { program: first, cycles, trace, serial },
+        "test" => Command::Test { program: first, expected: files.by_ref().collect() },
+        other => return Err(format!("unknown command {}", other)),
+    };
+    match command {
+        Command::Test { ref expected, .. } if expected.is_empty() => Err("test needs an expected state file".to_string()),
+        _ if files.next().is_some() => Err(format!("{} takes one program", subcommand)),
+        command => Ok(command),
+    }
+}
+
+fn integer<T: std::str::FromStr>(value: Option<&String>, name: &str) -> Result<T, String> {
+    value.and_then(|value| value.parse().ok()).ok_or_else(|| format!("{} needs a number", name))
+}
+
+///
+/// Program and what is known of its labels
+///
+#[derive(Clone, Debug)]
+struct Program {
+    image: Image,
+    map: SourceMap,
+}
+
+/// Load a program, by its extension, from the text or bytes of a file.
+fn load_program(name: &str, bytes: &[u8]) -> Result<Program, String> {
+    let extension = Path::new(name).extension().and_then(|extension| extension.to_str()).unwrap_or("").to_lowercase();
+    let text = || String::from_utf8(bytes.to_vec()).map_err(|_| format!("{} isn't text", name));
+    let assembly = match extension.as_str() {
+        "hc" => hivec::build(&text()?).map_err(|error| format!("{}: {}", name, error))?,
+        "asm" | "s" => assemble(&text()?).map_err(|error| format!("{}: {}", name, error))?,
+        _ => {
+            let image = Image::read(&mut Cursor::new(bytes), image_format(name)).map_err(|error| format!("{}: {}", name, error))?;
+            let map = SourceMap { symbols: image.symbols.clone(), ..SourceMap::new() };
+            return Ok(Program { image, map });
+        }
+    };
+    Ok(Program { image: assembly.image, map: assembly.map })
+}
+
+fn read_program(name: &str) -> Result<Program, String> {
+    let bytes = fs::read(name).map_err(|error| format!("unable to read {}: {}", name, error))?;
+    load_program(name, &bytes)
+}
+
+/// Image format of a file by its extension.
+fn image_format(name: &str) -> Format {
+    match Path::new(name).extension().and_then(|extension| extension.to_str()).unwrap_or("").to_lowercase().as_str() {
+        "hive" => Format::Hive,
+        "hex" => Format::IntelHex,
+        _ => Format::RawBigEndian,
+    }
+}
+
+/// Disassemble `count` instructions from `from`, or every segment of the image.
+fn dump(program: &Program, from: Option<u16>, count: Option<usize>) -> Vec<String> {
+    let rom = program.image.to_rom();
+    let fetch = |address: u16| rom.get(address as usize).cloned().unwrap_or(0);
+    let ranges: Vec<(u16, usize)> = match from {
+        Some(from) => vec![(from, if count.is_some() { 0x10000 } else { rom.len() })],
+        None => program.image.segments.iter().map(|segment| (segment.address, segment.address as usize + segment.words.len())).collect(),
+    };
+    let mut lines = Vec::new();
+    for (start, end) in ranges {
+        let mut address = start;
+        let mut left = count.unwrap_or(usize::MAX);
+        while left > 0 && (address as usize) < end {
+            let line = disassemble(&fetch, address, Some(&program.map));
+            lines.push(format!("0x{:04X} {:<12} {}", address, program.map.label(address).unwrap_or(""), line.text));
+            match address.checked_add(line.length) {
+                Some(next) => address = next,
+                None => break,
+            }
+            left -= 1;
+        }
+    }
+    lines
+}
+
+fn state(cpu: &VCPU16) -> &'static str {
+    if cpu.is_halted() {
+        "halted"
+    } else if cpu.is_hibernating() {
+        "hibernating"
+    } else if cpu.is_sleeping() {
+        "sleeping"
+    } else {
+        "running"
+    }
+}
+
+fn registers(cpu: &VCPU16) -> String {
+    let general = [cpu.get_a(), cpu.get_b(), cpu.get_c(), cpu.get_x(), cpu.get_y(), cpu.get_z(), cpu.get_i(), cpu.get_j()];
+    let general: Vec<String> = REGISTERS.iter().zip(general.iter()).map(|(name, value)| format!("{} {:04X}", name, value)).collect();
+    format!("{}  PC {:04X}  SP {:04X}  EX {:04X}  IA {:04X}", general.join("  "), cpu.get_pc(), cpu.get_sp(), cpu.get_ex(), cpu.get_ia())
+}
+
+///
+/// Run a CPU for at most `cycles` or until it halts or hibernates, writing
+/// every instruction and the registers before it to `trace`. Returns the
+/// cycles run.
+///
+fn run(cpu: &mut VCPU16, program: &Program, cycles: u64, devices: &mut Vec<Box<dyn Device>>, mut trace: Option<&mut dyn Write>) -> io::Result<u64> {
+    let mut ran = 0;
+    while ran < cycles && !cpu.is_halted() && !cpu.is_hibernating() {
+        if let Some(ref mut trace) = trace {
+            if !cpu.is_busy() && !cpu.is_sleeping() {
+                let line = disassemble(&|address| cpu.get_memory(address), cpu.get_pc(), Some(&program.map));
+                writeln!(trace, "0x{:04X} {:<24} {}", cpu.get_pc(), line.text, registers(cpu))?;
+            }
+        }
+        cpu.step_with(devices);
+        ran += 1;
+    }
+    Ok(ran)
+}
+
+fn start(program: &Program) -> Result<VCPU16, String> {
+    let mut cpu = VCPU16::default();
+    cpu.load_image(&program.image).map_err(|error| error.to_string())?;
+    Ok(cpu)
+}
+
+///
+/// CPU state expected after running a program
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Expected {
+    cycles: u64,
+    state: Option<String>,
+    /// Register index as in `register`, and value
+    registers: Vec<(usize, u16)>,
+    memory: Vec<(u16, Vec<u16>)>,
+}
+
+const REGISTER_NAMES: [&str; 12] = ["A", "B", "C", "X", "Y", "Z", "I", "J", "PC", "SP", "EX", "IA"];
+
+fn register(cpu: &VCPU16, index: usize) -> u16 {
+    let getters: [fn(&VCPU16) -> u16; 12] = [
+        VCPU16::get_a, VCPU16::get_b, VCPU16::get_c, VCPU16::get_x, VCPU16::get_y, VCPU16::get_z, VCPU16::get_i, VCPU16::get_j,
+        VCPU16::get_pc, VCPU16::get_sp, VCPU16::get_ex, VCPU16::get_ia,
+    ];
+    getters[index](cpu)
+}
+
+fn parse_expected(text: &str) -> Result<Expected, String> {
+    let mut expected = Expected { cycles: DEFAULT_CYCLES, state: None, registers: Vec::new(), memory: Vec::new() };
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("");
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let error = |message: &str| format!("line {}: {}", index + 1, message);
+        let values = || words[1..].iter().map(|word| number(word)).collect::<Option<Vec<u16>>>().ok_or_else(|| error("expected numbers"));
+        match words.first().cloned() {
+            None => {}
+            Some("cycles") => expected.cycles = words.get(1).and_then(|word| word.parse().ok()).ok_or_else(|| error("cycles needs a number"))?,
+            Some("state") => match words.get(1).cloned() {
+                Some(state @ ("running" | "sleeping" | "hibernating" | "halted")) => expected.state = Some(state.to_string()),
+                _ => return Err(error("state is one of running, sleeping, hibernating or halted")),
+            },
+            Some(word) if word.starts_with('[') && word.ends_with(']') => {
+                let address = number(&word[1..word.len() - 1]).ok_or_else(|| error("bad address"))?;
+                expected.memory.push((address, values()?));
+            }
+            Some(word) => {
+                let register = REGISTER_NAMES.iter().position(|name| name.eq_ignore_ascii_case(word)).ok_or_else(|| error(&format!("unknown register {}", word)))?;
+                match values()?[..] {
+                    [value] => expected.registers.push((register, value)),
+                    _ => return Err(error("a register takes one value")),
+                }
+            }
+        }
+    }
+    Ok(expected)
+}
+
+/// Differences between a CPU and what was expected of it.
+fn compare(cpu: &VCPU16, expected: &Expected) -> Vec<String> {
+    let mut differences = Vec::new();
+    if let Some(ref expected) = expected.state {
+        if state(cpu) != expected {
+            differences.push(format!("state is {}, expected {}", state(cpu), expected));
+        }
+    }
+    for &(index, value) in expected.registers.iter() {
+        if register(cpu, index) != value {
+            differences.push(format!("{} is 0x{:04X}, expected 0x{:04X}", REGISTER_NAMES[index], register(cpu, index), value));
+        }
+    }
+    for &(address, ref words) in expected.memory.iter() {
+        for (offset, &value) in words.iter().enumerate() {
+            let address = address.wrapping_add(offset as u16);
+            if cpu.get_memory(address) != value {
+                differences.push(format!("[0x{:04X}] is 0x{:04X}, expected 0x{:04X}", address, cpu.get_memory(address), value));
+            }
+        }
+    }
+    differences
+}
+
+/// Run `program` against one expected state, returning its differences.
+fn test(program: &Program, expected: &Expected) -> Result<Vec<String>, String> {
+    let mut cpu = start(program)?;
+    run(&mut cpu, program, expected.cycles, &mut Vec::new(), None).map_err(|error| error.to_string())?;
+    Ok(compare(&cpu, expected))
+}
+
+/// Carry out a command, returning whether it succeeded.
+fn execute(command: Command) -> Result<bool, String> {
+    match command {
+        Command::Build { source, output, map } => {
+            let program = read_program(&source)?;
+            let output = output.unwrap_or_else(|| Path::new(&source).with_extension("hive").to_string_lossy().into_owned());
+            let mut file = File::create(&output).map_err(|error| format!("unable to create {}: {}", output, error))?;
+            program.image.write(&mut file, image_format(&output)).map_err(|error| format!("unable to write {}: {}", output, error))?;
+            if let Some(map_file) = map {
+                let mut file = File::create(&map_file).map_err(|error| format!("unable to create {}: {}", map_file, error))?;
+                program.map.write(&mut file).map_err(|error| format!("unable to write {}: {}", map_file, error))?;
+            }
+            let words: usize = program.image.segments.iter().map(|segment| segment.words.len()).sum();
+            println!("{} words to {}", words, output);
+            Ok(true)
+        }
+        Command::Dump { program, from, count } => {
+            for line in dump(&read_program(&program)?, from, count) {
+                println!("{}", line);
+            }
+            Ok(true)
+        }
+        Command::Run { program, cycles, trace, serial } => {
+            let program = read_program(&program)?;
+            let mut cpu = start(&program)?;
+            let mut devices: Vec<Box<dyn Device>> = Vec::new();
+            if serial {
+                devices.push(Box::new(Serial::with_streams(io::stdin(), Box::new(io::stdout()))));
+            }
+            let stdout = io::stdout();
+            let mut lock = stdout.lock();
+            let ran = run(&mut cpu, &program, cycles, &mut devices, if trace { Some(&mut lock) } else { None }).map_err(|error| error.to_string())?;
+            println!("{} after {} cycles\n{}", state(&cpu), ran, registers(&cpu));
+            Ok(true)
+        }
+        Command::Test { program, expected } => {
+            let program = read_program(&program)?;
+            let mut passed = true;
+            for file in expected {
+                let text = fs::read_to_string(&file).map_err(|error| format!("unable to read {}: {}", file, error))?;
+                let differences = test(&program, &parse_expected(&text).map_err(|error| format!("{}: {}", file, error))?)?;
+                if differences.is_empty() {
+                    println!("pass {}", file);
+                } else {
+                    println!("FAIL {}: {}", file, differences.join(", "));
+                    passed = false;
+                }
+            }
+            Ok(passed)
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match parse_command(&args) {
+        Ok(command) => command,
+        Err(error) => {
+            eprintln!("{}\n{}", error, USAGE);
+            process::exit(2);
+        }
+    };
+    match execute(command) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dump, load_program, parse_command, parse_expected, run, start, test, Command};
+
+    const SOURCE: &str = "
+        start:  SET A, 0
+        loop:   ADD A, 1
+                IFN A, 3
+                    SET PC, loop
+                SET [0x8000], A
+                HIB
+    ";
+
+    #[test]
+    pub fn test_toolchain() {
+        let args: Vec<String> = ["run", "loop.asm", "--cycles", "50", "--trace"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(parse_command(&args).unwrap(), Command::Run { program: "loop.asm".to_string(), cycles: 50, trace: true, serial: false });
+        assert!(parse_command(&args[..1]).is_err());
+        assert!(parse_command(&["test".to_string(), "loop.asm".to_string()]).is_err());
+
+        let program = load_program("loop.asm", SOURCE.as_bytes()).unwrap();
+        let listing = dump(&program, None, None);
+        assert_eq!(listing[0], "0x0000 start        SET A, 0");
+        assert_eq!(listing[3], "0x0003              SET PC, loop");
+        assert_eq!(dump(&program, Some(1), Some(1)), vec!["0x0001 loop         ADD A, 1"]);
+
+        // Tracing shows each instruction before it runs
+        let mut cpu = start(&program).unwrap();
+        let mut trace = Vec::new();
+        let ran = run(&mut cpu, &program, 1000, &mut Vec::new(), Some(&mut trace)).unwrap();
+        assert!(ran < 1000 && cpu.is_hibernating());
+        let trace = String::from_utf8(trace).unwrap();
+        assert!(trace.starts_with("0x0000 SET A, 0                 A 0000  B 0000"));
+        assert_eq!(trace.lines().filter(|line| line.contains("ADD A, 1")).count(), 3);
+
+        // Expected states
+        let expected = parse_expected("cycles 200\nstate hibernating ; done\nA 3\n[0x8000] 3 0\n").unwrap();
+        assert!(test(&program, &expected).unwrap().is_empty());
+        let expected = parse_expected("state halted\nPC 0\n[0x8000] 4").unwrap();
+        assert_eq!(test(&program, &expected).unwrap(), vec![
+            "state is hibernating, expected halted".to_string(),
+            "PC is 0x0008, expected 0x0000".to_string(),
+            "[0x8000] is 0x0003, expected 0x0004".to_string(),
+        ]);
+        assert_eq!(parse_expected("Q 1").unwrap_err(), "line 1: unknown register Q");
+    }
+}
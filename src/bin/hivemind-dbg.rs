@@ -0,0 +1,469 @@
+//!
+//! Hivemind CPU Debugger
+//!
+//! Loads a program into one or more drones of a running Simulation and
+//! monitors a chosen drone's CPU: registers, disassembly around PC, a memory
+//! hex view, the stack and the devices on its bus, redrawn after every
+//! command. The chosen CPU is paused in the cluster and stepped by the
+//! debugger, so it stops at breakpoints while the rest of the world ticks.
+//! Programs are HiveC (`.hc`), assembly (`.asm`, `.s`), `.hive` images,
+//! Intel HEX (`.hex`) or raw big-endian words.
+//!
+//! ```text
+//! hivemind-dbg <program> [--world <directory>] [--drones <count>] [--at <x> <y> <z>] [--plain]
+//! ```
+//!
+//! The screen is drawn with ANSI escapes unless `--plain` is given.
+//!
+
+extern crate hivemind;
+
+use hivemind::admin::format_entity;
+use hivemind::model::component::Position;
+use hivemind::model::entity::{EntityID, EntityManager};
+use hivemind::model::world::World;
+use hivemind::pool::Poolable;
+use hivemind::simulation::Simulation;
+use hivemind::vcpu::asm::{assemble, SourceMap, REGISTERS};
+use hivemind::vcpu::cluster::{CpuComponent, CpuId};
+use hivemind::vcpu::cpu::VCPU16;
+use hivemind::vcpu::debugger::{Debugger, Stop};
+use hivemind::vcpu::disasm::disassemble_range;
+use hivemind::vcpu::hivec;
+use hivemind::vcpu::image::{Format, Image};
+use hivemind::vcpu::profiler::{Mode, Profiler};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Cursor, Write};
+use std::path::Path;
+use std::process;
+
+/// Cycles `continue` runs for before giving up on reaching a breakpoint
+const DEFAULT_CONTINUE: u64 = 100_000;
+/// Instructions shown from PC
+const CODE_LINES: usize = 10;
+/// Words of the stack shown from SP
+const STACK_WORDS: u16 = 8;
+/// Functions listed by `profile`
+const PROFILE_LINES: usize = 5;
+/// Rows of eight words in the memory view
+const MEMORY_ROWS: u16 = 8;
+
+const HELP: &str = "commands: [s]tep [count], [c]ontinue [cycles], [t]ick [count], [b]reak <location>, [d]elete <location>, \
+    [m]em <address>, [p]rofile [cycles], cpu <index>, reset, [q]uit; an empty line steps, locations are labels, :lines or addresses";
+
+///
+/// Command Line Options
+///
+#[derive(Clone, PartialEq, Debug)]
+struct Options {
+    program: String,
+    world: Option<String>,
+    drones: u32,
+    at: (f64, f64, f64),
+    plain: bool,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options { program: String::new(), world: None, drones: 1, at: (0.5, 1.0, 0.5), plain: false };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--world" => options.world = Some(args.next().ok_or("--world needs a directory")?.to_string()),
+            "--drones" => options.drones = number(args.next(), "--drones")?,
+            "--at" => options.at = (number(args.next(), "--at")?, number(args.next(), "--at")?, number(args.next(), "--at")?),
+            "--plain" => options.plain = true,
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            program if options.program.is_empty() => options.program = program.to_string(),
+            extra => return Err(format!("unexpected argument {}", extra)),
+        }
+    }
+    if options.program.is_empty() {
+        return Err("missing program".to_string());
+    }
+    if options.drones == 0 {
+        return Err("--drones needs at least one drone".to_string());
+    }
+    Ok(options)
+}
+
+fn number<T: std::str::FromStr>(value: Option<&String>, name: &str) -> Result<T, String> {
+    value.and_then(|value| value.parse().ok()).ok_or_else(|| format!("{} needs a number", name))
+}
+
+///
+/// Program to debug and what is known of its source
+///
+#[derive(Clone, Debug)]
+struct Program {
+    image: Image,
+    map: SourceMap,
+    source: Option<String>,
+}
+
+/// Load a program, by its extension, from the text or bytes of a file.
+fn load_program(name: &str, bytes: &[u8]) -> Result<Program, String> {
+    let extension = Path::new(name).extension().and_then(|extension| extension.to_str()).unwrap_or("").to_lowercase();
+    let text = || String::from_utf8(bytes.to_vec()).map_err(|_| format!("{} isn't text", name));
+    let (assembly, source) = match extension.as_str() {
+        "hc" => {
+            let source = text()?;
+            (hivec::build(&source).map_err(|error| format!("{}: {}", name, error))?, source)
+        }
+        "asm" | "s" => {
+            let source = text()?;
+            (assemble(&source).map_err(|error| format!("{}: {}", name, error))?, source)
+        }
+        _ => {
+            let format = match extension.as_str() {
+                "hive" => Format::Hive,
+                "hex" => Format::IntelHex,
+                _ => Format::RawBigEndian,
+            };
+            let image = Image::read(&mut Cursor::new(bytes), format).map_err(|error| format!("{}: {}", name, error))?;
+            let map = SourceMap { symbols: image.symbols.clone(), ..SourceMap::new() };
+            return Ok(Program { image, map, source: None });
+        }
+    };
+    Ok(Program { image: assembly.image, map: assembly.map, source: Some(source) })
+}
+
+///
+/// Result of a debugger command
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Reply {
+    Continue(String),
+    Stop,
+}
+
+///
+/// Drones running the program and the debugger watching one of them
+///
+struct Session {
+    simulation: Simulation,
+    program: Program,
+    debugger: Debugger,
+    drones: Vec<(EntityID, CpuId)>,
+    selected: usize,
+    /// First address of the memory view
+    memory: u16,
+}
+
+impl Session {
+    fn new(simulation: Simulation, program: Program, drones: u32, at: (f64, f64, f64)) -> Result<Session, String> {
+        let mut debugger = Debugger::new(program.map.clone());
+        if let Some(ref source) = program.source {
+            debugger.set_source(source);
+        }
+        let mut session = Session { simulation, program, debugger, drones: Vec::new(), selected: 0, memory: 0 };
+        for index in 0..drones {
+            let entities = session.simulation.entities_mut();
+            let drone = entities.create_entity();
+            entities.add_component(drone, Position::from_f64(at.0 + index as f64, at.1, at.2));
+            let cpu = session.simulation.attach(drone, &[]).map_err(|error| error.to_string())?;
+            session.simulation.cluster_mut().get_mut(cpu).unwrap().load_image(&session.program.image).map_err(|error| error.to_string())?;
+            session.drones.push((drone, cpu));
+        }
+        session.select(0);
+        Ok(session)
+    }
+    fn cpu_id(&self) -> CpuId { self.drones[self.selected].1 }
+    fn cpu(&self) -> Option<&VCPU16> { self.simulation.cluster().get(self.cpu_id()) }
+    /// Debug drone `index`, letting the one debugged before run freely.
+    fn select(&mut self, index: usize) {
+        let previous = self.cpu_id();
+        self.simulation.cluster_mut().resume(previous);
+        self.selected = index;
+        let cpu = self.cpu_id();
+        self.simulation.cluster_mut().pause(cpu);
+    }
+    /// Step the selected CPU by whole instructions.
+    fn step(&mut self, count: u32) -> String {
+        let stepped = self.simulation.with_cpu(self.cpu_id(), |cpu, bus| {
+            let mut cycles = 0;
+            for _ in 0..count {
+                if cpu.is_halted() {
+                    break;
+                }
+                cpu.step_with(bus);
+                cycles += 1;
+                while cpu.is_busy() {
+                    cpu.step_with(bus);
+                    cycles += 1;
+                }
+            }
+            cycles
+        });
+        match stepped {
+            Some(cycles) => format!("{} cycles", cycles),
+            None => "the drone is gone".to_string(),
+        }
+    }
+    /// Run the selected CPU until a breakpoint, for at most `cycles`.
+    fn run(&mut self, cycles: u64) -> Option<(Stop, u64)> {
+        let debugger = &self.debugger;
+        self.simulation.with_cpu(self.drones[self.selected].1, |cpu, bus| debugger.run(cpu, cycles, bus))
+    }
+    /// Tick the Simulation, running the selected CPU its clock each tick.
+    fn tick(&mut self, count: u32) -> String {
+        let (drone, _) = self.drones[self.selected];
+        for ran in 0..count {
+            self.simulation.step();
+            let clock = match self.simulation.entities_mut().get_component_mut::<CpuComponent>(drone) {
+                Some(component) => {
+                    component.interface.begin_tick();
+                    component.clock as u64
+                }
+                None => return "the drone is gone".to_string(),
+            };
+            if let Some((Stop::Breakpoint(address), _)) = self.run(clock) {
+                return format!("breakpoint at {} during tick {} of {}", self.debugger.locate(address), ran + 1, count);
+            }
+        }
+        format!("tick {}", self.simulation.tick())
+    }
+    fn command(&mut self, line: &str) -> Reply {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let count = |default: u64| words.get(1).and_then(|word| word.parse().ok()).unwrap_or(default);
+        let location = || words.get(1..).map(|words| words.join(" ")).unwrap_or_default();
+        let reply = match words.first().cloned() {
+            None => self.step(1),
+            Some("s") | Some("step") => self.step(count(1) as u32),
+            Some("c") | Some("continue") => match self.run(count(DEFAULT_CONTINUE)) {
+                Some((Stop::Breakpoint(address), cycles)) => format!("breakpoint at {} after {} cycles", self.debugger.locate(address), cycles),
+                Some((Stop::Halted, cycles)) => format!("halted after {} cycles", cycles),
+                Some((Stop::Finished, cycles)) => format!("ran {} cycles", cycles),
+                None => "the drone is gone".to_string(),
+            },
+            Some("t") | Some("tick") => self.tick(count(1) as u32),
+            Some("b") | Some("break") => match self.debugger.break_at(&location()) {
+                Some(address) => format!("breakpoint at 0x{:04X} {}", address, self.debugger.locate(address)),
+                None => format!("no such location {}", location()),
+            },
+            Some("d") | Some("delete") => match self.debugger.resolve(&location()) {
+                Some(address) if self.debugger.clear_breakpoint(address) => format!("deleted breakpoint at 0x{:04X}", address),
+                _ => format!("no breakpoint at {}", location()),
+            },
+            Some("m") | Some("mem") => match self.debugger.resolve(&location()) {
+                Some(address) => {
+                    self.memory = address;
+                    format!("memory from 0x{:04X}", address)
+                }
+                None => format!("no such location {}", location()),
+            },
+            Some("p") | Some("profile") => {
+                let mut profiler = Profiler::new(Mode::Exact);
+                let cycles = count(DEFAULT_CONTINUE);
+                match self.simulation.with_cpu(self.cpu_id(), |cpu, bus| profiler.run(cpu, cycles, bus)) {
+                    Some(()) => {
+                        let functions: Vec<String> = profiler
+                            .by_symbol(self.debugger.map())
+                            .into_iter()
+                            .take(PROFILE_LINES)
+                            .map(|(name, cycles)| format!("{} {}%", name, cycles * 100 / profiler.total().max(1)))
+                            .collect();
+                        format!("{} cycles: {}", profiler.total(), functions.join(", "))
+                    }
+                    None => "the drone is gone".to_string(),
+                }
+            }
+            Some("cpu") => match words.get(1).and_then(|word| word.parse::<usize>().ok()) {
+                Some(index) if index < self.drones.len() => {
+                    self.select(index);
+                    format!("debugging cpu {}", index)
+                }
+                _ => format!("usage: cpu <0 to {}>", self.drones.len() - 1),
+            },
+            Some("reset") => {
+                let image = self.program.image.clone();
+                match self.simulation.with_cpu(self.cpu_id(), |cpu, _| {
+                    cpu.recycle();
+                    cpu.load_image(&image)
+                }) {
+                    Some(Ok(())) => "reloaded the program".to_string(),
+                    Some(Err(error)) => error.to_string(),
+                    None => "the drone is gone".to_string(),
+                }
+            }
+            Some("help") => HELP.to_string(),
+            Some("q") | Some("quit") => return Reply::Stop,
+            Some(other) => format!("unknown command {}, {}", other, HELP),
+        };
+        Reply::Continue(reply)
+    }
+    ///
+    /// The monitor's screen: status, registers, code, stack, memory and
+    /// devices of the selected CPU.
+    ///
+    fn render(&mut self) -> String {
+        let (drone, id) = self.drones[self.selected];
+        let devices = self.simulation.with_cpu(id, |_, bus| (0..bus.count()).filter_map(|index| bus.info(index)).collect::<Vec<_>>());
+        let cpu = match self.cpu() {
+            Some(cpu) => cpu,
+            None => return format!("cpu {} of drone {} is gone\n", self.selected, format_entity(drone)),
+        };
+        let state = if cpu.is_halted() {
+            "halted"
+        } else if cpu.is_hibernating() {
+            "hibernating"
+        } else if cpu.is_sleeping() {
+            "sleeping"
+        } else {
+            "running"
+        };
+        let mut screen = format!(
+            "cpu {} of {}, drone {}, tick {}, {} at {}\n",
+            self.selected,
+            self.drones.len(),
+            format_entity(drone),
+            self.simulation.tick(),
+            state,
+            self.debugger.locate(cpu.get_pc()),
+        );
+        let registers = [cpu.get_a(), cpu.get_b(), cpu.get_c(), cpu.get_x(), cpu.get_y(), cpu.get_z(), cpu.get_i(), cpu.get_j()];
+        let registers: Vec<String> = REGISTERS.iter().zip(registers.iter()).map(|(name, value)| format!("{} {:04X}", name, value)).collect();
+        screen.push_str(&format!(" {}\n", registers.join("  ")));
+        screen.push_str(&format!(" PC {:04X}  SP {:04X}  EX {:04X}  IA {:04X}\n", cpu.get_pc(), cpu.get_sp(), cpu.get_ex(), cpu.get_ia()));
+
+        screen.push_str("-- code\n");
+        let breakpoints: Vec<u16> = self.debugger.breakpoints().collect();
+        let fetch = |address: u16| cpu.get_memory(address);
+        for line in disassemble_range(&fetch, cpu.get_pc(), CODE_LINES, Some(self.debugger.map())) {
+            let marker = if line.address == cpu.get_pc() { '>' } else if breakpoints.contains(&line.address) { '*' } else { ' ' };
+            screen.push_str(&format!("{}{}\n", marker, &self.debugger.describe(cpu, line.address)));
+        }
+
+        screen.push_str("-- stack\n");
+        let stack: Vec<String> = (0..STACK_WORDS)
+            .map(|offset| cpu.get_sp().wrapping_add(offset))
+            .take_while(|&address| address >= cpu.get_sp() && cpu.get_sp() != 0)
+            .map(|address| format!("{:04X}", cpu.get_memory(address)))
+            .collect();
+        screen.push_str(&format!(" {}\n", if stack.is_empty() { "empty".to_string() } else { stack.join(" ") }));
+
+        screen.push_str("-- memory\n");
+        for row in 0..MEMORY_ROWS {
+            let start = self.memory.wrapping_add(row * 8);
+            let words: Vec<String> = (0..8).map(|offset| format!("{:04X}", cpu.get_memory(start.wrapping_add(offset)))).collect();
+            screen.push_str(&format!(" {:04X}: {}\n", start, words.join(" ")));
+        }
+
+        screen.push_str("-- devices\n");
+        match devices {
+            Some(ref devices) if !devices.is_empty() => {
+                for (index, device) in devices.iter().enumerate() {
+                    screen.push_str(&format!(" {} {:08X} v{} by {:08X}\n", index, device.id, device.version, device.manufacturer));
+                }
+            }
+            _ => screen.push_str(" none\n"),
+        }
+        screen
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_options(&args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\nusage: hivemind-dbg <program> [--world <directory>] [--drones <count>] [--at <x> <y> <z>] [--plain]", error);
+            process::exit(2);
+        }
+    };
+    let program = match fs::read(&options.program).map_err(|error| format!("unable to read {}: {}", options.program, error)).and_then(|bytes| load_program(&options.program, &bytes)) {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+    let world = match options.world {
+        Some(ref directory) => match World::open(directory) {
+            Ok(world) => world,
+            Err(error) => {
+                eprintln!("unable to open {}: {}", directory, error);
+                process::exit(1);
+            }
+        },
+        None => World::new(),
+    };
+    let mut session = match Session::new(Simulation::new(world, EntityManager::new()), program, options.drones, options.at) {
+        Ok(session) => session,
+        Err(error) => {
+            eprintln!("unable to start the drones: {}", error);
+            process::exit(1);
+        }
+    };
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut status = HELP.to_string();
+    loop {
+        let screen = session.render();
+        if !options.plain {
+            // Clear the terminal and home the cursor
+            print!("\x1b[2J\x1b[H");
+        }
+        print!("{}{}\n> ", screen, status);
+        let _ = io::stdout().flush();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => return,
+        };
+        status = match session.command(&line) {
+            Reply::Continue(reply) => reply,
+            Reply::Stop => return,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_program, parse_options, Reply, Session};
+    use hivemind::model::entity::EntityManager;
+    use hivemind::model::world::World;
+    use hivemind::simulation::Simulation;
+
+    const SOURCE: &str = "
+        start:  SET A, 0
+        loop:   ADD A, 1
+                SET PUSH, A
+                IFN A, 3
+                    SET PC, loop
+                HIB
+    ";
+
+    #[test]
+    pub fn test_monitor() {
+        let args: Vec<String> = ["loop.asm", "--drones", "2", "--at", "1", "2", "3"].iter().map(|arg| arg.to_string()).collect();
+        let options = parse_options(&args).unwrap();
+        assert_eq!((options.program.as_str(), options.drones, options.at), ("loop.asm", 2, (1.0, 2.0, 3.0)));
+        assert!(parse_options(&args[1..]).is_err());
+
+        let program = load_program("loop.asm", SOURCE.as_bytes()).unwrap();
+        let mut session = Session::new(Simulation::new(World::new(), EntityManager::new()), program, 2, options.at).unwrap();
+        assert!(session.simulation.cluster().is_paused(session.cpu_id()));
+        assert_eq!(session.command("b loop"), Reply::Continue("breakpoint at 0x0001 loop".to_string()));
+        assert_eq!(session.command("c"), Reply::Continue("breakpoint at loop after 1 cycles".to_string()));
+        assert_eq!(session.command(""), Reply::Continue("2 cycles".to_string()));
+        assert_eq!(session.command("c"), Reply::Continue("breakpoint at loop after 4 cycles".to_string()));
+
+        let screen = session.render();
+        assert!(screen.starts_with("cpu 0 of 2, drone 0:0, tick 0, running at loop\n A 0001  B 0000"));
+        assert!(screen.contains(">0x0001 loop         ADD A, 1    ; 3: loop:   ADD A, 1\n"));
+        assert!(screen.contains("-- stack\n 0001\n"));
+        assert!(screen.contains(" 0 48574946 v1 by 48495645\n"));
+
+        // Ticking runs the other drone freely and this one up to its breakpoint
+        assert_eq!(session.command("tick 2"), Reply::Continue("breakpoint at loop during tick 1 of 2".to_string()));
+        assert_eq!(session.command("d loop"), Reply::Continue("deleted breakpoint at 0x0001".to_string()));
+        assert_eq!(session.command("p 10"), Reply::Continue("10 cycles: loop 100%".to_string()));
+        assert_eq!(session.command("t"), Reply::Continue("tick 2".to_string()));
+        assert!(session.render().contains("hibernating"));
+        assert_eq!(session.command("cpu 1"), Reply::Continue("debugging cpu 1".to_string()));
+        assert_eq!(session.command("m 0xFFFD"), Reply::Continue("memory from 0xFFFD".to_string()));
+        assert!(session.render().contains("-- memory\n FFFD: 0003 0002 0001 "));
+        assert_eq!(session.command("q"), Reply::Stop);
+    }
+}
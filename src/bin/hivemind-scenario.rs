@@ -0,0 +1,119 @@
+//!
+//! Hivemind Scenario Runner
+//!
+//! Runs scenario files headless and reports whether each passed.
+//!
+//! ```text
+//! hivemind-scenario [--ticks <limit>] <scenario file>...
+//! ```
+//!
+//! Each scenario prints one line, `pass` with the ticks it took or `FAIL`
+//! with the condition that failed it. `--ticks` overrides every scenario's
+//! tick limit. The runner exits with 1 if any scenario failed or couldn't be
+//! run, so it can gate a build.
+//!
+
+extern crate hivemind;
+
+use hivemind::scenario::Scenario;
+use std::env;
+use std::process;
+use std::str::FromStr;
+
+const USAGE: &str = "usage: hivemind-scenario [--ticks <limit>] <scenario file>...";
+
+///
+/// Command line options
+///
+#[derive(Clone, PartialEq, Debug)]
+struct Options {
+    ticks: Option<u64>,
+    files: Vec<String>,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options { ticks: None, files: Vec::new() };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ticks" => options.ticks = Some(number(args.next(), "--ticks")?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            file => options.files.push(file.to_string()),
+        }
+    }
+    if options.files.is_empty() {
+        return Err("missing scenario file".to_string());
+    }
+    Ok(options)
+}
+
+fn number<T: FromStr>(value: Option<&String>, flag: &str) -> Result<T, String> {
+    value.and_then(|value| value.parse().ok()).ok_or_else(|| format!("{} needs a number", flag))
+}
+
+/// Run one scenario file, returning its report line and whether it passed.
+fn run(file: &str, ticks: Option<u64>) -> (String, bool) {
+    let report = Scenario::load(file).and_then(|mut scenario| {
+        scenario.ticks = ticks.unwrap_or(scenario.ticks);
+        scenario.run()
+    });
+    match report {
+        Ok(report) => (report.to_string(), report.passed()),
+        Err(error) => (format!("FAIL {}: {}", file, error), false),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_options(&args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n{}", error, USAGE);
+            process::exit(2);
+        }
+    };
+    let mut failed = 0;
+    for file in options.files.iter() {
+        let (line, passed) = run(file, options.ticks);
+        println!("{}", line);
+        if !passed {
+            failed += 1;
+        }
+    }
+    println!("{} of {} scenarios passed", options.files.len() - failed, options.files.len());
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_options, run};
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    pub fn test_scenario_runner() {
+        let args: Vec<String> = ["--ticks", "20", "a.toml", "b.toml"].iter().map(|arg| arg.to_string()).collect();
+        let options = parse_options(&args).unwrap();
+        assert_eq!((options.ticks, options.files.len()), (Some(20), 2));
+        assert!(parse_options(&args[..2]).is_err());
+        assert!(parse_options(&["--fast".to_string()]).is_err());
+
+        // Firmware found next to the scenario, reaching its victory on the first tick
+        let directory = env::temp_dir().join(format!("hivemind-scenario-runner-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("store.asm"), "SET [0x9000], 7\n:end SET PC, end\n").unwrap();
+        let scenario = "name = \"store\"\nticks = 10\n\n[[drone]]\nfirmware = \"store.asm\"\nx = 1\ny = 1\nz = 1\n\n\
+                        [[victory]]\ntype = \"memory\"\ndrone = 0\naddress = 0x9000\nvalue = 7\n";
+        let file = directory.join("store.toml");
+        fs::write(&file, scenario).unwrap();
+        assert_eq!(run(file.to_str().unwrap(), None), ("pass store in 1 ticks".to_string(), true));
+        fs::write(&file, scenario.replace("value = 7", "value = 8")).unwrap();
+        assert_eq!(run(file.to_str().unwrap(), Some(3)), ("FAIL store: no victory in 3 ticks".to_string(), false));
+        let (line, passed) = run(directory.join("missing.toml").to_str().unwrap(), None);
+        assert!(line.starts_with("FAIL") && !passed);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,402 @@
+//!
+//! Headless Hivemind Server
+//!
+//! Opens a world save directory, runs its Simulation at a fixed tick rate and
+//! autosaves on an interval. Autosaves only write the chunks changed since the
+//! last save, with every COMPACT_EVERY'th one, like `save` and stopping, a
+//! full save which also compacts the region files. Admin commands are read line by line from stdin:
+//! simulation controls, every command of the admin module, and `script <file>`
+//! to run a file of them. Structure files in the world's `blueprints`
+//! directory can be spawned by name, and Components watched with `watch`
+//! are reported as they change. Built with the `script` feature, a
+//! mission script given with `--script` runs its hooks after every tick.
+//! With `--metrics` the Simulation's metrics are logged on an interval, and
+//! with `--memory` saved chunks are evicted to keep within a memory budget.
+//! With `--record` every tick's events, and each console command as a
+//! message, are written to an event log, see `hivemind::recorder`. With
+//! `--history` a keyframe is kept every so many ticks so `at <tick> ...`
+//! can inspect the recent past, see `hivemind::history`.
+//!
+//! ```text
+//! hivemind-server <world directory> [--rate <ticks per second>] [--autosave <seconds>] [--script <file>] [--metrics <seconds>] [--memory <megabytes>] [--record <event log>] [--history <ticks>]
+//! ```
+//!
+
+extern crate hivemind;
+
+use hivemind::admin::{Admin, AdminError};
+use hivemind::budget::MemoryBudget;
+use hivemind::history::{History, DEFAULT_CAPACITY};
+use hivemind::metrics::{Exporter, LogExporter};
+use hivemind::model::entity::EntityManager;
+use hivemind::model::world::World;
+use hivemind::recorder::EventRecorder;
+#[cfg(feature = "script")]
+use hivemind::script::Script;
+use hivemind::simulation::{Simulation, DEFAULT_TICK_RATE};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter};
+use std::path::Path;
+use std::process;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default seconds between autosaves
+const DEFAULT_AUTOSAVE: u64 = 300;
+/// Autosaves per full save
+const COMPACT_EVERY: u32 = 12;
+
+const HELP: &str = "commands: status, metrics, pause, resume, step [ticks], speed <multiplier>, rate <ticks per second>, save, \
+    script <file>, stop, cpus, pause <entity>, resume <entity>, interrupt <entity> <message>, set <x> <y> <z> <material>, \
+    spawn <blueprint> <x> <y> <z>, trace <entity>, block <x> <y> <z>, at <tick> cpus|trace <entity>|block <x> <y> <z>, \
+    entities [<component>|#<label>]..., inspect <entity>, edit <entity> <component>.<field> <value>, \
+    watch <entity> <component>, unwatch <entity> <component>";
+
+///
+/// Command Line Options
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Options {
+    directory: String,
+    rate: u32,
+    /// Seconds between autosaves, 0 to disable
+    autosave: u64,
+    script: Option<String>,
+    /// Seconds between metrics logs, 0 to disable
+    metrics: u64,
+    /// Memory budget in megabytes, 0 for none
+    memory: usize,
+    /// Event log to record to
+    record: Option<String>,
+    /// Ticks between History keyframes, 0 to keep none
+    history: u64,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options { directory: String::new(), rate: DEFAULT_TICK_RATE, autosave: DEFAULT_AUTOSAVE, script: None, metrics: 0, memory: 0, record: None, history: 0 };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rate" => options.rate = number(args.next(), "--rate")?,
+            "--autosave" => options.autosave = number(args.next(), "--autosave")?,
+            "--script" => options.script = Some(args.next().ok_or("--script needs a file")?.to_string()),
+            "--metrics" => options.metrics = number(args.next(), "--metrics")?,
+            "--memory" => options.memory = number(args.next(), "--memory")?,
+            "--history" => options.history = number(args.next(), "--history")?,
+            "--record" => options.record = Some(args.next().ok_or("--record needs a file")?.to_string()),
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            directory if options.directory.is_empty() => options.directory = directory.to_string(),
+            extra => return Err(format!("unexpected argument {}", extra)),
+        }
+    }
+    if options.directory.is_empty() {
+        return Err("missing world directory".to_string());
+    }
+    Ok(options)
+}
+
+fn number<T: std::str::FromStr>(value: Option<&String>, name: &str) -> Result<T, String> {
+    value.and_then(|value| value.parse().ok()).ok_or_else(|| format!("{} needs a number", name))
+}
+
+///
+/// Result of an admin command
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Reply {
+    Continue(String),
+    Stop,
+}
+
+///
+/// Run one admin command against the Simulation.
+///
+fn command(simulation: &mut Simulation, admin: &mut Admin, line: &str) -> Reply {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let argument = |index: usize| words.get(index).and_then(|word| word.parse::<u32>().ok());
+    let reply = match words.first().cloned() {
+        None => return Reply::Continue(String::new()),
+        Some("help") => HELP.to_string(),
+        Some("status") => format!(
+            "tick {} at {} ticks/s x{}{}, {} chunks, {} entities, {} cpus",
+            simulation.tick(),
+            simulation.tick_rate(),
+            simulation.speed(),
+            if simulation.is_paused() { " (paused)" } else { "" },
+            simulation.world().loaded_chunks().len(),
+            simulation.entities().entities().len(),
+            simulation.cluster().len(),
+        ),
+        Some("metrics") => simulation.metrics().log_line(),
+        Some("pause") if words.len() == 1 => {
+            simulation.pause();
+            "paused".to_string()
+        }
+        Some("resume") if words.len() == 1 => {
+            simulation.resume();
+            "resumed".to_string()
+        }
+        Some("step") => {
+            let ticks = argument(1).unwrap_or(1);
+            for _ in 0..ticks {
+                simulation.step();
+            }
+            format!("tick {}", simulation.tick())
+        }
+        Some("speed") => match argument(1) {
+            Some(speed) => {
+                simulation.fast_forward(speed);
+                format!("speed x{}", simulation.speed())
+            }
+            None => "usage: speed <multiplier>".to_string(),
+        },
+        Some("rate") => match argument(1) {
+            Some(rate) => {
+                simulation.set_tick_rate(rate);
+                format!("{} ticks/s", simulation.tick_rate())
+            }
+            None => "usage: rate <ticks per second>".to_string(),
+        },
+        Some("save") => save(simulation, true),
+        Some("script") => match words.get(1).map(fs::read_to_string) {
+            Some(Ok(script)) => match admin.script(simulation, &script) {
+                Ok(replies) => replies.join("\n"),
+                Err((line, error)) => format!("line {}: {}", line, error),
+            },
+            Some(Err(error)) => format!("unable to read script: {}", error),
+            None => "usage: script <file>".to_string(),
+        },
+        Some("stop") | Some("quit") => return Reply::Stop,
+        Some(_) => match admin.run(simulation, line) {
+            Ok(reply) => reply,
+            Err(AdminError::UnknownCommand(name)) => format!("unknown command {}, {}", name, HELP),
+            Err(error) => error.to_string(),
+        },
+    };
+    Reply::Continue(reply)
+}
+
+/// Save the World, everything changed or in `full` everything with its entities, also compacting its files.
+fn save(simulation: &mut Simulation, full: bool) -> String {
+    let tick = simulation.tick();
+    let saved = if full {
+        simulation.save().map(|_| "saved".to_string())
+    } else {
+        simulation.save_dirty().map(|count| format!("saved {} changed chunks", count))
+    };
+    match saved {
+        Ok(saved) => format!("{} at tick {}", saved, tick),
+        Err(error) => format!("save failed: {}", error),
+    }
+}
+
+/// Without the script feature there are no scripts to run
+#[cfg(not(feature = "script"))]
+enum Script {}
+
+/// Mission script run alongside the Simulation, if any
+type Mission = Option<Script>;
+
+#[cfg(feature = "script")]
+fn start_mission(path: Option<&String>, simulation: &mut Simulation) -> Result<Mission, String> {
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let source = fs::read_to_string(path).map_err(|error| format!("unable to read {}: {}", path, error))?;
+    let mut script = Script::compile(&source).map_err(|error| format!("{}: {}", path, error))?;
+    let started = script.start(simulation);
+    print_output(&mut script);
+    started.map_err(|error| format!("{}: {}", path, error))?;
+    Ok(Some(script))
+}
+
+#[cfg(not(feature = "script"))]
+fn start_mission(path: Option<&String>, _: &mut Simulation) -> Result<Mission, String> {
+    match path {
+        Some(_) => Err("this server was built without the script feature".to_string()),
+        None => Ok(None),
+    }
+}
+
+#[cfg(feature = "script")]
+fn print_output(script: &mut Script) {
+    for line in script.take_output() {
+        println!("{}", line);
+    }
+}
+
+/// Advance the Simulation, through the mission script when there is one.
+#[cfg(feature = "script")]
+fn advance(simulation: &mut Simulation, mission: &mut Mission, elapsed: Duration) {
+    match *mission {
+        Some(ref mut script) => {
+            if let Err(error) = script.advance(simulation, elapsed) {
+                eprintln!("script error, {}", error);
+            }
+            print_output(script);
+        }
+        None => {
+            simulation.advance(elapsed);
+        }
+    }
+}
+
+#[cfg(not(feature = "script"))]
+fn advance(simulation: &mut Simulation, mission: &mut Mission, elapsed: Duration) {
+    match *mission {
+        Some(ref script) => match *script {},
+        None => {
+            simulation.advance(elapsed);
+        }
+    }
+}
+
+/// Read stdin on its own thread so the tick loop never blocks on it.
+fn console() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_options(&args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\nusage: hivemind-server <world directory> [--rate <ticks per second>] [--autosave <seconds>] [--script <file>] [--metrics <seconds>] [--memory <megabytes>] [--record <event log>] [--history <ticks>]", error);
+            process::exit(2);
+        }
+    };
+    let world = match World::open(&options.directory) {
+        Ok(world) => world,
+        Err(error) => {
+            eprintln!("unable to open {}: {}", options.directory, error);
+            process::exit(1);
+        }
+    };
+    let mut admin = Admin::default();
+    let blueprints = Path::new(&options.directory).join("blueprints");
+    if blueprints.is_dir() {
+        match admin.load_blueprints(&blueprints) {
+            Ok(count) => println!("loaded {} blueprints", count),
+            Err(error) => eprintln!("unable to load blueprints: {}", error),
+        }
+    }
+    let mut simulation = Simulation::new(world, EntityManager::new());
+    match simulation.load_saved() {
+        Ok(count) => println!("loaded {} chunks", count),
+        Err(error) => {
+            eprintln!("unable to load {}: {}", options.directory, error);
+            process::exit(1);
+        }
+    }
+    simulation.set_tick_rate(options.rate);
+    if options.memory > 0 {
+        simulation.set_memory_budget(Some(MemoryBudget::new(options.memory << 20)));
+    }
+    if options.history > 0 {
+        simulation.set_history(Some(History::new(options.history, DEFAULT_CAPACITY)));
+    }
+    if let Some(ref path) = options.record {
+        match File::create(path).and_then(|file| EventRecorder::new(Box::new(BufWriter::new(file)))) {
+            Ok(recorder) => simulation.set_recorder(Some(recorder)),
+            Err(error) => {
+                eprintln!("unable to record to {}: {}", path, error);
+                process::exit(1);
+            }
+        };
+    }
+    let mut mission = match start_mission(options.script.as_ref(), &mut simulation) {
+        Ok(mission) => mission,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+    println!("serving {} at {} ticks/s, {}", options.directory, simulation.tick_rate(), HELP);
+
+    let commands = console();
+    let autosave = Duration::from_secs(options.autosave);
+    let metrics = Duration::from_secs(options.metrics);
+    let mut exporter = LogExporter::new(io::stdout());
+    let (mut last, mut last_save, mut last_metrics) = (Instant::now(), Instant::now(), Instant::now());
+    let mut autosaves = 0u32;
+    loop {
+        // Once stdin closes the server runs until killed
+        while let Ok(line) = commands.try_recv() {
+            simulation.record_message(None, &line);
+            match command(&mut simulation, &mut admin, &line) {
+                Reply::Continue(reply) => if !reply.is_empty() { println!("{}", reply) },
+                Reply::Stop => {
+                    println!("{}", save(&mut simulation, true));
+                    if let Some(error) = simulation.set_recorder(None).as_ref().and_then(EventRecorder::error) {
+                        eprintln!("event log stopped early: {}", error);
+                    }
+                    return;
+                }
+            }
+        }
+        let now = Instant::now();
+        advance(&mut simulation, &mut mission, now - last);
+        for line in admin.poll(&simulation) {
+            println!("{}", line);
+        }
+        last = now;
+        if options.autosave > 0 && now - last_save >= autosave {
+            autosaves += 1;
+            println!("{}", save(&mut simulation, autosaves.is_multiple_of(COMPACT_EVERY)));
+            last_save = now;
+        }
+        if options.metrics > 0 && now - last_metrics >= metrics {
+            if let Err(error) = exporter.export(simulation.metrics()) {
+                eprintln!("unable to log metrics: {}", error);
+            }
+            last_metrics = now;
+        }
+        thread::sleep(simulation.tick_interval() / simulation.speed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{command, parse_options, Reply};
+    use hivemind::admin::Admin;
+    use hivemind::model::entity::EntityManager;
+    use hivemind::model::world::World;
+    use hivemind::simulation::Simulation;
+
+    #[test]
+    pub fn test_admin_console() {
+        let args: Vec<String> = ["saves/alpha", "--rate", "10", "--metrics", "60", "--memory", "512", "--record", "events.log"].iter().map(|arg| arg.to_string()).collect();
+        let options = parse_options(&args).unwrap();
+        assert_eq!((options.directory.as_str(), options.rate, options.metrics, options.memory), ("saves/alpha", 10, 60, 512));
+        assert_eq!(options.record.as_deref(), Some("events.log"));
+        assert_eq!(parse_options(&["saves/alpha".to_string(), "--history".to_string(), "600".to_string()]).unwrap().history, 600);
+        assert!(parse_options(&args[1..]).is_err());
+
+        let mut simulation = Simulation::new(World::new(), EntityManager::new());
+        let mut admin = Admin::default();
+        assert_eq!(command(&mut simulation, &mut admin, "pause"), Reply::Continue("paused".to_string()));
+        assert_eq!(command(&mut simulation, &mut admin, "step 3"), Reply::Continue("tick 3".to_string()));
+        assert_eq!(command(&mut simulation, &mut admin, "speed 4"), Reply::Continue("speed x4".to_string()));
+        assert_eq!(
+            command(&mut simulation, &mut admin, "status"),
+            Reply::Continue("tick 3 at 20 ticks/s x4 (paused), 0 chunks, 0 entities, 0 cpus".to_string())
+        );
+        assert_eq!(command(&mut simulation, &mut admin, "metrics"), Reply::Continue(simulation.metrics().log_line()));
+        assert_eq!(command(&mut simulation, &mut admin, "pause 0:0"), Reply::Continue("entity 0:0 has no cpu".to_string()));
+        assert_eq!(command(&mut simulation, &mut admin, "cpus"), Reply::Continue("no cpus".to_string()));
+        assert_eq!(command(&mut simulation, &mut admin, "save"), Reply::Continue("saved at tick 3".to_string()));
+        assert_eq!(command(&mut simulation, &mut admin, "stop"), Reply::Stop);
+    }
+}
@@ -0,0 +1,419 @@
+//!
+//! Hivemind World Tool
+//!
+//! Inspects and edits a world save directory while no server is running it.
+//!
+//! ```text
+//! hivemind-world info <world directory>
+//! hivemind-world export <world directory> <x1> <y1> <z1> <x2> <y2> <z2> <structure file>
+//! hivemind-world import <world directory> <structure file> <x> <y> <z> [--rotate <degrees>] [--mirror-x] [--mirror-z] [--no-air]
+//! hivemind-world prune <world directory> [--dry-run]
+//! hivemind-world migrate <world directory>
+//! hivemind-world events <world directory> <event log> <output file> [--columns]
+//! ```
+//!
+//! `info` lists every region with its format version, Chunks, entities and
+//! size, followed by a histogram of the materials of every saved Block.
+//! `export` copies an inclusive box of Blocks to a structure file and
+//! `import` pastes one with its minimum corner at a Block, both loading the
+//! Chunks they touch from the save. `prune` deletes regions which no entity
+//! is stored in, which hold only terrain nothing has visited since it was
+//! generated. `migrate` brings region files and the structure files in the
+//! `blueprints` directory up to this build's format versions, compacting
+//! region files as it goes. `events` exports an event log recorded from the
+//! world, see `hivemind::recorder`, as CSV or with `--columns` in columnar
+//! form, naming materials as the save does.
+//!
+
+extern crate hivemind;
+
+use hivemind::migrate::{Artifact, Migrations};
+use hivemind::model::material::{MaterialId, MaterialRegistry};
+use hivemind::model::persist::read_entities;
+use hivemind::model::storage::{decode_chunk, read_region, RegionStorage};
+use hivemind::model::structure::{Placement, Rotation, Structure};
+use hivemind::model::update::BlockPosition;
+use hivemind::model::world::{chunk_of, Chunk, Vector2, World, CHUNK_SIZE};
+use hivemind::recorder::{export_columns, export_csv, read_events};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "usage: hivemind-world info <world directory>
+       hivemind-world export <world directory> <x1> <y1> <z1> <x2> <y2> <z2> <structure file>
+       hivemind-world import <world directory> <structure file> <x> <y> <z> [--rotate <degrees>] [--mirror-x] [--mirror-z] [--no-air]
+       hivemind-world prune <world directory> [--dry-run]
+       hivemind-world migrate <world directory>
+       hivemind-world events <world directory> <event log> <output file> [--columns]";
+
+///
+/// Subcommand and its options
+///
+#[derive(Clone, PartialEq, Debug)]
+enum Command {
+    Info { directory: String },
+    Export { directory: String, min: BlockPosition, max: BlockPosition, file: String },
+    Import { directory: String, file: String, origin: BlockPosition, placement: Placement },
+    Prune { directory: String, dry_run: bool },
+    Migrate { directory: String },
+    Events { directory: String, log: String, output: String, columnar: bool },
+}
+
+fn parse_command(args: &[String]) -> Result<Command, String> {
+    let subcommand = args.first().ok_or("missing command")?;
+    let mut words = Vec::new();
+    let mut placement = Placement::default();
+    let mut dry_run = false;
+    let mut columnar = false;
+    let mut args = args[1..].iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rotate" => {
+                placement.rotation = match number::<u32>(args.next(), "--rotate")? {
+                    0 => Rotation::None,
+                    90 => Rotation::Clockwise90,
+                    180 => Rotation::Clockwise180,
+                    270 => Rotation::Clockwise270,
+                    _ => return Err("--rotate takes 0, 90, 180 or 270".to_string()),
+                }
+            }
+            "--mirror-x" => placement.mirror_x = true,
+            "--mirror-z" => placement.mirror_z = true,
+            "--no-air" => placement.include_air = false,
+            "--dry-run" => dry_run = true,
+            "--columns" => columnar = true,
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            word => words.push(word),
+        }
+    }
+    let position = |at: usize| -> Result<BlockPosition, String> {
+        let coordinate = |index: usize| words.get(at + index).and_then(|word| word.parse::<u64>().ok()).ok_or_else(|| format!("{} needs block coordinates", subcommand));
+        Ok((coordinate(0)?, coordinate(1)? as usize, coordinate(2)?))
+    };
+    let directory = words.first().ok_or("missing world directory")?.to_string();
+    let (command, expected) = match subcommand.as_str() {
+        "info" => (Command::Info { directory }, 1),
+        "export" => {
+            let file = words.get(7).ok_or("export needs a structure file")?.to_string();
+            (Command::Export { directory, min: position(1)?, max: position(4)?, file }, 8)
+        }
+        "import" => {
+            let file = words.get(1).ok_or("import needs a structure file")?.to_string();
+            (Command::Import { directory, file, origin: position(2)?, placement }, 5)
+        }
+        "prune" => (Command::Prune { directory, dry_run }, 1),
+        "migrate" => (Command::Migrate { directory }, 1),
+        "events" => {
+            let log = words.get(1).ok_or("events needs an event log")?.to_string();
+            let output = words.get(2).ok_or("events needs an output file")?.to_string();
+            (Command::Events { directory, log, output, columnar }, 3)
+        }
+        other => return Err(format!("unknown command {}", other)),
+    };
+    match words.get(expected) {
+        Some(extra) => Err(format!("unexpected argument {}", extra)),
+        None => Ok(command),
+    }
+}
+
+fn number<T: std::str::FromStr>(value: Option<&String>, name: &str) -> Result<T, String> {
+    value.and_then(|value| value.parse().ok()).ok_or_else(|| format!("{} needs a number", name))
+}
+
+///
+/// Saved contents of a region file
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct RegionStats {
+    region: Vector2<u64>,
+    version: u16,
+    bytes: u64,
+    chunks: usize,
+    entities: usize,
+    /// Blocks by material
+    materials: BTreeMap<MaterialId, u64>,
+}
+
+fn region_stats(storage: &RegionStorage, region: Vector2<u64>) -> Result<RegionStats, String> {
+    let path = storage.region_path(region);
+    let bytes = fs::read(&path).map_err(|error| format!("unable to read {}: {}", path.display(), error))?;
+    let corrupt = |error: ::std::io::Error| format!("{}: {}", path.display(), error);
+    let version = Artifact::Region.version_of(&bytes).map_err(corrupt)?;
+    let mut stats = RegionStats { region, version, bytes: bytes.len() as u64, chunks: 0, entities: 0, materials: BTreeMap::new() };
+    let (chunks, entities) = read_region(bytes).map_err(corrupt)?;
+    let mut chunk = Chunk::new();
+    for payload in chunks.iter().flatten() {
+        decode_chunk(&mut &payload[..], &mut chunk).map_err(corrupt)?;
+        stats.chunks += 1;
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    *stats.materials.entry(chunk.get_block(x, y, z).material()).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+    for payload in entities.iter().flatten() {
+        stats.entities += read_entities(&mut &payload[..]).map_err(corrupt)?.len();
+    }
+    Ok(stats)
+}
+
+fn open_storage(directory: &str) -> Result<RegionStorage, String> {
+    if !Path::new(directory).is_dir() {
+        return Err(format!("{} isn't a directory", directory));
+    }
+    RegionStorage::open(directory).map_err(|error| format!("unable to open {}: {}", directory, error))
+}
+
+fn info(directory: &str) -> Result<Vec<String>, String> {
+    let storage = open_storage(directory)?;
+    let regions = storage.regions().map_err(|error| error.to_string())?;
+    let stats = regions.into_iter().map(|region| region_stats(&storage, region)).collect::<Result<Vec<_>, String>>()?;
+    let mut lines = vec![format!(
+        "{} regions, {} chunks, {} entities, {} bytes",
+        stats.len(),
+        stats.iter().map(|stats| stats.chunks).sum::<usize>(),
+        stats.iter().map(|stats| stats.entities).sum::<usize>(),
+        stats.iter().map(|stats| stats.bytes).sum::<u64>(),
+    )];
+    let mut materials: BTreeMap<MaterialId, u64> = BTreeMap::new();
+    for stats in stats.iter() {
+        lines.push(format!(
+            "region {},{}: version {}, {} chunks, {} entities, {} bytes",
+            stats.region.x, stats.region.y, stats.version, stats.chunks, stats.entities, stats.bytes
+        ));
+        for (&material, &count) in stats.materials.iter() {
+            *materials.entry(material).or_insert(0) += count;
+        }
+    }
+    let total: u64 = materials.values().sum();
+    let registry = MaterialRegistry::default();
+    let mut histogram: Vec<(MaterialId, u64)> = materials.into_iter().collect();
+    histogram.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    for (material, count) in histogram {
+        let name = registry.get(material).map_or_else(|| format!("#{}", material.id()), |material| material.name().to_string());
+        lines.push(format!("{:>12} {:>10} {:>5.1}%", name, count, count as f64 * 100.0 / total as f64));
+    }
+    Ok(lines)
+}
+
+/// Load every saved Chunk with a Block in the inclusive box.
+fn load_box(world: &mut World, min: BlockPosition, max: BlockPosition) -> Result<(), String> {
+    let (low, _, _) = chunk_of(min.0.min(max.0), min.2.min(max.2));
+    let (high, _, _) = chunk_of(min.0.max(max.0), min.2.max(max.2));
+    for x in low.x..=high.x {
+        for z in low.y..=high.y {
+            world.load_chunk(Vector2::new(x, z)).map_err(|error| error.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+fn export(directory: &str, min: BlockPosition, max: BlockPosition, file: &str) -> Result<String, String> {
+    open_storage(directory)?;
+    let mut world = World::open(directory).map_err(|error| error.to_string())?;
+    load_box(&mut world, min, max)?;
+    let structure = Structure::copy(&world, min, max).map_err(|error| error.to_string())?;
+    let mut writer = BufWriter::new(File::create(file).map_err(|error| format!("unable to create {}: {}", file, error))?);
+    structure.save(&mut writer).map_err(|error| format!("unable to write {}: {}", file, error))?;
+    let size = structure.size();
+    Ok(format!("exported {}x{}x{} blocks to {}", size.0, size.1, size.2, file))
+}
+
+fn import(directory: &str, file: &str, origin: BlockPosition, placement: &Placement) -> Result<String, String> {
+    open_storage(directory)?;
+    let reader = File::open(file).map_err(|error| format!("unable to read {}: {}", file, error))?;
+    let structure = Structure::load(&mut BufReader::new(reader)).map_err(|error| format!("{}: {}", file, error))?;
+    let mut world = World::open(directory).map_err(|error| error.to_string())?;
+    let size = structure.placed_size(placement);
+    let max = (origin.0 + size.0 as u64 - 1, origin.1 + size.1 - 1, origin.2 + size.2 as u64 - 1);
+    load_box(&mut world, origin, max)?;
+    structure.paste(&mut world, origin, placement).map_err(|error| error.to_string())?;
+    world.save().map_err(|error| error.to_string())?;
+    Ok(format!("imported {}x{}x{} blocks at {} {} {}", size.0, size.1, size.2, origin.0, origin.1, origin.2))
+}
+
+/// Regions without entities, deleted unless `dry_run`.
+fn prune(directory: &str, dry_run: bool) -> Result<Vec<String>, String> {
+    let storage = open_storage(directory)?;
+    let mut lines = Vec::new();
+    let mut pruned = 0;
+    for region in storage.regions().map_err(|error| error.to_string())? {
+        let stats = region_stats(&storage, region)?;
+        if stats.entities > 0 {
+            continue;
+        }
+        if !dry_run {
+            fs::remove_file(storage.region_path(region)).map_err(|error| error.to_string())?;
+        }
+        lines.push(format!("{} region {},{} of {} chunks", if dry_run { "would prune" } else { "pruned" }, region.x, region.y, stats.chunks));
+        pruned += 1;
+    }
+    lines.push(format!("{} regions pruned", if dry_run { 0 } else { pruned }));
+    Ok(lines)
+}
+
+fn migrate(directory: &str) -> Result<Vec<String>, String> {
+    let storage = open_storage(directory)?;
+    let mut lines = Vec::new();
+    for region in storage.regions().map_err(|error| error.to_string())? {
+        let path = storage.region_path(region);
+        let bytes = fs::read(&path).map_err(|error| format!("unable to read {}: {}", path.display(), error))?;
+        let version = Artifact::Region.version_of(&bytes).map_err(|error| format!("{}: {}", path.display(), error))?;
+        let reclaimed = storage.compact(region).map_err(|error| format!("{}: {}", path.display(), error))?;
+        if version < Artifact::Region.current_version() || reclaimed > 0 {
+            lines.push(format!("region {},{}: version {} to {}, {} bytes reclaimed", region.x, region.y, version, Artifact::Region.current_version(), reclaimed));
+        }
+    }
+    let blueprints = Path::new(directory).join("blueprints");
+    if blueprints.is_dir() {
+        let migrations = Migrations::default();
+        for entry in fs::read_dir(&blueprints).map_err(|error| error.to_string())? {
+            let path = entry.map_err(|error| error.to_string())?.path();
+            let bytes = fs::read(&path).map_err(|error| format!("unable to read {}: {}", path.display(), error))?;
+            let version = match Artifact::Structure.version_of(&bytes) {
+                Ok(version) if version < Artifact::Structure.current_version() => version,
+                _ => continue,
+            };
+            let upgraded = migrations.upgrade(Artifact::Structure, bytes).map_err(|error| format!("{}: {}", path.display(), error))?;
+            fs::write(&path, upgraded).map_err(|error| format!("unable to write {}: {}", path.display(), error))?;
+            lines.push(format!("{}: version {} to {}", path.display(), version, Artifact::Structure.current_version()));
+        }
+    }
+    lines.push(format!("{} files migrated", lines.len()));
+    Ok(lines)
+}
+
+fn events(directory: &str, log: &str, output: &str, columnar: bool) -> Result<String, String> {
+    let world = World::open(directory).map_err(|error| format!("unable to open {}: {}", directory, error))?;
+    let file = File::open(log).map_err(|error| format!("unable to open {}: {}", log, error))?;
+    let records = read_events(&mut BufReader::new(file)).map_err(|error| format!("unable to read {}: {}", log, error))?;
+    let materials = world.materials();
+    let name = |id: MaterialId| materials.get(id).map_or_else(|| id.id().to_string(), |material| material.name().to_string());
+    let mut writer = BufWriter::new(File::create(output).map_err(|error| format!("unable to create {}: {}", output, error))?);
+    let exported = match columnar {
+        true => export_columns(&records, &name, &mut writer),
+        false => export_csv(&records, &name, &mut writer),
+    };
+    exported.and_then(|_| writer.flush()).map_err(|error| format!("unable to write {}: {}", output, error))?;
+    Ok(format!("exported {} events to {}", records.len(), output))
+}
+
+fn execute(command: &Command) -> Result<Vec<String>, String> {
+    match *command {
+        Command::Info { ref directory } => info(directory),
+        Command::Export { ref directory, min, max, ref file } => export(directory, min, max, file).map(|line| vec![line]),
+        Command::Import { ref directory, ref file, origin, ref placement } => import(directory, file, origin, placement).map(|line| vec![line]),
+        Command::Prune { ref directory, dry_run } => prune(directory, dry_run),
+        Command::Migrate { ref directory } => migrate(directory),
+        Command::Events { ref directory, ref log, ref output, columnar } => events(directory, log, output, columnar).map(|line| vec![line]),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match parse_command(&args) {
+        Ok(command) => command,
+        Err(error) => {
+            eprintln!("{}\n{}", error, USAGE);
+            process::exit(2);
+        }
+    };
+    match execute(&command) {
+        Ok(lines) => {
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{execute, parse_command, Command};
+    use hivemind::model::storage::REGION_SIZE;
+    use hivemind::model::entity::EntityManager;
+    use hivemind::model::persist::StoredEntity;
+    use hivemind::model::structure::Rotation;
+    use hivemind::model::world::{Block, Chunk, Vector2, World};
+    use hivemind::recorder::EventRecorder;
+    use hivemind::simulation::Simulation;
+    use std::env;
+    use std::fs::{self, File};
+    use std::process;
+
+    #[test]
+    pub fn test_world_tool() {
+        let args: Vec<String> = ["import", "saves/alpha", "tower.hvst", "1", "2", "3", "--rotate", "90"].iter().map(|arg| arg.to_string()).collect();
+        match parse_command(&args).unwrap() {
+            Command::Import { origin, placement, .. } => assert_eq!((origin, placement.rotation), ((1, 2, 3), Rotation::Clockwise90)),
+            command => panic!("parsed {:?}", command),
+        }
+        assert!(parse_command(&args[..5]).is_err());
+        assert!(parse_command(&["info".to_string(), "a".to_string(), "b".to_string()]).is_err());
+
+        // A save with a Chunk near the origin holding an entity and one far away
+        let directory = env::temp_dir().join(format!("hivemind-world-tool-{}", process::id()));
+        let name = directory.to_str().unwrap().to_string();
+        let mut world = World::open(&directory).unwrap();
+        let metal = world.materials().id("metal").unwrap();
+        world.insert_chunk(Vector2::new(0, 0), Box::new(Chunk::new()));
+        world.insert_chunk(Vector2::new(REGION_SIZE, 0), Box::new(Chunk::new()));
+        world.set_block(1, 2, 3, Block::new(metal));
+        world.set_block(1, 3, 3, Block::new(metal));
+        world.save().unwrap();
+        world.storage().unwrap().write_entities(Vector2::new(0, 0), &[StoredEntity { components: Vec::new() }]).unwrap();
+
+        let run = |line: &str| {
+            let args: Vec<String> = line.replace("WORLD", &name).split_whitespace().map(String::from).collect();
+            execute(&parse_command(&args).unwrap())
+        };
+        let info = run("info WORLD").unwrap();
+        assert!(info[0].starts_with("2 regions, 2 chunks, 1 entities"));
+        assert!(info[1].starts_with("region 0,0: version 3, 1 chunks, 1 entities"));
+        assert!(info[3].trim_start().starts_with("air"));
+        assert!(info[4].trim_start().starts_with("metal          2"));
+
+        // Export and import a column of metal
+        let file = directory.join("column.hvst");
+        let file = file.to_str().unwrap();
+        assert_eq!(run(&format!("export WORLD 1 2 3 1 3 3 {}", file)).unwrap(), vec![format!("exported 1x2x1 blocks to {}", file)]);
+        assert!(run(&format!("import WORLD {} 5 0 5", file)).unwrap()[0].starts_with("imported 1x2x1"));
+        let mut world = World::open(&directory).unwrap();
+        world.load_chunk(Vector2::new(0, 0)).unwrap();
+        assert_eq!(world.get_block(5, 1, 5), Some(Block::new(metal)));
+        assert!(run(&format!("import WORLD {} 100 0 5", file)).is_err());
+
+        // Only the region without entities is pruned
+        assert_eq!(run("prune WORLD --dry-run").unwrap(), vec!["would prune region 1,0 of 1 chunks", "0 regions pruned"]);
+        assert_eq!(run("prune WORLD").unwrap(), vec!["pruned region 1,0 of 1 chunks", "1 regions pruned"]);
+        assert!(run("info WORLD").unwrap()[0].starts_with("1 regions, 1 chunks, 1 entities"));
+        run("migrate WORLD").unwrap();
+        assert_eq!(run("migrate WORLD").unwrap(), vec!["0 files migrated"]);
+
+        // Export a recorded metal Block change with the save's material names
+        let log = directory.join("events.log");
+        let mut world = World::new();
+        world.insert_chunk(Vector2::new(0, 0), Box::new(Chunk::new()));
+        let mut simulation = Simulation::new(world, EntityManager::new());
+        simulation.set_recorder(Some(EventRecorder::new(Box::new(File::create(&log).unwrap())).unwrap()));
+        simulation.world_mut().set_block(0, 0, 0, Block::new(metal));
+        simulation.step();
+        simulation.set_recorder(None);
+        let csv = directory.join("events.csv");
+        assert_eq!(run(&format!("events WORLD {} {}", log.display(), csv.display())).unwrap(), vec![format!("exported 1 events to {}", csv.display())]);
+        assert_eq!(fs::read_to_string(&csv).unwrap().lines().nth(1), Some("1,block,0,0,0,,air,metal,,,,"));
+        run(&format!("events WORLD {} {} --columns", log.display(), csv.display())).unwrap();
+        assert_eq!(&fs::read(&csv).unwrap()[..4], b"HVCL");
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
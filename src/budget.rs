@@ -0,0 +1,93 @@
+//!
+//! Memory Budget
+//!
+//! Accounts for the bytes a Simulation holds in loaded Chunks, CPU memories
+//! and component stores. Given a MemoryBudget the Simulation evicts Chunks
+//! after every tick while it is over: least recently used first, and only
+//! Chunks which are clean, so eviction never writes, and hold no entities.
+//! A Chunk counts as used when it is loaded, written or touched.
+//!
+//! Figures are estimates of the fixed size of each structure: what a
+//! component owns on the heap, such as an Inventory's items, isn't counted.
+//!
+
+///
+/// Bytes held, by owner
+///
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct MemoryUsage {
+    /// Loaded Chunks of every World, with their heightmaps
+    pub chunks: usize,
+    /// CPU memory pages, shared pages counted once
+    pub cpus: usize,
+    /// Component stores of the EntityManager
+    pub components: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize { self.chunks + self.cpus + self.components }
+}
+
+///
+/// Most memory a Simulation should hold
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct MemoryBudget {
+    /// Bytes
+    pub limit: usize,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> MemoryBudget { MemoryBudget { limit } }
+    /// Bytes over the budget, 0 when within it.
+    pub fn excess(&self, usage: &MemoryUsage) -> usize { usage.total().saturating_sub(self.limit) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryBudget;
+    use metrics::CHUNKS_EVICTED;
+    use model::component::Position;
+    use model::entity::EntityManager;
+    use model::world::{Block, Chunk, Vector2, World};
+    use simulation::Simulation;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    pub fn test_memory_budget() {
+        let directory = env::temp_dir().join(format!("hivemind-budget-{}", process::id()));
+        let mut simulation = Simulation::new(World::open(&directory).unwrap(), EntityManager::new());
+        let chunks: Vec<_> = (0..4).map(|x| Vector2::new(x, 0)).collect();
+        for &position in chunks.iter() {
+            simulation.world_mut().insert_chunk(position, Box::new(Chunk::new()));
+        }
+        let drone = simulation.entities_mut().create_entity();
+        simulation.entities_mut().add_component(drone, Position::from_f64(70.0, 1.0, 1.0));
+        let usage = simulation.memory_usage();
+        assert_eq!(usage.chunks, simulation.world().chunk_bytes());
+        assert!(usage.components > 0 && usage.cpus == 0);
+
+        // Nothing is evicted before it is saved
+        let per_chunk = usage.chunks / 4;
+        simulation.set_memory_budget(Some(MemoryBudget::new(usage.total() - 2 * per_chunk)));
+        simulation.step();
+        assert_eq!(simulation.world().loaded_chunks().len(), 4);
+
+        // Then the least recently used clean chunks go first, sparing those with entities
+        simulation.world_mut().save().unwrap();
+        let rock = Block::new(simulation.world().materials().id("rock").unwrap());
+        simulation.world_mut().set_block(0, 0, 0, rock);
+        simulation.world_mut().save_dirty().unwrap();
+        simulation.world_mut().touch_chunk(chunks[1]);
+        simulation.step();
+        assert_eq!(simulation.world().loaded_chunks(), vec![chunks[1], chunks[2]]);
+        assert_eq!(simulation.metrics().counter(CHUNKS_EVICTED).total, 2);
+        assert!(simulation.memory_usage().total() <= usage.total() - 2 * per_chunk);
+        assert!(simulation.world_mut().load_chunk(chunks[0]).unwrap());
+        assert_eq!(simulation.world().get_block(0, 0, 0), Some(rock));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
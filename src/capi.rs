@@ -0,0 +1,608 @@
+//!
+//! C API
+//!
+//! extern "C" functions for embedding the simulation in engines written in
+//! other languages, enabled by the `capi` feature and declared for C and C++
+//! in `include/hivemind.h`. CPUs, clusters and worlds are opaque handles
+//! created and destroyed through the API; everything else crosses as plain
+//! numbers, word buffers and the repr(C) structs below.
+//!
+//! Functions return HM_OK or a negative error code, with the message of the
+//! last error on the calling thread available from `hm_last_error`. Handles
+//! must come from the matching `_new` and not be used after their `_free`,
+//! and buffers must hold at least the length passed with them; null handles
+//! and buffers are caught and reported as HM_ERR_NULL.
+//!
+#![allow(clippy::missing_safety_doc)]
+
+use devices::Device;
+use model::component::Position;
+use model::entity::{EntityID, EntityManager};
+use model::material::MaterialId;
+use model::observer::{EventFilter, SubscriberId, WorldEvent};
+use model::world::{Block, Chunk, Vector2, World};
+use simulation::Simulation;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::ptr;
+use std::slice;
+use vcpu::cluster::{CpuComponent, CpuId, HiveCluster};
+use vcpu::cpu::VCPU16;
+
+pub const HM_OK: i32 = 0;
+/// A handle or buffer was null
+pub const HM_ERR_NULL: i32 = -1;
+/// No such CPU, entity, register or Block
+pub const HM_ERR_NOT_FOUND: i32 = -2;
+/// The operation failed, see `hm_last_error`
+pub const HM_ERR_FAILED: i32 = -3;
+
+pub const HM_EVENT_BLOCK_CHANGED: u32 = 0;
+pub const HM_EVENT_CHUNK_LOADED: u32 = 1;
+pub const HM_EVENT_CHUNK_GENERATED: u32 = 2;
+pub const HM_EVENT_CHUNK_UNLOADED: u32 = 3;
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn fail(code: i32, message: &str) -> i32 {
+    LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(message.replace('\0', " ")).unwrap_or_default());
+    code
+}
+
+///
+/// CPU of a cluster
+///
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct HmCpuId {
+    pub slot: u32,
+    pub generation: u32,
+}
+
+impl From<CpuId> for HmCpuId {
+    fn from(id: CpuId) -> HmCpuId { HmCpuId { slot: id.slot() as u32, generation: id.generation() } }
+}
+
+impl From<HmCpuId> for CpuId {
+    fn from(id: HmCpuId) -> CpuId { CpuId::from_parts(id.slot as usize, id.generation) }
+}
+
+///
+/// Entity of a world
+///
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct HmEntity {
+    pub slot: u32,
+    pub suffix: u32,
+}
+
+impl From<EntityID> for HmEntity {
+    fn from(id: EntityID) -> HmEntity { HmEntity { slot: id.slot() as u32, suffix: id.suffix() as u32 } }
+}
+
+impl From<HmEntity> for EntityID {
+    fn from(id: HmEntity) -> EntityID { EntityID::new(id.slot as usize, id.suffix as usize) }
+}
+
+///
+/// World event, one of the HM_EVENT kinds
+///
+/// Chunk events give the Chunk in x and z; Block changes give the Block and
+/// its material before and after.
+///
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct HmEvent {
+    pub kind: u32,
+    pub x: u64,
+    pub y: u64,
+    pub z: u64,
+    pub before: u16,
+    pub after: u16,
+}
+
+impl From<WorldEvent> for HmEvent {
+    fn from(event: WorldEvent) -> HmEvent {
+        let chunk = |kind, position: Vector2<u64>| HmEvent { kind, x: position.x, z: position.y, ..HmEvent::default() };
+        match event {
+            WorldEvent::BlockChanged { position: (x, y, z), before, after } => HmEvent {
+                kind: HM_EVENT_BLOCK_CHANGED,
+                x,
+                y: y as u64,
+                z,
+                before: before.material().id(),
+                after: after.material().id(),
+            },
+            WorldEvent::ChunkLoaded(position) => chunk(HM_EVENT_CHUNK_LOADED, position),
+            WorldEvent::ChunkGenerated(position) => chunk(HM_EVENT_CHUNK_GENERATED, position),
+            WorldEvent::ChunkUnloaded(position) => chunk(HM_EVENT_CHUNK_UNLOADED, position),
+        }
+    }
+}
+
+///
+/// Cluster of CPUs without a world to act on
+///
+pub struct HmCluster {
+    cluster: HiveCluster,
+    world: World,
+    entities: EntityManager,
+}
+
+///
+/// Simulation with the events not yet polled
+///
+pub struct HmWorld {
+    simulation: Simulation,
+    subscriber: SubscriberId,
+    events: VecDeque<WorldEvent>,
+}
+
+unsafe fn words<'a>(buffer: *const u16, length: usize) -> Option<&'a [u16]> {
+    match length {
+        0 => Some(&[]),
+        _ if buffer.is_null() => None,
+        _ => Some(slice::from_raw_parts(buffer, length)),
+    }
+}
+
+unsafe fn words_mut<'a>(buffer: *mut u16, length: usize) -> Option<&'a mut [u16]> {
+    match length {
+        0 => Some(&mut []),
+        _ if buffer.is_null() => None,
+        _ => Some(slice::from_raw_parts_mut(buffer, length)),
+    }
+}
+
+fn read_memory(cpu: &VCPU16, address: u16, buffer: &mut [u16]) {
+    for (offset, word) in buffer.iter_mut().enumerate() {
+        *word = cpu.get_memory(address.wrapping_add(offset as u16));
+    }
+}
+
+fn write_memory(cpu: &mut VCPU16, address: u16, buffer: &[u16]) {
+    for (offset, &word) in buffer.iter().enumerate() {
+        cpu.set_memory(address.wrapping_add(offset as u16), word);
+    }
+}
+
+/// Message of the last error on this thread, valid until the next call failing.
+#[no_mangle]
+pub extern "C" fn hm_last_error() -> *const c_char { LAST_ERROR.with(|last| last.borrow().as_ptr()) }
+
+#[no_mangle]
+pub extern "C" fn hm_cpu_new() -> *mut VCPU16 { Box::into_raw(Box::default()) }
+
+#[no_mangle]
+pub unsafe extern "C" fn hm_cpu_free(cpu: *mut VCPU16) {
+    if !cpu.is_null() {
+        drop(Box::from_raw(cpu));
+    }
+}
+
+/// Reset and load `length` words of `rom` at address 0.
+#[no_mangle]
+pub unsafe extern "C" fn hm_cpu_load_rom(cpu: *mut VCPU16, rom: *const u16, length: usize) -> i32 {
+    let (cpu, rom) = match (cpu.as_mut(), words(rom, length)) {
+        (Some(cpu), Some(rom)) => (cpu, rom),
+        _ => return fail(HM_ERR_NULL, "null cpu or rom"),
+    };
+    cpu.reset(true);
+    match cpu.load_rom(rom) {
+        Ok(()) => HM_OK,
+        Err(error) => fail(HM_ERR_FAILED, &error.to_string()),
+    }
+}
+
+/// Run `cycles` cycles with no devices attached.
+#[no_mangle]
+pub unsafe extern "C" fn hm_cpu_run(cpu: *mut VCPU16, cycles: u64) -> i32 {
+    match cpu.as_mut() {
+        Some(cpu) => {
+            cpu.run_with(cycles, &mut Vec::<Box<dyn Device>>::new());
+            HM_OK
+        }
+        None => fail(HM_ERR_NULL, "null cpu"),
+    }
+}
+
+/// Copy `length` words from `address`, wrapping past the end of memory.
+#[no_mangle]
+pub unsafe extern "C" fn hm_cpu_read_memory(cpu: *const VCPU16, address: u16, buffer: *mut u16, length: usize) -> i32 {
+    match (cpu.as_ref(), words_mut(buffer, length)) {
+        (Some(cpu), Some(buffer)) => {
+            read_memory(cpu, address, buffer);
+            HM_OK
+        }
+        _ => fail(HM_ERR_NULL, "null cpu or buffer"),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hm_cpu_write_memory(cpu: *mut VCPU16, address: u16, buffer: *const u16, length: usize) -> i32 {
+    match (cpu.as_mut(), words(buffer, length)) {
+        (Some(cpu), Some(buffer)) => {
+            write_memory(cpu, address, buffer);
+            HM_OK
+        }
+        _ => fail(HM_ERR_NULL, "null cpu or buffer"),
+    }
+}
+
+/// Copy A, B, C, X, Y, Z, I, J, PC, SP, EX and IA into a buffer of 12.
+#[no_mangle]
+pub unsafe extern "C" fn hm_cpu_registers(cpu: *const VCPU16, registers: *mut u16) -> i32 {
+    match (cpu.as_ref(), words_mut(registers, 12)) {
+        (Some(cpu), Some(registers)) => {
+            registers.copy_from_slice(&[
+                cpu.get_a(), cpu.get_b(), cpu.get_c(), cpu.get_x(), cpu.get_y(), cpu.get_z(), cpu.get_i(), cpu.get_j(),
+                cpu.get_pc(), cpu.get_sp(), cpu.get_ex(), cpu.get_ia(),
+            ]);
+            HM_OK
+        }
+        _ => fail(HM_ERR_NULL, "null cpu or registers"),
+    }
+}
+
+/// Set a register by its index in `hm_cpu_registers`.
+#[no_mangle]
+pub unsafe extern "C" fn hm_cpu_set_register(cpu: *mut VCPU16, index: u32, value: u16) -> i32 {
+    let setters: [fn(&mut VCPU16, u16); 12] = [
+        VCPU16::set_a, VCPU16::set_b, VCPU16::set_c, VCPU16::set_x, VCPU16::set_y, VCPU16::set_z, VCPU16::set_i, VCPU16::set_j,
+        VCPU16::set_pc, VCPU16::set_sp, VCPU16::set_ex, VCPU16::set_ia,
+    ];
+    match (cpu.as_mut(), setters.get(index as usize)) {
+        (None, _) => fail(HM_ERR_NULL, "null cpu"),
+        (_, None) => fail(HM_ERR_NOT_FOUND, "no such register"),
+        (Some(cpu), Some(set)) => {
+            set(cpu, value);
+            HM_OK
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hm_cpu_is_halted(cpu: *const VCPU16) -> bool { cpu.as_ref().is_some_and(VCPU16::is_halted) }
+
+#[no_mangle]
+pub extern "C" fn hm_cluster_new() -> *mut HmCluster {
+    Box::into_raw(Box::new(HmCluster { cluster: HiveCluster::new(), world: World::new(), entities: EntityManager::new() }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hm_cluster_free(cluster: *mut HmCluster) {
+    if !cluster.is_null() {
+        drop(Box::from_raw(cluster));
+    }
+}
+
+/// Start a CPU running `length` words of `rom`, storing its id in `id`.
+#[no_mangle]
+pub unsafe extern "C" fn hm_cluster_spawn(cluster: *mut HmCluster, rom: *const u16, length: usize, id: *mut HmCpuId) -> i32 {
+    let (cluster, rom, id) = match (cluster.as_mut(), words(rom, length), id.as_mut()) {
+        (Some(cluster), Some(rom), Some(id)) => (cluster, rom, id),
+        _ => return fail(HM_ERR_NULL, "null cluster, rom or id"),
+    };
+    match cluster.cluster.spawn(rom) {
+        Ok(spawned) => {
+            *id = spawned.into();
+            HM_OK
+        }
+        Err(error) => fail(HM_ERR_FAILED, &error.to_string()),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hm_cluster_release(cluster: *mut HmCluster, id: HmCpuId) -> i32 {
+    match cluster.as_mut().map(|cluster| cluster.cluster.release(id.into())) {
+        Some(true) => HM_OK,
+        Some(false) => fail(HM_ERR_NOT_FOUND, "no such cpu"),
+        None => fail(HM_ERR_NULL, "null cluster"),
+    }
+}
+
+/// Run every CPU of the cluster for one tick.
+#[no_mangle]
+pub unsafe extern "C" fn hm_cluster_tick(cluster: *mut HmCluster) -> i32 {
+    match cluster.as_mut() {
+        Some(cluster) => {
+            cluster.cluster.tick(&mut cluster.world, &mut cluster.entities);
+            HM_OK
+        }
+        None => fail(HM_ERR_NULL, "null cluster"),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hm_cluster_read_memory(cluster: *const HmCluster, id: HmCpuId, address: u16, buffer: *mut u16, length: usize) -> i32 {
+    let (cluster, buffer) = match (cluster.as_ref(), words_mut(buffer, length)) {
+        (Some(cluster), Some(buffer)) => (cluster, buffer),
+        _ => return fail(HM_ERR_NULL, "null cluster or buffer"),
+    };
+    match cluster.cluster.get(id.into()) {
+        Some(cpu) => {
+            read_memory(cpu, address, buffer);
+            HM_OK
+        }
+        None => fail(HM_ERR_NOT_FOUND, "no such cpu"),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hm_cluster_write_memory(cluster: *mut HmCluster, id: HmCpuId, address: u16, buffer: *const u16, length: usize) -> i32 {
+    let (cluster, buffer) = match (cluster.as_mut(), words(buffer, length)) {
+        (Some(cluster), Some(buffer)) => (cluster, buffer),
+        _ => return fail(HM_ERR_NULL, "null cluster or buffer"),
+    };
+    match cluster.cluster.get_mut(id.into()) {
+        Some(cpu) => {
+            write_memory(cpu, address, buffer);
+            HM_OK
+        }
+        None => fail(HM_ERR_NOT_FOUND, "no such cpu"),
+    }
+}
+
+/// Empty world with no Chunks loaded.
+#[no_mangle]
+pub extern "C" fn hm_world_new() -> *mut HmWorld {
+    let mut simulation = Simulation::new(World::new(), EntityManager::new());
+    let subscriber = simulation.world_mut().subscribe(EventFilter::all());
+    Box::into_raw(Box::new(HmWorld { simulation, subscriber, events: VecDeque::new() }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hm_world_free(world: *mut HmWorld) {
+    if !world.is_null() {
+        drop(Box::from_raw(world));
+    }
+}
+
+/// Put an empty Chunk at Chunk position x, z, replacing any there.
+#[no_mangle]
+pub unsafe extern "C" fn hm_world_insert_chunk(world: *mut HmWorld, x: u64, z: u64) -> i32 {
+    match world.as_mut() {
+        Some(world) => {
+            world.simulation.world_mut().insert_chunk(Vector2::new(x, z), Box::new(Chunk::new()));
+            HM_OK
+        }
+        None => fail(HM_ERR_NULL, "null world"),
+    }
+}
+
+/// Store the material of a Block in `material`.
+#[no_mangle]
+pub unsafe extern "C" fn hm_world_get_block(world: *const HmWorld, x: u64, y: u64, z: u64, material: *mut u16) -> i32 {
+    let (world, material) = match (world.as_ref(), material.as_mut()) {
+        (Some(world), Some(material)) => (world, material),
+        _ => return fail(HM_ERR_NULL, "null world or material"),
+    };
+    match world.simulation.world().get_block(x, y as usize, z) {
+        Some(block) => {
+            *material = block.material().id();
+            HM_OK
+        }
+        None => fail(HM_ERR_NOT_FOUND, "block isn't loaded"),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hm_world_set_block(world: *mut HmWorld, x: u64, y: u64, z: u64, material: u16) -> i32 {
+    match world.as_mut().map(|world| world.simulation.world_mut().set_block(x, y as usize, z, Block::new(MaterialId::new(material)))) {
+        Some(true) => HM_OK,
+        Some(false) => fail(HM_ERR_NOT_FOUND, "block isn't loaded"),
+        None => fail(HM_ERR_NULL, "null world"),
+    }
+}
+
+/// Place a drone at x, y, z running `length` words of `rom`, storing it in `entity`.
+#[no_mangle]
+pub unsafe extern "C" fn hm_world_spawn(world: *mut HmWorld, x: f64, y: f64, z: f64, rom: *const u16, length: usize, entity: *mut HmEntity) -> i32 {
+    let (world, rom, entity) = match (world.as_mut(), words(rom, length), entity.as_mut()) {
+        (Some(world), Some(rom), Some(entity)) => (world, rom, entity),
+        _ => return fail(HM_ERR_NULL, "null world, rom or entity"),
+    };
+    let spawned = world.simulation.entities_mut().create_entity();
+    world.simulation.entities_mut().add_component(spawned, Position::from_f64(x, y, z));
+    match world.simulation.attach(spawned, rom) {
+        Ok(_) => {
+            *entity = spawned.into();
+            HM_OK
+        }
+        Err(error) => {
+            world.simulation.entities_mut().destroy_entity(spawned);
+            fail(HM_ERR_FAILED, &error.to_string())
+        }
+    }
+}
+
+///
+/// Copy up to `capacity` live entities into `entities` in slot order,
+/// returning how many there are in all.
+///
+#[no_mangle]
+pub unsafe extern "C" fn hm_world_entities(world: *const HmWorld, entities: *mut HmEntity, capacity: usize) -> usize {
+    let world = match world.as_ref() {
+        Some(world) => world,
+        None => return 0,
+    };
+    let live = world.simulation.entities().entities();
+    if !entities.is_null() {
+        for (index, entity) in live.iter().take(capacity).enumerate() {
+            ptr::write(entities.add(index), entity.into());
+        }
+    }
+    live.len()
+}
+
+/// Destroy an entity, its CPU being released on the next tick.
+#[no_mangle]
+pub unsafe extern "C" fn hm_world_destroy(world: *mut HmWorld, entity: HmEntity) -> i32 {
+    match world.as_mut().map(|world| world.simulation.entities_mut().destroy_entity(entity.into())) {
+        Some(true) => HM_OK,
+        Some(false) => fail(HM_ERR_NOT_FOUND, "no such entity"),
+        None => fail(HM_ERR_NULL, "null world"),
+    }
+}
+
+/// Store the position of an entity in a buffer of 3.
+#[no_mangle]
+pub unsafe extern "C" fn hm_world_position(world: *const HmWorld, entity: HmEntity, position: *mut f64) -> i32 {
+    let (world, position) = match world.as_ref() {
+        Some(world) if !position.is_null() => (world, slice::from_raw_parts_mut(position, 3)),
+        _ => return fail(HM_ERR_NULL, "null world or position"),
+    };
+    match world.simulation.entities().get_component::<Position>(entity.into()) {
+        Some(found) => {
+            position.copy_from_slice(&[found.x.to_f64(), found.y.to_f64(), found.z.to_f64()]);
+            HM_OK
+        }
+        None => fail(HM_ERR_NOT_FOUND, "entity has no position"),
+    }
+}
+
+/// Copy `length` words of a drone's memory from `address`.
+#[no_mangle]
+pub unsafe extern "C" fn hm_world_read_memory(world: *mut HmWorld, entity: HmEntity, address: u16, buffer: *mut u16, length: usize) -> i32 {
+    let (world, buffer) = match (world.as_mut(), words_mut(buffer, length)) {
+        (Some(world), Some(buffer)) => (world, buffer),
+        _ => return fail(HM_ERR_NULL, "null world or buffer"),
+    };
+    match drone_cpu(world, entity).and_then(|id| world.simulation.with_cpu(id, |cpu, _| read_memory(cpu, address, buffer))) {
+        Some(()) => HM_OK,
+        None => fail(HM_ERR_NOT_FOUND, "entity has no cpu"),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hm_world_write_memory(world: *mut HmWorld, entity: HmEntity, address: u16, buffer: *const u16, length: usize) -> i32 {
+    let (world, buffer) = match (world.as_mut(), words(buffer, length)) {
+        (Some(world), Some(buffer)) => (world, buffer),
+        _ => return fail(HM_ERR_NULL, "null world or buffer"),
+    };
+    match drone_cpu(world, entity).and_then(|id| world.simulation.with_cpu(id, |cpu, _| write_memory(cpu, address, buffer))) {
+        Some(()) => HM_OK,
+        None => fail(HM_ERR_NOT_FOUND, "entity has no cpu"),
+    }
+}
+
+fn drone_cpu(world: &HmWorld, entity: HmEntity) -> Option<CpuId> {
+    world.simulation.entities().get_component::<CpuComponent>(entity.into()).map(|component| component.cpu)
+}
+
+/// Run `ticks` Simulation ticks, returning the tick reached.
+#[no_mangle]
+pub unsafe extern "C" fn hm_world_step(world: *mut HmWorld, ticks: u32) -> u64 {
+    match world.as_mut() {
+        Some(world) => {
+            for _ in 0..ticks {
+                world.simulation.step();
+            }
+            world.simulation.tick()
+        }
+        None => 0,
+    }
+}
+
+///
+/// Copy up to `capacity` World events into `events` oldest first, returning
+/// the number copied. Events not copied are kept for the next poll.
+///
+#[no_mangle]
+pub unsafe extern "C" fn hm_world_poll_events(world: *mut HmWorld, events: *mut HmEvent, capacity: usize) -> usize {
+    let world = match world.as_mut() {
+        Some(world) if capacity == 0 || !events.is_null() => world,
+        _ => return 0,
+    };
+    let drained = world.simulation.world_mut().drain_events(world.subscriber);
+    world.events.extend(drained);
+    let count = capacity.min(world.events.len());
+    for (index, event) in world.events.drain(..count).enumerate() {
+        ptr::write(events.add(index), event.into());
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::ptr;
+
+    #[test]
+    pub fn test_capi() {
+        unsafe {
+            // A lone CPU
+            let cpu = hm_cpu_new();
+            assert_eq!(hm_cpu_load_rom(cpu, [0x8401, 0x8802].as_ptr(), 2), HM_OK);
+            assert_eq!(hm_cpu_run(cpu, 4), HM_OK);
+            let mut registers = [0; 12];
+            assert_eq!(hm_cpu_registers(cpu, registers.as_mut_ptr()), HM_OK);
+            assert_eq!(&registers[..2], &[1, 0]);
+            assert_eq!(hm_cpu_set_register(cpu, 12, 0), HM_ERR_NOT_FOUND);
+            assert_eq!(hm_cpu_write_memory(cpu, 0xFFFF, [7, 8].as_ptr(), 2), HM_OK);
+            let mut memory = [0; 2];
+            assert_eq!(hm_cpu_read_memory(cpu, 0xFFFF, memory.as_mut_ptr(), 2), HM_OK);
+            assert_eq!(memory, [7, 8]);
+            assert_eq!(hm_cpu_load_rom(cpu, ptr::null(), 2), HM_ERR_NULL);
+            assert_eq!(CStr::from_ptr(hm_last_error()).to_str().unwrap(), "null cpu or rom");
+            hm_cpu_free(cpu);
+
+            // A cluster
+            let cluster = hm_cluster_new();
+            let mut id = HmCpuId { slot: 0, generation: 0 };
+            assert_eq!(hm_cluster_spawn(cluster, [0x8401].as_ptr(), 1, &mut id), HM_OK);
+            assert_eq!(hm_cluster_tick(cluster), HM_OK);
+            assert_eq!(hm_cluster_read_memory(cluster, id, 0, memory.as_mut_ptr(), 1), HM_OK);
+            assert_eq!(memory[0], 0x8401);
+            assert_eq!(hm_cluster_release(cluster, id), HM_OK);
+            assert_eq!(hm_cluster_write_memory(cluster, id, 0, memory.as_ptr(), 1), HM_ERR_NOT_FOUND);
+            hm_cluster_free(cluster);
+
+            // A world with a drone, and its events
+            let world = hm_world_new();
+            let mut material = 0;
+            assert_eq!(hm_world_get_block(world, 0, 0, 0, &mut material), HM_ERR_NOT_FOUND);
+            assert_eq!(hm_world_insert_chunk(world, 0, 0), HM_OK);
+            assert_eq!(hm_world_set_block(world, 1, 0, 2, 1), HM_OK);
+            assert_eq!(hm_world_get_block(world, 1, 0, 2, &mut material), HM_OK);
+            assert_eq!(material, 1);
+            let mut events = [HmEvent::default(); 4];
+            assert_eq!(hm_world_poll_events(world, events.as_mut_ptr(), 1), 1);
+            assert_eq!(events[0].kind, HM_EVENT_CHUNK_LOADED);
+            assert_eq!(hm_world_poll_events(world, events.as_mut_ptr(), 4), 1);
+            assert_eq!(events[0], HmEvent { kind: HM_EVENT_BLOCK_CHANGED, x: 1, y: 0, z: 2, before: 0, after: 1 });
+
+            let mut drone = HmEntity { slot: 0, suffix: 0 };
+            assert_eq!(hm_world_spawn(world, 1.5, 1.0, 1.5, [0x8401].as_ptr(), 1, &mut drone), HM_OK);
+            assert_eq!(hm_world_step(world, 2), 2);
+            let mut position = [0.0; 3];
+            assert_eq!(hm_world_position(world, drone, position.as_mut_ptr()), HM_OK);
+            assert_eq!(hm_world_write_memory(world, drone, 0x100, [5].as_ptr(), 1), HM_OK);
+            assert_eq!(hm_world_read_memory(world, drone, 0xFF, memory.as_mut_ptr(), 2), HM_OK);
+            assert_eq!(memory, [0, 5]);
+            let mut entities = [HmEntity { slot: 9, suffix: 9 }; 2];
+            assert_eq!(hm_world_entities(world, entities.as_mut_ptr(), 2), 1);
+            assert_eq!(entities[0], drone);
+            assert_eq!(hm_world_destroy(world, drone), HM_OK);
+            assert_eq!(hm_world_entities(world, ptr::null_mut(), 0), 0);
+            assert_eq!(hm_world_destroy(world, drone), HM_ERR_NOT_FOUND);
+            hm_world_free(world);
+        }
+    }
+
+    #[test]
+    pub fn test_capi_header() {
+        // Every function is declared in the header
+        let header = include_str!("../include/hivemind.h");
+        for line in include_str!("capi.rs").lines().filter(|line| line.starts_with("pub ") && line.contains("extern \"C\" fn ")) {
+            let name = line.split("fn ").nth(1).and_then(|rest| rest.split('(').next()).unwrap();
+            assert!(header.contains(&format!("{}(", name)), "{} isn't in include/hivemind.h", name);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//!
+//! Binary Encoding Helpers
+//!
+//! All persisted and transmitted data is little-endian.
+//!
+use std::io::{self, Read, Write};
+
+pub fn write_u8(writer: &mut dyn Write, value: u8) -> io::Result<()> { writer.write_all(&[value]) }
+pub fn write_u16(writer: &mut dyn Write, value: u16) -> io::Result<()> { writer.write_all(&value.to_le_bytes()) }
+pub fn write_u32(writer: &mut dyn Write, value: u32) -> io::Result<()> { writer.write_all(&value.to_le_bytes()) }
+pub fn write_u64(writer: &mut dyn Write, value: u64) -> io::Result<()> { writer.write_all(&value.to_le_bytes()) }
+
+pub fn read_u8(reader: &mut dyn Read) -> io::Result<u8> {
+    let mut buffer = [0; 1];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer[0])
+}
+pub fn read_u16(reader: &mut dyn Read) -> io::Result<u16> {
+    let mut buffer = [0; 2];
+    reader.read_exact(&mut buffer)?;
+    Ok(u16::from_le_bytes(buffer))
+}
+pub fn read_u32(reader: &mut dyn Read) -> io::Result<u32> {
+    let mut buffer = [0; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_le_bytes(buffer))
+}
+pub fn read_u64(reader: &mut dyn Read) -> io::Result<u64> {
+    let mut buffer = [0; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+/// Unsigned LEB128, seven bits a byte, small values in one byte.
+pub fn write_varint(writer: &mut dyn Write, mut value: u64) -> io::Result<()> {
+    while value >= 0x80 {
+        write_u8(writer, (value as u8) | 0x80)?;
+        value >>= 7;
+    }
+    write_u8(writer, value as u8)
+}
+pub fn read_varint(reader: &mut dyn Read) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = read_u8(reader)?;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("varint is too long"))
+}
+
+/// Length prefixed UTF-8 string, at most 65535 bytes.
+pub fn write_string(writer: &mut dyn Write, value: &str) -> io::Result<()> {
+    if value.len() > u16::MAX as usize {
+        return Err(invalid_data("string is too long"));
+    }
+    write_u16(writer, value.len() as u16)?;
+    writer.write_all(value.as_bytes())
+}
+pub fn read_string(reader: &mut dyn Read) -> io::Result<String> {
+    let mut bytes = vec![0; read_u16(reader)? as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| invalid_data("string is not UTF-8"))
+}
+
+/// Error for structurally invalid input.
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,170 @@
+//!
+//! End-to-End Demo
+//!
+//! A small reference integration of the engine: a procedurally generated patch
+//! of world, a few drones each running a shipped ROM on their own VCPU, a text
+//! renderer and time controls. Run it with `cargo run --features demo`.
+//!
+use error::HivemindError;
+use model::entity::{EntityID, EntityManager};
+use model::world::{Vector2, World, CHUNK_SIZE};
+use model::worldgen::LayeredGenerator;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::fmt::Write;
+use vcpu::cluster::HiveCluster;
+
+/// Demo area edge length in Chunks
+const DEMO_CHUNKS: u64 = 2;
+/// Demo world seed
+const DEMO_SEED: u64 = 0x4869_7665;
+
+///
+/// Shipped Drone Firmware
+///
+/// ```text
+///         SET A, 0
+/// :loop   ADD A, 1
+///         SET PC, loop
+/// ```
+///
+pub const DRONE_ROM: [u16; 3] = [0x8401, 0x8802, 0x8B81];
+
+///
+/// Drone Blueprint Component
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Drone {
+    pub x: u64,
+    pub z: u64,
+}
+
+///
+/// Demo Hive
+///
+pub struct Hive {
+    world: World,
+    entities: EntityManager,
+    cluster: HiveCluster,
+    rng: XorShiftRng,
+    tick: u64,
+    paused: bool,
+}
+
+impl Hive {
+    /// Build the demo world with three drones.
+    pub fn demo() -> Hive {
+        let mut hive = Hive {
+            world: World::new(),
+            entities: EntityManager::new(),
+            cluster: HiveCluster::new(),
+            rng: XorShiftRng::from_seed([0x4869, 0x7665, 0x6d69, 0x6e64]),
+            tick: 0,
+            paused: false,
+        };
+        hive.generate();
+        for &(x, z) in [(4, 4), (20, 30), (50, 12)].iter() {
+            hive.spawn_drone(x, z, &DRONE_ROM).expect("drone rom fits in memory");
+        }
+        hive
+    }
+    /// Generate the demo area from the layered terrain generator.
+    fn generate(&mut self) {
+        let generator = LayeredGenerator::new(DEMO_SEED, self.world.materials());
+        for cx in 0..DEMO_CHUNKS {
+            for cz in 0..DEMO_CHUNKS {
+                self.world.generate_chunk(Vector2::new(cx, cz), &generator);
+            }
+        }
+    }
+    /// Spawn a drone running `rom` at column (x, z).
+    pub fn spawn_drone(&mut self, x: u64, z: u64, rom: &[u16]) -> Result<EntityID, HivemindError> {
+        let entity = self.entities.create_entity();
+        self.entities.add_component(entity, Drone { x, z });
+        if let Err(error) = self.cluster.attach(&mut self.entities, entity, rom) {
+            self.entities.destroy_entity(entity);
+            return Err(error);
+        }
+        Ok(entity)
+    }
+    pub fn world(&self) -> &World { &self.world }
+    pub fn entities(&self) -> &EntityManager { &self.entities }
+    pub fn cluster(&self) -> &HiveCluster { &self.cluster }
+    pub fn current_tick(&self) -> u64 { self.tick }
+    pub fn is_paused(&self) -> bool { self.paused }
+    pub fn pause(&mut self) { self.paused = true }
+    pub fn resume(&mut self) { self.paused = false }
+    /// Advance one tick unless paused.
+    pub fn tick(&mut self) {
+        if !self.paused {
+            self.step();
+        }
+    }
+    /// Advance one tick regardless of the pause state.
+    pub fn step(&mut self) {
+        self.cluster.tick(&mut self.world, &mut self.entities);
+        let size = DEMO_CHUNKS * CHUNK_SIZE as u64;
+        let rng = &mut self.rng;
+        for (_, drone) in self.entities.iter_mut::<Drone>() {
+            let (dx, dz) = *rng.choose(&[(1, 0), (-1, 0), (0, 1), (0, -1), (0, 0)]).unwrap();
+            drone.x = (drone.x as i64 + dx).max(0).min(size as i64 - 1) as u64;
+            drone.z = (drone.z as i64 + dz).max(0).min(size as i64 - 1) as u64;
+        }
+        self.tick += 1;
+    }
+    /// Advance `ticks` ticks unless paused.
+    pub fn run(&mut self, ticks: u64) {
+        for _ in 0..ticks {
+            self.tick();
+        }
+    }
+    /// Top down text rendering of the demo area.
+    pub fn render(&self) -> String {
+        let size = DEMO_CHUNKS * CHUNK_SIZE as u64;
+        let mut frame = String::new();
+        writeln!(frame, "tick {}{}", self.tick, if self.paused { " (paused)" } else { "" }).unwrap();
+        for z in 0..size {
+            for x in 0..size {
+                let drone = self.entities.iter::<Drone>().any(|(_, drone)| drone.x == x && drone.z == z);
+                frame.push(if drone { '@' } else { self.surface_glyph(x, z) });
+            }
+            frame.push('\n');
+        }
+        frame
+    }
+    fn surface_glyph(&self, x: u64, z: u64) -> char {
+        for y in (0..CHUNK_SIZE).rev() {
+            match self.world.get_block(x, y, z) {
+                Some(block) if !block.is_air() => {
+                    return match self.world.materials().get(block.material()).map(|m| m.name()) {
+                        Some("rock") => '.',
+                        Some("chitin") => ',',
+                        Some(name) => name.chars().next().unwrap_or('?'),
+                        None => '?',
+                    };
+                }
+                _ => {}
+            }
+        }
+        ' '
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Drone, Hive};
+
+    #[test]
+    pub fn test_demo_time_controls() {
+        let mut hive = Hive::demo();
+        assert_eq!(hive.entities().iter::<Drone>().count(), 3);
+        assert_eq!(hive.cluster().len(), 3);
+        hive.run(5);
+        assert_eq!(hive.current_tick(), 5);
+        hive.pause();
+        hive.run(5);
+        assert_eq!(hive.current_tick(), 5);
+        hive.step();
+        assert_eq!(hive.current_tick(), 6);
+        assert_eq!(hive.render().matches('@').count(), 3);
+    }
+}
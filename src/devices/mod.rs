@@ -0,0 +1,129 @@
+//!
+//! Hardware Devices
+//!
+//! Devices are attached to a VCPU16 through a Bus. HWN counts the devices on
+//! the Bus, HWQ reads a device's identity into A, B, C, X and Y, and HWI hands
+//! the CPU to the device, which may stall it for extra cycles. Devices beyond
+//! the built in ones are created by name from a DeviceRegistry.
+//!
+//! Devices can be plugged into and unplugged from a running CPU. Each one
+//! sits in a socket which keeps its index while others come and go, an empty
+//! socket answering HWQ with all zeroes and HWI with nothing. Every change
+//! interrupts the CPU with HOTPLUG_MESSAGE so firmware can enumerate again.
+//!
+
+use std::collections::HashMap;
+use vcpu::cpu::VCPU16;
+
+pub mod serial;
+pub mod speaker;
+pub mod world;
+
+pub use self::serial::Serial;
+pub use self::speaker::Speaker;
+
+/// Manufacturer id shared by the built in devices, "HIVE"
+pub const MANUFACTURER: u32 = 0x4849_5645;
+/// Interrupt message raised when a device is plugged in or unplugged
+pub const HOTPLUG_MESSAGE: u16 = 0xFFFF;
+/// Identity HWQ reads from an empty socket
+pub const EMPTY_SOCKET: DeviceInfo = DeviceInfo { id: 0, version: 0, manufacturer: 0 };
+
+///
+/// Device Identity as reported by HWQ
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DeviceInfo {
+    pub id: u32,
+    pub version: u16,
+    pub manufacturer: u32,
+}
+
+///
+/// Hardware Device
+///
+pub trait Device {
+    fn info(&self) -> DeviceInfo;
+    /// Handle HWI, returning the extra cycles the CPU is stalled for.
+    fn interrupt(&mut self, cpu: &mut VCPU16) -> u16;
+    /// Called every world tick before the CPU runs, so the device can raise interrupts of its own.
+    fn tick(&mut self, _cpu: &mut VCPU16) {}
+    /// Copy for a forked HiveCluster, None if the device can't be copied or reaches outside the Simulation.
+    fn fork(&self) -> Option<Box<dyn Device>> { None }
+}
+
+///
+/// Devices visible to one CPU, indexed from zero
+///
+pub trait Bus {
+    fn count(&self) -> u16;
+    fn info(&self, index: u16) -> Option<DeviceInfo>;
+    /// Handle HWI for a device, missing devices cost nothing.
+    fn interrupt(&mut self, index: u16, cpu: &mut VCPU16) -> u16;
+}
+
+///
+/// Bus with nothing attached
+///
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct NoDevices;
+
+impl Bus for NoDevices {
+    fn count(&self) -> u16 { 0 }
+    fn info(&self, _index: u16) -> Option<DeviceInfo> { None }
+    fn interrupt(&mut self, _index: u16, _cpu: &mut VCPU16) -> u16 { 0 }
+}
+
+impl Bus for Vec<Box<dyn Device>> {
+    fn count(&self) -> u16 { self.len() as u16 }
+    fn info(&self, index: u16) -> Option<DeviceInfo> { self.get(index as usize).map(|device| device.info()) }
+    fn interrupt(&mut self, index: u16, cpu: &mut VCPU16) -> u16 {
+        match self.get_mut(index as usize) {
+            Some(device) => device.interrupt(cpu),
+            None => 0,
+        }
+    }
+}
+
+/// Place on a Bus holding a Device, or nothing once it is unplugged
+pub type Socket = Option<Box<dyn Device>>;
+
+impl Bus for Vec<Socket> {
+    fn count(&self) -> u16 { self.len() as u16 }
+    fn info(&self, index: u16) -> Option<DeviceInfo> {
+        self.get(index as usize).map(|socket| socket.as_ref().map_or(EMPTY_SOCKET, |device| device.info()))
+    }
+    fn interrupt(&mut self, index: u16, cpu: &mut VCPU16) -> u16 {
+        match self.get_mut(index as usize) {
+            Some(Some(device)) => device.interrupt(cpu),
+            _ => 0,
+        }
+    }
+}
+
+/// Builds a fresh Device for one CPU
+pub type DeviceFactory = Box<dyn Fn() -> Box<dyn Device>>;
+
+///
+/// Named Device Factories
+///
+#[derive(Default)]
+pub struct DeviceRegistry {
+    factories: HashMap<String, DeviceFactory>,
+}
+
+impl DeviceRegistry {
+    pub fn new() -> DeviceRegistry { DeviceRegistry::default() }
+    /// Register a factory, replacing any other of the same name.
+    pub fn register(&mut self, name: &str, factory: DeviceFactory) { self.factories.insert(name.to_string(), factory); }
+    pub fn contains(&self, name: &str) -> bool { self.factories.contains_key(name) }
+    pub fn create(&self, name: &str) -> Option<Box<dyn Device>> { self.factories.get(name).map(|factory| factory()) }
+    /// Registered names, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.factories.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+    pub fn len(&self) -> usize { self.factories.len() }
+    pub fn is_empty(&self) -> bool { self.factories.is_empty() }
+}
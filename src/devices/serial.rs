@@ -0,0 +1,296 @@
+///
+/// Serial Device
+///
+/// A byte stream between a CPU and the host, one byte per word. The command
+/// goes in A and the status comes back in C. Bytes from the host wait in the
+/// device until read; with an interrupt message set, the CPU is interrupted
+/// on each tick in which new bytes arrived.
+///
+///  A | COMMAND   | ARGUMENTS          | RESULT
+/// ---+-----------+--------------------+-------------------------------------
+///  0 | INTERRUPT | B message, 0 off   |
+///  1 | RECEIVE   |                    | B: next byte, X: bytes still waiting
+///  2 | TRANSMIT  | B byte (low 8 bits)| byte sent
+///  3 | READ      | B address, C words | B: bytes read into memory from B
+///  4 | WRITE     | B address, C words | low bytes of the words sent
+///
+/// The host end is either a SerialPort, whose buffers the host reads and
+/// writes directly, or any Read and Write, such as stdin and stdout or a TCP
+/// socket. The reader is drained by a thread of its own so the CPU never
+/// waits on it.
+///
+use devices::{Device, DeviceInfo, MANUFACTURER};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use vcpu::cpu::VCPU16;
+
+/// Device id, "SERL"
+pub const DEVICE_ID: u32 = 0x5345_524C;
+pub const DEVICE_VERSION: u16 = 1;
+
+pub const INTERRUPT: u16 = 0;
+pub const RECEIVE: u16 = 1;
+pub const TRANSMIT: u16 = 2;
+pub const READ: u16 = 3;
+pub const WRITE: u16 = 4;
+
+pub const STATUS_OK: u16 = 0;
+/// No bytes are waiting
+pub const STATUS_EMPTY: u16 = 1;
+/// The host end has gone
+pub const STATUS_CLOSED: u16 = 2;
+/// Unknown command
+pub const STATUS_INVALID: u16 = 3;
+
+/// Cycles every command stalls the CPU, READ and WRITE add one per 8 words
+pub const COMMAND_CYCLES: u16 = 1;
+/// Bytes from the host kept waiting, later ones are dropped
+pub const MAX_WAITING: usize = 4096;
+
+/// Buffers of a SerialPort
+#[derive(Default, Debug)]
+struct Line {
+    /// Host to CPU
+    input: VecDeque<u8>,
+    /// CPU to host
+    output: Vec<u8>,
+    closed: bool,
+}
+
+///
+/// Host end of a Serial device held in memory, shared between its clones
+///
+#[derive(Clone, Default, Debug)]
+pub struct SerialPort {
+    line: Rc<RefCell<Line>>,
+}
+
+impl SerialPort {
+    pub fn new() -> SerialPort { SerialPort::default() }
+    /// Send bytes to the CPU.
+    pub fn write(&self, bytes: &[u8]) { self.line.borrow_mut().input.extend(bytes.iter().cloned()) }
+    /// Bytes sent by the CPU since last read.
+    pub fn read(&self) -> Vec<u8> { self.line.borrow_mut().output.drain(..).collect() }
+    /// Hang up, so the CPU's transmissions fail with STATUS_CLOSED.
+    pub fn close(&self) { self.line.borrow_mut().closed = true }
+}
+
+/// Where a Serial device's bytes come from and go
+enum Host {
+    Port(SerialPort),
+    Streams { input: Receiver<Vec<u8>>, output: Box<dyn Write> },
+}
+
+///
+/// Serial
+///
+pub struct Serial {
+    host: Host,
+    waiting: VecDeque<u8>,
+    message: u16,
+    /// Bytes arrived since the CPU was last interrupted
+    arrived: bool,
+}
+
+impl Serial {
+    /// Serial device connected to `port`.
+    pub fn new(port: &SerialPort) -> Serial { Serial::with_host(Host::Port(port.clone())) }
+    ///
+    /// Serial device reading from `reader` on a thread of its own, which
+    /// stops at the end of the stream, and writing to `writer`.
+    ///
+    pub fn with_streams<R: Read + Send + 'static>(mut reader: R, writer: Box<dyn Write>) -> Serial {
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 256];
+            while let Ok(read) = reader.read(&mut buffer) {
+                if read == 0 || sender.send(buffer[..read].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+        Serial::with_host(Host::Streams { input: receiver, output: writer })
+    }
+    fn with_host(host: Host) -> Serial { Serial { host, waiting: VecDeque::new(), message: 0, arrived: false } }
+    /// Bytes received but not yet read by the CPU.
+    pub fn waiting(&self) -> usize { self.waiting.len() }
+    /// Move bytes the host has sent into the waiting queue.
+    fn fill(&mut self) {
+        let before = self.waiting.len();
+        match self.host {
+            Host::Port(ref port) => {
+                let mut line = port.line.borrow_mut();
+                let room = MAX_WAITING.saturating_sub(self.waiting.len());
+                let count = line.input.len().min(room);
+                self.waiting.extend(line.input.drain(..count));
+            }
+            Host::Streams { ref input, .. } => {
+                while let Ok(bytes) = input.try_recv() {
+                    let room = MAX_WAITING.saturating_sub(self.waiting.len());
+                    self.waiting.extend(bytes.into_iter().take(room));
+                }
+            }
+        }
+        self.arrived |= self.waiting.len() > before;
+    }
+    fn send(&mut self, bytes: &[u8]) -> u16 {
+        let sent = match self.host {
+            Host::Port(ref port) => {
+                let mut line = port.line.borrow_mut();
+                line.output.extend_from_slice(bytes);
+                !line.closed
+            }
+            Host::Streams { ref mut output, .. } => output.write_all(bytes).and_then(|_| output.flush()).is_ok(),
+        };
+        if sent { STATUS_OK } else { STATUS_CLOSED }
+    }
+    fn command(&mut self, cpu: &mut VCPU16) -> (u16, u16) {
+        self.fill();
+        match cpu.get_a() {
+            INTERRUPT => {
+                self.message = cpu.get_b();
+                (STATUS_OK, 0)
+            }
+            RECEIVE => {
+                let status = match self.waiting.pop_front() {
+                    Some(byte) => {
+                        cpu.set_b(byte as u16);
+                        STATUS_OK
+                    }
+                    None => STATUS_EMPTY,
+                };
+                cpu.set_x(self.waiting.len().min(0xFFFF) as u16);
+                (status, 0)
+            }
+            TRANSMIT => (self.send(&[cpu.get_b() as u8]), 0),
+            READ => {
+                let (address, words) = (cpu.get_b(), cpu.get_c());
+                let count = (words as usize).min(self.waiting.len()) as u16;
+                for offset in 0..count {
+                    let byte = self.waiting.pop_front().unwrap();
+                    cpu.set_memory(address.wrapping_add(offset), byte as u16);
+                }
+                cpu.set_b(count);
+                (if count == 0 && words > 0 { STATUS_EMPTY } else { STATUS_OK }, count / 8)
+            }
+            WRITE => {
+                let (address, words) = (cpu.get_b(), cpu.get_c());
+                let bytes: Vec<u8> = (0..words).map(|offset| cpu.get_memory(address.wrapping_add(offset)) as u8).collect();
+                (self.send(&bytes), words / 8)
+            }
+            _ => (STATUS_INVALID, 0),
+        }
+    }
+}
+
+impl Device for Serial {
+    fn info(&self) -> DeviceInfo { DeviceInfo { id: DEVICE_ID, version: DEVICE_VERSION, manufacturer: MANUFACTURER } }
+    fn interrupt(&mut self, cpu: &mut VCPU16) -> u16 {
+        let (status, extra) = self.command(cpu);
+        cpu.set_c(status);
+        COMMAND_CYCLES + extra
+    }
+    fn tick(&mut self, cpu: &mut VCPU16) {
+        self.fill();
+        if self.arrived && self.message != 0 {
+            cpu.interrupt(self.message);
+        }
+        self.arrived = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use devices::{Bus, Socket};
+    use std::io::{self, Cursor};
+    use std::sync::{Arc, Mutex};
+
+    /// HWI 0
+    const HWI: u16 = 0x8640;
+
+    fn call(cpu: &mut VCPU16, bus: &mut dyn Bus, a: u16, b: u16, c: u16) -> u16 {
+        cpu.set_pc(0);
+        cpu.set_memory(0, HWI);
+        cpu.set_a(a);
+        cpu.set_b(b);
+        cpu.set_c(c);
+        cpu.step_with(bus);
+        while cpu.is_busy() {
+            cpu.step_with(bus);
+        }
+        cpu.get_c()
+    }
+
+    #[test]
+    pub fn test_serial_port() {
+        let port = SerialPort::new();
+        let mut bus: Vec<Socket> = vec![Some(Box::new(Serial::new(&port)))];
+        let mut cpu = VCPU16::new();
+        cpu.set_ia(0x100);
+        assert_eq!(bus.info(0).map(|info| info.id), Some(DEVICE_ID));
+        assert_eq!(call(&mut cpu, &mut bus, RECEIVE, 0, 0), STATUS_EMPTY);
+
+        // Input interrupts the CPU once it is asked to
+        port.write(b"hi");
+        bus[0].as_mut().unwrap().tick(&mut cpu);
+        assert_eq!(cpu.pending_interrupts(), 0);
+        assert_eq!(call(&mut cpu, &mut bus, INTERRUPT, 7, 0), STATUS_OK);
+        port.write(b"!");
+        bus[0].as_mut().unwrap().tick(&mut cpu);
+        bus[0].as_mut().unwrap().tick(&mut cpu);
+        assert_eq!(cpu.pending_interrupts(), 1);
+        cpu.reset(false);
+
+        assert_eq!(call(&mut cpu, &mut bus, RECEIVE, 0, 0), STATUS_OK);
+        assert_eq!((cpu.get_b(), cpu.get_x()), (b'h' as u16, 2));
+        assert_eq!(call(&mut cpu, &mut bus, READ, 0x200, 8), STATUS_OK);
+        assert_eq!((cpu.get_b(), cpu.get_memory(0x200), cpu.get_memory(0x201)), (2, b'i' as u16, b'!' as u16));
+
+        // Output goes to the host a byte or a block at a time
+        assert_eq!(call(&mut cpu, &mut bus, TRANSMIT, 0x1234, 0), STATUS_OK);
+        for (offset, &byte) in b"ok\n".iter().enumerate() {
+            cpu.set_memory(0x300 + offset as u16, byte as u16);
+        }
+        assert_eq!(call(&mut cpu, &mut bus, WRITE, 0x300, 3), STATUS_OK);
+        assert_eq!(port.read(), b"\x34ok\n".to_vec());
+        assert_eq!(call(&mut cpu, &mut bus, 9, 0, 0), STATUS_INVALID);
+        port.close();
+        assert_eq!(call(&mut cpu, &mut bus, TRANSMIT, 0, 0), STATUS_CLOSED);
+    }
+
+    /// Write shared with the test
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    pub fn test_serial_streams() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut serial = Serial::with_streams(Cursor::new(b"login".to_vec()), Box::new(Shared(written.clone())));
+        let mut cpu = VCPU16::new();
+        // The reader thread delivers in its own time
+        for _ in 0..1000 {
+            serial.fill();
+            if serial.waiting() == 5 {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let mut bus: Vec<Socket> = vec![Some(Box::new(serial))];
+        assert_eq!(call(&mut cpu, &mut bus, READ, 0x10, 16), STATUS_OK);
+        assert_eq!(cpu.get_b(), 5);
+        assert_eq!(call(&mut cpu, &mut bus, WRITE, 0x10, 5), STATUS_OK);
+        assert_eq!(*written.lock().unwrap(), b"login".to_vec());
+    }
+}
@@ -0,0 +1,216 @@
+///
+/// Speaker Device
+///
+/// Lets a CPU ask for sounds. The command goes in A and the status comes
+/// back in C; what is heard is up to the host, which is handed each request
+/// through a callback or collects them in a SoundBuffer to render.
+///
+///  A | COMMAND  | ARGUMENTS             | RESULT
+/// ---+----------+-----------------------+---------------------------------------
+///  0 | TONE     | B Hz, C ms, X volume  | tone queued, 0 Hz for a rest
+///  1 | SILENCE  |                       | queued tones dropped
+///
+/// Volume runs from 0 to 255, higher values saturating. A SoundBuffer holds at
+/// most MAX_QUEUED tones, TONE is refused with STATUS_FULL beyond that.
+///
+use devices::{Device, DeviceInfo, MANUFACTURER};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use vcpu::cpu::VCPU16;
+
+/// Device id, "SPKR"
+pub const DEVICE_ID: u32 = 0x5350_4B52;
+pub const DEVICE_VERSION: u16 = 1;
+
+pub const TONE: u16 = 0;
+pub const SILENCE: u16 = 1;
+
+pub const STATUS_OK: u16 = 0;
+/// Too many tones are waiting to be played
+pub const STATUS_FULL: u16 = 1;
+/// Unknown command
+pub const STATUS_INVALID: u16 = 2;
+
+/// Cycles every command stalls the CPU
+pub const COMMAND_CYCLES: u16 = 1;
+/// Most tones a SoundBuffer holds
+pub const MAX_QUEUED: usize = 64;
+
+///
+/// Tone requested by a CPU
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Tone {
+    /// Hz, 0 for a rest
+    pub frequency: u16,
+    /// Milliseconds
+    pub duration: u16,
+    pub volume: u8,
+}
+
+///
+/// Request handed to the host
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Sound {
+    Tone(Tone),
+    /// Stop playing and drop anything queued
+    Silence,
+}
+
+/// Host side handler of a Speaker's sounds, returning false to refuse a tone
+pub type SoundCallback = Box<dyn FnMut(Sound) -> bool>;
+
+///
+/// Speaker
+///
+pub struct Speaker {
+    callback: SoundCallback,
+}
+
+impl Speaker {
+    pub fn new(callback: SoundCallback) -> Speaker { Speaker { callback } }
+    /// Speaker queueing its tones in `buffer`.
+    pub fn buffered(buffer: &SoundBuffer) -> Speaker {
+        let buffer = buffer.clone();
+        Speaker::new(Box::new(move |sound| buffer.push(sound)))
+    }
+    fn command(&mut self, cpu: &VCPU16) -> u16 {
+        let sound = match cpu.get_a() {
+            TONE => Sound::Tone(Tone { frequency: cpu.get_b(), duration: cpu.get_c(), volume: cpu.get_x().min(255) as u8 }),
+            SILENCE => Sound::Silence,
+            _ => return STATUS_INVALID,
+        };
+        if (self.callback)(sound) { STATUS_OK } else { STATUS_FULL }
+    }
+}
+
+impl Device for Speaker {
+    fn info(&self) -> DeviceInfo { DeviceInfo { id: DEVICE_ID, version: DEVICE_VERSION, manufacturer: MANUFACTURER } }
+    fn interrupt(&mut self, cpu: &mut VCPU16) -> u16 {
+        let status = self.command(cpu);
+        cpu.set_c(status);
+        COMMAND_CYCLES
+    }
+}
+
+///
+/// Tones queued by Speakers, shared between its clones
+///
+/// The host either takes the tones to play them itself, or renders them into
+/// square wave samples.
+///
+#[derive(Clone, Default, Debug)]
+pub struct SoundBuffer {
+    tones: Rc<RefCell<VecDeque<Tone>>>,
+}
+
+impl SoundBuffer {
+    pub fn new() -> SoundBuffer { SoundBuffer::default() }
+    /// Queue a sound, returns false if the buffer is full.
+    pub fn push(&self, sound: Sound) -> bool {
+        let mut tones = self.tones.borrow_mut();
+        match sound {
+            Sound::Tone(_) if tones.len() >= MAX_QUEUED => false,
+            Sound::Tone(tone) => {
+                tones.push_back(tone);
+                true
+            }
+            Sound::Silence => {
+                tones.clear();
+                true
+            }
+        }
+    }
+    pub fn len(&self) -> usize { self.tones.borrow().len() }
+    pub fn is_empty(&self) -> bool { self.tones.borrow().is_empty() }
+    /// Queued tones, oldest first, leaving the buffer empty.
+    pub fn take(&self) -> Vec<Tone> { self.tones.borrow_mut().drain(..).collect() }
+    ///
+    /// Take the queued tones and append them to `samples` as square waves at
+    /// `sample_rate` samples per second, rests as silence.
+    ///
+    pub fn render(&self, sample_rate: u32, samples: &mut Vec<i16>) {
+        for tone in self.take() {
+            let count = sample_rate as u64 * tone.duration as u64 / 1000;
+            let amplitude = tone.volume as i32 * i16::MAX as i32 / 255;
+            for index in 0..count {
+                let sample = if tone.frequency == 0 {
+                    0
+                } else if (index * 2 * tone.frequency as u64 / sample_rate as u64).is_multiple_of(2) {
+                    amplitude
+                } else {
+                    -amplitude
+                };
+                samples.push(sample as i16);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use devices::{Bus, Socket};
+
+    /// HWI 0
+    const HWI: u16 = 0x8640;
+
+    fn call(cpu: &mut VCPU16, bus: &mut dyn Bus, a: u16, b: u16, c: u16, x: u16) -> u16 {
+        cpu.set_pc(0);
+        cpu.set_memory(0, HWI);
+        cpu.set_a(a);
+        cpu.set_b(b);
+        cpu.set_c(c);
+        cpu.set_x(x);
+        cpu.step_with(bus);
+        while cpu.is_busy() {
+            cpu.step_with(bus);
+        }
+        cpu.get_c()
+    }
+
+    #[test]
+    pub fn test_speaker() {
+        let buffer = SoundBuffer::new();
+        let mut bus: Vec<Socket> = vec![Some(Box::new(Speaker::buffered(&buffer)))];
+        let mut cpu = VCPU16::new();
+        assert_eq!(bus.info(0).map(|info| info.id), Some(DEVICE_ID));
+
+        assert_eq!(call(&mut cpu, &mut bus, TONE, 440, 250, 1000), STATUS_OK);
+        assert_eq!(call(&mut cpu, &mut bus, TONE, 0, 100, 0), STATUS_OK);
+        assert_eq!(call(&mut cpu, &mut bus, 9, 0, 0, 0), STATUS_INVALID);
+        assert_eq!(buffer.take(), vec![
+            Tone { frequency: 440, duration: 250, volume: 255 },
+            Tone { frequency: 0, duration: 100, volume: 0 },
+        ]);
+
+        // The buffer fills up, and silence empties it
+        for _ in 0..MAX_QUEUED {
+            call(&mut cpu, &mut bus, TONE, 1000, 10, 128);
+        }
+        assert_eq!(call(&mut cpu, &mut bus, TONE, 1000, 10, 128), STATUS_FULL);
+        assert_eq!(call(&mut cpu, &mut bus, SILENCE, 0, 0, 0), STATUS_OK);
+        assert!(buffer.is_empty());
+
+        // A 1kHz tone at 8kHz goes high for 4 samples then low for 4
+        call(&mut cpu, &mut bus, TONE, 1000, 1, 255);
+        call(&mut cpu, &mut bus, TONE, 0, 1, 255);
+        let mut samples = Vec::new();
+        buffer.render(8000, &mut samples);
+        assert_eq!(samples.len(), 16);
+        assert_eq!(&samples[2..6], &[i16::MAX, i16::MAX, -i16::MAX, -i16::MAX]);
+        assert!(samples[8..].iter().all(|&sample| sample == 0));
+
+        // Or the host hears each sound as it is made
+        let heard = Rc::new(RefCell::new(Vec::new()));
+        let log = heard.clone();
+        bus[0] = Some(Box::new(Speaker::new(Box::new(move |sound| {
+            log.borrow_mut().push(sound);
+            true
+        }))));
+        call(&mut cpu, &mut bus, SILENCE, 0, 0, 0);
+        assert_eq!(*heard.borrow(), vec![Sound::Silence]);
+    }
+}
@@ -0,0 +1,529 @@
+///
+/// World Interface Device
+///
+/// Gives a CPU eyes and hands in the World through its host entity. The
+/// command goes in A, offsets from the host's Block in X, Y and Z (signed), and
+/// the status comes back in C. Actions which change the World are limited per
+/// tick; every command stalls the CPU for a fixed number of cycles.
+///
+///  A | COMMAND  | ARGUMENTS           | RESULT
+/// ---+----------+---------------------+---------------------------------------
+///  0 | LOCATE   |                     | X, Y, Z: host Block (low 16 bits)
+///  1 | PROBE    | X, Y, Z offset      | B: material id
+///  2 | SCAN     | B address, X radius | (2r+1)^3 material ids at B, x major
+///  3 | MOVE     | X, Y, Z offset      | host moved one Block (action)
+///  4 | BREAK    | X, Y, Z offset      | B: material broken (action)
+///  5 | PLACE    | X, Y, Z offset, B   | material B placed into air (action)
+///  6 | SMELL    | X, Y, Z offset, B   | B: strength of pheromone channel B
+///  7 | MARK     | X, Y, Z offset, B, I| I strength of channel B deposited
+///  8 | CLIMATE  |                     | B: time of day, X: light, Y: degrees
+///  9 | CLAIM    | B kind, 0 for any   | claims a Task or keeps the one held, as TASK
+/// 10 | TASK     | B 0 start, 1 end    | B kind, I material or item, J count,
+///    |          |                     | X, Y, Z offset to the Task's Block
+/// 11 | DONE     |                     | held Task completed
+/// 12 | RELEASE  |                     | held Task put back on the board
+///
+/// Unloaded Blocks read as UNLOADED (0xFFFF) in a SCAN. Pheromone strengths
+/// are whole units, saturating at 0xFFFF. MOVE, BREAK, PLACE and MARK are
+/// refused with DENIED in Chunks claimed by a Faction the host isn't allied
+/// with.
+/// CLIMATE reads the Environment resource: the time of day out of 0x10000
+/// from midnight, the light at the host's Block as it is at that time, and
+/// the temperature of its biome in whole degrees (signed); without one it is
+/// INVALID.
+/// CLAIM, TASK, DONE and RELEASE work the host's Tasks on the JobBoard
+/// resource, see `model::job`, and are INVALID without one. Each of them
+/// is NO_TASK when no Task is held, or none of the kind is open to CLAIM.
+/// CLAIM and TASK renew the claim. The end of a Haul is where it delivers,
+/// of any other Task its start; I is the material to mine or build, or the
+/// item to haul, and the offset wraps to 16 bits.
+/// Offsets reach across the edges of a wrapping World.
+///
+use devices::{Bus, DeviceInfo, Socket, MANUFACTURER};
+use math::{Fixed, Vec3};
+use model::component::Position;
+use model::entity::{EntityID, EntityManager};
+use model::environment::Environment;
+use model::job::{self, JobBoard, TaskKind};
+use model::material::{MaterialId, AIR};
+use model::pheromone::ChannelId;
+use model::update::BlockPosition;
+use model::world::{Block, World, CHUNK_SIZE};
+use vcpu::cpu::VCPU16;
+
+/// Device id, "HWIF"
+pub const DEVICE_ID: u32 = 0x4857_4946;
+pub const DEVICE_VERSION: u16 = 1;
+
+pub const LOCATE: u16 = 0;
+pub const PROBE: u16 = 1;
+pub const SCAN: u16 = 2;
+pub const MOVE: u16 = 3;
+pub const BREAK: u16 = 4;
+pub const PLACE: u16 = 5;
+pub const SMELL: u16 = 6;
+pub const MARK: u16 = 7;
+pub const CLIMATE: u16 = 8;
+pub const CLAIM: u16 = 9;
+pub const TASK: u16 = 10;
+pub const DONE: u16 = 11;
+pub const RELEASE: u16 = 12;
+
+pub const STATUS_OK: u16 = 0;
+/// The action limit for this tick has been used up
+pub const STATUS_BUSY: u16 = 1;
+/// The target Block is solid (MOVE, PLACE) or air (BREAK)
+pub const STATUS_BLOCKED: u16 = 2;
+pub const STATUS_OUT_OF_RANGE: u16 = 3;
+pub const STATUS_UNLOADED: u16 = 4;
+/// Unknown command, material or pheromone channel
+pub const STATUS_INVALID: u16 = 5;
+/// The host entity is gone or has no Position
+pub const STATUS_NO_HOST: u16 = 6;
+/// The host's Faction may not modify the target Block
+pub const STATUS_DENIED: u16 = 7;
+/// No Task is held, or none of the kind asked for is open
+pub const STATUS_NO_TASK: u16 = 8;
+
+/// Material id reported for unloaded Blocks in a SCAN
+pub const UNLOADED: u16 = 0xFFFF;
+
+///
+/// Limits and Costs
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct WorldInterfaceConfig {
+    /// Largest radius, per axis, PROBE and SCAN can see
+    pub scan_radius: u16,
+    /// Largest offset, per axis, BREAK, PLACE and MARK can touch
+    pub reach: u16,
+    /// MOVE, BREAK and PLACE commands allowed per tick
+    pub actions_per_tick: u16,
+    /// Cycles per command, SCAN adds one per 8 Blocks
+    pub query_cycles: u16,
+    pub move_cycles: u16,
+    pub edit_cycles: u16,
+    pub mark_cycles: u16,
+}
+
+impl Default for WorldInterfaceConfig {
+    fn default() -> WorldInterfaceConfig {
+        WorldInterfaceConfig {
+            scan_radius: 4,
+            reach: 2,
+            actions_per_tick: 1,
+            query_cycles: 1,
+            move_cycles: 8,
+            edit_cycles: 16,
+            mark_cycles: 2,
+        }
+    }
+}
+
+///
+/// World Interface Device State
+///
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct WorldInterface {
+    config: WorldInterfaceConfig,
+    actions: u16,
+}
+
+impl WorldInterface {
+    pub fn new() -> WorldInterface { WorldInterface::default() }
+    pub fn with_config(config: WorldInterfaceConfig) -> WorldInterface { WorldInterface { config, actions: 0 } }
+    pub fn config(&self) -> &WorldInterfaceConfig { &self.config }
+    pub fn info(&self) -> DeviceInfo {
+        DeviceInfo { id: DEVICE_ID, version: DEVICE_VERSION, manufacturer: MANUFACTURER }
+    }
+    /// Actions taken since the last `begin_tick`.
+    pub fn actions(&self) -> u16 { self.actions }
+    /// Reset the action limit, call once per world tick.
+    pub fn begin_tick(&mut self) { self.actions = 0 }
+    ///
+    /// Handle HWI on behalf of `host`, returning the cycles it costs.
+    ///
+    pub fn interrupt(&mut self, cpu: &mut VCPU16, world: &mut World, entities: &mut EntityManager, host: EntityID) -> u16 {
+        let (status, cycles) = self.command(cpu, world, entities, host);
+        cpu.set_c(status);
+        cycles
+    }
+    fn command(&mut self, cpu: &mut VCPU16, world: &mut World, entities: &mut EntityManager, host: EntityID) -> (u16, u16) {
+        let config = self.config;
+        let position = match entities.get_component::<Position>(host) {
+            Some(&position) if entities.is_alive(host) => position,
+            _ => return (STATUS_NO_HOST, 0),
+        };
+        let origin = position.block();
+        let offset = (cpu.get_x() as i16 as i64, cpu.get_y() as i16 as i64, cpu.get_z() as i16 as i64);
+        let within = |limit: u16| offset.0.abs() <= limit as i64 && offset.1.abs() <= limit as i64 && offset.2.abs() <= limit as i64;
+        let target = block_at(world, origin.0 + offset.0, origin.1 + offset.1, origin.2 + offset.2);
+        match cpu.get_a() {
+            LOCATE => {
+                cpu.set_x(origin.0 as u16);
+                cpu.set_y(origin.1 as u16);
+                cpu.set_z(origin.2 as u16);
+                (STATUS_OK, 0)
+            }
+            PROBE => {
+                if !within(config.scan_radius) {
+                    return (STATUS_OUT_OF_RANGE, config.query_cycles);
+                }
+                match target.and_then(|(x, y, z)| world.get_block(x, y, z)) {
+                    Some(block) => {
+                        cpu.set_b(block.material().id());
+                        (STATUS_OK, config.query_cycles)
+                    }
+                    None => (STATUS_UNLOADED, config.query_cycles),
+                }
+            }
+            SCAN => {
+                let radius = cpu.get_x();
+                if radius > config.scan_radius {
+                    return (STATUS_OUT_OF_RANGE, config.query_cycles);
+                }
+                let (mut address, radius) = (cpu.get_b(), radius as i64);
+                let mut count = 0u16;
+                for dx in -radius..radius + 1 {
+                    for dy in -radius..radius + 1 {
+                        for dz in -radius..radius + 1 {
+                            let material = block_at(world, origin.0 + dx, origin.1 + dy, origin.2 + dz)
+                                .and_then(|(x, y, z)| world.get_block(x, y, z))
+                                .map_or(UNLOADED, |block| block.material().id());
+                            cpu.set_memory(address, material);
+                            address = address.wrapping_add(1);
+                            count = count.wrapping_add(1);
+                        }
+                    }
+                }
+                (STATUS_OK, config.query_cycles + count / 8)
+            }
+            MOVE => {
+                if !within(1) {
+                    return (STATUS_OUT_OF_RANGE, 0);
+                }
+                if target.is_some_and(|(x, _, z)| !world.factions().may_modify_block(entities, host, x, z)) {
+                    return (STATUS_DENIED, 0);
+                }
+                if !self.take_action() {
+                    return (STATUS_BUSY, 0);
+                }
+                let (x, y, z) = match target {
+                    Some(target) if world.get_block(target.0, target.1, target.2).is_some() => target,
+                    _ => return (STATUS_UNLOADED, config.move_cycles),
+                };
+                if world.is_solid(x, y, z) {
+                    return (STATUS_BLOCKED, config.move_cycles);
+                }
+                let moved = Position::from_vector(position.vector() + Vec3::new(Fixed::from_int(offset.0), Fixed::from_int(offset.1), Fixed::from_int(offset.2)));
+                entities.add_component(host, moved);
+                (STATUS_OK, config.move_cycles)
+            }
+            BREAK | PLACE => {
+                let place = cpu.get_a() == PLACE;
+                if !within(config.reach) {
+                    return (STATUS_OUT_OF_RANGE, 0);
+                }
+                let material = MaterialId::new(cpu.get_b());
+                if place && (material == AIR || world.materials().get(material).is_none()) {
+                    return (STATUS_INVALID, 0);
+                }
+                if target.is_some_and(|(x, _, z)| !world.factions().may_modify_block(entities, host, x, z)) {
+                    return (STATUS_DENIED, 0);
+                }
+                if !self.take_action() {
+                    return (STATUS_BUSY, 0);
+                }
+                let ((x, y, z), existing) = match target.and_then(|(x, y, z)| world.get_block(x, y, z).map(|block| ((x, y, z), block))) {
+                    Some(found) => found,
+                    None => return (STATUS_UNLOADED, config.edit_cycles),
+                };
+                if existing.is_air() != place {
+                    return (STATUS_BLOCKED, config.edit_cycles);
+                }
+                if place {
+                    world.set_block(x, y, z, Block::new(material));
+                } else {
+                    world.set_block(x, y, z, Block::new(AIR));
+                    cpu.set_b(existing.material().id());
+                }
+                (STATUS_OK, config.edit_cycles)
+            }
+            SMELL | MARK => {
+                let mark = cpu.get_a() == MARK;
+                if !within(if mark { config.reach } else { config.scan_radius }) {
+                    return (STATUS_OUT_OF_RANGE, 0);
+                }
+                let channel = ChannelId::new(cpu.get_b());
+                if world.pheromones().channel(channel).is_none() {
+                    return (STATUS_INVALID, 0);
+                }
+                let (x, y, z) = match target {
+                    Some(target) if world.get_block(target.0, target.1, target.2).is_some() => target,
+                    _ => return (STATUS_UNLOADED, config.query_cycles),
+                };
+                if mark && !world.factions().may_modify_block(entities, host, x, z) {
+                    return (STATUS_DENIED, 0);
+                }
+                if mark {
+                    world.pheromones_mut().deposit(channel, x, y, z, cpu.get_i() as f32);
+                    (STATUS_OK, config.mark_cycles)
+                } else {
+                    let strength = world.pheromones().get(channel, x, y, z);
+                    cpu.set_b(strength.round().min(u16::MAX as f32) as u16);
+                    (STATUS_OK, config.query_cycles)
+                }
+            }
+            CLIMATE => {
+                let environment = match entities.resource::<Environment>() {
+                    Some(environment) => environment,
+                    None => return (STATUS_INVALID, 0),
+                };
+                let here = block_at(world, origin.0, origin.1, origin.2);
+                let light = here.and_then(|(x, y, z)| world.light_at(x, y, z)).map_or(0, |light| environment.effective_light(light));
+                let biome = here.and_then(|(x, _, z)| world.biome_at(x, z));
+                cpu.set_b(environment.time_of_day());
+                cpu.set_x(light as u16);
+                cpu.set_y(environment.temperature(biome).floor() as i16 as u16);
+                (STATUS_OK, config.query_cycles)
+            }
+            CLAIM | TASK | DONE | RELEASE => {
+                if entities.resource::<JobBoard>().is_none() {
+                    return (STATUS_INVALID, 0);
+                }
+                let (command, argument) = (cpu.get_a(), cpu.get_b());
+                let held = match command {
+                    CLAIM => job::claim(entities, host, &|task| argument == 0 || task.kind.code() == argument),
+                    TASK if job::renew(entities, host) => job::held(entities, host),
+                    DONE => return (if job::complete(entities, host).is_some() { STATUS_OK } else { STATUS_NO_TASK }, config.query_cycles),
+                    RELEASE => return (if job::release(entities, host).is_some() { STATUS_OK } else { STATUS_NO_TASK }, config.query_cycles),
+                    _ => None,
+                };
+                let task = match held.and_then(|id| entities.resource::<JobBoard>().and_then(|board| board.task(id)).cloned()) {
+                    Some(task) => task,
+                    None => return (STATUS_NO_TASK, config.query_cycles),
+                };
+                let (x, y, z) = match task.kind {
+                    TaskKind::Haul { to, .. } if command == TASK && argument == 1 => to,
+                    kind => kind.position(),
+                };
+                let (thing, count) = match task.kind {
+                    TaskKind::Mine(_) => (world.get_block(x, y, z).map_or(UNLOADED, |block| block.material().id()), 1),
+                    TaskKind::Haul { item, count, .. } => (item.id(), count),
+                    TaskKind::Build { material, .. } => (material.id(), 1),
+                };
+                cpu.set_b(task.kind.code());
+                cpu.set_i(thing);
+                cpu.set_j(count);
+                cpu.set_x((x as i64 - origin.0) as u16);
+                cpu.set_y((y as i64 - origin.1) as u16);
+                cpu.set_z((z as i64 - origin.2) as u16);
+                (STATUS_OK, config.query_cycles)
+            }
+            _ => (STATUS_INVALID, 0),
+        }
+    }
+    fn take_action(&mut self) -> bool {
+        if self.actions >= self.config.actions_per_tick {
+            return false;
+        }
+        self.actions += 1;
+        true
+    }
+}
+
+/// World Block at signed coordinates, None outside the world.
+fn block_at(world: &World, x: i64, y: i64, z: i64) -> Option<BlockPosition> {
+    if y < 0 || y >= CHUNK_SIZE as i64 {
+        return None;
+    }
+    world.resolve(x, z).map(|(x, z)| (x, y as usize, z))
+}
+
+///
+/// Bus connecting a CPU's WorldInterface, as device 0, to its host entity
+/// for the duration of a step. Any other devices follow from index 1.
+///
+pub struct WorldBus<'a> {
+    pub interface: &'a mut WorldInterface,
+    pub world: &'a mut World,
+    pub entities: &'a mut EntityManager,
+    pub host: EntityID,
+    pub devices: &'a mut Vec<Socket>,
+}
+
+impl<'a> Bus for WorldBus<'a> {
+    fn count(&self) -> u16 { 1 + self.devices.count() }
+    fn info(&self, index: u16) -> Option<DeviceInfo> {
+        if index == 0 { Some(self.interface.info()) } else { self.devices.info(index - 1) }
+    }
+    fn interrupt(&mut self, index: u16, cpu: &mut VCPU16) -> u16 {
+        if index != 0 {
+            return self.devices.interrupt(index - 1, cpu);
+        }
+        self.interface.interrupt(cpu, self.world, self.entities, self.host)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::faction::{Faction, Ownership};
+    use model::pheromone::Channel;
+    use model::world::{Chunk, Vector2};
+    use pool::Poolable;
+
+    /// HWI 0
+    const HWI: u16 = 0x8640;
+
+    /// Run the HWI at address 0 to completion, including any stall.
+    fn call(cpu: &mut VCPU16, bus: &mut WorldBus) {
+        cpu.set_pc(0);
+        cpu.set_memory(0, HWI);
+        cpu.step_with(bus);
+        while cpu.is_busy() {
+            cpu.step_with(bus);
+        }
+    }
+
+    #[test]
+    pub fn test_world_interface() {
+        let mut world = World::new();
+        world.insert_chunk(Vector2::new(0, 0), Chunk::allocate());
+        let rock = world.materials().id("rock").unwrap();
+        world.set_block(6, 2, 5, Block::new(rock));
+        let mut entities = EntityManager::new();
+        let host = entities.create_entity();
+        entities.add_component(host, Position::from_f64(5.5, 2.0, 5.5));
+        let mut interface = WorldInterface::new();
+        let mut cpu = VCPU16::allocate();
+        let mut devices = Vec::new();
+        let mut bus = WorldBus { interface: &mut interface, world: &mut world, entities: &mut entities, host, devices: &mut devices };
+
+        cpu.set_a(LOCATE);
+        call(&mut cpu, &mut bus);
+        assert_eq!((cpu.get_c(), cpu.get_x(), cpu.get_y(), cpu.get_z()), (STATUS_OK, 5, 2, 5));
+
+        cpu.set_a(PROBE);
+        cpu.set_x(1);
+        cpu.set_y(0);
+        cpu.set_z(0);
+        call(&mut cpu, &mut bus);
+        assert_eq!((cpu.get_c(), cpu.get_b()), (STATUS_OK, rock.id()));
+
+        // Walking into rock is blocked, but still uses the tick's action
+        cpu.set_a(MOVE);
+        call(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_c(), STATUS_BLOCKED);
+        cpu.set_a(BREAK);
+        call(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_c(), STATUS_BUSY);
+
+        bus.interface.begin_tick();
+        call(&mut cpu, &mut bus);
+        assert_eq!((cpu.get_c(), cpu.get_b()), (STATUS_OK, rock.id()));
+        assert!(bus.world.get_block(6, 2, 5).unwrap().is_air());
+
+        bus.interface.begin_tick();
+        cpu.set_a(MOVE);
+        cpu.set_x(0xFFFF);
+        call(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_c(), STATUS_OK);
+        assert_eq!(*bus.entities.get_component::<Position>(host).unwrap(), Position::from_f64(4.5, 2.0, 5.5));
+
+        // A radius 1 scan around (4, 2, 5) sees the rock placed beside it
+        bus.interface.begin_tick();
+        cpu.set_a(PLACE);
+        cpu.set_b(rock.id());
+        cpu.set_x(0);
+        cpu.set_y(1);
+        call(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_c(), STATUS_OK);
+        cpu.set_a(SCAN);
+        cpu.set_b(0x1000);
+        cpu.set_x(1);
+        call(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_c(), STATUS_OK);
+        assert_eq!(cpu.get_memory(0x1000 + 9 + 6 + 1), rock.id());
+        assert_eq!(cpu.get_memory(0x1000 + 13), AIR.id());
+
+        // Leave a trail where the drone stands and smell it from a Block away
+        let trail = bus.world.pheromones_mut().register(Channel::new("trail", 0.1, 0.0));
+        cpu.set_a(MARK);
+        cpu.set_b(trail.id());
+        cpu.set_x(0);
+        cpu.set_y(0);
+        cpu.set_i(40);
+        call(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_c(), STATUS_OK);
+        bus.world.tick_pheromones();
+        cpu.set_a(SMELL);
+        cpu.set_x(1);
+        call(&mut cpu, &mut bus);
+        assert_eq!((cpu.get_c(), cpu.get_b()), (STATUS_OK, 0));
+        cpu.set_x(0);
+        call(&mut cpu, &mut bus);
+        assert_eq!((cpu.get_c(), cpu.get_b()), (STATUS_OK, 36));
+        cpu.set_b(9);
+        call(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_c(), STATUS_INVALID);
+
+        // Claimed ground is off limits until the host joins the claimant
+        let hive = bus.world.factions_mut().register(Faction::new("hive"));
+        bus.world.factions_mut().claim(Vector2::new(0, 0), hive);
+        cpu.set_a(MARK);
+        cpu.set_b(trail.id());
+        call(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_c(), STATUS_DENIED);
+        bus.interface.begin_tick();
+        cpu.set_a(MOVE);
+        call(&mut cpu, &mut bus);
+        assert_eq!((cpu.get_c(), bus.interface.actions()), (STATUS_DENIED, 0));
+        cpu.set_a(BREAK);
+        cpu.set_y(1);
+        call(&mut cpu, &mut bus);
+        assert_eq!((cpu.get_c(), bus.interface.actions()), (STATUS_DENIED, 0));
+        bus.entities.add_component(host, Ownership::new(hive));
+        call(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_c(), STATUS_OK);
+
+        // At sunrise the open sky is dimmed, though still brighter than a lamp beside the drone
+        cpu.set_a(CLIMATE);
+        call(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_c(), STATUS_INVALID);
+        let mut environment = Environment::new(100);
+        environment.advance(25);
+        bus.entities.insert_resource(environment);
+        bus.world.set_light_emitter(4, 2, 5, 6);
+        bus.world.update_lighting();
+        call(&mut cpu, &mut bus);
+        assert_eq!((cpu.get_c(), cpu.get_b(), cpu.get_x(), cpu.get_y()), (STATUS_OK, 0x4000, 10, 15));
+
+        // Pull a Task off the board, two Blocks down from where the drone stands
+        cpu.set_a(CLAIM);
+        call(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_c(), STATUS_INVALID);
+        let mut board = JobBoard::new();
+        let build = board.post(TaskKind::Build { position: (4, 0, 5), material: rock }, 1);
+        bus.entities.insert_resource(board);
+        cpu.set_b(job::KIND_MINE);
+        call(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_c(), STATUS_NO_TASK);
+        cpu.set_b(0);
+        call(&mut cpu, &mut bus);
+        assert_eq!((cpu.get_c(), cpu.get_b(), cpu.get_i(), cpu.get_j()), (STATUS_OK, job::KIND_BUILD, rock.id(), 1));
+        assert_eq!((cpu.get_x(), cpu.get_y(), cpu.get_z()), (0, 0xFFFE, 0));
+        assert_eq!(bus.entities.resource::<JobBoard>().unwrap().worker(build), Some(host));
+        cpu.set_a(DONE);
+        call(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_c(), STATUS_OK);
+        cpu.set_a(TASK);
+        call(&mut cpu, &mut bus);
+        assert!(cpu.get_c() == STATUS_NO_TASK && bus.entities.resource::<JobBoard>().unwrap().is_empty());
+
+        bus.entities.destroy_entity(host);
+        cpu.set_a(LOCATE);
+        call(&mut cpu, &mut bus);
+        assert_eq!(cpu.get_c(), STATUS_NO_HOST);
+    }
+}
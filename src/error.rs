@@ -0,0 +1,98 @@
+//!
+//! Crate Errors
+//!
+//! HivemindError is returned by the APIs an embedding application feeds with
+//! outside data: memory images and ROMs, saved Chunks and entity ids. Errors
+//! local to one module, such as a rejected edit or structure paste, keep their
+//! own type and convert into HivemindError where they cross into these APIs.
+//!
+use math::Vector2;
+use model::edit::EditError;
+use model::entity::EntityID;
+use model::structure::StructureError;
+use net::lockstep::LockstepError;
+use scenario::ScenarioError;
+use vcpu::machine::MachineError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+///
+/// Hivemind Error
+///
+#[derive(Debug)]
+pub enum HivemindError {
+    /// Storage or a stream failed
+    Io(io::Error),
+    /// A saved Chunk couldn't be decoded
+    CorruptChunk { position: Vector2<u64>, reason: String },
+    /// A ROM of this many words doesn't fit in a CPU's memory
+    RomTooLarge(usize),
+    /// The entity was destroyed, or never existed
+    DeadEntity(EntityID),
+    Edit(EditError),
+    Structure(StructureError),
+    /// A machine template couldn't be parsed
+    Machine(MachineError),
+    /// No device of this name is registered
+    UnknownDevice(String),
+    /// A scenario couldn't be parsed or set up
+    Scenario(ScenarioError),
+    /// A lockstep session desynced or was sent a bad packet
+    Lockstep(LockstepError),
+}
+
+impl fmt::Display for HivemindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HivemindError::Io(ref error) => write!(f, "{}", error),
+            HivemindError::CorruptChunk { position, ref reason } => write!(f, "chunk {},{} is corrupt: {}", position.x, position.y, reason),
+            HivemindError::RomTooLarge(words) => write!(f, "rom of {} words is larger than memory", words),
+            HivemindError::DeadEntity(entity) => write!(f, "entity {}:{} is not alive", entity.slot(), entity.suffix()),
+            HivemindError::Edit(EditError::Unloaded(position)) => write!(f, "block {:?} is not loaded", position),
+            HivemindError::Structure(ref error) => write!(f, "{}", error),
+            HivemindError::Machine(ref error) => write!(f, "{}", error),
+            HivemindError::UnknownDevice(ref name) => write!(f, "no device named {}", name),
+            HivemindError::Scenario(ref error) => write!(f, "{}", error),
+            HivemindError::Lockstep(ref error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for HivemindError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            HivemindError::Io(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HivemindError {
+    fn from(error: io::Error) -> HivemindError { HivemindError::Io(error) }
+}
+
+impl From<EditError> for HivemindError {
+    fn from(error: EditError) -> HivemindError { HivemindError::Edit(error) }
+}
+
+impl From<MachineError> for HivemindError {
+    fn from(error: MachineError) -> HivemindError { HivemindError::Machine(error) }
+}
+
+impl From<LockstepError> for HivemindError {
+    fn from(error: LockstepError) -> HivemindError { HivemindError::Lockstep(error) }
+}
+
+impl From<ScenarioError> for HivemindError {
+    fn from(error: ScenarioError) -> HivemindError { HivemindError::Scenario(error) }
+}
+
+impl From<StructureError> for HivemindError {
+    fn from(error: StructureError) -> HivemindError {
+        match error {
+            StructureError::Edit(error) => HivemindError::Edit(error),
+            error => HivemindError::Structure(error),
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//!
+//! Rewind History
+//!
+//! A History set on a Simulation keeps a keyframe, a fork of the whole
+//! Simulation, every `interval` ticks along with every Block change in the
+//! first World since the oldest keyframe, so the state at any recent past
+//! tick can be rebuilt for inspection: the nearest keyframe at or before the
+//! tick is forked again and stepped forward to it, with the Blocks changed
+//! from outside the ticks put back between them as they happened. Keyframes
+//! share Chunks and CPU memory pages with the Simulation until either side
+//! writes, so keeping a few costs far less than a save each.
+//!
+//! Replay runs the same ticks again, so it only differs from what happened
+//! where something outside the Simulation touched it: Blocks are restored
+//! from the recorded changes, but entities spawned, CPUs interrupted or
+//! components edited from outside between keyframes are not replayed. A
+//! shorter interval narrows that gap and makes rewinding faster at the cost
+//! of more keyframes. Only the last `capacity` keyframes are kept.
+//!
+use error::HivemindError;
+use model::observer::{EventFilter, SubscriberId, WorldEvent};
+use model::update::BlockPosition;
+use model::world::{Block, World};
+use simulation::Simulation;
+use std::collections::VecDeque;
+
+/// Default ticks between keyframes
+pub const DEFAULT_INTERVAL: u64 = 600;
+/// Default keyframes kept
+pub const DEFAULT_CAPACITY: usize = 16;
+
+///
+/// Block change and when it happened
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct Change {
+    tick: u64,
+    /// Made during the tick, otherwise from outside just before it
+    during: bool,
+    position: BlockPosition,
+    block: Block,
+}
+
+///
+/// Keyframes and Block changes of the recent past, see `Simulation::set_history`
+///
+pub struct History {
+    interval: u64,
+    capacity: usize,
+    /// Oldest first
+    keyframes: VecDeque<Simulation>,
+    /// Oldest first, none before the oldest keyframe
+    changes: VecDeque<Change>,
+    subscriber: Option<SubscriberId>,
+    /// Last tick captured
+    latest: u64,
+}
+
+impl History {
+    /// History with a keyframe every `interval` ticks, keeping the last `capacity`.
+    pub fn new(interval: u64, capacity: usize) -> History {
+        History { interval: interval.max(1), capacity: capacity.max(1), keyframes: VecDeque::new(), changes: VecDeque::new(), subscriber: None, latest: 0 }
+    }
+    pub fn interval(&self) -> u64 { self.interval }
+    pub fn capacity(&self) -> usize { self.capacity }
+    pub fn keyframes(&self) -> usize { self.keyframes.len() }
+    /// Oldest and latest tick that can be rebuilt, None before the first keyframe.
+    pub fn span(&self) -> Option<(u64, u64)> { self.keyframes.front().map(|keyframe| (keyframe.tick(), self.latest)) }
+    /// Start watching `world` for Block changes, called by the Simulation.
+    pub fn attach(&mut self, world: &mut World) {
+        self.subscriber = Some(world.subscribe(EventFilter { chunks: false, ..EventFilter::all() }));
+    }
+    /// Stop watching `world`, called by the Simulation.
+    pub fn detach(&mut self, world: &mut World) {
+        if let Some(subscriber) = self.subscriber.take() {
+            world.observers_mut().unsubscribe(subscriber);
+        }
+    }
+    /// Take the Block changes made in `world` since the last drain.
+    fn drain(&mut self, world: &mut World, tick: u64, during: bool) {
+        if let Some(subscriber) = self.subscriber {
+            for event in world.drain_events(subscriber) {
+                if let WorldEvent::BlockChanged { position, after, .. } = event {
+                    self.changes.push_back(Change { tick, during, position, block: after });
+                }
+            }
+        }
+    }
+    /// Note the changes made from outside before a tick, called by the Simulation as it starts one.
+    pub fn before_tick(&mut self, simulation: &mut Simulation) {
+        let tick = simulation.tick() + 1;
+        self.drain(simulation.world_mut(), tick, false);
+    }
+    ///
+    /// Note the changes made during the tick just run and take a keyframe if
+    /// one is due, called by the Simulation as it ends one. A keyframe which
+    /// can't be forked, because swapped out CPU memory couldn't be read, is
+    /// skipped.
+    ///
+    pub fn after_tick(&mut self, simulation: &mut Simulation) {
+        let tick = simulation.tick();
+        self.drain(simulation.world_mut(), tick, true);
+        self.latest = tick;
+        if tick.is_multiple_of(self.interval) {
+            self.keyframe(simulation);
+        }
+    }
+    /// Keep a keyframe of `simulation` as it is now, dropping the oldest beyond capacity.
+    pub fn keyframe(&mut self, simulation: &Simulation) {
+        self.latest = simulation.tick();
+        if let Ok(keyframe) = simulation.fork() {
+            self.keyframes.push_back(keyframe);
+        }
+        while self.keyframes.len() > self.capacity {
+            self.keyframes.pop_front();
+        }
+        let oldest = self.keyframes.front().map_or(0, Simulation::tick);
+        while self.changes.front().is_some_and(|change| change.tick <= oldest) {
+            self.changes.pop_front();
+        }
+    }
+    ///
+    /// Rebuild the Simulation as it was at the end of `tick`, None if that is
+    /// outside the span kept. The rebuilt Simulation is a fork: changing it
+    /// touches neither the live one nor the History.
+    ///
+    pub fn rewind(&self, tick: u64) -> Result<Option<Simulation>, HivemindError> {
+        if tick > self.latest {
+            return Ok(None);
+        }
+        let keyframe = match self.keyframes.iter().rev().find(|keyframe| keyframe.tick() <= tick) {
+            Some(keyframe) => keyframe,
+            None => return Ok(None),
+        };
+        let mut simulation = keyframe.fork()?;
+        let start = simulation.tick();
+        let mut changes = self.changes.iter().skip_while(|change| change.tick <= start).peekable();
+        while simulation.tick() < tick {
+            let next = simulation.tick() + 1;
+            while let Some(change) = changes.next_if(|change| change.tick == next && !change.during) {
+                let (x, y, z) = change.position;
+                simulation.world_mut().set_block(x, y, z, change.block);
+            }
+            simulation.step();
+            while let Some(change) = changes.next_if(|change| change.tick == next) {
+                let (x, y, z) = change.position;
+                simulation.world_mut().set_block(x, y, z, change.block);
+            }
+        }
+        Ok(Some(simulation))
+    }
+}
+
+impl Default for History {
+    fn default() -> History { History::new(DEFAULT_INTERVAL, DEFAULT_CAPACITY) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+    use model::entity::EntityManager;
+    use model::world::{Block, Chunk, Vector2, World};
+    use simulation::Simulation;
+    use vcpu::asm::assemble;
+
+    #[test]
+    pub fn test_rewind() {
+        let mut world = World::new();
+        world.insert_chunk(Vector2::new(0, 0), Box::new(Chunk::new()));
+        let metal = world.materials().id("metal").unwrap();
+        let mut simulation = Simulation::new(world, EntityManager::new());
+        let counter = assemble(":loop ADD A, 1\nSET PC, loop").unwrap().image.to_rom();
+        let drone = simulation.entities_mut().create_entity();
+        let cpu = simulation.attach(drone, &counter).unwrap();
+        simulation.set_history(Some(History::new(10, 3)));
+
+        let mut counts = Vec::new();
+        for tick in 1..=45 {
+            if tick == 17 {
+                simulation.world_mut().set_block(1, 1, 1, Block::new(metal));
+            }
+            simulation.step();
+            counts.push(simulation.cluster().get(cpu).unwrap().get_a());
+        }
+        let history = simulation.history().unwrap();
+        assert_eq!((history.keyframes(), history.span()), (3, Some((20, 45))));
+        assert!(history.rewind(19).unwrap().is_none());
+        assert!(history.rewind(46).unwrap().is_none());
+
+        let past = simulation.rewind(33).unwrap().unwrap();
+        assert_eq!(past.tick(), 33);
+        assert_eq!(past.cluster().get(cpu).unwrap().get_a(), counts[32]);
+        assert_eq!(past.world().get_block(1, 1, 1), Some(Block::new(metal)));
+        assert_eq!(simulation.rewind(45).unwrap().unwrap().cluster().get(cpu).unwrap().get_a(), counts[44]);
+
+        // A Block changed from outside is back as it was between the ticks
+        simulation.world_mut().set_block(1, 1, 1, Block::default());
+        simulation.step();
+        simulation.step();
+        assert_eq!(simulation.rewind(46).unwrap().unwrap().world().get_block(1, 1, 1), Some(Block::default()));
+        assert_eq!(simulation.rewind(45).unwrap().unwrap().world().get_block(1, 1, 1), Some(Block::new(metal)));
+    }
+}
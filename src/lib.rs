@@ -0,0 +1,29 @@
+#![allow(clippy::upper_case_acronyms, clippy::doc_lazy_continuation)]
+
+extern crate rand;
+
+pub mod admin;
+pub mod budget;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod codec;
+#[cfg(feature = "demo")]
+pub mod demo;
+pub mod devices;
+pub mod error;
+pub mod history;
+pub mod math;
+pub mod metrics;
+pub mod migrate;
+pub mod model;
+pub mod net;
+pub mod plugin;
+pub mod pool;
+pub mod recorder;
+pub mod scenario;
+#[cfg(feature = "script")]
+pub mod script;
+pub mod simulation;
+pub mod vcpu;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -1,7 +1,15 @@
-extern crate rand;
-
-mod vcpu;
+extern crate hivemind;
 
+#[cfg(not(feature = "demo"))]
 fn main() {
     println!("Hello, model!");
 }
+
+#[cfg(feature = "demo")]
+fn main() {
+    let mut hive = hivemind::demo::Hive::demo();
+    for _ in 0..10 {
+        hive.tick();
+        println!("{}", hive.render());
+    }
+}
@@ -0,0 +1,233 @@
+//!
+//! Deterministic Math
+//!
+//! Entity positions, velocities and physics never hold floats: a Fixed is a
+//! signed 64 bit number with 16 fractional bits, so every platform computes
+//! bit-identical results and lockstep peers stay in sync. Multiplication and
+//! division go through 128 bit intermediates and saturate rather than wrap.
+//! Elsewhere floats remain: pheromone strengths are f32, kept deterministic
+//! by using only basic IEEE arithmetic on them, and raycasts take f64 queries
+//! which read the World without changing it. Otherwise floats only appear
+//! at the edges, when converting configuration in or values out for display.
+//!
+
+use std::fmt;
+use std::ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub, SubAssign};
+
+/// Fractional bits in a Fixed
+pub const FRACTION_BITS: u32 = 16;
+
+///
+/// Fixed Point Number, i64.16
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Debug)]
+pub struct Fixed(i64);
+
+impl Fixed {
+    pub const ZERO: Fixed = Fixed(0);
+    pub const ONE: Fixed = Fixed(1 << FRACTION_BITS);
+    pub const HALF: Fixed = Fixed(1 << (FRACTION_BITS - 1));
+    /// Smallest step above zero
+    pub const EPSILON: Fixed = Fixed(1);
+    pub const MAX: Fixed = Fixed(i64::MAX);
+    pub const MIN: Fixed = Fixed(i64::MIN);
+
+    pub const fn from_raw(raw: i64) -> Fixed { Fixed(raw) }
+    pub const fn raw(self) -> i64 { self.0 }
+    pub const fn from_int(value: i64) -> Fixed { Fixed(value << FRACTION_BITS) }
+    /// Nearest Fixed to a float, for configuration and tests.
+    pub fn from_f64(value: f64) -> Fixed { Fixed((value * (1u64 << FRACTION_BITS) as f64).round() as i64) }
+    pub fn to_f64(self) -> f64 { self.0 as f64 / (1u64 << FRACTION_BITS) as f64 }
+    /// Largest integer not above the value.
+    pub fn floor(self) -> i64 { self.0 >> FRACTION_BITS }
+    /// Smallest integer not below the value.
+    pub fn ceil(self) -> i64 { -((-self).floor()) }
+    /// Part above the floor, always in 0..1.
+    pub fn fract(self) -> Fixed { Fixed(self.0 & ((1 << FRACTION_BITS) - 1)) }
+    pub fn abs(self) -> Fixed { Fixed(self.0.saturating_abs()) }
+    pub fn is_negative(self) -> bool { self.0 < 0 }
+    pub fn is_positive(self) -> bool { self.0 > 0 }
+    ///
+    /// Square root rounded down, zero for negative values.
+    ///
+    pub fn sqrt(self) -> Fixed {
+        if self.0 <= 0 {
+            return Fixed::ZERO;
+        }
+        Fixed(((self.0 as u128) << FRACTION_BITS).isqrt() as i64)
+    }
+}
+
+fn saturate(value: i128) -> Fixed { Fixed(value.clamp(i64::MIN as i128, i64::MAX as i128) as i64) }
+
+impl Add for Fixed {
+    type Output = Fixed;
+    fn add(self, other: Fixed) -> Fixed { Fixed(self.0.saturating_add(other.0)) }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+    fn sub(self, other: Fixed) -> Fixed { Fixed(self.0.saturating_sub(other.0)) }
+}
+
+impl Mul for Fixed {
+    type Output = Fixed;
+    fn mul(self, other: Fixed) -> Fixed { saturate((self.0 as i128 * other.0 as i128) >> FRACTION_BITS) }
+}
+
+impl Div for Fixed {
+    type Output = Fixed;
+    /// Division rounds toward negative infinity, and by zero saturates.
+    fn div(self, other: Fixed) -> Fixed {
+        if other.0 == 0 {
+            return match self.0 {
+                0 => Fixed::ZERO,
+                value if value > 0 => Fixed::MAX,
+                _ => Fixed::MIN,
+            };
+        }
+        let (numerator, denominator) = ((self.0 as i128) << FRACTION_BITS, other.0 as i128);
+        let quotient = numerator / denominator;
+        if numerator % denominator != 0 && (numerator < 0) != (denominator < 0) {
+            saturate(quotient - 1)
+        } else {
+            saturate(quotient)
+        }
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+    fn neg(self) -> Fixed { Fixed(self.0.saturating_neg()) }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, other: Fixed) { *self = *self + other }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, other: Fixed) { *self = *self - other }
+}
+
+impl MulAssign for Fixed {
+    fn mul_assign(&mut self, other: Fixed) { *self = *self * other }
+}
+
+impl fmt::Display for Fixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{}", self.to_f64()) }
+}
+
+///
+/// Fixed Point 2D Vector
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Vec2 {
+    pub x: Fixed,
+    pub y: Fixed,
+}
+
+impl Vec2 {
+    pub const ZERO: Vec2 = Vec2 { x: Fixed::ZERO, y: Fixed::ZERO };
+    pub fn new(x: Fixed, y: Fixed) -> Vec2 { Vec2 { x, y } }
+    pub fn from_f64(x: f64, y: f64) -> Vec2 { Vec2::new(Fixed::from_f64(x), Fixed::from_f64(y)) }
+    pub fn dot(&self, other: &Vec2) -> Fixed { self.x * other.x + self.y * other.y }
+    pub fn length_squared(&self) -> Fixed { self.dot(self) }
+    pub fn length(&self) -> Fixed { self.length_squared().sqrt() }
+}
+
+impl Add for Vec2 {
+    type Output = Vec2;
+    fn add(self, other: Vec2) -> Vec2 { Vec2::new(self.x + other.x, self.y + other.y) }
+}
+
+impl Sub for Vec2 {
+    type Output = Vec2;
+    fn sub(self, other: Vec2) -> Vec2 { Vec2::new(self.x - other.x, self.y - other.y) }
+}
+
+impl Mul<Fixed> for Vec2 {
+    type Output = Vec2;
+    fn mul(self, scale: Fixed) -> Vec2 { Vec2::new(self.x * scale, self.y * scale) }
+}
+
+impl Neg for Vec2 {
+    type Output = Vec2;
+    fn neg(self) -> Vec2 { Vec2::new(-self.x, -self.y) }
+}
+
+///
+/// Fixed Point 3D Vector
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Vec3 {
+    pub x: Fixed,
+    pub y: Fixed,
+    pub z: Fixed,
+}
+
+impl Vec3 {
+    pub const ZERO: Vec3 = Vec3 { x: Fixed::ZERO, y: Fixed::ZERO, z: Fixed::ZERO };
+    pub fn new(x: Fixed, y: Fixed, z: Fixed) -> Vec3 { Vec3 { x, y, z } }
+    pub fn from_f64(x: f64, y: f64, z: f64) -> Vec3 { Vec3::new(Fixed::from_f64(x), Fixed::from_f64(y), Fixed::from_f64(z)) }
+    pub fn dot(&self, other: &Vec3) -> Fixed { self.x * other.x + self.y * other.y + self.z * other.z }
+    pub fn length_squared(&self) -> Fixed { self.dot(self) }
+    pub fn length(&self) -> Fixed { self.length_squared().sqrt() }
+    /// Block containing the point.
+    pub fn floor(&self) -> (i64, i64, i64) { (self.x.floor(), self.y.floor(), self.z.floor()) }
+}
+
+impl Add for Vec3 {
+    type Output = Vec3;
+    fn add(self, other: Vec3) -> Vec3 { Vec3::new(self.x + other.x, self.y + other.y, self.z + other.z) }
+}
+
+impl Sub for Vec3 {
+    type Output = Vec3;
+    fn sub(self, other: Vec3) -> Vec3 { Vec3::new(self.x - other.x, self.y - other.y, self.z - other.z) }
+}
+
+impl Mul<Fixed> for Vec3 {
+    type Output = Vec3;
+    fn mul(self, scale: Fixed) -> Vec3 { Vec3::new(self.x * scale, self.y * scale, self.z * scale) }
+}
+
+impl Neg for Vec3 {
+    type Output = Vec3;
+    fn neg(self) -> Vec3 { Vec3::new(-self.x, -self.y, -self.z) }
+}
+
+/// Integer 2D Coordinate
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Default)]
+pub struct Vector2<T> {
+    pub x: T,
+    pub y: T,
+}
+
+impl<T> Vector2<T> {
+    pub fn new(x: T, y: T) -> Vector2<T> { Vector2 { x, y } }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fixed, Vec3};
+
+    #[test]
+    pub fn test_fixed_arithmetic() {
+        let (a, b) = (Fixed::from_f64(2.5), Fixed::from_int(-3));
+        assert_eq!(a + b, Fixed::from_f64(-0.5));
+        assert_eq!(a * b, Fixed::from_f64(-7.5));
+        assert_eq!(b / Fixed::from_int(2), Fixed::from_f64(-1.5));
+        assert_eq!(Fixed::ONE / Fixed::from_int(3), Fixed::from_raw(21845));
+        assert_eq!(-Fixed::ONE / Fixed::from_int(3), Fixed::from_raw(-21846));
+        assert_eq!(Fixed::ONE / Fixed::ZERO, Fixed::MAX);
+        assert_eq!((a.floor(), a.ceil(), (-a).floor(), (-a).ceil()), (2, 3, -3, -2));
+        assert_eq!((-a).fract(), Fixed::HALF);
+        assert_eq!(Fixed::from_int(1 << 40) * Fixed::from_int(1 << 40), Fixed::MAX);
+        assert_eq!(Fixed::from_int(9).sqrt(), Fixed::from_int(3));
+        assert_eq!(Fixed::from_int(2).sqrt(), Fixed::from_raw(92681));
+
+        let v = Vec3::from_f64(3.0, 4.0, 12.0);
+        assert_eq!(v.length(), Fixed::from_int(13));
+        assert_eq!((v - v * Fixed::HALF).floor(), (1, 2, 6));
+    }
+}
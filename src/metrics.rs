@@ -0,0 +1,271 @@
+//!
+//! Telemetry
+//!
+//! Metrics holds counters, gauges and timing histograms for a Simulation.
+//! Counters keep both their count for the current tick and a running total;
+//! `begin_tick` zeroes the former. Histograms bucket durations, one per
+//! System, so slow stages of a big hive show up without println. An Exporter
+//! turns the whole set into text: a single log line, or with the `prometheus`
+//! feature the Prometheus text format.
+//!
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// CPU cycles executed, counted by the Simulation
+pub const CPU_CYCLES: &str = "cpu_cycles";
+/// CPUs granted fewer cycles than they wanted, counted by the Simulation
+pub const CPUS_STARVED: &str = "cpus_starved";
+/// Faults raised by CPUs, counted by the Simulation
+pub const CPU_FAULTS: &str = "cpu_faults";
+/// Chunks arriving from storage or generation, counted by the Simulation
+pub const CHUNKS_LOADED: &str = "chunks_loaded";
+/// Chunks evicted to keep within the memory budget, counted by the Simulation
+pub const CHUNKS_EVICTED: &str = "chunks_evicted";
+/// Packets sent to clients, counted by whoever sends them
+pub const PACKETS_SENT: &str = "packets_sent";
+/// Bytes sent to clients, counted by whoever sends them, see `net::batch`
+pub const BYTES_SENT: &str = "bytes_sent";
+/// Bytes batching and compression saved sending, see `net::batch`
+pub const BYTES_SAVED: &str = "bytes_saved";
+/// Living entities, a gauge set by the Simulation
+pub const ENTITIES: &str = "entities";
+/// Estimated bytes held, a gauge set by the Simulation, see `budget`
+pub const MEMORY_BYTES: &str = "memory_bytes";
+/// Histogram of whole ticks
+pub const TICK: &str = "tick";
+
+/// Upper bounds of the histogram buckets in microseconds, beyond the last is overflow
+pub const BUCKETS: [u64; 8] = [10, 50, 100, 500, 1_000, 5_000, 10_000, 50_000];
+
+///
+/// Count of something happening
+///
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Counter {
+    /// Since the current tick began
+    pub tick: u64,
+    pub total: u64,
+}
+
+///
+/// Distribution of Durations
+///
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Histogram {
+    /// Per bucket, not cumulative, the last is overflow
+    buckets: [u64; BUCKETS.len() + 1],
+    count: u64,
+    sum: Duration,
+    max: Duration,
+}
+
+impl Histogram {
+    pub fn new() -> Histogram { Histogram::default() }
+    pub fn record(&mut self, elapsed: Duration) {
+        let micros = elapsed.as_micros();
+        let bucket = BUCKETS.iter().position(|&bound| micros <= bound as u128).unwrap_or(BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += elapsed;
+        self.max = self.max.max(elapsed);
+    }
+    /// Samples per bucket of `BUCKETS`, followed by the overflow.
+    pub fn buckets(&self) -> &[u64] { &self.buckets }
+    pub fn count(&self) -> u64 { self.count }
+    pub fn sum(&self) -> Duration { self.sum }
+    pub fn max(&self) -> Duration { self.max }
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 { Duration::from_secs(0) } else { self.sum / self.count as u32 }
+    }
+}
+
+///
+/// Named Counters, Gauges and Histograms
+///
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct Metrics {
+    counters: BTreeMap<String, Counter>,
+    gauges: BTreeMap<String, i64>,
+    histograms: BTreeMap<String, Histogram>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics { Metrics::default() }
+    /// Zero the per tick count of every counter.
+    pub fn begin_tick(&mut self) {
+        for counter in self.counters.values_mut() {
+            counter.tick = 0;
+        }
+    }
+    pub fn add(&mut self, name: &str, amount: u64) {
+        let counter = self.counters.entry(name.to_string()).or_default();
+        counter.tick += amount;
+        counter.total += amount;
+    }
+    /// A counter never added to is zero.
+    pub fn counter(&self, name: &str) -> Counter { self.counters.get(name).cloned().unwrap_or_default() }
+    pub fn counters(&self) -> &BTreeMap<String, Counter> { &self.counters }
+    pub fn set_gauge(&mut self, name: &str, value: i64) { self.gauges.insert(name.to_string(), value); }
+    pub fn gauge(&self, name: &str) -> Option<i64> { self.gauges.get(name).cloned() }
+    pub fn gauges(&self) -> &BTreeMap<String, i64> { &self.gauges }
+    pub fn record(&mut self, name: &str, elapsed: Duration) {
+        self.histograms.entry(name.to_string()).or_default().record(elapsed)
+    }
+    pub fn histogram(&self, name: &str) -> Option<&Histogram> { self.histograms.get(name) }
+    pub fn histograms(&self) -> &BTreeMap<String, Histogram> { &self.histograms }
+    /// Forget everything recorded so far.
+    pub fn clear(&mut self) {
+        self.counters.clear();
+        self.gauges.clear();
+        self.histograms.clear();
+    }
+    ///
+    /// One line summary: counters as `name=tick/total`, gauges as
+    /// `name=value` and histograms as `name=mean/max` in microseconds.
+    ///
+    pub fn log_line(&self) -> String {
+        let mut fields = Vec::new();
+        for (name, counter) in self.counters.iter() {
+            fields.push(format!("{}={}/{}", name, counter.tick, counter.total));
+        }
+        for (name, value) in self.gauges.iter() {
+            fields.push(format!("{}={}", name, value));
+        }
+        for (name, histogram) in self.histograms.iter() {
+            fields.push(format!("{}={}/{}us", name, histogram.mean().as_micros(), histogram.max().as_micros()));
+        }
+        fields.join(" ")
+    }
+    ///
+    /// Prometheus text exposition format. Counters and gauges become
+    /// `hivemind_<name>` metrics, histograms share `hivemind_system_seconds`
+    /// with the histogram's name as its `system` label.
+    ///
+    #[cfg(feature = "prometheus")]
+    pub fn prometheus(&self) -> String {
+        use std::fmt::Write;
+
+        let mut text = String::new();
+        for (name, counter) in self.counters.iter() {
+            let name = metric_name(name);
+            let _ = writeln!(text, "# TYPE hivemind_{}_total counter", name);
+            let _ = writeln!(text, "hivemind_{}_total {}", name, counter.total);
+        }
+        for (name, value) in self.gauges.iter() {
+            let name = metric_name(name);
+            let _ = writeln!(text, "# TYPE hivemind_{} gauge", name);
+            let _ = writeln!(text, "hivemind_{} {}", name, value);
+        }
+        if !self.histograms.is_empty() {
+            let _ = writeln!(text, "# TYPE hivemind_system_seconds histogram");
+        }
+        for (name, histogram) in self.histograms.iter() {
+            let system = label_value(name);
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(histogram.buckets.iter()) {
+                cumulative += count;
+                let le = *bound as f64 / 1_000_000.0;
+                let _ = writeln!(text, "hivemind_system_seconds_bucket{{system=\"{}\",le=\"{}\"}} {}", system, le, cumulative);
+            }
+            let _ = writeln!(text, "hivemind_system_seconds_bucket{{system=\"{}\",le=\"+Inf\"}} {}", system, histogram.count);
+            let _ = writeln!(text, "hivemind_system_seconds_sum{{system=\"{}\"}} {}", system, histogram.sum.as_secs_f64());
+            let _ = writeln!(text, "hivemind_system_seconds_count{{system=\"{}\"}} {}", system, histogram.count);
+        }
+        text
+    }
+}
+
+/// Metric names may only hold ASCII letters, digits and underscores.
+#[cfg(feature = "prometheus")]
+fn metric_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+}
+
+#[cfg(feature = "prometheus")]
+fn label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+///
+/// Destination for Metrics
+///
+pub trait Exporter {
+    fn export(&mut self, metrics: &Metrics) -> io::Result<()>;
+}
+
+///
+/// Writes `Metrics::log_line` followed by a newline
+///
+pub struct LogExporter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> LogExporter<W> {
+    pub fn new(writer: W) -> LogExporter<W> { LogExporter { writer } }
+    pub fn into_inner(self) -> W { self.writer }
+}
+
+impl<W: Write> Exporter for LogExporter<W> {
+    fn export(&mut self, metrics: &Metrics) -> io::Result<()> { writeln!(self.writer, "{}", metrics.log_line()) }
+}
+
+///
+/// Writes `Metrics::prometheus`, typically to a file scraped by a node exporter
+///
+#[cfg(feature = "prometheus")]
+pub struct PrometheusExporter<W: Write> {
+    writer: W,
+}
+
+#[cfg(feature = "prometheus")]
+impl<W: Write> PrometheusExporter<W> {
+    pub fn new(writer: W) -> PrometheusExporter<W> { PrometheusExporter { writer } }
+    pub fn into_inner(self) -> W { self.writer }
+}
+
+#[cfg(feature = "prometheus")]
+impl<W: Write> Exporter for PrometheusExporter<W> {
+    fn export(&mut self, metrics: &Metrics) -> io::Result<()> { self.writer.write_all(metrics.prometheus().as_bytes()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Exporter, LogExporter, Metrics, CPU_CYCLES, ENTITIES};
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_metrics() {
+        let mut metrics = Metrics::new();
+        metrics.add(CPU_CYCLES, 100);
+        metrics.begin_tick();
+        metrics.add(CPU_CYCLES, 40);
+        metrics.set_gauge(ENTITIES, 3);
+        metrics.record("physics", Duration::from_micros(40));
+        metrics.record("physics", Duration::from_micros(60));
+        metrics.record("physics", Duration::from_secs(1));
+
+        assert_eq!((metrics.counter(CPU_CYCLES).tick, metrics.counter(CPU_CYCLES).total), (40, 140));
+        assert_eq!(metrics.counter("missing").total, 0);
+        let physics = metrics.histogram("physics").unwrap();
+        assert_eq!(physics.buckets(), &[0, 1, 1, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(physics.max(), Duration::from_secs(1));
+
+        let mut exporter = LogExporter::new(Vec::new());
+        exporter.export(&metrics).unwrap();
+        assert_eq!(String::from_utf8(exporter.into_inner()).unwrap(), "cpu_cycles=40/140 entities=3 physics=333366/1000000us\n");
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    pub fn test_prometheus_format() {
+        let mut metrics = Metrics::new();
+        metrics.add("mining/claims", 2);
+        metrics.record("census", Duration::from_micros(20));
+        let text = metrics.prometheus();
+        assert!(text.contains("# TYPE hivemind_mining_claims_total counter\nhivemind_mining_claims_total 2\n"));
+        assert!(text.contains("hivemind_system_seconds_bucket{system=\"census\",le=\"0.00001\"} 0\n"));
+        assert!(text.contains("hivemind_system_seconds_bucket{system=\"census\",le=\"0.00005\"} 1\n"));
+        assert!(text.contains("hivemind_system_seconds_count{system=\"census\"} 1\n"));
+    }
+}
@@ -0,0 +1,177 @@
+//!
+//! Save Format Versions and Migrations
+//!
+//! Every saved artifact starts with its magic, if it has one, followed by a
+//! u16 format version. When a format changes its version is bumped and a
+//! Migration registered which rewrites the previous version into the new
+//! one, so old saves are upgraded a version at a time as they are loaded.
+//! Artifacts from a newer build are refused rather than misread. Memory
+//! images of version 0 had no header at all and are given one as they are
+//! read.
+//!
+//! ---+-----------+-------+--------+-------------------------------------------
+//!  # | ARTIFACT  | MAGIC | VERSION| DESCRIPTION
+//! ---+-----------+-------+--------+-------------------------------------------
+//!  1 | Region    | HVRG  | 3      | Region file, see `model::storage`
+//!  2 | Entities  |       | 1      | Entities of a Chunk, see `model::persist`
+//!  3 | Structure | HVST  | 1      | Structure file, see `model::structure`
+//!  4 | Bundle    | HVEB  | 1      | Entity bundle, see `model::bundle`
+//!  5 | CpuState  | HVCS  | 1      | CPU memory image, see `vcpu::cpu`
+//! ---+-----------+-------+--------+-------------------------------------------
+//!
+use codec::invalid_data;
+use model::{bundle, persist, storage, structure};
+use std::collections::HashMap;
+use std::io::{self, Read};
+use vcpu::cpu;
+
+///
+/// Kind of saved data
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Artifact {
+    Region,
+    Entities,
+    Structure,
+    Bundle,
+    CpuState,
+}
+
+impl Artifact {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Artifact::Region => "region file",
+            Artifact::Entities => "entity list",
+            Artifact::Structure => "structure file",
+            Artifact::Bundle => "entity bundle",
+            Artifact::CpuState => "memory image",
+        }
+    }
+    /// Bytes every artifact of this kind starts with, before its version.
+    pub fn magic(&self) -> &'static [u8] {
+        match *self {
+            Artifact::Region => storage::MAGIC,
+            Artifact::Entities => b"",
+            Artifact::Structure => structure::MAGIC,
+            Artifact::Bundle => bundle::MAGIC,
+            Artifact::CpuState => cpu::MAGIC,
+        }
+    }
+    /// Version written by this build.
+    pub fn current_version(&self) -> u16 {
+        match *self {
+            Artifact::Region => storage::VERSION,
+            Artifact::Entities => persist::VERSION,
+            Artifact::Structure => structure::VERSION,
+            Artifact::Bundle => bundle::VERSION,
+            Artifact::CpuState => cpu::VERSION,
+        }
+    }
+    /// Version of an artifact of this kind, checking its magic.
+    pub fn version_of(&self, bytes: &[u8]) -> io::Result<u16> {
+        let magic = self.magic();
+        if bytes.len() < magic.len() + 2 || &bytes[..magic.len()] != magic {
+            return Err(invalid_data(&format!("not a {}", self.name())));
+        }
+        Ok(u16::from_le_bytes([bytes[magic.len()], bytes[magic.len() + 1]]))
+    }
+}
+
+/// Rewrite a whole artifact of one version as the next, version field included.
+pub type Migration = fn(Vec<u8>) -> io::Result<Vec<u8>>;
+
+///
+/// Migrations by Artifact and the version they upgrade from
+///
+/// The default registry holds every migration of this build.
+///
+#[derive(Clone)]
+pub struct Migrations {
+    steps: HashMap<(Artifact, u16), Migration>,
+}
+
+impl Migrations {
+    /// Registry without any migrations.
+    pub fn empty() -> Migrations { Migrations { steps: HashMap::new() } }
+    /// Upgrade `artifact` from version `from` to `from + 1` with `migration`.
+    pub fn register(&mut self, artifact: Artifact, from: u16, migration: Migration) -> Option<Migration> {
+        self.steps.insert((artifact, from), migration)
+    }
+    pub fn get(&self, artifact: Artifact, from: u16) -> Option<Migration> { self.steps.get(&(artifact, from)).cloned() }
+    ///
+    /// Bring a whole artifact up to its current version, returning it
+    /// untouched if it already is.
+    ///
+    pub fn upgrade(&self, artifact: Artifact, mut bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+        let current = artifact.current_version();
+        let mut version = artifact.version_of(&bytes)?;
+        if version > current {
+            return Err(invalid_data(&format!("{} version {} is newer than this build's {}", artifact.name(), version, current)));
+        }
+        while version < current {
+            let migration = self.get(artifact, version)
+                .ok_or_else(|| invalid_data(&format!("no migration for {} version {}", artifact.name(), version)))?;
+            bytes = migration(bytes)?;
+            let upgraded = artifact.version_of(&bytes)?;
+            if upgraded != version + 1 {
+                return Err(invalid_data(&format!("{} migration from version {} produced version {}", artifact.name(), version, upgraded)));
+            }
+            version = upgraded;
+        }
+        Ok(bytes)
+    }
+    /// Read the rest of `reader` as an artifact brought up to its current version.
+    pub fn read(&self, artifact: Artifact, reader: &mut dyn Read) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        self.upgrade(artifact, bytes)
+    }
+}
+
+impl Default for Migrations {
+    fn default() -> Migrations {
+        let mut migrations = Migrations::empty();
+        migrations.register(Artifact::Region, 1, storage::upgrade_region_v1);
+        migrations.register(Artifact::Region, 2, storage::upgrade_region_v2);
+        migrations.register(Artifact::CpuState, 0, cpu::upgrade_cpu_state_v0);
+        migrations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Artifact, Migrations};
+    use model::structure::{Placement, Structure};
+    use model::world::{Block, Chunk, Vector2, World};
+    use pool::Poolable;
+    use std::io;
+
+    fn bump(mut bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+        bytes[4] += 1;
+        bytes.push(0xAA);
+        Ok(bytes)
+    }
+
+    #[test]
+    pub fn test_migrations() {
+        let current = Artifact::Structure.current_version() as u8;
+        let mut migrations = Migrations::empty();
+        let old = vec![b'H', b'V', b'S', b'T', current - 1, 0];
+        assert!(migrations.upgrade(Artifact::Structure, old.clone()).is_err());
+        migrations.register(Artifact::Structure, current as u16 - 1, bump);
+        assert_eq!(migrations.upgrade(Artifact::Structure, old).unwrap(), vec![b'H', b'V', b'S', b'T', current, 0, 0xAA]);
+
+        // Saves from the future and other files are refused
+        assert!(migrations.upgrade(Artifact::Structure, vec![b'H', b'V', b'S', b'T', current + 1, 0]).is_err());
+        assert!(migrations.upgrade(Artifact::Structure, b"HVRG\x01\x00".to_vec()).is_err());
+
+        // A version 1 structure saved by an earlier build
+        let fixture: &[u8] = include_bytes!("../fixtures/saves/structure-v1.hvs");
+        let structure = Structure::load(&mut &fixture[..]).unwrap();
+        let mut world = World::new();
+        world.insert_chunk(Vector2::new(0, 0), Chunk::allocate());
+        structure.paste(&mut world, (0, 0, 0), &Placement::default()).unwrap();
+        assert_eq!(world.get_block(0, 0, 0), Some(Block::new(world.materials().id("rock").unwrap())));
+        assert_eq!(structure.size(), (1, 2, 1));
+    }
+}
@@ -0,0 +1,391 @@
+///
+/// Behavior Trees
+///
+/// Simple creatures are driven by data-defined trees instead of a VCPU each.
+/// A tree is a Node description flattened into preorder so that the per entity
+/// state (one counter per node) is a plain vector which can be saved alongside
+/// the entity. Leaves name Actions registered with the Behaviors system; each
+/// Action is a closure over the entity, the ECS and the World.
+///
+/// Composites remember which child was running and resume there on the next
+/// tick. Whenever a node finishes, with success or failure, its whole subtree
+/// is reset.
+///
+use codec::{invalid_data, read_string, read_u16, read_u32, read_u8, write_string, write_u16, write_u32, write_u8};
+use model::entity::{EntityID, EntityManager};
+use model::world::World;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+///
+/// Result of running a node
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Status {
+    Success,
+    Failure,
+    Running,
+}
+
+///
+/// Behavior Tree Description
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Node {
+    /// Run children in order until one fails
+    Sequence(Vec<Node>),
+    /// Run children in order until one succeeds
+    Selector(Vec<Node>),
+    /// Swap the child's success and failure
+    Invert(Box<Node>),
+    /// Succeed once the child finishes, however it finishes
+    Succeed(Box<Node>),
+    /// Run the child to success this many times, one per tick, 0 for forever
+    Repeat(u32, Box<Node>),
+    /// Keep running for this many ticks
+    Wait(u32),
+    /// Registered Action
+    Action(String),
+}
+
+impl Node {
+    fn children(&self) -> Vec<&Node> {
+        match *self {
+            Node::Sequence(ref children) | Node::Selector(ref children) => children.iter().collect(),
+            Node::Invert(ref child) | Node::Succeed(ref child) | Node::Repeat(_, ref child) => vec![&**child],
+            Node::Wait(_) | Node::Action(_) => Vec::new(),
+        }
+    }
+    pub fn save(&self, writer: &mut dyn Write) -> io::Result<()> {
+        match *self {
+            Node::Sequence(ref children) | Node::Selector(ref children) => {
+                write_u8(writer, if let Node::Sequence(_) = *self { 0 } else { 1 })?;
+                write_u16(writer, children.len() as u16)?;
+                for child in children.iter() {
+                    child.save(writer)?;
+                }
+            }
+            Node::Invert(ref child) => {
+                write_u8(writer, 2)?;
+                child.save(writer)?;
+            }
+            Node::Succeed(ref child) => {
+                write_u8(writer, 3)?;
+                child.save(writer)?;
+            }
+            Node::Repeat(count, ref child) => {
+                write_u8(writer, 4)?;
+                write_u32(writer, count)?;
+                child.save(writer)?;
+            }
+            Node::Wait(ticks) => {
+                write_u8(writer, 5)?;
+                write_u32(writer, ticks)?;
+            }
+            Node::Action(ref name) => {
+                write_u8(writer, 6)?;
+                write_string(writer, name)?;
+            }
+        }
+        Ok(())
+    }
+    pub fn load(reader: &mut dyn Read) -> io::Result<Node> {
+        Ok(match read_u8(reader)? {
+            tag @ 0..=1 => {
+                let mut children = Vec::new();
+                for _ in 0..read_u16(reader)? {
+                    children.push(Node::load(reader)?);
+                }
+                if tag == 0 { Node::Sequence(children) } else { Node::Selector(children) }
+            }
+            2 => Node::Invert(Box::new(Node::load(reader)?)),
+            3 => Node::Succeed(Box::new(Node::load(reader)?)),
+            4 => {
+                let count = read_u32(reader)?;
+                Node::Repeat(count, Box::new(Node::load(reader)?))
+            }
+            5 => Node::Wait(read_u32(reader)?),
+            6 => Node::Action(read_string(reader)?),
+            _ => return Err(invalid_data("unknown behavior node")),
+        })
+    }
+}
+
+/// Node without its children
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Kind {
+    Sequence,
+    Selector,
+    Invert,
+    Succeed,
+    Repeat(u32),
+    Wait(u32),
+    Action(String),
+}
+
+///
+/// Flattened node, its subtree is the `size` nodes starting with itself
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Flat {
+    kind: Kind,
+    size: usize,
+}
+
+///
+/// Runnable Behavior Tree
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BehaviorTree {
+    root: Node,
+    nodes: Vec<Flat>,
+}
+
+impl BehaviorTree {
+    pub fn new(root: Node) -> BehaviorTree {
+        let mut nodes = Vec::new();
+        flatten(&root, &mut nodes);
+        BehaviorTree { root, nodes }
+    }
+    pub fn root(&self) -> &Node { &self.root }
+    /// Number of nodes, and of state counters an entity needs.
+    pub fn len(&self) -> usize { self.nodes.len() }
+    pub fn is_empty(&self) -> bool { self.nodes.is_empty() }
+    fn children(&self, index: usize) -> Vec<usize> {
+        let mut children = Vec::new();
+        let mut child = index + 1;
+        while child < index + self.nodes[index].size {
+            children.push(child);
+            child += self.nodes[child].size;
+        }
+        children
+    }
+    fn reset(&self, index: usize, state: &mut [u32]) {
+        for counter in state[index..index + self.nodes[index].size].iter_mut() {
+            *counter = 0;
+        }
+    }
+    fn run(&self, index: usize, state: &mut [u32], context: &mut Context) -> Status {
+        let status = match self.nodes[index].kind {
+            Kind::Sequence | Kind::Selector => {
+                let (sequence, children) = (self.nodes[index].kind == Kind::Sequence, self.children(index));
+                let mut outcome = if sequence { Status::Success } else { Status::Failure };
+                for (cursor, &child) in children.iter().enumerate().skip(state[index] as usize) {
+                    match self.run(child, state, context) {
+                        Status::Running => {
+                            state[index] = cursor as u32;
+                            return Status::Running;
+                        }
+                        Status::Success if !sequence => {
+                            outcome = Status::Success;
+                            break;
+                        }
+                        Status::Failure if sequence => {
+                            outcome = Status::Failure;
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                outcome
+            }
+            Kind::Invert => match self.run(index + 1, state, context) {
+                Status::Success => Status::Failure,
+                Status::Failure => Status::Success,
+                Status::Running => Status::Running,
+            },
+            Kind::Succeed => match self.run(index + 1, state, context) {
+                Status::Running => Status::Running,
+                _ => Status::Success,
+            },
+            Kind::Repeat(count) => match self.run(index + 1, state, context) {
+                Status::Success => {
+                    state[index] += 1;
+                    if count != 0 && state[index] >= count { Status::Success } else { return Status::Running }
+                }
+                other => other,
+            },
+            Kind::Wait(ticks) => {
+                state[index] += 1;
+                if state[index] >= ticks { Status::Success } else { return Status::Running }
+            }
+            Kind::Action(ref name) => match context.actions.get_mut(name) {
+                Some(action) => action(context.entity, context.entities, context.world),
+                None => Status::Failure,
+            },
+        };
+        if status != Status::Running {
+            self.reset(index, state);
+        }
+        status
+    }
+}
+
+fn flatten(node: &Node, nodes: &mut Vec<Flat>) {
+    let index = nodes.len();
+    let kind = match *node {
+        Node::Sequence(_) => Kind::Sequence,
+        Node::Selector(_) => Kind::Selector,
+        Node::Invert(_) => Kind::Invert,
+        Node::Succeed(_) => Kind::Succeed,
+        Node::Repeat(count, _) => Kind::Repeat(count),
+        Node::Wait(ticks) => Kind::Wait(ticks),
+        Node::Action(ref name) => Kind::Action(name.clone()),
+    };
+    nodes.push(Flat { kind, size: 1 });
+    for child in node.children() {
+        flatten(child, nodes);
+    }
+    nodes[index].size = nodes.len() - index;
+}
+
+/// Leaf behavior run against one entity
+pub type Action = Box<dyn FnMut(EntityID, &mut EntityManager, &mut World) -> Status>;
+
+struct Context<'a> {
+    actions: &'a mut HashMap<String, Action>,
+    entity: EntityID,
+    entities: &'a mut EntityManager,
+    world: &'a mut World,
+}
+
+///
+/// Behavior Component, the tree an entity runs and its progress through it
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Behavior {
+    pub tree: String,
+    state: Vec<u32>,
+}
+
+impl Behavior {
+    pub fn new(tree: &str) -> Behavior { Behavior { tree: tree.to_string(), state: Vec::new() } }
+    /// Per node counters, empty until first run.
+    pub fn state(&self) -> &[u32] { &self.state }
+    pub fn save(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write_string(writer, &self.tree)?;
+        write_u16(writer, self.state.len() as u16)?;
+        for &counter in self.state.iter() {
+            write_u32(writer, counter)?;
+        }
+        Ok(())
+    }
+    pub fn load(reader: &mut dyn Read) -> io::Result<Behavior> {
+        let tree = read_string(reader)?;
+        let mut state = Vec::new();
+        for _ in 0..read_u16(reader)? {
+            state.push(read_u32(reader)?);
+        }
+        Ok(Behavior { tree, state })
+    }
+}
+
+///
+/// Behavior System: named trees and the Actions their leaves call
+///
+#[derive(Default)]
+pub struct Behaviors {
+    trees: HashMap<String, BehaviorTree>,
+    actions: HashMap<String, Action>,
+}
+
+impl Behaviors {
+    pub fn new() -> Behaviors { Behaviors::default() }
+    /// Add or replace a tree. Entities running a replaced tree restart it.
+    pub fn register_tree(&mut self, name: &str, root: Node) {
+        self.trees.insert(name.to_string(), BehaviorTree::new(root));
+    }
+    pub fn tree(&self, name: &str) -> Option<&BehaviorTree> { self.trees.get(name) }
+    /// Bind an Action name used by tree leaves, unbound Actions fail.
+    pub fn register_action(&mut self, name: &str, action: Action) {
+        self.actions.insert(name.to_string(), action);
+    }
+    ///
+    /// Run one tick of every entity's tree, returning the root status of each
+    /// in entity order. Entities naming an unknown tree are skipped.
+    ///
+    pub fn tick(&mut self, world: &mut World, entities: &mut EntityManager) -> Vec<(EntityID, Status)> {
+        let mut running: Vec<EntityID> = entities.iter::<Behavior>().map(|(entity, _)| entity).collect();
+        running.sort();
+        let mut results = Vec::new();
+        for entity in running {
+            let mut behavior = match entities.remove_component::<Behavior>(entity) {
+                Some(behavior) => behavior,
+                None => continue,
+            };
+            if let Some(tree) = self.trees.get(&behavior.tree) {
+                if behavior.state.len() != tree.len() {
+                    behavior.state = vec![0; tree.len()];
+                }
+                let mut context = Context { actions: &mut self.actions, entity, entities, world };
+                results.push((entity, tree.run(0, &mut behavior.state, &mut context)));
+            }
+            entities.add_component(entity, behavior);
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Behavior, Behaviors, Node, Status};
+    use math::Fixed;
+    use model::component::Position;
+    use model::entity::EntityManager;
+    use model::world::World;
+
+    /// Walk up to x = 3 one step per tick, rest two ticks, then head home.
+    fn forager() -> Node {
+        Node::Selector(vec![
+            Node::Sequence(vec![
+                Node::Invert(Box::new(Node::Action("at_food".to_string()))),
+                Node::Action("step".to_string()),
+            ]),
+            Node::Sequence(vec![
+                Node::Wait(2),
+                Node::Action("go_home".to_string()),
+            ]),
+        ])
+    }
+
+    #[test]
+    pub fn test_behavior_tree() {
+        let mut behaviors = Behaviors::new();
+        behaviors.register_tree("forager", forager());
+        behaviors.register_action("at_food", Box::new(|entity, entities, _| {
+            if entities.get_component::<Position>(entity).unwrap().x >= Fixed::from_int(3) { Status::Success } else { Status::Failure }
+        }));
+        behaviors.register_action("step", Box::new(|entity, entities, _| {
+            entities.get_component_mut::<Position>(entity).unwrap().x += Fixed::ONE;
+            Status::Success
+        }));
+        behaviors.register_action("go_home", Box::new(|entity, entities, _| {
+            entities.get_component_mut::<Position>(entity).unwrap().x = Fixed::ZERO;
+            Status::Success
+        }));
+
+        let mut world = World::new();
+        let mut entities = EntityManager::new();
+        let ant = entities.create_entity();
+        entities.add_component(ant, Position::default());
+        entities.add_component(ant, Behavior::new("forager"));
+        let mut statuses = Vec::new();
+        for _ in 0..6 {
+            statuses.push(behaviors.tick(&mut world, &mut entities)[0].1);
+        }
+        assert_eq!(statuses, vec![Status::Success, Status::Success, Status::Success, Status::Running, Status::Success, Status::Success]);
+        assert_eq!(entities.get_component::<Position>(ant).unwrap().x, Fixed::ONE);
+
+        // Trees and progress survive a save
+        let mut buffer = Vec::new();
+        forager().save(&mut buffer).unwrap();
+        assert_eq!(Node::load(&mut &buffer[..]).unwrap(), forager());
+        behaviors.register_action("at_food", Box::new(|_, _, _| Status::Success));
+        assert_eq!(behaviors.tick(&mut world, &mut entities)[0].1, Status::Running);
+        let mut saved = Vec::new();
+        entities.get_component::<Behavior>(ant).unwrap().save(&mut saved).unwrap();
+        let restored = Behavior::load(&mut &saved[..]).unwrap();
+        assert_eq!(&restored, entities.get_component::<Behavior>(ant).unwrap());
+        assert_eq!(restored.state(), &[1, 0, 0, 0, 0, 0, 1, 0]);
+    }
+}
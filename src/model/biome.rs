@@ -0,0 +1,173 @@
+///
+/// Biomes
+///
+/// Regions of the World with their own terrain and climate. A BiomeMap
+/// assigns every column a biome from 2D noise, so like the terrain it is a
+/// pure function of the seed and can be asked about anywhere, loaded or
+/// not. Each Biome carries the material palette and terrain parameters the
+/// LayeredGenerator builds with, and the temperature offset the Environment
+/// applies in it.
+///
+use math::Fixed;
+use model::material::{MaterialId, MaterialRegistry, AIR};
+use model::worldgen::Noise;
+use std::collections::HashMap;
+
+///
+/// Compact Biome Identifier
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Default)]
+pub struct BiomeId(u16);
+
+impl BiomeId {
+    pub fn new(id: u16) -> BiomeId { BiomeId(id) }
+    pub fn id(&self) -> u16 { self.0 }
+    pub fn index(&self) -> usize { self.0 as usize }
+}
+
+///
+/// Biome Properties
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct Biome {
+    pub name: String,
+    /// Surface material
+    pub crust: MaterialId,
+    /// Material under the crust
+    pub stone: MaterialId,
+    /// Material of the veins in the stone
+    pub ore: MaterialId,
+    /// Average surface height
+    pub base_height: f64,
+    /// Largest rise or fall of the surface from the base height
+    pub amplitude: f64,
+    /// Degrees warmer than the Environment's base temperature
+    pub temperature: Fixed,
+}
+
+impl Biome {
+    /// Biome of rock hills crusted with `crust`, using the standard materials from `materials`.
+    pub fn new(name: &str, crust: MaterialId, materials: &MaterialRegistry) -> Biome {
+        let rock = materials.id("rock").unwrap_or(AIR);
+        Biome {
+            name: name.to_string(),
+            crust,
+            stone: rock,
+            ore: materials.id("metal").unwrap_or(rock),
+            base_height: 12.0,
+            amplitude: 8.0,
+            temperature: Fixed::ZERO,
+        }
+    }
+}
+
+///
+/// Biome Registry
+///
+#[derive(Clone, Default, Debug)]
+pub struct BiomeRegistry {
+    biomes: Vec<Biome>,
+    names: HashMap<String, BiomeId>,
+}
+
+impl BiomeRegistry {
+    pub fn new() -> BiomeRegistry { BiomeRegistry::default() }
+    /// Register a Biome, replacing any of the same name and keeping its id.
+    pub fn register(&mut self, biome: Biome) -> BiomeId {
+        if let Some(&id) = self.names.get(&biome.name) {
+            self.biomes[id.index()] = biome;
+            return id;
+        }
+        assert!(self.biomes.len() <= u16::MAX as usize, "Biome Registry is full");
+        let id = BiomeId(self.biomes.len() as u16);
+        self.names.insert(biome.name.clone(), id);
+        self.biomes.push(biome);
+        id
+    }
+    pub fn id(&self, name: &str) -> Option<BiomeId> { self.names.get(name).cloned() }
+    pub fn get(&self, id: BiomeId) -> Option<&Biome> { self.biomes.get(id.index()) }
+    pub fn len(&self) -> usize { self.biomes.len() }
+    pub fn is_empty(&self) -> bool { self.biomes.is_empty() }
+    pub fn iter(&self) -> impl Iterator<Item=(BiomeId, &Biome)> {
+        self.biomes.iter().enumerate().map(|(index, biome)| (BiomeId(index as u16), biome))
+    }
+}
+
+///
+/// Biome of every column of a World
+///
+/// Low frequency noise is cut into equal bands, one per registered biome in
+/// id order, so neighbouring ids tend to border each other.
+///
+#[derive(Clone, Debug)]
+pub struct BiomeMap {
+    pub seed: u64,
+    /// Horizontal scale of biomes in Blocks
+    pub scale: f64,
+    biomes: BiomeRegistry,
+}
+
+impl BiomeMap {
+    pub fn new(seed: u64, biomes: BiomeRegistry) -> BiomeMap { BiomeMap { seed, scale: 256.0, biomes } }
+    pub fn biomes(&self) -> &BiomeRegistry { &self.biomes }
+    /// Biome of a world column, None if no biomes are registered.
+    pub fn biome_at(&self, x: u64, z: u64) -> Option<BiomeId> {
+        if self.biomes.is_empty() {
+            return None;
+        }
+        let noise = Noise::new(self.seed).layer(4);
+        let sample = noise.fractal2(x as f64 / self.scale, z as f64 / self.scale, 2);
+        let band = ((sample + 1.0) / 2.0 * self.biomes.len() as f64) as usize;
+        Some(BiomeId(band.min(self.biomes.len() - 1) as u16))
+    }
+    /// Properties of the biome of a world column.
+    pub fn biome(&self, x: u64, z: u64) -> Option<&Biome> { self.biome_at(x, z).and_then(|id| self.biomes.get(id)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Biome, BiomeMap, BiomeRegistry};
+    use math::Fixed;
+    use model::environment::Environment;
+    use model::material::{Material, MaterialRegistry};
+    use model::world::{Chunk, Vector2, World};
+    use model::worldgen::{ChunkGenerator, LayeredGenerator};
+
+    #[test]
+    pub fn test_biome_map() {
+        let mut materials = MaterialRegistry::default();
+        let sand = materials.register(Material::new("sand", 1.0, 1.0));
+        let ice = materials.register(Material::new("ice", 1.0, 0.5));
+        let mut biomes = BiomeRegistry::new();
+        let desert = biomes.register(Biome { temperature: Fixed::from_int(15), ..Biome::new("desert", sand, &materials) });
+        let tundra = biomes.register(Biome { base_height: 6.0, amplitude: 1.0, temperature: Fixed::from_int(-20), ..Biome::new("tundra", ice, &materials) });
+        assert_eq!(biomes.register(Biome::new("desert", sand, &materials)), desert);
+        let map = BiomeMap::new(9, biomes.clone());
+        assert!(BiomeMap::new(9, BiomeRegistry::new()).biome_at(0, 0).is_none());
+
+        // Both biomes turn up, and the same seed always gives the same map
+        let columns: Vec<_> = (0..64).map(|step| map.biome_at(step * 97, step * 31).unwrap()).collect();
+        assert!(columns.contains(&desert) && columns.contains(&tundra));
+        assert_eq!(columns, (0..64).map(|step| BiomeMap::new(9, biomes.clone()).biome_at(step * 97, step * 31).unwrap()).collect::<Vec<_>>());
+
+        // Terrain takes its surface from the biome
+        let (x, z) = (0..4096).map(|step| (step * 7, step * 3)).find(|&(x, z)| map.biome_at(x, z) == Some(tundra)).unwrap();
+        let generator = LayeredGenerator { biomes: Some(map.clone()), ..LayeredGenerator::new(9, &materials) };
+        let height = generator.height_at(x, z);
+        assert!((5..=7).contains(&height));
+        let position = Vector2::new(x / 32, z / 32);
+        let mut chunk = Box::new(Chunk::new());
+        generator.generate(position, &mut chunk);
+        assert_eq!(chunk.get_block((x % 32) as usize, height, (z % 32) as usize).material(), ice);
+
+        // And so does the climate
+        let mut world = World::with_materials(materials);
+        assert_eq!(world.biome_at(x, z), None);
+        world.set_biomes(Some(map));
+        assert_eq!(world.biome_at(x, z), Some(tundra));
+        let mut environment = Environment::new(100);
+        environment.add_biomes(&biomes);
+        environment.set_time_of_day(0x4000);
+        assert_eq!(environment.temperature(world.biome_at(x, z)), Fixed::from_int(-5));
+    }
+}
@@ -0,0 +1,114 @@
+///
+/// World Bounds
+///
+/// Block and Chunk coordinates are unsigned: the World starts at 0 on both
+/// horizontal axes. Anything working in signed space, such as entity
+/// Positions and relative offsets, resolves its coordinates through the
+/// World's bounds before touching a Block, and the bounds decide what lies
+/// past the edges:
+///
+///  Bounds    | x < 0                | beyond width x depth Chunks
+/// -----------+----------------------+----------------------------
+///  Unbounded | nothing              | the World goes on forever
+///  Wall      | nothing              | nothing
+///  Wrap      | the far edge         | the near edge
+///
+/// A wrapping World is a torus: walking off one edge brings a drone in on
+/// the opposite side, and Chunks on opposite edges are neighbours.
+///
+use model::world::{Vector2, CHUNK_SIZE};
+
+///
+/// What lies past the edges of a finite World
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Edge {
+    Wall,
+    Wrap,
+}
+
+///
+/// Extent of a World
+///
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub enum WorldBounds {
+    #[default]
+    Unbounded,
+    /// `width` Chunks along x by `depth` along z
+    Finite { width: u64, depth: u64, edge: Edge },
+}
+
+impl WorldBounds {
+    pub fn walled(width: u64, depth: u64) -> WorldBounds { WorldBounds::Finite { width, depth, edge: Edge::Wall } }
+    pub fn wrapping(width: u64, depth: u64) -> WorldBounds { WorldBounds::Finite { width, depth, edge: Edge::Wrap } }
+    pub fn contains_chunk(&self, position: Vector2<u64>) -> bool {
+        match *self {
+            WorldBounds::Unbounded => true,
+            WorldBounds::Finite { width, depth, .. } => position.x < width && position.y < depth,
+        }
+    }
+    /// Chunk at signed Chunk coordinates, None if there is none.
+    pub fn resolve_chunk(&self, x: i64, z: i64) -> Option<Vector2<u64>> {
+        match *self {
+            WorldBounds::Finite { width, depth, edge: Edge::Wrap } => Some(Vector2::new(wrap(x, width), wrap(z, depth))),
+            _ if x < 0 || z < 0 => None,
+            _ => Some(Vector2::new(x as u64, z as u64)).filter(|&position| self.contains_chunk(position)),
+        }
+    }
+    /// Block column at signed Block coordinates, None if there is none.
+    pub fn resolve(&self, x: i64, z: i64) -> Option<(u64, u64)> {
+        let (chunk, lx, lz) = signed_chunk_of(x, z);
+        let size = CHUNK_SIZE as u64;
+        self.resolve_chunk(chunk.x, chunk.y).map(|chunk| (chunk.x * size + lx as u64, chunk.y * size + lz as u64))
+    }
+    /// Chunk `dx`, `dz` Chunks away from `position`, None past a wall.
+    pub fn neighbour(&self, position: Vector2<u64>, dx: i64, dz: i64) -> Option<Vector2<u64>> {
+        self.resolve_chunk((position.x as i64).checked_add(dx)?, (position.y as i64).checked_add(dz)?)
+    }
+}
+
+/// Signed Chunk containing a Block column and the column's position inside it, rounding down.
+pub fn signed_chunk_of(x: i64, z: i64) -> (Vector2<i64>, usize, usize) {
+    let size = CHUNK_SIZE as i64;
+    (Vector2::new(x.div_euclid(size), z.div_euclid(size)), x.rem_euclid(size) as usize, z.rem_euclid(size) as usize)
+}
+
+fn wrap(value: i64, length: u64) -> u64 { value.rem_euclid(length.max(1) as i64) as u64 }
+
+#[cfg(test)]
+mod tests {
+    use super::{signed_chunk_of, WorldBounds};
+    use model::world::{Block, Chunk, Vector2, World, CHUNK_SIZE};
+
+    #[test]
+    pub fn test_world_bounds() {
+        let size = CHUNK_SIZE as i64;
+        assert_eq!(signed_chunk_of(-1, -size), (Vector2::new(-1, -1), CHUNK_SIZE - 1, 0));
+        assert_eq!(signed_chunk_of(size + 2, 5), (Vector2::new(1, 0), 2, 5));
+
+        let unbounded = WorldBounds::Unbounded;
+        assert_eq!(unbounded.resolve(1 << 40, 3), Some((1 << 40, 3)));
+        assert_eq!(unbounded.resolve(-1, 3), None);
+        let walled = WorldBounds::walled(2, 1);
+        assert_eq!(walled.resolve(2 * size - 1, 0), Some((2 * CHUNK_SIZE as u64 - 1, 0)));
+        assert_eq!(walled.resolve(2 * size, 0), None);
+        assert_eq!(walled.neighbour(Vector2::new(1, 0), 0, 1), None);
+        let wrapping = WorldBounds::wrapping(2, 1);
+        assert_eq!(wrapping.resolve(-1, -1), Some((2 * CHUNK_SIZE as u64 - 1, CHUNK_SIZE as u64 - 1)));
+        assert_eq!(wrapping.resolve(2 * size + 3, size), Some((3, 0)));
+        assert_eq!(wrapping.neighbour(Vector2::new(0, 0), -1, 0), Some(Vector2::new(1, 0)));
+
+        // Chunks outside a finite World are refused
+        let mut world = World::new();
+        world.set_bounds(WorldBounds::wrapping(2, 1));
+        assert!(world.insert_chunk(Vector2::new(0, 0), Box::new(Chunk::new())).is_none());
+        assert!(world.insert_chunk(Vector2::new(0, 1), Box::new(Chunk::new())).is_some());
+        assert!(!world.is_chunk_loaded(Vector2::new(0, 1)));
+        let rock = Block::new(world.materials().id("rock").unwrap());
+        let (x, z) = world.resolve(-size, -3).unwrap();
+        assert!(!world.set_block(x, 0, z, rock));
+        let (x, z) = world.resolve(2 * size + 1, 1).unwrap();
+        assert!(world.set_block(x, 0, z, rock));
+        assert_eq!(world.get_block(1, 0, 1), Some(rock));
+    }
+}
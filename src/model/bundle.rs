@@ -0,0 +1,238 @@
+///
+/// Entity Bundles
+///
+/// A group of entities lifted out of one EntityManager, with their
+/// persistent Components, labels and whether they were disabled, to be
+/// merged into another or the same one later: entities copied with a
+/// Structure, or sent to another server. Entities are picked by id, by a
+/// query over the EntityManager or by the box their Positions fall in.
+///
+/// Merging creates a fresh entity for each one bundled and points the
+/// references between them, in Components registered with `register_mapped`,
+/// at the new ids. References to entities outside the bundle are kept as
+/// they are. A merge can move every Position by an offset, so entities copied
+/// with a Structure follow it to where it is pasted.
+///
+/// ---+--------+---------------------------------------------------------------
+///  # | SIZE   | DESCRIPTION
+/// ---+--------+---------------------------------------------------------------
+///  1 | 4      | Magic, HVEB
+///  2 | 2      | Format Version
+///  3 | 4      | Entity Count
+///  4 | ...    | Entities: slot and suffix u64, disabled u8, label count u16
+///    |        | and labels, then the StoredEntity, see `model::persist`
+/// ---+--------+---------------------------------------------------------------
+///
+use codec::{read_string, read_u16, read_u32, read_u64, read_u8, write_string, write_u16, write_u32, write_u64, write_u8};
+use math::Vec3;
+use migrate::{Artifact, Migrations};
+use model::component::Position;
+use model::entity::{EntityID, EntityManager};
+use model::persist::{ComponentCodecs, StoredEntity};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+/// Magic number of a bundle file
+pub const MAGIC: &[u8; 4] = b"HVEB";
+/// Format version of a bundle, see `migrate`
+pub const VERSION: u16 = 1;
+
+///
+/// One entity of a Bundle
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BundledEntity {
+    /// Id it had where it was extracted
+    pub id: EntityID,
+    pub labels: Vec<String>,
+    pub disabled: bool,
+    pub stored: StoredEntity,
+}
+
+///
+/// Entities extracted from an EntityManager
+///
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct EntityBundle {
+    entities: Vec<BundledEntity>,
+}
+
+impl EntityBundle {
+    ///
+    /// Bundle the listed entities, skipping any which are dead. The source
+    /// is left as it was; destroy them there to move rather than copy them.
+    ///
+    pub fn extract(entities: &EntityManager, codecs: &ComponentCodecs, ids: &[EntityID]) -> io::Result<EntityBundle> {
+        let mut bundled = Vec::new();
+        for &id in ids.iter().filter(|&&id| entities.is_alive(id)) {
+            bundled.push(BundledEntity {
+                id,
+                labels: entities.labels(id).map(String::from).collect(),
+                disabled: entities.is_disabled(id),
+                stored: codecs.capture(entities, id)?,
+            });
+        }
+        Ok(EntityBundle { entities: bundled })
+    }
+    /// Bundle every live entity a query selects.
+    pub fn extract_where(entities: &EntityManager, codecs: &ComponentCodecs, mut query: impl FnMut(&EntityManager, EntityID) -> bool) -> io::Result<EntityBundle> {
+        let ids: Vec<EntityID> = entities.entities().iter().filter(|&id| query(entities, id)).collect();
+        EntityBundle::extract(entities, codecs, &ids)
+    }
+    /// Bundle every live entity whose Position lies within `min` and `max`, inclusive.
+    pub fn extract_area(entities: &EntityManager, codecs: &ComponentCodecs, min: Position, max: Position) -> io::Result<EntityBundle> {
+        EntityBundle::extract_where(entities, codecs, |entities, id| entities.get_component::<Position>(id).is_some_and(|position| {
+            position.x >= min.x && position.x <= max.x && position.y >= min.y && position.y <= max.y && position.z >= min.z && position.z <= max.z
+        }))
+    }
+    pub fn entities(&self) -> &[BundledEntity] { &self.entities }
+    /// Ids the entities had where they were extracted.
+    pub fn ids(&self) -> impl Iterator<Item=EntityID> + '_ { self.entities.iter().map(|entity| entity.id) }
+    pub fn len(&self) -> usize { self.entities.len() }
+    pub fn is_empty(&self) -> bool { self.entities.is_empty() }
+    /// Merge the entities where they were, see `merge_offset`.
+    pub fn merge(&self, entities: &mut EntityManager, codecs: &ComponentCodecs) -> io::Result<HashMap<EntityID, EntityID>> {
+        self.merge_offset(entities, codecs, Vec3::default())
+    }
+    ///
+    /// Create the bundled entities in an EntityManager with their Positions
+    /// moved by `offset`, returning the new id of each by its old one. If any
+    /// entity fails to restore none are left behind.
+    ///
+    pub fn merge_offset(&self, entities: &mut EntityManager, codecs: &ComponentCodecs, offset: Vec3) -> io::Result<HashMap<EntityID, EntityID>> {
+        let mut ids = HashMap::new();
+        for bundled in self.entities.iter() {
+            match codecs.restore(entities, &bundled.stored) {
+                Ok(id) => ids.insert(bundled.id, id),
+                Err(error) => {
+                    for &id in ids.values() {
+                        entities.destroy_entity(id);
+                    }
+                    return Err(error);
+                }
+            };
+        }
+        for bundled in self.entities.iter() {
+            let id = ids[&bundled.id];
+            codecs.remap(entities, id, &|old| ids.get(&old).cloned().unwrap_or(old));
+            if let Some(position) = entities.get_component_mut::<Position>(id) {
+                *position = Position::from_vector(position.vector() + offset);
+            }
+            for label in bundled.labels.iter() {
+                entities.add_label(id, label);
+            }
+            if bundled.disabled {
+                entities.disable(id);
+            }
+        }
+        Ok(ids)
+    }
+    pub fn save(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        write_u16(writer, VERSION)?;
+        write_u32(writer, self.entities.len() as u32)?;
+        for bundled in self.entities.iter() {
+            write_u64(writer, bundled.id.slot() as u64)?;
+            write_u64(writer, bundled.id.suffix() as u64)?;
+            write_u8(writer, bundled.disabled as u8)?;
+            write_u16(writer, bundled.labels.len() as u16)?;
+            for label in bundled.labels.iter() {
+                write_string(writer, label)?;
+            }
+            bundled.stored.save(writer)?;
+        }
+        Ok(())
+    }
+    /// Read a bundle, upgrading one saved by an earlier version.
+    pub fn load(reader: &mut dyn Read) -> io::Result<EntityBundle> {
+        let bytes = Migrations::default().read(Artifact::Bundle, reader)?;
+        let reader: &mut dyn Read = &mut &bytes[MAGIC.len() + 2..];
+        let mut entities = Vec::new();
+        for _ in 0..read_u32(reader)? {
+            let id = EntityID::new(read_u64(reader)? as usize, read_u64(reader)? as usize);
+            let disabled = read_u8(reader)? != 0;
+            let mut labels = Vec::new();
+            for _ in 0..read_u16(reader)? {
+                labels.push(read_string(reader)?);
+            }
+            entities.push(BundledEntity { id, labels, disabled, stored: StoredEntity::load(reader)? });
+        }
+        Ok(EntityBundle { entities })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EntityBundle;
+    use codec::{read_u64, write_u64};
+    use math::Vec3;
+    use model::component::{Position, Velocity};
+    use model::entity::{EntityID, EntityManager};
+    use model::persist::{ComponentCodecs, MapEntities, Persistent};
+    use std::io::{self, Read, Write};
+
+    /// Entity a drone follows
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    struct Follow(EntityID);
+
+    impl Persistent for Follow {
+        fn save(&self, writer: &mut dyn Write) -> io::Result<()> {
+            write_u64(writer, self.0.slot() as u64)?;
+            write_u64(writer, self.0.suffix() as u64)
+        }
+        fn load(reader: &mut dyn Read) -> io::Result<Follow> { Ok(Follow(EntityID::new(read_u64(reader)? as usize, read_u64(reader)? as usize))) }
+    }
+
+    impl MapEntities for Follow {
+        fn map_entities(&mut self, map: &dyn Fn(EntityID) -> EntityID) { self.0 = map(self.0) }
+    }
+
+    #[test]
+    pub fn test_entity_bundles() {
+        let mut codecs = ComponentCodecs::default();
+        codecs.register_mapped::<Follow>("follow");
+        let mut source = EntityManager::new();
+        let outside = source.create_entity();
+        source.add_component(outside, Position::from_f64(50.0, 1.0, 50.0));
+        let leader = source.create_entity();
+        source.add_component(leader, Position::from_f64(2.0, 1.0, 2.0));
+        source.add_label(leader, "leader");
+        let follower = source.create_entity();
+        source.add_component(follower, Position::from_f64(3.0, 1.0, 2.0));
+        source.add_component(follower, Velocity::from_f64(0.5, 0.0, 0.0));
+        source.add_component(follower, Follow(leader));
+        source.disable(follower);
+        let straggler = source.create_entity();
+        source.add_component(straggler, Position::from_f64(4.0, 1.0, 4.0));
+        source.add_component(straggler, Follow(outside));
+
+        // Only what stands in the box, written out and read back
+        let bundle = EntityBundle::extract_area(&source, &codecs, Position::from_f64(0.0, 0.0, 0.0), Position::from_f64(8.0, 4.0, 8.0)).unwrap();
+        assert_eq!(bundle.ids().collect::<Vec<_>>(), vec![leader, follower, straggler]);
+        let mut bytes = Vec::new();
+        bundle.save(&mut bytes).unwrap();
+        let bundle = EntityBundle::load(&mut &bytes[..]).unwrap();
+
+        // Pasted further along into a manager with entities of its own
+        let mut target = EntityManager::new();
+        let resident = target.create_entity();
+        target.add_component(resident, Follow(resident));
+        let ids = bundle.merge_offset(&mut target, &codecs, Vec3::from_f64(10.0, 0.0, 0.0)).unwrap();
+        assert_eq!((ids.len(), target.entities().len()), (3, 4));
+        let (new_leader, new_follower) = (ids[&leader], ids[&follower]);
+        assert_eq!(target.get_component::<Follow>(new_follower), Some(&Follow(new_leader)));
+        assert_eq!(target.get_component::<Follow>(ids[&straggler]), Some(&Follow(outside)));
+        assert_eq!(target.get_component::<Follow>(resident), Some(&Follow(resident)));
+        assert_eq!(target.get_component::<Position>(new_follower), Some(&Position::from_f64(13.0, 1.0, 2.0)));
+        assert_eq!(target.get_component::<Velocity>(new_follower), Some(&Velocity::from_f64(0.5, 0.0, 0.0)));
+        assert!(target.is_disabled(new_follower) && target.find_by_label("leader").eq(vec![new_leader]));
+
+        // By query, and a failed restore leaves nothing behind
+        let bundle = EntityBundle::extract_where(&source, &codecs, |entities, id| entities.has_component::<Follow>(id)).unwrap();
+        assert_eq!(bundle.ids().collect::<Vec<_>>(), vec![follower, straggler]);
+        let mut broken = bundle.clone();
+        broken.entities[1].stored.components.push(("velocity".to_string(), vec![1, 2]));
+        assert!(broken.merge(&mut target, &codecs).is_err());
+        assert_eq!(target.entities().len(), 4);
+    }
+}
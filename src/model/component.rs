@@ -0,0 +1,80 @@
+//!
+//! Common Entity Components
+//!
+//! Positions, velocities and sizes are Fixed so simulation stays deterministic.
+//!
+
+use math::{Fixed, Vec3};
+use model::entity::{EntityID, Relation};
+
+///
+/// World space Position
+///
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Position {
+    pub x: Fixed,
+    pub y: Fixed,
+    pub z: Fixed,
+}
+
+impl Position {
+    pub fn new(x: Fixed, y: Fixed, z: Fixed) -> Position { Position { x, y, z } }
+    pub fn from_f64(x: f64, y: f64, z: f64) -> Position { Position::from_vector(Vec3::from_f64(x, y, z)) }
+    pub fn from_vector(vector: Vec3) -> Position { Position::new(vector.x, vector.y, vector.z) }
+    pub fn vector(&self) -> Vec3 { Vec3::new(self.x, self.y, self.z) }
+    /// Block containing the Position.
+    pub fn block(&self) -> (i64, i64, i64) { self.vector().floor() }
+    pub fn distance_squared(&self, other: &Position) -> Fixed { (self.vector() - other.vector()).length_squared() }
+    pub fn distance(&self, other: &Position) -> Fixed { self.distance_squared(other).sqrt() }
+}
+
+///
+/// World space Velocity in Blocks per second
+///
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Velocity {
+    pub x: Fixed,
+    pub y: Fixed,
+    pub z: Fixed,
+}
+
+impl Velocity {
+    pub fn new(x: Fixed, y: Fixed, z: Fixed) -> Velocity { Velocity { x, y, z } }
+    pub fn from_f64(x: f64, y: f64, z: f64) -> Velocity { Velocity::from_vector(Vec3::from_f64(x, y, z)) }
+    pub fn from_vector(vector: Vec3) -> Velocity { Velocity::new(vector.x, vector.y, vector.z) }
+    pub fn vector(&self) -> Vec3 { Vec3::new(self.x, self.y, self.z) }
+}
+
+///
+/// Axis aligned bounding box centred on the entity's Position
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Collider {
+    pub width: Fixed,
+    pub height: Fixed,
+    pub depth: Fixed,
+    /// Affected by gravity
+    pub gravity: bool,
+    /// Resting on something solid as of the last physics step
+    pub grounded: bool,
+}
+
+impl Collider {
+    pub fn new(width: Fixed, height: Fixed, depth: Fixed) -> Collider {
+        Collider { width, height, depth, gravity: true, grounded: false }
+    }
+    pub fn from_f64(width: f64, height: f64, depth: f64) -> Collider {
+        Collider::new(Fixed::from_f64(width), Fixed::from_f64(height), Fixed::from_f64(depth))
+    }
+}
+
+///
+/// Entity this one is acting on: following, attacking or carrying to
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct TargetOf(pub EntityID);
+
+impl Relation for TargetOf {
+    type Target = EntityID;
+    fn target(&self) -> EntityID { self.0 }
+}
@@ -0,0 +1,129 @@
+///
+/// Dimensions
+///
+/// A Simulation can host several independent Worlds, such as the surface,
+/// the underhive below it and test arenas, sharing one EntityManager. Each
+/// entity lives in the World named by its WorldId component, and entities
+/// without one live in the first World, OVERWORLD. Entities cross between
+/// Worlds by transfer, or by stepping into a Portal linking a Block of one
+/// World to a Position in another.
+///
+use model::component::Position;
+use model::entity::{EntityID, EntityManager};
+use model::update::BlockPosition;
+use std::collections::HashMap;
+
+///
+/// World Identifier, and the component placing an entity in that World
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Debug)]
+pub struct WorldId(pub u16);
+
+/// The first World of a Simulation
+pub const OVERWORLD: WorldId = WorldId(0);
+
+impl WorldId {
+    pub fn index(&self) -> usize { self.0 as usize }
+}
+
+/// World an entity lives in.
+pub fn world_of(entities: &EntityManager, entity: EntityID) -> WorldId {
+    entities.get_component::<WorldId>(entity).cloned().unwrap_or(OVERWORLD)
+}
+
+/// Move an entity to `position` in another World. Returns false if it is dead.
+pub fn transfer(entities: &mut EntityManager, entity: EntityID, world: WorldId, position: Position) -> bool {
+    if !entities.is_alive(entity) {
+        return false;
+    }
+    entities.add_component(entity, world);
+    entities.add_component(entity, position);
+    true
+}
+
+///
+/// One way link from a Block to a Position in another World
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Portal {
+    pub world: WorldId,
+    pub position: Position,
+}
+
+///
+/// Every Portal of a Simulation, by the World and Block they are entered from
+///
+#[derive(Clone, Default, Debug)]
+pub struct Portals {
+    links: HashMap<(WorldId, BlockPosition), Portal>,
+}
+
+impl Portals {
+    pub fn new() -> Portals { Portals::default() }
+    /// Send entities entering `block` of `world` to `destination`, returning any Portal it replaced.
+    pub fn link(&mut self, world: WorldId, block: BlockPosition, destination: Portal) -> Option<Portal> {
+        self.links.insert((world, block), destination)
+    }
+    pub fn unlink(&mut self, world: WorldId, block: BlockPosition) -> Option<Portal> { self.links.remove(&(world, block)) }
+    pub fn get(&self, world: WorldId, block: BlockPosition) -> Option<&Portal> { self.links.get(&(world, block)) }
+    pub fn len(&self) -> usize { self.links.len() }
+    pub fn is_empty(&self) -> bool { self.links.is_empty() }
+    ///
+    /// Transfer every entity standing in a Portal to its destination,
+    /// returning the entities moved in id order.
+    ///
+    pub fn traverse(&self, entities: &mut EntityManager) -> Vec<EntityID> {
+        if self.links.is_empty() {
+            return Vec::new();
+        }
+        let mut travellers: Vec<(EntityID, Portal)> = entities.iter::<Position>()
+            .filter_map(|(entity, position)| {
+                let (x, y, z) = position.block();
+                if x < 0 || y < 0 || z < 0 {
+                    return None;
+                }
+                self.get(world_of(entities, entity), (x as u64, y as usize, z as u64)).map(|&portal| (entity, portal))
+            })
+            .collect();
+        travellers.sort_by_key(|&(entity, _)| entity);
+        for &(entity, portal) in travellers.iter() {
+            transfer(entities, entity, portal.world, portal.position);
+        }
+        travellers.into_iter().map(|(entity, _)| entity).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{world_of, Portal, WorldId, OVERWORLD};
+    use model::component::{Position, Velocity};
+    use model::world::{Block, Chunk, Vector2, World};
+    use model::entity::EntityManager;
+    use simulation::Simulation;
+
+    #[test]
+    pub fn test_worlds_and_portals() {
+        let mut simulation = Simulation::new(World::new(), EntityManager::new());
+        let mut underhive = World::new();
+        underhive.insert_chunk(Vector2::new(0, 0), Box::new(Chunk::new()));
+        let rock = Block::new(underhive.materials().id("rock").unwrap());
+        underhive.set_block(0, 0, 0, rock);
+        let below = simulation.add_world("underhive", underhive);
+        assert_eq!((below, simulation.world_id("underhive"), simulation.world_count()), (WorldId(1), Some(below), 2));
+        assert!(simulation.get_world(WorldId(2)).is_none());
+
+        // Each World only moves the entities living in it
+        let walker = simulation.entities_mut().create_entity();
+        simulation.entities_mut().add_component(walker, Position::from_f64(0.5, 4.0, 0.5));
+        simulation.entities_mut().add_component(walker, Velocity::from_f64(20.0, 0.0, 0.0));
+        simulation.portals_mut().link(OVERWORLD, (1, 4, 0), Portal { world: below, position: Position::from_f64(0.5, 1.0, 0.5) });
+        simulation.step();
+        assert_eq!(world_of(simulation.entities(), walker), below);
+        assert_eq!(simulation.entities().get_component::<Position>(walker), Some(&Position::from_f64(0.5, 1.0, 0.5)));
+
+        // Back by hand
+        assert!(simulation.transfer(walker, OVERWORLD, Position::from_f64(8.0, 4.0, 8.0)));
+        assert_eq!(world_of(simulation.entities(), walker), OVERWORLD);
+        assert!(!simulation.transfer(walker, WorldId(7), Position::default()));
+    }
+}
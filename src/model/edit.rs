@@ -0,0 +1,212 @@
+///
+/// World Editing Transactions
+///
+/// A WorldEdit is a batch of Block changes. Submitted edits are applied together
+/// at the end of the tick, each one all or nothing, and the inverse of every
+/// applied edit is kept so it can be undone and redone.
+///
+use model::update::BlockPosition;
+use model::world::{Block, World};
+use std::collections::VecDeque;
+use std::mem;
+
+/// Default number of edits kept for undo
+pub const DEFAULT_HISTORY: usize = 64;
+
+///
+/// Reason an Edit was rejected
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EditError {
+    /// The Block's Chunk isn't loaded or it is outside the world
+    Unloaded(BlockPosition),
+}
+
+///
+/// Batch of Block Changes
+///
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct WorldEdit {
+    changes: Vec<(BlockPosition, Block)>,
+}
+
+impl WorldEdit {
+    pub fn new() -> WorldEdit { WorldEdit::default() }
+    /// Set one Block, later changes to the same Block win.
+    pub fn set(&mut self, x: u64, y: usize, z: u64, block: Block) -> &mut WorldEdit {
+        self.changes.push(((x, y, z), block));
+        self
+    }
+    /// Set every Block in an inclusive box.
+    pub fn fill(&mut self, min: BlockPosition, max: BlockPosition, block: Block) -> &mut WorldEdit {
+        for x in min.0..max.0 + 1 {
+            for y in min.1..max.1 + 1 {
+                for z in min.2..max.2 + 1 {
+                    self.changes.push(((x, y, z), block));
+                }
+            }
+        }
+        self
+    }
+    pub fn changes(&self) -> &[(BlockPosition, Block)] { &self.changes }
+    pub fn len(&self) -> usize { self.changes.len() }
+    pub fn is_empty(&self) -> bool { self.changes.is_empty() }
+    ///
+    /// Apply every change or none of them, returning the edit which reverts it.
+    ///
+    pub fn apply(&self, world: &mut World) -> Result<WorldEdit, EditError> {
+        for &((x, y, z), _) in self.changes.iter() {
+            if world.get_block(x, y, z).is_none() {
+                return Err(EditError::Unloaded((x, y, z)));
+            }
+        }
+        let mut inverse = Vec::with_capacity(self.changes.len());
+        for &((x, y, z), block) in self.changes.iter() {
+            inverse.push(((x, y, z), world.get_block(x, y, z).unwrap()));
+            world.set_block(x, y, z, block);
+        }
+        inverse.reverse();
+        Ok(WorldEdit { changes: inverse })
+    }
+}
+
+///
+/// Pending Edits and Undo History
+///
+#[derive(Clone, Debug)]
+pub struct EditHistory {
+    pending: Vec<WorldEdit>,
+    /// (edit, inverse) pairs, most recent last
+    undo: VecDeque<(WorldEdit, WorldEdit)>,
+    redo: Vec<(WorldEdit, WorldEdit)>,
+    limit: usize,
+}
+
+impl EditHistory {
+    pub fn new() -> EditHistory { EditHistory::with_limit(DEFAULT_HISTORY) }
+    pub fn with_limit(limit: usize) -> EditHistory {
+        EditHistory {
+            pending: Vec::new(),
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            limit,
+        }
+    }
+    /// Queue an edit for the next commit.
+    pub fn submit(&mut self, edit: WorldEdit) {
+        if !edit.is_empty() {
+            self.pending.push(edit);
+        }
+    }
+    pub fn pending(&self) -> usize { self.pending.len() }
+    pub fn can_undo(&self) -> bool { !self.undo.is_empty() }
+    pub fn can_redo(&self) -> bool { !self.redo.is_empty() }
+    ///
+    /// Apply the queued edits in submission order; call at the end of a tick.
+    /// Rejected edits leave the world untouched and are reported in order.
+    ///
+    pub fn commit(&mut self, world: &mut World) -> Vec<EditError> {
+        let mut errors = Vec::new();
+        for edit in mem::take(&mut self.pending) {
+            match edit.apply(world) {
+                Ok(inverse) => {
+                    self.redo.clear();
+                    self.record(edit, inverse);
+                }
+                Err(error) => errors.push(error),
+            }
+        }
+        errors
+    }
+    fn record(&mut self, edit: WorldEdit, inverse: WorldEdit) {
+        self.undo.push_back((edit, inverse));
+        while self.undo.len() > self.limit {
+            self.undo.pop_front();
+        }
+    }
+    /// Revert the latest edit, returns Ok(false) if there is nothing to undo.
+    pub fn undo(&mut self, world: &mut World) -> Result<bool, EditError> {
+        let (edit, inverse) = match self.undo.pop_back() {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        match inverse.apply(world) {
+            Ok(_) => {
+                self.redo.push((edit, inverse));
+                Ok(true)
+            }
+            Err(error) => {
+                self.undo.push_back((edit, inverse));
+                Err(error)
+            }
+        }
+    }
+    /// Reapply the latest undone edit, returns Ok(false) if there is nothing to redo.
+    pub fn redo(&mut self, world: &mut World) -> Result<bool, EditError> {
+        let (edit, inverse) = match self.redo.pop() {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        match edit.apply(world) {
+            Ok(_) => {
+                self.record(edit, inverse);
+                Ok(true)
+            }
+            Err(error) => {
+                self.redo.push((edit, inverse));
+                Err(error)
+            }
+        }
+    }
+}
+
+impl Default for EditHistory {
+    fn default() -> EditHistory { EditHistory::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EditError, EditHistory, WorldEdit};
+    use model::material::AIR;
+    use model::world::{Block, Chunk, Vector2, World};
+    use pool::Poolable;
+
+    #[test]
+    pub fn test_edit_undo_redo() {
+        let mut world = World::new();
+        world.insert_chunk(Vector2::new(0, 0), Chunk::allocate());
+        let rock = Block::new(world.materials().id("rock").unwrap());
+        let metal = Block::new(world.materials().id("metal").unwrap());
+        let mut history = EditHistory::new();
+
+        let mut wall = WorldEdit::new();
+        wall.fill((0, 0, 0), (3, 2, 0), rock).set(1, 1, 0, metal);
+        history.submit(wall);
+        assert_eq!(world.get_block(1, 1, 0), Some(Block::new(AIR)));
+        assert!(history.commit(&mut world).is_empty());
+        assert_eq!(world.get_block(1, 1, 0), Some(metal));
+        assert_eq!(world.get_block(3, 2, 0), Some(rock));
+
+        // Half in an unloaded chunk: nothing is applied
+        let mut partial = WorldEdit::new();
+        partial.set(5, 5, 5, rock).set(40, 5, 5, rock);
+        history.submit(partial);
+        assert_eq!(history.commit(&mut world), vec![EditError::Unloaded((40, 5, 5))]);
+        assert_eq!(world.get_block(5, 5, 5), Some(Block::new(AIR)));
+
+        assert_eq!(history.undo(&mut world), Ok(true));
+        assert_eq!(world.get_block(1, 1, 0), Some(Block::new(AIR)));
+        assert_eq!(world.get_block(3, 2, 0), Some(Block::new(AIR)));
+        assert_eq!(history.undo(&mut world), Ok(false));
+        assert_eq!(history.redo(&mut world), Ok(true));
+        assert_eq!(world.get_block(1, 1, 0), Some(metal));
+
+        // A new edit discards the redo stack
+        history.undo(&mut world).unwrap();
+        let mut single = WorldEdit::new();
+        single.set(9, 9, 9, rock);
+        history.submit(single);
+        history.commit(&mut world);
+        assert!(!history.can_redo());
+    }
+}
@@ -2,136 +2,980 @@
 /// https://github.com/Hazurl/ECS/blob/master/include/ecs/component/ComponentPool.hpp
 /// https://github.com/Hazurl/ECS/blob/master/include/ecs/container/SparseSet.hpp
 ///
+use error::HivemindError;
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
 
-type EntityID = usize;
+///
+/// Entity Identifier
+///
+/// Slots are reused once an entity is destroyed, the suffix distinguishes the
+/// new occupant of a slot from stale ids held by the previous one.
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct EntityID {
+    slot: usize,
+    suffix: usize,
+}
 
+impl EntityID {
+    /// Rebuild an id from its parts, as received from elsewhere.
+    pub fn new(slot: usize, suffix: usize) -> EntityID { EntityID { slot, suffix } }
+    pub fn slot(&self) -> usize { self.slot }
+    pub fn suffix(&self) -> usize { self.suffix }
+}
+
+#[derive(Clone)]
 pub struct EntityMap {
     next_suffix_id: usize,
-    next_slot_id: usize,
     free_slot_list: Vec<usize>,
-    entities: Vec<Optional<Entity>>,
-    // $($compname: ComponentData<$comptype>),*
+    entities: Vec<Entity>,
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Entity {
-    Present(C),
+    Present(EntityID),
     Missing,
 }
 
+impl EntityMap {
+    pub fn new() -> EntityMap {
+        EntityMap {
+            next_suffix_id: 0,
+            free_slot_list: Vec::new(),
+            entities: Vec::new(),
+        }
+    }
+    pub fn create(&mut self) -> EntityID {
+        let suffix = self.next_suffix_id;
+        self.next_suffix_id += 1;
+        if let Some(slot) = self.free_slot_list.pop() {
+            let eid = EntityID { slot, suffix };
+            self.entities[slot] = Entity::Present(eid);
+            eid
+        } else {
+            let eid = EntityID { slot: self.entities.len(), suffix };
+            self.entities.push(Entity::Present(eid));
+            eid
+        }
+    }
+    pub fn destroy(&mut self, eid: EntityID) -> bool {
+        if self.is_alive(eid) {
+            self.entities[eid.slot] = Entity::Missing;
+            self.free_slot_list.push(eid.slot);
+            true
+        } else {
+            false
+        }
+    }
+    pub fn is_alive(&self, eid: EntityID) -> bool {
+        match self.entities.get(eid.slot) {
+            Some(&Entity::Present(current)) => current == eid,
+            _ => false,
+        }
+    }
+    /// Entity currently occupying a slot
+    pub fn at_slot(&self, slot: usize) -> Option<EntityID> {
+        match self.entities.get(slot) {
+            Some(&Entity::Present(eid)) => Some(eid),
+            _ => None,
+        }
+    }
+    pub fn len(&self) -> usize { self.entities.len() - self.free_slot_list.len() }
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+    /// Slots in use or free for reuse, one past the highest slot.
+    pub fn slots(&self) -> usize { self.entities.len() }
+    ///
+    /// Make room for `additional` more entities without reallocating,
+    /// returning the slot count they will reach at most.
+    ///
+    pub fn reserve(&mut self, additional: usize) -> usize {
+        let new = additional.saturating_sub(self.free_slot_list.len());
+        self.entities.reserve(new);
+        self.entities.len() + new
+    }
+    pub fn iter(&self) -> impl Iterator<Item=EntityID> + '_ {
+        self.entities.iter().filter_map(|entity| match *entity {
+            Entity::Present(eid) => Some(eid),
+            Entity::Missing => None,
+        })
+    }
+}
+
+impl Default for EntityMap {
+    fn default() -> EntityMap { EntityMap::new() }
+}
 
+#[derive(Clone)]
 pub enum Component<C> {
     Present(C),
     Missing,
 }
 
-/// Component Data
+///
+/// Component Data, indexed by Entity slot
+///
+#[derive(Clone)]
 pub struct ComponentType<C> {
     data: Vec<Component<C>>,
-    free: Vec<usize>,
 }
 
 impl<C> ComponentType<C> {
     pub fn new() -> ComponentType<C> {
         ComponentType {
             data: Vec::new(),
-            free: Vec::new(),
         }
     }
-    pub fn add(&mut self, component: C) -> usize {
-        if self.free.is_empty() {
-            let idx = self.data.len();
-            self.data.push(Component::Present(component));
-            return idx;
-        } else {
-            let idx = self.free.remove(0);
-            self.data[idx] = component;
-            return idx;
+    pub fn insert(&mut self, slot: usize, component: C) -> Option<C> {
+        while self.data.len() <= slot {
+            self.data.push(Component::Missing);
+        }
+        match ::std::mem::replace(&mut self.data[slot], Component::Present(component)) {
+            Component::Present(previous) => Some(previous),
+            Component::Missing => None,
         }
     }
-    pub fn remove(&mut self, idx: usize) {
-        if idx < self.data.len() {
-            self.data[idx] = Component::Missing;
-            self.free.push(idx);
+    /// Make room for slots up to `slots` without reallocating.
+    pub fn reserve_slots(&mut self, slots: usize) { self.data.reserve(slots.saturating_sub(self.data.len())) }
+    pub fn remove(&mut self, slot: usize) -> Option<C> {
+        if slot < self.data.len() {
+            match ::std::mem::replace(&mut self.data[slot], Component::Missing) {
+                Component::Present(previous) => Some(previous),
+                Component::Missing => None,
+            }
         } else {
-            // Error
+            None
+        }
+    }
+    pub fn get(&self, slot: usize) -> Option<&C> {
+        match self.data.get(slot) {
+            Some(Component::Present(component)) => Some(component),
+            _ => None,
         }
     }
+    pub fn get_mut(&mut self, slot: usize) -> Option<&mut C> {
+        match self.data.get_mut(slot) {
+            Some(Component::Present(component)) => Some(component),
+            _ => None,
+        }
+    }
+    pub fn iter(&self) -> impl Iterator<Item=(usize, &C)> {
+        self.data.iter().enumerate().filter_map(|(slot, component)| match *component {
+            Component::Present(ref component) => Some((slot, component)),
+            Component::Missing => None,
+        })
+    }
+    pub fn iter_mut(&mut self) -> impl Iterator<Item=(usize, &mut C)> {
+        self.data.iter_mut().enumerate().filter_map(|(slot, component)| match *component {
+            Component::Present(ref mut component) => Some((slot, component)),
+            Component::Missing => None,
+        })
+    }
 }
 
-#[macro_export]
-macro_rules! ECS {{
-    $($compname:ident: $comptype:ty),*
-} => {
-    $crate::EntityManager::new();
+impl<C> Default for ComponentType<C> {
+    fn default() -> ComponentType<C> { ComponentType::new() }
+}
 
-    pub struct EntityMap {
-        next_unique_id: usize,
-        next_slot_id: usize,
-        free_slot_list: Vec<usize>,
-        entities: Vec<Option<Entity>>,
+/// Type erased access to a ComponentType
+trait ComponentStore {
+    fn contains_slot(&self, slot: usize) -> bool;
+    fn reserve_slots(&mut self, slots: usize);
+    fn remove_slot(&mut self, slot: usize);
+    fn bytes(&self) -> usize;
+    fn fork(&self) -> Box<dyn ComponentStore>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+///
+/// Stores are shared between an EntityManager and its forks until one of
+/// them writes to it, which copies it first. Anything lending a store out
+/// mutably counts as writing.
+///
+impl<C: Clone + 'static> ComponentStore for Arc<ComponentType<C>> {
+    fn contains_slot(&self, slot: usize) -> bool { self.get(slot).is_some() }
+    fn reserve_slots(&mut self, slots: usize) {
+        if self.data.capacity() < slots {
+            Arc::make_mut(self).reserve_slots(slots)
+        }
+    }
+    fn remove_slot(&mut self, slot: usize) {
+        if self.contains_slot(slot) {
+            Arc::make_mut(self).remove(slot);
+        }
     }
+    fn bytes(&self) -> usize { self.data.capacity() * ::std::mem::size_of::<Component<C>>() }
+    fn fork(&self) -> Box<dyn ComponentStore> { Box::new(self.clone()) }
+    fn as_any(&self) -> &dyn Any { &**self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { Arc::<ComponentType<C>>::make_mut(self) }
+}
 
+/// Resource value
+#[derive(Clone)]
+struct ResourceCell<R>(R);
 
-    /// Core Entity System
-    //#[derive(Serialize, Deserialize)]
-    pub struct EntityManager {
-        next_entity_id: usize,
-        entities: Vec<Entity>,
-        $($compname: ComponentData<$comptype>),*
-    }
-
-    impl EntityManager {
-        pub fn new() -> EntityManager {
-            EntityManager {
-                next_entity_id: 0,
-                entities: vec![Entity],
-                $($compname: ComponentData::new()),*
+/// Type erased access to a ResourceCell
+trait Resource {
+    fn fork(&self) -> Box<dyn Resource>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<R: Clone + 'static> Resource for ResourceCell<R> {
+    fn fork(&self) -> Box<dyn Resource> { Box::new(self.clone()) }
+    fn as_any(&self) -> &dyn Any { self }
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+    fn into_any(self: Box<Self>) -> Box<dyn Any> { self }
+}
+
+///
+/// Component pointing at a target, whose sources are indexed by target once
+/// registered with `EntityManager::register_relation`
+///
+pub trait Relation: Clone + 'static {
+    type Target: Ord + Clone + 'static;
+    fn target(&self) -> Self::Target;
+}
+
+/// Type erased access to a RelationIndex
+trait RelationStore {
+    fn insert(&mut self, eid: EntityID, relation: &dyn Any);
+    fn remove(&mut self, eid: EntityID);
+    /// Note an entity, or every one if None, whose relation is lent out mutably.
+    fn loosen(&mut self, eid: Option<EntityID>);
+    /// Index the loosened entities again from their current relations.
+    fn settle(&mut self, store: Option<&dyn ComponentStore>);
+    fn fork(&self) -> Box<dyn RelationStore>;
+    fn as_any(&self) -> &dyn Any;
+}
+
+///
+/// Sources of a Relation by target
+///
+#[derive(Clone)]
+struct RelationIndex<R: Relation> {
+    targets: HashMap<EntityID, R::Target>,
+    sources: BTreeMap<R::Target, BTreeSet<EntityID>>,
+    /// Entities whose relation may have changed in place since it was indexed
+    loose: BTreeSet<EntityID>,
+}
+
+impl<R: Relation> RelationIndex<R> {
+    fn new() -> RelationIndex<R> { RelationIndex { targets: HashMap::new(), sources: BTreeMap::new(), loose: BTreeSet::new() } }
+    fn related(&self, store: Option<&ComponentType<R>>, target: &R::Target) -> Vec<EntityID> {
+        let mut related: Vec<EntityID> = self.sources.get(target).into_iter().flatten()
+            .filter(|eid| !self.loose.contains(eid))
+            .cloned()
+            .collect();
+        if !self.loose.is_empty() {
+            related.extend(self.loose.iter().filter(|eid| store.and_then(|store| store.get(eid.slot)).is_some_and(|relation| relation.target() == *target)));
+            related.sort();
+        }
+        related
+    }
+}
+
+impl<R: Relation> RelationStore for RelationIndex<R> {
+    fn insert(&mut self, eid: EntityID, relation: &dyn Any) {
+        if let Some(relation) = relation.downcast_ref::<R>() {
+            self.remove(eid);
+            self.sources.entry(relation.target()).or_default().insert(eid);
+            self.targets.insert(eid, relation.target());
+        }
+    }
+    fn remove(&mut self, eid: EntityID) {
+        self.loose.remove(&eid);
+        if let Some(target) = self.targets.remove(&eid) {
+            if let Some(sources) = self.sources.get_mut(&target) {
+                sources.remove(&eid);
+                if sources.is_empty() {
+                    self.sources.remove(&target);
+                }
             }
         }
-        pub fn create_entity(&mut self) -> EntityID {
-            let uid = next_uid;
-            next_uid += 1;
-            entities.push(Entity{
-                uid: uid,
-                $($compname: Component::Missing),*
-            });
-            (uid as EntityID)
+    }
+    fn loosen(&mut self, eid: Option<EntityID>) {
+        match eid {
+            Some(eid) => { self.loose.insert(eid); }
+            None => self.loose.extend(self.targets.keys().cloned()),
         }
-        $(pub fn add_$compname_component(&mut self, eid: EntityID, $compname: $comptype) {
-            self.$compname.date.push($compname)
-        }),*
     }
+    fn settle(&mut self, store: Option<&dyn ComponentStore>) {
+        let store = store.and_then(|store| store.as_any().downcast_ref::<ComponentType<R>>());
+        for eid in std::mem::take(&mut self.loose) {
+            match store.and_then(|store| store.get(eid.slot)) {
+                Some(relation) => self.insert(eid, relation),
+                None => self.remove(eid),
+            }
+        }
+    }
+    fn fork(&self) -> Box<dyn RelationStore> { Box::new(self.clone()) }
+    fn as_any(&self) -> &dyn Any { self }
+}
+
+///
+/// Components spawned together, implemented for tuples of up to eight
+///
+pub trait ComponentTuple {
+    /// Make room in each Component's store for slots up to `slots`.
+    fn reserve(manager: &mut EntityManager, slots: usize);
+    fn insert(self, manager: &mut EntityManager, eid: EntityID);
+}
+
+macro_rules! component_tuple {
+    ($($component:ident),*) => {
+        impl<$($component: Clone + 'static),*> ComponentTuple for ($($component,)*) {
+            #[allow(unused_variables)]
+            fn reserve(manager: &mut EntityManager, slots: usize) {
+                $(manager.reserve_component::<$component>(slots);)*
+            }
+            #[allow(non_snake_case, unused_variables)]
+            fn insert(self, manager: &mut EntityManager, eid: EntityID) {
+                let ($($component,)*) = self;
+                $(manager.add_component(eid, $component);)*
+            }
+        }
+    };
+}
 
-    /// Internal Entity containing Component Indexes
-    #[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Serialize, Deserialize)]
-    pub struct Entity {
-        /// Entity ID
-        uid: usize,
-        $($compname: Component),*
+component_tuple!();
+component_tuple!(A);
+component_tuple!(A, B);
+component_tuple!(A, B, C);
+component_tuple!(A, B, C, D);
+component_tuple!(A, B, C, D, E);
+component_tuple!(A, B, C, D, E, F);
+component_tuple!(A, B, C, D, E, F, G);
+component_tuple!(A, B, C, D, E, F, G, H);
+
+///
+/// Components an entity must and must not have to match a Query
+///
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct Query {
+    with: Vec<TypeId>,
+    without: Vec<TypeId>,
+}
+
+impl Query {
+    /// Query matching every enabled entity.
+    pub fn new() -> Query { Query::default() }
+    pub fn with<C: 'static>(mut self) -> Query {
+        self.with.push(TypeId::of::<C>());
+        self
+    }
+    pub fn without<C: 'static>(mut self) -> Query {
+        self.without.push(TypeId::of::<C>());
+        self
     }
+    fn mentions(&self, id: TypeId) -> bool { self.with.contains(&id) || self.without.contains(&id) }
+}
 
-}}
+///
+/// Handle of a cached Query
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct QueryId(usize);
+
+/// Query with the entities matching it
+#[derive(Clone)]
+struct CachedQuery {
+    query: Query,
+    matching: BTreeSet<EntityID>,
+}
+
+///
+/// Core Entity System
+///
+/// Components and resources are Clone, so the whole of it can be forked,
+/// each Component store copied only once it's written to.
+///
+/// Entities can carry any number of string labels, indexed so all those with
+/// a label are found without a scan; enum-like tags are better served by a
+/// unit struct Component. A disabled entity stays alive with all its
+/// Components but is skipped by `iter` and `iter_mut`, so no system sees it
+/// until it is enabled again.
+///
+/// Iteration is always in EntityID order, which for live entities is slot
+/// order, whatever order their Components were added in, so systems see
+/// entities in the same order on every run and on every peer. A Query
+/// cached with `cache_query` keeps its matching entities as Components are
+/// added and removed and entities come and go, rather than checking every
+/// entity each time it is run.
+///
+/// Spawning many entities at once, a wave of larvae, goes through
+/// `spawn_batch`, which reserves room for all of them in the entity map and
+/// each of their Component stores up front instead of growing them entity
+/// by entity. `reserve` does the same ahead of time for every store.
+///
+/// A Relation Component, a drone's TargetOf or AssignedTo, registered with
+/// `register_relation` is indexed by its target, so `related` finds every
+/// entity pointing at one without a scan. One changed in place through
+/// `get_component_mut` or `iter_mut` is indexed again by the next change to
+/// the EntityManager, and checked directly by `related` until then.
+///
+pub struct EntityManager {
+    entities: EntityMap,
+    components: HashMap<TypeId, Box<dyn ComponentStore>>,
+    /// Singleton values shared by systems, one per type
+    resources: HashMap<TypeId, Box<dyn Resource>>,
+    /// Entities by label
+    labelled: HashMap<String, BTreeSet<EntityID>>,
+    /// Labels by entity
+    labels: HashMap<EntityID, BTreeSet<String>>,
+    /// Whether the entity in each slot is disabled
+    disabled: Vec<bool>,
+    /// Cached Queries by QueryId, None once dropped
+    queries: Vec<Option<CachedQuery>>,
+    /// Relation indices by Component type
+    relations: HashMap<TypeId, Box<dyn RelationStore>>,
+}
+
+impl EntityManager {
+    pub fn new() -> EntityManager {
+        EntityManager {
+            entities: EntityMap::new(),
+            components: HashMap::new(),
+            resources: HashMap::new(),
+            labelled: HashMap::new(),
+            labels: HashMap::new(),
+            disabled: Vec::new(),
+            queries: Vec::new(),
+            relations: HashMap::new(),
+        }
+    }
+    /// Register a Component type ahead of its first use.
+    pub fn register<C: Clone + 'static>(&mut self) {
+        self.components.entry(TypeId::of::<C>())
+            .or_insert_with(|| Box::new(Arc::new(ComponentType::<C>::new())));
+    }
+    pub fn create_entity(&mut self) -> EntityID {
+        let eid = self.entities.create();
+        self.refresh(eid, None);
+        eid
+    }
+    ///
+    /// Create an entity for each tuple of Components, reserving room for as
+    /// many as the iterator says it holds first. Returns them in order.
+    ///
+    pub fn spawn_batch<T: ComponentTuple, I: IntoIterator<Item=T>>(&mut self, batch: I) -> Vec<EntityID> {
+        let batch = batch.into_iter();
+        let count = batch.size_hint().0;
+        let slots = self.entities.reserve(count);
+        T::reserve(self, slots);
+        let mut spawned = Vec::with_capacity(count);
+        for components in batch {
+            let eid = self.create_entity();
+            components.insert(self, eid);
+            spawned.push(eid);
+        }
+        spawned
+    }
+    /// Destroy every entity listed, returning how many were alive.
+    pub fn despawn_batch(&mut self, batch: &[EntityID]) -> usize { batch.iter().filter(|&&eid| self.destroy_entity(eid)).count() }
+    /// Make room for `additional` more entities in the entity map and every registered store.
+    pub fn reserve(&mut self, additional: usize) {
+        let slots = self.entities.reserve(additional);
+        for store in self.components.values_mut() {
+            store.reserve_slots(slots);
+        }
+    }
+    /// Make room for C on `slots` entity slots, registering it if need be.
+    pub fn reserve_component<C: Clone + 'static>(&mut self, slots: usize) {
+        self.register::<C>();
+        if let Some(store) = self.components.get_mut(&TypeId::of::<C>()) {
+            store.reserve_slots(slots);
+        }
+    }
+    pub fn destroy_entity(&mut self, eid: EntityID) -> bool {
+        self.settle_relations();
+        if self.entities.destroy(eid) {
+            for index in self.relations.values_mut() {
+                index.remove(eid);
+            }
+            for cached in self.queries.iter_mut().flatten() {
+                cached.matching.remove(&eid);
+            }
+            for store in self.components.values_mut() {
+                store.remove_slot(eid.slot);
+            }
+            for label in self.labels.remove(&eid).unwrap_or_default() {
+                self.unindex(&label, eid);
+            }
+            self.enable(eid);
+            true
+        } else {
+            false
+        }
+    }
+    pub fn is_alive(&self, eid: EntityID) -> bool { self.entities.is_alive(eid) }
+    /// Every live entity, the disabled included.
+    pub fn entities(&self) -> &EntityMap { &self.entities }
+    ///
+    /// Hide a live entity from `iter` and `iter_mut`, keeping its Components.
+    /// Returns false if it is dead or already disabled.
+    ///
+    pub fn disable(&mut self, eid: EntityID) -> bool {
+        if !self.entities.is_alive(eid) || self.is_disabled(eid) {
+            return false;
+        }
+        if self.disabled.len() <= eid.slot {
+            self.disabled.resize(eid.slot + 1, false);
+        }
+        self.disabled[eid.slot] = true;
+        self.refresh(eid, None);
+        true
+    }
+    /// Return a disabled entity to queries, false if it wasn't disabled.
+    pub fn enable(&mut self, eid: EntityID) -> bool {
+        match self.disabled.get_mut(eid.slot) {
+            Some(disabled) if *disabled => {
+                *disabled = false;
+                self.refresh(eid, None);
+                true
+            }
+            _ => false,
+        }
+    }
+    pub fn is_disabled(&self, eid: EntityID) -> bool { self.entities.is_alive(eid) && self.disabled.get(eid.slot).cloned().unwrap_or(false) }
+    /// Disabled entities in slot order.
+    pub fn disabled(&self) -> impl Iterator<Item=EntityID> + '_ { self.entities.iter().filter(move |&eid| self.is_disabled(eid)) }
+    /// Label a live entity, false if it is dead or already has the label.
+    pub fn add_label(&mut self, eid: EntityID, label: &str) -> bool {
+        if !self.entities.is_alive(eid) || !self.labels.entry(eid).or_default().insert(label.to_string()) {
+            return false;
+        }
+        self.labelled.entry(label.to_string()).or_default().insert(eid);
+        true
+    }
+    pub fn remove_label(&mut self, eid: EntityID, label: &str) -> bool {
+        let removed = self.labels.get_mut(&eid).is_some_and(|labels| labels.remove(label));
+        if removed {
+            self.unindex(label, eid);
+            if self.labels.get(&eid).is_some_and(BTreeSet::is_empty) {
+                self.labels.remove(&eid);
+            }
+        }
+        removed
+    }
+    pub fn has_label(&self, eid: EntityID, label: &str) -> bool { self.labelled.get(label).is_some_and(|entities| entities.contains(&eid)) }
+    /// Labels of an entity in order.
+    pub fn labels(&self, eid: EntityID) -> impl Iterator<Item=&str> + '_ { self.labels.get(&eid).into_iter().flat_map(|labels| labels.iter().map(|label| &label[..])) }
+    /// Entities with a label in id order, the disabled included.
+    pub fn find_by_label(&self, label: &str) -> impl Iterator<Item=EntityID> + '_ { self.labelled.get(label).into_iter().flat_map(|entities| entities.iter().cloned()) }
+    fn unindex(&mut self, label: &str, eid: EntityID) {
+        if let Some(entities) = self.labelled.get_mut(label) {
+            entities.remove(&eid);
+            if entities.is_empty() {
+                self.labelled.remove(label);
+            }
+        }
+    }
+    /// Bytes reserved by every component store, not counting what components own on the heap.
+    pub fn component_bytes(&self) -> usize { self.components.values().map(|store| store.bytes()).sum() }
+    /// Returns the component replaced. One added to a dead entity is dropped.
+    pub fn add_component<C: Clone + 'static>(&mut self, eid: EntityID, component: C) -> Option<C> {
+        if !self.entities.is_alive(eid) {
+            return None;
+        }
+        self.register::<C>();
+        self.settle_relations();
+        if let Some(index) = self.relations.get_mut(&TypeId::of::<C>()) {
+            index.insert(eid, &component);
+        }
+        let previous = self.store_mut::<C>().and_then(|store| store.insert(eid.slot, component));
+        if previous.is_none() {
+            self.refresh(eid, Some(TypeId::of::<C>()));
+        }
+        previous
+    }
+    /// As `add_component`, but a dead entity is an error.
+    pub fn try_add_component<C: Clone + 'static>(&mut self, eid: EntityID, component: C) -> Result<Option<C>, HivemindError> {
+        if !self.entities.is_alive(eid) {
+            return Err(HivemindError::DeadEntity(eid));
+        }
+        Ok(self.add_component(eid, component))
+    }
+    pub fn remove_component<C: 'static>(&mut self, eid: EntityID) -> Option<C> {
+        // Without one to remove the store isn't written, or copied if shared
+        if !self.has_component::<C>(eid) {
+            return None;
+        }
+        self.settle_relations();
+        if let Some(index) = self.relations.get_mut(&TypeId::of::<C>()) {
+            index.remove(eid);
+        }
+        let removed = self.store_mut::<C>().and_then(|store| store.remove(eid.slot));
+        if removed.is_some() {
+            self.refresh(eid, Some(TypeId::of::<C>()));
+        }
+        removed
+    }
+    pub fn get_component<C: 'static>(&self, eid: EntityID) -> Option<&C> {
+        if !self.entities.is_alive(eid) {
+            return None;
+        }
+        self.store::<C>().and_then(|store| store.get(eid.slot))
+    }
+    pub fn get_component_mut<C: 'static>(&mut self, eid: EntityID) -> Option<&mut C> {
+        if !self.entities.is_alive(eid) {
+            return None;
+        }
+        self.settle_relations();
+        if let Some(index) = self.relations.get_mut(&TypeId::of::<C>()) {
+            index.loosen(Some(eid));
+        }
+        self.store_mut::<C>().and_then(|store| store.get_mut(eid.slot))
+    }
+    pub fn has_component<C: 'static>(&self, eid: EntityID) -> bool { self.get_component::<C>(eid).is_some() }
+    ///
+    /// Keep the entities matching a Query from now on, see `query`.
+    ///
+    pub fn cache_query(&mut self, query: Query) -> QueryId {
+        let matching = self.entities.iter().filter(|&eid| self.matches(&query, eid)).collect();
+        self.queries.push(Some(CachedQuery { query, matching }));
+        QueryId(self.queries.len() - 1)
+    }
+    /// Stop keeping a Query, false if it was already dropped.
+    pub fn drop_query(&mut self, id: QueryId) -> bool { self.queries.get_mut(id.0).and_then(Option::take).is_some() }
+    /// Entities matching a cached Query in EntityID order, none once it is dropped.
+    pub fn query(&self, id: QueryId) -> impl Iterator<Item=EntityID> + '_ {
+        self.queries.get(id.0).and_then(Option::as_ref).into_iter().flat_map(|cached| cached.matching.iter().cloned())
+    }
+    pub fn query_len(&self, id: QueryId) -> usize { self.queries.get(id.0).and_then(Option::as_ref).map_or(0, |cached| cached.matching.len()) }
+    /// Whether a live entity matches a Query, cached or not.
+    pub fn matches(&self, query: &Query, eid: EntityID) -> bool {
+        let has = |id: &TypeId| self.components.get(id).is_some_and(|store| store.contains_slot(eid.slot));
+        self.entities.is_alive(eid) && !self.is_disabled(eid) && query.with.iter().all(has) && !query.without.iter().any(has)
+    }
+    /// Bring the cached Queries up to date with an entity, only those mentioning `changed` if given.
+    fn refresh(&mut self, eid: EntityID, changed: Option<TypeId>) {
+        for index in 0..self.queries.len() {
+            let matches = match self.queries[index] {
+                Some(ref cached) if changed.is_none_or(|id| cached.query.mentions(id)) => self.matches(&cached.query, eid),
+                _ => continue,
+            };
+            if let Some(ref mut cached) = self.queries[index] {
+                if matches {
+                    cached.matching.insert(eid);
+                } else {
+                    cached.matching.remove(&eid);
+                }
+            }
+        }
+    }
+    ///
+    /// Index R by target from now on, the Components already added included.
+    ///
+    pub fn register_relation<R: Relation>(&mut self) {
+        self.register::<R>();
+        if self.relations.contains_key(&TypeId::of::<R>()) {
+            return;
+        }
+        let mut index = RelationIndex::<R>::new();
+        for (slot, relation) in self.store::<R>().into_iter().flat_map(|store| store.iter()) {
+            if let Some(eid) = self.entities.at_slot(slot) {
+                index.insert(eid, relation);
+            }
+        }
+        self.relations.insert(TypeId::of::<R>(), Box::new(index));
+    }
+    ///
+    /// Live entities whose R points at `target` in EntityID order, the
+    /// disabled included. Unless R is registered this scans every R.
+    ///
+    pub fn related<R: Relation>(&self, target: &R::Target) -> Vec<EntityID> {
+        let index = self.relations.get(&TypeId::of::<R>()).and_then(|index| index.as_any().downcast_ref::<RelationIndex<R>>());
+        match index {
+            Some(index) => index.related(self.store::<R>(), target),
+            None => self.store::<R>().into_iter().flat_map(|store| store.iter())
+                .filter(|&(_, relation)| relation.target() == *target)
+                .filter_map(|(slot, _)| self.entities.at_slot(slot))
+                .collect(),
+        }
+    }
+    fn settle_relations(&mut self) {
+        for (id, index) in self.relations.iter_mut() {
+            index.settle(self.components.get(id).map(|store| &**store));
+        }
+    }
+    /// Iterate every live and enabled entity holding a C, in EntityID order.
+    pub fn iter<C: 'static>(&self) -> impl Iterator<Item=(EntityID, &C)> {
+        let (entities, disabled) = (&self.entities, &self.disabled);
+        self.store::<C>().into_iter().flat_map(|store| store.iter())
+            .filter(move |&(slot, _)| !disabled.get(slot).cloned().unwrap_or(false))
+            .filter_map(move |(slot, component)| entities.at_slot(slot).map(|eid| (eid, component)))
+    }
+    pub fn iter_mut<C: 'static>(&mut self) -> impl Iterator<Item=(EntityID, &mut C)> {
+        self.settle_relations();
+        if let Some(index) = self.relations.get_mut(&TypeId::of::<C>()) {
+            index.loosen(None);
+        }
+        let (entities, disabled) = (&self.entities, &self.disabled);
+        let store = self.components.get_mut(&TypeId::of::<C>())
+            .and_then(|store| store.as_any_mut().downcast_mut::<ComponentType<C>>());
+        store.into_iter().flat_map(|store| store.iter_mut())
+            .filter(move |&(slot, _)| !disabled.get(slot).cloned().unwrap_or(false))
+            .filter_map(move |(slot, component)| entities.at_slot(slot).map(|eid| (eid, component)))
+    }
+    /// Store a resource, returning the one it replaced.
+    pub fn insert_resource<R: Clone + 'static>(&mut self, resource: R) -> Option<R> {
+        self.resources.insert(TypeId::of::<R>(), Box::new(ResourceCell(resource)))
+            .and_then(|previous| previous.into_any().downcast::<ResourceCell<R>>().ok())
+            .map(|previous| previous.0)
+    }
+    pub fn remove_resource<R: 'static>(&mut self) -> Option<R> {
+        self.resources.remove(&TypeId::of::<R>())
+            .and_then(|resource| resource.into_any().downcast::<ResourceCell<R>>().ok())
+            .map(|resource| resource.0)
+    }
+    pub fn resource<R: 'static>(&self) -> Option<&R> {
+        self.resources.get(&TypeId::of::<R>()).and_then(|resource| resource.as_any().downcast_ref::<ResourceCell<R>>()).map(|resource| &resource.0)
+    }
+    pub fn resource_mut<R: 'static>(&mut self) -> Option<&mut R> {
+        self.resources.get_mut(&TypeId::of::<R>()).and_then(|resource| resource.as_any_mut().downcast_mut::<ResourceCell<R>>()).map(|resource| &mut resource.0)
+    }
+    ///
+    /// Copy of every entity, component and resource, to change without
+    /// touching these. Component stores are shared until either side writes
+    /// to one, resources and indices are copied outright.
+    ///
+    pub fn fork(&self) -> EntityManager {
+        EntityManager {
+            entities: self.entities.clone(),
+            components: self.components.iter().map(|(&id, store)| (id, store.fork())).collect(),
+            resources: self.resources.iter().map(|(&id, resource)| (id, resource.fork())).collect(),
+            labelled: self.labelled.clone(),
+            labels: self.labels.clone(),
+            disabled: self.disabled.clone(),
+            queries: self.queries.clone(),
+            relations: self.relations.iter().map(|(&id, index)| (id, index.fork())).collect(),
+        }
+    }
+    fn store<C: 'static>(&self) -> Option<&ComponentType<C>> {
+        self.components.get(&TypeId::of::<C>())
+            .and_then(|store| store.as_any().downcast_ref::<ComponentType<C>>())
+    }
+    fn store_mut<C: 'static>(&mut self) -> Option<&mut ComponentType<C>> {
+        self.components.get_mut(&TypeId::of::<C>())
+            .and_then(|store| store.as_any_mut().downcast_mut::<ComponentType<C>>())
+    }
+}
+
+impl Default for EntityManager {
+    fn default() -> EntityManager { EntityManager::new() }
+}
+
+/// Create an EntityManager with the listed Component types registered.
+#[macro_export]
+macro_rules! ECS {
+    ($($compname:ident: $comptype:ty),* $(,)*) => {{
+        let mut manager = $crate::model::entity::EntityManager::new();
+        $(manager.register::<$comptype>();)*
+        manager
+    }};
+}
 
 #[cfg(test)]
 mod tests {
+    use super::{EntityManager, Query, Relation};
+    use error::HivemindError;
     //#[derive(Serialize, Deserialize)]
+    #[derive(Clone)]
     pub struct Position {
         x: i32,
         y: i32,
     }
 
     //#[derive(Serialize, Deserialize)]
+    #[derive(Clone)]
     pub struct Physics {
         weight: usize
     }
 
     #[test]
     pub fn test_ecs() {
-        let entity_manager = ECS!(
+        let mut entity_manager = ECS!(
             physics: Physics,
             position: Position,
         );
 
         let entity = entity_manager.create_entity();
+        entity_manager.add_component(entity, Position { x: 1, y: 2 });
+        entity_manager.add_component(entity, Physics { weight: 3 });
+        assert_eq!(entity_manager.get_component::<Position>(entity).map(|p| p.x + p.y), Some(3));
+
+        entity_manager.destroy_entity(entity);
+        let reused = entity_manager.create_entity();
+        assert_eq!(reused.slot(), entity.slot());
+        assert!(!entity_manager.is_alive(entity));
+        assert!(entity_manager.get_component::<Physics>(reused).is_none());
+        assert!(matches!(entity_manager.try_add_component(entity, Physics { weight: 1 }), Err(HivemindError::DeadEntity(dead)) if dead == entity));
+        assert!(entity_manager.try_add_component(reused, Physics { weight: 1 }).unwrap().is_none());
+
+        assert!(entity_manager.insert_resource(Physics { weight: 7 }).is_none());
+        entity_manager.resource_mut::<Physics>().unwrap().weight += 1;
+        assert_eq!(entity_manager.insert_resource(Physics { weight: 1 }).map(|p| p.weight), Some(8));
+        assert_eq!(entity_manager.remove_resource::<Physics>().map(|p| p.weight), Some(1));
+        assert!(entity_manager.resource::<Physics>().is_none());
+    }
+
+    #[test]
+    pub fn test_labels_and_disabling() {
+        let mut entity_manager = ECS!(position: Position);
+        let drones: Vec<_> = (0..3).map(|x| {
+            let drone = entity_manager.create_entity();
+            entity_manager.add_component(drone, Position { x, y: 0 });
+            drone
+        }).collect();
+        assert!(entity_manager.add_label(drones[0], "scout") && entity_manager.add_label(drones[2], "scout"));
+        assert!(entity_manager.add_label(drones[2], "leader") && !entity_manager.add_label(drones[2], "leader"));
+        assert_eq!(entity_manager.find_by_label("scout").collect::<Vec<_>>(), vec![drones[0], drones[2]]);
+        assert_eq!(entity_manager.labels(drones[2]).collect::<Vec<_>>(), vec!["leader", "scout"]);
+        assert!(entity_manager.remove_label(drones[0], "scout") && !entity_manager.has_label(drones[0], "scout"));
+
+        // Disabled entities drop out of queries but keep their data
+        assert!(entity_manager.disable(drones[1]) && !entity_manager.disable(drones[1]));
+        assert_eq!(entity_manager.iter::<Position>().map(|(_, p)| p.x).collect::<Vec<_>>(), vec![0, 2]);
+        for (_, position) in entity_manager.iter_mut::<Position>() {
+            position.y += 1;
+        }
+        assert_eq!(entity_manager.get_component::<Position>(drones[1]).map(|p| (p.x, p.y)), Some((1, 0)));
+        assert_eq!((entity_manager.disabled().collect::<Vec<_>>(), entity_manager.entities().len()), (vec![drones[1]], 3));
+        let fork = entity_manager.fork();
+        assert!(entity_manager.enable(drones[1]) && !entity_manager.enable(drones[1]));
+        assert_eq!(entity_manager.iter::<Position>().count(), 3);
+        assert!(fork.is_disabled(drones[1]) && fork.has_label(drones[2], "scout"));
+
+        // Destroying clears both, so the slot's next occupant starts clean
+        entity_manager.disable(drones[2]);
+        entity_manager.destroy_entity(drones[2]);
+        let reused = entity_manager.create_entity();
+        assert_eq!(reused.slot(), drones[2].slot());
+        assert!(entity_manager.find_by_label("scout").next().is_none() && !entity_manager.is_disabled(reused));
+    }
+
+    #[test]
+    pub fn test_cached_queries() {
+        // Two managers reaching the same entities by different histories
+        let mut first = ECS!(position: Position, physics: Physics);
+        let mut second = ECS!(position: Position, physics: Physics);
+        let moving = Query::new().with::<Position>().without::<Physics>();
+        let (a, b) = (first.cache_query(moving.clone()), second.cache_query(moving));
+        let drones: Vec<_> = (0..4).map(|_| (first.create_entity(), second.create_entity())).collect();
+        for (x, &(one, _)) in drones.iter().enumerate() {
+            first.add_component(one, Position { x: x as i32, y: 0 });
+        }
+        for (x, &(_, two)) in drones.iter().enumerate().rev() {
+            second.add_component(two, Physics { weight: 1 });
+            second.add_component(two, Position { x: x as i32, y: 0 });
+            second.remove_component::<Physics>(two);
+        }
+        first.add_component(drones[2].0, Physics { weight: 2 });
+        second.add_component(drones[2].1, Physics { weight: 2 });
+        let ids = |manager: &EntityManager, id| manager.query(id).map(|eid| eid.slot()).collect::<Vec<_>>();
+        assert_eq!((ids(&first, a), ids(&second, b)), (vec![0, 1, 3], vec![0, 1, 3]));
+        assert_eq!(first.iter::<Position>().map(|(eid, _)| eid).collect::<Vec<_>>(), second.iter::<Position>().map(|(eid, _)| eid).collect::<Vec<_>>());
+
+        // Kept up to date as entities come, go and are disabled
+        first.disable(drones[0].0);
+        first.destroy_entity(drones[1].0);
+        let late = first.create_entity();
+        first.add_component(late, Position { x: 9, y: 9 });
+        assert_eq!(ids(&first, a), vec![1, 3]);
+        assert_eq!(first.query(a).next(), Some(late));
+        let everything = first.cache_query(Query::new());
+        assert_eq!(first.query_len(everything), 3);
+        first.enable(drones[0].0);
+        assert_eq!((ids(&first, a), first.query_len(everything)), (vec![0, 1, 3], 4));
+        assert!(first.matches(&Query::new().with::<Physics>(), drones[2].0));
+
+        // Forks carry their queries, and dropped ones match nothing
+        let fork = first.fork();
+        assert!(first.drop_query(a) && !first.drop_query(a));
+        assert_eq!((first.query(a).count(), fork.query_len(a)), (0, 3));
+    }
+
+    #[test]
+    pub fn test_batch_spawning() {
+        let mut entity_manager = EntityManager::new();
+        let larvae = entity_manager.spawn_batch((0..1000).map(|x| (Position { x, y: 0 }, Physics { weight: 1 })));
+        assert_eq!((larvae.len(), entity_manager.entities().len()), (1000, 1000));
+        assert_eq!(entity_manager.get_component::<Position>(larvae[999]).map(|p| p.x), Some(999));
+
+        // Reserved up front, the next wave grows nothing
+        assert_eq!(entity_manager.despawn_batch(&larvae[..500]), 500);
+        assert_eq!(entity_manager.despawn_batch(&larvae[..10]), 0);
+        entity_manager.reserve(1500);
+        let bytes = entity_manager.component_bytes();
+        let wave = entity_manager.spawn_batch((0..1500).map(|x| (Position { x, y: 1 }, Physics { weight: 2 })));
+        assert_eq!((entity_manager.component_bytes(), entity_manager.entities().len()), (bytes, 2000));
+        assert_eq!(entity_manager.entities().slots(), 2000);
+        assert!(wave.iter().all(|&eid| entity_manager.get_component::<Physics>(eid).map(|p| p.weight) == Some(2)));
+        assert_eq!(entity_manager.spawn_batch(vec![(); 3]).len(), 3);
+    }
+
+    #[test]
+    pub fn test_forking() {
+        let mut entity_manager = EntityManager::new();
+        let drones = entity_manager.spawn_batch((0..100).map(|x| (Position { x, y: 0 }, Physics { weight: 1 })));
+        let mut fork = entity_manager.fork();
+        let shared = |first: &EntityManager, second: &EntityManager| ::std::ptr::eq(first.store::<Position>().unwrap(), second.store::<Position>().unwrap());
+        assert!(shared(&entity_manager, &fork));
+
+        // Reading, or removing what isn't there, leaves the stores shared
+        assert_eq!(fork.iter::<Position>().count(), 100);
+        assert!(fork.remove_component::<&str>(drones[0]).is_none());
+        fork.reserve_component::<Position>(100);
+        assert!(shared(&entity_manager, &fork));
+
+        // Writing copies the store on the side written to only
+        fork.get_component_mut::<Position>(drones[3]).unwrap().y = 7;
+        assert!(!shared(&entity_manager, &fork));
+        assert_eq!(entity_manager.get_component::<Position>(drones[3]).map(|p| p.y), Some(0));
+        assert!(::std::ptr::eq(entity_manager.store::<Physics>().unwrap(), fork.store::<Physics>().unwrap()));
+        entity_manager.destroy_entity(drones[4]);
+        assert_eq!(fork.get_component::<Physics>(drones[4]).map(|p| p.weight), Some(1));
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn test_relations() {
+        /// Task a drone works on
+        #[derive(Clone, PartialEq, Debug)]
+        struct AssignedTo(u32);
+        impl Relation for AssignedTo {
+            type Target = u32;
+            fn target(&self) -> u32 { self.0 }
+        }
+
+        let mut entity_manager = EntityManager::new();
+        let drones = entity_manager.spawn_batch((0..6).map(|task| (AssignedTo(task % 2),)));
+        assert_eq!(entity_manager.related::<AssignedTo>(&0), vec![drones[0], drones[2], drones[4]]);
+        entity_manager.register_relation::<AssignedTo>();
+        assert_eq!(entity_manager.related::<AssignedTo>(&1), vec![drones[1], drones[3], drones[5]]);
+
+        // Reassigned, unassigned and destroyed drones leave the index
+        entity_manager.add_component(drones[0], AssignedTo(7));
+        entity_manager.remove_component::<AssignedTo>(drones[2]);
+        entity_manager.destroy_entity(drones[3]);
+        entity_manager.disable(drones[5]);
+        assert_eq!(entity_manager.related::<AssignedTo>(&0), vec![drones[4]]);
+        assert_eq!(entity_manager.related::<AssignedTo>(&1), vec![drones[1], drones[5]]);
+
+        // Changed in place, found by the new task before and after the index catches up
+        entity_manager.get_component_mut::<AssignedTo>(drones[1]).unwrap().0 = 7;
+        assert_eq!(entity_manager.related::<AssignedTo>(&7), vec![drones[0], drones[1]]);
+        for (_, assigned) in entity_manager.iter_mut::<AssignedTo>() {
+            assigned.0 += 1;
+        }
+        let fork = entity_manager.fork();
+        entity_manager.create_entity();
+        assert_eq!(entity_manager.related::<AssignedTo>(&8), vec![drones[0], drones[1]]);
+        // Disabled drones were skipped
+        assert_eq!(fork.related::<AssignedTo>(&1), vec![drones[4], drones[5]]);
+        assert!(entity_manager.related::<AssignedTo>(&7).is_empty());
+    }
+}
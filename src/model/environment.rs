@@ -0,0 +1,137 @@
+///
+/// Environment Cycle
+///
+/// Time of day, the height of the sun and the temperature, kept as an
+/// EntityManager resource which the Simulation advances every tick. A day
+/// runs from midnight through sunrise, noon and sunset; the sun climbs and
+/// falls linearly so every peer computes the same sky. Sky light is stored
+/// by the Lighting at full strength and dimmed on the way out by
+/// `effective_light`, so nightfall never relights a Chunk.
+///
+/// Temperature swings with the sun around a base, shifted by the Biome.
+///
+use math::Fixed;
+use model::biome::{BiomeId, BiomeRegistry};
+use model::light::{Light, MAX_LIGHT};
+use std::collections::HashMap;
+
+/// Default ticks per day, 20 minutes at 20 ticks per second
+pub const DEFAULT_DAY_LENGTH: u64 = 24_000;
+/// Time of day of sunrise, noon and sunset, as fractions of 0x10000
+pub const SUNRISE: u16 = 0x4000;
+pub const NOON: u16 = 0x8000;
+pub const SUNSET: u16 = 0xC000;
+
+///
+/// Day and Night Cycle and Climate
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct Environment {
+    /// Ticks per day
+    pub day_length: u64,
+    /// Ticks since midnight of day 0
+    pub time: u64,
+    /// Sky light level at night
+    pub night_light: u8,
+    /// Degrees at sunrise and sunset
+    pub base_temperature: Fixed,
+    /// Degrees warmer at noon and colder at midnight
+    pub temperature_swing: Fixed,
+    /// Degrees added in each biome, by id
+    biome_temperatures: HashMap<BiomeId, Fixed>,
+}
+
+impl Environment {
+    pub fn new(day_length: u64) -> Environment {
+        Environment {
+            day_length: day_length.max(1),
+            time: 0,
+            night_light: 4,
+            base_temperature: Fixed::from_int(15),
+            temperature_swing: Fixed::from_int(8),
+            biome_temperatures: HashMap::new(),
+        }
+    }
+    /// Move the clock on `ticks`.
+    pub fn advance(&mut self, ticks: u64) { self.time = self.time.wrapping_add(ticks) }
+    /// Days completed.
+    pub fn day(&self) -> u64 { self.time / self.day_length }
+    /// Fraction of the day since midnight, out of 0x10000.
+    pub fn time_of_day(&self) -> u16 { ((self.time % self.day_length) as u128 * 0x10000 / self.day_length as u128) as u16 }
+    /// Set the clock to `time_of_day` on the current day.
+    pub fn set_time_of_day(&mut self, time_of_day: u16) {
+        self.time = self.day() * self.day_length + (time_of_day as u128 * self.day_length as u128 / 0x10000) as u64;
+    }
+    /// Height of the sun, 1 at noon, 0 at sunrise and sunset and -1 at midnight.
+    pub fn sun_height(&self) -> Fixed {
+        let from_noon = (self.time_of_day() as i64 - NOON as i64).abs();
+        Fixed::ONE - Fixed::from_raw(from_noon * Fixed::ONE.raw() / SUNRISE as i64)
+    }
+    pub fn is_night(&self) -> bool { !self.sun_height().is_positive() }
+    ///
+    /// Level full sky light is dimmed to: night_light while the sun is a
+    /// quarter below the horizon, rising to MAX_LIGHT a quarter above it.
+    ///
+    pub fn sky_level(&self) -> u8 {
+        let quarter = Fixed::ONE / Fixed::from_int(4);
+        let daylight = ((self.sun_height() + quarter) * Fixed::from_int(2)).max(Fixed::ZERO).min(Fixed::ONE);
+        let night = self.night_light.min(MAX_LIGHT) as i64;
+        (night + (daylight * Fixed::from_int(MAX_LIGHT as i64 - night) + Fixed::HALF).floor()) as u8
+    }
+    /// Brightness of `light` at this time of day.
+    pub fn effective_light(&self, light: Light) -> u8 { light.sky.min(self.sky_level()).max(light.block) }
+    pub fn set_biome_temperature(&mut self, biome: BiomeId, offset: Fixed) { self.biome_temperatures.insert(biome, offset); }
+    /// Take the temperature offset of every Biome in `biomes`.
+    pub fn add_biomes(&mut self, biomes: &BiomeRegistry) {
+        for (id, biome) in biomes.iter() {
+            self.set_biome_temperature(id, biome.temperature);
+        }
+    }
+    /// Degrees now in `biome`, or anywhere outside a known biome for None.
+    pub fn temperature(&self, biome: Option<BiomeId>) -> Fixed {
+        let offset = biome.and_then(|biome| self.biome_temperatures.get(&biome).cloned()).unwrap_or(Fixed::ZERO);
+        self.base_temperature + self.temperature_swing * self.sun_height() + offset
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Environment { Environment::new(DEFAULT_DAY_LENGTH) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Environment, NOON, SUNRISE, SUNSET};
+    use math::Fixed;
+    use model::biome::BiomeId;
+    use model::light::{Light, MAX_LIGHT};
+
+    #[test]
+    pub fn test_day_night_cycle() {
+        let mut environment = Environment::new(400);
+        assert_eq!((environment.time_of_day(), environment.sun_height(), environment.sky_level()), (0, -Fixed::ONE, 4));
+        assert!(environment.is_night());
+        environment.advance(100);
+        assert_eq!((environment.time_of_day(), environment.sun_height()), (SUNRISE, Fixed::ZERO));
+        // Twilight, halfway between night and day
+        assert_eq!(environment.sky_level(), 10);
+        environment.advance(100);
+        assert_eq!((environment.time_of_day(), environment.sky_level()), (NOON, MAX_LIGHT));
+        assert!(!environment.is_night());
+        environment.advance(500);
+        assert_eq!((environment.day(), environment.time_of_day()), (1, SUNSET));
+
+        // Stored light is dimmed by the time of day, block light isn't
+        environment.set_time_of_day(0);
+        assert_eq!(environment.day(), 1);
+        assert_eq!(environment.effective_light(Light { sky: MAX_LIGHT, block: 0 }), 4);
+        assert_eq!(environment.effective_light(Light { sky: MAX_LIGHT, block: 9 }), 9);
+
+        // Midnight is colder, more so in the tundra
+        let tundra = BiomeId::new(3);
+        environment.set_biome_temperature(tundra, Fixed::from_int(-20));
+        assert_eq!(environment.temperature(None), Fixed::from_int(7));
+        assert_eq!(environment.temperature(Some(tundra)), Fixed::from_int(-13));
+        environment.set_time_of_day(NOON);
+        assert_eq!(environment.temperature(Some(BiomeId::new(1))), Fixed::from_int(23));
+    }
+}
@@ -0,0 +1,190 @@
+///
+/// Factions and Ownership
+///
+/// Every hive belongs to a Faction. Entities carry an Ownership naming their
+/// Faction, and a Faction may claim whole Chunks. An actor may modify a Block
+/// in an unclaimed Chunk or one claimed by its own or an allied Faction, and
+/// may modify an entity which is unowned or owned by its own or an allied
+/// Faction. Actors without an Ownership may only touch unclaimed Blocks and
+/// unowned entities. A CPU acts with the permissions of its host entity.
+///
+use model::entity::{EntityID, EntityManager};
+use model::world::{chunk_of, Vector2};
+use std::collections::{HashMap, HashSet};
+
+///
+/// Compact Faction Identifier
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Default)]
+pub struct FactionId(u16);
+
+impl FactionId {
+    pub fn new(id: u16) -> FactionId { FactionId(id) }
+    pub fn id(&self) -> u16 { self.0 }
+    pub fn index(&self) -> usize { self.0 as usize }
+}
+
+///
+/// Faction Definition
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Faction {
+    name: String,
+}
+
+impl Faction {
+    pub fn new(name: &str) -> Faction { Faction { name: name.to_string() } }
+    pub fn name(&self) -> &str { &self.name }
+}
+
+///
+/// Faction an entity belongs to
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Ownership {
+    pub faction: FactionId,
+}
+
+impl Ownership {
+    pub fn new(faction: FactionId) -> Ownership { Ownership { faction } }
+}
+
+///
+/// Faction Registry, Alliances and Chunk Claims
+///
+#[derive(Clone, Default, Debug)]
+pub struct Factions {
+    factions: Vec<Faction>,
+    names: HashMap<String, FactionId>,
+    /// Allied pairs, smaller id first
+    alliances: HashSet<(FactionId, FactionId)>,
+    claims: HashMap<Vector2<u64>, FactionId>,
+}
+
+impl Factions {
+    pub fn new() -> Factions { Factions::default() }
+    ///
+    /// Register a Faction, returning its id. Registering a name that already
+    /// exists keeps the existing id.
+    ///
+    pub fn register(&mut self, faction: Faction) -> FactionId {
+        if let Some(&id) = self.names.get(faction.name()) {
+            self.factions[id.index()] = faction;
+            return id;
+        }
+        let id = FactionId(self.factions.len() as u16);
+        self.names.insert(faction.name().to_string(), id);
+        self.factions.push(faction);
+        id
+    }
+    pub fn id(&self, name: &str) -> Option<FactionId> { self.names.get(name).cloned() }
+    pub fn get(&self, id: FactionId) -> Option<&Faction> { self.factions.get(id.index()) }
+    pub fn len(&self) -> usize { self.factions.len() }
+    pub fn is_empty(&self) -> bool { self.factions.is_empty() }
+    /// Make two Factions allies, or end their alliance.
+    pub fn set_allied(&mut self, a: FactionId, b: FactionId, allied: bool) {
+        if a == b {
+            return;
+        }
+        let pair = (a.min(b), a.max(b));
+        if allied {
+            self.alliances.insert(pair);
+        } else {
+            self.alliances.remove(&pair);
+        }
+    }
+    /// Whether two Factions share permissions, a Faction is its own ally.
+    pub fn is_allied(&self, a: FactionId, b: FactionId) -> bool {
+        a == b || self.alliances.contains(&(a.min(b), a.max(b)))
+    }
+    /// Claim a Chunk for a Faction, returning the previous claimant.
+    pub fn claim(&mut self, chunk: Vector2<u64>, faction: FactionId) -> Option<FactionId> { self.claims.insert(chunk, faction) }
+    pub fn release(&mut self, chunk: Vector2<u64>) -> Option<FactionId> { self.claims.remove(&chunk) }
+    pub fn claimant(&self, chunk: Vector2<u64>) -> Option<FactionId> { self.claims.get(&chunk).cloned() }
+    /// Faction owning the Chunk a Block is in.
+    pub fn block_owner(&self, x: u64, z: u64) -> Option<FactionId> { self.claimant(chunk_of(x, z).0) }
+    /// Faction of an entity, None if it is unowned or dead.
+    pub fn faction_of(&self, entities: &EntityManager, entity: EntityID) -> Option<FactionId> {
+        if !entities.is_alive(entity) {
+            return None;
+        }
+        entities.get_component::<Ownership>(entity).map(|ownership| ownership.faction)
+    }
+    fn permits(&self, actor: Option<FactionId>, owner: Option<FactionId>) -> bool {
+        match (actor, owner) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(actor), Some(owner)) => self.is_allied(actor, owner),
+        }
+    }
+    ///
+    /// Whether `actor` may change Blocks in the column at (x, z).
+    ///
+    pub fn may_modify_block(&self, entities: &EntityManager, actor: EntityID, x: u64, z: u64) -> bool {
+        self.permits(self.faction_of(entities, actor), self.block_owner(x, z))
+    }
+    ///
+    /// Whether `actor` may change or remove `target`.
+    ///
+    pub fn may_modify_entity(&self, entities: &EntityManager, actor: EntityID, target: EntityID) -> bool {
+        actor == target || self.permits(self.faction_of(entities, actor), self.faction_of(entities, target))
+    }
+    ///
+    /// Whether a player in `faction`, None if in none, may command `target`.
+    ///
+    pub fn may_command(&self, entities: &EntityManager, faction: Option<FactionId>, target: EntityID) -> bool {
+        self.permits(faction, self.faction_of(entities, target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Faction, Factions, Ownership};
+    use model::entity::EntityManager;
+    use model::world::Vector2;
+
+    #[test]
+    pub fn test_faction_permissions() {
+        let mut factions = Factions::new();
+        let red = factions.register(Faction::new("red"));
+        let blue = factions.register(Faction::new("blue"));
+        let green = factions.register(Faction::new("green"));
+        assert_eq!(factions.register(Faction::new("red")), red);
+        assert_eq!(factions.len(), 3);
+
+        let mut entities = EntityManager::new();
+        let drone = entities.create_entity();
+        let rival = entities.create_entity();
+        let rock = entities.create_entity();
+        let stray = entities.create_entity();
+        entities.add_component(drone, Ownership::new(red));
+        entities.add_component(rival, Ownership::new(blue));
+        factions.claim(Vector2::new(1, 0), blue);
+
+        // Unclaimed Blocks and unowned entities are open to anyone
+        assert!(factions.may_modify_block(&entities, drone, 5, 5));
+        assert!(factions.may_modify_block(&entities, stray, 5, 5));
+        assert!(factions.may_modify_entity(&entities, drone, rock));
+        // Claims and owners keep others out
+        assert!(!factions.may_modify_block(&entities, drone, 40, 5));
+        assert!(!factions.may_modify_block(&entities, stray, 40, 5));
+        assert!(factions.may_modify_block(&entities, rival, 40, 5));
+        assert!(!factions.may_modify_entity(&entities, drone, rival));
+        assert!(!factions.may_modify_entity(&entities, stray, drone));
+        assert!(factions.may_modify_entity(&entities, stray, stray));
+
+        // Alliances share permissions both ways until broken
+        factions.set_allied(blue, red, true);
+        assert!(factions.is_allied(red, blue) && !factions.is_allied(red, green));
+        assert!(factions.may_modify_block(&entities, drone, 40, 5));
+        assert!(factions.may_modify_entity(&entities, rival, drone));
+        factions.set_allied(red, blue, false);
+        assert!(!factions.may_modify_entity(&entities, rival, drone));
+
+        // A dead actor has no faction left
+        entities.destroy_entity(rival);
+        assert_eq!(factions.faction_of(&entities, rival), None);
+        assert_eq!(factions.release(Vector2::new(1, 0)), Some(blue));
+        assert_eq!(factions.block_owner(40, 5), None);
+    }
+}
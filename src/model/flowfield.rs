@@ -0,0 +1,263 @@
+///
+/// Flow Fields
+///
+/// A FlowField is built once for a set of goals by searching outwards from them
+/// (Dijkstra over reversed moves), recording at every reachable Block its cost
+/// to the nearest goal and the next Block to step to. Any number of units can
+/// then follow it with a single lookup per step. Fields are stored per Chunk,
+/// and a Block change only invalidates fields which cover its Chunk or one
+/// beside it; invalid fields are rebuilt the next time they are asked for.
+///
+use model::pathfind::{standable, step_cost, MoveRules};
+use model::update::BlockPosition;
+use model::world::{chunk_of, Vector2, World, CHUNK_SIZE};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Default cost beyond which a field stops searching
+pub const DEFAULT_MAX_COST: u32 = 256;
+
+const UNREACHED: u32 = u32::MAX;
+
+fn index(x: usize, y: usize, z: usize) -> usize { (x * CHUNK_SIZE + y) * CHUNK_SIZE + z }
+
+///
+/// Costs and directions within one Chunk
+///
+#[derive(Clone, Debug)]
+struct ChunkFlow {
+    cost: Vec<u32>,
+    /// Offset to the next Block toward a goal
+    next: Vec<[i8; 3]>,
+}
+
+impl ChunkFlow {
+    fn new() -> ChunkFlow {
+        let size = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+        ChunkFlow { cost: vec![UNREACHED; size], next: vec![[0; 3]; size] }
+    }
+}
+
+///
+/// Directions toward a set of goals
+///
+#[derive(Clone, Debug)]
+pub struct FlowField {
+    goals: Vec<BlockPosition>,
+    rules: MoveRules,
+    max_cost: u32,
+    chunks: HashMap<Vector2<u64>, ChunkFlow>,
+    stale: bool,
+}
+
+impl FlowField {
+    /// Field for `goals`, which needs building before use.
+    pub fn new(goals: &[BlockPosition], rules: MoveRules, max_cost: u32) -> FlowField {
+        let mut goals = goals.to_vec();
+        goals.sort();
+        goals.dedup();
+        FlowField { goals, rules, max_cost, chunks: HashMap::new(), stale: true }
+    }
+    pub fn goals(&self) -> &[BlockPosition] { &self.goals }
+    /// Needs a rebuild before its directions can be trusted.
+    pub fn is_stale(&self) -> bool { self.stale }
+    /// Whether any Block in a Chunk was reached.
+    pub fn covers(&self, chunk: Vector2<u64>) -> bool { self.chunks.contains_key(&chunk) }
+    ///
+    /// Search out from the goals, replacing any previous result.
+    ///
+    pub fn build(&mut self, world: &World) {
+        self.chunks.clear();
+        self.stale = false;
+        let mut open = BinaryHeap::new();
+        for &goal in self.goals.clone().iter() {
+            if world.get_block(goal.0, goal.1, goal.2).is_some() {
+                self.set(goal, 0, [0; 3]);
+                open.push(Reverse((0, goal)));
+            }
+        }
+        while let Some(Reverse((cost, position))) = open.pop() {
+            if cost > self.cost(position).unwrap_or(UNREACHED) {
+                continue;
+            }
+            for from in self.predecessors(position) {
+                if !standable(world, from, &self.rules) {
+                    continue;
+                }
+                let step = match step_cost(world, from, position, &self.rules) {
+                    Some(step) => step,
+                    None => continue,
+                };
+                let total = cost + step;
+                if total <= self.max_cost && total < self.cost(from).unwrap_or(UNREACHED) {
+                    let offset = [
+                        (position.0 as i64 - from.0 as i64) as i8,
+                        (position.1 as i64 - from.1 as i64) as i8,
+                        (position.2 as i64 - from.2 as i64) as i8,
+                    ];
+                    self.set(from, total, offset);
+                    open.push(Reverse((total, from)));
+                }
+            }
+        }
+    }
+    /// Positions which may move into `to` in one step under the rules.
+    fn predecessors(&self, (x, y, z): BlockPosition) -> Vec<BlockPosition> {
+        let rules = &self.rules;
+        let vertical = rules.fly || rules.burrow.is_some();
+        let (below, above) = if vertical { (0, 0) } else { (rules.climb as i64, rules.drop as i64) };
+        let mut result = Vec::new();
+        for &(dx, dz) in [(1i64, 0i64), (-1, 0), (0, 1), (0, -1)].iter() {
+            let (fx, fz) = (x as i64 + dx, z as i64 + dz);
+            if fx < 0 || fz < 0 {
+                continue;
+            }
+            for dy in -below..above + 1 {
+                let fy = y as i64 + dy;
+                if fy >= 0 && fy < CHUNK_SIZE as i64 {
+                    result.push((fx as u64, fy as usize, fz as u64));
+                }
+            }
+        }
+        if vertical {
+            if y + 1 < CHUNK_SIZE {
+                result.push((x, y + 1, z));
+            }
+            if y > 0 {
+                result.push((x, y - 1, z));
+            }
+        }
+        result
+    }
+    fn set(&mut self, (x, y, z): BlockPosition, cost: u32, next: [i8; 3]) {
+        let (chunk, lx, lz) = chunk_of(x, z);
+        let flow = self.chunks.entry(chunk).or_insert_with(ChunkFlow::new);
+        flow.cost[index(lx, y, lz)] = cost;
+        flow.next[index(lx, y, lz)] = next;
+    }
+    /// Cost from a Block to the nearest goal, None if it wasn't reached.
+    pub fn cost(&self, (x, y, z): BlockPosition) -> Option<u32> {
+        if y >= CHUNK_SIZE {
+            return None;
+        }
+        let (chunk, lx, lz) = chunk_of(x, z);
+        self.chunks.get(&chunk).map(|flow| flow.cost[index(lx, y, lz)]).filter(|&cost| cost != UNREACHED)
+    }
+    /// Block to step to from `position`, None at a goal or if it wasn't reached.
+    pub fn next(&self, position: BlockPosition) -> Option<BlockPosition> {
+        match self.cost(position) {
+            Some(0) | None => None,
+            Some(_) => {
+                let (chunk, lx, lz) = chunk_of(position.0, position.2);
+                let offset = self.chunks[&chunk].next[index(lx, position.1, lz)];
+                Some((
+                    (position.0 as i64 + offset[0] as i64) as u64,
+                    (position.1 as i64 + offset[1] as i64) as usize,
+                    (position.2 as i64 + offset[2] as i64) as u64,
+                ))
+            }
+        }
+    }
+    ///
+    /// Note a Block change, marking the field stale if it covers the Block's
+    /// Chunk or one beside it. Returns whether the field was affected.
+    ///
+    pub fn invalidate(&mut self, (x, _, z): BlockPosition) -> bool {
+        let (chunk, _, _) = chunk_of(x, z);
+        let affected = self.goals.iter().any(|goal| chunk_of(goal.0, goal.2).0 == chunk) || (-1i64..2).any(|dx| (-1i64..2).any(|dz| {
+            let (cx, cz) = (chunk.x as i64 + dx, chunk.y as i64 + dz);
+            cx >= 0 && cz >= 0 && self.covers(Vector2::new(cx as u64, cz as u64))
+        }));
+        self.stale |= affected;
+        affected
+    }
+}
+
+///
+/// Shared Flow Fields keyed by goal set
+///
+#[derive(Clone, Debug)]
+pub struct FlowFields {
+    rules: MoveRules,
+    max_cost: u32,
+    fields: HashMap<Vec<BlockPosition>, FlowField>,
+}
+
+impl FlowFields {
+    pub fn new(rules: MoveRules) -> FlowFields { FlowFields::with_max_cost(rules, DEFAULT_MAX_COST) }
+    pub fn with_max_cost(rules: MoveRules, max_cost: u32) -> FlowFields {
+        FlowFields { rules, max_cost, fields: HashMap::new() }
+    }
+    pub fn len(&self) -> usize { self.fields.len() }
+    pub fn is_empty(&self) -> bool { self.fields.is_empty() }
+    /// Field toward `goals`, built or rebuilt if needed.
+    pub fn get(&mut self, world: &World, goals: &[BlockPosition]) -> &FlowField {
+        let (rules, max_cost) = (self.rules, self.max_cost);
+        let key = FlowField::new(goals, rules, max_cost).goals;
+        let field = self.fields.entry(key.clone()).or_insert_with(|| FlowField::new(&key, rules, max_cost));
+        if field.is_stale() {
+            field.build(world);
+        }
+        field
+    }
+    /// Drop the field toward `goals`.
+    pub fn remove(&mut self, goals: &[BlockPosition]) -> bool {
+        let key = FlowField::new(goals, self.rules, self.max_cost).goals;
+        self.fields.remove(&key).is_some()
+    }
+    /// Note a Block change, returning the number of fields invalidated.
+    pub fn block_changed(&mut self, position: BlockPosition) -> usize {
+        self.fields.values_mut().filter(|field| !field.is_stale()).map(|field| field.invalidate(position)).filter(|&hit| hit).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FlowFields;
+    use model::pathfind::MoveRules;
+    use model::world::{Block, Chunk, Vector2, World};
+    use pool::Poolable;
+
+    #[test]
+    pub fn test_flow_field() {
+        let mut world = World::new();
+        for cx in 0..4 {
+            world.insert_chunk(Vector2::new(cx, 0), Chunk::allocate());
+        }
+        let rock = Block::new(world.materials().id("rock").unwrap());
+        for x in 0..64 {
+            for z in 0..16 {
+                world.set_block(x, 0, z, rock);
+            }
+        }
+        // A wall across the floor with a gap at z = 15
+        for z in 0..15 {
+            world.set_block(8, 1, z, rock);
+            world.set_block(8, 2, z, rock);
+        }
+
+        let mut fields = FlowFields::new(MoveRules::walker());
+        let goal = (12, 1, 0);
+        let field = fields.get(&world, &[goal]);
+        assert_eq!(field.cost(goal), Some(0));
+        assert_eq!(field.next(goal), None);
+        // Round the end of the wall: 4 + 15 + 15 + 4 steps
+        assert_eq!(field.cost((4, 1, 0)), Some(38));
+        let mut position = (4, 1, 0);
+        let mut steps = 0;
+        while let Some(next) = field.next(position) {
+            position = next;
+            steps += 1;
+        }
+        assert_eq!((position, steps), (goal, 38));
+        assert!(!field.covers(Vector2::new(3, 0)));
+
+        // Changes far away are ignored, a hole in the wall opens a shortcut
+        assert_eq!(fields.block_changed((120, 1, 0)), 0);
+        world.set_block(8, 1, 0, Block::default());
+        world.set_block(8, 2, 0, Block::default());
+        assert_eq!(fields.block_changed((8, 1, 0)), 1);
+        assert_eq!(fields.get(&world, &[goal]).cost((4, 1, 0)), Some(8));
+        assert_eq!(fields.len(), 1);
+    }
+}
@@ -0,0 +1,96 @@
+///
+/// Heightmaps
+///
+/// The highest Block and the highest solid Block of every column of a
+/// Chunk, so finding the ground under a point costs a lookup rather than a
+/// scan down the column. The World keeps one per loaded Chunk, rescanning a
+/// column whenever a Block in it changes. Solid means a material with any
+/// resistance, as for World::is_solid.
+///
+use model::material::MaterialRegistry;
+use model::world::{Chunk, CHUNK_SIZE};
+
+/// Heights of one column, each the Block's y plus one so 0 is an empty column
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+struct Column {
+    top: u8,
+    solid: u8,
+}
+
+///
+/// Column heights of one Chunk
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Heightmap {
+    columns: [[Column; CHUNK_SIZE]; CHUNK_SIZE],
+}
+
+impl Heightmap {
+    pub fn build(chunk: &Chunk, materials: &MaterialRegistry) -> Heightmap {
+        let mut heightmap = Heightmap { columns: [[Column::default(); CHUNK_SIZE]; CHUNK_SIZE] };
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                heightmap.update(chunk, materials, x, z);
+            }
+        }
+        heightmap
+    }
+    /// Rescan a column of `chunk` after a Block in it changed.
+    pub fn update(&mut self, chunk: &Chunk, materials: &MaterialRegistry, x: usize, z: usize) {
+        let mut column = Column::default();
+        for y in (0..CHUNK_SIZE).rev() {
+            let block = chunk.get_block(x, y, z);
+            if block.is_air() {
+                continue;
+            }
+            if column.top == 0 {
+                column.top = y as u8 + 1;
+            }
+            if materials.get(block.material()).is_some_and(|material| material.resistance() > 0.0) {
+                column.solid = y as u8 + 1;
+                break;
+            }
+        }
+        self.columns[x][z] = column;
+    }
+    /// Highest Block which isn't air, None for an empty column.
+    pub fn topmost(&self, x: usize, z: usize) -> Option<usize> { (self.columns[x][z].top as usize).checked_sub(1) }
+    /// Highest solid Block, None if nothing in the column would bear weight.
+    pub fn topmost_solid(&self, x: usize, z: usize) -> Option<usize> { (self.columns[x][z].solid as usize).checked_sub(1) }
+    /// Lowest y with nothing but air from there up.
+    pub fn surface_height(&self, x: usize, z: usize) -> usize { self.columns[x][z].top as usize }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Heightmap;
+    use model::material::{Material, AIR};
+    use model::world::{Block, Chunk, Vector2, World, CHUNK_SIZE};
+
+    #[test]
+    pub fn test_heightmap() {
+        let mut world = World::new();
+        let rock = Block::new(world.materials().id("rock").unwrap());
+        let mist = Block::new(world.materials_mut().register(Material::new("mist", 0.0, 0.1)));
+        let mut chunk = Box::new(Chunk::new());
+        chunk.set_block(1, 3, 2, rock);
+        chunk.set_block(1, 5, 2, mist);
+        let heightmap = Heightmap::build(&chunk, world.materials());
+        assert_eq!((heightmap.topmost(1, 2), heightmap.topmost_solid(1, 2), heightmap.surface_height(1, 2)), (Some(5), Some(3), 6));
+        assert_eq!((heightmap.topmost(0, 0), heightmap.surface_height(0, 0)), (None, 0));
+
+        // The World keeps its heightmaps up to date with every Block set
+        world.insert_chunk(Vector2::new(1, 0), chunk);
+        let x = CHUNK_SIZE as u64 + 1;
+        assert_eq!((world.surface_height(x, 2), world.topmost_solid(x, 2)), (Some(6), Some(3)));
+        world.set_block(x, 10, 2, rock);
+        assert_eq!((world.surface_height(x, 2), world.topmost_solid(x, 2)), (Some(11), Some(10)));
+        world.set_block(x, 10, 2, Block::new(AIR));
+        world.set_block(x, 3, 2, Block::new(AIR));
+        assert_eq!((world.surface_height(x, 2), world.topmost_solid(x, 2)), (Some(6), None));
+        assert_eq!(world.heightmap(Vector2::new(1, 0)).unwrap().topmost(1, 2), Some(5));
+        world.unload_chunk(Vector2::new(1, 0)).unwrap();
+        assert_eq!(world.surface_height(x, 2), None);
+        assert!(world.heightmap(Vector2::new(1, 0)).is_none());
+    }
+}
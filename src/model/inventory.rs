@@ -0,0 +1,212 @@
+///
+/// Inventories
+///
+/// An Inventory is a fixed number of slots, each empty or holding one stack of
+/// a single Item no larger than that Item's max stack. Inserting tops up
+/// existing stacks before filling empty slots. Every slot change is recorded as
+/// an event which systems drain once per tick.
+///
+use model::entity::{EntityID, EntityManager};
+use model::item::{ItemId, ItemRegistry};
+
+///
+/// Stack of one Item
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ItemStack {
+    pub item: ItemId,
+    pub count: u32,
+}
+
+impl ItemStack {
+    pub fn new(item: ItemId, count: u32) -> ItemStack { ItemStack { item, count } }
+}
+
+///
+/// Slot Change Event
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct InventoryEvent {
+    pub slot: usize,
+    pub before: Option<ItemStack>,
+    pub after: Option<ItemStack>,
+}
+
+///
+/// Slotted Item Storage Component
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Inventory {
+    slots: Vec<Option<ItemStack>>,
+    events: Vec<InventoryEvent>,
+}
+
+impl Inventory {
+    pub fn new(size: usize) -> Inventory {
+        Inventory { slots: vec![None; size], events: Vec::new() }
+    }
+    /// Number of slots.
+    pub fn size(&self) -> usize { self.slots.len() }
+    pub fn slot(&self, slot: usize) -> Option<ItemStack> { self.slots.get(slot).cloned().unwrap_or(None) }
+    pub fn slots(&self) -> &[Option<ItemStack>] { &self.slots }
+    pub fn is_empty(&self) -> bool { self.slots.iter().all(Option::is_none) }
+    /// Total count of an Item across every slot.
+    pub fn count(&self, item: ItemId) -> u32 {
+        self.slots.iter().flatten().filter(|stack| stack.item == item).map(|stack| stack.count).sum()
+    }
+    /// How many more of an Item would fit.
+    pub fn space_for(&self, item: ItemId, items: &ItemRegistry) -> u32 {
+        let max = items.max_stack(item);
+        self.slots.iter().map(|slot| match *slot {
+            Some(stack) if stack.item == item => max.saturating_sub(stack.count),
+            Some(_) => 0,
+            None => max,
+        }).sum()
+    }
+    fn replace(&mut self, slot: usize, after: Option<ItemStack>) {
+        let before = self.slots[slot];
+        if before != after {
+            self.slots[slot] = after;
+            self.events.push(InventoryEvent { slot, before, after });
+        }
+    }
+    ///
+    /// Add as much of a stack as fits, returning the count left over.
+    ///
+    pub fn insert(&mut self, stack: ItemStack, items: &ItemRegistry) -> u32 {
+        let max = items.max_stack(stack.item);
+        let mut remaining = stack.count;
+        for slot in 0..self.slots.len() {
+            if remaining == 0 {
+                break;
+            }
+            if let Some(existing) = self.slots[slot].filter(|existing| existing.item == stack.item && existing.count < max) {
+                let moved = remaining.min(max - existing.count);
+                remaining -= moved;
+                self.replace(slot, Some(ItemStack::new(stack.item, existing.count + moved)));
+            }
+        }
+        for slot in 0..self.slots.len() {
+            if remaining == 0 {
+                break;
+            }
+            if self.slots[slot].is_none() {
+                let moved = remaining.min(max);
+                remaining -= moved;
+                self.replace(slot, Some(ItemStack::new(stack.item, moved)));
+            }
+        }
+        remaining
+    }
+    ///
+    /// Remove up to `count` of an Item, taking from the last slots first.
+    /// Returns the count removed.
+    ///
+    pub fn remove(&mut self, item: ItemId, count: u32) -> u32 {
+        let mut removed = 0;
+        for slot in (0..self.slots.len()).rev() {
+            if removed == count {
+                break;
+            }
+            if let Some(existing) = self.slots[slot].filter(|existing| existing.item == item) {
+                let taken = existing.count.min(count - removed);
+                removed += taken;
+                let left = existing.count - taken;
+                self.replace(slot, if left == 0 { None } else { Some(ItemStack::new(item, left)) });
+            }
+        }
+        removed
+    }
+    /// Empty a slot, returning what it held.
+    pub fn take_slot(&mut self, slot: usize) -> Option<ItemStack> {
+        let stack = self.slot(slot);
+        if stack.is_some() {
+            self.replace(slot, None);
+        }
+        stack
+    }
+    /// Exchange the contents of two slots.
+    pub fn swap(&mut self, a: usize, b: usize) {
+        if a < self.slots.len() && b < self.slots.len() && a != b {
+            let (first, second) = (self.slots[a], self.slots[b]);
+            self.replace(a, second);
+            self.replace(b, first);
+        }
+    }
+    ///
+    /// Move up to `count` of an Item into another Inventory, limited by the
+    /// space there. Returns the count moved.
+    ///
+    pub fn transfer(&mut self, to: &mut Inventory, item: ItemId, count: u32, items: &ItemRegistry) -> u32 {
+        let count = count.min(self.count(item)).min(to.space_for(item, items));
+        let moved = self.remove(item, count);
+        let left = to.insert(ItemStack::new(item, moved), items);
+        debug_assert_eq!(left, 0);
+        moved
+    }
+    /// Slot changes since the last drain, oldest first.
+    pub fn events(&self) -> &[InventoryEvent] { &self.events }
+    pub fn drain_events(&mut self) -> Vec<InventoryEvent> { self.events.drain(..).collect() }
+}
+
+///
+/// Move up to `count` of an Item between the Inventories of two entities,
+/// returning the count moved. Nothing moves unless both have an Inventory.
+///
+pub fn transfer_between(entities: &mut EntityManager, from: EntityID, to: EntityID, item: ItemId, count: u32, items: &ItemRegistry) -> u32 {
+    if from == to {
+        return 0;
+    }
+    let mut source = match entities.remove_component::<Inventory>(from) {
+        Some(inventory) => inventory,
+        None => return 0,
+    };
+    let moved = match entities.get_component_mut::<Inventory>(to) {
+        Some(target) => source.transfer(target, item, count, items),
+        None => 0,
+    };
+    entities.add_component(from, source);
+    moved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{transfer_between, Inventory, InventoryEvent, ItemStack};
+    use model::entity::EntityManager;
+    use model::item::{Item, ItemRegistry};
+    use model::material::MaterialRegistry;
+
+    #[test]
+    pub fn test_inventory_stacking_and_transfer() {
+        let materials = MaterialRegistry::default();
+        let mut items = ItemRegistry::from_materials(&materials);
+        let rock = items.for_material(materials.id("rock").unwrap()).unwrap();
+        let core = items.register(Item::new("core", 1));
+        assert_eq!(items.by_name("rock").map(|item| item.max_stack()), Some(64));
+
+        let mut inventory = Inventory::new(3);
+        assert_eq!(inventory.insert(ItemStack::new(rock, 100), &items), 0);
+        assert_eq!(inventory.insert(ItemStack::new(core, 2), &items), 1);
+        assert_eq!(inventory.slots(), &[Some(ItemStack::new(rock, 64)), Some(ItemStack::new(rock, 36)), Some(ItemStack::new(core, 1))]);
+        // Tops up the partial stack before giving up
+        assert_eq!(inventory.insert(ItemStack::new(rock, 30), &items), 2);
+        assert_eq!(inventory.count(rock), 128);
+        assert_eq!(inventory.drain_events().len(), 4);
+
+        assert_eq!(inventory.remove(rock, 70), 70);
+        assert_eq!(inventory.events(), &[
+            InventoryEvent { slot: 1, before: Some(ItemStack::new(rock, 64)), after: None },
+            InventoryEvent { slot: 0, before: Some(ItemStack::new(rock, 64)), after: Some(ItemStack::new(rock, 58)) },
+        ]);
+
+        let mut entities = EntityManager::new();
+        let drone = entities.create_entity();
+        let hopper = entities.create_entity();
+        entities.add_component(drone, inventory);
+        entities.add_component(hopper, Inventory::new(1));
+        assert_eq!(transfer_between(&mut entities, drone, hopper, rock, 100, &items), 58);
+        assert_eq!(transfer_between(&mut entities, drone, hopper, core, 1, &items), 0);
+        assert_eq!(entities.get_component::<Inventory>(hopper).unwrap().count(rock), 58);
+        assert_eq!(entities.get_component::<Inventory>(drone).unwrap().slots(), &[None, None, Some(ItemStack::new(core, 1))]);
+    }
+}
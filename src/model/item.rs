@@ -0,0 +1,106 @@
+///
+/// Item Registry
+///
+/// Items are interned by name into compact ids the same way Materials are, so
+/// an ItemStack only carries an ItemId and a count. Every solid Material has a
+/// matching Item of the same name so harvested Blocks can be carried.
+///
+use model::material::{MaterialId, MaterialRegistry, AIR};
+use std::collections::HashMap;
+
+/// Stack size for Items which don't specify one
+pub const DEFAULT_MAX_STACK: u32 = 64;
+
+///
+/// Compact Item Identifier
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Default)]
+pub struct ItemId(u16);
+
+impl ItemId {
+    pub fn new(id: u16) -> ItemId { ItemId(id) }
+    pub fn id(&self) -> u16 { self.0 }
+    pub fn index(&self) -> usize { self.0 as usize }
+}
+
+///
+/// Item Definition
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct Item {
+    name: String,
+    max_stack: u32,
+    /// Material placed when the Item is used as a Block
+    material: Option<MaterialId>,
+}
+
+impl Item {
+    pub fn new(name: &str, max_stack: u32) -> Item {
+        Item { name: name.to_string(), max_stack: max_stack.max(1), material: None }
+    }
+    /// Item which places `material` as a Block.
+    pub fn block(name: &str, material: MaterialId) -> Item {
+        Item { material: Some(material), ..Item::new(name, DEFAULT_MAX_STACK) }
+    }
+    pub fn name(&self) -> &str { &self.name }
+    pub fn max_stack(&self) -> u32 { self.max_stack }
+    pub fn material(&self) -> Option<MaterialId> { self.material }
+}
+
+///
+/// Item Registry
+///
+#[derive(Clone, Default, Debug)]
+pub struct ItemRegistry {
+    items: Vec<Item>,
+    names: HashMap<String, ItemId>,
+    blocks: HashMap<MaterialId, ItemId>,
+}
+
+impl ItemRegistry {
+    pub fn new() -> ItemRegistry { ItemRegistry::default() }
+    /// Registry with a block Item for every Material except air.
+    pub fn from_materials(materials: &MaterialRegistry) -> ItemRegistry {
+        let mut registry = ItemRegistry::new();
+        for (id, material) in materials.iter().filter(|&(id, _)| id != AIR) {
+            registry.register(Item::block(material.name(), id));
+        }
+        registry
+    }
+    ///
+    /// Register an Item, returning its id. Registering a name that already
+    /// exists replaces its definition and keeps the existing id.
+    ///
+    pub fn register(&mut self, item: Item) -> ItemId {
+        if let Some(&id) = self.names.get(item.name()) {
+            if let Some(material) = self.items[id.index()].material {
+                self.blocks.remove(&material);
+            }
+            if let Some(material) = item.material {
+                self.blocks.insert(material, id);
+            }
+            self.items[id.index()] = item;
+            return id;
+        }
+        assert!(self.items.len() <= u16::MAX as usize, "Item Registry is full");
+        let id = ItemId(self.items.len() as u16);
+        self.names.insert(item.name().to_string(), id);
+        if let Some(material) = item.material {
+            self.blocks.insert(material, id);
+        }
+        self.items.push(item);
+        id
+    }
+    pub fn id(&self, name: &str) -> Option<ItemId> { self.names.get(name).cloned() }
+    pub fn get(&self, id: ItemId) -> Option<&Item> { self.items.get(id.index()) }
+    pub fn by_name(&self, name: &str) -> Option<&Item> { self.id(name).and_then(|id| self.get(id)) }
+    /// Item dropped by a Block of `material`.
+    pub fn for_material(&self, material: MaterialId) -> Option<ItemId> { self.blocks.get(&material).cloned() }
+    /// Largest stack of an Item, unknown Items don't stack.
+    pub fn max_stack(&self, id: ItemId) -> u32 { self.get(id).map_or(1, |item| item.max_stack) }
+    pub fn len(&self) -> usize { self.items.len() }
+    pub fn is_empty(&self) -> bool { self.items.is_empty() }
+    pub fn iter(&self) -> impl Iterator<Item=(ItemId, &Item)> {
+        self.items.iter().enumerate().map(|(idx, item)| (ItemId(idx as u16), item))
+    }
+}
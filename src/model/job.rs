@@ -0,0 +1,384 @@
+///
+/// Job Board
+///
+/// Labor in the hive is allocated through a board of Tasks kept as a
+/// resource: mine a Block, haul items from one place to another, or build
+/// one cell of a schematic. Tasks are offered highest priority first, oldest
+/// first among equals, and a worker claims one at a time. Its entity is then
+/// AssignedTo the Task, so `EntityManager::related` finds who is working on
+/// what.
+///
+/// A claim lasts `timeout` ticks unless renewed, after which the Task goes
+/// back on the board for another worker; a drone which dies or wanders off
+/// holding a Task doesn't keep it forever. The board's clock is advanced
+/// once per tick by `expire`.
+///
+/// Behavior trees reach the board through the Actions `register_actions`
+/// binds, and firmware through the WorldInterface's CLAIM, TASK, DONE and
+/// RELEASE commands.
+///
+use model::behavior::{Behaviors, Status};
+use model::edit::WorldEdit;
+use model::entity::{EntityID, EntityManager, Relation};
+use model::item::ItemId;
+use model::material::MaterialId;
+use model::structure::{Placement, Structure, StructureError};
+use model::update::BlockPosition;
+use model::world::World;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Default ticks a claim lasts without being renewed
+pub const DEFAULT_CLAIM_TIMEOUT: u64 = 200;
+
+/// Kind codes reported to firmware
+pub const KIND_MINE: u16 = 1;
+pub const KIND_HAUL: u16 = 2;
+pub const KIND_BUILD: u16 = 3;
+
+///
+/// Task Identifier
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct TaskId(u32);
+
+impl TaskId {
+    pub fn id(&self) -> u32 { self.0 }
+}
+
+///
+/// Work to be done
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TaskKind {
+    /// Break the Block
+    Mine(BlockPosition),
+    /// Carry items from one Block to another
+    Haul { item: ItemId, count: u16, from: BlockPosition, to: BlockPosition },
+    /// Place a Block of the material into air
+    Build { position: BlockPosition, material: MaterialId },
+}
+
+impl TaskKind {
+    pub fn code(&self) -> u16 {
+        match *self {
+            TaskKind::Mine(_) => KIND_MINE,
+            TaskKind::Haul { .. } => KIND_HAUL,
+            TaskKind::Build { .. } => KIND_BUILD,
+        }
+    }
+    /// Block the work starts at, where a Haul picks up.
+    pub fn position(&self) -> BlockPosition {
+        match *self {
+            TaskKind::Mine(position) | TaskKind::Build { position, .. } => position,
+            TaskKind::Haul { from, .. } => from,
+        }
+    }
+}
+
+///
+/// Task on the board
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Task {
+    pub kind: TaskKind,
+    /// Higher is offered first
+    pub priority: u16,
+}
+
+///
+/// Relation Component, the Task a worker has claimed
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct AssignedTo(pub TaskId);
+
+impl Relation for AssignedTo {
+    type Target = TaskId;
+    fn target(&self) -> TaskId { self.0 }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct Claim {
+    worker: EntityID,
+    /// Tick the claim lapses at
+    expires: u64,
+}
+
+///
+/// Job Board resource
+///
+#[derive(Clone, Debug)]
+pub struct JobBoard {
+    next: u32,
+    now: u64,
+    timeout: u64,
+    tasks: BTreeMap<TaskId, Task>,
+    /// Unclaimed Tasks in the order they are offered
+    open: BTreeSet<(Reverse<u16>, TaskId)>,
+    claims: BTreeMap<TaskId, Claim>,
+}
+
+impl JobBoard {
+    pub fn new() -> JobBoard { JobBoard::with_timeout(DEFAULT_CLAIM_TIMEOUT) }
+    pub fn with_timeout(timeout: u64) -> JobBoard {
+        JobBoard { next: 0, now: 0, timeout, tasks: BTreeMap::new(), open: BTreeSet::new(), claims: BTreeMap::new() }
+    }
+    pub fn timeout(&self) -> u64 { self.timeout }
+    /// Ticks the board has been advanced.
+    pub fn now(&self) -> u64 { self.now }
+    pub fn post(&mut self, kind: TaskKind, priority: u16) -> TaskId {
+        let id = TaskId(self.next);
+        self.next += 1;
+        self.tasks.insert(id, Task { kind, priority });
+        self.open.insert((Reverse(priority), id));
+        id
+    }
+    ///
+    /// Post the work an edit would do to the World, a Build for each Block
+    /// it places and a Mine for each it clears, skipping those already done.
+    ///
+    pub fn post_edit(&mut self, world: &World, edit: &WorldEdit, priority: u16) -> Vec<TaskId> {
+        let mut posted = Vec::new();
+        for &((x, y, z), block) in edit.changes().iter() {
+            let existing = world.get_block(x, y, z);
+            if existing == Some(block) || (block.is_air() && existing.is_none_or(|existing| existing.is_air())) {
+                continue;
+            }
+            let kind = match block.is_air() {
+                true => TaskKind::Mine((x, y, z)),
+                false => TaskKind::Build { position: (x, y, z), material: block.material() },
+            };
+            posted.push(self.post(kind, priority));
+        }
+        posted
+    }
+    /// Post the cells of a schematic still to be built at `origin`, see `post_edit`.
+    pub fn post_structure(&mut self, world: &World, structure: &Structure, origin: BlockPosition, placement: &Placement, priority: u16) -> Result<Vec<TaskId>, StructureError> {
+        let edit = structure.to_edit(world.materials(), origin, placement)?;
+        Ok(self.post_edit(world, &edit, priority))
+    }
+    /// Take a Task off the board, claimed or not.
+    pub fn cancel(&mut self, id: TaskId) -> Option<Task> {
+        let task = self.tasks.remove(&id)?;
+        self.open.remove(&(Reverse(task.priority), id));
+        self.claims.remove(&id);
+        Some(task)
+    }
+    pub fn task(&self, id: TaskId) -> Option<&Task> { self.tasks.get(&id) }
+    /// Worker holding a Task, if claimed.
+    pub fn worker(&self, id: TaskId) -> Option<EntityID> { self.claims.get(&id).map(|claim| claim.worker) }
+    /// Unclaimed Tasks in the order they are offered.
+    pub fn open(&self) -> impl Iterator<Item=TaskId> + '_ { self.open.iter().map(|&(_, id)| id) }
+    pub fn open_len(&self) -> usize { self.open.len() }
+    pub fn claimed_len(&self) -> usize { self.claims.len() }
+    pub fn len(&self) -> usize { self.tasks.len() }
+    pub fn is_empty(&self) -> bool { self.tasks.is_empty() }
+    /// Claim the first open Task `accept` takes for `worker`.
+    pub fn claim(&mut self, worker: EntityID, accept: &dyn Fn(&Task) -> bool) -> Option<TaskId> {
+        let id = self.open().find(|id| accept(&self.tasks[id]))?;
+        self.claim_task(id, worker);
+        Some(id)
+    }
+    /// Claim a particular Task, false unless it is open.
+    pub fn claim_task(&mut self, id: TaskId, worker: EntityID) -> bool {
+        match self.tasks.get(&id) {
+            Some(task) if self.open.remove(&(Reverse(task.priority), id)) => {
+                self.claims.insert(id, Claim { worker, expires: self.now + self.timeout });
+                true
+            }
+            _ => false,
+        }
+    }
+    /// Restart the timeout of a claim the worker holds.
+    pub fn renew(&mut self, id: TaskId, worker: EntityID) -> bool {
+        match self.claims.get_mut(&id) {
+            Some(claim) if claim.worker == worker => {
+                claim.expires = self.now + self.timeout;
+                true
+            }
+            _ => false,
+        }
+    }
+    /// Put a Task the worker holds back on the board.
+    pub fn release(&mut self, id: TaskId, worker: EntityID) -> bool {
+        if self.worker(id) != Some(worker) {
+            return false;
+        }
+        self.claims.remove(&id);
+        self.open.insert((Reverse(self.tasks[&id].priority), id));
+        true
+    }
+    /// Take a Task the worker holds off the board as done.
+    pub fn complete(&mut self, id: TaskId, worker: EntityID) -> Option<Task> {
+        if self.worker(id) != Some(worker) {
+            return None;
+        }
+        self.cancel(id)
+    }
+    ///
+    /// Move the clock on, putting the Tasks whose claims lapsed back on the
+    /// board and returning them with the workers which held them.
+    ///
+    pub fn advance(&mut self, ticks: u64) -> Vec<(TaskId, EntityID)> {
+        self.now += ticks;
+        let now = self.now;
+        let lapsed: Vec<(TaskId, EntityID)> = self.claims.iter()
+            .filter(|&(_, claim)| claim.expires <= now)
+            .map(|(&id, claim)| (id, claim.worker))
+            .collect();
+        for &(id, worker) in lapsed.iter() {
+            self.release(id, worker);
+        }
+        lapsed
+    }
+}
+
+impl Default for JobBoard {
+    fn default() -> JobBoard { JobBoard::new() }
+}
+
+///
+/// Task a worker holds on the board resource. An AssignedTo the board no
+/// longer agrees with, the Task cancelled or its claim lapsed, is removed.
+///
+pub fn held(entities: &mut EntityManager, worker: EntityID) -> Option<TaskId> {
+    let id = entities.get_component::<AssignedTo>(worker)?.0;
+    if entities.resource::<JobBoard>().is_some_and(|board| board.worker(id) == Some(worker)) {
+        return Some(id);
+    }
+    entities.remove_component::<AssignedTo>(worker);
+    None
+}
+
+///
+/// Claim the first open Task `accept` takes for a worker which holds none,
+/// or keep the one it holds, renewing its claim.
+///
+pub fn claim(entities: &mut EntityManager, worker: EntityID, accept: &dyn Fn(&Task) -> bool) -> Option<TaskId> {
+    if let Some(id) = held(entities, worker) {
+        renew(entities, worker);
+        return Some(id);
+    }
+    if !entities.is_alive(worker) {
+        return None;
+    }
+    let id = entities.resource_mut::<JobBoard>()?.claim(worker, accept)?;
+    entities.add_component(worker, AssignedTo(id));
+    Some(id)
+}
+
+/// Restart the timeout of the worker's claim, false if it holds none.
+pub fn renew(entities: &mut EntityManager, worker: EntityID) -> bool {
+    match held(entities, worker) {
+        Some(id) => entities.resource_mut::<JobBoard>().is_some_and(|board| board.renew(id, worker)),
+        None => false,
+    }
+}
+
+/// Put the worker's Task back on the board.
+pub fn release(entities: &mut EntityManager, worker: EntityID) -> Option<TaskId> {
+    let id = held(entities, worker)?;
+    entities.remove_component::<AssignedTo>(worker);
+    entities.resource_mut::<JobBoard>()?.release(id, worker);
+    Some(id)
+}
+
+/// Take the worker's Task off the board as done.
+pub fn complete(entities: &mut EntityManager, worker: EntityID) -> Option<Task> {
+    let id = held(entities, worker)?;
+    entities.remove_component::<AssignedTo>(worker);
+    entities.resource_mut::<JobBoard>()?.complete(id, worker)
+}
+
+///
+/// Advance the board resource a tick, unassigning the workers whose claims
+/// lapsed. Returns them with the Tasks they lost.
+///
+pub fn expire(entities: &mut EntityManager) -> Vec<(TaskId, EntityID)> {
+    let lapsed = match entities.resource_mut::<JobBoard>() {
+        Some(board) => board.advance(1),
+        None => return Vec::new(),
+    };
+    for &(id, worker) in lapsed.iter() {
+        if entities.get_component::<AssignedTo>(worker) == Some(&AssignedTo(id)) {
+            entities.remove_component::<AssignedTo>(worker);
+        }
+    }
+    lapsed
+}
+
+///
+/// Bind the job Actions for behavior trees:
+///
+///   claim_task    claim a Task, or renew the one held; fails if none is open
+///   has_task      succeeds while a Task is held
+///   complete_task the held Task is done; fails if none is held
+///   release_task  put the held Task back; fails if none is held
+///
+pub fn register_actions(behaviors: &mut Behaviors) {
+    let status = |success: bool| if success { Status::Success } else { Status::Failure };
+    behaviors.register_action("claim_task", Box::new(move |entity, entities, _| status(claim(entities, entity, &|_| true).is_some())));
+    behaviors.register_action("has_task", Box::new(move |entity, entities, _| status(held(entities, entity).is_some())));
+    behaviors.register_action("complete_task", Box::new(move |entity, entities, _| status(complete(entities, entity).is_some())));
+    behaviors.register_action("release_task", Box::new(move |entity, entities, _| status(release(entities, entity).is_some())));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{claim, complete, expire, held, register_actions, AssignedTo, JobBoard, TaskKind, KIND_BUILD};
+    use model::behavior::{Behavior, Behaviors, Node, Status};
+    use model::edit::WorldEdit;
+    use model::entity::EntityManager;
+    use model::item::ItemId;
+    use model::world::{Block, Chunk, Vector2, World};
+    use pool::Poolable;
+
+    #[test]
+    pub fn test_job_board() {
+        let mut world = World::new();
+        world.insert_chunk(Vector2::new(0, 0), Chunk::allocate());
+        let rock = world.materials().id("rock").unwrap();
+        world.set_block(1, 1, 1, Block::new(rock));
+        world.set_block(2, 1, 1, Block::new(rock));
+
+        // Only the work left to do is posted, highest priority first
+        let mut board = JobBoard::with_timeout(3);
+        let mut edit = WorldEdit::new();
+        edit.set(1, 1, 1, Block::new(rock)).set(2, 1, 1, Block::default()).set(3, 1, 1, Block::new(rock)).set(4, 1, 1, Block::default());
+        let posted = board.post_edit(&world, &edit, 1);
+        assert_eq!(posted.iter().map(|id| board.task(*id).unwrap().kind).collect::<Vec<_>>(), vec![TaskKind::Mine((2, 1, 1)), TaskKind::Build { position: (3, 1, 1), material: rock }]);
+        let haul = board.post(TaskKind::Haul { item: ItemId::new(4), count: 8, from: (0, 1, 0), to: (5, 1, 5) }, 9);
+        assert_eq!(board.open().collect::<Vec<_>>(), vec![haul, posted[0], posted[1]]);
+
+        let mut entities = EntityManager::new();
+        entities.register_relation::<AssignedTo>();
+        entities.insert_resource(board);
+        let (hauler, builder) = (entities.create_entity(), entities.create_entity());
+        assert_eq!(claim(&mut entities, hauler, &|_| true), Some(haul));
+        assert_eq!(claim(&mut entities, builder, &|task| task.kind.code() == KIND_BUILD), Some(posted[1]));
+        assert_eq!(entities.related::<AssignedTo>(&posted[1]), vec![builder]);
+        assert_eq!(complete(&mut entities, builder).map(|task| task.kind.code()), Some(KIND_BUILD));
+
+        // The hauler's claim lapses unless renewed
+        expire(&mut entities);
+        expire(&mut entities);
+        assert_eq!(claim(&mut entities, hauler, &|_| true), Some(haul));
+        expire(&mut entities);
+        assert!(expire(&mut entities).is_empty());
+        assert_eq!(expire(&mut entities).len(), 1);
+        assert!(held(&mut entities, hauler).is_none() && entities.related::<AssignedTo>(&haul).is_empty());
+        assert_eq!(entities.resource::<JobBoard>().unwrap().open_len(), 2);
+
+        // Drones claim through their behavior trees
+        let mut behaviors = Behaviors::new();
+        register_actions(&mut behaviors);
+        behaviors.register_tree("worker", Node::Sequence(vec![Node::Action("claim_task".to_string()), Node::Action("complete_task".to_string())]));
+        for drone in [hauler, builder].iter() {
+            entities.add_component(*drone, Behavior::new("worker"));
+        }
+        let statuses: Vec<Status> = behaviors.tick(&mut world, &mut entities).into_iter().map(|(_, status)| status).collect();
+        assert_eq!(statuses, vec![Status::Success, Status::Success]);
+        assert!(entities.resource::<JobBoard>().unwrap().is_empty());
+        assert_eq!(behaviors.tick(&mut world, &mut entities)[0].1, Status::Failure);
+    }
+}
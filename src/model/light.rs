@@ -0,0 +1,350 @@
+///
+/// Light Propagation
+///
+/// Two channels are tracked per Block: sky light, which falls straight down
+/// from the top of the world, and block light from point emitters. Both spread
+/// by flood fill, losing one level per Block plus more through translucent
+/// materials; fully opaque materials stop light entirely.
+///
+/// Changes are batched: editing a Chunk marks it dirty and `update` relights
+/// the dirty Chunks together with their loaded neighbours. Light travels at most
+/// MAX_LIGHT Blocks, less than a Chunk, so nothing further away can be affected.
+///
+use model::material::{MaterialRegistry, AIR};
+use model::update::BlockPosition;
+use model::world::{chunk_of, Vector2, World, CHUNK_SIZE};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+/// Brightest light level
+pub const MAX_LIGHT: u8 = 15;
+
+/// Material property giving the light level a Block emits
+pub const EMISSION: &str = "emission";
+
+///
+/// Light at a Block
+///
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Light {
+    pub sky: u8,
+    pub block: u8,
+}
+
+impl Light {
+    /// Combined brightness.
+    pub fn level(&self) -> u8 { self.sky.max(self.block) }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Channel {
+    Sky,
+    Block,
+}
+
+///
+/// Light levels of one Chunk, sky in the high nibble and block in the low
+///
+#[derive(Clone)]
+pub struct ChunkLight {
+    levels: [[[u8; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
+}
+
+impl ChunkLight {
+    pub fn new() -> ChunkLight { ChunkLight { levels: [[[0; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE] } }
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Light {
+        let packed = self.levels[x][y][z];
+        Light { sky: packed >> 4, block: packed & 0x0F }
+    }
+    fn channel(&self, x: usize, y: usize, z: usize, channel: Channel) -> u8 {
+        let light = self.get(x, y, z);
+        match channel {
+            Channel::Sky => light.sky,
+            Channel::Block => light.block,
+        }
+    }
+    fn set_channel(&mut self, x: usize, y: usize, z: usize, channel: Channel, level: u8) {
+        let packed = &mut self.levels[x][y][z];
+        *packed = match channel {
+            Channel::Sky => (*packed & 0x0F) | (level << 4),
+            Channel::Block => (*packed & 0xF0) | (level & 0x0F),
+        };
+    }
+}
+
+impl Default for ChunkLight {
+    fn default() -> ChunkLight { ChunkLight::new() }
+}
+
+///
+/// Light levels of the loaded World
+///
+#[derive(Clone, Default)]
+pub struct Lighting {
+    chunks: HashMap<Vector2<u64>, Box<ChunkLight>>,
+    dirty: BTreeSet<Vector2<u64>>,
+    emitters: HashMap<BlockPosition, u8>,
+}
+
+impl Lighting {
+    pub fn new() -> Lighting { Lighting::default() }
+    /// Light at a Block, None if it hasn't been lit yet.
+    pub fn get(&self, x: u64, y: usize, z: u64) -> Option<Light> {
+        if y >= CHUNK_SIZE {
+            return None;
+        }
+        let (chunk, lx, lz) = chunk_of(x, z);
+        self.chunks.get(&chunk).map(|light| light.get(lx, y, lz))
+    }
+    /// Queue a Chunk for relighting.
+    pub fn mark_dirty(&mut self, chunk: Vector2<u64>) { self.dirty.insert(chunk); }
+    pub fn is_dirty(&self, chunk: Vector2<u64>) -> bool { self.dirty.contains(&chunk) }
+    pub fn dirty(&self) -> impl Iterator<Item = &Vector2<u64>> { self.dirty.iter() }
+    /// Forget an unloaded Chunk and queue its neighbours, which it may have lit.
+    pub fn remove(&mut self, chunk: Vector2<u64>) {
+        self.chunks.remove(&chunk);
+        self.dirty.remove(&chunk);
+        for neighbour in neighbours(chunk) {
+            self.dirty.insert(neighbour);
+        }
+    }
+    ///
+    /// Place a point light at a Block, in addition to any emission of its
+    /// material. A level of zero removes it.
+    ///
+    pub fn set_emitter(&mut self, x: u64, y: usize, z: u64, level: u8) {
+        if level == 0 {
+            self.emitters.remove(&(x, y, z));
+        } else {
+            self.emitters.insert((x, y, z), level.min(MAX_LIGHT));
+        }
+        self.mark_dirty(chunk_of(x, z).0);
+    }
+    pub fn emitter(&self, x: u64, y: usize, z: u64) -> u8 { self.emitters.get(&(x, y, z)).cloned().unwrap_or(0) }
+    ///
+    /// Relight every dirty Chunk of `world`, returning the number of Chunks
+    /// recomputed.
+    ///
+    pub fn update(&mut self, world: &World) -> usize {
+        let mut region = BTreeSet::new();
+        for &chunk in self.dirty.iter() {
+            region.insert(chunk);
+            region.extend(neighbours(chunk));
+        }
+        self.dirty.clear();
+        let region: Vec<Vector2<u64>> = region.into_iter().filter(|&chunk| world.is_chunk_loaded(chunk)).collect();
+        for &chunk in region.iter() {
+            self.chunks.insert(chunk, Box::new(ChunkLight::new()));
+        }
+        let cost = Attenuation::new(world.materials());
+        for &channel in [Channel::Sky, Channel::Block].iter() {
+            let mut queue = VecDeque::new();
+            for &chunk in region.iter() {
+                self.seed(world, &cost, chunk, channel, &mut queue);
+            }
+            self.seed_borders(&region, channel, &mut queue);
+            self.propagate(world, &cost, channel, queue);
+        }
+        region.len()
+    }
+    fn seed(&mut self, world: &World, cost: &Attenuation, position: Vector2<u64>, channel: Channel, queue: &mut VecDeque<BlockPosition>) {
+        let chunk = match world.get_chunk(position) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+        let light = self.chunks.get_mut(&position).unwrap();
+        let size = CHUNK_SIZE as u64;
+        for lx in 0..CHUNK_SIZE {
+            for lz in 0..CHUNK_SIZE {
+                let (x, z) = (position.x * size + lx as u64, position.y * size + lz as u64);
+                let mut sky = MAX_LIGHT;
+                for y in (0..CHUNK_SIZE).rev() {
+                    let material = chunk.get_block(lx, y, lz).material();
+                    let level = match channel {
+                        Channel::Sky => {
+                            if sky > 0 && material != AIR {
+                                sky = sky.saturating_sub(cost.through(material.index()).unwrap_or(MAX_LIGHT));
+                            }
+                            sky
+                        }
+                        Channel::Block => cost.emission(material.index()).max(self.emitters.get(&(x, y, z)).cloned().unwrap_or(0)),
+                    };
+                    if level > 0 {
+                        light.set_channel(lx, y, lz, channel, level);
+                        queue.push_back((x, y, z));
+                    }
+                }
+            }
+        }
+    }
+    /// Let light from lit Chunks outside the relit region flow back into it.
+    fn seed_borders(&self, region: &[Vector2<u64>], channel: Channel, queue: &mut VecDeque<BlockPosition>) {
+        let inside: BTreeSet<Vector2<u64>> = region.iter().cloned().collect();
+        let size = CHUNK_SIZE as u64;
+        for &chunk in region.iter() {
+            for neighbour in neighbours(chunk) {
+                if inside.contains(&neighbour) || !self.chunks.contains_key(&neighbour) {
+                    continue;
+                }
+                let light = &self.chunks[&neighbour];
+                for lx in 0..CHUNK_SIZE {
+                    for lz in 0..CHUNK_SIZE {
+                        for y in 0..CHUNK_SIZE {
+                            if light.channel(lx, y, lz, channel) > 1 {
+                                queue.push_back((neighbour.x * size + lx as u64, y, neighbour.y * size + lz as u64));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    fn propagate(&mut self, world: &World, cost: &Attenuation, channel: Channel, mut queue: VecDeque<BlockPosition>) {
+        while let Some((x, y, z)) = queue.pop_front() {
+            let level = match self.get(x, y, z) {
+                Some(light) if channel == Channel::Sky => light.sky,
+                Some(light) => light.block,
+                None => continue,
+            };
+            for (nx, ny, nz) in adjacent(x, y, z) {
+                let material = match world.get_block(nx, ny, nz) {
+                    Some(block) => block.material(),
+                    None => continue,
+                };
+                let next = match cost.through(material.index()) {
+                    Some(cost) => level.saturating_sub(cost.max(1)),
+                    None => continue,
+                };
+                let (chunk, lx, lz) = chunk_of(nx, nz);
+                if let Some(light) = self.chunks.get_mut(&chunk) {
+                    if next > light.channel(lx, ny, lz, channel) {
+                        light.set_channel(lx, ny, lz, channel, next);
+                        queue.push_back((nx, ny, nz));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Per material light cost and emission, looked up once per update
+struct Attenuation {
+    cost: Vec<Option<u8>>,
+    emission: Vec<u8>,
+}
+
+impl Attenuation {
+    fn new(materials: &MaterialRegistry) -> Attenuation {
+        let mut cost = Vec::with_capacity(materials.len());
+        let mut emission = Vec::with_capacity(materials.len());
+        for (_, material) in materials.iter() {
+            let opacity = material.opacity();
+            cost.push(if opacity >= 1.0 {
+                None
+            } else {
+                Some((opacity.max(0.0) * MAX_LIGHT as f32) as u8)
+            });
+            let glow = material.property(EMISSION).unwrap_or(0.0);
+            emission.push(glow.max(0.0).min(MAX_LIGHT as f32) as u8);
+        }
+        Attenuation { cost, emission }
+    }
+    /// Extra levels lost entering a Block, None if it is opaque.
+    fn through(&self, material: usize) -> Option<u8> { self.cost.get(material).cloned().unwrap_or(None) }
+    fn emission(&self, material: usize) -> u8 { self.emission.get(material).cloned().unwrap_or(0) }
+}
+
+fn neighbours(chunk: Vector2<u64>) -> Vec<Vector2<u64>> {
+    let mut result = Vec::with_capacity(8);
+    for dx in -1i64..2 {
+        for dz in -1i64..2 {
+            let (x, z) = (chunk.x as i64 + dx, chunk.y as i64 + dz);
+            if (dx != 0 || dz != 0) && x >= 0 && z >= 0 {
+                result.push(Vector2::new(x as u64, z as u64));
+            }
+        }
+    }
+    result
+}
+
+fn adjacent(x: u64, y: usize, z: u64) -> Vec<BlockPosition> {
+    let mut result = Vec::with_capacity(6);
+    result.push((x + 1, y, z));
+    result.push((x, y, z + 1));
+    if y + 1 < CHUNK_SIZE {
+        result.push((x, y + 1, z));
+    }
+    if x > 0 {
+        result.push((x - 1, y, z));
+    }
+    if y > 0 {
+        result.push((x, y - 1, z));
+    }
+    if z > 0 {
+        result.push((x, y, z - 1));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EMISSION, MAX_LIGHT};
+    use model::material::{Material, AIR};
+    use model::world::{Block, Chunk, Vector2, World};
+    use pool::Poolable;
+
+    /// Two chunks of flat rock floor at y = 0 with a rock roof over x, z in 4..12
+    fn world() -> World {
+        let mut world = World::new();
+        let rock = world.materials().id("rock").unwrap();
+        world.insert_chunk(Vector2::new(0, 0), Chunk::allocate());
+        world.insert_chunk(Vector2::new(1, 0), Chunk::allocate());
+        for x in 0..64 {
+            for z in 0..32 {
+                world.set_block(x, 0, z, Block::new(rock));
+            }
+        }
+        for x in 4..12 {
+            for z in 4..12 {
+                world.set_block(x, 5, z, Block::new(rock));
+            }
+        }
+        world.update_lighting();
+        world
+    }
+
+    #[test]
+    pub fn test_sky_light() {
+        let mut world = world();
+        assert_eq!(world.light_at(20, 1, 20).unwrap().sky, MAX_LIGHT);
+        assert_eq!(world.light_at(20, 0, 20).unwrap().sky, 0);
+        // Under the roof light falls off with the distance to its edge
+        assert_eq!(world.light_at(4, 4, 8).unwrap().sky, MAX_LIGHT - 1);
+        assert_eq!(world.light_at(7, 4, 8).unwrap().sky, MAX_LIGHT - 4);
+
+        // Opening the roof relights below it
+        world.set_block(7, 5, 8, Block::new(AIR));
+        assert!(world.lighting().is_dirty(Vector2::new(0, 0)));
+        world.update_lighting();
+        assert_eq!(world.light_at(7, 4, 8).unwrap().sky, MAX_LIGHT);
+        assert_eq!(world.light_at(8, 4, 8).unwrap().sky, MAX_LIGHT - 1);
+    }
+
+    #[test]
+    pub fn test_block_light_crosses_chunks() {
+        let mut world = world();
+        let glow = world.materials_mut().register(Material::new("glowstone", 1.0, 1.0).with_property(EMISSION, 12.0));
+        world.set_block(30, 1, 16, Block::new(glow));
+        world.set_light_emitter(8, 2, 8, 10);
+        world.update_lighting();
+
+        assert_eq!(world.light_at(30, 1, 16).unwrap().block, 12);
+        assert_eq!(world.light_at(33, 1, 16).unwrap().block, 9);
+        assert_eq!(world.light_at(8, 3, 8).unwrap().block, 9);
+        assert_eq!(world.light_at(8, 3, 8).unwrap().level(), MAX_LIGHT - 4);
+
+        // Removing the source clears the neighbouring chunk too
+        world.set_block(30, 1, 16, Block::new(AIR));
+        world.update_lighting();
+        assert_eq!(world.light_at(33, 1, 16).unwrap().block, 0);
+    }
+}
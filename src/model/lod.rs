@@ -0,0 +1,194 @@
+///
+/// Chunk Level of Detail
+///
+/// Coarse copies of Chunks for rendering far away and planning paths across
+/// long distances. Each level divides the Chunk into cubes of FACTORS Blocks
+/// a side, 16³, 8³ and 4³ cells for a 32 Block Chunk, each cell taking the
+/// material most of its Blocks are made of. Ties go to solid material over
+/// air, so thin walls survive, then to the lowest MaterialId.
+///
+/// A LodCache follows the World through its observer events: a changed Block
+/// only recomputes the cells containing it, and a Chunk is only rebuilt whole
+/// when it is loaded or generated.
+///
+use model::material::{MaterialId, AIR};
+use model::observer::{EventFilter, SubscriberId, WorldEvent};
+use model::world::{chunk_of, Chunk, Vector2, World, CHUNK_SIZE};
+use std::collections::HashMap;
+
+/// Edge length in Blocks of the cells of each level, finest first
+pub const FACTORS: [usize; 3] = [2, 4, 8];
+
+///
+/// Downsampled Chunk
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LodChunk {
+    factor: usize,
+    cells: Vec<MaterialId>,
+}
+
+impl LodChunk {
+    /// Downsample `chunk` into cells of `factor` Blocks a side, which must divide CHUNK_SIZE.
+    pub fn build(chunk: &Chunk, factor: usize) -> LodChunk {
+        assert!(factor > 0 && CHUNK_SIZE.is_multiple_of(factor), "LOD factor must divide the Chunk size");
+        let size = CHUNK_SIZE / factor;
+        let mut lod = LodChunk { factor, cells: vec![AIR; size * size * size] };
+        for x in 0..size {
+            for y in 0..size {
+                for z in 0..size {
+                    lod.refresh_cell(chunk, x, y, z);
+                }
+            }
+        }
+        lod
+    }
+    /// Blocks along each edge of a cell.
+    pub fn factor(&self) -> usize { self.factor }
+    /// Cells along each edge.
+    pub fn size(&self) -> usize { CHUNK_SIZE / self.factor }
+    pub fn get(&self, x: usize, y: usize, z: usize) -> MaterialId {
+        let size = self.size();
+        self.cells[(x * size + y) * size + z]
+    }
+    /// Material of the cell holding the Block at `x`, `y`, `z` of the Chunk.
+    pub fn at_block(&self, x: usize, y: usize, z: usize) -> MaterialId { self.get(x / self.factor, y / self.factor, z / self.factor) }
+    /// Recompute the cell holding a Block after it changed in `chunk`.
+    pub fn refresh(&mut self, chunk: &Chunk, x: usize, y: usize, z: usize) {
+        let factor = self.factor;
+        self.refresh_cell(chunk, x / factor, y / factor, z / factor);
+    }
+    fn refresh_cell(&mut self, chunk: &Chunk, x: usize, y: usize, z: usize) {
+        let factor = self.factor;
+        let mut counts: HashMap<MaterialId, usize> = HashMap::new();
+        for bx in x * factor..(x + 1) * factor {
+            for by in y * factor..(y + 1) * factor {
+                for bz in z * factor..(z + 1) * factor {
+                    *counts.entry(chunk.get_block(bx, by, bz).material()).or_insert(0) += 1;
+                }
+            }
+        }
+        let majority = counts.into_iter().max_by_key(|&(material, count)| (count, material != AIR, std::cmp::Reverse(material))).map(|(material, _)| material);
+        let size = self.size();
+        self.cells[(x * size + y) * size + z] = majority.unwrap_or(AIR);
+    }
+}
+
+///
+/// Every level of detail of the loaded Chunks of a World
+///
+pub struct LodCache {
+    subscriber: SubscriberId,
+    chunks: HashMap<Vector2<u64>, Vec<LodChunk>>,
+}
+
+impl LodCache {
+    /// Downsample every loaded Chunk of `world`, and subscribe to its changes.
+    pub fn new(world: &mut World) -> LodCache {
+        let subscriber = world.subscribe(EventFilter::all());
+        let mut cache = LodCache { subscriber, chunks: HashMap::new() };
+        for position in world.loaded_chunks() {
+            cache.rebuild(world, position);
+        }
+        cache
+    }
+    /// Stop following `world`.
+    pub fn detach(self, world: &mut World) { world.observers_mut().unsubscribe(self.subscriber); }
+    ///
+    /// Level of a Chunk with cells of `factor` Blocks a side, None if the
+    /// Chunk isn't loaded or `factor` isn't one of FACTORS.
+    ///
+    pub fn get(&self, position: Vector2<u64>, factor: usize) -> Option<&LodChunk> {
+        let level = FACTORS.iter().position(|&candidate| candidate == factor)?;
+        self.chunks.get(&position).map(|levels| &levels[level])
+    }
+    pub fn len(&self) -> usize { self.chunks.len() }
+    pub fn is_empty(&self) -> bool { self.chunks.is_empty() }
+    ///
+    /// Catch up with the changes to `world` since the last update, returning
+    /// the number of cells recomputed.
+    ///
+    pub fn update(&mut self, world: &mut World) -> usize {
+        let cells_per_chunk: usize = FACTORS.iter().map(|factor| (CHUNK_SIZE / factor).pow(3)).sum();
+        let mut recomputed = 0;
+        for event in world.drain_events(self.subscriber) {
+            match event {
+                WorldEvent::BlockChanged { position: (x, y, z), .. } => {
+                    let (position, lx, lz) = chunk_of(x, z);
+                    if let (Some(levels), Some(chunk)) = (self.chunks.get_mut(&position), world.get_chunk(position)) {
+                        for lod in levels.iter_mut() {
+                            lod.refresh(chunk, lx, y, lz);
+                        }
+                        recomputed += FACTORS.len();
+                    }
+                }
+                WorldEvent::ChunkLoaded(position) | WorldEvent::ChunkGenerated(position) => {
+                    if self.rebuild(world, position) {
+                        recomputed += cells_per_chunk;
+                    }
+                }
+                WorldEvent::ChunkUnloaded(position) => {
+                    self.chunks.remove(&position);
+                }
+            }
+        }
+        recomputed
+    }
+    fn rebuild(&mut self, world: &World, position: Vector2<u64>) -> bool {
+        match world.get_chunk(position) {
+            Some(chunk) => {
+                self.chunks.insert(position, FACTORS.iter().map(|&factor| LodChunk::build(chunk, factor)).collect());
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LodCache, LodChunk};
+    use model::material::AIR;
+    use model::world::{Block, Chunk, Vector2, World};
+
+    #[test]
+    pub fn test_lod_levels() {
+        let mut world = World::new();
+        let rock = Block::new(world.materials().id("rock").unwrap());
+        let metal = Block::new(world.materials().id("metal").unwrap());
+        let mut chunk = Box::new(Chunk::new());
+        // Half of the first 2³ cell is rock, a quarter of the 4³ cell around it
+        for x in 0..2 {
+            for z in 0..2 {
+                chunk.set_block(x, 0, z, rock);
+            }
+        }
+        let fine = LodChunk::build(&chunk, 2);
+        assert_eq!((fine.size(), fine.get(0, 0, 0), fine.get(1, 0, 0)), (16, rock.material(), AIR));
+        assert_eq!(LodChunk::build(&chunk, 4).get(0, 0, 0), AIR);
+
+        world.insert_chunk(Vector2::new(0, 0), chunk);
+        let mut cache = LodCache::new(&mut world);
+        assert_eq!(cache.get(Vector2::new(0, 0), 8).map(LodChunk::size), Some(4));
+        assert!(cache.get(Vector2::new(0, 0), 3).is_none());
+
+        // Changing a Block recomputes one cell of each level
+        world.set_block(0, 1, 0, metal);
+        world.set_block(1, 1, 0, metal);
+        world.set_block(0, 1, 1, metal);
+        assert_eq!(cache.update(&mut world), 9);
+        assert_eq!(cache.get(Vector2::new(0, 0), 2).unwrap().at_block(1, 1, 1), rock.material());
+        world.set_block(0, 0, 0, metal);
+        cache.update(&mut world);
+        assert_eq!(cache.get(Vector2::new(0, 0), 2).unwrap().at_block(1, 1, 1), metal.material());
+
+        // Chunks come and go with the World
+        world.insert_chunk(Vector2::new(1, 0), Box::new(Chunk::new()));
+        assert_eq!(cache.update(&mut world), 16 * 16 * 16 + 8 * 8 * 8 + 4 * 4 * 4);
+        world.unload_chunk(Vector2::new(0, 0)).unwrap();
+        cache.update(&mut world);
+        assert_eq!(cache.len(), 1);
+        cache.detach(&mut world);
+        assert!(world.observers().is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+///
+/// Material Registry
+///
+/// Materials are interned by name into compact ids so that a Block only has to
+/// carry a MaterialId rather than the full set of material properties.
+///
+use std::collections::HashMap;
+
+///
+/// Compact Material Identifier
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Default)]
+pub struct MaterialId(u16);
+
+/// Air is always registered first so a zeroed Block is empty space.
+pub const AIR: MaterialId = MaterialId(0);
+
+impl MaterialId {
+    pub fn new(id: u16) -> MaterialId { MaterialId(id) }
+    pub fn id(&self) -> u16 { self.0 }
+    pub fn index(&self) -> usize { self.0 as usize }
+}
+
+///
+/// Material Properties
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct Material {
+    name: String,
+    resistance: f32,
+    opacity: f32,
+    properties: HashMap<String, f32>,
+}
+
+impl Material {
+    pub fn new(name: &str, resistance: f32, opacity: f32) -> Material {
+        Material {
+            name: name.to_string(),
+            resistance,
+            opacity,
+            properties: HashMap::new(),
+        }
+    }
+    /// Attach an additional named property to this Material.
+    pub fn with_property(mut self, name: &str, value: f32) -> Material {
+        self.properties.insert(name.to_string(), value);
+        self
+    }
+    pub fn name(&self) -> &str { &self.name }
+    pub fn resistance(&self) -> f32 { self.resistance }
+    pub fn opacity(&self) -> f32 { self.opacity }
+    pub fn property(&self, name: &str) -> Option<f32> { self.properties.get(name).cloned() }
+    pub fn set_property(&mut self, name: &str, value: f32) {
+        self.properties.insert(name.to_string(), value);
+    }
+}
+
+///
+/// Material Registry
+///
+#[derive(Clone)]
+pub struct MaterialRegistry {
+    materials: Vec<Material>,
+    names: HashMap<String, MaterialId>,
+}
+
+impl MaterialRegistry {
+    /// Create a Registry containing only Air.
+    pub fn new() -> MaterialRegistry {
+        let mut registry = MaterialRegistry {
+            materials: Vec::new(),
+            names: HashMap::new(),
+        };
+        registry.register(Material::new("air", 0.0, 0.0));
+        registry
+    }
+    ///
+    /// Register a Material, returning its id. Registering a name that already
+    /// exists replaces its properties and keeps the existing id.
+    ///
+    pub fn register(&mut self, material: Material) -> MaterialId {
+        if let Some(&id) = self.names.get(material.name()) {
+            self.materials[id.index()] = material;
+            return id;
+        }
+        assert!(self.materials.len() <= u16::MAX as usize, "Material Registry is full");
+        let id = MaterialId(self.materials.len() as u16);
+        self.names.insert(material.name().to_string(), id);
+        self.materials.push(material);
+        id
+    }
+    pub fn id(&self, name: &str) -> Option<MaterialId> { self.names.get(name).cloned() }
+    pub fn get(&self, id: MaterialId) -> Option<&Material> { self.materials.get(id.index()) }
+    pub fn get_mut(&mut self, id: MaterialId) -> Option<&mut Material> { self.materials.get_mut(id.index()) }
+    pub fn by_name(&self, name: &str) -> Option<&Material> { self.id(name).and_then(|id| self.get(id)) }
+    pub fn len(&self) -> usize { self.materials.len() }
+    pub fn is_empty(&self) -> bool { self.materials.is_empty() }
+    pub fn iter(&self) -> impl Iterator<Item=(MaterialId, &Material)> {
+        self.materials.iter().enumerate().map(|(idx, material)| (MaterialId(idx as u16), material))
+    }
+}
+
+impl Default for MaterialRegistry {
+    /// Registry with the standard hive materials.
+    fn default() -> MaterialRegistry {
+        let mut registry = MaterialRegistry::new();
+        registry.register(Material::new("rock", 5.0, 1.0));
+        registry.register(Material::new("chitin", 2.0, 1.0));
+        registry.register(Material::new("metal", 10.0, 1.0));
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Material, MaterialRegistry, AIR};
+
+    #[test]
+    pub fn test_material_interning() {
+        let mut registry = MaterialRegistry::default();
+        assert_eq!(registry.id("air"), Some(AIR));
+        let rock = registry.id("rock").unwrap();
+        assert_eq!(registry.get(rock).unwrap().resistance(), 5.0);
+
+        // Re-registering keeps the id but replaces the properties
+        let again = registry.register(Material::new("rock", 6.0, 1.0).with_property("density", 2.5));
+        assert_eq!(again, rock);
+        assert_eq!(registry.get(rock).unwrap().resistance(), 6.0);
+        assert_eq!(registry.get(rock).unwrap().property("density"), Some(2.5));
+        assert_eq!(registry.len(), 4);
+    }
+}
@@ -0,0 +1,226 @@
+///
+/// Chunk Meshing
+///
+/// Turns a Chunk into triangles for whatever renders the World, as plain
+/// vertex and index buffers so the crate needs no graphics API. Only faces
+/// which can be seen are kept: a face is hidden by an opaque neighbour, and
+/// between two Blocks of the same material. Adjacent visible faces of one
+/// material facing the same way are merged into rectangles greedily, so a
+/// flat floor is two triangles rather than two per Block.
+///
+/// Faces on the Chunk's edges are tested against the neighbouring Chunks'
+/// borders. A neighbour which isn't loaded hides them, so the Chunk should be
+/// meshed again when it loads; the top of the World is open sky and the
+/// bottom is never seen.
+///
+use model::material::{MaterialId, MaterialRegistry};
+use model::world::{Block, Chunk, Vector2, World, CHUNK_SIZE};
+
+///
+/// Chunks sharing the edges of the Chunk being meshed
+///
+#[derive(Copy, Clone, Default)]
+pub struct Borders<'a> {
+    pub negative_x: Option<&'a Chunk>,
+    pub positive_x: Option<&'a Chunk>,
+    pub negative_z: Option<&'a Chunk>,
+    pub positive_z: Option<&'a Chunk>,
+}
+
+///
+/// Triangles of a Chunk
+///
+/// Positions are in Blocks from the Chunk's corner, texture coordinates in
+/// Blocks so textures repeat across merged faces, and every vertex carries
+/// the id of the material it shows. Triangles wind counter-clockwise seen
+/// from outside.
+///
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct Mesh {
+    /// x, y, z per vertex
+    pub positions: Vec<f32>,
+    /// x, y, z per vertex
+    pub normals: Vec<f32>,
+    /// u, v per vertex
+    pub uvs: Vec<f32>,
+    /// MaterialId per vertex
+    pub materials: Vec<u32>,
+    /// Three vertices per triangle
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    pub fn new() -> Mesh { Mesh::default() }
+    ///
+    /// Mesh `chunk`, culling its faces against `borders` and the opacity of
+    /// its materials in `materials`.
+    ///
+    pub fn build(chunk: &Chunk, borders: &Borders, materials: &MaterialRegistry) -> Mesh {
+        let opaque: Vec<bool> = materials.iter().map(|(_, material)| material.opacity() >= 1.0).collect();
+        let is_opaque = |material: MaterialId| opaque.get(material.index()).cloned().unwrap_or(true);
+        let size = CHUNK_SIZE as isize;
+        // Material of a Block around the Chunk, None where faces are hidden
+        let sample = |x: isize, y: isize, z: isize| -> Option<Block> {
+            if y >= size {
+                return Some(Block::default());
+            }
+            let (neighbour, x, z) = match (x, z) {
+                _ if y < 0 => return None,
+                (x, z) if x < 0 => (borders.negative_x, x + size, z),
+                (x, z) if x >= size => (borders.positive_x, x - size, z),
+                (x, z) if z < 0 => (borders.negative_z, x, z + size),
+                (x, z) if z >= size => (borders.positive_z, x, z - size),
+                (x, z) => (Some(chunk), x, z),
+            };
+            neighbour.map(|neighbour| neighbour.get_block(x as usize, y as usize, z as usize))
+        };
+        let mut mesh = Mesh::new();
+        let mut mask: Vec<Option<MaterialId>> = vec![None; CHUNK_SIZE * CHUNK_SIZE];
+        for axis in 0..3 {
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            for &step in [-1isize, 1].iter() {
+                for slice in 0..size {
+                    for a in 0..size {
+                        for b in 0..size {
+                            let mut position = [0isize; 3];
+                            position[axis] = slice;
+                            position[u] = a;
+                            position[v] = b;
+                            let block = chunk.get_block(position[0] as usize, position[1] as usize, position[2] as usize);
+                            position[axis] += step;
+                            let visible = !block.is_air() && match sample(position[0], position[1], position[2]) {
+                                Some(other) => !is_opaque(other.material()) && other.material() != block.material(),
+                                None => false,
+                            };
+                            mask[(a * size + b) as usize] = if visible { Some(block.material()) } else { None };
+                        }
+                    }
+                    let depth = if step > 0 { slice + 1 } else { slice };
+                    mesh.merge(&mut mask, axis, step, depth);
+                }
+            }
+        }
+        mesh
+    }
+    /// Mesh a loaded Chunk of `world` against its loaded neighbours, across the seam of a wrapping World.
+    pub fn of_chunk(world: &World, position: Vector2<u64>) -> Option<Mesh> {
+        let chunk = world.get_chunk(position)?;
+        let neighbour = |dx: i64, dz: i64| world.bounds().neighbour(position, dx, dz).and_then(|neighbour| world.get_chunk(neighbour));
+        let borders = Borders { negative_x: neighbour(-1, 0), positive_x: neighbour(1, 0), negative_z: neighbour(0, -1), positive_z: neighbour(0, 1) };
+        Some(Mesh::build(chunk, &borders, world.materials()))
+    }
+    pub fn vertex_count(&self) -> usize { self.materials.len() }
+    pub fn triangle_count(&self) -> usize { self.indices.len() / 3 }
+    pub fn is_empty(&self) -> bool { self.indices.is_empty() }
+    /// Cover the faces in `mask` with as few rectangles as it takes, clearing it.
+    fn merge(&mut self, mask: &mut [Option<MaterialId>], axis: usize, step: isize, depth: isize) {
+        let size = CHUNK_SIZE;
+        for a in 0..size {
+            let mut b = 0;
+            while b < size {
+                let material = match mask[a * size + b] {
+                    Some(material) => material,
+                    None => {
+                        b += 1;
+                        continue;
+                    }
+                };
+                let mut height = 1;
+                while b + height < size && mask[a * size + b + height] == Some(material) {
+                    height += 1;
+                }
+                let mut width = 1;
+                while a + width < size && (b..b + height).all(|row| mask[(a + width) * size + row] == Some(material)) {
+                    width += 1;
+                }
+                for column in a..a + width {
+                    for row in b..b + height {
+                        mask[column * size + row] = None;
+                    }
+                }
+                self.quad(axis, step, depth, (a, b), (width, height), material);
+                b += height;
+            }
+        }
+    }
+    fn quad(&mut self, axis: usize, step: isize, depth: isize, (a, b): (usize, usize), (width, height): (usize, usize), material: MaterialId) {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let first = self.vertex_count() as u32;
+        for &(du, dv) in [(0, 0), (width, 0), (width, height), (0, height)].iter() {
+            let mut position = [0f32; 3];
+            position[axis] = depth as f32;
+            position[u] = (a + du) as f32;
+            position[v] = (b + dv) as f32;
+            let mut normal = [0f32; 3];
+            normal[axis] = step as f32;
+            self.positions.extend_from_slice(&position);
+            self.normals.extend_from_slice(&normal);
+            self.uvs.extend_from_slice(&[du as f32, dv as f32]);
+            self.materials.push(material.id() as u32);
+        }
+        // Corners run counter-clockwise seen from the positive side of the axis
+        let order: [u32; 6] = if step > 0 { [0, 1, 2, 0, 2, 3] } else { [0, 2, 1, 0, 3, 2] };
+        self.indices.extend(order.iter().map(|corner| first + corner));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Borders, Mesh};
+    use model::material::{Material, MaterialRegistry};
+    use model::world::{Block, Chunk, Vector2, World, CHUNK_SIZE};
+
+    #[test]
+    pub fn test_greedy_mesh() {
+        let mut materials = MaterialRegistry::default();
+        let rock = Block::new(materials.id("rock").unwrap());
+        let glass = Block::new(materials.register(Material::new("glass", 1.0, 0.2)));
+        let mut chunk = Chunk::new();
+        let open = Chunk::new();
+        let around = Borders { negative_x: Some(&open), positive_x: Some(&open), negative_z: Some(&open), positive_z: Some(&open) };
+
+        // A lone Block shows all six faces
+        chunk.set_block(4, 4, 4, rock);
+        let mesh = Mesh::build(&chunk, &around, &materials);
+        assert_eq!((mesh.vertex_count(), mesh.triangle_count()), (24, 12));
+        assert_eq!(mesh.positions.len(), 72);
+        assert_eq!(mesh.normals.len(), 72);
+        assert_eq!(mesh.uvs.len(), 48);
+
+        // A 3x2x4 slab is still six rectangles
+        for x in 4..7 {
+            for y in 4..6 {
+                for z in 4..8 {
+                    chunk.set_block(x, y, z, rock);
+                }
+            }
+        }
+        let mesh = Mesh::build(&chunk, &around, &materials);
+        assert_eq!(mesh.triangle_count(), 12);
+        let top = (0..mesh.vertex_count()).find(|&vertex| mesh.normals[vertex * 3 + 1] == 1.0).unwrap();
+        assert_eq!(mesh.positions[top * 3 + 1], 6.0);
+
+        // Glass shows the rock behind it, but rock hides the glass face against it
+        chunk.set_block(7, 4, 4, glass);
+        let mesh = Mesh::build(&chunk, &around, &materials);
+        let glass_faces = mesh.materials.iter().filter(|&&material| material == glass.material().id() as u32).count() / 4;
+        assert_eq!(glass_faces, 5);
+        assert_eq!(mesh.triangle_count() - glass_faces * 2, 12);
+
+        // A floor across the Chunk hides its edges against loaded floors and missing Chunks
+        let mut world = World::with_materials(materials);
+        let mut floor = Box::new(Chunk::new());
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                floor.set_block(x, 0, z, rock);
+            }
+        }
+        world.insert_chunk(Vector2::new(1, 1), floor);
+        let mesh = Mesh::of_chunk(&world, Vector2::new(1, 1)).unwrap();
+        assert_eq!(mesh.triangle_count(), 2);
+        world.insert_chunk(Vector2::new(2, 1), Box::new(Chunk::new()));
+        assert_eq!(Mesh::of_chunk(&world, Vector2::new(1, 1)).unwrap().triangle_count(), 4);
+        assert!(Mesh::of_chunk(&world, Vector2::new(2, 1)).unwrap().is_empty());
+        assert!(Mesh::of_chunk(&world, Vector2::new(3, 1)).is_none());
+    }
+}
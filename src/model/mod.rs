@@ -1,7 +1,39 @@
-///
-///
-///
-///
+//!
+//! World Model: Blocks, Materials and Entities
+//!
 
+pub mod behavior;
+pub mod biome;
+pub mod bounds;
+pub mod bundle;
+pub mod component;
+pub mod dimension;
+pub mod edit;
 pub mod entity;
-pub mod world;
\ No newline at end of file
+pub mod environment;
+pub mod faction;
+pub mod flowfield;
+pub mod heightmap;
+pub mod inventory;
+pub mod item;
+pub mod job;
+pub mod light;
+pub mod lod;
+pub mod material;
+pub mod mesh;
+pub mod observer;
+pub mod pathfind;
+pub mod persist;
+pub mod pheromone;
+pub mod physics;
+pub mod power;
+pub mod provider;
+pub mod random;
+pub mod raycast;
+pub mod reflect;
+pub mod spatial;
+pub mod storage;
+pub mod structure;
+pub mod update;
+pub mod world;
+pub mod worldgen;
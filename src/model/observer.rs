@@ -0,0 +1,197 @@
+///
+/// World Observers
+///
+/// Systems which react to changes in the World, such as meshing, networking
+/// and scripts, subscribe to its events instead of polling every Chunk each
+/// tick. Each subscriber has a queue of its own which the World fills as
+/// Blocks change and Chunks come and go, and which the subscriber drains when
+/// it runs. A subscriber can narrow its events to a rectangle of Chunks, so a
+/// client only hears about the part of the World it can see.
+///
+use model::update::BlockPosition;
+use model::world::{chunk_of, Block, Vector2};
+
+///
+/// Change to the World
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum WorldEvent {
+    /// A Block was set to a different Block
+    BlockChanged { position: BlockPosition, before: Block, after: Block },
+    /// A Chunk was put in memory, paged in from storage or by a provider
+    ChunkLoaded(Vector2<u64>),
+    /// A Chunk was generated in place
+    ChunkGenerated(Vector2<u64>),
+    /// A Chunk was released from memory
+    ChunkUnloaded(Vector2<u64>),
+}
+
+impl WorldEvent {
+    /// Chunk the event happened in.
+    pub fn chunk(&self) -> Vector2<u64> {
+        match *self {
+            WorldEvent::BlockChanged { position: (x, _, z), .. } => chunk_of(x, z).0,
+            WorldEvent::ChunkLoaded(chunk) | WorldEvent::ChunkGenerated(chunk) | WorldEvent::ChunkUnloaded(chunk) => chunk,
+        }
+    }
+}
+
+///
+/// Which events a subscriber hears about
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct EventFilter {
+    /// Chunk rectangle, inclusive, None for the whole World
+    pub area: Option<(Vector2<u64>, Vector2<u64>)>,
+    pub blocks: bool,
+    /// Chunk loads, generation and unloads
+    pub chunks: bool,
+}
+
+impl EventFilter {
+    /// Every event everywhere.
+    pub fn all() -> EventFilter { EventFilter { area: None, blocks: true, chunks: true } }
+    /// Every event in the Chunks from `min` to `max` inclusive.
+    pub fn area(min: Vector2<u64>, max: Vector2<u64>) -> EventFilter { EventFilter { area: Some((min, max)), ..EventFilter::all() } }
+    pub fn accepts(&self, event: &WorldEvent) -> bool {
+        let kind = match *event {
+            WorldEvent::BlockChanged { .. } => self.blocks,
+            _ => self.chunks,
+        };
+        let chunk = event.chunk();
+        kind && self.area.is_none_or(|(min, max)| min.x <= chunk.x && chunk.x <= max.x && min.y <= chunk.y && chunk.y <= max.y)
+    }
+}
+
+impl Default for EventFilter {
+    fn default() -> EventFilter { EventFilter::all() }
+}
+
+///
+/// World Event Subscriber Identifier
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct SubscriberId(usize);
+
+struct Subscriber {
+    filter: EventFilter,
+    queue: Vec<WorldEvent>,
+}
+
+///
+/// Subscribers to a World's events
+///
+#[derive(Default)]
+pub struct Observers {
+    /// Indexed by SubscriberId, None once unsubscribed
+    subscribers: Vec<Option<Subscriber>>,
+}
+
+impl Observers {
+    pub fn new() -> Observers { Observers::default() }
+    pub fn subscribe(&mut self, filter: EventFilter) -> SubscriberId {
+        let subscriber = Some(Subscriber { filter, queue: Vec::new() });
+        match self.subscribers.iter().position(Option::is_none) {
+            Some(index) => {
+                self.subscribers[index] = subscriber;
+                SubscriberId(index)
+            }
+            None => {
+                self.subscribers.push(subscriber);
+                SubscriberId(self.subscribers.len() - 1)
+            }
+        }
+    }
+    /// Stop queueing events for `id`, returns false if it wasn't subscribed.
+    pub fn unsubscribe(&mut self, id: SubscriberId) -> bool {
+        self.subscribers.get_mut(id.0).and_then(Option::take).is_some()
+    }
+    pub fn filter(&self, id: SubscriberId) -> Option<EventFilter> {
+        self.subscribers.get(id.0).and_then(Option::as_ref).map(|subscriber| subscriber.filter)
+    }
+    /// Change what `id` hears about from now on, such as when a client moves.
+    pub fn set_filter(&mut self, id: SubscriberId, filter: EventFilter) -> bool {
+        match self.subscribers.get_mut(id.0).and_then(Option::as_mut) {
+            Some(subscriber) => {
+                subscriber.filter = filter;
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn is_empty(&self) -> bool { self.subscribers.iter().all(Option::is_none) }
+    /// Queue `event` for every subscriber whose filter accepts it.
+    pub fn publish(&mut self, event: WorldEvent) {
+        for subscriber in self.subscribers.iter_mut().flatten() {
+            if subscriber.filter.accepts(&event) {
+                subscriber.queue.push(event);
+            }
+        }
+    }
+    /// Events queued for `id` since it last drained, oldest first.
+    pub fn drain(&mut self, id: SubscriberId) -> Vec<WorldEvent> {
+        self.subscribers.get_mut(id.0).and_then(Option::as_mut).map(|subscriber| subscriber.queue.drain(..).collect()).unwrap_or_default()
+    }
+    pub fn pending(&self, id: SubscriberId) -> usize {
+        self.subscribers.get(id.0).and_then(Option::as_ref).map_or(0, |subscriber| subscriber.queue.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventFilter, WorldEvent};
+    use model::material::{MaterialId, AIR};
+    use model::world::{Block, Chunk, Vector2, World, CHUNK_SIZE};
+    use model::worldgen::ChunkGenerator;
+
+    /// Solid floor one Block deep
+    struct Floor;
+
+    impl ChunkGenerator for Floor {
+        fn generate(&self, _position: Vector2<u64>, chunk: &mut Chunk) {
+            for x in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    chunk.set_block(x, 0, z, Block::new(MaterialId::new(1)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_world_observers() {
+        let mut world = World::new();
+        let everything = world.subscribe(EventFilter::all());
+        let near = world.subscribe(EventFilter::area(Vector2::new(1, 0), Vector2::new(1, 0)));
+        let chunks = world.subscribe(EventFilter { blocks: false, ..EventFilter::all() });
+
+        world.insert_chunk(Vector2::new(0, 0), Box::new(Chunk::new()));
+        world.generate_chunk(Vector2::new(1, 0), &Floor);
+        let stone = world.get_block(CHUNK_SIZE as u64, 0, 0).unwrap();
+        assert!(world.set_block(CHUNK_SIZE as u64, 0, 0, Block::new(AIR)));
+        // Setting a Block to what it already is changes nothing
+        assert!(world.set_block(CHUNK_SIZE as u64, 0, 0, Block::new(AIR)));
+        assert!(world.set_block(3, 0, 3, stone));
+        world.unload_chunk(Vector2::new(0, 0)).unwrap();
+
+        let changed = |position, before, after| WorldEvent::BlockChanged { position, before, after };
+        assert_eq!(world.drain_events(everything), vec![
+            WorldEvent::ChunkLoaded(Vector2::new(0, 0)),
+            WorldEvent::ChunkGenerated(Vector2::new(1, 0)),
+            changed((CHUNK_SIZE as u64, 0, 0), stone, Block::new(AIR)),
+            changed((3, 0, 3), Block::new(AIR), stone),
+            WorldEvent::ChunkUnloaded(Vector2::new(0, 0)),
+        ]);
+        assert_eq!(world.drain_events(near), vec![
+            WorldEvent::ChunkGenerated(Vector2::new(1, 0)),
+            changed((CHUNK_SIZE as u64, 0, 0), stone, Block::new(AIR)),
+        ]);
+        assert_eq!(world.drain_events(chunks).len(), 3);
+        assert!(world.drain_events(everything).is_empty());
+
+        // Unsubscribed ids hear nothing more
+        assert!(world.observers_mut().unsubscribe(near));
+        world.insert_chunk(Vector2::new(1, 0), Box::new(Chunk::new()));
+        assert_eq!(world.observers().pending(near), 0);
+        assert_eq!(world.observers().pending(everything), 1);
+    }
+}
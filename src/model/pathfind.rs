@@ -0,0 +1,398 @@
+///
+/// Voxel Pathfinding
+///
+/// `find_path` is a plain A* over Block positions. Long trips go through
+/// `find_route`, which first plans over Chunks (linked by portals, the border
+/// crossings between them) and then refines each leg with a bounded A*, so the
+/// work per search stays proportional to the distance rather than its square.
+///
+use model::update::BlockPosition;
+use model::world::{chunk_of, Vector2, World, CHUNK_SIZE};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+///
+/// Movement Rules
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct MoveRules {
+    /// Highest ledge that can be stepped up in one move
+    pub climb: usize,
+    /// Deepest drop that can be stepped down in one move
+    pub drop: usize,
+    /// Move freely through the air without support
+    pub fly: bool,
+    /// Extra cost per point of material resistance to dig through a solid Block,
+    /// None if solid Blocks are impassable
+    pub burrow: Option<u32>,
+    /// Nodes A* may expand before giving up
+    pub max_nodes: usize,
+}
+
+impl MoveRules {
+    pub fn walker() -> MoveRules { MoveRules::default() }
+    pub fn flyer() -> MoveRules { MoveRules { fly: true, ..MoveRules::default() } }
+    pub fn burrower(cost: u32) -> MoveRules { MoveRules { burrow: Some(cost), ..MoveRules::default() } }
+}
+
+impl Default for MoveRules {
+    fn default() -> MoveRules {
+        MoveRules {
+            climb: 1,
+            drop: 2,
+            fly: false,
+            burrow: None,
+            max_nodes: 16384,
+        }
+    }
+}
+
+///
+/// Pathfinding Failure
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PathError {
+    /// The start or goal is not somewhere the unit can stand
+    Blocked,
+    /// Every reachable position was searched
+    Unreachable,
+    /// The search expanded `max_nodes` without reaching the goal
+    Budget,
+}
+
+///
+/// Path from start to goal inclusive
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Path {
+    pub nodes: Vec<BlockPosition>,
+    pub cost: u32,
+}
+
+impl Path {
+    pub fn start(&self) -> BlockPosition { self.nodes[0] }
+    pub fn goal(&self) -> BlockPosition { self.nodes[self.nodes.len() - 1] }
+    /// Number of moves.
+    pub fn len(&self) -> usize { self.nodes.len() - 1 }
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+}
+
+///
+/// Shortest path between two Blocks by A*.
+///
+pub fn find_path(world: &World, from: BlockPosition, to: BlockPosition, rules: &MoveRules) -> Result<Path, PathError> {
+    if enter_cost(world, from, rules).is_none() || !standable(world, from, rules) || enter_cost(world, to, rules).is_none() || !standable(world, to, rules) {
+        return Err(PathError::Blocked);
+    }
+    let mut open = BinaryHeap::new();
+    let mut best: HashMap<BlockPosition, u32> = HashMap::new();
+    let mut parent: HashMap<BlockPosition, BlockPosition> = HashMap::new();
+    best.insert(from, 0);
+    // Ordered by estimate, then remaining distance so ties run toward the goal
+    open.push(Reverse((distance(from, to), distance(from, to), from)));
+    let mut expanded = 0;
+    while let Some(Reverse((estimate, remaining, position))) = open.pop() {
+        let cost = estimate - remaining;
+        if position == to {
+            let mut nodes = vec![to];
+            let mut current = to;
+            while let Some(&previous) = parent.get(&current) {
+                nodes.push(previous);
+                current = previous;
+            }
+            nodes.reverse();
+            return Ok(Path { nodes, cost });
+        }
+        if best.get(&position).is_some_and(|&known| known < cost) {
+            continue;
+        }
+        expanded += 1;
+        if expanded > rules.max_nodes {
+            return Err(PathError::Budget);
+        }
+        for (next, step) in moves(world, position, rules) {
+            let total = cost + step;
+            if best.get(&next).is_none_or(|&known| total < known) {
+                best.insert(next, total);
+                parent.insert(next, position);
+                open.push(Reverse((total + distance(next, to), distance(next, to), next)));
+            }
+        }
+    }
+    Err(PathError::Unreachable)
+}
+
+///
+/// Path between two Blocks planned over Chunks first. Each leg is searched
+/// with the full `max_nodes` budget, so routes may be slightly longer than
+/// `find_path` but stay cheap however far apart the ends are.
+///
+pub fn find_route(world: &World, from: BlockPosition, to: BlockPosition, rules: &MoveRules) -> Result<Path, PathError> {
+    let (start, goal) = (chunk_of(from.0, from.2).0, chunk_of(to.0, to.2).0);
+    if chunk_distance(start, goal) <= 1 {
+        return find_path(world, from, to, rules);
+    }
+    if enter_cost(world, to, rules).is_none() || !standable(world, to, rules) {
+        return Err(PathError::Blocked);
+    }
+    let mut portals = Portals { world, rules, goal: to, known: HashMap::new() };
+    let chunks = portals.plan(start, goal)?;
+    let mut path = Path { nodes: vec![from], cost: 0 };
+    let mut position = from;
+    for pair in chunks.windows(2) {
+        let (exit, entry) = portals.between(pair[0], pair[1]).unwrap();
+        let leg = find_path(world, position, exit, rules)?;
+        path.cost += leg.cost + step_cost(world, exit, entry, rules).unwrap_or(0);
+        path.nodes.extend_from_slice(&leg.nodes[1..]);
+        path.nodes.push(entry);
+        position = entry;
+    }
+    let leg = find_path(world, position, to, rules)?;
+    path.cost += leg.cost;
+    path.nodes.extend_from_slice(&leg.nodes[1..]);
+    Ok(path)
+}
+
+/// Last Block before and first Block after a Chunk border crossing
+type Portal = (BlockPosition, BlockPosition);
+
+/// Chunk level graph, discovered lazily
+struct Portals<'a> {
+    world: &'a World,
+    rules: &'a MoveRules,
+    goal: BlockPosition,
+    known: HashMap<(Vector2<u64>, Vector2<u64>), Option<Portal>>,
+}
+
+impl<'a> Portals<'a> {
+    /// Chunks from `start` to `goal` by A* over portals.
+    fn plan(&mut self, start: Vector2<u64>, goal: Vector2<u64>) -> Result<Vec<Vector2<u64>>, PathError> {
+        let mut open = BinaryHeap::new();
+        let mut best: HashMap<Vector2<u64>, u64> = HashMap::new();
+        let mut parent: HashMap<Vector2<u64>, Vector2<u64>> = HashMap::new();
+        best.insert(start, 0);
+        open.push(Reverse((chunk_distance(start, goal), 0, start)));
+        while let Some(Reverse((_, cost, chunk))) = open.pop() {
+            if chunk == goal {
+                let mut chunks = vec![goal];
+                let mut current = goal;
+                while let Some(&previous) = parent.get(&current) {
+                    chunks.push(previous);
+                    current = previous;
+                }
+                chunks.reverse();
+                return Ok(chunks);
+            }
+            if best.get(&chunk).is_some_and(|&known| known < cost) {
+                continue;
+            }
+            for next in chunk_neighbours(chunk) {
+                if !self.world.is_chunk_loaded(next) || self.between(chunk, next).is_none() {
+                    continue;
+                }
+                let total = cost + 1;
+                if best.get(&next).is_none_or(|&known| total < known) {
+                    best.insert(next, total);
+                    parent.insert(next, chunk);
+                    open.push(Reverse((total + chunk_distance(next, goal), total, next)));
+                }
+            }
+        }
+        Err(PathError::Unreachable)
+    }
+    /// Crossing from `from` into the adjacent `to`, the one nearest the goal.
+    fn between(&mut self, from: Vector2<u64>, to: Vector2<u64>) -> Option<Portal> {
+        if let Some(portal) = self.known.get(&(from, to)) {
+            return *portal;
+        }
+        let size = CHUNK_SIZE as u64;
+        let mut crossing: Option<(u64, BlockPosition, BlockPosition)> = None;
+        for along in 0..size {
+            let (x, z) = if from.x != to.x {
+                (if to.x > from.x { from.x * size + size - 1 } else { from.x * size }, from.y * size + along)
+            } else {
+                (from.x * size + along, if to.y > from.y { from.y * size + size - 1 } else { from.y * size })
+            };
+            for y in 0..CHUNK_SIZE {
+                let exit = (x, y, z);
+                if enter_cost(self.world, exit, self.rules).is_none() || !standable(self.world, exit, self.rules) {
+                    continue;
+                }
+                for (entry, _) in moves(self.world, exit, self.rules) {
+                    let score = distance(entry, self.goal) as u64;
+                    if chunk_of(entry.0, entry.2).0 == to && crossing.is_none_or(|(best, _, _)| score < best) {
+                        crossing = Some((score, exit, entry));
+                    }
+                }
+            }
+        }
+        let portal = crossing.map(|(_, exit, entry)| (exit, entry));
+        self.known.insert((from, to), portal);
+        portal
+    }
+}
+
+/// Legal moves out of a position and their cost.
+fn moves(world: &World, (x, y, z): BlockPosition, rules: &MoveRules) -> Vec<(BlockPosition, u32)> {
+    let mut result = Vec::new();
+    let vertical = rules.fly || rules.burrow.is_some();
+    for &(dx, dz) in [(1i64, 0i64), (-1, 0), (0, 1), (0, -1)].iter() {
+        let (nx, nz) = (x as i64 + dx, z as i64 + dz);
+        if nx < 0 || nz < 0 {
+            continue;
+        }
+        let (nx, nz) = (nx as u64, nz as u64);
+        let (low, high) = if vertical { (0, 0) } else { (rules.drop, rules.climb) };
+        for dy in -(low as i64)..(high as i64 + 1) {
+            let ny = y as i64 + dy;
+            if ny < 0 || ny >= CHUNK_SIZE as i64 {
+                continue;
+            }
+            let target = (nx, ny as usize, nz);
+            if let Some(cost) = step_cost(world, (x, y, z), target, rules) {
+                result.push((target, cost));
+            }
+        }
+    }
+    if vertical {
+        if y + 1 < CHUNK_SIZE {
+            if let Some(cost) = step_cost(world, (x, y, z), (x, y + 1, z), rules) {
+                result.push(((x, y + 1, z), cost));
+            }
+        }
+        if y > 0 {
+            if let Some(cost) = step_cost(world, (x, y, z), (x, y - 1, z), rules) {
+                result.push(((x, y - 1, z), cost));
+            }
+        }
+    }
+    result
+}
+
+/// Cost of moving between two nearby positions, None if the move isn't legal.
+pub fn step_cost(world: &World, from: BlockPosition, to: BlockPosition, rules: &MoveRules) -> Option<u32> {
+    let mut cost = enter_cost(world, to, rules)?;
+    if !standable(world, to, rules) {
+        return None;
+    }
+    if (from.0, from.2) == (to.0, to.2) {
+        return Some(cost + 1);
+    }
+    cost += 1 + (from.1 as i64 - to.1 as i64).unsigned_abs() as u32;
+    // Headroom above the lower end of a step up or down
+    let lower = if to.1 > from.1 { from } else { to };
+    for y in from.1.min(to.1) + 1..from.1.max(to.1) + 1 {
+        cost += enter_cost(world, (lower.0, y, lower.2), rules)?;
+    }
+    Some(cost)
+}
+
+/// Extra cost of occupying a Block, None if it can't be entered.
+fn enter_cost(world: &World, (x, y, z): BlockPosition, rules: &MoveRules) -> Option<u32> {
+    let block = world.get_block(x, y, z)?;
+    let resistance = world.materials().get(block.material()).map_or(0.0, |material| material.resistance());
+    if block.is_air() || resistance <= 0.0 {
+        Some(0)
+    } else {
+        rules.burrow.map(|cost| (resistance * cost as f32).ceil() as u32)
+    }
+}
+
+/// Whether a unit can rest in a Block: on solid ground, or inside it if burrowing.
+pub fn standable(world: &World, (x, y, z): BlockPosition, rules: &MoveRules) -> bool {
+    rules.fly || (y > 0 && world.is_solid(x, y - 1, z)) || (rules.burrow.is_some() && world.is_solid(x, y, z))
+}
+
+fn distance(a: BlockPosition, b: BlockPosition) -> u32 {
+    (a.0 as i64 - b.0 as i64).unsigned_abs() as u32
+        + (a.1 as i64 - b.1 as i64).unsigned_abs() as u32
+        + (a.2 as i64 - b.2 as i64).unsigned_abs() as u32
+}
+
+fn chunk_distance(a: Vector2<u64>, b: Vector2<u64>) -> u64 {
+    (a.x as i64 - b.x as i64).unsigned_abs() + (a.y as i64 - b.y as i64).unsigned_abs()
+}
+
+fn chunk_neighbours(chunk: Vector2<u64>) -> Vec<Vector2<u64>> {
+    let mut result = vec![Vector2::new(chunk.x + 1, chunk.y), Vector2::new(chunk.x, chunk.y + 1)];
+    if chunk.x > 0 {
+        result.push(Vector2::new(chunk.x - 1, chunk.y));
+    }
+    if chunk.y > 0 {
+        result.push(Vector2::new(chunk.x, chunk.y - 1));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_path, find_route, MoveRules, PathError};
+    use model::world::{Block, Chunk, Vector2, World};
+    use pool::Poolable;
+
+    /// Rock floor at y = 0 across `chunks` Chunks along x, with a wall at x = 10
+    /// three blocks high except for a gap at z = 20.
+    fn world(chunks: u64) -> World {
+        let mut world = World::new();
+        let rock = world.materials().id("rock").unwrap();
+        for cx in 0..chunks {
+            world.insert_chunk(Vector2::new(cx, 0), Chunk::allocate());
+        }
+        for x in 0..chunks * 32 {
+            for z in 0..32 {
+                world.set_block(x, 0, z, Block::new(rock));
+            }
+        }
+        for z in 0..32 {
+            for y in 1..4 {
+                if z != 20 {
+                    world.set_block(10, y, z, Block::new(rock));
+                }
+            }
+        }
+        world
+    }
+
+    #[test]
+    pub fn test_movement_rules() {
+        let world = world(1);
+        let (from, to) = ((5, 1, 5), (15, 1, 5));
+
+        // Walkers detour through the gap
+        let walk = find_path(&world, from, to, &MoveRules::walker()).unwrap();
+        assert_eq!(walk.start(), from);
+        assert_eq!(walk.goal(), to);
+        assert!(walk.nodes.contains(&(10, 1, 20)));
+        assert_eq!(walk.len(), 10 + 2 * 15);
+
+        // Climbers go over, flyers too
+        let climb = find_path(&world, from, to, &MoveRules { climb: 3, drop: 3, ..MoveRules::walker() }).unwrap();
+        assert!(climb.len() < walk.len());
+        assert!(climb.nodes.contains(&(10, 4, 5)));
+        assert!(find_path(&world, from, to, &MoveRules::flyer()).unwrap().len() < walk.len());
+
+        // Burrowers dig straight through
+        let dig = find_path(&world, from, to, &MoveRules::burrower(1)).unwrap();
+        assert_eq!(dig.len(), 10);
+        assert_eq!(dig.cost, 10 + 5);
+
+        assert_eq!(find_path(&world, from, (15, 0, 5), &MoveRules::walker()), Err(PathError::Blocked));
+        assert_eq!(find_path(&world, from, to, &MoveRules { max_nodes: 10, ..MoveRules::walker() }), Err(PathError::Budget));
+    }
+
+    #[test]
+    pub fn test_hierarchical_route() {
+        let world = world(4);
+        let (from, to) = ((15, 1, 5), (120, 1, 30));
+        let rules = MoveRules { max_nodes: 100, ..MoveRules::walker() };
+        assert_eq!(find_path(&world, from, to, &rules), Err(PathError::Budget));
+        let route = find_route(&world, from, to, &rules).unwrap();
+        assert_eq!(route.start(), from);
+        assert_eq!(route.goal(), to);
+        for pair in route.nodes.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!((a.0 as i64 - b.0 as i64).abs() + (a.2 as i64 - b.2 as i64).abs(), 1);
+        }
+        assert_eq!(route.cost as usize, route.len());
+    }
+}
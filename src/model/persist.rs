@@ -0,0 +1,432 @@
+///
+/// Entity Persistence
+///
+/// Entities unload with the Chunk they stand in and are written beside it in
+/// its region file. Components are type erased in the EntityManager, so only
+/// those registered with ComponentCodecs under a stable name are stored, each
+/// entity as a list of (name, payload) pairs:
+///
+/// ---+--------+---------------------------------------------------------------
+///  # | SIZE   | DESCRIPTION
+/// ---+--------+---------------------------------------------------------------
+///  1 | 2      | Format Version
+///  2 | 4      | Entity Count
+///  3 | ...    | Entities: component count u16, then per component its name
+///    |        | string, payload length u32 and payload
+/// ---+--------+---------------------------------------------------------------
+///
+/// Components no codec knows are skipped on restore, so a save outlives the
+/// removal of a component type. Restored entities get fresh EntityIDs, so
+/// components naming other entities implement MapEntities and are registered
+/// with `register_mapped`, letting whatever restores a group of entities,
+/// such as `model::bundle`, point them at the new ids. The Simulation adds the
+/// CPU an entity runs under `simulation::CPU_COMPONENT`.
+///
+use codec::{invalid_data, read_string, read_u16, read_u32, read_u64, read_u8, write_string, write_u16, write_u32, write_u64, write_u8};
+use math::Fixed;
+use migrate::{Artifact, Migrations};
+use model::component::{Collider, Position, TargetOf, Velocity};
+use model::dimension::WorldId;
+use model::entity::{EntityID, EntityManager};
+use std::io::{self, Read, Write};
+
+/// Format version of an entity list, see `migrate`
+pub const VERSION: u16 = 1;
+
+///
+/// Component which can be written to and read back from a save
+///
+pub trait Persistent: Sized {
+    fn save(&self, writer: &mut dyn Write) -> io::Result<()>;
+    fn load(reader: &mut dyn Read) -> io::Result<Self>;
+}
+
+impl Persistent for Position {
+    fn save(&self, writer: &mut dyn Write) -> io::Result<()> { write_fixed3(writer, self.x, self.y, self.z) }
+    fn load(reader: &mut dyn Read) -> io::Result<Position> {
+        let (x, y, z) = read_fixed3(reader)?;
+        Ok(Position::new(x, y, z))
+    }
+}
+
+impl Persistent for Velocity {
+    fn save(&self, writer: &mut dyn Write) -> io::Result<()> { write_fixed3(writer, self.x, self.y, self.z) }
+    fn load(reader: &mut dyn Read) -> io::Result<Velocity> {
+        let (x, y, z) = read_fixed3(reader)?;
+        Ok(Velocity::new(x, y, z))
+    }
+}
+
+impl Persistent for Collider {
+    fn save(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write_fixed3(writer, self.width, self.height, self.depth)?;
+        write_u8(writer, self.gravity as u8 | (self.grounded as u8) << 1)
+    }
+    fn load(reader: &mut dyn Read) -> io::Result<Collider> {
+        let (width, height, depth) = read_fixed3(reader)?;
+        let flags = read_u8(reader)?;
+        Ok(Collider { width, height, depth, gravity: flags & 1 != 0, grounded: flags & 2 != 0 })
+    }
+}
+
+impl Persistent for WorldId {
+    fn save(&self, writer: &mut dyn Write) -> io::Result<()> { write_u16(writer, self.0) }
+    fn load(reader: &mut dyn Read) -> io::Result<WorldId> { Ok(WorldId(read_u16(reader)?)) }
+}
+
+impl Persistent for TargetOf {
+    fn save(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write_u64(writer, self.0.slot() as u64)?;
+        write_u64(writer, self.0.suffix() as u64)
+    }
+    fn load(reader: &mut dyn Read) -> io::Result<TargetOf> { Ok(TargetOf(EntityID::new(read_u64(reader)? as usize, read_u64(reader)? as usize))) }
+}
+
+///
+/// Component holding the EntityIDs of other entities
+///
+pub trait MapEntities {
+    /// Replace every EntityID held with what `map` gives for it.
+    fn map_entities(&mut self, map: &dyn Fn(EntityID) -> EntityID);
+}
+
+impl MapEntities for TargetOf {
+    fn map_entities(&mut self, map: &dyn Fn(EntityID) -> EntityID) { self.0 = map(self.0) }
+}
+
+///
+/// Stored Components of one Entity
+///
+#[derive(Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct StoredEntity {
+    /// (codec name, payload) in codec registration order
+    pub components: Vec<(String, Vec<u8>)>,
+}
+
+impl StoredEntity {
+    /// Payload of the component stored under `name`.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.components.iter().find(|&(candidate, _)| candidate == name).map(|(_, payload)| &payload[..])
+    }
+    /// Decode the component stored under `name`.
+    pub fn component<C: Persistent>(&self, name: &str) -> Option<io::Result<C>> {
+        self.get(name).map(|mut payload| C::load(&mut payload))
+    }
+    pub fn save(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write_u16(writer, self.components.len() as u16)?;
+        for (name, payload) in self.components.iter() {
+            write_string(writer, name)?;
+            write_u32(writer, payload.len() as u32)?;
+            writer.write_all(payload)?;
+        }
+        Ok(())
+    }
+    pub fn load(reader: &mut dyn Read) -> io::Result<StoredEntity> {
+        let count = read_u16(reader)?;
+        let mut components = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let name = read_string(reader)?;
+            let length = read_u32(reader)? as u64;
+            let mut payload = Vec::new();
+            if reader.take(length).read_to_end(&mut payload)? as u64 != length {
+                return Err(invalid_data("truncated component"));
+            }
+            components.push((name, payload));
+        }
+        Ok(StoredEntity { components })
+    }
+}
+
+/// Write a Chunk's entities as a versioned list of StoredEntity.
+pub fn write_entities(writer: &mut dyn Write, entities: &[StoredEntity]) -> io::Result<()> {
+    write_u16(writer, VERSION)?;
+    write_u32(writer, entities.len() as u32)?;
+    for entity in entities.iter() {
+        entity.save(writer)?;
+    }
+    Ok(())
+}
+
+/// Read the rest of `reader` as an entity list, upgrading one saved by an earlier version.
+pub fn read_entities(reader: &mut dyn Read) -> io::Result<Vec<StoredEntity>> {
+    let bytes = Migrations::default().read(Artifact::Entities, reader)?;
+    let reader: &mut dyn Read = &mut &bytes[2..];
+    let count = read_u32(reader)?;
+    let mut entities = Vec::new();
+    for _ in 0..count {
+        entities.push(StoredEntity::load(reader)?);
+    }
+    Ok(entities)
+}
+
+type CaptureFn = fn(&EntityManager, EntityID) -> Option<io::Result<Vec<u8>>>;
+type RestoreFn = fn(&mut EntityManager, EntityID, &[u8]) -> io::Result<()>;
+type RemapFn = fn(&mut EntityManager, EntityID, &dyn Fn(EntityID) -> EntityID);
+
+#[derive(Clone)]
+struct Codec {
+    name: String,
+    capture: CaptureFn,
+    restore: RestoreFn,
+    remap: Option<RemapFn>,
+}
+
+fn capture<C: Persistent + Clone + 'static>(entities: &EntityManager, entity: EntityID) -> Option<io::Result<Vec<u8>>> {
+    entities.get_component::<C>(entity).map(|component| {
+        let mut payload = Vec::new();
+        component.save(&mut payload).map(|_| payload)
+    })
+}
+
+fn restore<C: Persistent + Clone + 'static>(entities: &mut EntityManager, entity: EntityID, mut payload: &[u8]) -> io::Result<()> {
+    entities.add_component(entity, C::load(&mut payload)?);
+    Ok(())
+}
+
+fn remap<C: MapEntities + 'static>(entities: &mut EntityManager, entity: EntityID, map: &dyn Fn(EntityID) -> EntityID) {
+    if let Some(component) = entities.get_component_mut::<C>(entity) {
+        component.map_entities(map);
+    }
+}
+
+///
+/// Persistent Component types by name
+///
+/// The default set stores Position, Velocity, Collider, WorldId and TargetOf.
+///
+#[derive(Clone)]
+pub struct ComponentCodecs {
+    codecs: Vec<Codec>,
+}
+
+impl ComponentCodecs {
+    /// Codecs for no components at all.
+    pub fn empty() -> ComponentCodecs { ComponentCodecs { codecs: Vec::new() } }
+    /// Store C under `name`, replacing any codec of that name.
+    pub fn register<C: Persistent + Clone + 'static>(&mut self, name: &str) {
+        self.insert(Codec { name: name.to_string(), capture: capture::<C>, restore: restore::<C>, remap: None });
+    }
+    /// Store C under `name`, remapping the entities it names when restored in a group.
+    pub fn register_mapped<C: Persistent + MapEntities + Clone + 'static>(&mut self, name: &str) {
+        self.insert(Codec { name: name.to_string(), capture: capture::<C>, restore: restore::<C>, remap: Some(remap::<C>) });
+    }
+    fn insert(&mut self, codec: Codec) {
+        match self.codecs.iter_mut().find(|existing| existing.name == codec.name) {
+            Some(existing) => *existing = codec,
+            None => self.codecs.push(codec),
+        }
+    }
+    pub fn is_registered(&self, name: &str) -> bool { self.codecs.iter().any(|codec| codec.name == name) }
+    /// Names of the registered codecs in registration order.
+    pub fn names(&self) -> Vec<&str> { self.codecs.iter().map(|codec| &codec.name[..]).collect() }
+    /// Serialize every persistent component of `entity`.
+    pub fn capture(&self, entities: &EntityManager, entity: EntityID) -> io::Result<StoredEntity> {
+        let mut components = Vec::new();
+        for codec in self.codecs.iter() {
+            if let Some(payload) = (codec.capture)(entities, entity) {
+                components.push((codec.name.clone(), payload?));
+            }
+        }
+        Ok(StoredEntity { components })
+    }
+    /// Create an entity from a StoredEntity, leaving nothing behind if a component fails to decode.
+    pub fn restore(&self, entities: &mut EntityManager, stored: &StoredEntity) -> io::Result<EntityID> {
+        let entity = entities.create_entity();
+        for (name, payload) in stored.components.iter() {
+            if let Some(codec) = self.codecs.iter().find(|codec| &codec.name == name) {
+                if let Err(error) = (codec.restore)(entities, entity, payload) {
+                    entities.destroy_entity(entity);
+                    return Err(error);
+                }
+            }
+        }
+        Ok(entity)
+    }
+    /// Point the entity references in `entity`'s mapped components through `map`.
+    pub fn remap(&self, entities: &mut EntityManager, entity: EntityID, map: &dyn Fn(EntityID) -> EntityID) {
+        for remap in self.codecs.iter().filter_map(|codec| codec.remap) {
+            remap(entities, entity, map);
+        }
+    }
+}
+
+impl Default for ComponentCodecs {
+    fn default() -> ComponentCodecs {
+        let mut codecs = ComponentCodecs::empty();
+        codecs.register::<Position>("position");
+        codecs.register::<Velocity>("velocity");
+        codecs.register::<Collider>("collider");
+        codecs.register::<WorldId>("world");
+        codecs.register_mapped::<TargetOf>("target");
+        codecs
+    }
+}
+
+fn write_fixed3(writer: &mut dyn Write, x: Fixed, y: Fixed, z: Fixed) -> io::Result<()> {
+    for value in [x, y, z].iter() {
+        write_u64(writer, value.raw() as u64)?;
+    }
+    Ok(())
+}
+
+fn read_fixed3(reader: &mut dyn Read) -> io::Result<(Fixed, Fixed, Fixed)> {
+    let x = Fixed::from_raw(read_u64(reader)? as i64);
+    let y = Fixed::from_raw(read_u64(reader)? as i64);
+    Ok((x, y, Fixed::from_raw(read_u64(reader)? as i64)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ComponentCodecs;
+    use model::component::{Collider, Position, Velocity};
+    use model::dimension::{world_of, WorldId};
+    use model::entity::EntityManager;
+    use model::world::{Chunk, Vector2, World};
+    use simulation::Simulation;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use vcpu::cluster::CpuComponent;
+
+    #[test]
+    pub fn test_entities_unload_with_their_chunk() {
+        let directory = env::temp_dir().join(format!("hivemind-persist-{}", process::id()));
+        let mut world = World::open(&directory).unwrap();
+        world.insert_chunk(Vector2::new(0, 0), Box::new(Chunk::new()));
+        world.insert_chunk(Vector2::new(1, 0), Box::new(Chunk::new()));
+        let mut simulation = Simulation::new(World::new(), EntityManager::new());
+        let underhive = simulation.add_world("underhive", world);
+
+        let crate_ = simulation.entities_mut().create_entity();
+        let collider = Collider { grounded: true, ..Collider::from_f64(0.8, 1.0, 0.8) };
+        simulation.entities_mut().add_component(crate_, Position::from_f64(5.5, 3.0, 5.5));
+        simulation.entities_mut().add_component(crate_, Velocity::from_f64(0.0, -1.5, 0.0));
+        simulation.entities_mut().add_component(crate_, collider);
+        simulation.entities_mut().add_component(crate_, WorldId(1));
+        simulation.entities_mut().add_component(crate_, "not persistent");
+        let neighbour = simulation.entities_mut().create_entity();
+        simulation.entities_mut().add_component(neighbour, Position::from_f64(40.0, 3.0, 5.5));
+        simulation.entities_mut().add_component(neighbour, WorldId(1));
+
+        // Only the entities standing in the Chunk go with it
+        assert!(simulation.unload_chunk(underhive, Vector2::new(0, 0)).unwrap());
+        assert!(!simulation.entities().is_alive(crate_));
+        assert!(simulation.entities().is_alive(neighbour));
+        let world = simulation.get_world_mut(underhive).unwrap();
+        assert!(world.load_chunk(Vector2::new(0, 0)).unwrap());
+        let pending: Vec<_> = world.pending_entities(Vector2::new(0, 0)).collect();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].component::<Position>("position").unwrap().unwrap(), Position::from_f64(5.5, 3.0, 5.5));
+        assert!(world.unload_chunk(Vector2::new(0, 0)).unwrap());
+
+        // And come back with it, without the components nothing knows how to store
+        let spawned = simulation.load_chunk(underhive, Vector2::new(0, 0)).unwrap();
+        assert_eq!(spawned.len(), 1);
+        let entities = simulation.entities();
+        assert_eq!(entities.get_component::<Collider>(spawned[0]), Some(&collider));
+        assert_eq!(entities.get_component::<Velocity>(spawned[0]), Some(&Velocity::from_f64(0.0, -1.5, 0.0)));
+        assert_eq!(world_of(entities, spawned[0]), underhive);
+        assert!(entities.get_component::<&str>(spawned[0]).is_none());
+        assert_eq!(simulation.get_world(underhive).unwrap().pending_entities(Vector2::new(0, 0)).count(), 0);
+
+        // Unknown components are skipped
+        let mut codecs = ComponentCodecs::empty();
+        codecs.register::<Velocity>("velocity");
+        let stored = ComponentCodecs::default().capture(simulation.entities(), spawned[0]).unwrap();
+        let mut entities = EntityManager::new();
+        let copy = codecs.restore(&mut entities, &stored).unwrap();
+        assert!(entities.get_component::<Velocity>(copy).is_some() && entities.get_component::<Position>(copy).is_none());
+
+        // A full save stores the entities standing in loaded Chunks and leaves them live
+        simulation.save().unwrap();
+        assert!(simulation.entities().is_alive(neighbour));
+        let mut reopened = World::open(&directory).unwrap();
+        assert!(reopened.load_chunk(Vector2::new(1, 0)).unwrap());
+        let pending: Vec<_> = reopened.pending_entities(Vector2::new(1, 0)).collect();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].component::<Position>("position").unwrap().unwrap(), Position::from_f64(40.0, 3.0, 5.5));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    pub fn test_spawned_entities_survive_saves() {
+        let directory = env::temp_dir().join(format!("hivemind-persist-saves-{}", process::id()));
+        let mut world = World::open(&directory).unwrap();
+        world.insert_chunk(Vector2::new(0, 0), Box::new(Chunk::new()));
+        world.insert_chunk(Vector2::new(1, 0), Box::new(Chunk::new()));
+        let mut simulation = Simulation::new(World::new(), EntityManager::new());
+        let underhive = simulation.add_world("underhive", world);
+        let crate_ = simulation.entities_mut().create_entity();
+        simulation.entities_mut().add_component(crate_, Position::from_f64(5.5, 3.0, 5.5));
+        simulation.entities_mut().add_component(crate_, WorldId(1));
+        assert_eq!(simulation.save_dirty().unwrap(), 2);
+
+        // Loading spawns the stored entities and dirties the Chunk, saving writes them back
+        assert!(simulation.unload_chunk(underhive, Vector2::new(0, 0)).unwrap());
+        let spawned = simulation.load_chunk(underhive, Vector2::new(0, 0)).unwrap();
+        assert_eq!(spawned.len(), 1);
+        assert_eq!(simulation.save_dirty().unwrap(), 1);
+        let mut reopened = World::open(&directory).unwrap();
+        assert!(reopened.load_chunk(Vector2::new(0, 0)).unwrap());
+        assert_eq!(reopened.pending_entities(Vector2::new(0, 0)).count(), 1);
+
+        // Entities moving between clean Chunks rewrite both their entities
+        simulation.entities_mut().add_component(spawned[0], Position::from_f64(40.0, 3.0, 5.5));
+        assert!(simulation.get_world(underhive).unwrap().dirty_chunks().is_empty());
+        assert_eq!(simulation.save_dirty().unwrap(), 2);
+        assert_eq!(simulation.save_dirty().unwrap(), 0);
+        let mut reopened = World::open(&directory).unwrap();
+        assert!(reopened.load_chunk(Vector2::new(0, 0)).unwrap());
+        assert!(reopened.load_chunk(Vector2::new(1, 0)).unwrap());
+        assert_eq!(reopened.pending_entities(Vector2::new(0, 0)).count(), 0);
+        let pending: Vec<_> = reopened.pending_entities(Vector2::new(1, 0)).collect();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].component::<Position>("position").unwrap().unwrap(), Position::from_f64(40.0, 3.0, 5.5));
+
+        // And a Chunk left behind by its saved entities doesn't bring them back
+        let moved = spawned[0];
+        assert!(simulation.unload_chunk(underhive, Vector2::new(0, 0)).unwrap());
+        assert!(simulation.load_chunk(underhive, Vector2::new(0, 0)).unwrap().is_empty());
+        assert!(simulation.entities().is_alive(moved));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    pub fn test_saved_world_survives_restart() {
+        let directory = env::temp_dir().join(format!("hivemind-persist-restart-{}", process::id()));
+        let mut simulation = Simulation::new(World::open(&directory).unwrap(), EntityManager::new());
+        simulation.world_mut().insert_chunk(Vector2::new(0, 0), Box::new(Chunk::new()));
+        let crate_ = simulation.entities_mut().create_entity();
+        simulation.entities_mut().add_component(crate_, Position::from_f64(5.5, 3.0, 5.5));
+        let drone = simulation.entities_mut().create_entity();
+        simulation.entities_mut().add_component(drone, Position::from_f64(1.5, 3.0, 1.5));
+        // ADD [0x8000], 1 then SET PC, 0
+        let cpu = simulation.attach(drone, &[0x8BC2, 0x8000, 0x8781]).unwrap();
+        simulation.entities_mut().get_component_mut::<CpuComponent>(drone).unwrap().clock = 30;
+        simulation.step();
+        let counter = simulation.cluster().get(cpu).unwrap().get_memory(0x8000);
+        assert!(counter > 0);
+        simulation.save().unwrap();
+        drop(simulation);
+
+        // A fresh Simulation over the same directory comes back with both, the drone's CPU running
+        let mut restarted = Simulation::new(World::open(&directory).unwrap(), EntityManager::new());
+        assert_eq!(restarted.load_saved().unwrap(), 1);
+        assert_eq!(restarted.entities().iter::<Position>().count(), 2);
+        let (drone, _) = restarted.entities().iter::<Position>().find(|&(_, at)| *at == Position::from_f64(1.5, 3.0, 1.5)).unwrap();
+        let component = restarted.entities().get_component::<CpuComponent>(drone).unwrap().clone();
+        assert_eq!(component.clock, 30);
+        assert_eq!(restarted.cluster().owner(component.cpu), Some(drone));
+        assert_eq!(restarted.cluster().get(component.cpu).unwrap().get_memory(0x8000), counter);
+        restarted.step();
+        assert!(restarted.cluster().get(component.cpu).unwrap().get_memory(0x8000) > counter);
+
+        // Unloading releases the CPU along with its entity
+        assert!(restarted.unload_chunk(WorldId(0), Vector2::new(0, 0)).unwrap());
+        assert!(restarted.cluster().is_empty());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
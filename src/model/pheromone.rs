@@ -0,0 +1,208 @@
+///
+/// Pheromone Fields
+///
+/// Named scalar channels laid over the World one Chunk at a time. Units deposit
+/// strength at a Block; every tick each channel spreads a fraction of every
+/// Block's strength evenly to its open, loaded neighbours and then decays.
+/// Solid Blocks neither hold nor pass a scent. Chunks of a channel which have
+/// faded below MIN_STRENGTH everywhere are dropped.
+///
+use model::world::{chunk_of, Vector2, World, CHUNK_SIZE};
+use std::collections::HashMap;
+
+/// Strength below which a Block reads as zero
+pub const MIN_STRENGTH: f32 = 0.01;
+
+///
+/// Compact Channel Identifier
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Default)]
+pub struct ChannelId(u16);
+
+impl ChannelId {
+    pub fn new(id: u16) -> ChannelId { ChannelId(id) }
+    pub fn id(&self) -> u16 { self.0 }
+    pub fn index(&self) -> usize { self.0 as usize }
+}
+
+///
+/// Channel Definition
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct Channel {
+    name: String,
+    /// Fraction lost per tick
+    decay: f32,
+    /// Fraction spread to neighbours per tick
+    diffusion: f32,
+}
+
+impl Channel {
+    pub fn new(name: &str, decay: f32, diffusion: f32) -> Channel {
+        Channel { name: name.to_string(), decay: decay.clamp(0.0, 1.0), diffusion: diffusion.clamp(0.0, 1.0) }
+    }
+    pub fn name(&self) -> &str { &self.name }
+    pub fn decay(&self) -> f32 { self.decay }
+    pub fn diffusion(&self) -> f32 { self.diffusion }
+}
+
+type Layer = Vec<f32>;
+
+fn index(x: usize, y: usize, z: usize) -> usize { (x * CHUNK_SIZE + y) * CHUNK_SIZE + z }
+
+///
+/// Pheromone Channels over the World
+///
+#[derive(Clone, Default, Debug)]
+pub struct PheromoneField {
+    channels: Vec<Channel>,
+    names: HashMap<String, ChannelId>,
+    layers: HashMap<(ChannelId, Vector2<u64>), Layer>,
+}
+
+impl PheromoneField {
+    pub fn new() -> PheromoneField { PheromoneField::default() }
+    ///
+    /// Register a channel, returning its id. Registering a name that already
+    /// exists replaces its rates and keeps the existing id.
+    ///
+    pub fn register(&mut self, channel: Channel) -> ChannelId {
+        if let Some(&id) = self.names.get(channel.name()) {
+            self.channels[id.index()] = channel;
+            return id;
+        }
+        let id = ChannelId(self.channels.len() as u16);
+        self.names.insert(channel.name().to_string(), id);
+        self.channels.push(channel);
+        id
+    }
+    pub fn id(&self, name: &str) -> Option<ChannelId> { self.names.get(name).cloned() }
+    pub fn channel(&self, id: ChannelId) -> Option<&Channel> { self.channels.get(id.index()) }
+    pub fn channels(&self) -> impl Iterator<Item=(ChannelId, &Channel)> {
+        self.channels.iter().enumerate().map(|(idx, channel)| (ChannelId(idx as u16), channel))
+    }
+    /// Number of Chunk layers held across all channels.
+    pub fn layers(&self) -> usize { self.layers.len() }
+    /// Strength at a Block, zero where nothing was laid.
+    pub fn get(&self, channel: ChannelId, x: u64, y: usize, z: u64) -> f32 {
+        if y >= CHUNK_SIZE {
+            return 0.0;
+        }
+        let (chunk, lx, lz) = chunk_of(x, z);
+        self.layers.get(&(channel, chunk)).map_or(0.0, |layer| layer[index(lx, y, lz)])
+    }
+    /// Overwrite the strength at a Block.
+    pub fn set(&mut self, channel: ChannelId, x: u64, y: usize, z: u64, strength: f32) {
+        if y >= CHUNK_SIZE || self.channel(channel).is_none() {
+            return;
+        }
+        let (chunk, lx, lz) = chunk_of(x, z);
+        let layer = self.layers.entry((channel, chunk)).or_insert_with(|| vec![0.0; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE]);
+        layer[index(lx, y, lz)] = strength.max(0.0);
+    }
+    /// Add strength at a Block, returning the new strength.
+    pub fn deposit(&mut self, channel: ChannelId, x: u64, y: usize, z: u64, amount: f32) -> f32 {
+        let strength = self.get(channel, x, y, z) + amount;
+        self.set(channel, x, y, z, strength);
+        self.get(channel, x, y, z)
+    }
+    /// Forget every channel within a Chunk.
+    pub fn remove_chunk(&mut self, position: Vector2<u64>) {
+        self.layers.retain(|&(_, chunk), _| chunk != position);
+    }
+    ///
+    /// Diffuse and decay every channel by one tick.
+    ///
+    pub fn tick(&mut self, world: &World) {
+        let mut next: HashMap<(ChannelId, Vector2<u64>), Layer> = HashMap::new();
+        for (&(channel, chunk), layer) in self.layers.iter() {
+            let share = self.channels[channel.index()].diffusion / 6.0;
+            let (ox, oz) = (chunk.x * CHUNK_SIZE as u64, chunk.y * CHUNK_SIZE as u64);
+            for lx in 0..CHUNK_SIZE {
+                for y in 0..CHUNK_SIZE {
+                    for lz in 0..CHUNK_SIZE {
+                        let strength = layer[index(lx, y, lz)];
+                        if strength <= 0.0 {
+                            continue;
+                        }
+                        let (x, z) = (ox + lx as u64, oz + lz as u64);
+                        let mut kept = strength;
+                        for &(dx, dy, dz) in [(-1i64, 0i64, 0i64), (1, 0, 0), (0, -1, 0), (0, 1, 0), (0, 0, -1), (0, 0, 1)].iter() {
+                            let (nx, ny, nz) = (x as i64 + dx, y as i64 + dy, z as i64 + dz);
+                            if nx < 0 || nz < 0 || ny < 0 || ny >= CHUNK_SIZE as i64 {
+                                continue;
+                            }
+                            let (nx, ny, nz) = (nx as u64, ny as usize, nz as u64);
+                            if world.get_block(nx, ny, nz).is_none() || world.is_solid(nx, ny, nz) {
+                                continue;
+                            }
+                            kept -= strength * share;
+                            add(&mut next, channel, nx, ny, nz, strength * share);
+                        }
+                        add(&mut next, channel, x, y, z, kept);
+                    }
+                }
+            }
+        }
+        for (&(channel, _), layer) in next.iter_mut() {
+            let keep = 1.0 - self.channels[channel.index()].decay;
+            for strength in layer.iter_mut() {
+                *strength *= keep;
+                if *strength < MIN_STRENGTH {
+                    *strength = 0.0;
+                }
+            }
+        }
+        next.retain(|_, layer| layer.iter().any(|&strength| strength > 0.0));
+        self.layers = next;
+    }
+}
+
+fn add(layers: &mut HashMap<(ChannelId, Vector2<u64>), Layer>, channel: ChannelId, x: u64, y: usize, z: u64, amount: f32) {
+    let (chunk, lx, lz) = chunk_of(x, z);
+    let layer = layers.entry((channel, chunk)).or_insert_with(|| vec![0.0; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE]);
+    layer[index(lx, y, lz)] += amount;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Channel, PheromoneField};
+    use model::world::{Block, Chunk, Vector2, World};
+    use pool::Poolable;
+
+    #[test]
+    pub fn test_pheromone_diffusion_and_decay() {
+        let mut world = World::new();
+        world.insert_chunk(Vector2::new(0, 0), Chunk::allocate());
+        world.insert_chunk(Vector2::new(1, 0), Chunk::allocate());
+        let rock = Block::new(world.materials().id("rock").unwrap());
+        world.set_block(30, 5, 5, rock);
+
+        let mut field = PheromoneField::new();
+        let trail = field.register(Channel::new("trail", 0.0, 0.6));
+        let alarm = field.register(Channel::new("alarm", 0.5, 0.0));
+        field.deposit(trail, 31, 5, 5, 60.0);
+        field.deposit(alarm, 2, 2, 2, 1.0);
+        field.tick(&world);
+
+        // Spread to the five open neighbours, one across the Chunk border, none into rock
+        assert!((field.get(trail, 31, 5, 5) - 30.0).abs() < 1e-4);
+        assert!((field.get(trail, 32, 5, 5) - 6.0).abs() < 1e-4);
+        assert!((field.get(trail, 31, 6, 5) - 6.0).abs() < 1e-4);
+        assert_eq!(field.get(trail, 30, 5, 5), 0.0);
+        assert_eq!(field.get(alarm, 2, 2, 2), 0.5);
+
+        // Without decay the total is conserved, with it the field eventually fades away
+        let total: f32 = (28..36).flat_map(|x| (2..9).flat_map(move |y| (1..9).map(move |z| (x, y, z))))
+            .map(|(x, y, z)| field.get(trail, x, y, z))
+            .sum();
+        assert!((total - 60.0).abs() < 1e-3);
+        for _ in 0..10 {
+            field.tick(&world);
+        }
+        assert_eq!(field.get(alarm, 2, 2, 2), 0.0);
+        assert_eq!(field.layers(), 2);
+        field.remove_chunk(Vector2::new(1, 0));
+        assert_eq!(field.layers(), 1);
+    }
+}
@@ -0,0 +1,233 @@
+///
+/// Motion Integration and Block Collision
+///
+/// Entities with a Position and Velocity move every step. Those with a Collider
+/// also fall under gravity and are swept one axis at a time (vertical first)
+/// against solid Blocks, stopping flush with whatever they run into. The bottom
+/// of the world is solid, unloaded Chunks are not.
+///
+use math::Fixed;
+use model::component::{Collider, Position, Velocity};
+use model::dimension::{world_of, WorldId};
+use model::entity::{EntityID, EntityManager};
+use model::raycast::Face;
+use model::update::BlockPosition;
+use model::world::{World, CHUNK_SIZE};
+
+///
+/// Collision Event
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Collision {
+    pub entity: EntityID,
+    /// Block run into, None for the bottom of the world
+    pub block: Option<BlockPosition>,
+    /// Face of the Block that was hit
+    pub face: Face,
+    /// Speed along the blocked axis at impact, in Blocks per second
+    pub speed: Fixed,
+}
+
+///
+/// Physics System
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PhysicsSystem {
+    /// Vertical acceleration in Blocks per second squared
+    pub gravity: Fixed,
+    /// Fastest fall in Blocks per second
+    pub terminal_velocity: Fixed,
+    /// Seconds per step
+    pub timestep: Fixed,
+}
+
+impl PhysicsSystem {
+    pub fn new() -> PhysicsSystem {
+        PhysicsSystem {
+            gravity: Fixed::from_int(-20),
+            terminal_velocity: Fixed::from_int(50),
+            timestep: Fixed::ONE / Fixed::from_int(20),
+        }
+    }
+    /// Advance every moving entity one timestep, returning the collisions in entity order.
+    pub fn step(&self, world: &World, entities: &mut EntityManager) -> Vec<Collision> { self.step_entities(world, entities, None) }
+    /// Step only the entities living in World `id`, which is `world`.
+    pub fn step_in(&self, world: &World, entities: &mut EntityManager, id: WorldId) -> Vec<Collision> { self.step_entities(world, entities, Some(id)) }
+    fn step_entities(&self, world: &World, entities: &mut EntityManager, id: Option<WorldId>) -> Vec<Collision> {
+        let moving: Vec<EntityID> = entities.iter::<Velocity>()
+            .map(|(entity, _)| entity)
+            .filter(|&entity| id.is_none_or(|id| world_of(entities, entity) == id))
+            .collect();
+        let mut collisions = Vec::new();
+        for entity in moving {
+            let (mut position, mut velocity) = match (entities.get_component::<Position>(entity), entities.get_component::<Velocity>(entity)) {
+                (Some(&position), Some(&velocity)) => (position, velocity),
+                _ => continue,
+            };
+            match entities.get_component::<Collider>(entity).cloned() {
+                Some(mut collider) => {
+                    self.collide(world, entity, &mut position, &mut velocity, &mut collider, &mut collisions);
+                    entities.add_component(entity, collider);
+                }
+                None => {
+                    position = Position::from_vector(position.vector() + velocity.vector() * self.timestep);
+                }
+            }
+            entities.add_component(entity, position);
+            entities.add_component(entity, velocity);
+        }
+        collisions
+    }
+    fn collide(&self, world: &World, entity: EntityID, position: &mut Position, velocity: &mut Velocity, collider: &mut Collider, collisions: &mut Vec<Collision>) {
+        if collider.gravity {
+            velocity.y = (velocity.y + self.gravity * self.timestep).max(-self.terminal_velocity);
+        }
+        let half = [collider.width * Fixed::HALF, collider.height * Fixed::HALF, collider.depth * Fixed::HALF];
+        let mut centre = [position.x, position.y, position.z];
+        let mut speed = [velocity.x, velocity.y, velocity.z];
+        let was_grounded = collider.grounded;
+        collider.grounded = false;
+        for &axis in [1, 0, 2].iter() {
+            let delta = speed[axis] * self.timestep;
+            let (moved, hit) = sweep(world, &centre, &half, axis, delta);
+            centre[axis] += moved;
+            if let Some(block) = hit {
+                let landing = axis == 1 && delta.is_negative();
+                collider.grounded |= landing;
+                // Resting on the ground isn't a new collision every step
+                if !(landing && was_grounded && moved == Fixed::ZERO) {
+                    collisions.push(Collision { entity, block, face: face(axis, delta), speed: speed[axis].abs() });
+                }
+                speed[axis] = Fixed::ZERO;
+            }
+        }
+        *position = Position::new(centre[0], centre[1], centre[2]);
+        *velocity = Velocity::new(speed[0], speed[1], speed[2]);
+    }
+}
+
+impl Default for PhysicsSystem {
+    fn default() -> PhysicsSystem { PhysicsSystem::new() }
+}
+
+/// Face of a Block struck when moving along `axis` in the direction of `delta`.
+fn face(axis: usize, delta: Fixed) -> Face {
+    match (axis, delta.is_positive()) {
+        (0, true) => Face::NegX,
+        (0, false) => Face::PosX,
+        (1, true) => Face::NegY,
+        (1, false) => Face::PosY,
+        (_, true) => Face::NegZ,
+        (_, false) => Face::PosZ,
+    }
+}
+
+///
+/// Move a box along one axis, returning how far it got and what stopped it.
+/// Only cells the box doesn't already overlap are tested, so a box spawned
+/// inside a Block can still move out of it.
+///
+fn sweep(world: &World, centre: &[Fixed; 3], half: &[Fixed; 3], axis: usize, delta: Fixed) -> (Fixed, Option<Option<BlockPosition>>) {
+    if delta == Fixed::ZERO {
+        return (Fixed::ZERO, None);
+    }
+    let mut span = [(0i64, 0i64); 3];
+    for other in 0..3 {
+        span[other] = ((centre[other] - half[other]).floor(), (centre[other] + half[other]).ceil() - 1);
+    }
+    let cells: Vec<i64> = if delta.is_positive() {
+        let lead = centre[axis] + half[axis];
+        (lead.ceil()..(lead + delta).ceil()).collect()
+    } else {
+        let lead = centre[axis] - half[axis];
+        ((lead + delta).floor()..lead.floor()).rev().collect()
+    };
+    for cell in cells {
+        span[axis] = (cell, cell);
+        if let Some(block) = first_solid(world, &span) {
+            let boundary = Fixed::from_int(if delta.is_positive() { cell } else { cell + 1 });
+            let lead = centre[axis] + if delta.is_positive() { half[axis] } else { -half[axis] };
+            return (boundary - lead, Some(block));
+        }
+    }
+    (delta, None)
+}
+
+/// First solid cell within an inclusive range of cells.
+fn first_solid(world: &World, span: &[(i64, i64); 3]) -> Option<Option<BlockPosition>> {
+    if span[1].0 < 0 {
+        return Some(None);
+    }
+    for x in span[0].0..span[0].1 + 1 {
+        for y in span[1].0..span[1].1 + 1 {
+            for z in span[2].0..span[2].1 + 1 {
+                if x >= 0 && z >= 0 && y < CHUNK_SIZE as i64 && world.is_solid(x as u64, y as usize, z as u64) {
+                    return Some(Some((x as u64, y as usize, z as u64)));
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PhysicsSystem;
+    use math::Fixed;
+    use model::component::{Collider, Position, Velocity};
+    use model::entity::EntityManager;
+    use model::raycast::Face;
+    use model::world::{Block, Chunk, Vector2, World};
+    use pool::Poolable;
+
+    /// Rock floor at y = 0 with a wall at x = 10
+    fn world() -> World {
+        let mut world = World::new();
+        let rock = world.materials().id("rock").unwrap();
+        world.insert_chunk(Vector2::new(0, 0), Chunk::allocate());
+        for x in 0..32 {
+            for z in 0..32 {
+                world.set_block(x, 0, z, Block::new(rock));
+                for y in 1..4 {
+                    if x == 10 {
+                        world.set_block(x, y, z, Block::new(rock));
+                    }
+                }
+            }
+        }
+        world
+    }
+
+    #[test]
+    pub fn test_falling_and_walls() {
+        let world = world();
+        let physics = PhysicsSystem::new();
+        let mut entities = EntityManager::new();
+        let crate_ = entities.create_entity();
+        entities.add_component(crate_, Position::from_f64(5.5, 8.0, 5.5));
+        entities.add_component(crate_, Velocity::from_f64(6.0, 0.0, 0.0));
+        entities.add_component(crate_, Collider::from_f64(0.8, 1.0, 0.8));
+        let dust = entities.create_entity();
+        entities.add_component(dust, Position::from_f64(1.0, 1.0, 1.0));
+        entities.add_component(dust, Velocity::from_f64(0.0, 2.0, 0.0));
+
+        let mut collisions = Vec::new();
+        for _ in 0..40 {
+            collisions.extend(physics.step(&world, &mut entities));
+        }
+
+        // Hit the wall on the way down and landed, once each
+        let position = *entities.get_component::<Position>(crate_).unwrap();
+        assert_eq!(position, Position::from_f64(9.6, 1.5, 5.5));
+        assert!(entities.get_component::<Collider>(crate_).unwrap().grounded);
+        assert_eq!(*entities.get_component::<Velocity>(crate_).unwrap(), Velocity::default());
+        let faces: Vec<Face> = collisions.iter().map(|collision| collision.face).collect();
+        assert_eq!(faces, vec![Face::NegX, Face::PosY]);
+        assert_eq!(collisions[0].block.map(|block| block.0), Some(10));
+        assert_eq!(collisions[1].block.map(|block| block.1), Some(0));
+
+        // No Collider, no gravity or collision, and exactly 40 equal steps
+        let rise = Fixed::from_int(2) * physics.timestep;
+        assert_eq!(entities.get_component::<Position>(dust).unwrap().y, Fixed::ONE + rise * Fixed::from_int(40));
+    }
+}
@@ -0,0 +1,274 @@
+///
+/// Energy Networks
+///
+/// Entities with a Producer, Consumer, Storage or Conduit join a network with
+/// any other such entity in the same or a face adjacent Block, and with those
+/// named by a PowerLink. Each tick every network's production and stored charge
+/// is shared among its consumers in entity order; a consumer is either fully
+/// powered or not at all. Surplus production charges storage.
+///
+use model::component::Position;
+use model::entity::{EntityID, EntityManager};
+use std::collections::HashMap;
+
+///
+/// Generates energy every tick
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Producer {
+    pub output: u32,
+}
+
+///
+/// Needs energy every tick
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Consumer {
+    pub demand: u32,
+    /// Demand was met during the last tick
+    pub powered: bool,
+}
+
+impl Consumer {
+    /// Consumers start powered, so a first tick without supply is a brownout.
+    pub fn new(demand: u32) -> Consumer { Consumer { demand, powered: true } }
+}
+
+///
+/// Holds energy between ticks
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Storage {
+    pub capacity: u32,
+    pub charge: u32,
+    /// Most energy moved in or out per tick
+    pub rate: u32,
+}
+
+impl Storage {
+    pub fn new(capacity: u32, rate: u32) -> Storage { Storage { capacity, charge: 0, rate } }
+}
+
+///
+/// Carries energy without producing or using it
+///
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Conduit;
+
+///
+/// Explicit connections to other entities, regardless of distance
+///
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct PowerLink {
+    pub links: Vec<EntityID>,
+}
+
+///
+/// Power State Change
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PowerEvent {
+    /// A consumer's demand stopped being met
+    Brownout(EntityID),
+    /// A consumer's demand is met again
+    Restored(EntityID),
+}
+
+///
+/// Connected set of entities as of the last tick
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PowerNetwork {
+    /// Members in entity order
+    pub members: Vec<EntityID>,
+    pub production: u32,
+    pub demand: u32,
+    /// Demand which was met
+    pub supplied: u32,
+    /// Charge held after the tick
+    pub stored: u32,
+}
+
+///
+/// Power Distribution System
+///
+#[derive(Clone, Default, Debug)]
+pub struct PowerSystem {
+    networks: Vec<PowerNetwork>,
+}
+
+impl PowerSystem {
+    pub fn new() -> PowerSystem { PowerSystem::default() }
+    /// Networks found by the last tick, ordered by their first member.
+    pub fn networks(&self) -> &[PowerNetwork] { &self.networks }
+    /// Network containing an entity as of the last tick.
+    pub fn network_of(&self, entity: EntityID) -> Option<&PowerNetwork> {
+        self.networks.iter().find(|network| network.members.binary_search(&entity).is_ok())
+    }
+    ///
+    /// Rebuild the networks and distribute one tick of energy, returning the
+    /// consumers whose power state changed in entity order.
+    ///
+    pub fn tick(&mut self, entities: &mut EntityManager) -> Vec<PowerEvent> {
+        let networks = build_networks(entities);
+        let mut events = Vec::new();
+        self.networks = networks.into_iter().map(|members| distribute(entities, members, &mut events)).collect();
+        events.sort_by_key(|event| match *event {
+            PowerEvent::Brownout(entity) | PowerEvent::Restored(entity) => entity,
+        });
+        events
+    }
+}
+
+/// Members of every network, each sorted, ordered by first member.
+fn build_networks(entities: &EntityManager) -> Vec<Vec<EntityID>> {
+    let mut nodes: Vec<EntityID> = entities.iter::<Producer>().map(|(entity, _)| entity)
+        .chain(entities.iter::<Consumer>().map(|(entity, _)| entity))
+        .chain(entities.iter::<Storage>().map(|(entity, _)| entity))
+        .chain(entities.iter::<Conduit>().map(|(entity, _)| entity))
+        .chain(entities.iter::<PowerLink>().map(|(entity, _)| entity))
+        .collect();
+    nodes.sort();
+    nodes.dedup();
+    let index: HashMap<EntityID, usize> = nodes.iter().enumerate().map(|(i, &entity)| (entity, i)).collect();
+    let mut parent: Vec<usize> = (0..nodes.len()).collect();
+
+    let mut cells: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+    for (i, &entity) in nodes.iter().enumerate() {
+        if let Some(position) = entities.get_component::<Position>(entity) {
+            cells.entry(position.block()).or_default().push(i);
+        }
+    }
+    for (&(x, y, z), members) in cells.iter() {
+        for &i in members.iter() {
+            union(&mut parent, i, members[0]);
+        }
+        for &(dx, dy, dz) in [(1, 0, 0), (0, 1, 0), (0, 0, 1)].iter() {
+            if let Some(neighbours) = cells.get(&(x + dx, y + dy, z + dz)) {
+                union(&mut parent, members[0], neighbours[0]);
+            }
+        }
+    }
+    for (entity, link) in entities.iter::<PowerLink>() {
+        for other in link.links.iter() {
+            if let Some(&j) = index.get(other) {
+                union(&mut parent, index[&entity], j);
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<EntityID>> = HashMap::new();
+    for (i, &entity) in nodes.iter().enumerate() {
+        let root = find(&mut parent, i);
+        groups.entry(root).or_default().push(entity);
+    }
+    let mut networks: Vec<Vec<EntityID>> = groups.into_values().collect();
+    networks.sort();
+    networks
+}
+
+fn find(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+fn union(parent: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parent, a), find(parent, b));
+    if a != b {
+        parent[a.max(b)] = a.min(b);
+    }
+}
+
+/// Share one tick of energy within a network.
+fn distribute(entities: &mut EntityManager, members: Vec<EntityID>, events: &mut Vec<PowerEvent>) -> PowerNetwork {
+    let production: u32 = members.iter().filter_map(|&entity| entities.get_component::<Producer>(entity)).map(|producer| producer.output).sum();
+    let available: u32 = members.iter().filter_map(|&entity| entities.get_component::<Storage>(entity))
+        .map(|storage| storage.rate.min(storage.charge))
+        .sum();
+    let mut budget = production + available;
+    let (mut demand, mut supplied) = (0, 0);
+    for &entity in members.iter() {
+        if let Some(consumer) = entities.get_component_mut::<Consumer>(entity) {
+            demand += consumer.demand;
+            let powered = consumer.demand <= budget;
+            if powered {
+                budget -= consumer.demand;
+                supplied += consumer.demand;
+            }
+            if powered != consumer.powered {
+                consumer.powered = powered;
+                events.push(if powered { PowerEvent::Restored(entity) } else { PowerEvent::Brownout(entity) });
+            }
+        }
+    }
+    // Draw any shortfall from storage, or bank the surplus
+    let (mut draw, mut surplus) = (supplied.saturating_sub(production), production.saturating_sub(supplied));
+    let mut stored = 0;
+    for &entity in members.iter() {
+        if let Some(storage) = entities.get_component_mut::<Storage>(entity) {
+            let out = draw.min(storage.rate).min(storage.charge);
+            draw -= out;
+            storage.charge -= out;
+            let into = surplus.min(storage.rate).min(storage.capacity.saturating_sub(storage.charge));
+            surplus -= into;
+            storage.charge += into;
+            stored += storage.charge;
+        }
+    }
+    PowerNetwork { members, production, demand, supplied, stored }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Conduit, Consumer, PowerEvent, PowerLink, PowerSystem, Producer, Storage};
+    use model::component::Position;
+    use model::entity::EntityManager;
+
+    #[test]
+    pub fn test_power_distribution() {
+        let mut entities = EntityManager::new();
+        let generator = entities.create_entity();
+        let drone = entities.create_entity();
+        let lamp = entities.create_entity();
+        let battery = entities.create_entity();
+        let wire = entities.create_entity();
+        let remote = entities.create_entity();
+        // generator - wire - drone in a row, lamp beside the drone, battery far away but linked
+        entities.add_component(generator, Position::from_f64(0.5, 0.0, 0.5));
+        entities.add_component(generator, Producer { output: 5 });
+        entities.add_component(wire, Position::from_f64(1.5, 0.0, 0.5));
+        entities.add_component(wire, Conduit);
+        entities.add_component(drone, Position::from_f64(2.5, 0.0, 0.5));
+        entities.add_component(drone, Consumer::new(3));
+        entities.add_component(lamp, Position::from_f64(2.5, 0.0, 1.5));
+        entities.add_component(lamp, Consumer::new(4));
+        entities.add_component(battery, Position::from_f64(20.0, 0.0, 20.0));
+        entities.add_component(battery, Storage::new(10, 2));
+        entities.add_component(battery, PowerLink { links: vec![generator] });
+        entities.add_component(remote, Position::from_f64(9.0, 0.0, 9.0));
+        entities.add_component(remote, Consumer::new(1));
+
+        let mut power = PowerSystem::new();
+        assert_eq!(power.tick(&mut entities), vec![PowerEvent::Brownout(lamp), PowerEvent::Brownout(remote)]);
+        assert_eq!(power.networks().len(), 2);
+        let network = power.network_of(drone).unwrap();
+        assert_eq!((network.production, network.demand, network.supplied, network.stored), (5, 7, 3, 2));
+        assert_eq!(network.members, vec![generator, drone, lamp, battery, wire]);
+
+        // The banked charge carries the lamp for one tick at a time
+        assert_eq!(power.tick(&mut entities), vec![PowerEvent::Restored(lamp)]);
+        assert_eq!(power.network_of(lamp).unwrap().stored, 0);
+        assert_eq!(power.tick(&mut entities), vec![PowerEvent::Brownout(lamp)]);
+        entities.get_component_mut::<Consumer>(drone).unwrap().demand = 1;
+        assert_eq!(power.tick(&mut entities), vec![PowerEvent::Restored(lamp)]);
+        assert_eq!(power.network_of(lamp).unwrap().stored, 2);
+
+        // With the generator idle the battery only covers the drone
+        entities.get_component_mut::<Producer>(generator).unwrap().output = 0;
+        assert_eq!(power.tick(&mut entities), vec![PowerEvent::Brownout(lamp)]);
+        assert_eq!(power.network_of(battery).unwrap().stored, 1);
+    }
+}
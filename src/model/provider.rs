@@ -0,0 +1,187 @@
+///
+/// Asynchronous Chunk Provider
+///
+/// Missing chunks are requested from a pool of worker threads which try each
+/// ChunkSource in turn (disk, then generation). Finished chunks are only handed
+/// to the World when it calls `sync`, so the simulation never blocks on I/O
+/// and chunks appear at a deterministic point in the tick.
+///
+use model::persist::StoredEntity;
+use model::storage::RegionStorage;
+use model::world::{Chunk, Vector2};
+use std::collections::HashMap;
+use std::io;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+///
+/// Chunk Load State
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ChunkStatus {
+    /// Not in memory and not requested
+    Unloaded,
+    /// Queued or in progress on a worker
+    Loading,
+    /// In memory
+    Ready,
+    /// Every source failed or declined the chunk
+    Failed(String),
+}
+
+///
+/// Something which can fill a Chunk buffer
+///
+pub trait ChunkSource: Send + Sync {
+    /// Fill `chunk`, returning false if this source has nothing for `position`.
+    fn provide(&self, position: Vector2<u64>, chunk: &mut Chunk) -> io::Result<bool>;
+    /// Entities stored with a Chunk this source provided, none by default.
+    fn provide_entities(&self, _position: Vector2<u64>) -> io::Result<Vec<StoredEntity>> { Ok(Vec::new()) }
+}
+
+impl ChunkSource for RegionStorage {
+    fn provide(&self, position: Vector2<u64>, chunk: &mut Chunk) -> io::Result<bool> {
+        self.read_chunk(position, chunk)
+    }
+    fn provide_entities(&self, position: Vector2<u64>) -> io::Result<Vec<StoredEntity>> { self.read_entities(position) }
+}
+
+struct Job {
+    position: Vector2<u64>,
+    chunk: Box<Chunk>,
+}
+
+type Delivery = (Vector2<u64>, Result<(Box<Chunk>, Vec<StoredEntity>), (Box<Chunk>, String)>);
+
+///
+/// Requests completed since the previous sync
+///
+pub struct Completed {
+    /// Loaded chunks with the entities stored with them, in ascending position order
+    pub ready: Vec<(Vector2<u64>, Box<Chunk>, Vec<StoredEntity>)>,
+    /// Buffers from failed requests, for recycling
+    pub spare: Vec<Box<Chunk>>,
+}
+
+///
+/// Worker Pool servicing Chunk requests
+///
+pub struct ChunkProvider {
+    jobs: Option<Sender<Job>>,
+    deliveries: Receiver<Delivery>,
+    workers: Vec<JoinHandle<()>>,
+    status: HashMap<Vector2<u64>, ChunkStatus>,
+}
+
+impl ChunkProvider {
+    /// Start `workers` threads trying `sources` in order.
+    pub fn new(sources: Vec<Arc<dyn ChunkSource>>, workers: usize) -> ChunkProvider {
+        let (job_sender, job_receiver) = channel::<Job>();
+        let (delivery_sender, delivery_receiver) = channel::<Delivery>();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let sources = Arc::new(sources);
+        let workers = (0..workers.max(1)).map(|index| {
+            let jobs = job_receiver.clone();
+            let deliveries = delivery_sender.clone();
+            let sources = sources.clone();
+            thread::Builder::new()
+                .name(format!("chunk-provider-{}", index))
+                .spawn(move || worker(&jobs, &deliveries, &sources))
+                .expect("unable to spawn chunk provider worker")
+        }).collect();
+        ChunkProvider {
+            jobs: Some(job_sender),
+            deliveries: delivery_receiver,
+            workers,
+            status: HashMap::new(),
+        }
+    }
+    ///
+    /// Queue a load into the provided buffer. Returns the buffer back if the
+    /// chunk is already loading.
+    ///
+    pub fn request(&mut self, position: Vector2<u64>, chunk: Box<Chunk>) -> Option<Box<Chunk>> {
+        if self.status(position) == ChunkStatus::Loading {
+            return Some(chunk);
+        }
+        match self.jobs.as_ref().map(|jobs| jobs.send(Job { position, chunk })) {
+            Some(Ok(())) => {
+                self.status.insert(position, ChunkStatus::Loading);
+                None
+            }
+            Some(Err(failed)) => Some(failed.0.chunk),
+            None => None,
+        }
+    }
+    /// Load status as known to the provider.
+    pub fn status(&self, position: Vector2<u64>) -> ChunkStatus {
+        self.status.get(&position).cloned().unwrap_or(ChunkStatus::Unloaded)
+    }
+    /// Forget a chunk's status once the World has taken ownership of it.
+    pub fn forget(&mut self, position: Vector2<u64>) { self.status.remove(&position); }
+    /// Number of requests not yet delivered.
+    pub fn pending(&self) -> usize { self.status.values().filter(|status| **status == ChunkStatus::Loading).count() }
+    /// Collect finished requests.
+    pub fn sync(&mut self) -> Completed {
+        let mut ready = Vec::new();
+        let mut spare = Vec::new();
+        while let Ok((position, result)) = self.deliveries.try_recv() {
+            match result {
+                Ok((chunk, entities)) => {
+                    self.status.insert(position, ChunkStatus::Ready);
+                    ready.push((position, chunk, entities));
+                }
+                Err((chunk, message)) => {
+                    self.status.insert(position, ChunkStatus::Failed(message));
+                    spare.push(chunk);
+                }
+            }
+        }
+        ready.sort_by_key(|entry| entry.0);
+        Completed { ready, spare }
+    }
+}
+
+impl Drop for ChunkProvider {
+    fn drop(&mut self) {
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn worker(jobs: &Mutex<Receiver<Job>>, deliveries: &Sender<Delivery>, sources: &[Arc<dyn ChunkSource>]) {
+    loop {
+        let job = match jobs.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        let Job { position, mut chunk } = match job {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        let mut result = Err("no source provided the chunk".to_string());
+        for source in sources.iter() {
+            match source.provide(position, &mut chunk).and_then(|found| if found { source.provide_entities(position).map(Some) } else { Ok(None) }) {
+                Ok(Some(entities)) => {
+                    result = Ok(entities);
+                    break;
+                }
+                Ok(None) => {}
+                Err(error) => {
+                    result = Err(error.to_string());
+                    break;
+                }
+            }
+        }
+        let delivery = match result {
+            Ok(entities) => (position, Ok((chunk, entities))),
+            Err(message) => (position, Err((chunk, message))),
+        };
+        if deliveries.send(delivery).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,146 @@
+///
+/// Deterministic Random Streams
+///
+/// A Random resource holds the Simulation's seed and hands out Streams,
+/// each derived from nothing but the seed and a key: a system's name, a
+/// Chunk position or an entity. Adding a consumer of randomness never shifts
+/// what the others draw, and a replay from the same seed draws the same
+/// values as long as each consumer draws in the same order.
+///
+/// Named streams are kept in the resource and carry on from tick to tick.
+/// Streams for a Chunk or entity are rebuilt from their key every time they
+/// are asked for; split one by the tick for different values every tick.
+///
+use math::{Fixed, Vector2};
+use model::entity::EntityID;
+use rand::Rng;
+use std::collections::BTreeMap;
+
+///
+/// SplitMix64 finalizer, scrambling every bit of `value` into every other.
+///
+pub fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// FNV-1a hash of a stream name
+fn hash_name(name: &str) -> u64 {
+    name.bytes().fold(0xCBF2_9CE4_8422_2325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3))
+}
+
+///
+/// Random Number Stream
+///
+/// SplitMix64, so any 64 bit state is a good one. Also usable through
+/// `rand::Rng` for ranges, shuffles and the like.
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Stream {
+    state: u64,
+}
+
+impl Stream {
+    pub fn new(seed: u64) -> Stream { Stream { state: mix(seed) } }
+    /// Independent stream derived from this one's current state and `key`, leaving this one as it was.
+    pub fn split(&self, key: u64) -> Stream { Stream::new(self.state ^ mix(key)) }
+    /// Next 64 random bits.
+    pub fn draw(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        mix(self.state)
+    }
+    /// Uniform value below `bound`, 0 for a bound of 0.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            return 0;
+        }
+        // Reject the top of the range which would favour small values
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.draw();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+    /// Uniform Fixed in [0, 1).
+    pub fn fraction(&mut self) -> Fixed { Fixed::from_raw((self.draw() >> 48) as i64) }
+    /// True with probability `chance`, out of Fixed::ONE.
+    pub fn chance(&mut self, chance: Fixed) -> bool { self.fraction() < chance }
+}
+
+impl Rng for Stream {
+    fn next_u32(&mut self) -> u32 { (self.draw() >> 32) as u32 }
+    fn next_u64(&mut self) -> u64 { self.draw() }
+}
+
+///
+/// Seeded source of Streams, as an EntityManager resource
+///
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Random {
+    seed: u64,
+    streams: BTreeMap<String, Stream>,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random { Random { seed, streams: BTreeMap::new() } }
+    pub fn seed(&self) -> u64 { self.seed }
+    /// Stream of a system, starting from the seed and `name` on first use and carrying on after.
+    pub fn stream(&mut self, name: &str) -> &mut Stream {
+        let seed = self.seed;
+        self.streams.entry(name.to_string()).or_insert_with(|| Stream::new(seed ^ hash_name(name)))
+    }
+    /// Stream of `name` for `key`, the same every time it is asked for.
+    pub fn keyed(&self, name: &str, key: u64) -> Stream { Stream::new(self.seed ^ hash_name(name)).split(key) }
+    /// Stream of `name` for a Chunk.
+    pub fn chunk(&self, name: &str, position: Vector2<u64>) -> Stream { self.keyed(name, mix(position.x) ^ position.y) }
+    /// Stream of `name` for an entity, a reused slot getting a stream of its own.
+    pub fn entity(&self, name: &str, entity: EntityID) -> Stream { self.keyed(name, mix(entity.slot() as u64) ^ entity.suffix() as u64) }
+    /// Names of the streams drawn from so far.
+    pub fn names(&self) -> impl Iterator<Item = &str> { self.streams.keys().map(String::as_str) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Random, Stream};
+    use math::{Fixed, Vector2};
+    use model::entity::EntityManager;
+    use rand::Rng;
+
+    #[test]
+    pub fn test_random_streams() {
+        // A new consumer leaves the draws of the others alone
+        let mut quiet = Random::new(42);
+        let expected: Vec<u64> = (0..8).map(|_| quiet.stream("physics").draw()).collect();
+        let mut busy = Random::new(42);
+        busy.stream("behavior").draw();
+        let mut drawn = Vec::new();
+        for _ in 0..8 {
+            drawn.push(busy.stream("physics").draw());
+            busy.stream("weather").draw();
+        }
+        assert_eq!(drawn, expected);
+        assert_ne!(Random::new(43).stream("physics").draw(), expected[0]);
+        assert_eq!(busy.names().collect::<Vec<_>>(), vec!["behavior", "physics", "weather"]);
+
+        // Keyed streams depend on their key alone
+        let mut entities = EntityManager::new();
+        let (first, second) = (entities.create_entity(), entities.create_entity());
+        let random = Random::new(42);
+        assert_eq!(random.entity("wander", first).draw(), random.entity("wander", first).draw());
+        assert_ne!(random.entity("wander", first).draw(), random.entity("wander", second).draw());
+        assert_ne!(random.chunk("ore", Vector2::new(1, 2)).draw(), random.chunk("ore", Vector2::new(2, 1)).draw());
+        let stream = random.chunk("ore", Vector2::new(1, 2));
+        assert_ne!(stream.split(1).draw(), stream.split(2).draw());
+
+        // Draws stay in range
+        let mut stream = Stream::new(7);
+        assert!((0..1000).all(|_| stream.below(6) < 6));
+        assert!((0..1000).all(|_| stream.fraction() < Fixed::ONE));
+        assert!(!(0..100).any(|_| stream.chance(Fixed::ZERO)));
+        assert!((0..100).all(|_| (3..9).contains(&stream.gen_range(3, 9))));
+    }
+}
@@ -0,0 +1,208 @@
+///
+/// World Raycasting
+///
+/// Rays are traversed block by block with a voxel DDA (Amanatides & Woo), so
+/// cost is proportional to the number of blocks crossed rather than distance
+/// sampled. Blocks in unloaded chunks or outside the world are treated as empty.
+///
+use model::material::MaterialId;
+use model::world::{World, CHUNK_SIZE};
+
+///
+/// Block Face, named by the direction it faces
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Face {
+    NegX,
+    PosX,
+    NegY,
+    PosY,
+    NegZ,
+    PosZ,
+}
+
+///
+/// Which Blocks stop a Ray
+///
+/// A Block stops the Ray when its material's opacity or resistance exceeds the
+/// corresponding threshold.
+///
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RayFilter {
+    pub opacity: f32,
+    pub resistance: f32,
+}
+
+impl RayFilter {
+    /// Stop on anything that blocks sight, ignoring resistance.
+    pub fn sight() -> RayFilter { RayFilter { opacity: 0.5, resistance: f32::INFINITY } }
+}
+
+impl Default for RayFilter {
+    /// Stop on any non-empty material.
+    fn default() -> RayFilter { RayFilter { opacity: 0.0, resistance: 0.0 } }
+}
+
+///
+/// Ray Intersection
+///
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RayHit {
+    /// Block coordinates
+    pub block: (u64, usize, u64),
+    pub material: MaterialId,
+    /// Face the Ray entered through, None if it started inside the Block
+    pub face: Option<Face>,
+    /// Entry point in world space
+    pub point: (f64, f64, f64),
+    /// Distance from the origin to the entry point
+    pub distance: f64,
+}
+
+impl World {
+    /// First non-empty Block along a Ray, within `max_distance`.
+    pub fn raycast(&self, origin: (f64, f64, f64), direction: (f64, f64, f64), max_distance: f64) -> Option<RayHit> {
+        self.raycast_filtered(origin, direction, max_distance, RayFilter::default())
+    }
+    /// First Block along a Ray which `filter` considers solid, none if `max_distance` isn't finite.
+    pub fn raycast_filtered(&self, origin: (f64, f64, f64), direction: (f64, f64, f64), max_distance: f64, filter: RayFilter) -> Option<RayHit> {
+        let length = (direction.0 * direction.0 + direction.1 * direction.1 + direction.2 * direction.2).sqrt();
+        if length == 0.0 || !length.is_finite() || !max_distance.is_finite() {
+            return None;
+        }
+        let origin = [origin.0, origin.1, origin.2];
+        let direction = [direction.0 / length, direction.1 / length, direction.2 / length];
+        let mut cell = [origin[0].floor() as i64, origin[1].floor() as i64, origin[2].floor() as i64];
+        let mut step = [0i64; 3];
+        let mut t_max = [f64::INFINITY; 3];
+        let mut t_delta = [f64::INFINITY; 3];
+        for axis in 0..3 {
+            if direction[axis] > 0.0 {
+                step[axis] = 1;
+                t_max[axis] = (cell[axis] as f64 + 1.0 - origin[axis]) / direction[axis];
+                t_delta[axis] = 1.0 / direction[axis];
+            } else if direction[axis] < 0.0 {
+                step[axis] = -1;
+                t_max[axis] = (origin[axis] - cell[axis] as f64) / -direction[axis];
+                t_delta[axis] = -1.0 / direction[axis];
+            }
+        }
+        let mut distance = 0.0;
+        let mut face = None;
+        while distance <= max_distance {
+            if let Some(material) = self.solid_at(cell, filter) {
+                return Some(RayHit {
+                    block: (cell[0] as u64, cell[1] as usize, cell[2] as u64),
+                    material,
+                    face,
+                    point: (
+                        origin[0] + direction[0] * distance,
+                        origin[1] + direction[1] * distance,
+                        origin[2] + direction[2] * distance,
+                    ),
+                    distance,
+                });
+            }
+            let axis = if t_max[0] <= t_max[1] && t_max[0] <= t_max[2] {
+                0
+            } else if t_max[1] <= t_max[2] {
+                1
+            } else {
+                2
+            };
+            if !t_max[axis].is_finite() {
+                return None;
+            }
+            distance = t_max[axis];
+            t_max[axis] += t_delta[axis];
+            cell[axis] += step[axis];
+            face = Some(match (axis, step[axis] > 0) {
+                (0, true) => Face::NegX,
+                (0, false) => Face::PosX,
+                (1, true) => Face::NegY,
+                (1, false) => Face::PosY,
+                (_, true) => Face::NegZ,
+                (_, false) => Face::PosZ,
+            });
+        }
+        None
+    }
+    ///
+    /// True if nothing opaque lies strictly between two points.
+    ///
+    pub fn line_of_sight(&self, from: (f64, f64, f64), to: (f64, f64, f64)) -> bool {
+        let direction = (to.0 - from.0, to.1 - from.1, to.2 - from.2);
+        let distance = (direction.0 * direction.0 + direction.1 * direction.1 + direction.2 * direction.2).sqrt();
+        let target = (to.0.floor() as u64, to.1.floor() as usize, to.2.floor() as u64);
+        match self.raycast_filtered(from, direction, distance, RayFilter::sight()) {
+            Some(hit) => hit.block == target,
+            None => true,
+        }
+    }
+    fn solid_at(&self, cell: [i64; 3], filter: RayFilter) -> Option<MaterialId> {
+        if cell[0] < 0 || cell[1] < 0 || cell[2] < 0 || cell[1] >= CHUNK_SIZE as i64 {
+            return None;
+        }
+        let block = self.get_block(cell[0] as u64, cell[1] as usize, cell[2] as u64)?;
+        let material = self.materials().get(block.material())?;
+        if material.opacity() > filter.opacity || material.resistance() > filter.resistance {
+            Some(block.material())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Face, RayFilter};
+    use model::material::Material;
+    use model::world::{Block, Chunk, Vector2, World};
+    use pool::Poolable;
+
+    fn world() -> World {
+        let mut world = World::new();
+        let glass = world.materials_mut().register(Material::new("glass", 1.0, 0.0));
+        let rock = world.materials().id("rock").unwrap();
+        world.insert_chunk(Vector2::new(0, 0), Chunk::allocate());
+        for x in 0..32 {
+            for z in 0..32 {
+                world.set_block(x, 0, z, Block::new(rock));
+            }
+        }
+        world.set_block(10, 5, 5, Block::new(rock));
+        world.set_block(10, 5, 8, Block::new(glass));
+        world
+    }
+
+    #[test]
+    pub fn test_raycast_hits_face() {
+        let world = world();
+        let hit = world.raycast((2.5, 5.5, 5.5), (1.0, 0.0, 0.0), 32.0).unwrap();
+        assert_eq!(hit.block, (10, 5, 5));
+        assert_eq!(hit.face, Some(Face::NegX));
+        assert_eq!(hit.point, (10.0, 5.5, 5.5));
+        assert_eq!(hit.distance, 7.5);
+
+        let down = world.raycast((4.5, 9.5, 4.5), (0.0, -1.0, 0.0), 32.0).unwrap();
+        assert_eq!(down.block, (4, 0, 4));
+        assert_eq!(down.face, Some(Face::PosY));
+
+        assert!(world.raycast((2.5, 5.5, 5.5), (1.0, 0.0, 0.0), 7.0).is_none());
+        assert!(world.raycast((2.5, 5.5, 5.5), (0.0, 1.0, 0.0), 64.0).is_none());
+        // An unbounded ray is refused rather than walked forever
+        assert!(world.raycast((2.5, 5.5, 5.5), (0.0, 1.0, 0.0), f64::INFINITY).is_none());
+        assert!(world.raycast((2.5, 5.5, 5.5), (1.0, 0.0, 0.0), f64::NAN).is_none());
+    }
+
+    #[test]
+    pub fn test_line_of_sight() {
+        let world = world();
+        // Rock blocks sight, glass resists but is transparent
+        assert!(!world.line_of_sight((2.5, 5.5, 5.5), (20.5, 5.5, 5.5)));
+        assert!(world.line_of_sight((2.5, 5.5, 8.5), (20.5, 5.5, 8.5)));
+        assert_eq!(world.raycast_filtered((2.5, 5.5, 8.5), (1.0, 0.0, 0.0), 32.0, RayFilter::default()).unwrap().block, (10, 5, 8));
+        // The target block itself doesn't obstruct
+        assert!(world.line_of_sight((2.5, 5.5, 5.5), (10.5, 5.5, 5.5)));
+    }
+}
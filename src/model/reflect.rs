@@ -0,0 +1,443 @@
+///
+/// Component Reflection
+///
+/// Components are type erased in the EntityManager, so tooling which has to
+/// work on any of them, consoles, inspectors and generic saves, goes through
+/// a ComponentRegistry instead. Each Component type registered under a name
+/// describes its fields, each with the Kind of Value it holds, and reads and
+/// writes them by name, see Reflect. Types which are also Persistent bring
+/// their save hooks along, so the registry can store any entity's reflected
+/// Components without knowing their types.
+///
+/// The default registry holds Position, Velocity, Collider, WorldId and
+/// Ownership, all but the last persistent under the names the default
+/// ComponentCodecs use.
+///
+use math::Fixed;
+use model::component::{Collider, Position, Velocity};
+use model::dimension::WorldId;
+use model::entity::{EntityID, EntityManager};
+use model::faction::{FactionId, Ownership};
+use model::persist::{ComponentCodecs, Persistent, StoredEntity};
+use std::fmt;
+use std::io;
+
+///
+/// Type of a reflected field
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Bool,
+    Int,
+    Fixed,
+    Text,
+    Entity,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Kind::Bool => "bool",
+            Kind::Int => "int",
+            Kind::Fixed => "fixed",
+            Kind::Text => "text",
+            Kind::Entity => "entity",
+        })
+    }
+}
+
+///
+/// Value of a reflected field
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Fixed(Fixed),
+    Text(String),
+    Entity(EntityID),
+}
+
+impl Value {
+    pub fn kind(&self) -> Kind {
+        match *self {
+            Value::Bool(_) => Kind::Bool,
+            Value::Int(_) => Kind::Int,
+            Value::Fixed(_) => Kind::Fixed,
+            Value::Text(_) => Kind::Text,
+            Value::Entity(_) => Kind::Entity,
+        }
+    }
+    /// Read a Value of a Kind as it is displayed, entities as `<slot>:<suffix>`.
+    pub fn parse(kind: Kind, text: &str) -> Option<Value> {
+        match kind {
+            Kind::Bool => text.parse().ok().map(Value::Bool),
+            Kind::Int => text.parse().ok().map(Value::Int),
+            Kind::Fixed => text.parse::<f64>().ok().filter(|value| value.is_finite()).map(|value| Value::Fixed(Fixed::from_f64(value))),
+            Kind::Text => Some(Value::Text(text.to_string())),
+            Kind::Entity => {
+                let mut parts = text.splitn(2, ':');
+                let slot = parts.next()?.parse().ok()?;
+                let suffix = parts.next()?.parse().ok()?;
+                Some(Value::Entity(EntityID::new(slot, suffix)))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Fixed(value) => write!(f, "{}", value),
+            Value::Text(ref value) => write!(f, "{:?}", value),
+            Value::Entity(entity) => write!(f, "{}:{}", entity.slot(), entity.suffix()),
+        }
+    }
+}
+
+///
+/// Reflected field of a Component
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct FieldInfo {
+    pub name: &'static str,
+    pub kind: Kind,
+}
+
+impl FieldInfo {
+    pub const fn new(name: &'static str, kind: Kind) -> FieldInfo { FieldInfo { name, kind } }
+}
+
+///
+/// Reflection Failure
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ReflectError {
+    UnknownComponent(String),
+    UnknownField { component: String, field: String },
+    /// The entity is dead or doesn't have the Component
+    Missing { component: String, entity: EntityID },
+    /// A Value of the wrong Kind, or text which isn't one
+    WrongKind { field: String, expected: Kind },
+    /// The Value is of the right Kind but out of the field's range
+    Invalid { field: String, reason: String },
+}
+
+impl fmt::Display for ReflectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReflectError::UnknownComponent(ref name) => write!(f, "unknown component {}", name),
+            ReflectError::UnknownField { ref component, ref field } => write!(f, "{} has no field {}", component, field),
+            ReflectError::Missing { ref component, entity } => write!(f, "entity {}:{} has no {}", entity.slot(), entity.suffix(), component),
+            ReflectError::WrongKind { ref field, expected } => write!(f, "{} takes a {}", field, expected),
+            ReflectError::Invalid { ref field, ref reason } => write!(f, "{}: {}", field, reason),
+        }
+    }
+}
+
+///
+/// Component whose fields can be read and written by name
+///
+pub trait Reflect: Clone + 'static {
+    fn fields() -> &'static [FieldInfo];
+    /// Value of a field, None if there is no such field.
+    fn get(&self, field: &str) -> Option<Value>;
+    /// Write a field with a Value of its Kind.
+    fn set(&mut self, field: &str, value: Value) -> Result<(), ReflectError>;
+}
+
+fn expected_fixed<T>(field: &str) -> Result<T, ReflectError> {
+    Err(ReflectError::WrongKind { field: field.to_string(), expected: Kind::Fixed })
+}
+
+const XYZ: &[FieldInfo] = &[FieldInfo::new("x", Kind::Fixed), FieldInfo::new("y", Kind::Fixed), FieldInfo::new("z", Kind::Fixed)];
+
+/// Field of an x, y, z triple of Fixed
+fn get_axis(x: Fixed, y: Fixed, z: Fixed, field: &str) -> Option<Value> {
+    match field {
+        "x" => Some(Value::Fixed(x)),
+        "y" => Some(Value::Fixed(y)),
+        "z" => Some(Value::Fixed(z)),
+        _ => None,
+    }
+}
+
+fn set_axis(x: &mut Fixed, y: &mut Fixed, z: &mut Fixed, component: &str, field: &str, value: Value) -> Result<(), ReflectError> {
+    let axis = match field {
+        "x" => x,
+        "y" => y,
+        "z" => z,
+        _ => return Err(ReflectError::UnknownField { component: component.to_string(), field: field.to_string() }),
+    };
+    match value {
+        Value::Fixed(value) => *axis = value,
+        _ => return expected_fixed(field),
+    }
+    Ok(())
+}
+
+impl Reflect for Position {
+    fn fields() -> &'static [FieldInfo] { XYZ }
+    fn get(&self, field: &str) -> Option<Value> { get_axis(self.x, self.y, self.z, field) }
+    fn set(&mut self, field: &str, value: Value) -> Result<(), ReflectError> { set_axis(&mut self.x, &mut self.y, &mut self.z, "position", field, value) }
+}
+
+impl Reflect for Velocity {
+    fn fields() -> &'static [FieldInfo] { XYZ }
+    fn get(&self, field: &str) -> Option<Value> { get_axis(self.x, self.y, self.z, field) }
+    fn set(&mut self, field: &str, value: Value) -> Result<(), ReflectError> { set_axis(&mut self.x, &mut self.y, &mut self.z, "velocity", field, value) }
+}
+
+impl Reflect for Collider {
+    fn fields() -> &'static [FieldInfo] {
+        const FIELDS: &[FieldInfo] = &[
+            FieldInfo::new("width", Kind::Fixed), FieldInfo::new("height", Kind::Fixed), FieldInfo::new("depth", Kind::Fixed),
+            FieldInfo::new("gravity", Kind::Bool), FieldInfo::new("grounded", Kind::Bool),
+        ];
+        FIELDS
+    }
+    fn get(&self, field: &str) -> Option<Value> {
+        Some(match field {
+            "width" => Value::Fixed(self.width),
+            "height" => Value::Fixed(self.height),
+            "depth" => Value::Fixed(self.depth),
+            "gravity" => Value::Bool(self.gravity),
+            "grounded" => Value::Bool(self.grounded),
+            _ => return None,
+        })
+    }
+    fn set(&mut self, field: &str, value: Value) -> Result<(), ReflectError> {
+        match (field, value) {
+            ("width", Value::Fixed(value)) => self.width = value,
+            ("height", Value::Fixed(value)) => self.height = value,
+            ("depth", Value::Fixed(value)) => self.depth = value,
+            ("gravity", Value::Bool(value)) => self.gravity = value,
+            ("grounded", Value::Bool(value)) => self.grounded = value,
+            ("width", _) | ("height", _) | ("depth", _) => return expected_fixed(field),
+            ("gravity", _) | ("grounded", _) => return Err(ReflectError::WrongKind { field: field.to_string(), expected: Kind::Bool }),
+            _ => return Err(ReflectError::UnknownField { component: "collider".to_string(), field: field.to_string() }),
+        }
+        Ok(())
+    }
+}
+
+/// Value of the one Int field of a newtype Component, checked against its range.
+fn int_field<T: Copy>(component: &str, name: &str, field: &str, value: Value, convert: fn(i64) -> Option<T>) -> Result<T, ReflectError> {
+    match (field == name, value) {
+        (false, _) => Err(ReflectError::UnknownField { component: component.to_string(), field: field.to_string() }),
+        (true, Value::Int(value)) => convert(value).ok_or_else(|| ReflectError::Invalid { field: field.to_string(), reason: format!("{} is out of range", value) }),
+        (true, _) => Err(ReflectError::WrongKind { field: field.to_string(), expected: Kind::Int }),
+    }
+}
+
+impl Reflect for WorldId {
+    fn fields() -> &'static [FieldInfo] {
+        const FIELDS: &[FieldInfo] = &[FieldInfo::new("id", Kind::Int)];
+        FIELDS
+    }
+    fn get(&self, field: &str) -> Option<Value> { Some(Value::Int(self.0 as i64)).filter(|_| field == "id") }
+    fn set(&mut self, field: &str, value: Value) -> Result<(), ReflectError> {
+        self.0 = int_field("world", "id", field, value, |value| if (0..=u16::MAX as i64).contains(&value) { Some(value as u16) } else { None })?;
+        Ok(())
+    }
+}
+
+impl Reflect for Ownership {
+    fn fields() -> &'static [FieldInfo] {
+        const FIELDS: &[FieldInfo] = &[FieldInfo::new("faction", Kind::Int)];
+        FIELDS
+    }
+    fn get(&self, field: &str) -> Option<Value> { Some(Value::Int(self.faction.id() as i64)).filter(|_| field == "faction") }
+    fn set(&mut self, field: &str, value: Value) -> Result<(), ReflectError> {
+        self.faction = int_field("ownership", "faction", field, value, |value| if (0..=u16::MAX as i64).contains(&value) { Some(FactionId::new(value as u16)) } else { None })?;
+        Ok(())
+    }
+}
+
+type ReadFn = fn(&EntityManager, EntityID) -> Option<Vec<(&'static str, Value)>>;
+type WriteFn = fn(&mut EntityManager, EntityID, &str, Value) -> Option<Result<(), ReflectError>>;
+type RemoveFn = fn(&mut EntityManager, EntityID) -> bool;
+
+#[derive(Clone)]
+struct Registration {
+    name: String,
+    fields: &'static [FieldInfo],
+    read: ReadFn,
+    write: WriteFn,
+    remove: RemoveFn,
+}
+
+fn read<C: Reflect>(entities: &EntityManager, entity: EntityID) -> Option<Vec<(&'static str, Value)>> {
+    let component = entities.get_component::<C>(entity)?;
+    Some(C::fields().iter().filter_map(|field| component.get(field.name).map(|value| (field.name, value))).collect())
+}
+
+fn write<C: Reflect>(entities: &mut EntityManager, entity: EntityID, field: &str, value: Value) -> Option<Result<(), ReflectError>> {
+    entities.get_component_mut::<C>(entity).map(|component| component.set(field, value))
+}
+
+fn remove<C: Reflect>(entities: &mut EntityManager, entity: EntityID) -> bool { entities.remove_component::<C>(entity).is_some() }
+
+///
+/// Reflected Component types by name
+///
+#[derive(Clone)]
+pub struct ComponentRegistry {
+    types: Vec<Registration>,
+    codecs: ComponentCodecs,
+}
+
+impl ComponentRegistry {
+    /// Registry of no Components at all.
+    pub fn empty() -> ComponentRegistry { ComponentRegistry { types: Vec::new(), codecs: ComponentCodecs::empty() } }
+    /// Reflect C under `name`, replacing any type of that name.
+    pub fn register<C: Reflect>(&mut self, name: &str) {
+        let registration = Registration { name: name.to_string(), fields: C::fields(), read: read::<C>, write: write::<C>, remove: remove::<C> };
+        match self.types.iter_mut().find(|existing| existing.name == name) {
+            Some(existing) => *existing = registration,
+            None => self.types.push(registration),
+        }
+    }
+    /// Reflect C under `name` and store it with its Persistent hooks.
+    pub fn register_persistent<C: Reflect + Persistent>(&mut self, name: &str) {
+        self.register::<C>(name);
+        self.codecs.register::<C>(name);
+    }
+    /// Names of the registered Components in registration order.
+    pub fn names(&self) -> Vec<&str> { self.types.iter().map(|registration| &registration.name[..]).collect() }
+    pub fn is_registered(&self, name: &str) -> bool { self.types.iter().any(|registration| registration.name == name) }
+    pub fn fields(&self, name: &str) -> Option<&'static [FieldInfo]> { self.registration(name).ok().map(|registration| registration.fields) }
+    /// Codecs of the persistent Components.
+    pub fn codecs(&self) -> &ComponentCodecs { &self.codecs }
+    /// Names of the registered Components an entity has.
+    pub fn components_of(&self, entities: &EntityManager, entity: EntityID) -> Vec<&str> {
+        self.types.iter().filter(|registration| (registration.read)(entities, entity).is_some()).map(|registration| &registration.name[..]).collect()
+    }
+    /// Fields of one of an entity's Components with their Values.
+    pub fn read(&self, entities: &EntityManager, entity: EntityID, component: &str) -> Result<Vec<(&'static str, Value)>, ReflectError> {
+        (self.registration(component)?.read)(entities, entity).ok_or_else(|| ReflectError::Missing { component: component.to_string(), entity })
+    }
+    pub fn get(&self, entities: &EntityManager, entity: EntityID, component: &str, field: &str) -> Result<Value, ReflectError> {
+        self.read(entities, entity, component)?.into_iter().find(|&(name, _)| name == field).map(|(_, value)| value)
+            .ok_or_else(|| ReflectError::UnknownField { component: component.to_string(), field: field.to_string() })
+    }
+    pub fn set(&self, entities: &mut EntityManager, entity: EntityID, component: &str, field: &str, value: Value) -> Result<(), ReflectError> {
+        (self.registration(component)?.write)(entities, entity, field, value).unwrap_or_else(|| Err(ReflectError::Missing { component: component.to_string(), entity }))
+    }
+    /// Set a field from text, read as the Kind of the field.
+    pub fn set_text(&self, entities: &mut EntityManager, entity: EntityID, component: &str, field: &str, text: &str) -> Result<(), ReflectError> {
+        let info = self.registration(component)?.fields.iter().find(|info| info.name == field)
+            .ok_or_else(|| ReflectError::UnknownField { component: component.to_string(), field: field.to_string() })?;
+        let value = Value::parse(info.kind, text).ok_or_else(|| ReflectError::WrongKind { field: field.to_string(), expected: info.kind })?;
+        self.set(entities, entity, component, field, value)
+    }
+    /// Remove a Component by name, false if the entity didn't have it.
+    pub fn remove(&self, entities: &mut EntityManager, entity: EntityID, component: &str) -> Result<bool, ReflectError> {
+        Ok((self.registration(component)?.remove)(entities, entity))
+    }
+    /// One of an entity's Components as `name { field: value, .. }`.
+    pub fn describe_component(&self, entities: &EntityManager, entity: EntityID, component: &str) -> Result<String, ReflectError> {
+        let fields: Vec<String> = self.read(entities, entity, component)?.iter().map(|(name, value)| format!("{}: {}", name, value)).collect();
+        Ok(format!("{} {{ {} }}", component, fields.join(", ")))
+    }
+    /// Every registered Component of an entity, one per line, see `describe_component`.
+    pub fn describe(&self, entities: &EntityManager, entity: EntityID) -> String {
+        let lines: Vec<String> = self.components_of(entities, entity).into_iter().filter_map(|name| self.describe_component(entities, entity, name).ok()).collect();
+        lines.join("\n")
+    }
+    /// Stored form of an entity's persistent Components.
+    pub fn save(&self, entities: &EntityManager, entity: EntityID) -> io::Result<StoredEntity> { self.codecs.capture(entities, entity) }
+    fn registration(&self, name: &str) -> Result<&Registration, ReflectError> {
+        self.types.iter().find(|registration| registration.name == name).ok_or_else(|| ReflectError::UnknownComponent(name.to_string()))
+    }
+}
+
+impl Default for ComponentRegistry {
+    fn default() -> ComponentRegistry {
+        let mut registry = ComponentRegistry::empty();
+        registry.register_persistent::<Position>("position");
+        registry.register_persistent::<Velocity>("velocity");
+        registry.register_persistent::<Collider>("collider");
+        registry.register_persistent::<WorldId>("world");
+        registry.register::<Ownership>("ownership");
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ComponentRegistry, FieldInfo, Kind, Reflect, ReflectError, Value};
+    use math::Fixed;
+    use model::component::{Collider, Position};
+    use model::entity::{EntityID, EntityManager};
+    use model::faction::{FactionId, Ownership};
+
+    /// A game's own Component, unknown to the registry's defaults
+    #[derive(Clone)]
+    struct Nameplate {
+        text: String,
+        target: EntityID,
+    }
+
+    impl Reflect for Nameplate {
+        fn fields() -> &'static [FieldInfo] {
+            const FIELDS: &[FieldInfo] = &[FieldInfo::new("text", Kind::Text), FieldInfo::new("target", Kind::Entity)];
+            FIELDS
+        }
+        fn get(&self, field: &str) -> Option<Value> {
+            match field {
+                "text" => Some(Value::Text(self.text.clone())),
+                "target" => Some(Value::Entity(self.target)),
+                _ => None,
+            }
+        }
+        fn set(&mut self, field: &str, value: Value) -> Result<(), ReflectError> {
+            match (field, value) {
+                ("text", Value::Text(text)) => self.text = text,
+                ("target", Value::Entity(target)) => self.target = target,
+                _ => return Err(ReflectError::WrongKind { field: field.to_string(), expected: Kind::Text }),
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_component_reflection() {
+        let mut registry = ComponentRegistry::default();
+        registry.register::<Nameplate>("nameplate");
+        let mut entities = EntityManager::new();
+        let drone = entities.create_entity();
+        entities.add_component(drone, Position::from_f64(1.5, 2.0, -3.25));
+        entities.add_component(drone, Collider::from_f64(0.5, 1.0, 0.5));
+        entities.add_component(drone, Ownership::new(FactionId::new(2)));
+        entities.add_component(drone, Nameplate { text: "worker".to_string(), target: drone });
+
+        assert_eq!(registry.components_of(&entities, drone), vec!["position", "collider", "ownership", "nameplate"]);
+        assert_eq!(registry.fields("nameplate").unwrap()[1], FieldInfo::new("target", Kind::Entity));
+        assert_eq!(registry.describe(&entities, drone).lines().next(), Some("position { x: 1.5, y: 2, z: -3.25 }"));
+        assert!(registry.describe(&entities, drone).ends_with("nameplate { text: \"worker\", target: 0:0 }"));
+
+        // Written by name, from Values or text
+        registry.set(&mut entities, drone, "position", "y", Value::Fixed(Fixed::from_int(7))).unwrap();
+        registry.set_text(&mut entities, drone, "collider", "gravity", "false").unwrap();
+        registry.set_text(&mut entities, drone, "nameplate", "text", "queen").unwrap();
+        assert_eq!(entities.get_component::<Position>(drone).unwrap().y, Fixed::from_int(7));
+        assert_eq!(registry.get(&entities, drone, "collider", "gravity"), Ok(Value::Bool(false)));
+        assert_eq!(registry.get(&entities, drone, "nameplate", "text"), Ok(Value::Text("queen".to_string())));
+
+        // Mistakes are explained
+        assert_eq!(registry.set_text(&mut entities, drone, "position", "x", "north"), Err(ReflectError::WrongKind { field: "x".to_string(), expected: Kind::Fixed }));
+        assert!(matches!(registry.set_text(&mut entities, drone, "ownership", "faction", "70000"), Err(ReflectError::Invalid { .. })));
+        assert!(matches!(registry.get(&entities, drone, "position", "w"), Err(ReflectError::UnknownField { .. })));
+        assert!(matches!(registry.read(&entities, drone, "velocity"), Err(ReflectError::Missing { .. })));
+        assert!(matches!(registry.read(&entities, drone, "heat"), Err(ReflectError::UnknownComponent(_))));
+
+        // Saved through the persistent hooks, only what they cover
+        let stored = registry.save(&entities, drone).unwrap();
+        assert_eq!(stored.components.iter().map(|(name, _)| &name[..]).collect::<Vec<_>>(), vec!["position", "collider"]);
+        assert!(registry.remove(&mut entities, drone, "collider").unwrap());
+        assert_eq!(registry.components_of(&entities, drone), vec!["position", "ownership", "nameplate"]);
+    }
+}
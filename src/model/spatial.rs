@@ -0,0 +1,226 @@
+///
+/// Spatial Index
+///
+/// Entities are bucketed into cubic cells (a Chunk wide by default) keyed by
+/// their Position, so neighbour queries only visit the cells they overlap.
+/// Moving an entity touches the buckets only when it changes cell.
+///
+use math::Fixed;
+use model::component::Position;
+use model::entity::{EntityID, EntityManager};
+use model::world::CHUNK_SIZE;
+use std::collections::HashMap;
+
+type Cell = (i64, i64, i64);
+
+///
+/// Cell bucketed Entity Positions
+///
+#[derive(Clone, Debug)]
+pub struct SpatialIndex {
+    cell_size: Fixed,
+    cells: HashMap<Cell, Vec<EntityID>>,
+    entries: HashMap<EntityID, (Cell, Position)>,
+}
+
+impl SpatialIndex {
+    pub fn new() -> SpatialIndex { SpatialIndex::with_cell_size(Fixed::from_int(CHUNK_SIZE as i64)) }
+    pub fn with_cell_size(cell_size: Fixed) -> SpatialIndex {
+        SpatialIndex {
+            cell_size: cell_size.max(Fixed::ONE),
+            cells: HashMap::new(),
+            entries: HashMap::new(),
+        }
+    }
+    pub fn cell_size(&self) -> Fixed { self.cell_size }
+    pub fn len(&self) -> usize { self.entries.len() }
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+    pub fn contains(&self, entity: EntityID) -> bool { self.entries.contains_key(&entity) }
+    /// Last indexed position of an entity.
+    pub fn position(&self, entity: EntityID) -> Option<Position> { self.entries.get(&entity).map(|entry| entry.1) }
+    fn cell(&self, position: &Position) -> Cell {
+        (
+            (position.x / self.cell_size).floor(),
+            (position.y / self.cell_size).floor(),
+            (position.z / self.cell_size).floor(),
+        )
+    }
+    /// Insert or move an entity.
+    pub fn update(&mut self, entity: EntityID, position: Position) {
+        let cell = self.cell(&position);
+        match self.entries.insert(entity, (cell, position)) {
+            Some((previous, _)) if previous == cell => return,
+            Some((previous, _)) => self.unlink(entity, previous),
+            None => {}
+        }
+        self.cells.entry(cell).or_default().push(entity);
+    }
+    /// Remove an entity, returns false if it wasn't indexed.
+    pub fn remove(&mut self, entity: EntityID) -> bool {
+        match self.entries.remove(&entity) {
+            Some((cell, _)) => {
+                self.unlink(entity, cell);
+                true
+            }
+            None => false,
+        }
+    }
+    fn unlink(&mut self, entity: EntityID, cell: Cell) {
+        let empty = match self.cells.get_mut(&cell) {
+            Some(bucket) => {
+                bucket.retain(|&other| other != entity);
+                bucket.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            self.cells.remove(&cell);
+        }
+    }
+    ///
+    /// Bring the index in line with the Position components of `entities`,
+    /// dropping entities which were destroyed or lost their Position.
+    ///
+    pub fn sync(&mut self, entities: &EntityManager) {
+        let stale: Vec<EntityID> = self.entries.keys()
+            .filter(|&&entity| !entities.has_component::<Position>(entity))
+            .cloned()
+            .collect();
+        for entity in stale {
+            self.remove(entity);
+        }
+        for (entity, position) in entities.iter::<Position>() {
+            self.update(entity, *position);
+        }
+    }
+    fn visit(&self, min: &Position, max: &Position, visitor: &mut dyn FnMut(EntityID, &Position)) {
+        let (low, high) = (self.cell(min), self.cell(max));
+        for x in low.0..high.0 + 1 {
+            for y in low.1..high.1 + 1 {
+                for z in low.2..high.2 + 1 {
+                    if let Some(bucket) = self.cells.get(&(x, y, z)) {
+                        for &entity in bucket.iter() {
+                            visitor(entity, &self.entries[&entity].1);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    /// Entities inside an axis aligned box, in id order.
+    pub fn entities_in_aabb(&self, min: Position, max: Position) -> Vec<EntityID> {
+        let mut found = Vec::new();
+        self.visit(&min, &max, &mut |entity, position| {
+            if position.x >= min.x && position.x <= max.x
+                && position.y >= min.y && position.y <= max.y
+                && position.z >= min.z && position.z <= max.z {
+                found.push(entity);
+            }
+        });
+        found.sort();
+        found
+    }
+    /// Entities within `radius` of `center`, nearest first.
+    pub fn entities_in_radius(&self, center: Position, radius: Fixed) -> Vec<EntityID> {
+        let min = Position::new(center.x - radius, center.y - radius, center.z - radius);
+        let max = Position::new(center.x + radius, center.y + radius, center.z + radius);
+        let mut found = Vec::new();
+        self.visit(&min, &max, &mut |entity, position| {
+            let distance = position.distance_squared(&center);
+            if distance <= radius * radius {
+                found.push((distance, entity));
+            }
+        });
+        found.sort();
+        found.into_iter().map(|(_, entity)| entity).collect()
+    }
+    ///
+    /// Closest entity to `center` other than `exclude`, searching outwards one
+    /// ring of cells at a time.
+    ///
+    pub fn nearest_entity(&self, center: Position, exclude: Option<EntityID>) -> Option<EntityID> {
+        if self.cells.is_empty() {
+            return None;
+        }
+        let origin = self.cell(&center);
+        let reach = self.cells.keys()
+            .map(|cell| (cell.0 - origin.0).abs().max((cell.1 - origin.1).abs()).max((cell.2 - origin.2).abs()))
+            .max()
+            .unwrap_or(0);
+        let mut best: Option<(Fixed, EntityID)> = None;
+        for ring in 0..reach + 1 {
+            for x in -ring..ring + 1 {
+                for y in -ring..ring + 1 {
+                    for z in -ring..ring + 1 {
+                        if x.abs().max(y.abs()).max(z.abs()) != ring {
+                            continue;
+                        }
+                        let bucket = match self.cells.get(&(origin.0 + x, origin.1 + y, origin.2 + z)) {
+                            Some(bucket) => bucket,
+                            None => continue,
+                        };
+                        for &entity in bucket.iter() {
+                            if Some(entity) == exclude {
+                                continue;
+                            }
+                            let distance = self.entries[&entity].1.distance_squared(&center);
+                            if best.is_none_or(|(known, id)| distance < known || (distance == known && entity < id)) {
+                                best = Some((distance, entity));
+                            }
+                        }
+                    }
+                }
+            }
+            // Anything in further rings is at least `ring` whole cells away
+            let cleared = Fixed::from_int(ring) * self.cell_size;
+            if best.is_some_and(|(distance, _)| distance <= cleared * cleared) {
+                break;
+            }
+        }
+        best.map(|(_, entity)| entity)
+    }
+}
+
+impl Default for SpatialIndex {
+    fn default() -> SpatialIndex { SpatialIndex::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpatialIndex;
+    use math::Fixed;
+    use model::component::Position;
+    use model::entity::EntityManager;
+
+    #[test]
+    pub fn test_spatial_queries() {
+        let mut entities = EntityManager::new();
+        entities.register::<Position>();
+        let a = entities.create_entity();
+        let b = entities.create_entity();
+        let c = entities.create_entity();
+        let d = entities.create_entity();
+        entities.add_component(a, Position::from_f64(1.0, 1.0, 1.0));
+        entities.add_component(b, Position::from_f64(4.0, 1.0, 1.0));
+        entities.add_component(c, Position::from_f64(40.0, 2.0, 5.0));
+        entities.add_component(d, Position::from_f64(-20.0, 0.0, 0.0));
+
+        let mut index = SpatialIndex::with_cell_size(Fixed::from_int(8));
+        index.sync(&entities);
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.entities_in_aabb(Position::from_f64(0.0, 0.0, 0.0), Position::from_f64(10.0, 10.0, 10.0)), vec![a, b]);
+        assert_eq!(index.entities_in_radius(Position::from_f64(5.0, 1.0, 1.0), Fixed::from_int(5)), vec![b, a]);
+        assert_eq!(index.nearest_entity(Position::from_f64(30.0, 0.0, 0.0), None), Some(c));
+        assert_eq!(index.nearest_entity(Position::from_f64(1.0, 1.0, 1.0), Some(a)), Some(b));
+
+        // Moves and destruction are picked up by the next sync
+        entities.get_component_mut::<Position>(c).unwrap().x = Fixed::from_int(2);
+        entities.destroy_entity(b);
+        index.sync(&entities);
+        assert!(!index.contains(b));
+        assert_eq!(index.entities_in_radius(Position::from_f64(1.0, 1.0, 1.0), Fixed::from_int(5)), vec![a, c]);
+        assert_eq!(index.nearest_entity(Position::from_f64(-15.0, 0.0, 0.0), None), Some(d));
+        assert!(index.remove(d));
+        assert_eq!(index.nearest_entity(Position::from_f64(-15.0, 0.0, 0.0), None), Some(a));
+    }
+}
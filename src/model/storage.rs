@@ -0,0 +1,648 @@
+///
+/// Region File Storage
+///
+/// Chunks are persisted in region files, each holding up to REGION_SIZE x
+/// REGION_SIZE chunks and the entities standing in them. A region file
+/// starts with a fixed header and two offset tables followed by the payloads:
+///
+/// ---+--------+---------------------------------------------------------------
+///  # | SIZE   | DESCRIPTION
+/// ---+--------+---------------------------------------------------------------
+///  1 | 4      | Magic "HVRG"
+///  2 | 2      | Format Version
+///  3 | 2      | Reserved
+///  4 | 8*1024 | Chunk Table: (offset u32, length u32) per chunk, 0 = absent
+///  5 | 8*1024 | Entity Table: as the Chunk Table, see `model::persist`
+///  6 | ...    | Chunk and Entity Payloads
+/// ---+--------+---------------------------------------------------------------
+///
+/// Rewritten payloads are stored in place when they fit in their previous slot
+/// and appended to the end of the file otherwise. Region files written by an
+/// earlier version are upgraded in place the first time they are touched:
+///
+/// ---+---------------------------------------------------------------------
+///  # | CHANGE
+/// ---+---------------------------------------------------------------------
+///  1 | Chunk Table only
+///  2 | Entity Table added
+///  3 | Entity payloads start with their own format version
+/// ---+---------------------------------------------------------------------
+///
+use codec::{invalid_data, read_u16, read_u32, read_u8, write_u16, write_u32, write_u8};
+use migrate::{Artifact, Migrations};
+use model::material::MaterialId;
+use model::persist::{read_entities, write_entities, StoredEntity};
+use model::world::{Block, Chunk, Vector2, CHUNK_SIZE};
+#[cfg(feature = "lz4")]
+use net::lz4;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Edge length of a Region in Chunks
+pub const REGION_SIZE: u64 = 32;
+
+pub const MAGIC: &[u8; 4] = b"HVRG";
+/// Format version, see `migrate`
+pub const VERSION: u16 = 3;
+const TABLE_ENTRIES: usize = (REGION_SIZE * REGION_SIZE) as usize;
+const TABLE_SIZE: u64 = 8 * TABLE_ENTRIES as u64;
+const CHUNK_TABLE: u64 = 8;
+const ENTITY_TABLE: u64 = CHUNK_TABLE + TABLE_SIZE;
+const HEADER_SIZE: u64 = ENTITY_TABLE + TABLE_SIZE;
+const BLOCKS: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+/// Payloads of one offset table by position within the region, None where absent
+pub type Payloads = Vec<Option<Vec<u8>>>;
+
+///
+/// Chunk Payload Compression
+///
+/// ---+------+-----------------------------------------------------------------
+///  # | ID   | DESCRIPTION
+/// ---+------+-----------------------------------------------------------------
+///  1 | 0x00 | None: one u16 palette index per Block
+///  2 | 0x01 | Rle: (run length u16, palette index u16) pairs
+///  3 | 0x02 | Lz4: the Rle pairs as an LZ4 block, after their u32 length
+///    |      | and the block's u32 length, see `net::lz4`
+/// ---+------+-----------------------------------------------------------------
+///
+/// Lz4 is built with the `lz4` feature; without it such Chunks can't be
+/// read.
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Compression {
+    None = 0x00,
+    #[default]
+    Rle = 0x01,
+    #[cfg(feature = "lz4")]
+    Lz4 = 0x02,
+}
+
+const COMPRESSION_LZ4: u8 = 0x02;
+
+///
+/// Split a chunk coordinate into its region and the chunk's position within it.
+///
+pub fn region_of(chunk: Vector2<u64>) -> (Vector2<u64>, Vector2<u64>) {
+    (
+        Vector2::new(chunk.x / REGION_SIZE, chunk.y / REGION_SIZE),
+        Vector2::new(chunk.x % REGION_SIZE, chunk.y % REGION_SIZE),
+    )
+}
+
+///
+/// Serialize a Chunk as a material palette followed by palette indices.
+///
+pub fn encode_chunk(chunk: &Chunk, compression: Compression, writer: &mut dyn Write) -> io::Result<()> {
+    let mut palette: Vec<MaterialId> = Vec::new();
+    let mut lookup: HashMap<MaterialId, u16> = HashMap::new();
+    let mut indices: Vec<u16> = Vec::with_capacity(BLOCKS);
+    for x in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let material = chunk.get_block(x, y, z).material();
+                let index = *lookup.entry(material).or_insert_with(|| {
+                    palette.push(material);
+                    (palette.len() - 1) as u16
+                });
+                indices.push(index);
+            }
+        }
+    }
+    write_u8(writer, compression as u8)?;
+    write_u16(writer, palette.len() as u16)?;
+    for material in palette.iter() {
+        write_u16(writer, material.id())?;
+    }
+    match compression {
+        Compression::None => {
+            for index in indices {
+                write_u16(writer, index)?;
+            }
+        }
+        Compression::Rle => writer.write_all(&runs(&indices))?,
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => {
+            let runs = runs(&indices);
+            let packed = lz4::compress(&runs);
+            write_u32(writer, runs.len() as u32)?;
+            write_u32(writer, packed.len() as u32)?;
+            writer.write_all(&packed)?;
+        }
+    }
+    Ok(())
+}
+
+/// Palette indices as (run length u16, palette index u16) pairs.
+fn runs(indices: &[u16]) -> Vec<u8> {
+    let mut runs: Vec<(u16, u16)> = Vec::new();
+    for &index in indices {
+        match runs.last_mut() {
+            Some(&mut (ref mut length, current)) if current == index => *length += 1,
+            _ => runs.push((1, index)),
+        }
+    }
+    let mut bytes = Vec::with_capacity(runs.len() * 4);
+    for (length, index) in runs {
+        bytes.extend_from_slice(&length.to_le_bytes());
+        bytes.extend_from_slice(&index.to_le_bytes());
+    }
+    bytes
+}
+
+/// Read runs written by `runs` until the Chunk is full.
+fn read_runs(reader: &mut dyn Read, indices: &mut Vec<u16>) -> io::Result<()> {
+    while indices.len() < BLOCKS {
+        let length = read_u16(reader)? as usize;
+        let index = read_u16(reader)?;
+        if length == 0 || indices.len() + length > BLOCKS {
+            return Err(invalid_data("invalid chunk run length"));
+        }
+        indices.extend(::std::iter::repeat_n(index, length));
+    }
+    Ok(())
+}
+
+/// Read runs packed into an LZ4 block by `encode_chunk`.
+#[cfg(feature = "lz4")]
+fn read_packed_runs(reader: &mut dyn Read, indices: &mut Vec<u16>) -> io::Result<()> {
+    let length = read_u32(reader)? as usize;
+    let packed_length = read_u32(reader)? as usize;
+    // Runs are longest with each Block in one of its own, and LZ4 grows what it can't shrink by about 1/255
+    if length > BLOCKS * 4 || packed_length > length + length / 255 + 16 {
+        return Err(invalid_data("invalid packed chunk length"));
+    }
+    let mut packed = vec![0u8; packed_length];
+    reader.read_exact(&mut packed)?;
+    let runs = lz4::decompress(&packed, length)?;
+    if runs.len() != length {
+        return Err(invalid_data("packed chunk length is wrong"));
+    }
+    read_runs(&mut &runs[..], indices)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "lz4"))]
+fn read_packed_runs(_: &mut dyn Read, _: &mut Vec<u16>) -> io::Result<()> { Err(invalid_data("lz4 chunk compression needs the lz4 feature")) }
+
+///
+/// Deserialize a Chunk written by encode_chunk into an existing buffer.
+///
+pub fn decode_chunk(reader: &mut dyn Read, chunk: &mut Chunk) -> io::Result<()> {
+    let compression = read_u8(reader)?;
+    let palette_size = read_u16(reader)? as usize;
+    let mut palette: Vec<MaterialId> = Vec::with_capacity(palette_size);
+    for _ in 0..palette_size {
+        palette.push(MaterialId::new(read_u16(reader)?));
+    }
+    let mut indices: Vec<u16> = Vec::with_capacity(BLOCKS);
+    if compression == Compression::None as u8 {
+        for _ in 0..BLOCKS {
+            indices.push(read_u16(reader)?);
+        }
+    } else if compression == Compression::Rle as u8 {
+        read_runs(reader, &mut indices)?;
+    } else if compression == COMPRESSION_LZ4 {
+        read_packed_runs(reader, &mut indices)?;
+    } else {
+        return Err(invalid_data("unknown chunk compression"));
+    }
+    let mut indices = indices.into_iter();
+    for x in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let index = indices.next().unwrap_or(0) as usize;
+                let material = *palette.get(index).ok_or_else(|| invalid_data("palette index out of range"))?;
+                chunk.set_block(x, y, z, Block::new(material));
+            }
+        }
+    }
+    Ok(())
+}
+
+///
+/// Directory of Region Files
+///
+pub struct RegionStorage {
+    directory: PathBuf,
+    compression: Compression,
+}
+
+impl RegionStorage {
+    /// Open (creating if needed) a region directory.
+    pub fn open<P: AsRef<Path>>(directory: P) -> io::Result<RegionStorage> {
+        fs::create_dir_all(directory.as_ref())?;
+        Ok(RegionStorage {
+            directory: directory.as_ref().to_path_buf(),
+            compression: Compression::default(),
+        })
+    }
+    pub fn directory(&self) -> &Path { &self.directory }
+    pub fn compression(&self) -> Compression { self.compression }
+    /// Compression used for subsequently written Chunks.
+    pub fn set_compression(&mut self, compression: Compression) { self.compression = compression }
+    pub fn region_path(&self, region: Vector2<u64>) -> PathBuf {
+        self.directory.join(format!("r.{}.{}.hvr", region.x, region.y))
+    }
+    ///
+    /// Read a Chunk into the provided buffer, returning false if it has never been saved.
+    ///
+    pub fn read_chunk(&self, position: Vector2<u64>, chunk: &mut Chunk) -> io::Result<bool> {
+        match self.read_payload(position, CHUNK_TABLE)? {
+            Some(payload) => {
+                decode_chunk(&mut &payload[..], chunk)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+    ///
+    /// Persist a Chunk to its Region File.
+    ///
+    pub fn write_chunk(&self, position: Vector2<u64>, chunk: &Chunk) -> io::Result<()> {
+        let mut payload: Vec<u8> = Vec::new();
+        encode_chunk(chunk, self.compression, &mut payload)?;
+        self.write_payload(position, CHUNK_TABLE, &payload)
+    }
+    /// Entities stored with a Chunk, none if they never were.
+    pub fn read_entities(&self, position: Vector2<u64>) -> io::Result<Vec<StoredEntity>> {
+        match self.read_payload(position, ENTITY_TABLE)? {
+            Some(payload) => read_entities(&mut &payload[..]),
+            None => Ok(Vec::new()),
+        }
+    }
+    /// Replace the entities stored with a Chunk.
+    pub fn write_entities(&self, position: Vector2<u64>, entities: &[StoredEntity]) -> io::Result<()> {
+        let mut payload: Vec<u8> = Vec::new();
+        write_entities(&mut payload, entities)?;
+        self.write_payload(position, ENTITY_TABLE, &payload)
+    }
+    /// Regions with a file in the directory, in ascending order.
+    pub fn regions(&self) -> io::Result<Vec<Vector2<u64>>> {
+        let mut regions = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let name = entry?.file_name();
+            let parts: Vec<&str> = name.to_str().unwrap_or("").split('.').collect();
+            if let ["r", x, z, "hvr"] = parts[..] {
+                if let (Ok(x), Ok(z)) = (x.parse(), z.parse()) {
+                    regions.push(Vector2::new(x, z));
+                }
+            }
+        }
+        regions.sort();
+        Ok(regions)
+    }
+    /// Positions of every saved Chunk, in ascending region then table order.
+    pub fn chunks(&self) -> io::Result<Vec<Vector2<u64>>> {
+        let mut positions = Vec::new();
+        for region in self.regions()? {
+            let (chunks, _) = read_region(fs::read(self.region_path(region))?)?;
+            for (index, _) in chunks.iter().enumerate().filter(|(_, payload)| payload.is_some()) {
+                let index = index as u64;
+                positions.push(Vector2::new(region.x * REGION_SIZE + index % REGION_SIZE, region.y * REGION_SIZE + index / REGION_SIZE));
+            }
+        }
+        Ok(positions)
+    }
+    ///
+    /// Rewrite a region file without the stale payloads left behind by
+    /// rewrites which didn't fit their slot, returning the bytes reclaimed.
+    ///
+    pub fn compact(&self, region: Vector2<u64>) -> io::Result<u64> {
+        let path = self.region_path(region);
+        upgrade_file(&path)?;
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(error) => return Err(error),
+        };
+        Artifact::Region.version_of(&bytes)?;
+        let compacted = join_region(VERSION, &split_region(&bytes, 2)?)?;
+        if compacted.len() >= bytes.len() {
+            return Ok(0);
+        }
+        replace_file(&path, &compacted)?;
+        Ok((bytes.len() - compacted.len()) as u64)
+    }
+    fn read_payload(&self, position: Vector2<u64>, table: u64) -> io::Result<Option<Vec<u8>>> {
+        let (region, local) = region_of(position);
+        upgrade_file(&self.region_path(region))?;
+        let mut file = match File::open(self.region_path(region)) {
+            Ok(file) => file,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let (offset, length) = read_entry(&mut file, table, table_index(local))?;
+        if offset == 0 {
+            return Ok(None);
+        }
+        if offset as u64 + length as u64 > file.metadata()?.len() {
+            return Err(invalid_data("payload extends past the end of its region file"));
+        }
+        let mut payload = vec![0u8; length as usize];
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.read_exact(&mut payload)?;
+        Ok(Some(payload))
+    }
+    fn write_payload(&self, position: Vector2<u64>, table: u64, payload: &[u8]) -> io::Result<()> {
+        let (region, local) = region_of(position);
+        upgrade_file(&self.region_path(region))?;
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false)
+            .open(self.region_path(region))?;
+        if file.metadata()?.len() < HEADER_SIZE {
+            write_header(&mut file)?;
+        } else {
+            check_header(&mut file)?;
+        }
+        let index = table_index(local);
+        let (offset, length) = read_entry(&mut file, table, index)?;
+        let offset = if offset != 0 && payload.len() as u32 <= length {
+            offset as u64
+        } else {
+            file.seek(SeekFrom::End(0))?
+        };
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(payload)?;
+        file.seek(SeekFrom::Start(table + 8 * index as u64))?;
+        write_u32(&mut file, offset as u32)?;
+        write_u32(&mut file, payload.len() as u32)?;
+        file.flush()
+    }
+}
+
+///
+/// Chunk and entity payloads of a whole region file's bytes, by position
+/// within the region, upgrading them first if an earlier version wrote them.
+///
+pub fn read_region(bytes: Vec<u8>) -> io::Result<(Payloads, Payloads)> {
+    let bytes = Migrations::default().upgrade(Artifact::Region, bytes)?;
+    let mut tables = split_region(&bytes, 2)?;
+    let entities = tables.pop().unwrap_or_default();
+    Ok((tables.pop().unwrap_or_default(), entities))
+}
+
+fn table_index(local: Vector2<u64>) -> usize { (local.y * REGION_SIZE + local.x) as usize }
+
+/// Held while a region file is upgraded, so threads reading it at once upgrade it once.
+static UPGRADING: Mutex<()> = Mutex::new(());
+
+/// Rewrite a region file from an earlier version as the current one.
+fn upgrade_file(path: &Path) -> io::Result<()> {
+    if is_current(path)? {
+        return Ok(());
+    }
+    let _upgrading = UPGRADING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    // Another thread may have upgraded it while this one waited
+    if is_current(path)? {
+        return Ok(());
+    }
+    replace_file(path, &Migrations::default().upgrade(Artifact::Region, fs::read(path)?)?)
+}
+
+/// A region file is missing, empty or needs no upgrade.
+fn is_current(path: &Path) -> io::Result<bool> {
+    let mut header = [0u8; 6];
+    match File::open(path).and_then(|mut file| file.read_exact(&mut header)) {
+        Ok(()) => Ok(Artifact::Region.version_of(&header)? >= VERSION),
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound || error.kind() == io::ErrorKind::UnexpectedEof => Ok(true),
+        Err(error) => Err(error),
+    }
+}
+
+/// Swap in new contents for a region file, so a crash leaves the old or the new.
+fn replace_file(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let staging = path.with_extension("hvr.new");
+    fs::write(&staging, bytes)?;
+    fs::rename(&staging, path)
+}
+
+/// Payloads of a region file with `tables` offset tables, table by table.
+fn split_region(bytes: &[u8], tables: usize) -> io::Result<Vec<Payloads>> {
+    if (bytes.len() as u64) < CHUNK_TABLE + tables as u64 * TABLE_SIZE {
+        return Err(invalid_data("region file header is truncated"));
+    }
+    let mut payloads = Vec::with_capacity(tables);
+    for table in 0..tables {
+        let mut entries = Vec::with_capacity(TABLE_ENTRIES);
+        let mut reader = &bytes[(CHUNK_TABLE + table as u64 * TABLE_SIZE) as usize..];
+        for _ in 0..TABLE_ENTRIES {
+            let offset = read_u32(&mut reader)? as usize;
+            let length = read_u32(&mut reader)? as usize;
+            entries.push(match offset {
+                0 => None,
+                _ => Some(bytes.get(offset..offset + length).ok_or_else(|| invalid_data("payload extends past the end of its region file"))?.to_vec()),
+            });
+        }
+        payloads.push(entries);
+    }
+    Ok(payloads)
+}
+
+/// Lay out a region file of `version` holding `payloads`, table by table.
+fn join_region(version: u16, payloads: &[Payloads]) -> io::Result<Vec<u8>> {
+    let mut bytes = MAGIC.to_vec();
+    write_u16(&mut bytes, version)?;
+    write_u16(&mut bytes, 0)?;
+    let mut body: Vec<u8> = Vec::new();
+    let start = CHUNK_TABLE + payloads.len() as u64 * TABLE_SIZE;
+    for entries in payloads.iter() {
+        for entry in entries.iter() {
+            match *entry {
+                Some(ref payload) => {
+                    write_u32(&mut bytes, (start + body.len() as u64) as u32)?;
+                    write_u32(&mut bytes, payload.len() as u32)?;
+                    body.extend_from_slice(payload);
+                }
+                None => {
+                    write_u32(&mut bytes, 0)?;
+                    write_u32(&mut bytes, 0)?;
+                }
+            }
+        }
+    }
+    bytes.extend(body);
+    Ok(bytes)
+}
+
+/// Region file version 1 to 2: add an empty Entity Table.
+pub fn upgrade_region_v1(bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+    let mut payloads = split_region(&bytes, 1)?;
+    payloads.push(vec![None; TABLE_ENTRIES]);
+    join_region(2, &payloads)
+}
+
+/// Region file version 2 to 3: version the entity payloads.
+pub fn upgrade_region_v2(bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+    let mut payloads = split_region(&bytes, 2)?;
+    for payload in payloads[1].iter_mut().flatten() {
+        payload.splice(0..0, 1u16.to_le_bytes().iter().cloned());
+    }
+    join_region(3, &payloads)
+}
+
+fn write_header(file: &mut File) -> io::Result<()> {
+    let mut header: Vec<u8> = Vec::with_capacity(HEADER_SIZE as usize);
+    header.extend_from_slice(MAGIC);
+    write_u16(&mut header, VERSION)?;
+    write_u16(&mut header, 0)?;
+    header.resize(HEADER_SIZE as usize, 0);
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header)
+}
+
+fn check_header(file: &mut File) -> io::Result<()> {
+    let mut magic = [0u8; 4];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a region file"));
+    }
+    if read_u16(file)? != VERSION {
+        return Err(invalid_data("unsupported region file version"));
+    }
+    Ok(())
+}
+
+fn read_entry(file: &mut File, table: u64, index: usize) -> io::Result<(u32, u32)> {
+    check_header(file)?;
+    file.seek(SeekFrom::Start(table + 8 * index as u64))?;
+    let offset = read_u32(file)?;
+    let length = read_u32(file)?;
+    Ok((offset, length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_chunk, encode_chunk, read_region, Compression, RegionStorage, VERSION};
+    use model::component::Position;
+    use model::material::MaterialId;
+    use model::world::{Block, Chunk, Vector2};
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    pub fn test_region_file_roundtrip() {
+        let directory = env::temp_dir().join(format!("hivemind-storage-{}", process::id()));
+        let storage = RegionStorage::open(&directory).unwrap();
+
+        let mut first = Chunk::new();
+        first.set_block(0, 0, 0, Block::new(MaterialId::new(1)));
+        let mut second = Chunk::new();
+        second.set_block(31, 31, 31, Block::new(MaterialId::new(3)));
+        storage.write_chunk(Vector2::new(2, 3), &first).unwrap();
+        storage.write_chunk(Vector2::new(33, 3), &second).unwrap();
+        storage.write_chunk(Vector2::new(2, 3), &second).unwrap();
+
+        let mut chunk = Chunk::new();
+        assert!(storage.read_chunk(Vector2::new(2, 3), &mut chunk).unwrap());
+        assert_eq!(chunk.get_block(31, 31, 31).material(), MaterialId::new(3));
+        assert!(chunk.get_block(0, 0, 0).is_air());
+        assert!(!storage.read_chunk(Vector2::new(4, 4), &mut chunk).unwrap());
+
+        // The whole file reads the same in memory, and a truncated one is refused
+        let bytes = fs::read(storage.region_path(Vector2::new(0, 0))).unwrap();
+        let (chunks, entities) = read_region(bytes.clone()).unwrap();
+        assert_eq!(chunks.iter().flatten().count(), 1);
+        assert!(entities.iter().all(Option::is_none));
+        decode_chunk(&mut &chunks[3 * 32 + 2].as_ref().unwrap()[..], &mut chunk).unwrap();
+        assert_eq!(chunk.get_block(31, 31, 31).material(), MaterialId::new(3));
+        assert!(read_region(bytes[..bytes.len() - 1].to_vec()).is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    pub fn test_region_file_upgrades() {
+        let directory = env::temp_dir().join(format!("hivemind-upgrade-{}", process::id()));
+        let storage = RegionStorage::open(&directory).unwrap();
+        let fixtures: [&[u8]; 2] = [include_bytes!("../../fixtures/saves/region-v1.hvr"), include_bytes!("../../fixtures/saves/region-v2.hvr")];
+        for (version, fixture) in fixtures.iter().enumerate() {
+            fs::write(storage.region_path(Vector2::new(0, 0)), fixture).unwrap();
+            let mut chunk = Chunk::new();
+            assert!(storage.read_chunk(Vector2::new(2, 3), &mut chunk).unwrap());
+            assert_eq!(chunk.get_block(0, 0, 0).material(), MaterialId::new(1));
+            assert!(chunk.get_block(0, 1, 0).is_air());
+            let entities = storage.read_entities(Vector2::new(2, 3)).unwrap();
+            assert_eq!(entities.len(), version);
+            if let Some(entity) = entities.first() {
+                assert_eq!(entity.component::<Position>("position").unwrap().unwrap(), Position::from_f64(5.5, 3.0, 5.5));
+            }
+            // Upgraded for good
+            let bytes = fs::read(storage.region_path(Vector2::new(0, 0))).unwrap();
+            assert_eq!(u16::from_le_bytes([bytes[4], bytes[5]]), VERSION);
+        }
+
+        // Workers reading an old file at once upgrade it once between them
+        fs::write(storage.region_path(Vector2::new(0, 0)), fixtures[0]).unwrap();
+        let storage = Arc::new(storage);
+        let readers: Vec<_> = (0..8).map(|_| {
+            let storage = storage.clone();
+            thread::spawn(move || storage.read_chunk(Vector2::new(2, 3), &mut Chunk::new()))
+        }).collect();
+        for reader in readers {
+            assert!(reader.join().unwrap().unwrap());
+        }
+        assert!(!storage.region_path(Vector2::new(0, 0)).with_extension("hvr.new").exists());
+
+        // Files from a later version are left alone
+        let mut future = fixtures[1].to_vec();
+        future[4] = 9;
+        fs::write(storage.region_path(Vector2::new(0, 0)), &future).unwrap();
+        assert!(storage.read_chunk(Vector2::new(2, 3), &mut Chunk::new()).is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    pub fn test_chunk_compression() {
+        let mut chunk = Chunk::new();
+        for x in 0..32 {
+            for z in 0..32 {
+                chunk.set_block(x, 0, z, Block::new(MaterialId::new(1)));
+            }
+        }
+        chunk.set_block(5, 9, 5, Block::new(MaterialId::new(2)));
+
+        let mut raw: Vec<u8> = Vec::new();
+        encode_chunk(&chunk, Compression::None, &mut raw).unwrap();
+        let mut rle: Vec<u8> = Vec::new();
+        encode_chunk(&chunk, Compression::Rle, &mut rle).unwrap();
+        assert!(rle.len() < raw.len() / 100);
+
+        for payload in [&raw, &rle].iter() {
+            let mut decoded = Chunk::new();
+            decode_chunk(&mut &payload[..], &mut decoded).unwrap();
+            assert_eq!(decoded.get_block(31, 0, 31).material(), MaterialId::new(1));
+            assert_eq!(decoded.get_block(5, 9, 5).material(), MaterialId::new(2));
+            assert!(decoded.get_block(5, 10, 5).is_air());
+        }
+
+        // Lz4 packs the runs further
+        #[cfg(feature = "lz4")]
+        {
+            let mut packed: Vec<u8> = Vec::new();
+            encode_chunk(&chunk, Compression::Lz4, &mut packed).unwrap();
+            assert!(packed.len() < rle.len());
+            let mut decoded = Chunk::new();
+            decode_chunk(&mut &packed[..], &mut decoded).unwrap();
+            assert_eq!(decoded.get_block(31, 0, 31).material(), MaterialId::new(1));
+            assert_eq!(decoded.get_block(5, 9, 5).material(), MaterialId::new(2));
+            packed.truncate(packed.len() - 1);
+            assert!(decode_chunk(&mut &packed[..], &mut Chunk::new()).is_err());
+        }
+
+        #[cfg(not(feature = "lz4"))]
+        assert!(decode_chunk(&mut &[0x02, 0x00, 0x00][..], &mut Chunk::new()).is_err());
+
+        // Runs may not overflow the Chunk
+        let mut corrupt = vec![0x01, 0x01, 0x00, 0x00, 0x00];
+        corrupt.extend_from_slice(&[0xFF, 0xFF, 0x00, 0x00]);
+        assert!(decode_chunk(&mut &corrupt[..], &mut Chunk::new()).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+///
+/// Structures (Schematics)
+///
+/// A Structure is a box of Blocks copied out of a World. Materials are stored by
+/// name so a Structure can be pasted into a World with a different registry.
+/// Pasting produces a WorldEdit, so placement is atomic and can be undone.
+///
+/// ---+--------+---------------------------------------------------------------
+///  # | SIZE   | DESCRIPTION
+/// ---+--------+---------------------------------------------------------------
+///  1 | 4      | Magic "HVST"
+///  2 | 2      | Format Version
+///  3 | 2*3    | Size in Blocks: x, y, z
+///  4 | 2      | Palette Length
+///  5 | ...    | Palette: (name length u16, UTF-8 name) per material
+///  6 | 2*n    | Palette index per Block, x major then y then z
+/// ---+--------+---------------------------------------------------------------
+///
+use codec::{invalid_data, read_u16, write_u16};
+use migrate::{Artifact, Migrations};
+use model::edit::{EditError, WorldEdit};
+use model::material::{MaterialId, MaterialRegistry};
+use model::update::BlockPosition;
+use model::world::{Block, World};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+pub const MAGIC: &[u8; 4] = b"HVST";
+/// Format version, see `migrate`
+pub const VERSION: u16 = 1;
+
+///
+/// Quarter turns clockwise about the vertical axis, looking down
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Rotation {
+    #[default]
+    None,
+    Clockwise90,
+    Clockwise180,
+    Clockwise270,
+}
+
+///
+/// How to paste a Structure
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct Placement {
+    /// Applied after mirroring
+    pub rotation: Rotation,
+    pub mirror_x: bool,
+    pub mirror_z: bool,
+    /// Material to use in place of a named one
+    pub remap: HashMap<String, MaterialId>,
+    /// Overwrite the World with the Structure's air Blocks
+    pub include_air: bool,
+}
+
+impl Default for Placement {
+    fn default() -> Placement {
+        Placement {
+            rotation: Rotation::None,
+            mirror_x: false,
+            mirror_z: false,
+            remap: HashMap::new(),
+            include_air: true,
+        }
+    }
+}
+
+///
+/// Structure Error
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum StructureError {
+    /// A material isn't registered in the target World and wasn't remapped
+    UnknownMaterial(String),
+    Edit(EditError),
+}
+
+impl fmt::Display for StructureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StructureError::UnknownMaterial(ref name) => write!(f, "unknown material {}", name),
+            StructureError::Edit(EditError::Unloaded(position)) => write!(f, "block {:?} is not loaded", position),
+        }
+    }
+}
+
+///
+/// Copied box of Blocks
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Structure {
+    size: (usize, usize, usize),
+    palette: Vec<String>,
+    blocks: Vec<u16>,
+}
+
+impl Structure {
+    ///
+    /// Copy the inclusive box between two corners.
+    ///
+    pub fn copy(world: &World, min: BlockPosition, max: BlockPosition) -> Result<Structure, StructureError> {
+        let (low, high) = ((min.0.min(max.0), min.1.min(max.1), min.2.min(max.2)), (min.0.max(max.0), min.1.max(max.1), min.2.max(max.2)));
+        let size = ((high.0 - low.0 + 1) as usize, high.1 - low.1 + 1, (high.2 - low.2 + 1) as usize);
+        let mut palette = Vec::new();
+        let mut lookup: HashMap<MaterialId, u16> = HashMap::new();
+        let mut blocks = Vec::with_capacity(size.0 * size.1 * size.2);
+        for x in low.0..high.0 + 1 {
+            for y in low.1..high.1 + 1 {
+                for z in low.2..high.2 + 1 {
+                    let block = world.get_block(x, y, z).ok_or(StructureError::Edit(EditError::Unloaded((x, y, z))))?;
+                    let material = block.material();
+                    let index = match lookup.get(&material) {
+                        Some(&index) => index,
+                        None => {
+                            let name = world.materials().get(material).map_or_else(|| format!("#{}", material.id()), |m| m.name().to_string());
+                            palette.push(name);
+                            lookup.insert(material, (palette.len() - 1) as u16);
+                            (palette.len() - 1) as u16
+                        }
+                    };
+                    blocks.push(index);
+                }
+            }
+        }
+        Ok(Structure { size, palette, blocks })
+    }
+    /// Size in Blocks along x, y and z.
+    pub fn size(&self) -> (usize, usize, usize) { self.size }
+    /// Material names used by the Structure.
+    pub fn palette(&self) -> &[String] { &self.palette }
+    /// Material name at a local position.
+    pub fn material_at(&self, x: usize, y: usize, z: usize) -> &str {
+        &self.palette[self.blocks[(x * self.size.1 + y) * self.size.2 + z] as usize]
+    }
+    /// Size along x, y and z once placed.
+    pub fn placed_size(&self, placement: &Placement) -> (usize, usize, usize) {
+        match placement.rotation {
+            Rotation::None | Rotation::Clockwise180 => self.size,
+            Rotation::Clockwise90 | Rotation::Clockwise270 => (self.size.2, self.size.1, self.size.0),
+        }
+    }
+    ///
+    /// Edit which places the Structure with its minimum corner at `origin`.
+    ///
+    pub fn to_edit(&self, materials: &MaterialRegistry, origin: BlockPosition, placement: &Placement) -> Result<WorldEdit, StructureError> {
+        let mut ids = Vec::with_capacity(self.palette.len());
+        for name in self.palette.iter() {
+            let id = placement.remap.get(name).cloned().or_else(|| materials.id(name));
+            ids.push(id.ok_or_else(|| StructureError::UnknownMaterial(name.clone()))?);
+        }
+        let (sx, sz) = (self.size.0, self.size.2);
+        let mut edit = WorldEdit::new();
+        for x in 0..sx {
+            for y in 0..self.size.1 {
+                for z in 0..sz {
+                    let block = Block::new(ids[self.blocks[(x * self.size.1 + y) * sz + z] as usize]);
+                    if block.is_air() && !placement.include_air {
+                        continue;
+                    }
+                    let mx = if placement.mirror_x { sx - 1 - x } else { x };
+                    let mz = if placement.mirror_z { sz - 1 - z } else { z };
+                    let (px, pz) = match placement.rotation {
+                        Rotation::None => (mx, mz),
+                        Rotation::Clockwise90 => (sz - 1 - mz, mx),
+                        Rotation::Clockwise180 => (sx - 1 - mx, sz - 1 - mz),
+                        Rotation::Clockwise270 => (mz, sx - 1 - mx),
+                    };
+                    edit.set(origin.0 + px as u64, origin.1 + y, origin.2 + pz as u64, block);
+                }
+            }
+        }
+        Ok(edit)
+    }
+    ///
+    /// Place the Structure immediately, returning the edit which removes it.
+    ///
+    pub fn paste(&self, world: &mut World, origin: BlockPosition, placement: &Placement) -> Result<WorldEdit, StructureError> {
+        let edit = self.to_edit(world.materials(), origin, placement)?;
+        edit.apply(world).map_err(StructureError::Edit)
+    }
+    pub fn save(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        write_u16(writer, VERSION)?;
+        write_u16(writer, self.size.0 as u16)?;
+        write_u16(writer, self.size.1 as u16)?;
+        write_u16(writer, self.size.2 as u16)?;
+        write_u16(writer, self.palette.len() as u16)?;
+        for name in self.palette.iter() {
+            write_u16(writer, name.len() as u16)?;
+            writer.write_all(name.as_bytes())?;
+        }
+        for &index in self.blocks.iter() {
+            write_u16(writer, index)?;
+        }
+        Ok(())
+    }
+    /// Read a Structure, upgrading one saved by an earlier version.
+    pub fn load(reader: &mut dyn Read) -> io::Result<Structure> {
+        let bytes = Migrations::default().read(Artifact::Structure, reader)?;
+        let reader: &mut dyn Read = &mut &bytes[MAGIC.len() + 2..];
+        let size = (read_u16(reader)? as usize, read_u16(reader)? as usize, read_u16(reader)? as usize);
+        let mut palette = Vec::new();
+        for _ in 0..read_u16(reader)? {
+            let mut name = vec![0; read_u16(reader)? as usize];
+            reader.read_exact(&mut name)?;
+            palette.push(String::from_utf8(name).map_err(|_| invalid_data("material name is not UTF-8"))?);
+        }
+        let mut blocks = Vec::with_capacity(size.0 * size.1 * size.2);
+        for _ in 0..size.0 * size.1 * size.2 {
+            let index = read_u16(reader)?;
+            if index as usize >= palette.len() {
+                return Err(invalid_data("palette index out of range"));
+            }
+            blocks.push(index);
+        }
+        Ok(Structure { size, palette, blocks })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Placement, Rotation, Structure, StructureError};
+    use model::material::{Material, MaterialRegistry};
+    use model::world::{Block, Chunk, Vector2, World};
+    use pool::Poolable;
+
+    fn world(materials: MaterialRegistry) -> World {
+        let mut world = World::with_materials(materials);
+        world.insert_chunk(Vector2::new(0, 0), Chunk::allocate());
+        world
+    }
+
+    #[test]
+    pub fn test_structure_copy_paste() {
+        let mut source = world(MaterialRegistry::default());
+        let rock = Block::new(source.materials().id("rock").unwrap());
+        let metal = Block::new(source.materials().id("metal").unwrap());
+        // An L of rock with a metal tip, 3 long in x and 2 in z
+        source.set_block(1, 1, 1, rock);
+        source.set_block(2, 1, 1, rock);
+        source.set_block(3, 1, 1, metal);
+        source.set_block(1, 1, 2, rock);
+        let structure = Structure::copy(&source, (3, 1, 2), (1, 1, 1)).unwrap();
+        assert_eq!(structure.size(), (3, 1, 2));
+        assert_eq!(structure.material_at(2, 0, 0), "metal");
+
+        let mut buffer = Vec::new();
+        structure.save(&mut buffer).unwrap();
+        let loaded = Structure::load(&mut &buffer[..]).unwrap();
+        assert_eq!(loaded, structure);
+
+        // A registry with different ids, where metal is called steel
+        let mut materials = MaterialRegistry::new();
+        let steel = materials.register(Material::new("steel", 10.0, 1.0));
+        let stone = materials.register(Material::new("rock", 5.0, 1.0));
+        let mut target = world(materials);
+        let mut placement = Placement { rotation: Rotation::Clockwise90, ..Placement::default() };
+        assert_eq!(loaded.paste(&mut target, (10, 0, 10), &placement), Err(StructureError::UnknownMaterial("metal".to_string())));
+        placement.remap.insert("metal".to_string(), steel);
+        let undo = loaded.paste(&mut target, (10, 0, 10), &placement).unwrap();
+        assert_eq!(loaded.placed_size(&placement), (2, 1, 3));
+        // Rotated clockwise the arm along x now runs along z from the far x side
+        assert_eq!(target.get_block(11, 0, 10), Some(Block::new(stone)));
+        assert_eq!(target.get_block(11, 0, 12), Some(Block::new(steel)));
+        assert_eq!(target.get_block(10, 0, 10), Some(Block::new(stone)));
+        assert!(target.get_block(10, 0, 12).unwrap().is_air());
+
+        undo.apply(&mut target).unwrap();
+        assert!(target.get_block(11, 0, 12).unwrap().is_air());
+
+        // Mirrored in x the metal tip ends up at the origin side
+        let mirrored = Placement { mirror_x: true, include_air: false, ..placement.clone() };
+        loaded.paste(&mut target, (0, 0, 0), &Placement { rotation: Rotation::None, ..mirrored }).unwrap();
+        assert_eq!(target.get_block(0, 0, 0), Some(Block::new(steel)));
+        assert_eq!(target.get_block(2, 0, 1), Some(Block::new(stone)));
+    }
+}
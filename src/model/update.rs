@@ -0,0 +1,157 @@
+///
+/// Scheduled Block Updates
+///
+/// Blocks ask to be revisited a number of ticks in the future. Each tick the
+/// due updates are handed out in (due tick, position) order, capped by a budget
+/// so a burst of activity spills over into later ticks instead of stalling one.
+/// Fluids, growth, fire spread and decay are all built on top of this.
+///
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Block coordinates in the World
+pub type BlockPosition = (u64, usize, u64);
+
+/// Default maximum number of updates processed per tick
+pub const DEFAULT_UPDATE_BUDGET: usize = 4096;
+
+///
+/// Due Block Update
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BlockUpdate {
+    /// Tick the update was scheduled for
+    pub due: u64,
+    pub position: BlockPosition,
+}
+
+///
+/// Block Update Queue
+///
+#[derive(Clone, Debug)]
+pub struct UpdateScheduler {
+    tick: u64,
+    budget: usize,
+    queue: BinaryHeap<Reverse<(u64, BlockPosition)>>,
+    /// Live due tick per position, heap entries which disagree are stale
+    pending: HashMap<BlockPosition, u64>,
+}
+
+impl UpdateScheduler {
+    pub fn new() -> UpdateScheduler { UpdateScheduler::with_budget(DEFAULT_UPDATE_BUDGET) }
+    pub fn with_budget(budget: usize) -> UpdateScheduler {
+        UpdateScheduler {
+            tick: 0,
+            budget,
+            queue: BinaryHeap::new(),
+            pending: HashMap::new(),
+        }
+    }
+    /// Last tick processed.
+    pub fn tick(&self) -> u64 { self.tick }
+    pub fn budget(&self) -> usize { self.budget }
+    pub fn set_budget(&mut self, budget: usize) { self.budget = budget }
+    /// Number of scheduled updates.
+    pub fn len(&self) -> usize { self.pending.len() }
+    pub fn is_empty(&self) -> bool { self.pending.is_empty() }
+    /// Tick a position is scheduled for, if any.
+    pub fn scheduled(&self, position: BlockPosition) -> Option<u64> { self.pending.get(&position).cloned() }
+    ///
+    /// Schedule an update `delay` ticks from now; a delay of zero means the
+    /// next tick. A Block has at most one pending update, so this returns false
+    /// if one is already due no later than the requested tick.
+    ///
+    pub fn schedule(&mut self, position: BlockPosition, delay: u64) -> bool {
+        let due = self.tick + delay.max(1);
+        match self.pending.get(&position) {
+            Some(&existing) if existing <= due => return false,
+            _ => {}
+        }
+        self.pending.insert(position, due);
+        self.queue.push(Reverse((due, position)));
+        true
+    }
+    /// Drop a pending update, returns false if none was scheduled.
+    pub fn cancel(&mut self, position: BlockPosition) -> bool { self.pending.remove(&position).is_some() }
+    ///
+    /// Advance one tick and take the due updates, at most `budget` of them.
+    /// Updates over budget stay queued and come first on the next tick.
+    ///
+    pub fn advance(&mut self) -> Vec<BlockUpdate> {
+        self.tick += 1;
+        let mut due = Vec::new();
+        while due.len() < self.budget {
+            match self.queue.peek() {
+                Some(&Reverse((tick, _))) if tick <= self.tick => {}
+                _ => break,
+            }
+            let Reverse((tick, position)) = self.queue.pop().unwrap();
+            if self.pending.get(&position) == Some(&tick) {
+                self.pending.remove(&position);
+                due.push(BlockUpdate { due: tick, position });
+            }
+        }
+        due
+    }
+}
+
+impl Default for UpdateScheduler {
+    fn default() -> UpdateScheduler { UpdateScheduler::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UpdateScheduler;
+    use model::material::AIR;
+    use model::world::{Block, Chunk, Vector2, World};
+    use pool::Poolable;
+
+    #[test]
+    pub fn test_update_order_and_budget() {
+        let mut scheduler = UpdateScheduler::with_budget(2);
+        assert!(scheduler.schedule((5, 0, 0), 2));
+        assert!(scheduler.schedule((1, 0, 0), 2));
+        assert!(scheduler.schedule((3, 0, 0), 1));
+        assert!(scheduler.schedule((9, 0, 0), 2));
+        // Already due sooner
+        assert!(!scheduler.schedule((3, 0, 0), 4));
+        // Rescheduling sooner replaces the later update
+        assert!(scheduler.schedule((7, 0, 0), 5));
+        assert!(scheduler.schedule((7, 0, 0), 2));
+        assert!(scheduler.cancel((9, 0, 0)));
+        assert_eq!(scheduler.len(), 4);
+
+        let positions = |updates: Vec<super::BlockUpdate>| updates.iter().map(|u| u.position.0).collect::<Vec<_>>();
+        assert_eq!(positions(scheduler.advance()), vec![3]);
+        assert_eq!(positions(scheduler.advance()), vec![1, 5]);
+        assert_eq!(positions(scheduler.advance()), vec![7]);
+        assert!(scheduler.advance().is_empty());
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    pub fn test_world_block_updates() {
+        let mut world = World::new();
+        let rock = world.materials().id("rock").unwrap();
+        world.insert_chunk(Vector2::new(0, 0), Chunk::allocate());
+        world.set_block(4, 10, 4, Block::new(rock));
+        world.schedule_update(4, 10, 4, 1);
+
+        // Unsupported blocks fall one block per tick
+        for _ in 0..20 {
+            world.tick_updates(&mut |world, update| {
+                let (x, y, z) = update.position;
+                let block = world.get_block(x, y, z).unwrap();
+                if y > 0 && !block.is_air() && world.get_block(x, y - 1, z) == Some(Block::new(AIR)) {
+                    world.set_block(x, y, z, Block::new(AIR));
+                    world.set_block(x, y - 1, z, block);
+                    world.schedule_update(x, y - 1, z, 1);
+                }
+            });
+        }
+        assert_eq!(world.get_block(4, 0, 4).unwrap().material(), rock);
+        assert!(world.get_block(4, 10, 4).unwrap().is_air());
+        assert!(world.updates().is_empty());
+        assert_eq!(world.updates().tick(), 20);
+    }
+}
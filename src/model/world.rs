@@ -1,22 +1,785 @@
-use Entity;
+pub use math::Vector2;
+use error::HivemindError;
+use model::biome::{BiomeId, BiomeMap};
+use model::bounds::WorldBounds;
+use model::faction::Factions;
+use model::heightmap::Heightmap;
+use model::light::{Light, Lighting};
+use model::material::{AIR, MaterialId, MaterialRegistry};
+use model::persist::StoredEntity;
+use model::observer::{EventFilter, Observers, SubscriberId, WorldEvent};
+use model::pheromone::PheromoneField;
+use model::provider::{ChunkProvider, ChunkStatus};
+use model::storage::{region_of, RegionStorage, REGION_SIZE};
+use model::update::{BlockPosition, BlockUpdate, UpdateScheduler};
+use model::worldgen::ChunkGenerator;
+use pool::{Pool, Poolable};
+use std::collections::BTreeSet;
+use std::collections::HashMap as Map;
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Edge length of a Chunk in Blocks
+pub const CHUNK_SIZE: usize = 32;
 
 pub struct World {
+    materials: MaterialRegistry,
     regions: Map<Vector2<u64>, Region>,
+    storage: Option<RegionStorage>,
+    provider: Option<ChunkProvider>,
+    chunk_pool: Pool<Chunk>,
+    updates: UpdateScheduler,
+    lighting: Lighting,
+    pheromones: PheromoneField,
+    factions: Factions,
+    /// Blocks changed since last taken, None when not tracking
+    changes: Option<Vec<BlockPosition>>,
+    observers: Observers,
+    heightmaps: Map<Vector2<u64>, Heightmap>,
+    bounds: WorldBounds,
+    biomes: Option<BiomeMap>,
+    /// Entities read or stored with a Chunk and not yet spawned
+    pending_entities: Map<Vector2<u64>, Vec<StoredEntity>>,
+    /// Loaded Chunks changed since they were last saved
+    dirty: BTreeSet<Vector2<u64>>,
+    /// Value of use_clock when each loaded Chunk was last used
+    last_used: Map<Vector2<u64>, u64>,
+    use_clock: u64,
+}
+
+impl World {
+    pub fn new() -> World { World::with_materials(MaterialRegistry::default()) }
+    pub fn with_materials(materials: MaterialRegistry) -> World {
+        World {
+            materials,
+            regions: Map::new(),
+            storage: None,
+            provider: None,
+            chunk_pool: Pool::new(),
+            updates: UpdateScheduler::new(),
+            lighting: Lighting::new(),
+            pheromones: PheromoneField::new(),
+            factions: Factions::new(),
+            changes: None,
+            observers: Observers::new(),
+            heightmaps: Map::new(),
+            bounds: WorldBounds::Unbounded,
+            biomes: None,
+            pending_entities: Map::new(),
+            dirty: BTreeSet::new(),
+            last_used: Map::new(),
+            use_clock: 0,
+        }
+    }
+    /// Create a World which pages its chunks to region files in `directory`.
+    pub fn open<P: AsRef<Path>>(directory: P) -> Result<World, HivemindError> {
+        let mut world = World::new();
+        world.storage = Some(RegionStorage::open(directory)?);
+        Ok(world)
+    }
+    pub fn materials(&self) -> &MaterialRegistry { &self.materials }
+    pub fn materials_mut(&mut self) -> &mut MaterialRegistry { &mut self.materials }
+    pub fn storage(&self) -> Option<&RegionStorage> { self.storage.as_ref() }
+    pub fn set_storage(&mut self, storage: Option<RegionStorage>) { self.storage = storage }
+    /// Attach a background ChunkProvider used by request_chunk.
+    pub fn set_provider(&mut self, provider: Option<ChunkProvider>) { self.provider = provider }
+    pub fn provider(&self) -> Option<&ChunkProvider> { self.provider.as_ref() }
+    pub fn chunk_pool(&self) -> &Pool<Chunk> { &self.chunk_pool }
+    pub fn chunk_pool_mut(&mut self) -> &mut Pool<Chunk> { &mut self.chunk_pool }
+    pub fn is_chunk_loaded(&self, position: Vector2<u64>) -> bool { self.get_chunk(position).is_some() }
+    pub fn get_chunk(&self, position: Vector2<u64>) -> Option<&Chunk> {
+        let (region, local) = region_of(position);
+        self.regions.get(&region).and_then(|region| region.chunks.get(&local)).map(|chunk| &***chunk)
+    }
+    ///
+    /// Mutable Chunk access, call mark_light_dirty and refresh_heightmap after
+    /// editing it directly. A Chunk shared with a fork is copied first.
+    ///
+    pub fn get_chunk_mut(&mut self, position: Vector2<u64>) -> Option<&mut Chunk> {
+        let (region, local) = region_of(position);
+        if self.regions.get(&region).is_some_and(|region| region.chunks.contains_key(&local)) {
+            self.dirty.insert(position);
+            self.touch_chunk(position);
+        }
+        self.regions.get_mut(&region).and_then(|region| region.chunks.get_mut(&local)).map(|chunk| &mut **Arc::make_mut(chunk))
+    }
+    pub fn bounds(&self) -> WorldBounds { self.bounds }
+    /// Change the extent of the World. Chunks already loaded outside it stay until unloaded.
+    pub fn set_bounds(&mut self, bounds: WorldBounds) { self.bounds = bounds }
+    /// Block column at signed coordinates, such as an entity's, None if it is outside the World.
+    pub fn resolve(&self, x: i64, z: i64) -> Option<(u64, u64)> { self.bounds.resolve(x, z) }
+    ///
+    /// Place a Chunk in memory, returning any Chunk it replaced, or the Chunk
+    /// itself if it lies outside the World's bounds.
+    ///
+    pub fn insert_chunk(&mut self, position: Vector2<u64>, chunk: Box<Chunk>) -> Option<Box<Chunk>> {
+        self.place_chunk(position, chunk, WorldEvent::ChunkLoaded(position))
+    }
+    fn place_chunk(&mut self, position: Vector2<u64>, chunk: Box<Chunk>, event: WorldEvent) -> Option<Box<Chunk>> {
+        if !self.bounds.contains_chunk(position) {
+            return Some(chunk);
+        }
+        let (region, local) = region_of(position);
+        self.lighting.mark_dirty(position);
+        self.heightmaps.insert(position, Heightmap::build(&chunk, &self.materials));
+        self.dirty.insert(position);
+        self.use_clock += 1;
+        self.last_used.insert(position, self.use_clock);
+        self.observers.publish(event);
+        self.regions.entry(region).or_default().chunks.insert(local, Arc::new(chunk)).map(unshare)
+    }
+    /// Synchronously generate a Chunk in place, replacing any loaded copy.
+    pub fn generate_chunk(&mut self, position: Vector2<u64>, generator: &dyn ChunkGenerator) {
+        let mut chunk = self.chunk_pool.acquire();
+        generator.generate(position, &mut chunk);
+        if let Some(previous) = self.place_chunk(position, chunk, WorldEvent::ChunkGenerated(position)) {
+            self.chunk_pool.release(previous);
+        }
+    }
+    /// Positions of all Chunks currently in memory.
+    pub fn loaded_chunks(&self) -> Vec<Vector2<u64>> {
+        let mut positions: Vec<Vector2<u64>> = Vec::new();
+        for (region, chunks) in self.regions.iter() {
+            for local in chunks.chunks.keys() {
+                positions.push(Vector2::new(
+                    region.x * REGION_SIZE + local.x,
+                    region.y * REGION_SIZE + local.y,
+                ));
+            }
+        }
+        positions.sort();
+        positions
+    }
+    ///
+    /// Page a Chunk in from storage. Returns false if the Chunk has never been saved.
+    ///
+    pub fn load_chunk(&mut self, position: Vector2<u64>) -> Result<bool, HivemindError> {
+        if self.is_chunk_loaded(position) {
+            return Ok(true);
+        }
+        let storage = match self.storage {
+            Some(ref storage) => storage,
+            None => return Ok(false),
+        };
+        let mut chunk = self.chunk_pool.acquire();
+        match storage.read_chunk(position, &mut chunk).and_then(|found| Ok((found, storage.read_entities(position)?))) {
+            Ok((true, entities)) => {
+                self.insert_chunk(position, chunk);
+                self.dirty.remove(&position);
+                if !entities.is_empty() {
+                    self.pending_entities.entry(position).or_default().extend(entities);
+                }
+                Ok(true)
+            }
+            Ok((false, _)) => {
+                self.chunk_pool.release(chunk);
+                Ok(false)
+            }
+            Err(error) => {
+                self.chunk_pool.release(chunk);
+                Err(match error.kind() {
+                    io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => HivemindError::CorruptChunk { position, reason: error.to_string() },
+                    _ => HivemindError::Io(error),
+                })
+            }
+        }
+    }
+    ///
+    /// Write a Chunk and its pending entities to storage (if any) and release
+    /// them from memory.
+    ///
+    pub fn unload_chunk(&mut self, position: Vector2<u64>) -> Result<bool, HivemindError> {
+        if self.dirty.contains(&position) {
+            self.save_chunk(position)?;
+        }
+        let (region, local) = region_of(position);
+        let (chunk, empty) = match self.regions.get_mut(&region) {
+            Some(region) => (region.chunks.remove(&local), region.chunks.is_empty()),
+            None => (None, false),
+        };
+        if empty {
+            self.regions.remove(&region);
+        }
+        self.lighting.remove(position);
+        self.heightmaps.remove(&position);
+        self.pending_entities.remove(&position);
+        self.dirty.remove(&position);
+        self.last_used.remove(&position);
+        self.pheromones.remove_chunk(position);
+        if let Some(ref mut provider) = self.provider {
+            provider.forget(position);
+        }
+        match chunk {
+            Some(chunk) => {
+                // A Chunk still shared with a fork stays with the fork
+                if let Ok(chunk) = Arc::try_unwrap(chunk) {
+                    self.chunk_pool.release(chunk);
+                }
+                self.observers.publish(WorldEvent::ChunkUnloaded(position));
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+    /// Load status of a Chunk, including in-flight provider requests.
+    pub fn chunk_status(&self, position: Vector2<u64>) -> ChunkStatus {
+        if self.is_chunk_loaded(position) {
+            ChunkStatus::Ready
+        } else {
+            match self.provider {
+                Some(ref provider) => match provider.status(position) {
+                    ChunkStatus::Ready => ChunkStatus::Unloaded,
+                    status => status,
+                },
+                None => ChunkStatus::Unloaded,
+            }
+        }
+    }
+    ///
+    /// Ask the provider to load or generate a Chunk in the background. The Chunk
+    /// becomes available after a later sync_chunks.
+    ///
+    pub fn request_chunk(&mut self, position: Vector2<u64>) -> ChunkStatus {
+        if self.is_chunk_loaded(position) {
+            return ChunkStatus::Ready;
+        }
+        if let Some(ref mut provider) = self.provider {
+            let chunk = self.chunk_pool.acquire();
+            if let Some(chunk) = provider.request(position, chunk) {
+                self.chunk_pool.release(chunk);
+            }
+        }
+        self.chunk_status(position)
+    }
+    ///
+    /// Tick synchronization point: insert every Chunk the provider has finished,
+    /// returning their positions in ascending order. As with `load_chunk` they
+    /// start clean, with the entities stored with them pending, see
+    /// `Simulation::step`. A Chunk loaded meanwhile is kept over the copy
+    /// provided.
+    ///
+    pub fn sync_chunks(&mut self) -> Vec<Vector2<u64>> {
+        let completed = match self.provider {
+            Some(ref mut provider) => provider.sync(),
+            None => return Vec::new(),
+        };
+        for chunk in completed.spare {
+            self.chunk_pool.release(chunk);
+        }
+        let mut positions = Vec::with_capacity(completed.ready.len());
+        for (position, chunk, entities) in completed.ready {
+            if self.is_chunk_loaded(position) {
+                self.chunk_pool.release(chunk);
+                continue;
+            }
+            if let Some(rejected) = self.insert_chunk(position, chunk) {
+                self.chunk_pool.release(rejected);
+                continue;
+            }
+            self.dirty.remove(&position);
+            if !entities.is_empty() {
+                self.pending_entities.entry(position).or_default().extend(entities);
+            }
+            positions.push(position);
+        }
+        positions
+    }
+    /// Write a loaded Chunk and its pending entities to storage without unloading them.
+    pub fn save_chunk(&mut self, position: Vector2<u64>) -> Result<(), HivemindError> { self.save_chunk_with(position, &[]) }
+    ///
+    /// Write a loaded Chunk to storage with its pending entities and the live
+    /// `residents` standing in it, see `Simulation::save_chunk`.
+    ///
+    pub fn save_chunk_with(&mut self, position: Vector2<u64>, residents: &[StoredEntity]) -> Result<(), HivemindError> {
+        if let (Some(storage), Some(chunk)) = (self.storage.as_ref(), self.get_chunk(position)) {
+            storage.write_chunk(position, chunk)?;
+            let pending = self.pending_entities.get(&position).map_or(&[][..], |entities| &entities[..]);
+            if residents.is_empty() {
+                storage.write_entities(position, pending)?;
+            } else {
+                storage.write_entities(position, &[pending, residents].concat())?;
+            }
+            self.dirty.remove(&position);
+        }
+        Ok(())
+    }
+    /// Replace the entities stored with a loaded Chunk by its pending ones and `residents`, leaving its Blocks as stored.
+    pub fn save_entities(&mut self, position: Vector2<u64>, residents: &[StoredEntity]) -> Result<(), HivemindError> {
+        if let (Some(storage), true) = (self.storage.as_ref(), self.is_chunk_loaded(position)) {
+            let pending = self.pending_entities.get(&position).map_or(&[][..], |entities| &entities[..]);
+            storage.write_entities(position, &[pending, residents].concat())?;
+        }
+        Ok(())
+    }
+    /// Mark a loaded Chunk as just used, putting it last in eviction order.
+    pub fn touch_chunk(&mut self, position: Vector2<u64>) {
+        if let Some(used) = self.last_used.get_mut(&position) {
+            self.use_clock += 1;
+            *used = self.use_clock;
+        }
+    }
+    /// Estimated bytes held by the loaded Chunks and their heightmaps.
+    pub fn chunk_bytes(&self) -> usize { self.last_used.len() * (mem::size_of::<Chunk>() + mem::size_of::<Heightmap>()) }
+    /// Clean loaded Chunks, least recently used first.
+    pub fn eviction_order(&self) -> Vec<Vector2<u64>> {
+        let mut clean: Vec<(u64, Vector2<u64>)> = self.last_used.iter()
+            .filter(|&(position, _)| !self.dirty.contains(position))
+            .map(|(&position, &used)| (used, position))
+            .collect();
+        clean.sort();
+        clean.into_iter().map(|(_, position)| position).collect()
+    }
+    pub fn is_dirty(&self, position: Vector2<u64>) -> bool { self.dirty.contains(&position) }
+    /// Loaded Chunks changed since they were last saved, in ascending order.
+    pub fn dirty_chunks(&self) -> Vec<Vector2<u64>> { self.dirty.iter().cloned().collect() }
+    ///
+    /// Write only the Chunks changed since they were last saved, returning
+    /// how many were written. Region files keep the stale copies of rewritten
+    /// Chunks until the next full save compacts them.
+    ///
+    pub fn save_dirty(&mut self) -> Result<usize, HivemindError> {
+        let dirty = self.dirty_chunks();
+        for &position in dirty.iter() {
+            self.save_chunk(position)?;
+        }
+        Ok(dirty.len())
+    }
+    /// Entities loaded with a Chunk and waiting to be spawned.
+    pub fn pending_entities(&self, position: Vector2<u64>) -> impl Iterator<Item=&StoredEntity> {
+        self.pending_entities.get(&position).into_iter().flat_map(|entities| entities.iter())
+    }
+    ///
+    /// Take a Chunk's pending entities to spawn them, see
+    /// `Simulation::load_chunk`. The Chunk is then dirty, and only a save
+    /// through the Simulation writes the spawned entities back with it.
+    ///
+    pub fn take_pending_entities(&mut self, position: Vector2<u64>) -> Vec<StoredEntity> {
+        let entities = self.pending_entities.remove(&position).unwrap_or_default();
+        if !entities.is_empty() && self.is_chunk_loaded(position) {
+            self.dirty.insert(position);
+        }
+        entities
+    }
+    /// Keep entities with a Chunk, to be written when it is saved or unloaded.
+    pub fn store_entities(&mut self, position: Vector2<u64>, entities: Vec<StoredEntity>) {
+        if !entities.is_empty() {
+            self.pending_entities.entry(position).or_default().extend(entities);
+            if self.is_chunk_loaded(position) {
+                self.dirty.insert(position);
+            }
+        }
+    }
+    ///
+    /// Full save: write every changed Chunk, then compact every region file.
+    /// Entities spawned from a Chunk are only written with it by
+    /// `Simulation::save`.
+    ///
+    pub fn save(&mut self) -> Result<(), HivemindError> {
+        self.save_dirty()?;
+        self.compact()
+    }
+    /// Drop the stale copies of rewritten Chunks from every region file.
+    pub fn compact(&mut self) -> Result<(), HivemindError> {
+        if let Some(ref storage) = self.storage {
+            for region in storage.regions()? {
+                storage.compact(region)?;
+            }
+        }
+        Ok(())
+    }
+    /// Block at world coordinates, None if its Chunk isn't loaded.
+    pub fn get_block(&self, x: u64, y: usize, z: u64) -> Option<Block> {
+        if y >= CHUNK_SIZE {
+            return None;
+        }
+        let (chunk, lx, lz) = chunk_of(x, z);
+        self.get_chunk(chunk).map(|chunk| chunk.get_block(lx, y, lz))
+    }
+    /// Set a Block at world coordinates, returns false if its Chunk isn't loaded.
+    pub fn set_block(&mut self, x: u64, y: usize, z: u64, block: Block) -> bool {
+        if y >= CHUNK_SIZE {
+            return false;
+        }
+        let (position, lx, lz) = chunk_of(x, z);
+        let (region, local) = region_of(position);
+        match self.regions.get_mut(&region).and_then(|region| region.chunks.get_mut(&local)) {
+            Some(chunk) => {
+                let before = chunk.get_block(lx, y, lz);
+                if before != block {
+                    Arc::make_mut(chunk).set_block(lx, y, lz, block);
+                }
+                self.lighting.mark_dirty(position);
+                if let Some(used) = self.last_used.get_mut(&position) {
+                    self.use_clock += 1;
+                    *used = self.use_clock;
+                }
+                if before != block {
+                    self.dirty.insert(position);
+                    if let Some(heightmap) = self.heightmaps.get_mut(&position) {
+                        heightmap.update(chunk, &self.materials, lx, lz);
+                    }
+                    if let Some(ref mut changes) = self.changes {
+                        changes.push((x, y, z));
+                    }
+                    self.observers.publish(WorldEvent::BlockChanged { position: (x, y, z), before, after: block });
+                }
+                true
+            }
+            None => false,
+        }
+    }
+    /// Record every Block changed through set_block until disabled.
+    pub fn track_changes(&mut self, enabled: bool) {
+        if enabled != self.changes.is_some() {
+            self.changes = if enabled { Some(Vec::new()) } else { None };
+        }
+    }
+    pub fn is_tracking_changes(&self) -> bool { self.changes.is_some() }
+    /// Blocks changed since the last call, in the order they changed.
+    pub fn take_changes(&mut self) -> Vec<BlockPosition> {
+        self.changes.as_mut().map(mem::take).unwrap_or_default()
+    }
+    pub fn observers(&self) -> &Observers { &self.observers }
+    pub fn observers_mut(&mut self) -> &mut Observers { &mut self.observers }
+    /// Start queueing the events `filter` accepts, to be drained with drain_events.
+    pub fn subscribe(&mut self, filter: EventFilter) -> SubscriberId { self.observers.subscribe(filter) }
+    /// Events for `subscriber` since it last drained, in the order they happened.
+    pub fn drain_events(&mut self, subscriber: SubscriberId) -> Vec<WorldEvent> { self.observers.drain(subscriber) }
+    pub fn heightmap(&self, position: Vector2<u64>) -> Option<&Heightmap> { self.heightmaps.get(&position) }
+    /// Rebuild a Chunk's heightmap after editing it through get_chunk_mut or changing its materials.
+    pub fn refresh_heightmap(&mut self, position: Vector2<u64>) {
+        let (region, local) = region_of(position);
+        if let Some(chunk) = self.regions.get(&region).and_then(|region| region.chunks.get(&local)) {
+            self.heightmaps.insert(position, Heightmap::build(chunk, &self.materials));
+        }
+    }
+    /// Lowest y of a column above which there is only air, None if its Chunk isn't loaded.
+    pub fn surface_height(&self, x: u64, z: u64) -> Option<usize> {
+        let (chunk, lx, lz) = chunk_of(x, z);
+        self.heightmaps.get(&chunk).map(|heightmap| heightmap.surface_height(lx, lz))
+    }
+    /// Highest solid Block of a column, None if there is none or its Chunk isn't loaded.
+    pub fn topmost_solid(&self, x: u64, z: u64) -> Option<usize> {
+        let (chunk, lx, lz) = chunk_of(x, z);
+        self.heightmaps.get(&chunk).and_then(|heightmap| heightmap.topmost_solid(lx, lz))
+    }
+    /// Whether a Block is loaded and made of a material with any resistance.
+    pub fn is_solid(&self, x: u64, y: usize, z: u64) -> bool {
+        match self.get_block(x, y, z) {
+            Some(block) => !block.is_air() && self.materials.get(block.material()).is_some_and(|material| material.resistance() > 0.0),
+            None => false,
+        }
+    }
+    pub fn updates(&self) -> &UpdateScheduler { &self.updates }
+    pub fn updates_mut(&mut self) -> &mut UpdateScheduler { &mut self.updates }
+    /// Request an update for a Block `delay` ticks from now.
+    pub fn schedule_update(&mut self, x: u64, y: usize, z: u64, delay: u64) -> bool {
+        self.updates.schedule((x, y, z), delay)
+    }
+    ///
+    /// Advance the update clock one tick and run the due Block updates through
+    /// `updater`. Updates for Blocks whose Chunk isn't loaded are dropped.
+    /// Returns the number of updates run.
+    ///
+    pub fn tick_updates(&mut self, updater: &mut dyn FnMut(&mut World, BlockUpdate)) -> usize {
+        let mut processed = 0;
+        for update in self.updates.advance() {
+            let (x, y, z) = update.position;
+            if self.get_block(x, y, z).is_some() {
+                updater(self, update);
+                processed += 1;
+            }
+        }
+        processed
+    }
+    pub fn lighting(&self) -> &Lighting { &self.lighting }
+    /// Light at a Block, None if its Chunk isn't loaded or hasn't been lit.
+    pub fn light_at(&self, x: u64, y: usize, z: u64) -> Option<Light> { self.lighting.get(x, y, z) }
+    /// Queue a Chunk for relighting on the next update_lighting.
+    pub fn mark_light_dirty(&mut self, position: Vector2<u64>) { self.lighting.mark_dirty(position) }
+    /// Place or remove (level 0) a point light at a Block.
+    pub fn set_light_emitter(&mut self, x: u64, y: usize, z: u64, level: u8) { self.lighting.set_emitter(x, y, z, level) }
+    /// Relight dirty Chunks, returning the number recomputed.
+    pub fn update_lighting(&mut self) -> usize {
+        let mut lighting = mem::take(&mut self.lighting);
+        let relit = lighting.update(self);
+        self.lighting = lighting;
+        relit
+    }
+    pub fn pheromones(&self) -> &PheromoneField { &self.pheromones }
+    pub fn pheromones_mut(&mut self) -> &mut PheromoneField { &mut self.pheromones }
+    /// Diffuse and decay every pheromone channel by one tick.
+    pub fn tick_pheromones(&mut self) {
+        let mut pheromones = mem::take(&mut self.pheromones);
+        pheromones.tick(self);
+        self.pheromones = pheromones;
+    }
+    pub fn biomes(&self) -> Option<&BiomeMap> { self.biomes.as_ref() }
+    /// Give the World a BiomeMap, normally the one its generator uses.
+    pub fn set_biomes(&mut self, biomes: Option<BiomeMap>) { self.biomes = biomes }
+    /// Biome of a world column, None without a BiomeMap.
+    pub fn biome_at(&self, x: u64, z: u64) -> Option<BiomeId> { self.biomes.as_ref().and_then(|biomes| biomes.biome_at(x, z)) }
+    pub fn factions(&self) -> &Factions { &self.factions }
+    pub fn factions_mut(&mut self) -> &mut Factions { &mut self.factions }
+    ///
+    /// Copy of the World to run ahead without touching this one. Loaded
+    /// Chunks are shared until either side writes to one, which then copies
+    /// it, so forking costs little more than the lighting and heightmaps.
+    /// The fork has no storage, provider or subscribers: it never saves, only
+    /// sees the Chunks loaded here and publishes to no one.
+    ///
+    pub fn fork(&self) -> World {
+        World {
+            materials: self.materials.clone(),
+            regions: self.regions.iter().map(|(&position, region)| (position, Region { chunks: region.chunks.clone() })).collect(),
+            storage: None,
+            provider: None,
+            chunk_pool: Pool::new(),
+            updates: self.updates.clone(),
+            lighting: self.lighting.clone(),
+            pheromones: self.pheromones.clone(),
+            factions: self.factions.clone(),
+            changes: self.changes.clone(),
+            observers: Observers::new(),
+            heightmaps: self.heightmaps.clone(),
+            bounds: self.bounds,
+            biomes: self.biomes.clone(),
+            pending_entities: self.pending_entities.clone(),
+            dirty: self.dirty.clone(),
+            last_used: self.last_used.clone(),
+            use_clock: self.use_clock,
+        }
+    }
+    /// Loaded Chunks still shared with a fork, or with the World forked from.
+    pub fn shared_chunks(&self) -> usize {
+        self.regions.values().flat_map(|region| region.chunks.values()).filter(|chunk| Arc::strong_count(chunk) > 1).count()
+    }
+}
+
+impl Default for World {
+    fn default() -> World { World::new() }
 }
 
+/// Chunk containing a Block column and the column's position inside it
+pub fn chunk_of(x: u64, z: u64) -> (Vector2<u64>, usize, usize) {
+    let size = CHUNK_SIZE as u64;
+    (Vector2::new(x / size, z / size), (x % size) as usize, (z % size) as usize)
+}
+
+/// Chunk shared with forks until written, boxed so it can return to the chunk pool once unshared
+#[allow(clippy::redundant_allocation)]
+type SharedChunk = Arc<Box<Chunk>>;
+
 pub struct Region {
-    chunks: Map<Vector2<u64>, Chunk>,
+    chunks: Map<Vector2<u64>, SharedChunk>,
+}
+
+impl Region {
+    pub fn new() -> Region { Region { chunks: Map::new() } }
 }
 
+impl Default for Region {
+    fn default() -> Region { Region::new() }
+}
+
+#[derive(Clone)]
 pub struct Chunk {
-    blocks: [[[Block;32];32];32],
+    blocks: [[[Block; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
+}
+
+impl Chunk {
+    /// Create a Chunk filled with Air.
+    pub fn new() -> Chunk {
+        Chunk {
+            blocks: [[[Block::default(); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
+        }
+    }
+    pub fn get_block(&self, x: usize, y: usize, z: usize) -> Block { self.blocks[x][y][z] }
+    pub fn set_block(&mut self, x: usize, y: usize, z: usize, block: Block) { self.blocks[x][y][z] = block }
+}
+
+/// Take a Chunk out of its Arc, copying it if a fork still shares it.
+fn unshare(chunk: SharedChunk) -> Box<Chunk> { Arc::try_unwrap(chunk).unwrap_or_else(|shared| (*shared).clone()) }
+
+impl Default for Chunk {
+    fn default() -> Chunk { Chunk::new() }
 }
 
+impl Poolable for Chunk {
+    fn allocate() -> Box<Chunk> { Box::new(Chunk::new()) }
+    fn recycle(&mut self) {
+        for plane in self.blocks.iter_mut() {
+            for column in plane.iter_mut() {
+                for block in column.iter_mut() {
+                    *block = Block::default();
+                }
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Block {
-    material: Material,
+    material: MaterialId,
 }
 
-pub struct Material {
-    resistance: f32,
-    opacity: f32,
+impl Block {
+    pub fn new(material: MaterialId) -> Block { Block { material } }
+    pub fn material(&self) -> MaterialId { self.material }
+    pub fn is_air(&self) -> bool { self.material == AIR }
+}
+
+impl Default for Block {
+    fn default() -> Block { Block { material: AIR } }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Block, Chunk, Vector2, World};
+    use error::HivemindError;
+    use model::persist::StoredEntity;
+    use model::provider::{ChunkProvider, ChunkSource, ChunkStatus};
+    use model::storage::{region_of, RegionStorage};
+    use pool::Poolable;
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use std::mem;
+    use std::process;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_block_material_lookup() {
+        let mut world = World::new();
+        let rock = world.materials().id("rock").unwrap();
+        let mut chunk = Chunk::new();
+        assert!(chunk.get_block(1, 2, 3).is_air());
+
+        chunk.set_block(1, 2, 3, Block::new(rock));
+        let block = chunk.get_block(1, 2, 3);
+        assert_eq!(world.materials().get(block.material()).unwrap().name(), "rock");
+        assert_eq!(mem::size_of::<Block>(), 2);
+
+        // Only real changes are tracked, and only while enabled
+        world.insert_chunk(Vector2::new(0, 0), Box::new(chunk));
+        world.set_block(4, 4, 4, Block::new(rock));
+        world.track_changes(true);
+        world.set_block(1, 2, 3, Block::new(rock));
+        world.set_block(4, 4, 4, Block::default());
+        assert_eq!(world.take_changes(), vec![(4, 4, 4)]);
+        assert!(world.take_changes().is_empty());
+        world.track_changes(false);
+        assert!(!world.is_tracking_changes());
+    }
+
+    #[test]
+    pub fn test_chunk_paging() {
+        let directory = env::temp_dir().join(format!("hivemind-world-{}", process::id()));
+        let mut world = World::open(&directory).unwrap();
+        let metal = world.materials().id("metal").unwrap();
+        let position = Vector2::new(1, 0);
+
+        assert!(!world.load_chunk(position).unwrap());
+        world.insert_chunk(position, Chunk::allocate());
+        assert!(world.set_block(40, 7, 3, Block::new(metal)));
+        assert!(world.unload_chunk(position).unwrap());
+        assert_eq!(world.get_block(40, 7, 3), None);
+
+        assert!(world.load_chunk(position).unwrap());
+        assert_eq!(world.get_block(40, 7, 3), Some(Block::new(metal)));
+        assert_eq!(world.chunk_pool().stats().reused, 1);
+
+        // A truncated region file is reported as corrupt rather than panicking
+        world.unload_chunk(position).unwrap();
+        let path = world.storage().unwrap().region_path(region_of(position).0);
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(file.metadata().unwrap().len() - 1).unwrap();
+        assert!(matches!(world.load_chunk(position), Err(HivemindError::CorruptChunk { position: corrupt, .. }) if corrupt == position));
+        assert!(!world.is_chunk_loaded(position));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    pub fn test_incremental_saves() {
+        let directory = env::temp_dir().join(format!("hivemind-dirty-{}", process::id()));
+        let mut world = World::open(&directory).unwrap();
+        let (first, second) = (Vector2::new(0, 0), Vector2::new(1, 0));
+        world.insert_chunk(first, Chunk::allocate());
+        world.insert_chunk(second, Chunk::allocate());
+        assert_eq!(world.save_dirty().unwrap(), 2);
+        assert_eq!(world.save_dirty().unwrap(), 0);
+
+        // Only what changed is written again
+        let materials: Vec<_> = ["rock", "chitin", "metal"].iter().map(|name| world.materials().id(name).unwrap()).collect();
+        world.set_block(33, 1, 1, Block::new(materials[0]));
+        world.set_block(33, 1, 1, Block::new(materials[0]));
+        assert_eq!(world.dirty_chunks(), vec![second]);
+        assert_eq!(world.save_dirty().unwrap(), 1);
+        assert!(!world.is_dirty(second));
+
+        // Growing Chunks outgrow their slots, which a full save reclaims
+        for (index, &material) in materials.iter().cycle().take(30).enumerate() {
+            world.set_block(index as u64, index, 0, Block::new(material));
+            world.save_dirty().unwrap();
+        }
+        let path = world.storage().unwrap().region_path(Vector2::new(0, 0));
+        let before = fs::metadata(&path).unwrap().len();
+        world.save().unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < before);
+        world.unload_chunk(first).unwrap();
+        assert!(world.load_chunk(first).unwrap());
+        assert_eq!(world.get_block(29, 29, 0), Some(Block::new(materials[2])));
+        assert!(world.dirty_chunks().is_empty());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    pub fn test_background_chunk_loading() {
+        let directory = env::temp_dir().join(format!("hivemind-provider-{}", process::id()));
+        let storage = RegionStorage::open(&directory).unwrap();
+        let mut world = World::new();
+        let metal = world.materials().id("metal").unwrap();
+        let mut saved = Chunk::new();
+        saved.set_block(3, 3, 3, Block::new(metal));
+        storage.write_chunk(Vector2::new(0, 0), &saved).unwrap();
+        let drone = StoredEntity { components: vec![("position".to_string(), vec![1, 2, 3])] };
+        storage.write_entities(Vector2::new(0, 0), std::slice::from_ref(&drone)).unwrap();
+        storage.write_chunk(Vector2::new(1, 0), &saved).unwrap();
+
+        let sources: Vec<Arc<dyn ChunkSource>> = vec![Arc::new(storage)];
+        world.set_provider(Some(ChunkProvider::new(sources, 2)));
+        assert_eq!(world.request_chunk(Vector2::new(0, 0)), ChunkStatus::Loading);
+        world.request_chunk(Vector2::new(1, 0));
+        world.request_chunk(Vector2::new(9, 9));
+        // Edited while its stored copy is loading
+        let mut edited = Chunk::new();
+        edited.set_block(1, 1, 1, Block::new(metal));
+        world.insert_chunk(Vector2::new(1, 0), Box::new(edited));
+
+        for _ in 0..500 {
+            world.sync_chunks();
+            if world.provider().unwrap().pending() == 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(2));
+        }
+        assert_eq!(world.chunk_status(Vector2::new(0, 0)), ChunkStatus::Ready);
+        assert_eq!(world.get_block(3, 3, 3), Some(Block::new(metal)));
+        assert!(!world.is_dirty(Vector2::new(0, 0)));
+        assert_eq!(world.take_pending_entities(Vector2::new(0, 0)), vec![drone]);
+        assert_eq!(world.get_block(super::CHUNK_SIZE as u64 + 1, 1, 1), Some(Block::new(metal)));
+        assert!(world.get_block(super::CHUNK_SIZE as u64 + 3, 3, 3).unwrap().is_air());
+        assert!(world.is_dirty(Vector2::new(1, 0)));
+        match world.chunk_status(Vector2::new(9, 9)) {
+            ChunkStatus::Failed(_) => {}
+            status => panic!("unexpected status {:?}", status),
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
 }
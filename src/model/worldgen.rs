@@ -0,0 +1,244 @@
+///
+/// Procedural Terrain Generation
+///
+/// Generation is a pure function of the world seed and chunk position, so any
+/// chunk can be regenerated identically on any machine and in any order.
+///
+use model::biome::BiomeMap;
+use model::material::{MaterialId, MaterialRegistry, AIR};
+use model::provider::ChunkSource;
+use model::random::mix;
+use model::world::{Block, Chunk, Vector2, CHUNK_SIZE};
+use std::io;
+
+///
+/// Chunk Generator
+///
+pub trait ChunkGenerator: Send + Sync {
+    /// Fill `chunk` with the terrain at `position`.
+    fn generate(&self, position: Vector2<u64>, chunk: &mut Chunk);
+}
+
+impl<G: ChunkGenerator> ChunkSource for G {
+    fn provide(&self, position: Vector2<u64>, chunk: &mut Chunk) -> io::Result<bool> {
+        self.generate(position, chunk);
+        Ok(true)
+    }
+}
+
+///
+/// Seeded Value Noise
+///
+#[derive(Copy, Clone, Debug)]
+pub struct Noise {
+    seed: u64,
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Noise { Noise { seed } }
+    /// Derive an independent noise field from this one.
+    pub fn layer(&self, layer: u64) -> Noise { Noise { seed: mix(self.seed ^ mix(layer)) } }
+    /// Lattice value in [-1, 1]
+    fn lattice(&self, x: i64, y: i64, z: i64) -> f64 {
+        let hash = mix(self.seed ^ mix(x as u64 ^ mix(y as u64 ^ mix(z as u64))));
+        (hash >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+    /// Smooth 2D noise in [-1, 1]
+    pub fn sample2(&self, x: f64, y: f64) -> f64 { self.sample3(x, y, 0.0) }
+    /// Smooth 3D noise in [-1, 1]
+    pub fn sample3(&self, x: f64, y: f64, z: f64) -> f64 {
+        let (x0, y0, z0) = (x.floor() as i64, y.floor() as i64, z.floor() as i64);
+        let (fx, fy, fz) = (fade(x - x0 as f64), fade(y - y0 as f64), fade(z - z0 as f64));
+        let mut plane = [0.0; 2];
+        for (dz, value) in plane.iter_mut().enumerate() {
+            let z = z0 + dz as i64;
+            let near = lerp(self.lattice(x0, y0, z), self.lattice(x0 + 1, y0, z), fx);
+            let far = lerp(self.lattice(x0, y0 + 1, z), self.lattice(x0 + 1, y0 + 1, z), fx);
+            *value = lerp(near, far, fy);
+        }
+        lerp(plane[0], plane[1], fz)
+    }
+    /// Fractal sum of `octaves` layers, normalized to [-1, 1]
+    pub fn fractal2(&self, x: f64, y: f64, octaves: usize) -> f64 {
+        let (mut total, mut amplitude, mut frequency, mut range) = (0.0, 1.0, 1.0, 0.0);
+        for octave in 0..octaves {
+            total += self.layer(octave as u64).sample2(x * frequency, y * frequency) * amplitude;
+            range += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        if range > 0.0 { total / range } else { 0.0 }
+    }
+}
+
+/// SplitMix64 finalizer
+fn fade(t: f64) -> f64 { t * t * (3.0 - 2.0 * t) }
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 { a + (b - a) * t }
+
+///
+/// Layered Terrain Generator
+///
+/// ---+-------------------------------------------------------------------------
+///  # | LAYER
+/// ---+-------------------------------------------------------------------------
+///  1 | Heightmap: fractal noise around `base_height` +/- `amplitude`
+///  2 | Strata: `crust` at the surface, `stone` below, `bedrock` at y = 0
+///  3 | Veins: `ore` where 3D noise exceeds `ore_threshold` below the crust
+///  4 | Caves: carved to air where 3D noise is within `cave_width` of zero
+/// ---+-------------------------------------------------------------------------
+///
+/// With a BiomeMap, each column takes its height parameters and its crust,
+/// stone and ore from its biome instead.
+///
+#[derive(Clone, Debug)]
+pub struct LayeredGenerator {
+    pub seed: u64,
+    pub base_height: f64,
+    pub amplitude: f64,
+    /// Horizontal scale of terrain features in blocks
+    pub scale: f64,
+    pub crust_depth: usize,
+    pub crust: MaterialId,
+    pub stone: MaterialId,
+    pub bedrock: MaterialId,
+    pub ore: MaterialId,
+    pub ore_threshold: f64,
+    pub cave_width: f64,
+    pub biomes: Option<BiomeMap>,
+}
+
+impl LayeredGenerator {
+    /// Default terrain using the standard materials from `materials`.
+    pub fn new(seed: u64, materials: &MaterialRegistry) -> LayeredGenerator {
+        let rock = materials.id("rock").unwrap_or(AIR);
+        LayeredGenerator {
+            seed,
+            base_height: 12.0,
+            amplitude: 8.0,
+            scale: 48.0,
+            crust_depth: 1,
+            crust: materials.id("chitin").unwrap_or(rock),
+            stone: rock,
+            bedrock: rock,
+            ore: materials.id("metal").unwrap_or(rock),
+            ore_threshold: 0.55,
+            cave_width: 0.08,
+            biomes: None,
+        }
+    }
+    /// Terrain surface height of a world column.
+    pub fn height_at(&self, x: u64, z: u64) -> usize {
+        let noise = Noise::new(self.seed).layer(1);
+        let sample = noise.fractal2(x as f64 / self.scale, z as f64 / self.scale, 4);
+        let (base_height, amplitude) = match self.biomes.as_ref().and_then(|biomes| biomes.biome(x, z)) {
+            Some(biome) => (biome.base_height, biome.amplitude),
+            None => (self.base_height, self.amplitude),
+        };
+        let height = base_height + sample * amplitude;
+        height.max(1.0).min((CHUNK_SIZE - 1) as f64) as usize
+    }
+}
+
+impl ChunkGenerator for LayeredGenerator {
+    fn generate(&self, position: Vector2<u64>, chunk: &mut Chunk) {
+        let noise = Noise::new(self.seed);
+        let (ore, cave) = (noise.layer(2), noise.layer(3));
+        let size = CHUNK_SIZE as u64;
+        for lx in 0..CHUNK_SIZE {
+            for lz in 0..CHUNK_SIZE {
+                let (x, z) = (position.x * size + lx as u64, position.y * size + lz as u64);
+                let height = self.height_at(x, z);
+                let (crust, stone, ore_material) = match self.biomes.as_ref().and_then(|biomes| biomes.biome(x, z)) {
+                    Some(biome) => (biome.crust, biome.stone, biome.ore),
+                    None => (self.crust, self.stone, self.ore),
+                };
+                for y in 0..CHUNK_SIZE {
+                    let (fx, fy, fz) = (x as f64 / 12.0, y as f64 / 8.0, z as f64 / 12.0);
+                    let material = if y == 0 {
+                        self.bedrock
+                    } else if y > height || (y + 1 < height && cave.sample3(fx, fy, fz).abs() < self.cave_width) {
+                        AIR
+                    } else if y + self.crust_depth > height {
+                        crust
+                    } else if ore.sample3(fx * 2.0, fy * 2.0, fz * 2.0) > self.ore_threshold {
+                        ore_material
+                    } else {
+                        stone
+                    };
+                    chunk.set_block(lx, y, lz, Block::new(material));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChunkGenerator, LayeredGenerator};
+    use model::material::{MaterialRegistry, AIR};
+    use model::world::{Chunk, Vector2, CHUNK_SIZE};
+
+    fn generate(generator: &LayeredGenerator, x: u64, z: u64) -> Chunk {
+        let mut chunk = Chunk::new();
+        generator.generate(Vector2::new(x, z), &mut chunk);
+        chunk
+    }
+
+    fn count(chunk: &Chunk, predicate: &dyn Fn(u16) -> bool) -> usize {
+        let mut total = 0;
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    if predicate(chunk.get_block(x, y, z).material().id()) {
+                        total += 1;
+                    }
+                }
+            }
+        }
+        total
+    }
+
+    #[test]
+    pub fn test_generation_is_deterministic() {
+        let materials = MaterialRegistry::default();
+        let first = generate(&LayeredGenerator::new(7, &materials), 3, 4);
+        let second = generate(&LayeredGenerator::new(7, &materials), 3, 4);
+        let other = generate(&LayeredGenerator::new(8, &materials), 3, 4);
+        let same = |a: &Chunk, b: &Chunk| {
+            (0..CHUNK_SIZE).all(|x| (0..CHUNK_SIZE).all(|y| (0..CHUNK_SIZE).all(|z| {
+                a.get_block(x, y, z) == b.get_block(x, y, z)
+            })))
+        };
+        assert!(same(&first, &second));
+        assert!(!same(&first, &other));
+    }
+
+    #[test]
+    pub fn test_layered_strata() {
+        let materials = MaterialRegistry::default();
+        let generator = LayeredGenerator::new(42, &materials);
+        let chunk = generate(&generator, 0, 0);
+        let (rock, chitin, metal) = (generator.stone.id(), generator.crust.id(), generator.ore.id());
+
+        // Bedrock floor, crusted surface
+        assert_eq!(chunk.get_block(0, 0, 0).material().id(), rock);
+        let height = generator.height_at(5, 5);
+        assert_eq!(chunk.get_block(5, height, 5).material().id(), chitin);
+        assert_eq!(chunk.get_block(5, height + 1, 5).material(), AIR);
+
+        // Some ore and some caves across a handful of chunks
+        let mut ore = 0;
+        let mut caves = 0;
+        for x in 0..4 {
+            let chunk = generate(&generator, x, 1);
+            ore += count(&chunk, &|id| id == metal);
+            caves += (0..CHUNK_SIZE).map(|lx| (0..CHUNK_SIZE).filter(|&lz| {
+                let top = generator.height_at(x * 32 + lx as u64, 32 + lz as u64);
+                (1..top.saturating_sub(1)).any(|y| chunk.get_block(lx, y, lz).is_air())
+            }).count()).sum::<usize>();
+        }
+        assert!(ore > 0);
+        assert!(caves > 0);
+    }
+}
@@ -0,0 +1,391 @@
+///
+/// Authentication and Rate Limiting
+///
+/// A server with an Authenticator answers Hello with a Challenge carrying a
+/// fresh nonce, and only welcomes a client whose Authenticate proves its
+/// credentials within `HANDSHAKE_TIMEOUT`. How it proves them depends on the
+/// AuthMethod, each behind its own feature:
+///
+/// * `psk`: every client shares the server's key and answers with the
+///   HMAC-SHA256 of the nonce and its name under it, see `prove`. The key
+///   never crosses the wire and a proof is useless for any other nonce.
+/// * `tokens`: clients present a token signed by a login service holding
+///   the server's signing key, naming them and when it expires, see
+///   `issue_token`. The token is the session: a client reconnects with it
+///   until it expires, without logging in again.
+///
+/// A server built without either only has the Open method, which welcomes
+/// everyone under the name in their Hello.
+///
+/// The identity a connection was welcomed with is attached to every command
+/// it sends, so the game can check what it may touch, and each connection's
+/// packets are rate limited by a token bucket refilled at `per_second` up to
+/// `burst`. Packets over the limit are refused; the server decides whether to
+/// drop them or the connection.
+///
+#[cfg(any(feature = "psk", feature = "tokens"))]
+use net::hmac::{constant_time_eq, hmac_sha256};
+use net::packet::{Command, Packet};
+use net::{welcome, PROTOCOL_VERSION};
+#[cfg(any(feature = "psk", feature = "tokens"))]
+use rand::{self, OsRng, Rng};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+#[cfg(feature = "tokens")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bytes of nonce in a Challenge
+pub const NONCE_BYTES: usize = 16;
+/// Time a client has to answer its Challenge
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default packets per second a connection may send
+pub const DEFAULT_PER_SECOND: u32 = 30;
+/// Default packets a connection may send at once after being quiet
+pub const DEFAULT_BURST: u32 = 60;
+
+///
+/// How clients prove who they are
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AuthMethod {
+    /// No proof, the name in the Hello is taken as given
+    Open,
+    /// HMAC proof of a key shared by the server and every client
+    #[cfg(feature = "psk")]
+    PreSharedKey(Vec<u8>),
+    /// Token signed with this key, see `issue_token`
+    #[cfg(feature = "tokens")]
+    SignedToken(Vec<u8>),
+}
+
+///
+/// Per connection packet rate
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct RateLimit {
+    pub per_second: u32,
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> RateLimit { RateLimit { per_second: DEFAULT_PER_SECOND, burst: DEFAULT_BURST } }
+}
+
+///
+/// Authentication Failure
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AuthError {
+    /// The connection hasn't been welcomed
+    NotAuthenticated(u32),
+    /// Authenticate without an outstanding Challenge, or too late
+    NoChallenge,
+    /// The proof or token signature is wrong
+    BadProof,
+    /// The token is not of the form `name:expires:signature`
+    MalformedToken,
+    /// The token expired at this unix time
+    Expired(u64),
+    /// The connection is sending faster than its RateLimit
+    RateLimited(u32),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AuthError::NotAuthenticated(connection) => write!(f, "connection {} is not authenticated", connection),
+            AuthError::NoChallenge => write!(f, "no challenge is outstanding"),
+            AuthError::BadProof => write!(f, "authentication failed"),
+            AuthError::MalformedToken => write!(f, "malformed token"),
+            AuthError::Expired(expires) => write!(f, "token expired at {}", expires),
+            AuthError::RateLimited(connection) => write!(f, "connection {} is sending too fast", connection),
+        }
+    }
+}
+
+///
+/// Who a connection was welcomed as
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Identity {
+    pub connection: u32,
+    pub name: String,
+}
+
+///
+/// Command with the identity of the connection that sent it
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct IdentifiedCommand {
+    pub identity: Identity,
+    pub sequence: u64,
+    pub command: Command,
+}
+
+/// Challenge sent and not yet answered
+#[derive(Clone, Debug)]
+struct Pending {
+    /// Only a pre-shared key proof covers the nonce
+    #[cfg(feature = "psk")]
+    nonce: Vec<u8>,
+    sent: Instant,
+}
+
+/// Token bucket of one connection
+#[derive(Copy, Clone, Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+///
+/// Server side handshakes, identities and rate limits
+///
+#[derive(Clone, Debug)]
+pub struct Authenticator {
+    method: AuthMethod,
+    limit: RateLimit,
+    pending: HashMap<u32, Pending>,
+    identities: HashMap<u32, Identity>,
+    buckets: HashMap<u32, Bucket>,
+}
+
+impl Authenticator {
+    pub fn new(method: AuthMethod, limit: RateLimit) -> Authenticator {
+        Authenticator { method, limit, pending: HashMap::new(), identities: HashMap::new(), buckets: HashMap::new() }
+    }
+    pub fn method(&self) -> &AuthMethod { &self.method }
+    pub fn limit(&self) -> RateLimit { self.limit }
+    pub fn identity(&self, connection: u32) -> Option<&Identity> { self.identities.get(&connection) }
+    ///
+    /// Answer a connection's opening Hello: Welcome under the Open method,
+    /// otherwise a Challenge, or a Disconnect if the versions differ.
+    ///
+    pub fn hello(&mut self, connection: u32, hello: &Packet, tick: u64, now: Instant) -> Packet {
+        let reply = welcome(hello, connection, tick);
+        let name = match (hello, &reply) {
+            (Packet::Hello { name, .. }, Packet::Welcome { .. }) => name,
+            _ => return reply,
+        };
+        match self.method {
+            AuthMethod::Open => {
+                self.welcome(connection, name, now);
+                reply
+            }
+            #[cfg(any(feature = "psk", feature = "tokens"))]
+            _ => {
+                let nonce = nonce();
+                self.pending.insert(connection, Pending {
+                    #[cfg(feature = "psk")]
+                    nonce: nonce.clone(),
+                    sent: now,
+                });
+                Packet::Challenge { nonce }
+            }
+        }
+    }
+    ///
+    /// Check a connection's Authenticate against its Challenge, answering
+    /// with Welcome or, if it failed, a Disconnect.
+    ///
+    pub fn authenticate(&mut self, connection: u32, packet: &Packet, tick: u64, now: Instant) -> Packet {
+        match self.verify(connection, packet, now) {
+            Ok(name) => {
+                self.welcome(connection, &name, now);
+                Packet::Welcome { version: PROTOCOL_VERSION, client: connection, tick }
+            }
+            Err(error) => Packet::Disconnect { reason: error.to_string() },
+        }
+    }
+    #[cfg_attr(not(any(feature = "psk", feature = "tokens")), allow(unused_variables))]
+    fn verify(&mut self, connection: u32, packet: &Packet, now: Instant) -> Result<String, AuthError> {
+        let pending = self.pending.remove(&connection).ok_or(AuthError::NoChallenge)?;
+        if now.duration_since(pending.sent) > HANDSHAKE_TIMEOUT {
+            return Err(AuthError::NoChallenge);
+        }
+        let (name, proof) = match *packet {
+            Packet::Authenticate { ref name, ref proof } => (name, proof),
+            _ => return Err(AuthError::BadProof),
+        };
+        match self.method {
+            AuthMethod::Open => Ok(name.clone()),
+            #[cfg(feature = "psk")]
+            AuthMethod::PreSharedKey(ref key) => {
+                if constant_time_eq(&prove(key, &pending.nonce, name), proof) {
+                    Ok(name.clone())
+                } else {
+                    Err(AuthError::BadProof)
+                }
+            }
+            #[cfg(feature = "tokens")]
+            AuthMethod::SignedToken(ref key) => {
+                let token = String::from_utf8(proof.clone()).map_err(|_| AuthError::MalformedToken)?;
+                let unix = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+                verify_token(key, &token, unix)
+            }
+        }
+    }
+    fn welcome(&mut self, connection: u32, name: &str, now: Instant) {
+        self.identities.insert(connection, Identity { connection, name: name.to_string() });
+        self.buckets.insert(connection, Bucket { tokens: self.limit.burst as f64, refilled: now });
+    }
+    ///
+    /// Count a packet from a connection against its RateLimit, returning who
+    /// sent it.
+    ///
+    pub fn admit(&mut self, connection: u32, now: Instant) -> Result<&Identity, AuthError> {
+        let bucket = self.buckets.get_mut(&connection).ok_or(AuthError::NotAuthenticated(connection))?;
+        let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.limit.per_second as f64).min(self.limit.burst as f64);
+        bucket.refilled = now;
+        if bucket.tokens < 1.0 {
+            return Err(AuthError::RateLimited(connection));
+        }
+        bucket.tokens -= 1.0;
+        self.identities.get(&connection).ok_or(AuthError::NotAuthenticated(connection))
+    }
+    ///
+    /// Admit a packet from a connection, returning the command of an Input
+    /// with the sender's identity attached, None for any other packet.
+    ///
+    pub fn command(&mut self, connection: u32, packet: &Packet, now: Instant) -> Result<Option<IdentifiedCommand>, AuthError> {
+        let identity = self.admit(connection, now)?;
+        Ok(match *packet {
+            Packet::Input { sequence, ref command } => Some(IdentifiedCommand { identity: identity.clone(), sequence, command: command.clone() }),
+            _ => None,
+        })
+    }
+    /// Forget a connection which has gone.
+    pub fn disconnect(&mut self, connection: u32) -> Option<Identity> {
+        self.pending.remove(&connection);
+        self.buckets.remove(&connection);
+        self.identities.remove(&connection)
+    }
+}
+
+impl Default for Authenticator {
+    fn default() -> Authenticator { Authenticator::new(AuthMethod::Open, RateLimit::default()) }
+}
+
+/// Fresh Challenge nonce, from the operating system if it can provide one.
+#[cfg(any(feature = "psk", feature = "tokens"))]
+fn nonce() -> Vec<u8> {
+    let mut nonce = vec![0; NONCE_BYTES];
+    match OsRng::new() {
+        Ok(mut rng) => rng.fill_bytes(&mut nonce),
+        Err(_) => rand::thread_rng().fill_bytes(&mut nonce),
+    }
+    nonce
+}
+
+///
+/// Proof of a pre-shared key for a Challenge nonce, sent by the client as
+/// the Authenticate proof.
+///
+#[cfg(feature = "psk")]
+pub fn prove(key: &[u8], nonce: &[u8], name: &str) -> Vec<u8> {
+    let mut message = nonce.to_vec();
+    message.extend_from_slice(name.as_bytes());
+    hmac_sha256(key, &message).to_vec()
+}
+
+#[cfg(feature = "tokens")]
+fn signature(key: &[u8], name: &str, expires: u64) -> String {
+    hmac_sha256(key, format!("{}:{}", name, expires).as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+///
+/// Token naming a client until the unix time `expires`, signed with the
+/// server's key. Sent by the client as the Authenticate proof.
+///
+#[cfg(feature = "tokens")]
+pub fn issue_token(key: &[u8], name: &str, expires: u64) -> String { format!("{}:{}:{}", name, expires, signature(key, name, expires)) }
+
+///
+/// Check a token's signature and expiry at the unix time `now`, returning
+/// the name it carries.
+///
+#[cfg(feature = "tokens")]
+pub fn verify_token(key: &[u8], token: &str, now: u64) -> Result<String, AuthError> {
+    let mut fields = token.rsplitn(3, ':');
+    let (signed, expires, name) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(signed), Some(expires), Some(name)) => (signed, expires, name),
+        _ => return Err(AuthError::MalformedToken),
+    };
+    let expires: u64 = expires.parse().map_err(|_| AuthError::MalformedToken)?;
+    if !constant_time_eq(signature(key, name, expires).as_bytes(), signed.as_bytes()) {
+        return Err(AuthError::BadProof);
+    }
+    if now >= expires {
+        return Err(AuthError::Expired(expires));
+    }
+    Ok(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuthError, AuthMethod, Authenticator, RateLimit};
+    use model::entity::EntityID;
+    use net::packet::{Command, Packet};
+    use net::PROTOCOL_VERSION;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    pub fn test_authenticator() {
+        let now = Instant::now();
+        let hello = Packet::Hello { version: PROTOCOL_VERSION, name: "queen".to_string() };
+        let watch = Packet::Input { sequence: 1, command: Command::Watch(EntityID::new(1, 0)) };
+
+        // Open servers welcome anyone under their name, then hold them to the rate
+        let mut open = Authenticator::new(AuthMethod::Open, RateLimit { per_second: 10, burst: 2 });
+        assert_eq!(open.hello(1, &hello, 5, now), Packet::Welcome { version: PROTOCOL_VERSION, client: 1, tick: 5 });
+        assert_eq!(open.command(1, &watch, now).unwrap().unwrap().identity.name, "queen");
+        assert_eq!(open.command(1, &Packet::Ack { tick: 4 }, now), Ok(None));
+        assert_eq!(open.admit(1, now).unwrap_err(), AuthError::RateLimited(1));
+        assert!(open.admit(1, now + Duration::from_millis(100)).is_ok());
+        assert_eq!(open.command(2, &watch, now).unwrap_err(), AuthError::NotAuthenticated(2));
+
+        #[cfg(feature = "psk")]
+        {
+            use super::prove;
+            let mut psk = Authenticator::new(AuthMethod::PreSharedKey(b"hive key".to_vec()), RateLimit::default());
+            let nonce = match psk.hello(1, &hello, 5, now) {
+                Packet::Challenge { nonce } => nonce,
+                other => panic!("expected a challenge, got {:?}", other),
+            };
+            let answer = |key: &[u8], name: &str| Packet::Authenticate { name: name.to_string(), proof: prove(key, &nonce, name) };
+            assert!(matches!(psk.authenticate(1, &answer(b"wrong key", "queen"), 5, now), Packet::Disconnect { .. }));
+            // A failed answer uses up the challenge
+            assert!(matches!(psk.authenticate(1, &answer(b"hive key", "queen"), 5, now), Packet::Disconnect { .. }));
+            psk.hello(2, &hello, 5, now);
+            assert!(matches!(psk.authenticate(2, &answer(b"hive key", "queen"), 5, now), Packet::Disconnect { .. }));
+            let nonce = match psk.hello(3, &hello, 5, now) {
+                Packet::Challenge { nonce } => nonce,
+                other => panic!("expected a challenge, got {:?}", other),
+            };
+            let proof = prove(b"hive key", &nonce, "queen");
+            assert!(matches!(psk.authenticate(3, &Packet::Authenticate { name: "queen".to_string(), proof }, 5, now), Packet::Welcome { client: 3, .. }));
+            assert_eq!(psk.identity(3).unwrap().name, "queen");
+            assert!(psk.identity(1).is_none());
+        }
+
+        #[cfg(feature = "tokens")]
+        {
+            use super::{issue_token, verify_token};
+            let token = issue_token(b"signing key", "drone:master", 1000);
+            assert_eq!(verify_token(b"signing key", &token, 999), Ok("drone:master".to_string()));
+            assert_eq!(verify_token(b"signing key", &token, 1000), Err(AuthError::Expired(1000)));
+            assert_eq!(verify_token(b"other key", &token, 999), Err(AuthError::BadProof));
+            assert_eq!(verify_token(b"signing key", &token.replace(":1000:", ":9000:"), 999), Err(AuthError::BadProof));
+            assert_eq!(verify_token(b"signing key", "nonsense", 999), Err(AuthError::MalformedToken));
+
+            let mut tokens = Authenticator::new(AuthMethod::SignedToken(b"signing key".to_vec()), RateLimit::default());
+            assert!(matches!(tokens.hello(1, &hello, 5, now), Packet::Challenge { .. }));
+            let session = issue_token(b"signing key", "queen", u64::MAX);
+            let reply = tokens.authenticate(1, &Packet::Authenticate { name: "queen".to_string(), proof: session.into_bytes() }, 5, now);
+            assert!(matches!(reply, Packet::Welcome { client: 1, .. }));
+            assert_eq!(tokens.command(1, &watch, now).unwrap().unwrap().identity.name, "queen");
+        }
+    }
+}
@@ -0,0 +1,254 @@
+///
+/// Packet Batching and Compression
+///
+/// A Batcher per connection collects what the server sends it during a
+/// tick and flushes it as few packets as possible: runs of packets up to
+/// `max_bytes` are joined into a Batch, each one inside prefixed by its u32
+/// length, and a Batch or lone packet of at least `compress_above` bytes is
+/// compressed whenever that makes it smaller. Small lone packets go out as
+/// they are. The receiving end passes everything through `unbatch`, which
+/// hands back the packets inside a Batch and any other packet unchanged, so
+/// the rest of the protocol never sees the difference.
+///
+/// Compression is LZ4, built with the `lz4` feature; without it Batches are
+/// only joined, and a compressed Batch can't be read. The Batcher counts the
+/// bytes it would have sent one packet at a time against those it did, and
+/// `export` adds both to a Metrics, as `bytes_sent` and `bytes_saved`.
+///
+use codec::{invalid_data, read_u32, write_u32};
+use metrics::{Metrics, BYTES_SAVED, BYTES_SENT, PACKETS_SENT};
+#[cfg(feature = "lz4")]
+use net::lz4;
+use net::packet::Packet;
+use net::MAX_FRAME_SIZE;
+use std::io;
+
+/// Batch holding its packets as they are
+pub const CODEC_NONE: u8 = 0;
+/// Batch holding its packets as one LZ4 block
+pub const CODEC_LZ4: u8 = 1;
+/// Default unpacked bytes in one Batch, small enough for a datagram
+pub const DEFAULT_BATCH_BYTES: usize = 32 << 10;
+/// Default size from which compression is tried
+pub const DEFAULT_COMPRESS_ABOVE: usize = 256;
+
+///
+/// Batching Settings
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BatchConfig {
+    /// Most unpacked bytes joined into one Batch; a larger packet goes alone
+    pub max_bytes: usize,
+    /// Smallest Batch or packet compressed, usize::MAX to never compress
+    pub compress_above: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> BatchConfig { BatchConfig { max_bytes: DEFAULT_BATCH_BYTES, compress_above: DEFAULT_COMPRESS_ABOVE } }
+}
+
+///
+/// What a Batcher has sent
+///
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct BatchStats {
+    /// Packets queued
+    pub packets: u64,
+    /// Packets sent after batching
+    pub sent: u64,
+    /// Bytes the queued packets take one at a time
+    pub bytes: u64,
+    /// Bytes sent after batching and compression
+    pub sent_bytes: u64,
+}
+
+impl BatchStats {
+    pub fn saved(&self) -> u64 { self.bytes.saturating_sub(self.sent_bytes) }
+}
+
+///
+/// Outgoing packets of one connection
+///
+#[derive(Clone, Default, Debug)]
+pub struct Batcher {
+    config: BatchConfig,
+    queue: Vec<Vec<u8>>,
+    stats: BatchStats,
+    /// Stats already added to a Metrics
+    exported: BatchStats,
+}
+
+impl Batcher {
+    pub fn new(config: BatchConfig) -> Batcher { Batcher { config, ..Batcher::default() } }
+    pub fn config(&self) -> BatchConfig { self.config }
+    pub fn stats(&self) -> BatchStats { self.stats }
+    /// Packets waiting for the next flush
+    pub fn len(&self) -> usize { self.queue.len() }
+    pub fn is_empty(&self) -> bool { self.queue.is_empty() }
+    /// Queue a packet for the next flush.
+    pub fn push(&mut self, packet: &Packet) {
+        let bytes = packet.to_bytes();
+        self.stats.packets += 1;
+        self.stats.bytes += bytes.len() as u64;
+        self.queue.push(bytes);
+    }
+    /// Everything queued this tick as Batches and lone packets, in order.
+    pub fn flush(&mut self) -> Vec<Packet> {
+        let mut packets = Vec::new();
+        let mut group: Vec<Vec<u8>> = Vec::new();
+        let mut size = 0;
+        for bytes in self.queue.drain(..).collect::<Vec<_>>() {
+            if !group.is_empty() && size + 4 + bytes.len() > self.config.max_bytes {
+                packets.push(self.pack(&mut group));
+                size = 0;
+            }
+            size += 4 + bytes.len();
+            group.push(bytes);
+        }
+        if !group.is_empty() {
+            packets.push(self.pack(&mut group));
+        }
+        packets
+    }
+    /// One Batch, or a lone packet which is better off as it is.
+    fn pack(&mut self, group: &mut Vec<Vec<u8>>) -> Packet {
+        let lone = group.len() == 1;
+        let packet = if lone && group[0].len() < self.config.compress_above {
+            Packet::from_bytes(&group[0]).expect("queued packets decode")
+        } else {
+            let mut data = Vec::new();
+            for bytes in group.iter() {
+                write_u32(&mut data, bytes.len() as u32).expect("writing to memory");
+                data.extend_from_slice(bytes);
+            }
+            let length = data.len() as u32;
+            match compress(&data, self.config.compress_above) {
+                Some(packed) => Packet::Batch { codec: CODEC_LZ4, length, data: packed },
+                None if lone => Packet::from_bytes(&group[0]).expect("queued packets decode"),
+                None => Packet::Batch { codec: CODEC_NONE, length, data },
+            }
+        };
+        group.clear();
+        self.stats.sent += 1;
+        self.stats.sent_bytes += packet.to_bytes().len() as u64;
+        packet
+    }
+    /// Add what was sent since the last export to a Metrics.
+    pub fn export(&mut self, metrics: &mut Metrics) {
+        let (stats, exported) = (self.stats, self.exported);
+        metrics.add(PACKETS_SENT, stats.sent - exported.sent);
+        metrics.add(BYTES_SENT, stats.sent_bytes - exported.sent_bytes);
+        metrics.add(BYTES_SAVED, stats.saved().saturating_sub(exported.saved()));
+        self.exported = stats;
+    }
+}
+
+/// LZ4 block of the data if it is worth sending, None if it isn't smaller.
+#[cfg(feature = "lz4")]
+fn compress(data: &[u8], above: usize) -> Option<Vec<u8>> {
+    if data.len() < above {
+        return None;
+    }
+    let packed = lz4::compress(data);
+    if packed.len() < data.len() {
+        Some(packed)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(feature = "lz4"))]
+fn compress(_: &[u8], _: usize) -> Option<Vec<u8>> { None }
+
+///
+/// The packets inside a Batch, or any other packet on its own. Batches
+/// don't nest.
+///
+pub fn unbatch(packet: Packet) -> io::Result<Vec<Packet>> {
+    let (codec, length, data) = match packet {
+        Packet::Batch { codec, length, data } => (codec, length, data),
+        packet => return Ok(vec![packet]),
+    };
+    if length > MAX_FRAME_SIZE {
+        return Err(invalid_data("batch is too large"));
+    }
+    let data = match codec {
+        CODEC_NONE => data,
+        #[cfg(feature = "lz4")]
+        CODEC_LZ4 => lz4::decompress(&data, length as usize)?,
+        _ => return Err(invalid_data("batch codec is not supported")),
+    };
+    if data.len() != length as usize {
+        return Err(invalid_data("batch length is wrong"));
+    }
+    let mut reader = data.as_slice();
+    let mut packets = Vec::new();
+    while !reader.is_empty() {
+        let size = read_u32(&mut reader)? as usize;
+        if size > reader.len() {
+            return Err(invalid_data("batched packet runs past the batch"));
+        }
+        let packet = Packet::from_bytes(&reader[..size])?;
+        if let Packet::Batch { .. } = packet {
+            return Err(invalid_data("batches don't nest"));
+        }
+        packets.push(packet);
+        reader = &reader[size..];
+    }
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unbatch, BatchConfig, Batcher};
+    use metrics::{Metrics, BYTES_SAVED, BYTES_SENT, PACKETS_SENT};
+    use model::material::MaterialId;
+    use model::world::{Block, Chunk, Vector2};
+    use net::packet::Packet;
+    use pool::Poolable;
+
+    #[test]
+    pub fn test_batching() {
+        let mut chunk = Chunk::allocate();
+        for x in 0..16 {
+            chunk.set_block(x, 2, x, Block::new(MaterialId::new(x as u16 % 3)));
+        }
+        let mut sent = vec![Packet::Ack { tick: 1 }, Packet::InputAck { sequence: 4 }, Packet::chunk(Vector2::new(0, 1), &chunk)];
+        sent.extend((0..40).map(|tick| Packet::Ack { tick }));
+        let mut batcher = Batcher::new(BatchConfig { max_bytes: 200, compress_above: 64 });
+
+        // A lone small packet goes out as it is
+        batcher.push(&sent[0]);
+        assert_eq!(batcher.flush(), vec![sent[0].clone()]);
+
+        // The rest arrive in order however they were split and packed
+        for packet in sent.iter() {
+            batcher.push(packet);
+        }
+        let flushed = batcher.flush();
+        assert!(flushed.len() < sent.len() && batcher.is_empty());
+        let mut received = Vec::new();
+        for packet in flushed {
+            received.extend(unbatch(Packet::from_bytes(&packet.to_bytes()).unwrap()).unwrap());
+        }
+        assert_eq!(received, sent);
+
+        let stats = batcher.stats();
+        let mut metrics = Metrics::new();
+        batcher.export(&mut metrics);
+        batcher.export(&mut metrics);
+        assert_eq!(metrics.counter(BYTES_SENT).total, stats.sent_bytes);
+        assert_eq!(metrics.counter(BYTES_SAVED).total, stats.saved());
+        assert_eq!(metrics.counter(PACKETS_SENT).total, stats.sent);
+        #[cfg(feature = "lz4")]
+        assert!(stats.saved() > stats.bytes / 4);
+
+        // Nested and malformed batches are refused
+        let inner = Packet::Batch { codec: 0, length: 0, data: Vec::new() }.to_bytes();
+        let mut data = (inner.len() as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(&inner);
+        assert!(unbatch(Packet::Batch { codec: 0, length: data.len() as u32, data: data.clone() }).is_err());
+        assert!(unbatch(Packet::Batch { codec: 0, length: 3, data: vec![9, 0, 0] }).is_err());
+        assert!(unbatch(Packet::Batch { codec: 7, length: 0, data: Vec::new() }).is_err());
+    }
+}
@@ -0,0 +1,325 @@
+///
+/// Chat Channels
+///
+/// Text travels on three kinds of Channel: Global to everyone, Faction to
+/// the members of one Faction and Drone to a single drone's console. Each
+/// end of a connection numbers the Chat packets it sends and keeps them until
+/// the other end acknowledges them with a ChatAck, resending any left
+/// unacknowledged for `RESEND_TICKS`; the receiving end hands lines over in
+/// sequence order only, holding early ones back and dropping repeats. Over a
+/// stream this costs one ack per line, over datagrams it makes chat reliable
+/// and ordered.
+///
+/// The server routes with a ChatRouter. The sender's name is set from its
+/// membership so it can't be forged, a Faction line is only taken from that
+/// Faction's members and a Drone line only from a member whose Faction may
+/// command the drone. Accepted lines then pass through the routing hooks in
+/// the order added, each of which may rewrite the line, for filtering or
+/// commands, or reject it with a reason sent back to the sender. Lines for a
+/// drone come out as Console packets for the server to feed to the drone;
+/// the server announces what a drone writes on its Drone channel and it
+/// reaches the members who may command it.
+///
+use model::entity::EntityID;
+use model::faction::FactionId;
+use net::packet::{Channel, Packet};
+use simulation::Simulation;
+use std::collections::{BTreeMap, VecDeque};
+
+/// Ticks an unacknowledged line waits before it is sent again
+pub const RESEND_TICKS: u64 = 30;
+/// Lines received ahead of a missing one that are held, later ones are dropped until it arrives
+pub const MAX_PENDING: usize = 256;
+
+///
+/// Line of chat
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ChatMessage {
+    pub channel: Channel,
+    /// Sender's name, empty for the server
+    pub from: String,
+    pub text: String,
+}
+
+impl ChatMessage {
+    pub fn new(channel: Channel, from: &str, text: &str) -> ChatMessage { ChatMessage { channel, from: from.to_string(), text: text.to_string() } }
+}
+
+///
+/// Reliable ordered chat over one connection, one per end
+///
+#[derive(Clone, Debug)]
+pub struct ChatLink {
+    /// Sequence of the next line sent
+    next: u64,
+    /// Lines sent and not yet acknowledged, with the tick each was last sent
+    unacked: VecDeque<(u64, Packet)>,
+    /// Sequence of the next line handed over
+    expected: u64,
+    /// Lines received ahead of the expected one
+    pending: BTreeMap<u64, ChatMessage>,
+}
+
+impl ChatLink {
+    pub fn new() -> ChatLink { ChatLink { next: 1, unacked: VecDeque::new(), expected: 1, pending: BTreeMap::new() } }
+    /// Lines sent and not yet acknowledged
+    pub fn unacked(&self) -> usize { self.unacked.len() }
+    /// Number and send a line, keeping it until acknowledged.
+    pub fn send(&mut self, message: ChatMessage, tick: u64) -> Packet {
+        let packet = Packet::Chat { sequence: self.next, channel: message.channel, from: message.from, text: message.text };
+        self.next += 1;
+        self.unacked.push_back((tick, packet.clone()));
+        packet
+    }
+    /// Forget the lines the other end has received.
+    pub fn acknowledge(&mut self, sequence: u64) {
+        while self.unacked.front().is_some_and(|(_, packet)| matches!(*packet, Packet::Chat { sequence: sent, .. } if sent <= sequence)) {
+            self.unacked.pop_front();
+        }
+    }
+    /// Lines to send again, those unacknowledged for `RESEND_TICKS`.
+    pub fn resend(&mut self, tick: u64) -> Vec<Packet> {
+        let mut packets = Vec::new();
+        for &mut (ref mut sent, ref packet) in self.unacked.iter_mut() {
+            if tick >= *sent + RESEND_TICKS {
+                *sent = tick;
+                packets.push(packet.clone());
+            }
+        }
+        packets
+    }
+    ///
+    /// Take a Chat or ChatAck packet, returning the lines now in order and
+    /// the ChatAck to answer with. Other packets are ignored.
+    ///
+    pub fn receive(&mut self, packet: &Packet) -> (Vec<ChatMessage>, Option<Packet>) {
+        match *packet {
+            Packet::Chat { sequence, channel, ref from, ref text } => {
+                if sequence >= self.expected && (sequence == self.expected || self.pending.len() < MAX_PENDING) {
+                    self.pending.insert(sequence, ChatMessage { channel, from: from.clone(), text: text.clone() });
+                }
+                let mut messages = Vec::new();
+                while let Some(message) = self.pending.remove(&self.expected) {
+                    messages.push(message);
+                    self.expected += 1;
+                }
+                let ack = if self.expected > 1 { Some(Packet::ChatAck { sequence: self.expected - 1 }) } else { None };
+                (messages, ack)
+            }
+            Packet::ChatAck { sequence } => {
+                self.acknowledge(sequence);
+                (Vec::new(), None)
+            }
+            _ => (Vec::new(), None),
+        }
+    }
+}
+
+impl Default for ChatLink {
+    fn default() -> ChatLink { ChatLink::new() }
+}
+
+///
+/// Client taking part in chat
+///
+#[derive(Clone, Debug)]
+pub struct ChatMember {
+    pub name: String,
+    pub faction: Option<FactionId>,
+    pub link: ChatLink,
+}
+
+///
+/// Routing hook, given the sender and its line, which it may rewrite.
+/// Returning an error rejects the line with that reason.
+///
+pub type ChatHook = Box<dyn FnMut(&ChatMember, &mut ChatMessage) -> Result<(), String>>;
+
+///
+/// What routing a line produced
+///
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct Routed {
+    /// Packets for each client, acks included
+    pub packets: Vec<(u32, Packet)>,
+    /// Console packets to feed to drones
+    pub consoles: Vec<Packet>,
+}
+
+///
+/// Server side chat routing
+///
+#[derive(Default)]
+pub struct ChatRouter {
+    members: BTreeMap<u32, ChatMember>,
+    hooks: Vec<ChatHook>,
+}
+
+impl ChatRouter {
+    pub fn new() -> ChatRouter { ChatRouter::default() }
+    pub fn len(&self) -> usize { self.members.len() }
+    pub fn is_empty(&self) -> bool { self.members.is_empty() }
+    pub fn get(&self, client: u32) -> Option<&ChatMember> { self.members.get(&client) }
+    pub fn join(&mut self, client: u32, name: &str, faction: Option<FactionId>) {
+        self.members.insert(client, ChatMember { name: name.to_string(), faction, link: ChatLink::new() });
+    }
+    pub fn leave(&mut self, client: u32) -> Option<ChatMember> { self.members.remove(&client) }
+    pub fn set_faction(&mut self, client: u32, faction: Option<FactionId>) {
+        if let Some(member) = self.members.get_mut(&client) {
+            member.faction = faction;
+        }
+    }
+    /// Add a routing hook, run after those added before it.
+    pub fn add_hook(&mut self, hook: ChatHook) { self.hooks.push(hook); }
+    ///
+    /// Take a Chat or ChatAck packet from a client and route the lines it
+    /// completes. Packets from clients which haven't joined are ignored.
+    ///
+    pub fn receive(&mut self, client: u32, packet: &Packet, simulation: &Simulation, tick: u64) -> Routed {
+        let mut routed = Routed::default();
+        let messages = match self.members.get_mut(&client) {
+            Some(member) => {
+                let (messages, ack) = member.link.receive(packet);
+                routed.packets.extend(ack.map(|ack| (client, ack)));
+                messages
+            }
+            None => return routed,
+        };
+        for mut message in messages {
+            let member = &self.members[&client];
+            message.from = member.name.clone();
+            let mut verdict = if self.permits(member.faction, message.channel, simulation) { Ok(()) } else { Err("not allowed on this channel".to_string()) };
+            for hook in self.hooks.iter_mut() {
+                if verdict.is_err() {
+                    break;
+                }
+                verdict = hook(member, &mut message);
+            }
+            match verdict {
+                Ok(()) => self.route(Some(client), message, simulation, tick, &mut routed),
+                Err(reason) => {
+                    let notice = ChatMessage { channel: message.channel, from: String::new(), text: reason };
+                    let packet = self.members.get_mut(&client).unwrap().link.send(notice, tick);
+                    routed.packets.push((client, packet));
+                }
+            }
+        }
+        routed
+    }
+    ///
+    /// Route a line from the server, without the hooks. A line on a Drone
+    /// channel reaches the members who may command that drone.
+    ///
+    pub fn announce(&mut self, message: ChatMessage, simulation: &Simulation, tick: u64) -> Routed {
+        let mut routed = Routed::default();
+        self.route(None, message, simulation, tick, &mut routed);
+        routed
+    }
+    /// Lines to send again to every client.
+    pub fn resend(&mut self, tick: u64) -> Vec<(u32, Packet)> {
+        let mut packets = Vec::new();
+        for (&client, member) in self.members.iter_mut() {
+            packets.extend(member.link.resend(tick).into_iter().map(|packet| (client, packet)));
+        }
+        packets
+    }
+    /// Whether a member of `faction` may speak on `channel`.
+    fn permits(&self, faction: Option<FactionId>, channel: Channel, simulation: &Simulation) -> bool {
+        match channel {
+            Channel::Global => true,
+            Channel::Faction(target) => faction == Some(target),
+            Channel::Drone(entity) => may_command(faction, entity, simulation),
+        }
+    }
+    fn route(&mut self, sender: Option<u32>, message: ChatMessage, simulation: &Simulation, tick: u64, routed: &mut Routed) {
+        if let (Some(_), Channel::Drone(entity)) = (sender, message.channel) {
+            routed.consoles.push(Packet::Console { entity, text: message.text });
+            return;
+        }
+        for (&client, member) in self.members.iter_mut() {
+            let reached = match message.channel {
+                Channel::Global => true,
+                Channel::Faction(faction) => member.faction == Some(faction),
+                Channel::Drone(entity) => may_command(member.faction, entity, simulation),
+            };
+            if reached {
+                routed.packets.push((client, member.link.send(message.clone(), tick)));
+            }
+        }
+    }
+}
+
+/// Whether a member of `faction` may command a living drone.
+fn may_command(faction: Option<FactionId>, entity: EntityID, simulation: &Simulation) -> bool {
+    if !simulation.entities().is_alive(entity) {
+        return false;
+    }
+    simulation.world().factions().may_command(simulation.entities(), faction, entity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChatLink, ChatMessage, ChatRouter, RESEND_TICKS};
+    use model::entity::EntityManager;
+    use model::faction::{Faction, Ownership};
+    use model::world::World;
+    use net::packet::{Channel, Packet};
+    use simulation::Simulation;
+
+    #[test]
+    pub fn test_chat() {
+        // Lines arriving out of order or twice are handed over once, in order
+        let (mut client, mut server) = (ChatLink::new(), ChatLink::new());
+        let first = client.send(ChatMessage::new(Channel::Global, "", "one"), 0);
+        let second = client.send(ChatMessage::new(Channel::Global, "", "two"), 0);
+        assert_eq!(server.receive(&second), (Vec::new(), None));
+        let (messages, ack) = server.receive(&first);
+        assert_eq!(messages.iter().map(|message| message.text.as_str()).collect::<Vec<_>>(), vec!["one", "two"]);
+        assert_eq!(server.receive(&first), (Vec::new(), ack.clone()));
+        assert_eq!(client.resend(RESEND_TICKS), vec![first, second]);
+        client.receive(&ack.unwrap());
+        assert_eq!((client.unacked(), client.resend(RESEND_TICKS * 2)), (0, Vec::new()));
+
+        let mut world = World::new();
+        let blue = world.factions_mut().register(Faction::new("blue"));
+        let red = world.factions_mut().register(Faction::new("red"));
+        let mut simulation = Simulation::new(world, EntityManager::new());
+        let drone = simulation.entities_mut().create_entity();
+        simulation.entities_mut().add_component(drone, Ownership::new(blue));
+
+        let mut router = ChatRouter::new();
+        router.join(1, "queen", Some(blue));
+        router.join(2, "rival", Some(red));
+        router.join(3, "drifter", None);
+        router.add_hook(Box::new(|_, message| {
+            if message.text.contains("spam") {
+                return Err("no spam".to_string());
+            }
+            message.text = message.text.to_uppercase();
+            Ok(())
+        }));
+        let (mut queen, mut rival) = (ChatLink::new(), ChatLink::new());
+
+        // Faction lines reach only the Faction, with the sender named by the server
+        let routed = router.receive(1, &queen.send(ChatMessage::new(Channel::Faction(blue), "forged", "swarm"), 1), &simulation, 1);
+        assert_eq!(routed.packets, vec![
+            (1, Packet::ChatAck { sequence: 1 }),
+            (1, Packet::Chat { sequence: 1, channel: Channel::Faction(blue), from: "queen".to_string(), text: "SWARM".to_string() }),
+        ]);
+        let routed = router.receive(2, &rival.send(ChatMessage::new(Channel::Faction(blue), "", "hi"), 1), &simulation, 1);
+        assert!(matches!(routed.packets[1], (2, Packet::Chat { ref from, ref text, .. }) if from.is_empty() && text == "not allowed on this channel"));
+        let routed = router.receive(2, &rival.send(ChatMessage::new(Channel::Global, "", "spam"), 1), &simulation, 1);
+        assert!(matches!(routed.packets[1], (2, Packet::Chat { ref text, .. }) if text == "no spam"));
+
+        // Drone lines go to the drone's console, its output back to those who may command it
+        let routed = router.receive(1, &queen.send(ChatMessage::new(Channel::Drone(drone), "", "halt"), 2), &simulation, 2);
+        assert_eq!(routed.consoles, vec![Packet::Console { entity: drone, text: "HALT".to_string() }]);
+        assert!(router.receive(2, &rival.send(ChatMessage::new(Channel::Drone(drone), "", "halt"), 2), &simulation, 2).consoles.is_empty());
+        let routed = router.announce(ChatMessage::new(Channel::Drone(drone), "drone", "halted"), &simulation, 3);
+        assert_eq!(routed.packets.iter().map(|&(client, _)| client).collect::<Vec<_>>(), vec![1]);
+        let routed = router.announce(ChatMessage::new(Channel::Global, "", "restart"), &simulation, 3);
+        assert_eq!(routed.packets.len(), 3);
+        assert_eq!(router.resend(3 + RESEND_TICKS).len(), 8);
+    }
+}
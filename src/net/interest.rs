@@ -0,0 +1,230 @@
+///
+/// Interest Management
+///
+/// Each client only hears about the part of the World near the entities it
+/// watches (its avatar, or drones it has asked to follow). An entity is of
+/// interest while it is watched or within the radius of a watched entity; a
+/// Chunk while it is within the radius, rounded out to whole Chunks. Every
+/// update streams the Chunks which came into range nearest first, unloads
+/// those which left and sends Block changes in those held, see `streaming`,
+/// then sends the entity snapshot filtered to the area, as a delta against
+/// the last one the client acknowledged when there is one.
+///
+use math::Fixed;
+use model::entity::EntityID;
+use model::observer::WorldEvent;
+use model::world::{chunk_of, Vector2, World, CHUNK_SIZE};
+use net::packet::{Command, Packet};
+use net::snapshot::{Snapshot, SnapshotDelta};
+use net::streaming::{ChunkStreamer, StreamConfig};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+/// Default radius of interest in Blocks
+pub const DEFAULT_RADIUS: i64 = 48;
+/// Unacknowledged snapshots remembered per client
+pub const SNAPSHOT_HISTORY: usize = 32;
+
+///
+/// Change in what a client can see
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum InterestEvent {
+    Enter(EntityID),
+    Leave(EntityID),
+}
+
+///
+/// What one client should be sent
+///
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct Replication {
+    /// Chunk changes followed by the snapshot or delta
+    pub packets: Vec<Packet>,
+    /// Entities entering or leaving interest, in entity order
+    pub events: Vec<InterestEvent>,
+}
+
+///
+/// Per Client Interest Area
+///
+#[derive(Clone, Debug)]
+pub struct Interest {
+    radius: Fixed,
+    watched: BTreeSet<EntityID>,
+    visible: BTreeSet<EntityID>,
+    streamer: ChunkStreamer,
+    /// Snapshots sent but not yet acknowledged, oldest first
+    sent: VecDeque<Snapshot>,
+    acknowledged: Option<Snapshot>,
+}
+
+impl Interest {
+    pub fn new(radius: Fixed) -> Interest {
+        Interest {
+            radius,
+            watched: BTreeSet::new(),
+            visible: BTreeSet::new(),
+            streamer: ChunkStreamer::new(StreamConfig::default()),
+            sent: VecDeque::new(),
+            acknowledged: None,
+        }
+    }
+    pub fn radius(&self) -> Fixed { self.radius }
+    pub fn set_radius(&mut self, radius: Fixed) { self.radius = radius }
+    pub fn watch(&mut self, entity: EntityID) -> bool { self.watched.insert(entity) }
+    pub fn unwatch(&mut self, entity: EntityID) -> bool { self.watched.remove(&entity) }
+    pub fn watched(&self) -> impl Iterator<Item=EntityID> + '_ { self.watched.iter().cloned() }
+    /// Entities the client was last told about.
+    pub fn visible(&self) -> impl Iterator<Item=EntityID> + '_ { self.visible.iter().cloned() }
+    /// Chunks the client currently holds.
+    pub fn chunks(&self) -> impl Iterator<Item=Vector2<u64>> + '_ { self.streamer.chunks() }
+    pub fn streamer(&self) -> &ChunkStreamer { &self.streamer }
+    pub fn streamer_mut(&mut self) -> &mut ChunkStreamer { &mut self.streamer }
+    /// Note World events, so Block changes in the Chunks held are sent with the next update.
+    pub fn observe(&mut self, events: &[WorldEvent]) { self.streamer.observe(events) }
+    /// Apply a Watch or Unwatch command, returns false for other commands.
+    pub fn handle(&mut self, command: &Command) -> bool {
+        match *command {
+            Command::Watch(entity) => self.watch(entity),
+            Command::Unwatch(entity) => self.unwatch(entity),
+            _ => false,
+        }
+    }
+    /// The client holds the snapshot of `tick`, later deltas are made against it.
+    pub fn acknowledge(&mut self, tick: u64) -> bool {
+        match self.sent.iter().position(|snapshot| snapshot.tick == tick) {
+            Some(index) => {
+                self.acknowledged = self.sent.drain(..index + 1).next_back();
+                true
+            }
+            None => false,
+        }
+    }
+    ///
+    /// The part of a snapshot within this interest area.
+    ///
+    pub fn filter(&self, snapshot: &Snapshot) -> Snapshot {
+        let centres: Vec<_> = self.watched.iter().filter_map(|&entity| snapshot.get(entity)).map(|state| state.position).collect();
+        let reach = self.radius * self.radius;
+        let entities = snapshot.entities.iter()
+            .filter(|state| self.watched.contains(&state.entity) || centres.iter().any(|centre| centre.distance_squared(&state.position) <= reach))
+            .cloned()
+            .collect();
+        Snapshot { tick: snapshot.tick, entities }
+    }
+    ///
+    /// Chunks overlapping the interest area around the watched entities,
+    /// each with its streaming priority: the squared distance in Blocks from
+    /// its centre to the nearest watched entity or where it is heading.
+    ///
+    fn wanted_chunks(&self, snapshot: &Snapshot) -> BTreeMap<Vector2<u64>, i64> {
+        let span = (self.radius.ceil().max(0) + CHUNK_SIZE as i64 - 1) / CHUNK_SIZE as i64;
+        let lookahead = self.streamer.config().lookahead;
+        let mut wanted = BTreeMap::new();
+        for state in self.watched.iter().filter_map(|&entity| snapshot.get(entity)) {
+            let (x, _, z) = state.position.block();
+            let ahead = (state.position.x + state.velocity.x * lookahead, state.position.z + state.velocity.z * lookahead);
+            let (centre, _, _) = chunk_of(x.max(0) as u64, z.max(0) as u64);
+            for cx in centre.x as i64 - span..centre.x as i64 + span + 1 {
+                for cz in centre.y as i64 - span..centre.y as i64 + span + 1 {
+                    if cx < 0 || cz < 0 {
+                        continue;
+                    }
+                    let middle = |c: i64| c * CHUNK_SIZE as i64 + CHUNK_SIZE as i64 / 2;
+                    let distance = |px: i64, pz: i64| (middle(cx) - px).pow(2) + (middle(cz) - pz).pow(2);
+                    let priority = distance(x, z).min(distance(ahead.0.floor(), ahead.1.floor()));
+                    let entry = wanted.entry(Vector2::new(cx as u64, cz as u64)).or_insert(priority);
+                    *entry = (*entry).min(priority);
+                }
+            }
+        }
+        wanted
+    }
+    ///
+    /// Work out what the client needs to be sent for the current snapshot.
+    ///
+    pub fn update(&mut self, world: &World, snapshot: &Snapshot) -> Replication {
+        let mut replication = Replication::default();
+        let wanted = self.wanted_chunks(snapshot);
+        replication.packets = self.streamer.update(world, &wanted);
+
+        let filtered = self.filter(snapshot);
+        let visible: BTreeSet<EntityID> = filtered.entities.iter().map(|state| state.entity).collect();
+        let mut events: Vec<InterestEvent> = visible.difference(&self.visible).map(|&entity| InterestEvent::Enter(entity))
+            .chain(self.visible.difference(&visible).map(|&entity| InterestEvent::Leave(entity)))
+            .collect();
+        events.sort_by_key(|event| match *event {
+            InterestEvent::Enter(entity) | InterestEvent::Leave(entity) => entity,
+        });
+        replication.events = events;
+        self.visible = visible;
+
+        replication.packets.push(match self.acknowledged {
+            Some(ref baseline) => Packet::Delta(SnapshotDelta::between(baseline, &filtered)),
+            None => Packet::Snapshot(filtered.clone()),
+        });
+        self.sent.push_back(filtered);
+        if self.sent.len() > SNAPSHOT_HISTORY {
+            self.sent.pop_front();
+        }
+        replication
+    }
+}
+
+impl Default for Interest {
+    fn default() -> Interest { Interest::new(Fixed::from_int(DEFAULT_RADIUS)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Interest, InterestEvent};
+    use math::Fixed;
+    use model::component::Position;
+    use model::entity::EntityManager;
+    use model::world::{Chunk, Vector2, World};
+    use net::packet::{Command, Packet};
+    use net::snapshot::Snapshot;
+    use pool::Poolable;
+
+    #[test]
+    pub fn test_interest_area() {
+        let mut world = World::new();
+        for cx in 0..3 {
+            world.insert_chunk(Vector2::new(cx, 0), Chunk::allocate());
+        }
+        let mut entities = EntityManager::new();
+        let drone = entities.create_entity();
+        let near = entities.create_entity();
+        let far = entities.create_entity();
+        entities.add_component(drone, Position::from_f64(5.0, 1.0, 5.0));
+        entities.add_component(near, Position::from_f64(9.0, 1.0, 5.0));
+        entities.add_component(far, Position::from_f64(80.0, 1.0, 5.0));
+
+        let mut interest = Interest::new(Fixed::from_int(8));
+        assert!(interest.handle(&Command::Watch(drone)));
+        let first = interest.update(&world, &Snapshot::capture(&entities, 1));
+        assert_eq!(first.events, vec![InterestEvent::Enter(drone), InterestEvent::Enter(near)]);
+        assert_eq!(interest.chunks().collect::<Vec<_>>(), vec![Vector2::new(0, 0), Vector2::new(1, 0)]);
+        match first.packets.last() {
+            Some(Packet::Snapshot(snapshot)) => assert_eq!(snapshot.entities.len(), 2),
+            other => panic!("expected a full snapshot, got {:?}", other),
+        }
+
+        // After the ack only changes are sent, and moving shifts the chunks
+        assert!(interest.acknowledge(1));
+        entities.get_component_mut::<Position>(drone).unwrap().x = Fixed::from_int(76);
+        let second = interest.update(&world, &Snapshot::capture(&entities, 2));
+        assert_eq!(second.events, vec![InterestEvent::Leave(near), InterestEvent::Enter(far)]);
+        assert_eq!(second.packets[0], Packet::ChunkUnload { position: Vector2::new(0, 0) });
+        assert!(matches!(second.packets[1], Packet::ChunkData { position, .. } if position == Vector2::new(2, 0)));
+        match second.packets.last() {
+            Some(Packet::Delta(delta)) => {
+                assert_eq!(delta.baseline, 1);
+                assert_eq!(delta.removed, vec![near]);
+                assert_eq!(delta.changed.len(), 2);
+            }
+            other => panic!("expected a delta, got {:?}", other),
+        }
+        assert!(!interest.acknowledge(7));
+    }
+}
@@ -0,0 +1,468 @@
+///
+/// Lockstep Sessions
+///
+/// Peers sharing a hive each run the whole Simulation and exchange only what
+/// they put into it. Every tick each peer sends a TickInput of the commands
+/// it gives, scheduled `input_delay` ticks ahead so the packet has time to
+/// arrive, and a tick runs only once every peer's input for it is in, the
+/// commands applied in peer order. The Simulation being deterministic, every
+/// peer then holds the same state.
+///
+/// A peer whose input is late holds everyone up; the session reports whom it
+/// waits on and, past `stall_timeout`, that the wait has stalled so the game
+/// can show it or drop the peer. Every `hash_interval` ticks the peers swap
+/// hashes of their state, and a mismatch stops the session as desynced. With
+/// a dump directory set, each peer then writes the hashes of every entity,
+/// CPU and Chunk at that tick to `desync-<tick>-<peer>.txt`, so diffing two
+/// peers' dumps points at what diverged.
+///
+use error::HivemindError;
+use model::component::{Position, Velocity};
+use model::storage::{encode_chunk, Compression};
+use net::packet::{Command, Packet};
+use simulation::Simulation;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use vcpu::cluster::CpuComponent;
+
+/// Default ticks between giving a command and it running
+pub const DEFAULT_INPUT_DELAY: u64 = 3;
+/// Default wait on a peer's input before the session counts as stalled
+pub const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(2);
+/// Default ticks between state hash checks
+pub const DEFAULT_HASH_INTERVAL: u64 = 60;
+/// State digests kept for dumps, as late hashes can still find a desync in them
+const DIGESTS_KEPT: usize = 8;
+
+///
+/// Lockstep Settings
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct LockstepConfig {
+    pub input_delay: u64,
+    pub stall_timeout: Duration,
+    /// 0 to never check
+    pub hash_interval: u64,
+}
+
+impl Default for LockstepConfig {
+    fn default() -> LockstepConfig {
+        LockstepConfig { input_delay: DEFAULT_INPUT_DELAY, stall_timeout: DEFAULT_STALL_TIMEOUT, hash_interval: DEFAULT_HASH_INTERVAL }
+    }
+}
+
+///
+/// What a call to `advance` did
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LockstepStatus {
+    /// Ran this tick
+    Ran(u64),
+    /// The next tick is waiting on these peers' input
+    Waiting(Vec<u32>),
+    /// Still waiting on these peers after the stall timeout
+    Stalled(Vec<u32>),
+}
+
+///
+/// Lockstep Failure
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LockstepError {
+    /// A packet came from a peer outside the session
+    UnknownPeer(u32),
+    /// A peer sent a second, different input for a tick
+    ConflictingInput { peer: u32, tick: u64 },
+    /// A peer's state hash differs from ours, the session stops
+    Desync { tick: u64, peer: u32, local: u64, remote: u64 },
+}
+
+impl fmt::Display for LockstepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LockstepError::UnknownPeer(peer) => write!(f, "peer {} is not in the session", peer),
+            LockstepError::ConflictingInput { peer, tick } => write!(f, "peer {} sent two inputs for tick {}", peer, tick),
+            LockstepError::Desync { tick, peer, local, remote } => {
+                write!(f, "desync at tick {}: hash {:016x} here, {:016x} at peer {}", tick, local, remote, peer)
+            }
+        }
+    }
+}
+
+///
+/// Hash of a Simulation's state and of the parts it is made of
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct StateDigest {
+    pub tick: u64,
+    pub hash: u64,
+    /// Named parts and their hashes, in the order hashed
+    pub parts: Vec<(String, u64)>,
+}
+
+/// FNV-1a over bytes, continuing from `hash`
+fn fnv(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3))
+}
+
+const FNV_OFFSET: u64 = 0xCBF2_9CE4_8422_2325;
+
+///
+/// Hash the state peers must agree on: every entity's Position and Velocity,
+/// every CPU's registers and memory and every loaded Chunk of the first
+/// World. Resources such as the tick and environment follow from those.
+///
+pub fn state_digest(simulation: &Simulation) -> StateDigest {
+    let mut parts = Vec::new();
+    let entities = simulation.entities();
+    let mut ids: Vec<_> = entities.entities().iter().collect();
+    ids.sort();
+    for entity in ids {
+        let mut hash = fnv(FNV_OFFSET, &(entity.suffix() as u64).to_le_bytes());
+        if let Some(position) = entities.get_component::<Position>(entity) {
+            for value in [position.x, position.y, position.z].iter() {
+                hash = fnv(hash, &value.raw().to_le_bytes());
+            }
+        }
+        if let Some(velocity) = entities.get_component::<Velocity>(entity) {
+            for value in [velocity.x, velocity.y, velocity.z].iter() {
+                hash = fnv(hash, &value.raw().to_le_bytes());
+            }
+        }
+        parts.push((format!("entity {}:{}", entity.slot(), entity.suffix()), hash));
+    }
+    let cluster = simulation.cluster();
+    for id in cluster.ids() {
+        let cpu = cluster.get(id).unwrap();
+        let registers = [
+            cpu.get_a(), cpu.get_b(), cpu.get_c(), cpu.get_x(), cpu.get_y(), cpu.get_z(), cpu.get_i(), cpu.get_j(),
+            cpu.get_pc(), cpu.get_sp(), cpu.get_ex(), cpu.get_ia(),
+        ];
+        let mut hash = registers.iter().fold(FNV_OFFSET, |hash, register| fnv(hash, &register.to_le_bytes()));
+        for address in 0..=u16::MAX {
+            hash = fnv(hash, &cpu.get_memory(address).to_le_bytes());
+        }
+        parts.push((format!("cpu {}.{}", id.slot(), id.generation()), hash));
+    }
+    let world = simulation.world();
+    let mut chunks = world.loaded_chunks();
+    chunks.sort_by_key(|position| (position.x, position.y));
+    let mut bytes = Vec::new();
+    for position in chunks {
+        bytes.clear();
+        encode_chunk(world.get_chunk(position).unwrap(), Compression::None, &mut bytes).expect("writing to memory");
+        parts.push((format!("chunk {},{}", position.x, position.y), fnv(FNV_OFFSET, &bytes)));
+    }
+    let hash = parts.iter().fold(fnv(FNV_OFFSET, &simulation.tick().to_le_bytes()), |hash, &(_, part)| fnv(hash, &part.to_le_bytes()));
+    StateDigest { tick: simulation.tick(), hash, parts }
+}
+
+///
+/// Apply a peer's command to the Simulation. Commands naming an entity
+/// without a CPU do nothing, as do Watch and Unwatch, and Move only places
+/// entities which already have a Position.
+///
+pub fn apply_command(simulation: &mut Simulation, command: &Command) {
+    let cpu_of = |simulation: &Simulation, entity| simulation.entities().get_component::<CpuComponent>(entity).map(|component| component.cpu);
+    match *command {
+        Command::Upload { entity, address, ref words } => {
+            if let Some(cpu) = cpu_of(simulation, entity).and_then(|cpu| simulation.cluster_mut().get_mut(cpu)) {
+                for (offset, &word) in words.iter().enumerate() {
+                    cpu.set_memory(address.wrapping_add(offset as u16), word);
+                }
+            }
+        }
+        Command::Interrupt { entity, message } => {
+            if let Some(cpu) = cpu_of(simulation, entity).and_then(|cpu| simulation.cluster_mut().get_mut(cpu)) {
+                cpu.interrupt(message);
+            }
+        }
+        Command::Move { entity, position } => {
+            if let Some(current) = simulation.entities_mut().get_component_mut::<Position>(entity) {
+                *current = position;
+            }
+        }
+        Command::Watch(_) | Command::Unwatch(_) => {}
+    }
+}
+
+///
+/// Lockstep Session of one peer
+///
+pub struct LockstepSession {
+    local: u32,
+    /// Every peer, this one included, in the order their commands apply
+    peers: Vec<u32>,
+    config: LockstepConfig,
+    /// Inputs for ticks yet to run, by tick then peer
+    inputs: BTreeMap<u64, BTreeMap<u32, Vec<Command>>>,
+    /// Last tick this peer has sent its input for
+    scheduled: u64,
+    queued: Vec<Command>,
+    /// Hashes of ticks not yet checked, by tick then peer
+    hashes: BTreeMap<u64, HashMap<u32, u64>>,
+    digests: Vec<StateDigest>,
+    outgoing: Vec<Packet>,
+    waiting_since: Option<Instant>,
+    desync: Option<LockstepError>,
+    dump_directory: Option<PathBuf>,
+}
+
+impl LockstepSession {
+    ///
+    /// Session for `local` among `peers` over a Simulation at tick `start`,
+    /// which every peer must begin from. The first `input_delay` ticks run
+    /// with no commands, as nobody could have sent any for them.
+    ///
+    pub fn new(local: u32, peers: &[u32], config: LockstepConfig, start: u64) -> LockstepSession {
+        let mut peers = peers.to_vec();
+        if !peers.contains(&local) {
+            peers.push(local);
+        }
+        peers.sort();
+        peers.dedup();
+        let empty: BTreeMap<u32, Vec<Command>> = peers.iter().map(|&peer| (peer, Vec::new())).collect();
+        let inputs = (start + 1..=start + config.input_delay).map(|tick| (tick, empty.clone())).collect();
+        LockstepSession {
+            local,
+            peers,
+            config,
+            inputs,
+            scheduled: start + config.input_delay,
+            queued: Vec::new(),
+            hashes: BTreeMap::new(),
+            digests: Vec::new(),
+            outgoing: Vec::new(),
+            waiting_since: None,
+            desync: None,
+            dump_directory: None,
+        }
+    }
+    pub fn local(&self) -> u32 { self.local }
+    pub fn peers(&self) -> &[u32] { &self.peers }
+    pub fn config(&self) -> LockstepConfig { self.config }
+    /// Write state dumps to `directory` on a desync.
+    pub fn set_dump_directory(&mut self, directory: Option<PathBuf>) { self.dump_directory = directory }
+    /// The desync which stopped the session, if any.
+    pub fn desync(&self) -> Option<&LockstepError> { self.desync.as_ref() }
+    /// Give a command, run with this peer's input for the next tick scheduled.
+    pub fn queue(&mut self, command: Command) { self.queued.push(command) }
+    /// Packets to send every other peer, oldest first.
+    pub fn drain_outgoing(&mut self) -> Vec<Packet> { self.outgoing.drain(..).collect() }
+    ///
+    /// Take a packet from another peer. Inputs for ticks already run are
+    /// dropped as repeats; packets other than TickInput and StateHash are
+    /// ignored.
+    ///
+    pub fn receive(&mut self, packet: &Packet, simulation: &Simulation) -> Result<(), LockstepError> {
+        match *packet {
+            Packet::TickInput { peer, tick, ref commands } => {
+                self.check_peer(peer)?;
+                if tick <= simulation.tick() {
+                    return Ok(());
+                }
+                let inputs = self.inputs.entry(tick).or_default();
+                match inputs.get(&peer) {
+                    Some(existing) if existing != commands => Err(LockstepError::ConflictingInput { peer, tick }),
+                    _ => {
+                        inputs.insert(peer, commands.clone());
+                        Ok(())
+                    }
+                }
+            }
+            Packet::StateHash { peer, tick, hash } => {
+                self.check_peer(peer)?;
+                self.hashes.entry(tick).or_default().insert(peer, hash);
+                self.check_hashes(tick)
+            }
+            _ => Ok(()),
+        }
+    }
+    fn check_peer(&self, peer: u32) -> Result<(), LockstepError> {
+        match self.peers.contains(&peer) && peer != self.local {
+            true => Ok(()),
+            false => Err(LockstepError::UnknownPeer(peer)),
+        }
+    }
+    /// Compare the hashes in for `tick` with ours, stopping the session on a mismatch.
+    fn check_hashes(&mut self, tick: u64) -> Result<(), LockstepError> {
+        let hashes = match self.hashes.get(&tick) {
+            Some(hashes) => hashes,
+            None => return Ok(()),
+        };
+        let local = match hashes.get(&self.local) {
+            Some(&local) => local,
+            None => return Ok(()),
+        };
+        if let Some((&peer, &remote)) = hashes.iter().filter(|&(_, &hash)| hash != local).min_by_key(|&(&peer, _)| peer) {
+            let error = LockstepError::Desync { tick, peer, local, remote };
+            self.desync = Some(error.clone());
+            // A failed dump must not hide the desync itself
+            let _ = self.dump(tick);
+            return Err(error);
+        }
+        if hashes.len() == self.peers.len() {
+            self.hashes.remove(&tick);
+        }
+        Ok(())
+    }
+    /// Write the digest of `tick` to the dump directory, if both are there.
+    fn dump(&self, tick: u64) -> io::Result<Option<PathBuf>> {
+        let (directory, digest) = match (self.dump_directory.as_ref(), self.digests.iter().find(|digest| digest.tick == tick)) {
+            (Some(directory), Some(digest)) => (directory, digest),
+            _ => return Ok(None),
+        };
+        fs::create_dir_all(directory)?;
+        let mut text = format!("peer {} tick {} hash {:016x}\n", self.local, tick, digest.hash);
+        for &(ref name, hash) in digest.parts.iter() {
+            text.push_str(&format!("{} {:016x}\n", name, hash));
+        }
+        let path = directory.join(format!("desync-{}-{}.txt", tick, self.local));
+        fs::write(&path, text)?;
+        Ok(Some(path))
+    }
+    /// Peers whose input for `tick` is missing.
+    fn missing(&self, tick: u64) -> Vec<u32> {
+        let inputs = self.inputs.get(&tick);
+        self.peers.iter().cloned().filter(|peer| !inputs.is_some_and(|inputs| inputs.contains_key(peer))).collect()
+    }
+    ///
+    /// Send this peer's input for the tick `input_delay` after the next, then
+    /// run the next tick if every peer's input for it is in. Call it once per
+    /// tick interval; while waiting it only reports whom on.
+    ///
+    pub fn advance(&mut self, simulation: &mut Simulation) -> Result<LockstepStatus, HivemindError> {
+        if let Some(ref desync) = self.desync {
+            return Err(HivemindError::Lockstep(desync.clone()));
+        }
+        let next = simulation.tick() + 1;
+        while self.scheduled < next + self.config.input_delay {
+            self.scheduled += 1;
+            let commands: Vec<Command> = self.queued.drain(..).collect();
+            self.inputs.entry(self.scheduled).or_default().insert(self.local, commands.clone());
+            self.outgoing.push(Packet::TickInput { peer: self.local, tick: self.scheduled, commands });
+        }
+        let missing = self.missing(next);
+        if !missing.is_empty() {
+            let since = *self.waiting_since.get_or_insert_with(Instant::now);
+            return Ok(match since.elapsed() >= self.config.stall_timeout {
+                true => LockstepStatus::Stalled(missing),
+                false => LockstepStatus::Waiting(missing),
+            });
+        }
+        self.waiting_since = None;
+        let inputs = self.inputs.remove(&next).unwrap_or_default();
+        for commands in inputs.values() {
+            for command in commands.iter() {
+                apply_command(simulation, command);
+            }
+        }
+        simulation.step();
+        if self.config.hash_interval > 0 && next.is_multiple_of(self.config.hash_interval) {
+            let digest = state_digest(simulation);
+            self.outgoing.push(Packet::StateHash { peer: self.local, tick: next, hash: digest.hash });
+            self.hashes.entry(next).or_default().insert(self.local, digest.hash);
+            self.digests.push(digest);
+            if self.digests.len() > DIGESTS_KEPT {
+                self.digests.remove(0);
+            }
+            self.check_hashes(next)?;
+        }
+        Ok(LockstepStatus::Ran(next))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{state_digest, LockstepConfig, LockstepError, LockstepSession, LockstepStatus};
+    use error::HivemindError;
+    use model::entity::EntityManager;
+    use model::world::{Chunk, Vector2, World};
+    use net::packet::{Command, Packet};
+    use simulation::Simulation;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::time::Duration;
+    use vcpu::cluster::CpuComponent;
+
+    fn simulation() -> Simulation {
+        let mut world = World::new();
+        world.insert_chunk(Vector2::new(0, 0), Box::new(Chunk::new()));
+        let mut simulation = Simulation::new(world, EntityManager::new());
+        let drone = simulation.entities_mut().create_entity();
+        simulation.attach(drone, &[]).unwrap();
+        simulation
+    }
+
+    /// Hand every packet each session sends to the other.
+    fn exchange(sessions: &mut [(LockstepSession, Simulation); 2]) -> Result<(), LockstepError> {
+        let (left, right) = sessions.split_at_mut(1);
+        let (a, b) = (&mut left[0], &mut right[0]);
+        for packet in a.0.drain_outgoing() {
+            b.0.receive(&packet, &b.1)?;
+        }
+        for packet in b.0.drain_outgoing() {
+            a.0.receive(&packet, &a.1)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_lockstep() {
+        let config = LockstepConfig { input_delay: 2, stall_timeout: Duration::from_secs(0), hash_interval: 4 };
+        let mut sessions = [(LockstepSession::new(1, &[1, 2], config, 0), simulation()), (LockstepSession::new(2, &[1, 2], config, 0), simulation())];
+        let drone = sessions[0].1.entities().entities().iter().next().unwrap();
+        let cpu = sessions[0].1.entities().get_component::<CpuComponent>(drone).unwrap().cpu;
+
+        // A command given before tick 1 runs at tick 3 on both peers
+        sessions[0].0.queue(Command::Upload { entity: drone, address: 0x200, words: vec![7, 8] });
+        for tick in 1..=8 {
+            for (session, simulation) in sessions.iter_mut() {
+                assert_eq!(session.advance(simulation).unwrap(), LockstepStatus::Ran(tick));
+            }
+            if tick == 2 {
+                assert!(sessions.iter().all(|(_, simulation)| simulation.cluster().get(cpu).unwrap().get_memory(0x201) == 0));
+            }
+            exchange(&mut sessions).unwrap();
+        }
+        assert!(sessions.iter().all(|(_, simulation)| simulation.cluster().get(cpu).unwrap().get_memory(0x201) == 8));
+        assert_eq!(state_digest(&sessions[0].1), state_digest(&sessions[1].1));
+
+        // Peer 2 falls silent, so peer 1 runs out of input and stalls
+        for _ in 0..2 {
+            assert!(matches!(sessions[0].0.advance(&mut sessions[0].1).unwrap(), LockstepStatus::Ran(_)));
+        }
+        assert_eq!(sessions[0].0.advance(&mut sessions[0].1).unwrap(), LockstepStatus::Stalled(vec![2]));
+        assert_eq!(sessions[0].0.receive(&Packet::StateHash { peer: 3, tick: 4, hash: 0 }, &sessions[0].1), Err(LockstepError::UnknownPeer(3)));
+        for _ in 0..2 {
+            sessions[1].0.advance(&mut sessions[1].1).unwrap();
+        }
+        exchange(&mut sessions).unwrap();
+
+        // Peer 2's state drifts and the next hash check catches it
+        let directory = env::temp_dir().join(format!("hivemind-lockstep-{}", process::id()));
+        sessions[0].0.set_dump_directory(Some(directory.clone()));
+        sessions[1].1.cluster_mut().get_mut(cpu).unwrap().set_memory(0x300, 1);
+        let mut desync = None;
+        for _ in 0..4 {
+            for (session, simulation) in sessions.iter_mut() {
+                if let Err(HivemindError::Lockstep(error)) = session.advance(simulation) {
+                    desync = Some(error);
+                }
+            }
+            if let Err(error) = exchange(&mut sessions) {
+                desync = Some(error);
+            }
+        }
+        assert!(matches!(desync, Some(LockstepError::Desync { tick: 12, .. })), "{:?}", desync);
+        assert!(sessions[0].0.desync().is_some());
+        let dump = fs::read_to_string(directory.join("desync-12-1.txt")).unwrap();
+        assert!(dump.starts_with("peer 1 tick 12 hash ") && dump.contains(&format!("cpu {}.{} ", cpu.slot(), cpu.generation())));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+//!
+//! LZ4 Block Compression
+//!
+//! The LZ4 block format, without the frame around it, so any LZ4 library can
+//! read what `compress` writes. Matches are found greedily through a table of
+//! the last position each 4 byte sequence was seen at, fast rather than
+//! tight, which suits packets compressed every tick. Kept in the crate so
+//! compression needs no dependency.
+//!
+use codec::invalid_data;
+use std::io;
+
+/// Shortest match encoded
+const MIN_MATCH: usize = 4;
+/// The last bytes of a block are always literals
+const LAST_LITERALS: usize = 5;
+/// No match starts this close to the end of a block
+const MF_LIMIT: usize = 12;
+const HASH_BITS: u32 = 12;
+const MAX_OFFSET: usize = 0xFFFF;
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 { u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]) }
+
+fn hash(sequence: u32) -> usize { (sequence.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize }
+
+/// Length beyond what fits in a token nibble
+fn write_length(output: &mut Vec<u8>, mut length: usize) {
+    while length >= 255 {
+        output.push(255);
+        length -= 255;
+    }
+    output.push(length as u8);
+}
+
+fn write_sequence(output: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_nibble = matched.map_or(0, |(_, length)| (length - MIN_MATCH).min(15));
+    output.push(((literals.len().min(15) as u8) << 4) | match_nibble as u8);
+    if literals.len() >= 15 {
+        write_length(output, literals.len() - 15);
+    }
+    output.extend_from_slice(literals);
+    if let Some((offset, length)) = matched {
+        output.extend_from_slice(&(offset as u16).to_le_bytes());
+        if length - MIN_MATCH >= 15 {
+            write_length(output, length - MIN_MATCH - 15);
+        }
+    }
+}
+
+/// Compress bytes into an LZ4 block.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() / 2 + 16);
+    let mut table = vec![0usize; 1 << HASH_BITS];
+    let (mut anchor, mut position) = (0, 0);
+    if input.len() > MF_LIMIT {
+        let limit = input.len() - MF_LIMIT;
+        let match_limit = input.len() - LAST_LITERALS;
+        while position < limit {
+            let sequence = read_u32(input, position);
+            let slot = hash(sequence);
+            // Positions are kept one up so zero means empty
+            let candidate = table[slot];
+            table[slot] = position + 1;
+            if candidate > 0 && position - (candidate - 1) <= MAX_OFFSET && read_u32(input, candidate - 1) == sequence {
+                let from = candidate - 1;
+                let mut length = MIN_MATCH;
+                while position + length < match_limit && input[from + length] == input[position + length] {
+                    length += 1;
+                }
+                write_sequence(&mut output, &input[anchor..position], Some((position - from, length)));
+                position += length;
+                anchor = position;
+            } else {
+                position += 1;
+            }
+        }
+    }
+    write_sequence(&mut output, &input[anchor..], None);
+    output
+}
+
+/// Length continued past a token nibble
+fn read_length(input: &[u8], at: &mut usize) -> io::Result<usize> {
+    let mut length = 0;
+    loop {
+        let byte = *input.get(*at).ok_or_else(|| invalid_data("lz4 length runs past the block"))?;
+        *at += 1;
+        length += byte as usize;
+        if byte != 255 {
+            return Ok(length);
+        }
+    }
+}
+
+///
+/// Decompress an LZ4 block, failing if it is malformed or would grow past
+/// `limit` bytes.
+///
+pub fn decompress(input: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+    let mut output: Vec<u8> = Vec::new();
+    let mut at = 0;
+    loop {
+        let token = *input.get(at).ok_or_else(|| invalid_data("lz4 block is truncated"))?;
+        at += 1;
+        let mut literals = (token >> 4) as usize;
+        if literals == 15 {
+            literals += read_length(input, &mut at)?;
+        }
+        if at + literals > input.len() || output.len() + literals > limit {
+            return Err(invalid_data("lz4 literals run past the block"));
+        }
+        output.extend_from_slice(&input[at..at + literals]);
+        at += literals;
+        if at == input.len() {
+            return Ok(output);
+        }
+        if at + 2 > input.len() {
+            return Err(invalid_data("lz4 block is truncated"));
+        }
+        let offset = u16::from_le_bytes([input[at], input[at + 1]]) as usize;
+        at += 2;
+        let mut length = (token & 0x0F) as usize;
+        if length == 15 {
+            length += read_length(input, &mut at)?;
+        }
+        length += MIN_MATCH;
+        if offset == 0 || offset > output.len() || output.len() + length > limit {
+            return Err(invalid_data("lz4 match is out of range"));
+        }
+        // Matches may overlap what they write, so copy a byte at a time
+        let start = output.len() - offset;
+        for index in 0..length {
+            let byte = output[start + index];
+            output.push(byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress, decompress};
+
+    #[test]
+    pub fn test_lz4_roundtrip() {
+        let mut inputs: Vec<Vec<u8>> = vec![Vec::new(), b"tiny".to_vec(), vec![7; 5000]];
+        inputs.push((0..20_000u32).map(|i| ((i * 7) % 251) as u8 ^ (i / 300) as u8).collect());
+        inputs.push(b"drone drone drone hive hive queen ".iter().cycle().take(1000).cloned().collect());
+        for input in inputs.iter() {
+            let packed = compress(input);
+            assert_eq!(&decompress(&packed, input.len()).unwrap(), input);
+        }
+        assert!(compress(&inputs[2]).len() < 64);
+        // A hand made block: literals "ab" then a match of 6 copying them over and over
+        assert_eq!(decompress(&[0x22, b'a', b'b', 2, 0, 0x00], 8).unwrap(), b"abababab".to_vec());
+
+        // Corrupt blocks and ones growing past the limit are refused
+        assert!(decompress(&[], 10).is_err());
+        assert!(decompress(&[0x22, b'a', b'b', 9, 0], 8).is_err());
+        assert!(decompress(&compress(&inputs[2]), 4999).is_err());
+    }
+}
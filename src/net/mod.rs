@@ -0,0 +1,102 @@
+//!
+//! Client/Server Protocol
+//!
+//! A client opens with Hello carrying the protocol version and is answered
+//! with Welcome, or Disconnect if the versions differ. The server then streams
+//! ChunkData for the area it cares about and entity Snapshots, switching to
+//! Deltas against the last snapshot the client acknowledged. Clients send
+//! numbered Input commands, acknowledged with InputAck so they can predict
+//! locally, and console text back. What each client is sent is limited to its
+//! interest area, its Chunks streamed nearest first and kept current with
+//! BlockDeltas, see `streaming`. Games syncing more than positions describe per component
+//! which fields replicate, how coarsely and how often, see `replication`.
+//!
+//! A server can require clients to authenticate before the Welcome, with a
+//! pre-shared key or a signed token, and rate limits what each connection
+//! sends, see `auth`. Their commands can then be checked against the
+//! faction model, movement bounds and action rates before they are applied,
+//! see `validate`.
+//!
+//! Peers playing cooperatively can instead run the Simulation side by side in
+//! lockstep, exchanging only their commands for each tick and hashes of their
+//! state, see `lockstep`. Observers open with Spectate instead of Hello and
+//! are streamed what players see without being able to act, see `spectator`.
+//! Chat lines on the global, faction and drone console channels are
+//! numbered and acknowledged so they arrive once and in order over either
+//! transport, and are routed by the server, see `chat`.
+//!
+//! Over a stream (TCP) every packet is framed by a u32 length. Over datagrams
+//! (UDP) each datagram holds exactly one unframed packet. What the server
+//! sends a client in a tick can be joined into Batches, compressed with the
+//! `lz4` feature, see `batch`.
+//!
+
+pub mod auth;
+pub mod batch;
+pub mod chat;
+#[cfg(any(feature = "psk", feature = "tokens"))]
+pub mod hmac;
+pub mod interest;
+pub mod lockstep;
+#[cfg(feature = "lz4")]
+pub mod lz4;
+pub mod packet;
+pub mod prediction;
+pub mod replication;
+pub mod snapshot;
+pub mod spectator;
+pub mod streaming;
+pub mod validate;
+
+use codec::{invalid_data, write_u32};
+use net::packet::Packet;
+use std::io::{self, ErrorKind, Read, Write};
+
+/// Version spoken by this build, bumped on any incompatible change
+pub const PROTOCOL_VERSION: u16 = 1;
+/// Largest frame accepted from a stream
+pub const MAX_FRAME_SIZE: u32 = 1 << 20;
+
+///
+/// Write one length prefixed packet.
+///
+pub fn write_frame(writer: &mut dyn Write, packet: &Packet) -> io::Result<()> {
+    let bytes = packet.to_bytes();
+    if bytes.len() > MAX_FRAME_SIZE as usize {
+        return Err(invalid_data("packet is larger than a frame"));
+    }
+    write_u32(writer, bytes.len() as u32)?;
+    writer.write_all(&bytes)
+}
+
+///
+/// Read one length prefixed packet, None if the stream ended between frames.
+///
+pub fn read_frame(reader: &mut dyn Read) -> io::Result<Option<Packet>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(ref error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error),
+    }
+    let length = u32::from_le_bytes(length);
+    if length > MAX_FRAME_SIZE {
+        return Err(invalid_data("frame is too large"));
+    }
+    let mut bytes = vec![0; length as usize];
+    reader.read_exact(&mut bytes)?;
+    Packet::from_bytes(&bytes).map(Some)
+}
+
+///
+/// Server side answer to a client's opening packet.
+///
+pub fn welcome(hello: &Packet, client: u32, tick: u64) -> Packet {
+    match *hello {
+        Packet::Hello { version, .. } if version == PROTOCOL_VERSION => Packet::Welcome { version, client, tick },
+        Packet::Hello { version, .. } => Packet::Disconnect {
+            reason: format!("protocol version {} is not supported, this server speaks {}", version, PROTOCOL_VERSION),
+        },
+        _ => Packet::Disconnect { reason: "expected hello".to_string() },
+    }
+}
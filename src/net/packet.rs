@@ -0,0 +1,581 @@
+///
+/// Protocol Packets
+///
+/// Every packet starts with a one byte tag followed by its fields in the
+/// codec's little-endian encoding. Chunks travel in the same palette and
+/// run-length form used by region files.
+///
+///  TAG  | PACKET       | DIRECTION
+/// ------+--------------+------------------
+///  0x01 | Hello        | client -> server
+///  0x02 | Welcome      | server -> client
+///  0x03 | Disconnect   | either
+///  0x04 | Spectate     | client -> server
+///  0x05 | Challenge    | server -> client
+///  0x06 | Authenticate | client -> server
+///  0x07 | Batch        | either
+///  0x10 | ChunkData    | server -> client
+///  0x11 | ChunkUnload  | server -> client
+///  0x12 | BlockDelta   | server -> client
+///  0x20 | Snapshot     | server -> client
+///  0x21 | Delta        | server -> client
+///  0x22 | Ack          | client -> server
+///  0x23 | Replication  | server -> client
+///  0x30 | Input        | client -> server
+///  0x31 | InputAck     | server -> client
+///  0x32 | TickInput    | peer -> peer
+///  0x33 | StateHash    | peer -> peer
+///  0x40 | Console      | either
+///  0x41 | Chat         | either
+///  0x42 | ChatAck      | either
+///  0x50 | AdminLogin   | client -> server
+///  0x51 | Admin        | client -> server
+///  0x52 | AdminReply   | server -> client
+///  0x60 | Custom       | either
+///
+use codec::{invalid_data, read_string, read_u16, read_u32, read_u64, read_u8, write_string, write_u16, write_u32, write_u64, write_u8};
+use math::Fixed;
+use model::component::Position;
+use model::entity::EntityID;
+use model::faction::FactionId;
+use model::storage::{decode_chunk, encode_chunk, Compression};
+use model::world::{Chunk, Vector2};
+use net::replication::ReplicationDelta;
+use net::MAX_FRAME_SIZE;
+use net::snapshot::{read_entity, write_entity, Snapshot, SnapshotDelta};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+const HELLO: u8 = 0x01;
+const WELCOME: u8 = 0x02;
+const DISCONNECT: u8 = 0x03;
+const SPECTATE: u8 = 0x04;
+const CHALLENGE: u8 = 0x05;
+const AUTHENTICATE: u8 = 0x06;
+const BATCH: u8 = 0x07;
+const CHUNK_DATA: u8 = 0x10;
+const CHUNK_UNLOAD: u8 = 0x11;
+const BLOCK_DELTA: u8 = 0x12;
+const SNAPSHOT: u8 = 0x20;
+const DELTA: u8 = 0x21;
+const ACK: u8 = 0x22;
+const REPLICATION: u8 = 0x23;
+const INPUT: u8 = 0x30;
+const INPUT_ACK: u8 = 0x31;
+const TICK_INPUT: u8 = 0x32;
+const STATE_HASH: u8 = 0x33;
+const CONSOLE: u8 = 0x40;
+const CHAT: u8 = 0x41;
+const CHAT_ACK: u8 = 0x42;
+const ADMIN_LOGIN: u8 = 0x50;
+const ADMIN: u8 = 0x51;
+const ADMIN_REPLY: u8 = 0x52;
+const CUSTOM: u8 = 0x60;
+
+///
+/// Client Command
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Command {
+    /// Write words into a CPU's memory starting at an address
+    Upload { entity: EntityID, address: u16, words: Vec<u16> },
+    /// Raise a software interrupt on a CPU
+    Interrupt { entity: EntityID, message: u16 },
+    /// Follow an entity
+    Watch(EntityID),
+    Unwatch(EntityID),
+    /// Claim a controlled entity has moved to a Position, see `net::validate`
+    Move { entity: EntityID, position: Position },
+}
+
+///
+/// Chat Channel
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Channel {
+    /// Everyone on the server
+    Global,
+    /// Members of one Faction
+    Faction(FactionId),
+    /// A drone's console
+    Drone(EntityID),
+}
+
+///
+/// Protocol Packet
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Packet {
+    Hello { version: u16, name: String },
+    Welcome { version: u16, client: u32, tick: u64 },
+    Disconnect { reason: String },
+    /// Hello from a read-only observer, see `net::spectator`
+    Spectate { version: u16, name: String },
+    /// Nonce a client must prove its credentials against, see `net::auth`
+    Challenge { nonce: Vec<u8> },
+    /// Answer to a Challenge, a key proof or a signed token
+    Authenticate { name: String, proof: Vec<u8> },
+    /// Packets sent together, `length` bytes once unpacked, see `net::batch`
+    Batch { codec: u8, length: u32, data: Vec<u8> },
+    /// Chunk as written by `encode_chunk`
+    ChunkData { position: Vector2<u64>, data: Vec<u8> },
+    ChunkUnload { position: Vector2<u64> },
+    /// Blocks changed in a Chunk the client holds, by position within it and material, see `net::streaming`
+    BlockDelta { position: Vector2<u64>, blocks: Vec<([u8; 3], u16)> },
+    Snapshot(Snapshot),
+    Delta(SnapshotDelta),
+    /// The client holds the snapshot of this tick and can take deltas against it
+    Ack { tick: u64 },
+    /// Replicated components against the acknowledged state, see `net::replication`
+    Replication(ReplicationDelta),
+    /// Command numbered by the client's input sequence
+    Input { sequence: u64, command: Command },
+    /// Every input up to and including this sequence has been applied
+    InputAck { sequence: u64 },
+    /// A lockstep peer's commands for a tick, see `net::lockstep`
+    TickInput { peer: u32, tick: u64, commands: Vec<Command> },
+    /// A lockstep peer's hash of its state at the end of a tick
+    StateHash { peer: u32, tick: u64, hash: u64 },
+    /// Text written to or by a CPU's console
+    Console { entity: EntityID, text: String },
+    /// Chat line numbered by its sender's chat sequence, see `net::chat`
+    Chat { sequence: u64, channel: Channel, from: String, text: String },
+    /// Every chat line up to and including this sequence has been received
+    ChatAck { sequence: u64 },
+    /// Authenticate the connection for admin commands
+    AdminLogin { secret: String },
+    /// Admin command line, see `admin::AdminCommand`
+    Admin { line: String },
+    AdminReply { ok: bool, text: String },
+    /// Packet of a type registered in PacketTypes, its payload left to the registrant
+    Custom { kind: u16, data: Vec<u8> },
+}
+
+///
+/// Custom Packet Types by name
+///
+/// Kinds are handed out in registration order, so both ends must register
+/// the same types in the same order.
+///
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct PacketTypes {
+    names: Vec<String>,
+    kinds: HashMap<String, u16>,
+}
+
+impl PacketTypes {
+    pub fn new() -> PacketTypes { PacketTypes::default() }
+    /// Kind for a name, registering it if new.
+    pub fn register(&mut self, name: &str) -> u16 {
+        if let Some(&kind) = self.kinds.get(name) {
+            return kind;
+        }
+        let kind = self.names.len() as u16;
+        self.names.push(name.to_string());
+        self.kinds.insert(name.to_string(), kind);
+        kind
+    }
+    pub fn kind(&self, name: &str) -> Option<u16> { self.kinds.get(name).cloned() }
+    pub fn name(&self, kind: u16) -> Option<&str> { self.names.get(kind as usize).map(String::as_str) }
+    pub fn len(&self) -> usize { self.names.len() }
+    pub fn is_empty(&self) -> bool { self.names.is_empty() }
+}
+
+impl Packet {
+    /// ChunkData packet for a loaded Chunk.
+    pub fn chunk(position: Vector2<u64>, chunk: &Chunk) -> Packet {
+        let mut data = Vec::new();
+        encode_chunk(chunk, Compression::Rle, &mut data).expect("writing to memory");
+        Packet::ChunkData { position, data }
+    }
+    /// Decode the Chunk of a ChunkData packet into a buffer.
+    pub fn read_chunk(&self, chunk: &mut Chunk) -> io::Result<Vector2<u64>> {
+        match *self {
+            Packet::ChunkData { position, ref data } => {
+                decode_chunk(&mut data.as_slice(), chunk)?;
+                Ok(position)
+            }
+            _ => Err(invalid_data("not a chunk packet")),
+        }
+    }
+    pub fn save(&self, writer: &mut dyn Write) -> io::Result<()> {
+        match *self {
+            Packet::Hello { version, ref name } => {
+                write_u8(writer, HELLO)?;
+                write_u16(writer, version)?;
+                write_string(writer, name)
+            }
+            Packet::Welcome { version, client, tick } => {
+                write_u8(writer, WELCOME)?;
+                write_u16(writer, version)?;
+                write_u32(writer, client)?;
+                write_u64(writer, tick)
+            }
+            Packet::Disconnect { ref reason } => {
+                write_u8(writer, DISCONNECT)?;
+                write_string(writer, reason)
+            }
+            Packet::Spectate { version, ref name } => {
+                write_u8(writer, SPECTATE)?;
+                write_u16(writer, version)?;
+                write_string(writer, name)
+            }
+            Packet::Challenge { ref nonce } => {
+                write_u8(writer, CHALLENGE)?;
+                write_u32(writer, nonce.len() as u32)?;
+                writer.write_all(nonce)
+            }
+            Packet::Authenticate { ref name, ref proof } => {
+                write_u8(writer, AUTHENTICATE)?;
+                write_string(writer, name)?;
+                write_u32(writer, proof.len() as u32)?;
+                writer.write_all(proof)
+            }
+            Packet::Batch { codec, length, ref data } => {
+                write_u8(writer, BATCH)?;
+                write_u8(writer, codec)?;
+                write_u32(writer, length)?;
+                write_u32(writer, data.len() as u32)?;
+                writer.write_all(data)
+            }
+            Packet::ChunkData { position, ref data } => {
+                write_u8(writer, CHUNK_DATA)?;
+                write_position(writer, position)?;
+                write_u32(writer, data.len() as u32)?;
+                writer.write_all(data)
+            }
+            Packet::ChunkUnload { position } => {
+                write_u8(writer, CHUNK_UNLOAD)?;
+                write_position(writer, position)
+            }
+            Packet::BlockDelta { position, ref blocks } => {
+                write_u8(writer, BLOCK_DELTA)?;
+                write_position(writer, position)?;
+                write_u32(writer, blocks.len() as u32)?;
+                for &(local, material) in blocks.iter() {
+                    writer.write_all(&local)?;
+                    write_u16(writer, material)?;
+                }
+                Ok(())
+            }
+            Packet::Snapshot(ref snapshot) => {
+                write_u8(writer, SNAPSHOT)?;
+                snapshot.save(writer)
+            }
+            Packet::Delta(ref delta) => {
+                write_u8(writer, DELTA)?;
+                delta.save(writer)
+            }
+            Packet::Ack { tick } => {
+                write_u8(writer, ACK)?;
+                write_u64(writer, tick)
+            }
+            Packet::Replication(ref delta) => {
+                write_u8(writer, REPLICATION)?;
+                delta.save(writer)
+            }
+            Packet::Input { sequence, ref command } => {
+                write_u8(writer, INPUT)?;
+                write_u64(writer, sequence)?;
+                write_command(writer, command)
+            }
+            Packet::InputAck { sequence } => {
+                write_u8(writer, INPUT_ACK)?;
+                write_u64(writer, sequence)
+            }
+            Packet::TickInput { peer, tick, ref commands } => {
+                write_u8(writer, TICK_INPUT)?;
+                write_u32(writer, peer)?;
+                write_u64(writer, tick)?;
+                if commands.len() > u16::MAX as usize {
+                    return Err(invalid_data("too many commands in a tick"));
+                }
+                write_u16(writer, commands.len() as u16)?;
+                for command in commands.iter() {
+                    write_command(writer, command)?;
+                }
+                Ok(())
+            }
+            Packet::StateHash { peer, tick, hash } => {
+                write_u8(writer, STATE_HASH)?;
+                write_u32(writer, peer)?;
+                write_u64(writer, tick)?;
+                write_u64(writer, hash)
+            }
+            Packet::Console { entity, ref text } => {
+                write_u8(writer, CONSOLE)?;
+                write_entity(writer, entity)?;
+                write_string(writer, text)
+            }
+            Packet::Chat { sequence, channel, ref from, ref text } => {
+                write_u8(writer, CHAT)?;
+                write_u64(writer, sequence)?;
+                write_channel(writer, channel)?;
+                write_string(writer, from)?;
+                write_string(writer, text)
+            }
+            Packet::ChatAck { sequence } => {
+                write_u8(writer, CHAT_ACK)?;
+                write_u64(writer, sequence)
+            }
+            Packet::AdminLogin { ref secret } => {
+                write_u8(writer, ADMIN_LOGIN)?;
+                write_string(writer, secret)
+            }
+            Packet::Admin { ref line } => {
+                write_u8(writer, ADMIN)?;
+                write_string(writer, line)
+            }
+            Packet::AdminReply { ok, ref text } => {
+                write_u8(writer, ADMIN_REPLY)?;
+                write_u8(writer, ok as u8)?;
+                write_string(writer, text)
+            }
+            Packet::Custom { kind, ref data } => {
+                write_u8(writer, CUSTOM)?;
+                write_u16(writer, kind)?;
+                write_u32(writer, data.len() as u32)?;
+                writer.write_all(data)
+            }
+        }
+    }
+    pub fn load(reader: &mut dyn Read) -> io::Result<Packet> {
+        Ok(match read_u8(reader)? {
+            HELLO => Packet::Hello { version: read_u16(reader)?, name: read_string(reader)? },
+            WELCOME => Packet::Welcome { version: read_u16(reader)?, client: read_u32(reader)?, tick: read_u64(reader)? },
+            DISCONNECT => Packet::Disconnect { reason: read_string(reader)? },
+            SPECTATE => Packet::Spectate { version: read_u16(reader)?, name: read_string(reader)? },
+            CHALLENGE => Packet::Challenge { nonce: read_data(reader)? },
+            AUTHENTICATE => Packet::Authenticate { name: read_string(reader)?, proof: read_data(reader)? },
+            BATCH => Packet::Batch { codec: read_u8(reader)?, length: read_u32(reader)?, data: read_data(reader)? },
+            CHUNK_DATA => Packet::ChunkData { position: read_position(reader)?, data: read_data(reader)? },
+            CHUNK_UNLOAD => Packet::ChunkUnload { position: read_position(reader)? },
+            BLOCK_DELTA => {
+                let position = read_position(reader)?;
+                let mut blocks = Vec::new();
+                for _ in 0..read_u32(reader)? {
+                    let mut local = [0; 3];
+                    reader.read_exact(&mut local)?;
+                    blocks.push((local, read_u16(reader)?));
+                }
+                Packet::BlockDelta { position, blocks }
+            }
+            SNAPSHOT => Packet::Snapshot(Snapshot::load(reader)?),
+            DELTA => Packet::Delta(SnapshotDelta::load(reader)?),
+            ACK => Packet::Ack { tick: read_u64(reader)? },
+            REPLICATION => Packet::Replication(ReplicationDelta::load(reader)?),
+            INPUT => Packet::Input { sequence: read_u64(reader)?, command: read_command(reader)? },
+            INPUT_ACK => Packet::InputAck { sequence: read_u64(reader)? },
+            TICK_INPUT => {
+                let (peer, tick) = (read_u32(reader)?, read_u64(reader)?);
+                let mut commands = Vec::new();
+                for _ in 0..read_u16(reader)? {
+                    commands.push(read_command(reader)?);
+                }
+                Packet::TickInput { peer, tick, commands }
+            }
+            STATE_HASH => Packet::StateHash { peer: read_u32(reader)?, tick: read_u64(reader)?, hash: read_u64(reader)? },
+            CONSOLE => Packet::Console { entity: read_entity(reader)?, text: read_string(reader)? },
+            CHAT => Packet::Chat { sequence: read_u64(reader)?, channel: read_channel(reader)?, from: read_string(reader)?, text: read_string(reader)? },
+            CHAT_ACK => Packet::ChatAck { sequence: read_u64(reader)? },
+            ADMIN_LOGIN => Packet::AdminLogin { secret: read_string(reader)? },
+            ADMIN => Packet::Admin { line: read_string(reader)? },
+            ADMIN_REPLY => Packet::AdminReply { ok: read_u8(reader)? != 0, text: read_string(reader)? },
+            CUSTOM => Packet::Custom { kind: read_u16(reader)?, data: read_data(reader)? },
+            _ => return Err(invalid_data("unknown packet")),
+        })
+    }
+    /// Encode as a single datagram.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.save(&mut bytes).expect("writing to memory");
+        bytes
+    }
+    /// Decode a single datagram, which must hold exactly one packet.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Packet> {
+        let mut reader = bytes;
+        let packet = Packet::load(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(invalid_data("trailing bytes after packet"));
+        }
+        Ok(packet)
+    }
+}
+
+/// Length prefixed bytes, bounded by the frame size
+fn read_data(reader: &mut dyn Read) -> io::Result<Vec<u8>> {
+    let length = read_u32(reader)?;
+    if length > MAX_FRAME_SIZE {
+        return Err(invalid_data("packet data is too large"));
+    }
+    let mut data = vec![0; length as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn write_position(writer: &mut dyn Write, position: Vector2<u64>) -> io::Result<()> {
+    write_u64(writer, position.x)?;
+    write_u64(writer, position.y)
+}
+
+fn read_position(reader: &mut dyn Read) -> io::Result<Vector2<u64>> {
+    let x = read_u64(reader)?;
+    Ok(Vector2::new(x, read_u64(reader)?))
+}
+
+fn write_command(writer: &mut dyn Write, command: &Command) -> io::Result<()> {
+    match *command {
+        Command::Upload { entity, address, ref words } => {
+            write_u8(writer, 0)?;
+            write_entity(writer, entity)?;
+            write_u16(writer, address)?;
+            write_u16(writer, words.len() as u16)?;
+            for &word in words.iter() {
+                write_u16(writer, word)?;
+            }
+            Ok(())
+        }
+        Command::Interrupt { entity, message } => {
+            write_u8(writer, 1)?;
+            write_entity(writer, entity)?;
+            write_u16(writer, message)
+        }
+        Command::Watch(entity) => {
+            write_u8(writer, 2)?;
+            write_entity(writer, entity)
+        }
+        Command::Unwatch(entity) => {
+            write_u8(writer, 3)?;
+            write_entity(writer, entity)
+        }
+        Command::Move { entity, position } => {
+            write_u8(writer, 4)?;
+            write_entity(writer, entity)?;
+            for &axis in [position.x, position.y, position.z].iter() {
+                write_u64(writer, axis.raw() as u64)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn read_command(reader: &mut dyn Read) -> io::Result<Command> {
+    Ok(match read_u8(reader)? {
+        0 => {
+            let entity = read_entity(reader)?;
+            let address = read_u16(reader)?;
+            let mut words = Vec::new();
+            for _ in 0..read_u16(reader)? {
+                words.push(read_u16(reader)?);
+            }
+            Command::Upload { entity, address, words }
+        }
+        1 => Command::Interrupt { entity: read_entity(reader)?, message: read_u16(reader)? },
+        2 => Command::Watch(read_entity(reader)?),
+        3 => Command::Unwatch(read_entity(reader)?),
+        4 => {
+            let entity = read_entity(reader)?;
+            let mut axes = [Fixed::ZERO; 3];
+            for axis in axes.iter_mut() {
+                *axis = Fixed::from_raw(read_u64(reader)? as i64);
+            }
+            Command::Move { entity, position: Position::new(axes[0], axes[1], axes[2]) }
+        }
+        _ => return Err(invalid_data("unknown command")),
+    })
+}
+
+fn write_channel(writer: &mut dyn Write, channel: Channel) -> io::Result<()> {
+    match channel {
+        Channel::Global => write_u8(writer, 0),
+        Channel::Faction(faction) => {
+            write_u8(writer, 1)?;
+            write_u16(writer, faction.id())
+        }
+        Channel::Drone(entity) => {
+            write_u8(writer, 2)?;
+            write_entity(writer, entity)
+        }
+    }
+}
+
+fn read_channel(reader: &mut dyn Read) -> io::Result<Channel> {
+    Ok(match read_u8(reader)? {
+        0 => Channel::Global,
+        1 => Channel::Faction(FactionId::new(read_u16(reader)?)),
+        2 => Channel::Drone(read_entity(reader)?),
+        _ => return Err(invalid_data("unknown chat channel")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Channel, Command, Packet};
+    use model::component::Position;
+    use model::entity::EntityID;
+    use model::faction::FactionId;
+    use model::material::MaterialId;
+    use model::world::{Block, Chunk, Vector2};
+    use net::replication::ReplicationDelta;
+    use net::snapshot::Snapshot;
+    use net::{read_frame, welcome, write_frame, PROTOCOL_VERSION};
+    use pool::Poolable;
+
+    #[test]
+    pub fn test_packet_roundtrip() {
+        let drone = EntityID::new(3, 7);
+        let mut chunk = Chunk::allocate();
+        chunk.set_block(1, 2, 3, Block::new(MaterialId::new(4)));
+        let packets = vec![
+            Packet::Hello { version: PROTOCOL_VERSION, name: "overmind".to_string() },
+            Packet::Welcome { version: PROTOCOL_VERSION, client: 9, tick: 1200 },
+            Packet::chunk(Vector2::new(2, 5), &chunk),
+            Packet::ChunkUnload { position: Vector2::new(2, 5) },
+            Packet::Snapshot(Snapshot { tick: 4, entities: Vec::new() }),
+            Packet::Ack { tick: 4 },
+            Packet::Input { sequence: 5, command: Command::Upload { entity: drone, address: 0x100, words: vec![0x8401, 0x8802] } },
+            Packet::Input { sequence: 6, command: Command::Watch(drone) },
+            Packet::InputAck { sequence: 6 },
+            Packet::TickInput { peer: 2, tick: 40, commands: vec![Command::Interrupt { entity: drone, message: 3 }, Command::Unwatch(drone), Command::Move { entity: drone, position: Position::from_f64(1.5, -2.0, 40.25) }] },
+            Packet::StateHash { peer: 2, tick: 40, hash: 0xDEAD_BEEF_0BAD_F00D },
+            Packet::Console { entity: drone, text: "hello".to_string() },
+            Packet::Admin { line: "pause 3:7".to_string() },
+            Packet::AdminReply { ok: false, text: "denied".to_string() },
+            Packet::Custom { kind: 2, data: vec![1, 2, 3] },
+            Packet::Disconnect { reason: "bye".to_string() },
+            Packet::Spectate { version: PROTOCOL_VERSION, name: "caster".to_string() },
+            Packet::Chat { sequence: 1, channel: Channel::Faction(FactionId::new(2)), from: "overmind".to_string(), text: "swarm".to_string() },
+            Packet::Chat { sequence: 2, channel: Channel::Drone(drone), from: String::new(), text: "halt".to_string() },
+            Packet::ChatAck { sequence: 2 },
+            Packet::Challenge { nonce: vec![7; 16] },
+            Packet::Authenticate { name: "overmind".to_string(), proof: b"overmind:0:00".to_vec() },
+            Packet::Batch { codec: 0, length: 3, data: vec![1, 2, 3] },
+            Packet::Replication(ReplicationDelta { tick: 9, baseline: Some(4), changed: Vec::new(), removed: vec![drone] }),
+            Packet::BlockDelta { position: Vector2::new(2, 5), blocks: vec![([1, 2, 3], 4), ([31, 0, 31], 0)] },
+        ];
+
+        // Framed on a stream, one after another
+        let mut stream = Vec::new();
+        for packet in packets.iter() {
+            write_frame(&mut stream, packet).unwrap();
+        }
+        let mut reader = stream.as_slice();
+        for packet in packets.iter() {
+            assert_eq!(read_frame(&mut reader).unwrap().as_ref(), Some(packet));
+        }
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+
+        // As datagrams, and the chunk survives the trip
+        let datagram = packets[2].to_bytes();
+        let mut received = Chunk::allocate();
+        assert_eq!(Packet::from_bytes(&datagram).unwrap().read_chunk(&mut received).unwrap(), Vector2::new(2, 5));
+        assert_eq!(received.get_block(1, 2, 3), Block::new(MaterialId::new(4)));
+        assert!(Packet::from_bytes(&datagram[..datagram.len() - 1]).is_err());
+        assert!(Packet::from_bytes(&[0xEE]).is_err());
+
+        // Mismatched versions are turned away
+        assert_eq!(welcome(&packets[0], 9, 1200), packets[1]);
+        let old = Packet::Hello { version: 0, name: "old".to_string() };
+        assert!(matches!(welcome(&old, 9, 1200), Packet::Disconnect { .. }));
+    }
+}
@@ -0,0 +1,223 @@
+///
+/// Prediction and Interpolation
+///
+/// Entities a client controls are moved locally as soon as input is given.
+/// Each input is numbered and kept, together with the state predicted after
+/// it, until the server acknowledges that sequence. The server's state at that
+/// point is then taken as truth and the inputs it has not seen yet are
+/// replayed on top of it.
+///
+/// Everything else is drawn a fixed delay in the past, between the two
+/// snapshots around that moment, so remote entities move smoothly however
+/// unevenly the snapshots arrive.
+///
+use math::Fixed;
+use model::component::{Position, Velocity};
+use model::entity::EntityID;
+use net::packet::Packet;
+use net::snapshot::{EntityState, Snapshot};
+use std::collections::VecDeque;
+
+/// Default interpolation delay in ticks
+pub const DEFAULT_DELAY: u64 = 2;
+
+///
+/// Server side Input Sequencing for one client
+///
+/// Inputs which arrive late or twice are dropped. Over datagrams a lost input
+/// is simply skipped, the next one supersedes it.
+///
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct InputSequence {
+    last: u64,
+}
+
+impl InputSequence {
+    pub fn new() -> InputSequence { InputSequence::default() }
+    pub fn last(&self) -> u64 { self.last }
+    /// True if the input is newer than any applied so far.
+    pub fn accept(&mut self, sequence: u64) -> bool {
+        if sequence > self.last {
+            self.last = sequence;
+            true
+        } else {
+            false
+        }
+    }
+    /// Acknowledgment of every input applied so far.
+    pub fn ack(&self) -> Packet { Packet::InputAck { sequence: self.last } }
+}
+
+///
+/// Client side Prediction for a locally controlled Entity
+///
+#[derive(Clone, Debug)]
+pub struct Prediction<I> {
+    state: EntityState,
+    next: u64,
+    acknowledged: u64,
+    /// Unacknowledged inputs and the state predicted after each
+    pending: VecDeque<(u64, I, EntityState)>,
+}
+
+impl<I: Clone> Prediction<I> {
+    pub fn new(state: EntityState) -> Prediction<I> {
+        Prediction { state, next: 1, acknowledged: 0, pending: VecDeque::new() }
+    }
+    pub fn entity(&self) -> EntityID { self.state.entity }
+    /// Predicted current state.
+    pub fn state(&self) -> &EntityState { &self.state }
+    pub fn acknowledged(&self) -> u64 { self.acknowledged }
+    /// Inputs the server has not acknowledged yet, oldest first.
+    pub fn pending(&self) -> impl Iterator<Item=(u64, &I)> + '_ {
+        self.pending.iter().map(|&(sequence, ref input, _)| (sequence, input))
+    }
+    ///
+    /// Number an input and apply it locally, returns its sequence.
+    ///
+    pub fn predict<F>(&mut self, input: I, step: F) -> u64 where F: Fn(&EntityState, &I) -> EntityState {
+        let sequence = self.next;
+        self.next += 1;
+        self.state = step(&self.state, &input);
+        self.pending.push_back((sequence, input, self.state));
+        sequence
+    }
+    ///
+    /// Rewind to the server's state after `sequence` and replay the newer
+    /// inputs, returns true if the prediction for that sequence was wrong.
+    ///
+    pub fn reconcile<F>(&mut self, sequence: u64, authoritative: EntityState, step: F) -> bool where F: Fn(&EntityState, &I) -> EntityState {
+        if sequence <= self.acknowledged {
+            return false;
+        }
+        self.acknowledged = sequence;
+        let mut mispredicted = true;
+        while self.pending.front().is_some_and(|&(pending, _, _)| pending <= sequence) {
+            if let Some((pending, _, predicted)) = self.pending.pop_front() {
+                if pending == sequence {
+                    mispredicted = predicted != authoritative;
+                }
+            }
+        }
+        self.state = authoritative;
+        for &mut (_, ref input, ref mut predicted) in self.pending.iter_mut() {
+            self.state = step(&self.state, input);
+            *predicted = self.state;
+        }
+        mispredicted
+    }
+}
+
+///
+/// Interpolation Buffer for Remote Entities
+///
+#[derive(Clone, Debug)]
+pub struct Interpolation {
+    delay: u64,
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl Interpolation {
+    pub fn new(delay: u64) -> Interpolation { Interpolation { delay, snapshots: VecDeque::new() } }
+    pub fn delay(&self) -> u64 { self.delay }
+    pub fn len(&self) -> usize { self.snapshots.len() }
+    pub fn is_empty(&self) -> bool { self.snapshots.is_empty() }
+    /// Buffer a received snapshot, dropping it if it is not newer than the last.
+    pub fn push(&mut self, snapshot: Snapshot) -> bool {
+        if self.snapshots.back().is_some_and(|last| last.tick >= snapshot.tick) {
+            return false;
+        }
+        self.snapshots.push_back(snapshot);
+        true
+    }
+    ///
+    /// Entity states at `time` (in ticks) less the delay. Entities are held at
+    /// their last state outside the buffered range and appear once they are
+    /// in the earlier of the two snapshots around that moment.
+    ///
+    pub fn sample(&mut self, time: Fixed) -> Option<Snapshot> {
+        let time = time - Fixed::from_int(self.delay as i64);
+        // Snapshots before the one at or before `time` are no longer needed
+        while self.snapshots.len() > 1 && Fixed::from_int(self.snapshots[1].tick as i64) <= time {
+            self.snapshots.pop_front();
+        }
+        let before = self.snapshots.front()?;
+        let after = match self.snapshots.get(1) {
+            Some(after) if Fixed::from_int(before.tick as i64) <= time => after,
+            _ => return Some(before.clone()),
+        };
+        let span = Fixed::from_int((after.tick - before.tick) as i64);
+        let fraction = (time - Fixed::from_int(before.tick as i64)) / span;
+        let entities = before.entities.iter().map(|state| match after.get(state.entity) {
+            Some(next) => EntityState {
+                entity: state.entity,
+                position: Position::from_vector(state.position.vector() + (next.position.vector() - state.position.vector()) * fraction),
+                velocity: Velocity::from_vector(state.velocity.vector() + (next.velocity.vector() - state.velocity.vector()) * fraction),
+            },
+            None => *state,
+        }).collect();
+        Some(Snapshot { tick: time.floor().max(0) as u64, entities })
+    }
+}
+
+impl Default for Interpolation {
+    fn default() -> Interpolation { Interpolation::new(DEFAULT_DELAY) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InputSequence, Interpolation, Prediction};
+    use math::{Fixed, Vec3};
+    use model::component::{Position, Velocity};
+    use model::entity::EntityID;
+    use net::packet::Packet;
+    use net::snapshot::{EntityState, Snapshot};
+
+    fn walk(state: &EntityState, step: &i64) -> EntityState {
+        let position = Position::from_vector(state.position.vector() + Vec3::new(Fixed::from_int(*step), Fixed::ZERO, Fixed::ZERO));
+        EntityState { position, ..*state }
+    }
+
+    fn at(entity: EntityID, x: f64) -> EntityState {
+        EntityState { entity, position: Position::from_f64(x, 0.0, 0.0), velocity: Velocity::default() }
+    }
+
+    #[test]
+    pub fn test_prediction_and_interpolation() {
+        let drone = EntityID::new(0, 1);
+        let mut prediction = Prediction::new(at(drone, 0.0));
+        let mut server = InputSequence::new();
+        let mut authoritative = at(drone, 0.0);
+        for step in [1, 2, 3].iter() {
+            let sequence = prediction.predict(*step, walk);
+            assert!(server.accept(sequence));
+        }
+        assert_eq!(prediction.state().position.x, Fixed::from_int(6));
+        assert!(!server.accept(2));
+
+        // The server applied the first two inputs exactly as predicted
+        authoritative = walk(&walk(&authoritative, &1), &2);
+        assert!(!prediction.reconcile(2, authoritative, walk));
+        assert_eq!(prediction.pending().map(|(sequence, _)| sequence).collect::<Vec<_>>(), vec![3]);
+        assert_eq!(prediction.state().position.x, Fixed::from_int(6));
+
+        // A blocked move is corrected and later inputs replayed on top
+        assert_eq!(server.ack(), Packet::InputAck { sequence: 3 });
+        prediction.predict(4, walk);
+        assert!(prediction.reconcile(3, authoritative, walk));
+        assert_eq!(prediction.state().position.x, Fixed::from_int(7));
+        assert!(!prediction.reconcile(2, at(drone, 0.0), walk));
+
+        // Remote entities are drawn between the snapshots around now - delay
+        let rock = EntityID::new(1, 1);
+        let mut interpolation = Interpolation::new(2);
+        assert!(interpolation.push(Snapshot { tick: 10, entities: vec![at(rock, 0.0)] }));
+        assert!(interpolation.push(Snapshot { tick: 14, entities: vec![at(rock, 8.0)] }));
+        assert!(!interpolation.push(Snapshot { tick: 12, entities: Vec::new() }));
+        assert_eq!(interpolation.sample(Fixed::from_int(11)).unwrap().entities[0].position.x, Fixed::ZERO);
+        let sample = interpolation.sample(Fixed::from_int(14)).unwrap();
+        assert_eq!((sample.tick, sample.entities[0].position.x), (12, Fixed::from_int(4)));
+        assert_eq!(interpolation.sample(Fixed::from_int(20)).unwrap().entities[0].position.x, Fixed::from_int(8));
+        assert_eq!(interpolation.len(), 1);
+    }
+}
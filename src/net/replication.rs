@@ -0,0 +1,448 @@
+///
+/// Replication Descriptors
+///
+/// Where a Snapshot carries the Position and Velocity of every entity at full
+/// precision, a ReplicationRegistry describes what is synced per component:
+/// which fields, the quantum each is rounded to, and how many ticks apart
+/// changes are sent. States are captured already quantized, so a drift
+/// smaller than a field's quantum is never sent, and a ReplicationDelta
+/// against the client's acknowledged state holds only the fields which
+/// changed, each as its change in quanta. Entity ids, masks and changes are
+/// varints, so a unit nudged one quantum costs a handful of bytes.
+///
+/// A component whose interval hasn't come round keeps the value the client
+/// has, unless the client has never seen it on that entity. Each client's
+/// Replicator remembers what it sent for every tick until it is
+/// acknowledged, and encodes the next delta against the latest acknowledged
+/// state, or against nothing until the first Ack.
+///
+use codec::{invalid_data, read_u64, read_u8, read_varint, write_u64, write_u8, write_varint};
+use math::Fixed;
+use model::entity::{EntityID, EntityManager};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Read, Write};
+
+/// Most components or fields of one component a registry can describe, one bit each in a mask
+pub const MAX_COMPONENTS: usize = 64;
+/// Sent states a Replicator keeps waiting for an Ack
+pub const STATES_KEPT: usize = 32;
+
+///
+/// Replicated field and the quantum its value is rounded to
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Field {
+    pub name: String,
+    pub quantum: Fixed,
+}
+
+impl Field {
+    /// Field sent at full precision.
+    pub fn exact(name: &str) -> Field { Field { name: name.to_string(), quantum: Fixed::EPSILON } }
+    /// Field rounded to a multiple of `quantum`.
+    pub fn quantized(name: &str, quantum: Fixed) -> Field { Field { name: name.to_string(), quantum: quantum.max(Fixed::EPSILON) } }
+    fn quantize(&self, value: Fixed) -> i64 {
+        let (raw, quantum) = (value.raw() as i128, self.quantum.raw() as i128);
+        ((raw + quantum / 2).div_euclid(quantum)) as i64
+    }
+    fn dequantize(&self, quanta: i64) -> Fixed { Fixed::from_raw(quanta.saturating_mul(self.quantum.raw())) }
+}
+
+/// Every entity with the component and its field values
+type Collect = Box<dyn Fn(&EntityManager) -> Vec<(EntityID, Vec<Fixed>)>>;
+/// Set the component from field values, or remove it given None
+type Store = Box<dyn Fn(&mut EntityManager, EntityID, Option<&[Fixed]>)>;
+
+///
+/// How one component is replicated
+///
+pub struct ComponentReplication {
+    pub name: String,
+    pub fields: Vec<Field>,
+    /// Ticks between sending changes, 1 for every tick
+    pub interval: u64,
+    collect: Collect,
+    store: Store,
+}
+
+///
+/// Replicated components in registration order
+///
+#[derive(Default)]
+pub struct ReplicationRegistry {
+    components: Vec<ComponentReplication>,
+}
+
+impl ReplicationRegistry {
+    pub fn new() -> ReplicationRegistry { ReplicationRegistry::default() }
+    pub fn len(&self) -> usize { self.components.len() }
+    pub fn is_empty(&self) -> bool { self.components.is_empty() }
+    pub fn get(&self, index: usize) -> Option<&ComponentReplication> { self.components.get(index) }
+    pub fn index(&self, name: &str) -> Option<usize> { self.components.iter().position(|component| component.name == name) }
+    ///
+    /// Replicate component C through functions reading its fields and
+    /// rebuilding it from them, returning its index. Both ends must register
+    /// the same components in the same order.
+    ///
+    pub fn register<C: Clone + 'static>(&mut self, name: &str, fields: Vec<Field>, interval: u64, read: fn(&C) -> Vec<Fixed>, write: fn(&[Fixed]) -> C) -> usize {
+        assert!(self.components.len() < MAX_COMPONENTS && fields.len() <= MAX_COMPONENTS, "too many replicated components or fields");
+        let collect: Collect = Box::new(move |entities| entities.iter::<C>().map(|(entity, component)| (entity, read(component))).collect());
+        let store: Store = Box::new(move |entities, entity, values| {
+            match values {
+                Some(values) => entities.add_component(entity, write(values)),
+                None => entities.remove_component::<C>(entity),
+            };
+        });
+        self.components.push(ComponentReplication { name: name.to_string(), fields, interval: interval.max(1), collect, store });
+        self.components.len() - 1
+    }
+    /// Quantized state of every entity with a replicated component.
+    pub fn capture(&self, entities: &EntityManager, tick: u64) -> ReplicatedState {
+        let mut state = ReplicatedState { tick, entities: BTreeMap::new() };
+        for (index, component) in self.components.iter().enumerate() {
+            for (entity, values) in (component.collect)(entities) {
+                let quantized = component.fields.iter().zip(values.iter()).map(|(field, &value)| field.quantize(value)).collect();
+                state.entities.entry(entity).or_insert_with(|| vec![None; self.components.len()])[index] = Some(quantized);
+            }
+        }
+        state
+    }
+    /// Field values of a component of an entity in a state.
+    pub fn values(&self, state: &ReplicatedState, entity: EntityID, component: usize) -> Option<Vec<Fixed>> {
+        let quanta = state.entities.get(&entity)?.get(component)?.as_ref()?;
+        Some(self.components[component].fields.iter().zip(quanta.iter()).map(|(field, &quanta)| field.dequantize(quanta)).collect())
+    }
+    ///
+    /// Encode `current` against the state the client holds, returning the
+    /// delta and the state the client will hold once it applies it.
+    ///
+    pub fn encode(&self, baseline: Option<&ReplicatedState>, current: &ReplicatedState) -> (ReplicationDelta, ReplicatedState) {
+        let mut sent = current.clone();
+        let empty = BTreeMap::new();
+        let before = baseline.map_or(&empty, |baseline| &baseline.entities);
+        for (index, component) in self.components.iter().enumerate() {
+            if current.tick.is_multiple_of(component.interval) {
+                continue;
+            }
+            for (entity, components) in sent.entities.iter_mut() {
+                if let Some(old) = before.get(entity).and_then(|old| old[index].as_ref()) {
+                    components[index] = Some(old.clone());
+                }
+            }
+        }
+        let mut changed = Vec::new();
+        for (&entity, components) in sent.entities.iter() {
+            let old = before.get(&entity);
+            let mut change = EntityChange { entity, changed: Vec::new(), dropped: Vec::new() };
+            for (index, now) in components.iter().enumerate() {
+                let was = old.and_then(|old| old[index].as_ref());
+                match (was, now.as_ref()) {
+                    (Some(was), Some(now)) => {
+                        let fields: Vec<(u8, i64)> = was.iter().zip(now.iter()).enumerate()
+                            .filter(|&(_, (was, now))| was != now)
+                            .map(|(field, (was, now))| (field as u8, now.wrapping_sub(*was)))
+                            .collect();
+                        if !fields.is_empty() {
+                            change.changed.push(ComponentChange { component: index as u8, fields });
+                        }
+                    }
+                    (None, Some(now)) => {
+                        let fields = now.iter().enumerate().map(|(field, &value)| (field as u8, value)).collect();
+                        change.changed.push(ComponentChange { component: index as u8, fields });
+                    }
+                    (Some(_), None) => change.dropped.push(index as u8),
+                    (None, None) => {}
+                }
+            }
+            if !change.changed.is_empty() || !change.dropped.is_empty() {
+                changed.push(change);
+            }
+        }
+        let removed = before.keys().filter(|entity| !sent.entities.contains_key(entity)).cloned().collect();
+        let delta = ReplicationDelta { tick: current.tick, baseline: baseline.map(|baseline| baseline.tick), changed, removed };
+        (delta, sent)
+    }
+    ///
+    /// Rebuild the state a delta was encoded to from the baseline it was
+    /// encoded against, None for a delta against nothing.
+    ///
+    pub fn apply(&self, delta: &ReplicationDelta, baseline: Option<&ReplicatedState>) -> io::Result<ReplicatedState> {
+        if delta.baseline != baseline.map(|baseline| baseline.tick) {
+            return Err(invalid_data("replication delta applied to the wrong baseline"));
+        }
+        let mut entities = baseline.map_or_else(BTreeMap::new, |baseline| baseline.entities.clone());
+        for entity in delta.removed.iter() {
+            entities.remove(entity);
+        }
+        for change in delta.changed.iter() {
+            let components = entities.entry(change.entity).or_insert_with(|| vec![None; self.components.len()]);
+            for &dropped in change.dropped.iter() {
+                *components.get_mut(dropped as usize).ok_or_else(|| invalid_data("unknown replicated component"))? = None;
+            }
+            for component in change.changed.iter() {
+                let index = component.component as usize;
+                let fields = self.components.get(index).ok_or_else(|| invalid_data("unknown replicated component"))?.fields.len();
+                let new = components[index].is_none();
+                let values = components[index].get_or_insert_with(|| vec![0; fields]);
+                if new && component.fields.len() != fields {
+                    return Err(invalid_data("partial replication of a new component"));
+                }
+                for &(field, quanta) in component.fields.iter() {
+                    let value = values.get_mut(field as usize).ok_or_else(|| invalid_data("unknown replicated field"))?;
+                    *value = if new { quanta } else { value.wrapping_add(quanta) };
+                }
+            }
+        }
+        Ok(ReplicatedState { tick: delta.tick, entities })
+    }
+    ///
+    /// Make the entities match a state, client side: replicated components
+    /// are set or removed as the state has them. Entities missing from the
+    /// state are left for the caller to destroy.
+    ///
+    pub fn store(&self, state: &ReplicatedState, entities: &mut EntityManager) {
+        for (&entity, components) in state.entities.iter() {
+            if !entities.is_alive(entity) {
+                continue;
+            }
+            for (index, component) in self.components.iter().enumerate() {
+                let values = components[index].as_ref().map(|_| self.values(state, entity, index).unwrap());
+                (component.store)(entities, entity, values.as_deref());
+            }
+        }
+    }
+}
+
+///
+/// Quantized field values of every replicated entity at one tick, None
+/// where an entity lacks a component
+///
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct ReplicatedState {
+    pub tick: u64,
+    pub entities: BTreeMap<EntityID, Vec<Option<Vec<i64>>>>,
+}
+
+///
+/// Changed fields of one component as changes in quanta, or every field's
+/// value in quanta for a component new to the entity
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ComponentChange {
+    pub component: u8,
+    pub fields: Vec<(u8, i64)>,
+}
+
+///
+/// What changed on one entity
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EntityChange {
+    pub entity: EntityID,
+    pub changed: Vec<ComponentChange>,
+    /// Components the entity no longer has
+    pub dropped: Vec<u8>,
+}
+
+///
+/// ReplicatedState encoded against an earlier one
+///
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct ReplicationDelta {
+    pub tick: u64,
+    /// Tick of the state this applies to, None for one against nothing
+    pub baseline: Option<u64>,
+    /// In entity order
+    pub changed: Vec<EntityChange>,
+    pub removed: Vec<EntityID>,
+}
+
+fn zigzag(value: i64) -> u64 { ((value << 1) ^ (value >> 63)) as u64 }
+
+fn unzigzag(value: u64) -> i64 { ((value >> 1) as i64) ^ -((value & 1) as i64) }
+
+fn mask(bits: impl Iterator<Item=u8>) -> u64 { bits.fold(0, |mask, bit| mask | 1 << bit) }
+
+fn bits(mask: u64) -> impl Iterator<Item=u8> { (0..64).filter(move |bit| mask & (1 << bit) != 0) }
+
+/// Entity ids in ascending order as slot steps and suffixes
+fn write_entities(writer: &mut dyn Write, entities: &mut dyn Iterator<Item=EntityID>, count: usize) -> io::Result<()> {
+    write_varint(writer, count as u64)?;
+    let mut slot = 0;
+    for entity in entities {
+        write_varint(writer, (entity.slot() - slot) as u64)?;
+        write_varint(writer, entity.suffix() as u64)?;
+        slot = entity.slot();
+    }
+    Ok(())
+}
+
+fn read_entity(reader: &mut dyn Read, slot: &mut usize) -> io::Result<EntityID> {
+    *slot = slot.checked_add(read_varint(reader)? as usize).ok_or_else(|| invalid_data("entity slot overflows"))?;
+    Ok(EntityID::new(*slot, read_varint(reader)? as usize))
+}
+
+impl ReplicationDelta {
+    pub fn save(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write_u64(writer, self.tick)?;
+        match self.baseline {
+            Some(baseline) => {
+                write_u8(writer, 1)?;
+                write_u64(writer, baseline)?;
+            }
+            None => write_u8(writer, 0)?,
+        }
+        write_entities(writer, &mut self.changed.iter().map(|change| change.entity), self.changed.len())?;
+        for change in self.changed.iter() {
+            write_varint(writer, mask(change.changed.iter().map(|component| component.component)))?;
+            for component in change.changed.iter() {
+                write_varint(writer, mask(component.fields.iter().map(|&(field, _)| field)))?;
+                for &(_, quanta) in component.fields.iter() {
+                    write_varint(writer, zigzag(quanta))?;
+                }
+            }
+            write_varint(writer, mask(change.dropped.iter().cloned()))?;
+        }
+        write_entities(writer, &mut self.removed.iter().cloned(), self.removed.len())
+    }
+    pub fn load(reader: &mut dyn Read) -> io::Result<ReplicationDelta> {
+        let tick = read_u64(reader)?;
+        let baseline = match read_u8(reader)? {
+            0 => None,
+            _ => Some(read_u64(reader)?),
+        };
+        let mut slot = 0;
+        let mut entities = Vec::new();
+        for _ in 0..read_varint(reader)? {
+            entities.push(read_entity(reader, &mut slot)?);
+        }
+        let mut changed = Vec::new();
+        for entity in entities {
+            let mut components = Vec::new();
+            for component in bits(read_varint(reader)?) {
+                let mut fields = Vec::new();
+                for field in bits(read_varint(reader)?) {
+                    fields.push((field, unzigzag(read_varint(reader)?)));
+                }
+                components.push(ComponentChange { component, fields });
+            }
+            changed.push(EntityChange { entity, changed: components, dropped: bits(read_varint(reader)?).collect() });
+        }
+        let mut slot = 0;
+        let mut removed = Vec::new();
+        for _ in 0..read_varint(reader)? {
+            removed.push(read_entity(reader, &mut slot)?);
+        }
+        if changed.windows(2).any(|pair| pair[0].entity >= pair[1].entity) || removed.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(invalid_data("replicated entities out of order"));
+        }
+        Ok(ReplicationDelta { tick, baseline, changed, removed })
+    }
+}
+
+///
+/// Server side replication to one client
+///
+#[derive(Clone, Default, Debug)]
+pub struct Replicator {
+    /// States sent and not yet superseded by an Ack, oldest first
+    sent: VecDeque<ReplicatedState>,
+    acknowledged: Option<ReplicatedState>,
+}
+
+impl Replicator {
+    pub fn new() -> Replicator { Replicator::default() }
+    /// Tick of the state the client last acknowledged.
+    pub fn acknowledged(&self) -> Option<u64> { self.acknowledged.as_ref().map(|state| state.tick) }
+    /// Delta bringing the client to `current`, against what it acknowledged.
+    pub fn update(&mut self, registry: &ReplicationRegistry, current: &ReplicatedState) -> ReplicationDelta {
+        let (delta, sent) = registry.encode(self.acknowledged.as_ref(), current);
+        self.sent.push_back(sent);
+        while self.sent.len() > STATES_KEPT {
+            self.sent.pop_front();
+        }
+        delta
+    }
+    /// The client holds the state of this tick.
+    pub fn acknowledge(&mut self, tick: u64) {
+        while let Some(state) = self.sent.pop_front() {
+            if state.tick == tick {
+                self.acknowledged = Some(state);
+                return;
+            }
+            if state.tick > tick {
+                self.sent.push_front(state);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Field, ReplicationDelta, ReplicationRegistry, Replicator};
+    use math::Fixed;
+    use model::component::{Position, Velocity};
+    use model::entity::EntityManager;
+
+    #[test]
+    pub fn test_replication() {
+        let mut registry = ReplicationRegistry::new();
+        let quarter = Fixed::from_f64(0.25);
+        let fields = vec![Field::quantized("x", quarter), Field::quantized("y", quarter), Field::quantized("z", quarter)];
+        registry.register::<Position>("position", fields, 1, |p| vec![p.x, p.y, p.z], |v| Position::new(v[0], v[1], v[2]));
+        let fields = vec![Field::exact("x"), Field::exact("y"), Field::exact("z")];
+        let velocity = registry.register::<Velocity>("velocity", fields, 4, |v| vec![v.x, v.y, v.z], |v| Velocity::new(v[0], v[1], v[2]));
+
+        let mut server = EntityManager::new();
+        let drones: Vec<_> = (0..1000).map(|i| {
+            let drone = server.create_entity();
+            server.add_component(drone, Position::from_f64(i as f64, 1.0, 2.0));
+            server.add_component(drone, Velocity::from_f64(1.0, 0.0, 0.0));
+            drone
+        }).collect();
+        let mut client = EntityManager::new();
+        for _ in 0..1000 {
+            client.create_entity();
+        }
+
+        // The first update carries everything, the client applies and acknowledges it
+        let mut replicator = Replicator::new();
+        let first = replicator.update(&registry, &registry.capture(&server, 1));
+        let state = registry.apply(&first, None).unwrap();
+        registry.store(&state, &mut client);
+        assert_eq!(client.get_component::<Position>(drones[7]), Some(&Position::from_f64(7.0, 1.0, 2.0)));
+        replicator.acknowledge(1);
+
+        // A drift under a quantum is not sent, a move is sent as the one field it changed
+        server.get_component_mut::<Position>(drones[3]).unwrap().y = Fixed::from_f64(1.1);
+        server.get_component_mut::<Position>(drones[5]).unwrap().x = Fixed::from_f64(6.0);
+        server.get_component_mut::<Velocity>(drones[5]).unwrap().z = Fixed::ONE;
+        server.remove_component::<Velocity>(drones[9]);
+        let delta = replicator.update(&registry, &registry.capture(&server, 2));
+        assert_eq!(delta.changed.len(), 1);
+        assert_eq!(delta.changed[0].changed[0].fields, vec![(0, 4)]);
+        let mut bytes = Vec::new();
+        delta.save(&mut bytes).unwrap();
+        assert!(bytes.len() < 32);
+        let mut full = Vec::new();
+        first.save(&mut full).unwrap();
+        assert!(full.len() < 1000 * 16);
+        assert_eq!(ReplicationDelta::load(&mut bytes.as_slice()).unwrap(), delta);
+
+        // Velocity changes wait for its interval, against the same acknowledged baseline
+        let delta = replicator.update(&registry, &registry.capture(&server, 4));
+        let state = registry.apply(&delta, Some(&state)).unwrap();
+        registry.store(&state, &mut client);
+        assert_eq!(client.get_component::<Velocity>(drones[5]).unwrap().z, Fixed::ONE);
+        assert!(client.get_component::<Velocity>(drones[9]).is_none());
+        assert_eq!(registry.values(&state, drones[5], velocity), Some(vec![Fixed::ONE, Fixed::ZERO, Fixed::ONE]));
+        assert!(registry.apply(&delta, None).is_err());
+
+        // Destroyed entities are listed as removed
+        replicator.acknowledge(4);
+        server.destroy_entity(drones[0]);
+        let delta = replicator.update(&registry, &registry.capture(&server, 5));
+        assert_eq!((delta.baseline, delta.removed.clone()), (Some(4), vec![drones[0]]));
+    }
+}
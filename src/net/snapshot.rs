@@ -0,0 +1,298 @@
+///
+/// Entity Snapshots
+///
+/// A Snapshot is the replicated state of every entity with a Position at one
+/// tick, in entity order. Once a client acknowledges a snapshot the server
+/// sends later ones as a SnapshotDelta against it: only the fields which
+/// changed, plus the entities which went away.
+///
+use codec::{invalid_data, read_u32, read_u64, read_u8, write_u32, write_u64, write_u8};
+use math::Fixed;
+use model::component::{Position, Velocity};
+use model::entity::{EntityID, EntityManager};
+use std::io::{self, Read, Write};
+
+/// Fields of an EntityState in delta order
+const FIELDS: usize = 6;
+
+///
+/// Replicated State of one Entity
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct EntityState {
+    pub entity: EntityID,
+    pub position: Position,
+    pub velocity: Velocity,
+}
+
+impl EntityState {
+    fn fields(&self) -> [Fixed; FIELDS] {
+        let (position, velocity) = (self.position, self.velocity);
+        [position.x, position.y, position.z, velocity.x, velocity.y, velocity.z]
+    }
+    fn from_fields(entity: EntityID, fields: &[Fixed; FIELDS]) -> EntityState {
+        EntityState {
+            entity,
+            position: Position::new(fields[0], fields[1], fields[2]),
+            velocity: Velocity::new(fields[3], fields[4], fields[5]),
+        }
+    }
+    pub fn save(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write_entity(writer, self.entity)?;
+        for field in self.fields().iter() {
+            write_fixed(writer, *field)?;
+        }
+        Ok(())
+    }
+    pub fn load(reader: &mut dyn Read) -> io::Result<EntityState> {
+        let entity = read_entity(reader)?;
+        let mut fields = [Fixed::ZERO; FIELDS];
+        for field in fields.iter_mut() {
+            *field = read_fixed(reader)?;
+        }
+        Ok(EntityState::from_fields(entity, &fields))
+    }
+}
+
+///
+/// State of every replicated Entity at one tick
+///
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct Snapshot {
+    pub tick: u64,
+    /// Sorted by entity
+    pub entities: Vec<EntityState>,
+}
+
+impl Snapshot {
+    /// Capture every entity with a Position.
+    pub fn capture(entities: &EntityManager, tick: u64) -> Snapshot {
+        let mut states: Vec<EntityState> = entities.iter::<Position>().map(|(entity, &position)| EntityState {
+            entity,
+            position,
+            velocity: entities.get_component::<Velocity>(entity).cloned().unwrap_or_default(),
+        }).collect();
+        states.sort_by_key(|state| state.entity);
+        Snapshot { tick, entities: states }
+    }
+    pub fn get(&self, entity: EntityID) -> Option<&EntityState> {
+        self.entities.binary_search_by_key(&entity, |state| state.entity).ok().map(|index| &self.entities[index])
+    }
+    pub fn save(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write_u64(writer, self.tick)?;
+        write_u32(writer, self.entities.len() as u32)?;
+        for state in self.entities.iter() {
+            state.save(writer)?;
+        }
+        Ok(())
+    }
+    pub fn load(reader: &mut dyn Read) -> io::Result<Snapshot> {
+        let tick = read_u64(reader)?;
+        let count = read_u32(reader)?;
+        let mut entities = Vec::new();
+        for _ in 0..count {
+            entities.push(EntityState::load(reader)?);
+        }
+        if entities.windows(2).any(|pair| pair[0].entity >= pair[1].entity) {
+            return Err(invalid_data("snapshot entities out of order"));
+        }
+        Ok(Snapshot { tick, entities })
+    }
+}
+
+///
+/// Changed Fields of one Entity, None where unchanged
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct EntityDelta {
+    pub entity: EntityID,
+    pub fields: [Option<Fixed>; FIELDS],
+}
+
+///
+/// Snapshot encoded against an earlier one
+///
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct SnapshotDelta {
+    pub tick: u64,
+    /// Tick of the snapshot this applies to
+    pub baseline: u64,
+    /// New or changed entities in entity order, new ones carry every field
+    pub changed: Vec<EntityDelta>,
+    pub removed: Vec<EntityID>,
+}
+
+impl SnapshotDelta {
+    ///
+    /// Encode `current` as the changes from `baseline`.
+    ///
+    pub fn between(baseline: &Snapshot, current: &Snapshot) -> SnapshotDelta {
+        let mut changed = Vec::new();
+        for state in current.entities.iter() {
+            let now = state.fields();
+            let fields = match baseline.get(state.entity) {
+                Some(before) => {
+                    let before = before.fields();
+                    let mut fields = [None; FIELDS];
+                    for (field, (old, new)) in fields.iter_mut().zip(before.iter().zip(now.iter())) {
+                        if old != new {
+                            *field = Some(*new);
+                        }
+                    }
+                    fields
+                }
+                None => {
+                    let mut fields = [None; FIELDS];
+                    for (field, value) in fields.iter_mut().zip(now.iter()) {
+                        *field = Some(*value);
+                    }
+                    fields
+                }
+            };
+            if fields.iter().any(Option::is_some) {
+                changed.push(EntityDelta { entity: state.entity, fields });
+            }
+        }
+        let removed = baseline.entities.iter()
+            .map(|state| state.entity)
+            .filter(|&entity| current.get(entity).is_none())
+            .collect();
+        SnapshotDelta { tick: current.tick, baseline: baseline.tick, changed, removed }
+    }
+    ///
+    /// Rebuild the full snapshot from the baseline it was encoded against.
+    ///
+    pub fn apply(&self, baseline: &Snapshot) -> io::Result<Snapshot> {
+        if baseline.tick != self.baseline {
+            return Err(invalid_data("delta applied to the wrong baseline"));
+        }
+        let mut entities: Vec<EntityState> = baseline.entities.iter()
+            .filter(|state| !self.removed.contains(&state.entity))
+            .cloned()
+            .collect();
+        for delta in self.changed.iter() {
+            match entities.binary_search_by_key(&delta.entity, |state| state.entity) {
+                Ok(index) => {
+                    let mut fields = entities[index].fields();
+                    for (field, change) in fields.iter_mut().zip(delta.fields.iter()) {
+                        if let Some(value) = *change {
+                            *field = value;
+                        }
+                    }
+                    entities[index] = EntityState::from_fields(delta.entity, &fields);
+                }
+                Err(index) => {
+                    if delta.fields.iter().any(Option::is_none) {
+                        return Err(invalid_data("partial delta for an unknown entity"));
+                    }
+                    let mut fields = [Fixed::ZERO; FIELDS];
+                    for (field, change) in fields.iter_mut().zip(delta.fields.iter()) {
+                        *field = change.unwrap_or_default();
+                    }
+                    entities.insert(index, EntityState::from_fields(delta.entity, &fields));
+                }
+            }
+        }
+        Ok(Snapshot { tick: self.tick, entities })
+    }
+    pub fn save(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write_u64(writer, self.tick)?;
+        write_u64(writer, self.baseline)?;
+        write_u32(writer, self.changed.len() as u32)?;
+        for delta in self.changed.iter() {
+            write_entity(writer, delta.entity)?;
+            let mask = delta.fields.iter().enumerate()
+                .filter(|&(_, field)| field.is_some())
+                .fold(0u8, |mask, (bit, _)| mask | 1 << bit);
+            write_u8(writer, mask)?;
+            for value in delta.fields.iter().flatten() {
+                write_fixed(writer, *value)?;
+            }
+        }
+        write_u32(writer, self.removed.len() as u32)?;
+        for &entity in self.removed.iter() {
+            write_entity(writer, entity)?;
+        }
+        Ok(())
+    }
+    pub fn load(reader: &mut dyn Read) -> io::Result<SnapshotDelta> {
+        let tick = read_u64(reader)?;
+        let baseline = read_u64(reader)?;
+        let mut changed = Vec::new();
+        for _ in 0..read_u32(reader)? {
+            let entity = read_entity(reader)?;
+            let mask = read_u8(reader)?;
+            let mut fields = [None; FIELDS];
+            for (bit, field) in fields.iter_mut().enumerate() {
+                if mask & (1 << bit) != 0 {
+                    *field = Some(read_fixed(reader)?);
+                }
+            }
+            changed.push(EntityDelta { entity, fields });
+        }
+        let mut removed = Vec::new();
+        for _ in 0..read_u32(reader)? {
+            removed.push(read_entity(reader)?);
+        }
+        Ok(SnapshotDelta { tick, baseline, changed, removed })
+    }
+}
+
+pub fn write_entity(writer: &mut dyn Write, entity: EntityID) -> io::Result<()> {
+    write_u32(writer, entity.slot() as u32)?;
+    write_u32(writer, entity.suffix() as u32)
+}
+
+pub fn read_entity(reader: &mut dyn Read) -> io::Result<EntityID> {
+    let slot = read_u32(reader)? as usize;
+    Ok(EntityID::new(slot, read_u32(reader)? as usize))
+}
+
+fn write_fixed(writer: &mut dyn Write, value: Fixed) -> io::Result<()> { write_u64(writer, value.raw() as u64) }
+
+fn read_fixed(reader: &mut dyn Read) -> io::Result<Fixed> { Ok(Fixed::from_raw(read_u64(reader)? as i64)) }
+
+#[cfg(test)]
+mod tests {
+    use super::{Snapshot, SnapshotDelta};
+    use math::Fixed;
+    use model::component::{Position, Velocity};
+    use model::entity::EntityManager;
+
+    #[test]
+    pub fn test_snapshot_delta() {
+        let mut entities = EntityManager::new();
+        let drone = entities.create_entity();
+        let rock = entities.create_entity();
+        let crate_ = entities.create_entity();
+        entities.add_component(drone, Position::from_f64(1.0, 2.0, 3.0));
+        entities.add_component(drone, Velocity::from_f64(1.0, 0.0, 0.0));
+        entities.add_component(rock, Position::from_f64(5.0, 0.0, 5.0));
+        entities.add_component(crate_, Position::from_f64(8.0, 1.0, 8.0));
+        let baseline = Snapshot::capture(&entities, 10);
+
+        entities.get_component_mut::<Position>(drone).unwrap().x = Fixed::from_int(2);
+        entities.destroy_entity(crate_);
+        let spark = entities.create_entity();
+        entities.add_component(spark, Position::from_f64(0.5, 0.5, 0.5));
+        let current = Snapshot::capture(&entities, 12);
+
+        // Only the moved field, the new entity and the removal are sent
+        let delta = SnapshotDelta::between(&baseline, &current);
+        assert_eq!(delta.changed.len(), 2);
+        assert_eq!(delta.changed[0].fields, [Some(Fixed::from_int(2)), None, None, None, None, None]);
+        assert_eq!(delta.removed, vec![crate_]);
+
+        let mut bytes = Vec::new();
+        delta.save(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 8 + 8 + 4 + (8 + 1 + 8) + (8 + 1 + 6 * 8) + 4 + 8);
+        let loaded = SnapshotDelta::load(&mut bytes.as_slice()).unwrap();
+        assert_eq!(loaded, delta);
+        assert_eq!(loaded.apply(&baseline).unwrap(), current);
+        assert!(loaded.apply(&current).is_err());
+
+        let mut bytes = Vec::new();
+        current.save(&mut bytes).unwrap();
+        assert_eq!(Snapshot::load(&mut bytes.as_slice()).unwrap(), current);
+    }
+}
@@ -0,0 +1,216 @@
+///
+/// Spectators
+///
+/// Observers of a hive battle open with Spectate instead of Hello and are
+/// sent the same interest managed Chunks and snapshots as players, but can
+/// never act on the Simulation: the only packets taken from them are Watch
+/// and Unwatch inputs to move their view and Acks of the snapshots they hold.
+/// Any other input disconnects them.
+///
+/// The server caps how many spectators it admits and how many bytes it
+/// streams to them, each spectator and all together. Every tick tops up a
+/// spectator's allowance by its share, up to BURST_TICKS worth; a spectator
+/// in debt from a large update, such as the Chunks of a new area, is skipped
+/// until the allowance is back above zero, which only lowers its snapshot
+/// rate since deltas are made against what it last acknowledged. The shared
+/// allowance is spent in turn, starting one spectator further along each
+/// tick, so nobody is starved for long.
+///
+use model::world::World;
+use net::interest::Interest;
+use net::packet::{Command, Packet};
+use net::snapshot::Snapshot;
+use net::PROTOCOL_VERSION;
+use std::collections::BTreeMap;
+
+/// Default spectators admitted
+pub const DEFAULT_MAX_SPECTATORS: usize = 64;
+/// Default bytes streamed to one spectator per tick
+pub const DEFAULT_SPECTATOR_BYTES: usize = 8 << 10;
+/// Default bytes streamed to every spectator together per tick
+pub const DEFAULT_TOTAL_BYTES: usize = 256 << 10;
+/// Ticks of allowance a spectator can save up
+pub const BURST_TICKS: usize = 4;
+
+///
+/// Spectator Limits
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SpectatorConfig {
+    pub max_spectators: usize,
+    /// Bytes per tick for each spectator
+    pub bytes_per_spectator: usize,
+    /// Bytes per tick for all spectators
+    pub total_bytes: usize,
+}
+
+impl Default for SpectatorConfig {
+    fn default() -> SpectatorConfig {
+        SpectatorConfig { max_spectators: DEFAULT_MAX_SPECTATORS, bytes_per_spectator: DEFAULT_SPECTATOR_BYTES, total_bytes: DEFAULT_TOTAL_BYTES }
+    }
+}
+
+///
+/// One connected spectator
+///
+#[derive(Clone, Debug)]
+pub struct Spectator {
+    pub name: String,
+    pub interest: Interest,
+    /// Bytes it may still be sent, negative while in debt
+    allowance: i64,
+    /// Updates skipped for bandwidth
+    skipped: u64,
+}
+
+impl Spectator {
+    pub fn allowance(&self) -> i64 { self.allowance }
+    pub fn skipped(&self) -> u64 { self.skipped }
+}
+
+///
+/// Spectators of one server
+///
+#[derive(Clone, Debug)]
+pub struct Spectators {
+    config: SpectatorConfig,
+    spectators: BTreeMap<u32, Spectator>,
+    next: u32,
+    /// Spectator served first next tick
+    turn: usize,
+}
+
+impl Spectators {
+    pub fn new(config: SpectatorConfig) -> Spectators { Spectators { config, spectators: BTreeMap::new(), next: 1, turn: 0 } }
+    pub fn config(&self) -> SpectatorConfig { self.config }
+    pub fn len(&self) -> usize { self.spectators.len() }
+    pub fn is_empty(&self) -> bool { self.spectators.is_empty() }
+    pub fn get(&self, id: u32) -> Option<&Spectator> { self.spectators.get(&id) }
+    pub fn get_mut(&mut self, id: u32) -> Option<&mut Spectator> { self.spectators.get_mut(&id) }
+    pub fn ids(&self) -> Vec<u32> { self.spectators.keys().cloned().collect() }
+    ///
+    /// Answer an opening Spectate, admitting the spectator with a Welcome
+    /// carrying its id or turning it away with a Disconnect.
+    ///
+    pub fn admit(&mut self, packet: &Packet, tick: u64) -> Packet {
+        let (version, name) = match *packet {
+            Packet::Spectate { version, ref name } => (version, name),
+            _ => return Packet::Disconnect { reason: "expected spectate".to_string() },
+        };
+        if version != PROTOCOL_VERSION {
+            return Packet::Disconnect { reason: format!("protocol version {} is not supported, this server speaks {}", version, PROTOCOL_VERSION) };
+        }
+        if self.spectators.len() >= self.config.max_spectators {
+            return Packet::Disconnect { reason: "no room for more spectators".to_string() };
+        }
+        let id = self.next;
+        self.next += 1;
+        let allowance = self.config.bytes_per_spectator as i64;
+        self.spectators.insert(id, Spectator { name: name.clone(), interest: Interest::default(), allowance, skipped: 0 });
+        Packet::Welcome { version, client: id, tick }
+    }
+    pub fn remove(&mut self, id: u32) -> Option<Spectator> { self.spectators.remove(&id) }
+    ///
+    /// Take a packet from a spectator, returning a Disconnect for it if it
+    /// tried to act, after which it is removed.
+    ///
+    pub fn receive(&mut self, id: u32, packet: &Packet) -> Option<Packet> {
+        let spectator = self.spectators.get_mut(&id)?;
+        match *packet {
+            Packet::Ack { tick } => {
+                spectator.interest.acknowledge(tick);
+                None
+            }
+            Packet::Input { ref command, .. } if matches!(*command, Command::Watch(_) | Command::Unwatch(_)) => {
+                spectator.interest.handle(command);
+                None
+            }
+            Packet::Disconnect { .. } => {
+                self.spectators.remove(&id);
+                None
+            }
+            _ => {
+                self.spectators.remove(&id);
+                Some(Packet::Disconnect { reason: "spectators cannot act".to_string() })
+            }
+        }
+    }
+    ///
+    /// Work out what each spectator is sent for this tick's snapshot, within
+    /// the bandwidth caps. Spectators skipped this tick are left out.
+    ///
+    pub fn update(&mut self, world: &World, snapshot: &Snapshot) -> Vec<(u32, Vec<Packet>)> {
+        let share = self.config.bytes_per_spectator as i64;
+        let mut total = self.config.total_bytes as i64;
+        let ids = self.ids();
+        let mut updates = Vec::new();
+        if ids.is_empty() {
+            return updates;
+        }
+        let start = self.turn % ids.len();
+        self.turn = self.turn.wrapping_add(1);
+        for &id in ids[start..].iter().chain(ids[..start].iter()) {
+            let spectator = self.spectators.get_mut(&id).unwrap();
+            spectator.allowance = (spectator.allowance + share).min(share * BURST_TICKS as i64);
+            if spectator.allowance <= 0 || total <= 0 {
+                spectator.skipped += 1;
+                continue;
+            }
+            let packets = spectator.interest.update(world, snapshot).packets;
+            let bytes: i64 = packets.iter().map(|packet| packet.to_bytes().len() as i64).sum();
+            spectator.allowance -= bytes;
+            total -= bytes;
+            updates.push((id, packets));
+        }
+        updates
+    }
+}
+
+impl Default for Spectators {
+    fn default() -> Spectators { Spectators::new(SpectatorConfig::default()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SpectatorConfig, Spectators};
+    use model::component::Position;
+    use model::entity::EntityManager;
+    use model::world::{Chunk, Vector2, World};
+    use net::packet::{Command, Packet};
+    use net::snapshot::Snapshot;
+    use net::PROTOCOL_VERSION;
+
+    #[test]
+    pub fn test_spectators() {
+        let mut world = World::new();
+        world.insert_chunk(Vector2::new(0, 0), Box::new(Chunk::new()));
+        let mut entities = EntityManager::new();
+        let drone = entities.create_entity();
+        entities.add_component(drone, Position::from_f64(5.0, 1.0, 5.0));
+
+        let mut spectators = Spectators::new(SpectatorConfig { max_spectators: 2, bytes_per_spectator: 8, total_bytes: 1 << 20 });
+        let spectate = Packet::Spectate { version: PROTOCOL_VERSION, name: "caster".to_string() };
+        assert_eq!(spectators.admit(&spectate, 10), Packet::Welcome { version: PROTOCOL_VERSION, client: 1, tick: 10 });
+        assert_eq!(spectators.admit(&spectate, 10), Packet::Welcome { version: PROTOCOL_VERSION, client: 2, tick: 10 });
+        assert!(matches!(spectators.admit(&spectate, 10), Packet::Disconnect { .. }));
+        assert!(matches!(spectators.admit(&Packet::Spectate { version: 0, name: "old".to_string() }, 10), Packet::Disconnect { .. }));
+
+        // Watching is allowed, acting gets a spectator thrown out
+        assert_eq!(spectators.receive(1, &Packet::Input { sequence: 1, command: Command::Watch(drone) }), None);
+        let interrupt = Packet::Input { sequence: 2, command: Command::Interrupt { entity: drone, message: 1 } };
+        assert!(matches!(spectators.receive(2, &interrupt), Some(Packet::Disconnect { .. })));
+        assert_eq!(spectators.ids(), vec![1]);
+
+        // The Chunk overdraws the allowance, so the following ticks are skipped until it recovers
+        let updates = spectators.update(&world, &Snapshot::capture(&entities, 11));
+        assert_eq!(updates.len(), 1);
+        assert!(matches!(updates[0].1[0], Packet::ChunkData { .. }));
+        assert!(spectators.get(1).unwrap().allowance() < 0);
+        let mut tick = 12;
+        while spectators.update(&world, &Snapshot::capture(&entities, tick)).is_empty() {
+            tick += 1;
+        }
+        assert!(spectators.get(1).unwrap().skipped() > 0);
+        assert_eq!(spectators.receive(1, &Packet::Ack { tick }), None);
+    }
+}
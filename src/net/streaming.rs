@@ -0,0 +1,213 @@
+///
+/// Chunk Streaming
+///
+/// A client's Interest wants the Chunks around what it watches, each with a
+/// priority: the squared distance from the Chunk's centre to the nearest
+/// watched entity, or to where that entity will be `lookahead` seconds on at
+/// its current Velocity, whichever is nearer, so the Chunks it is heading
+/// into come before those behind it. The ChunkStreamer sends the wanted
+/// Chunks nearest first within `bytes_per_update`, always at least one, and
+/// leaves the rest queued. The queue is rebuilt from what is wanted on every
+/// update, so a Chunk the client moved away from before its turn is
+/// cancelled without ever being sent, and one it leaves after being sent is
+/// unloaded.
+///
+/// Once a Chunk is sent, the Block changes observed in it are sent as a
+/// BlockDelta of just those Blocks on the next update, unless more than
+/// `resend_above` changed, when sending the whole Chunk again is cheaper.
+///
+use codec::invalid_data;
+use math::Fixed;
+use model::material::MaterialId;
+use model::observer::WorldEvent;
+use model::world::{chunk_of, Block, Vector2, World, CHUNK_SIZE};
+use net::packet::Packet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+/// Default bytes of Chunks sent per update
+pub const DEFAULT_STREAM_BYTES: usize = 64 << 10;
+/// Default seconds of movement looked ahead
+pub const DEFAULT_LOOKAHEAD: i64 = 2;
+/// Default Blocks changed in a Chunk beyond which it is sent whole again
+pub const DEFAULT_RESEND_ABOVE: usize = 512;
+
+/// Changed Blocks of one Chunk by local position
+type BlockChanges = BTreeMap<(u8, u8, u8), Block>;
+
+///
+/// Streaming Settings
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct StreamConfig {
+    /// Bytes of ChunkData sent per update, though at least one Chunk is
+    pub bytes_per_update: usize,
+    /// Seconds of a watched entity's movement its Chunks are prioritised along
+    pub lookahead: Fixed,
+    /// Changed Blocks in a sent Chunk beyond which it is sent whole again
+    pub resend_above: usize,
+}
+
+impl Default for StreamConfig {
+    fn default() -> StreamConfig {
+        StreamConfig { bytes_per_update: DEFAULT_STREAM_BYTES, lookahead: Fixed::from_int(DEFAULT_LOOKAHEAD), resend_above: DEFAULT_RESEND_ABOVE }
+    }
+}
+
+///
+/// Chunks sent to one client and those still to come
+///
+#[derive(Clone, Default, Debug)]
+pub struct ChunkStreamer {
+    config: StreamConfig,
+    sent: BTreeSet<Vector2<u64>>,
+    /// Wanted but not yet sent, as of the last update
+    queued: BTreeSet<Vector2<u64>>,
+    /// Blocks changed in sent Chunks since the last update, by Chunk and local position
+    changes: BTreeMap<Vector2<u64>, BlockChanges>,
+    /// Queued Chunks no longer wanted before they were sent
+    cancelled: u64,
+}
+
+impl ChunkStreamer {
+    pub fn new(config: StreamConfig) -> ChunkStreamer { ChunkStreamer { config, ..ChunkStreamer::default() } }
+    pub fn config(&self) -> StreamConfig { self.config }
+    pub fn set_config(&mut self, config: StreamConfig) { self.config = config }
+    /// Chunks the client holds.
+    pub fn chunks(&self) -> impl Iterator<Item=Vector2<u64>> + '_ { self.sent.iter().cloned() }
+    /// Chunks wanted and not yet sent.
+    pub fn queued(&self) -> usize { self.queued.len() }
+    pub fn cancelled(&self) -> u64 { self.cancelled }
+    /// Note the Block changes among World events for the next update.
+    pub fn observe(&mut self, events: &[WorldEvent]) {
+        for event in events.iter() {
+            if let WorldEvent::BlockChanged { position: (x, y, z), after, .. } = *event {
+                let (chunk, lx, lz) = chunk_of(x, z);
+                if self.sent.contains(&chunk) {
+                    self.changes.entry(chunk).or_default().insert((lx as u8, y as u8, lz as u8), after);
+                }
+            }
+        }
+    }
+    ///
+    /// Packets bringing the client's Chunks up to date with `wanted`, each
+    /// wanted Chunk with its priority, lowest first.
+    ///
+    pub fn update(&mut self, world: &World, wanted: &BTreeMap<Vector2<u64>, i64>) -> Vec<Packet> {
+        let mut packets = Vec::new();
+        for &position in self.sent.iter().filter(|position| !wanted.contains_key(position)) {
+            packets.push(Packet::ChunkUnload { position });
+        }
+        self.sent.retain(|position| wanted.contains_key(position));
+        self.cancelled += self.queued.iter().filter(|position| !wanted.contains_key(position)).count() as u64;
+
+        for (position, blocks) in std::mem::take(&mut self.changes) {
+            if !self.sent.contains(&position) {
+                continue;
+            }
+            if blocks.len() > self.config.resend_above {
+                if let Some(chunk) = world.get_chunk(position) {
+                    packets.push(Packet::chunk(position, chunk));
+                }
+            } else {
+                let blocks = blocks.into_iter().map(|((x, y, z), block)| ([x, y, z], block.material().id())).collect();
+                packets.push(Packet::BlockDelta { position, blocks });
+            }
+        }
+
+        let mut queue: Vec<(i64, Vector2<u64>)> = wanted.iter()
+            .filter(|&(position, _)| !self.sent.contains(position))
+            .map(|(&position, &priority)| (priority, position))
+            .collect();
+        queue.sort();
+        let mut budget = self.config.bytes_per_update as i64;
+        let mut streamed = false;
+        for &(_, position) in queue.iter() {
+            if budget <= 0 && streamed {
+                break;
+            }
+            // Unloaded Chunks are sent once they load
+            if let Some(chunk) = world.get_chunk(position) {
+                let packet = Packet::chunk(position, chunk);
+                budget -= packet.to_bytes().len() as i64;
+                packets.push(packet);
+                self.sent.insert(position);
+                streamed = true;
+            }
+        }
+        self.queued = queue.into_iter().map(|(_, position)| position).filter(|position| !self.sent.contains(position)).collect();
+        packets
+    }
+}
+
+///
+/// Apply a BlockDelta packet to the client's copy of the World.
+///
+pub fn apply_block_delta(packet: &Packet, world: &mut World) -> io::Result<()> {
+    let (position, blocks) = match *packet {
+        Packet::BlockDelta { position, ref blocks } => (position, blocks),
+        _ => return Err(invalid_data("not a block delta")),
+    };
+    let size = CHUNK_SIZE as u64;
+    for &([x, y, z], material) in blocks.iter() {
+        if x as usize >= CHUNK_SIZE || y as usize >= CHUNK_SIZE || z as usize >= CHUNK_SIZE {
+            return Err(invalid_data("block delta outside its chunk"));
+        }
+        world.set_block(position.x * size + x as u64, y as usize, position.y * size + z as u64, Block::new(MaterialId::new(material)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_block_delta, ChunkStreamer, StreamConfig};
+    use math::Fixed;
+    use model::observer::EventFilter;
+    use model::world::{Block, Chunk, Vector2, World};
+    use net::packet::Packet;
+    use pool::Poolable;
+    use std::collections::BTreeMap;
+
+    #[test]
+    pub fn test_chunk_streaming() {
+        let mut world = World::new();
+        for cx in 0..4 {
+            world.insert_chunk(Vector2::new(cx, 0), Chunk::allocate());
+        }
+        let metal = world.materials().id("metal").unwrap();
+        let subscriber = world.subscribe(EventFilter { chunks: false, ..EventFilter::all() });
+        let chunk = Packet::chunk(Vector2::new(0, 0), world.get_chunk(Vector2::new(0, 0)).unwrap()).to_bytes().len();
+        let mut streamer = ChunkStreamer::new(StreamConfig { bytes_per_update: chunk + 1, lookahead: Fixed::ZERO, resend_above: 2 });
+
+        // Nearest first, two a time within the budget
+        let wanted: BTreeMap<_, _> = vec![(Vector2::new(0, 0), 900), (Vector2::new(1, 0), 100), (Vector2::new(2, 0), 400)].into_iter().collect();
+        let sent: Vec<_> = streamer.update(&world, &wanted).iter().map(|packet| match *packet {
+            Packet::ChunkData { position, .. } => position,
+            ref other => panic!("expected chunks, got {:?}", other),
+        }).collect();
+        assert_eq!((sent, streamer.queued()), (vec![Vector2::new(1, 0), Vector2::new(2, 0)], 1));
+
+        // Moving away cancels the queued Chunk and unloads a sent one
+        let wanted: BTreeMap<_, _> = vec![(Vector2::new(1, 0), 100), (Vector2::new(3, 0), 200)].into_iter().collect();
+        world.set_block(33, 4, 2, Block::new(metal));
+        world.set_block(70, 4, 2, Block::new(metal));
+        streamer.observe(&world.drain_events(subscriber));
+        let packets = streamer.update(&world, &wanted);
+        assert_eq!(packets[0], Packet::ChunkUnload { position: Vector2::new(2, 0) });
+        assert_eq!(packets[1], Packet::BlockDelta { position: Vector2::new(1, 0), blocks: vec![([1, 4, 2], metal.id())] });
+        assert!(matches!(packets[2], Packet::ChunkData { position, .. } if position == Vector2::new(3, 0)));
+        assert_eq!((packets.len(), streamer.cancelled()), (3, 1));
+
+        // The client applies the delta, and a Chunk changed all over is sent whole
+        let mut copy = World::new();
+        copy.insert_chunk(Vector2::new(1, 0), Chunk::allocate());
+        apply_block_delta(&packets[1], &mut copy).unwrap();
+        assert_eq!(copy.get_block(33, 4, 2), Some(Block::new(metal)));
+        for x in 96..100 {
+            world.set_block(x, 1, 1, Block::new(metal));
+        }
+        streamer.observe(&world.drain_events(subscriber));
+        assert!(matches!(streamer.update(&world, &wanted)[..], [Packet::ChunkData { position, .. }] if position == Vector2::new(3, 0)));
+        assert!(apply_block_delta(&Packet::BlockDelta { position: Vector2::new(1, 0), blocks: vec![([40, 0, 0], 1)] }, &mut copy).is_err());
+    }
+}
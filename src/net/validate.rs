@@ -0,0 +1,371 @@
+///
+/// Command Validation
+///
+/// An authoritative server passes every command a client sends through a
+/// CommandValidator before applying it. Each Validator in turn may refuse
+/// the command with a reason; the first refusal rejects it and is recorded as
+/// a Violation naming the connection, the command's sequence and the
+/// validator, which the server drains to log, warn or disconnect as it sees
+/// fit. Only once every Validator has passed a command is it accepted, so
+/// stateful validators only count what is actually applied.
+///
+/// The standard validators are
+///
+/// * `Permission`: commands must name a living entity the player's Faction
+///   may command, see `Factions::may_command`. Watching is not restricted.
+/// * `MovementBounds`: a Move must stay in the loaded World and cover no
+///   more than `max_speed` Blocks a second since the entity last moved.
+/// * `ActionRate`: at most `max_actions` Uploads and Interrupts per
+///   connection in any `window` ticks.
+///
+/// Games add their own with `add`, or a closure with `add_check`.
+///
+use math::{Fixed, Vec3};
+use model::component::Position;
+use model::entity::EntityID;
+use model::faction::FactionId;
+use model::world::{chunk_of, CHUNK_SIZE};
+use net::auth::{IdentifiedCommand, Identity};
+use net::packet::Command;
+use simulation::Simulation;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+/// Default Blocks a second an entity may Move
+pub const DEFAULT_MAX_SPEED: i64 = 8;
+/// Default actions a connection may take in a window
+pub const DEFAULT_MAX_ACTIONS: usize = 20;
+/// Default ticks actions are counted over
+pub const DEFAULT_ACTION_WINDOW: u64 = 20;
+
+///
+/// Connected player commands are checked for
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Player {
+    pub identity: Identity,
+    pub faction: Option<FactionId>,
+}
+
+///
+/// Command being checked, with the player who sent it
+///
+#[derive(Copy, Clone, Debug)]
+pub struct Claim<'a> {
+    pub player: &'a Player,
+    pub sequence: u64,
+    pub command: &'a Command,
+}
+
+///
+/// Rejected Command
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Violation {
+    pub connection: u32,
+    pub name: String,
+    pub sequence: u64,
+    pub tick: u64,
+    /// Validator which refused the command
+    pub validator: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (connection {}) command {} refused by {}: {}", self.name, self.connection, self.sequence, self.validator, self.reason)
+    }
+}
+
+///
+/// Check on inbound commands
+///
+pub trait Validator {
+    fn name(&self) -> &str;
+    /// Refuse a command with the reason, or let it pass.
+    fn check(&mut self, claim: &Claim, simulation: &Simulation) -> Result<(), String>;
+    /// The command passed every Validator and will be applied.
+    fn accept(&mut self, _claim: &Claim, _simulation: &Simulation) {}
+    /// Forget a connection which has gone.
+    fn forget(&mut self, _connection: u32) {}
+}
+
+/// Stateless check, see `CommandValidator::add_check`
+pub type Check = Box<dyn FnMut(&Claim, &Simulation) -> Result<(), String>>;
+
+struct CheckValidator {
+    name: String,
+    check: Check,
+}
+
+impl Validator for CheckValidator {
+    fn name(&self) -> &str { &self.name }
+    fn check(&mut self, claim: &Claim, simulation: &Simulation) -> Result<(), String> { (self.check)(claim, simulation) }
+}
+
+/// Entity named by a command, None for Watch and Unwatch.
+fn target(command: &Command) -> Option<EntityID> {
+    match *command {
+        Command::Upload { entity, .. } | Command::Interrupt { entity, .. } | Command::Move { entity, .. } => Some(entity),
+        Command::Watch(_) | Command::Unwatch(_) => None,
+    }
+}
+
+///
+/// Commands must name a living entity the player's Faction may command
+///
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Permission;
+
+impl Validator for Permission {
+    fn name(&self) -> &str { "permission" }
+    fn check(&mut self, claim: &Claim, simulation: &Simulation) -> Result<(), String> {
+        let entity = match target(claim.command) {
+            Some(entity) => entity,
+            None => return Ok(()),
+        };
+        if !simulation.entities().is_alive(entity) {
+            return Err(format!("entity {:?} does not exist", entity));
+        }
+        if !simulation.world().factions().may_command(simulation.entities(), claim.player.faction, entity) {
+            return Err(format!("entity {:?} belongs to another faction", entity));
+        }
+        Ok(())
+    }
+}
+
+///
+/// Moves must stay in the loaded World and within a speed
+///
+#[derive(Clone, Debug)]
+pub struct MovementBounds {
+    /// Blocks a second
+    pub max_speed: Fixed,
+    /// Tick each entity last moved at
+    moved: HashMap<EntityID, u64>,
+}
+
+impl MovementBounds {
+    pub fn new(max_speed: Fixed) -> MovementBounds { MovementBounds { max_speed, moved: HashMap::new() } }
+}
+
+impl Default for MovementBounds {
+    fn default() -> MovementBounds { MovementBounds::new(Fixed::from_int(DEFAULT_MAX_SPEED)) }
+}
+
+impl Validator for MovementBounds {
+    fn name(&self) -> &str { "movement" }
+    fn check(&mut self, claim: &Claim, simulation: &Simulation) -> Result<(), String> {
+        let (entity, position) = match *claim.command {
+            Command::Move { entity, position } => (entity, position),
+            _ => return Ok(()),
+        };
+        let (x, y, z) = position.block();
+        if x < 0 || z < 0 || y < 0 || y >= CHUNK_SIZE as i64 || simulation.world().get_chunk(chunk_of(x as u64, z as u64).0).is_none() {
+            return Err(format!("({}, {}, {}) is outside the world", x, y, z));
+        }
+        let current = match simulation.entities().get_component::<Position>(entity) {
+            Some(current) => *current,
+            None => return Err(format!("entity {:?} can't move", entity)),
+        };
+        // At least a tick's movement is allowed, however soon it moves again
+        let ticks = simulation.tick().saturating_sub(self.moved.get(&entity).cloned().unwrap_or(0)).max(1);
+        let reach = self.max_speed * Fixed::from_int(ticks.min(i32::MAX as u64) as i64) / Fixed::from_int(simulation.tick_rate() as i64);
+        let moved: Vec3 = position.vector() - current.vector();
+        if moved.length() > reach {
+            return Err(format!("moved {} blocks where {} is possible", moved.length(), reach));
+        }
+        Ok(())
+    }
+    fn accept(&mut self, claim: &Claim, simulation: &Simulation) {
+        if let Command::Move { entity, .. } = *claim.command {
+            self.moved.insert(entity, simulation.tick());
+        }
+    }
+}
+
+///
+/// Uploads and Interrupts a connection may send in a window of ticks
+///
+#[derive(Clone, Debug)]
+pub struct ActionRate {
+    pub max_actions: usize,
+    pub window: u64,
+    /// Ticks of each connection's actions within the window
+    taken: HashMap<u32, VecDeque<u64>>,
+}
+
+impl ActionRate {
+    pub fn new(max_actions: usize, window: u64) -> ActionRate { ActionRate { max_actions, window, taken: HashMap::new() } }
+    fn is_action(command: &Command) -> bool { matches!(*command, Command::Upload { .. } | Command::Interrupt { .. }) }
+}
+
+impl Default for ActionRate {
+    fn default() -> ActionRate { ActionRate::new(DEFAULT_MAX_ACTIONS, DEFAULT_ACTION_WINDOW) }
+}
+
+impl Validator for ActionRate {
+    fn name(&self) -> &str { "rate" }
+    fn check(&mut self, claim: &Claim, simulation: &Simulation) -> Result<(), String> {
+        if !ActionRate::is_action(claim.command) {
+            return Ok(());
+        }
+        let window = self.window;
+        let taken = self.taken.entry(claim.player.identity.connection).or_default();
+        while taken.front().is_some_and(|&tick| tick + window <= simulation.tick()) {
+            taken.pop_front();
+        }
+        if taken.len() >= self.max_actions {
+            return Err(format!("more than {} actions in {} ticks", self.max_actions, window));
+        }
+        Ok(())
+    }
+    fn accept(&mut self, claim: &Claim, simulation: &Simulation) {
+        if ActionRate::is_action(claim.command) {
+            self.taken.entry(claim.player.identity.connection).or_default().push_back(simulation.tick());
+        }
+    }
+    fn forget(&mut self, connection: u32) { self.taken.remove(&connection); }
+}
+
+///
+/// Server side validation of every connection's commands
+///
+#[derive(Default)]
+pub struct CommandValidator {
+    validators: Vec<Box<dyn Validator>>,
+    players: HashMap<u32, Player>,
+    violations: Vec<Violation>,
+    /// Violations of each connection since it joined
+    counts: HashMap<u32, u64>,
+}
+
+impl CommandValidator {
+    /// Validator without any checks, add them with `add`.
+    pub fn new() -> CommandValidator { CommandValidator::default() }
+    /// Validator with the standard checks at their default settings.
+    pub fn standard() -> CommandValidator {
+        let mut validator = CommandValidator::new();
+        validator.add(Box::new(Permission));
+        validator.add(Box::new(MovementBounds::default()));
+        validator.add(Box::new(ActionRate::default()));
+        validator
+    }
+    /// Add a Validator, run after those added before it.
+    pub fn add(&mut self, validator: Box<dyn Validator>) { self.validators.push(validator) }
+    /// Add a stateless check under a name.
+    pub fn add_check(&mut self, name: &str, check: Check) { self.add(Box::new(CheckValidator { name: name.to_string(), check })) }
+    pub fn validators(&self) -> Vec<&str> { self.validators.iter().map(|validator| validator.name()).collect() }
+    /// Start checking a welcomed connection's commands as a member of a Faction.
+    pub fn join(&mut self, identity: Identity, faction: Option<FactionId>) {
+        self.counts.insert(identity.connection, 0);
+        self.players.insert(identity.connection, Player { identity, faction });
+    }
+    pub fn leave(&mut self, connection: u32) -> Option<Player> {
+        for validator in self.validators.iter_mut() {
+            validator.forget(connection);
+        }
+        self.counts.remove(&connection);
+        self.players.remove(&connection)
+    }
+    pub fn player(&self, connection: u32) -> Option<&Player> { self.players.get(&connection) }
+    pub fn set_faction(&mut self, connection: u32, faction: Option<FactionId>) -> bool {
+        self.players.get_mut(&connection).map(|player| player.faction = faction).is_some()
+    }
+    /// Violations by a connection since it joined.
+    pub fn violation_count(&self, connection: u32) -> u64 { self.counts.get(&connection).cloned().unwrap_or(0) }
+    /// Violations recorded since the last drain.
+    pub fn drain_violations(&mut self) -> Vec<Violation> { std::mem::take(&mut self.violations) }
+    ///
+    /// Check a command, accepting it if every Validator passes it, otherwise
+    /// recording and returning the Violation. Commands from connections
+    /// which haven't joined are always refused.
+    ///
+    pub fn validate(&mut self, command: &IdentifiedCommand, simulation: &Simulation) -> Result<(), Violation> {
+        let connection = command.identity.connection;
+        let refused = match self.players.get(&connection) {
+            None => Some(("validator".to_string(), "connection has not joined".to_string())),
+            Some(player) => {
+                let claim = Claim { player, sequence: command.sequence, command: &command.command };
+                let refused = self.validators.iter_mut().find_map(|validator| validator.check(&claim, simulation).err().map(|reason| (validator.name().to_string(), reason)));
+                if refused.is_none() {
+                    for validator in self.validators.iter_mut() {
+                        validator.accept(&claim, simulation);
+                    }
+                }
+                refused
+            }
+        };
+        let (validator, reason) = match refused {
+            Some(refused) => refused,
+            None => return Ok(()),
+        };
+        let violation = Violation { connection, name: command.identity.name.clone(), sequence: command.sequence, tick: simulation.tick(), validator, reason };
+        *self.counts.entry(connection).or_insert(0) += 1;
+        self.violations.push(violation.clone());
+        Err(violation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ActionRate, CommandValidator};
+    use model::component::Position;
+    use model::entity::EntityManager;
+    use model::faction::{Faction, Ownership};
+    use model::world::{Chunk, Vector2, World};
+    use net::auth::{IdentifiedCommand, Identity};
+    use net::packet::Command;
+    use pool::Poolable;
+    use simulation::Simulation;
+
+    #[test]
+    pub fn test_command_validation() {
+        let mut world = World::new();
+        world.insert_chunk(Vector2::new(0, 0), Chunk::allocate());
+        let hive = world.factions_mut().register(Faction::new("hive"));
+        let swarm = world.factions_mut().register(Faction::new("swarm"));
+        let mut simulation = Simulation::new(world, EntityManager::new());
+        let ours = simulation.entities_mut().create_entity();
+        simulation.entities_mut().add_component(ours, Ownership::new(hive));
+        simulation.entities_mut().add_component(ours, Position::from_f64(4.0, 2.0, 4.0));
+        let theirs = simulation.entities_mut().create_entity();
+        simulation.entities_mut().add_component(theirs, Ownership::new(swarm));
+
+        let mut validator = CommandValidator::standard();
+        validator.add(Box::new(ActionRate::new(2, 100)));
+        validator.add_check("no-vectors", Box::new(|claim, _| match *claim.command {
+            Command::Upload { address, .. } if address < 0x10 => Err("interrupt vectors are read-only".to_string()),
+            _ => Ok(()),
+        }));
+        let identity = Identity { connection: 3, name: "queen".to_string() };
+        validator.join(identity.clone(), Some(hive));
+        let mut sequence = 0;
+        let mut send = |validator: &mut CommandValidator, command: Command| {
+            sequence += 1;
+            validator.validate(&IdentifiedCommand { identity: identity.clone(), sequence, command }, &simulation).map_err(|violation| violation.validator)
+        };
+
+        // Permission through the faction model
+        assert_eq!(send(&mut validator, Command::Interrupt { entity: ours, message: 1 }), Ok(()));
+        assert_eq!(send(&mut validator, Command::Interrupt { entity: theirs, message: 1 }), Err("permission".to_string()));
+        assert_eq!(send(&mut validator, Command::Watch(theirs)), Ok(()));
+
+        // A tick's worth of movement at 8 Blocks a second at 20 ticks, inside the loaded World
+        assert_eq!(send(&mut validator, Command::Move { entity: ours, position: Position::from_f64(4.3, 2.0, 4.0) }), Ok(()));
+        assert_eq!(send(&mut validator, Command::Move { entity: ours, position: Position::from_f64(9.0, 2.0, 4.0) }), Err("movement".to_string()));
+        assert_eq!(send(&mut validator, Command::Move { entity: ours, position: Position::from_f64(40.0, 2.0, 4.0) }), Err("movement".to_string()));
+
+        // The custom check, then the tighter rate, which only counts accepted actions
+        assert_eq!(send(&mut validator, Command::Upload { entity: ours, address: 2, words: vec![0] }), Err("no-vectors".to_string()));
+        assert_eq!(send(&mut validator, Command::Upload { entity: ours, address: 0x200, words: vec![0] }), Ok(()));
+        assert_eq!(send(&mut validator, Command::Interrupt { entity: ours, message: 2 }), Err("rate".to_string()));
+
+        let violations = validator.drain_violations();
+        assert_eq!(violations.iter().map(|violation| violation.sequence).collect::<Vec<_>>(), vec![2, 5, 6, 7, 9]);
+        assert_eq!((violations[0].name.as_str(), validator.violation_count(3)), ("queen", 5));
+        assert!(validator.drain_violations().is_empty());
+        validator.leave(3);
+        assert_eq!(send(&mut validator, Command::Watch(ours)).unwrap_err(), "validator");
+    }
+}
@@ -0,0 +1,248 @@
+///
+/// Plugins
+///
+/// A Plugin extends the simulation without forking it: through a Registrar it
+/// can register components, Systems, materials, hardware devices and custom
+/// packet types. Plugins name the plugins they depend on, and a PluginHost
+/// applies them in dependency order, otherwise in the order they were added.
+///
+use devices::DeviceFactory;
+use model::material::{Material, MaterialId};
+use net::packet::PacketTypes;
+use simulation::{Simulation, System};
+use std::collections::HashMap;
+use std::fmt;
+
+///
+/// Simulation Extension
+///
+pub trait Plugin {
+    /// Unique name, used by dependents
+    fn name(&self) -> &str;
+    /// Plugins which must be applied first
+    fn dependencies(&self) -> Vec<String> { Vec::new() }
+    fn build(&mut self, registrar: &mut Registrar) -> Result<(), String>;
+}
+
+///
+/// What a Plugin may register
+///
+pub struct Registrar<'a> {
+    simulation: &'a mut Simulation,
+    packets: &'a mut PacketTypes,
+}
+
+impl<'a> Registrar<'a> {
+    /// Direct access for anything not covered below.
+    pub fn simulation(&mut self) -> &mut Simulation { self.simulation }
+    pub fn register_component<C: Clone + 'static>(&mut self) { self.simulation.entities_mut().register::<C>() }
+    pub fn add_system(&mut self, system: Box<dyn System>) { self.simulation.add_system(system) }
+    pub fn register_material(&mut self, material: Material) -> MaterialId {
+        self.simulation.world_mut().materials_mut().register(material)
+    }
+    /// Make a device available to `HiveCluster::install` by name.
+    pub fn register_device(&mut self, name: &str, factory: DeviceFactory) {
+        self.simulation.cluster_mut().device_registry_mut().register(name, factory)
+    }
+    /// Kind to send a custom packet type as.
+    pub fn register_packet(&mut self, name: &str) -> u16 { self.packets.register(name) }
+}
+
+///
+/// Plugin Failure
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PluginError {
+    Duplicate(String),
+    MissingDependency { plugin: String, dependency: String },
+    /// Plugins which depend on each other, in the order they were added
+    Cycle(Vec<String>),
+    Failed { plugin: String, message: String },
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PluginError::Duplicate(ref name) => write!(f, "plugin {} was added twice", name),
+            PluginError::MissingDependency { ref plugin, ref dependency } => write!(f, "plugin {} needs missing plugin {}", plugin, dependency),
+            PluginError::Cycle(ref names) => write!(f, "plugins {} depend on each other", names.join(", ")),
+            PluginError::Failed { ref plugin, ref message } => write!(f, "plugin {} failed: {}", plugin, message),
+        }
+    }
+}
+
+///
+/// Applies Plugins at startup
+///
+#[derive(Default)]
+pub struct PluginHost {
+    plugins: Vec<Box<dyn Plugin>>,
+    packets: PacketTypes,
+    applied: Vec<String>,
+}
+
+impl PluginHost {
+    pub fn new() -> PluginHost { PluginHost::default() }
+    pub fn add(&mut self, plugin: Box<dyn Plugin>) { self.plugins.push(plugin) }
+    pub fn len(&self) -> usize { self.plugins.len() }
+    pub fn is_empty(&self) -> bool { self.plugins.is_empty() }
+    /// Packet types registered by applied plugins.
+    pub fn packet_types(&self) -> &PacketTypes { &self.packets }
+    /// Names of the applied plugins in the order they were applied.
+    pub fn applied(&self) -> &[String] { &self.applied }
+    ///
+    /// Indices of the added plugins in the order to apply them.
+    ///
+    pub fn order(&self) -> Result<Vec<usize>, PluginError> {
+        let mut index = HashMap::new();
+        for (position, plugin) in self.plugins.iter().enumerate() {
+            if index.insert(plugin.name().to_string(), position).is_some() {
+                return Err(PluginError::Duplicate(plugin.name().to_string()));
+            }
+        }
+        let mut dependencies = Vec::with_capacity(self.plugins.len());
+        for plugin in self.plugins.iter() {
+            let mut needs = Vec::new();
+            for dependency in plugin.dependencies() {
+                match index.get(&dependency) {
+                    Some(&position) => needs.push(position),
+                    None => return Err(PluginError::MissingDependency { plugin: plugin.name().to_string(), dependency }),
+                }
+            }
+            dependencies.push(needs);
+        }
+        // Repeatedly take the first plugin whose dependencies are all placed
+        let mut placed = vec![false; self.plugins.len()];
+        let mut order = Vec::with_capacity(self.plugins.len());
+        while order.len() < self.plugins.len() {
+            let next = (0..self.plugins.len()).find(|&position| !placed[position] && dependencies[position].iter().all(|&dependency| placed[dependency]));
+            match next {
+                Some(position) => {
+                    placed[position] = true;
+                    order.push(position);
+                }
+                None => {
+                    let stuck = (0..self.plugins.len()).filter(|&position| !placed[position]).map(|position| self.plugins[position].name().to_string()).collect();
+                    return Err(PluginError::Cycle(stuck));
+                }
+            }
+        }
+        Ok(order)
+    }
+    ///
+    /// Build every plugin not yet applied against the Simulation, stopping
+    /// at the first failure.
+    ///
+    pub fn apply(&mut self, simulation: &mut Simulation) -> Result<(), PluginError> {
+        for position in self.order()? {
+            let plugin = &mut self.plugins[position];
+            if self.applied.iter().any(|name| name == plugin.name()) {
+                continue;
+            }
+            let mut registrar = Registrar { simulation, packets: &mut self.packets };
+            plugin.build(&mut registrar).map_err(|message| PluginError::Failed { plugin: plugin.name().to_string(), message })?;
+            self.applied.push(plugin.name().to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Plugin, PluginError, PluginHost, Registrar};
+    use devices::{Device, DeviceInfo};
+    use model::entity::{EntityManager, EntityMap};
+    use model::material::Material;
+    use model::world::World;
+    use simulation::{Simulation, System};
+    use vcpu::cpu::VCPU16;
+
+    struct Beacon;
+
+    impl Device for Beacon {
+        fn info(&self) -> DeviceInfo { DeviceInfo { id: 0xBEAC, version: 1, manufacturer: 0 } }
+        fn interrupt(&mut self, _cpu: &mut VCPU16) -> u16 { 0 }
+    }
+
+    struct Census;
+
+    impl System for Census {
+        fn name(&self) -> &str { "census" }
+        fn run(&mut self, _world: &mut World, entities: &mut EntityManager) {
+            let count = entities.entities().len();
+            entities.insert_resource(count);
+        }
+    }
+
+    struct Mining;
+
+    impl Plugin for Mining {
+        fn name(&self) -> &str { "mining" }
+        fn dependencies(&self) -> Vec<String> { vec!["ores".to_string()] }
+        fn build(&mut self, registrar: &mut Registrar) -> Result<(), String> {
+            if registrar.simulation().world().materials().id("copper").is_none() {
+                return Err("copper is missing".to_string());
+            }
+            registrar.add_system(Box::new(Census));
+            registrar.register_device("beacon", Box::new(|| Box::new(Beacon) as Box<dyn Device>));
+            assert_eq!(registrar.register_packet("mining/claim"), 0);
+            Ok(())
+        }
+    }
+
+    struct Ores;
+
+    impl Plugin for Ores {
+        fn name(&self) -> &str { "ores" }
+        fn build(&mut self, registrar: &mut Registrar) -> Result<(), String> {
+            registrar.register_material(Material::new("copper", 3.0, 1.0));
+            registrar.register_component::<EntityMap>();
+            Ok(())
+        }
+    }
+
+    struct Loop(&'static str, &'static str);
+
+    impl Plugin for Loop {
+        fn name(&self) -> &str { self.0 }
+        fn dependencies(&self) -> Vec<String> { vec![self.1.to_string()] }
+        fn build(&mut self, _registrar: &mut Registrar) -> Result<(), String> { Ok(()) }
+    }
+
+    #[test]
+    pub fn test_plugin_host() {
+        let mut simulation = Simulation::new(World::new(), EntityManager::new());
+        let mut host = PluginHost::new();
+        host.add(Box::new(Mining));
+        host.add(Box::new(Ores));
+        assert_eq!(host.order(), Ok(vec![1, 0]));
+        host.apply(&mut simulation).unwrap();
+        assert_eq!(host.applied(), &["ores".to_string(), "mining".to_string()]);
+        assert_eq!(host.packet_types().kind("mining/claim"), Some(0));
+        assert_eq!(simulation.systems(), vec!["census"]);
+
+        // The plugin's device shows up on a CPU's bus after the WorldInterface
+        let drone = simulation.entities_mut().create_entity();
+        let cpu = simulation.attach(drone, &[0x0200]).unwrap();
+        assert!(simulation.cluster_mut().install(cpu, "beacon"));
+        assert!(!simulation.cluster_mut().install(cpu, "laser"));
+        simulation.step();
+        assert_eq!(simulation.cluster().get(cpu).unwrap().get_a(), 2);
+        assert_eq!(simulation.entities().resource::<usize>(), Some(&1));
+
+        // Applying again only builds what is new
+        host.apply(&mut simulation).unwrap();
+        assert_eq!(simulation.systems().len(), 1);
+
+        let mut broken = PluginHost::new();
+        broken.add(Box::new(Mining));
+        assert_eq!(broken.order(), Err(PluginError::MissingDependency { plugin: "mining".to_string(), dependency: "ores".to_string() }));
+        broken.add(Box::new(Loop("ores", "mining")));
+        assert_eq!(broken.order(), Err(PluginError::Cycle(vec!["mining".to_string(), "ores".to_string()])));
+        broken.add(Box::new(Ores));
+        assert_eq!(broken.order(), Err(PluginError::Duplicate("ores".to_string())));
+        let mut failing = PluginHost::new();
+        failing.add(Box::new(Loop("ores", "ores")));
+        assert!(matches!(failing.apply(&mut simulation), Err(PluginError::Cycle(_))));
+    }
+}
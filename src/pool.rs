@@ -0,0 +1,169 @@
+//!
+//! Warm-Start Object Pools
+//!
+//! VCPUs (128KB of memory each) and Chunk buffers are expensive to allocate, so
+//! released instances are kept and handed back out on the next acquire instead
+//! of hitting the allocator in the middle of a tick.
+//!
+
+///
+/// Types which can be stored in a Pool
+///
+pub trait Poolable {
+    /// Allocate a fresh instance.
+    fn allocate() -> Box<Self>;
+    /// Return a used instance to its freshly allocated state.
+    fn recycle(&mut self);
+}
+
+///
+/// Pool Tunables
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PoolConfig {
+    /// Instances allocated up front when the Pool is created
+    pub preallocate: usize,
+    /// Maximum number of idle instances retained, extra releases are dropped
+    pub max_idle: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> PoolConfig {
+        PoolConfig {
+            preallocate: 0,
+            max_idle: 64,
+        }
+    }
+}
+
+///
+/// Pool Usage Metrics
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct PoolStats {
+    /// Total calls to acquire
+    pub acquired: u64,
+    /// Acquires satisfied from the idle list
+    pub reused: u64,
+    /// Acquires which had to allocate
+    pub allocated: u64,
+    /// Instances returned to the Pool
+    pub released: u64,
+    /// Released instances dropped because the Pool was full
+    pub discarded: u64,
+}
+
+impl PoolStats {
+    /// Fraction of acquires satisfied without allocating.
+    pub fn reuse_rate(&self) -> f64 {
+        if self.acquired == 0 {
+            0.0
+        } else {
+            self.reused as f64 / self.acquired as f64
+        }
+    }
+}
+
+///
+/// Pool of reusable boxed instances
+///
+pub struct Pool<T: Poolable> {
+    idle: Vec<Box<T>>,
+    config: PoolConfig,
+    stats: PoolStats,
+}
+
+impl<T: Poolable> Pool<T> {
+    pub fn new() -> Pool<T> { Pool::with_config(PoolConfig::default()) }
+    pub fn with_config(config: PoolConfig) -> Pool<T> {
+        let mut pool = Pool {
+            idle: Vec::with_capacity(config.max_idle.min(config.preallocate)),
+            config,
+            stats: PoolStats::default(),
+        };
+        pool.warm(config.preallocate);
+        pool
+    }
+    /// Allocate idle instances until `count` are available (bounded by max_idle).
+    pub fn warm(&mut self, count: usize) {
+        let target = count.min(self.config.max_idle);
+        while self.idle.len() < target {
+            self.idle.push(T::allocate());
+        }
+    }
+    pub fn acquire(&mut self) -> Box<T> {
+        self.stats.acquired += 1;
+        match self.idle.pop() {
+            Some(instance) => {
+                self.stats.reused += 1;
+                instance
+            }
+            None => {
+                self.stats.allocated += 1;
+                T::allocate()
+            }
+        }
+    }
+    pub fn release(&mut self, mut instance: Box<T>) {
+        self.stats.released += 1;
+        if self.idle.len() < self.config.max_idle {
+            instance.recycle();
+            self.idle.push(instance);
+        } else {
+            self.stats.discarded += 1;
+        }
+    }
+    /// Change the retention limit, dropping idle instances above it.
+    pub fn set_max_idle(&mut self, max_idle: usize) {
+        self.config.max_idle = max_idle;
+        self.idle.truncate(max_idle);
+    }
+    pub fn config(&self) -> PoolConfig { self.config }
+    pub fn stats(&self) -> PoolStats { self.stats }
+    pub fn idle(&self) -> usize { self.idle.len() }
+}
+
+impl<T: Poolable> Default for Pool<T> {
+    fn default() -> Pool<T> { Pool::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Pool, PoolConfig};
+    use model::world::{Block, Chunk};
+    use model::material::MaterialId;
+    use vcpu::cpu::VCPU16;
+
+    #[test]
+    pub fn test_pool_reuse() {
+        let mut pool: Pool<VCPU16> = Pool::with_config(PoolConfig { preallocate: 2, max_idle: 2 });
+        assert_eq!(pool.idle(), 2);
+
+        let mut cpu = pool.acquire();
+        cpu.set_memory(0x100, 0xBEEF);
+        pool.release(cpu);
+        let cpu = pool.acquire();
+        assert_eq!(cpu.get_memory(0x100), 0);
+
+        let extra = (pool.acquire(), pool.acquire());
+        pool.release(extra.0);
+        pool.release(extra.1);
+        pool.release(cpu);
+
+        let stats = pool.stats();
+        assert_eq!(stats.acquired, 4);
+        assert_eq!(stats.reused, 3);
+        assert_eq!(stats.allocated, 1);
+        assert_eq!(stats.discarded, 1);
+        assert_eq!(stats.reuse_rate(), 0.75);
+    }
+
+    #[test]
+    pub fn test_chunk_pool_recycles_blocks() {
+        let mut pool: Pool<Chunk> = Pool::new();
+        let mut chunk = pool.acquire();
+        chunk.set_block(4, 5, 6, Block::new(MaterialId::new(3)));
+        pool.release(chunk);
+        assert!(pool.acquire().get_block(4, 5, 6).is_air());
+    }
+}
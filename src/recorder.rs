@@ -0,0 +1,483 @@
+//!
+//! Event Recording
+//!
+//! An EventRecorder set on a Simulation writes what happened in each tick to
+//! a log as it runs: Blocks changing in the first World, entities spawning
+//! and dying, CPUs faulting and messages the host passes in, such as chat or
+//! console commands, each stamped with its tick. The log is read back with
+//! `read_events` and turned into tables for offline analysis of a hive with
+//! `export_csv`, or `export_columns` for tools which read a column at a time.
+//!
+//! The log is a header followed by records, little-endian:
+//!
+//! ```text
+//! header  "HVEL" version:u16
+//! record  ticks since the previous record:u32 kind:u8 body
+//! ```
+//!
+//! ---+---------+----------------------------------------------------------------
+//!  # | KIND    | BODY
+//! ---+---------+----------------------------------------------------------------
+//!  0 | block   | x:u64 y:u16 z:u64 material before:u16 material after:u16
+//!  1 | spawned | entity slot:u32 suffix:u32
+//!  2 | died    | entity slot:u32 suffix:u32
+//!  3 | fault   | cpu slot:u32 generation:u32 owner code:u16 address:u16
+//!  4 | message | owner text:string
+//! ---+---------+----------------------------------------------------------------
+//!
+//! An owner is a u8 of 1 followed by the entity, or 0 for none. Spawns and
+//! deaths are found by comparing the living entities with the previous tick,
+//! so an entity both spawned and destroyed within one tick isn't recorded.
+//!
+use codec::{invalid_data, read_string, read_u16, read_u32, read_u64, read_u8, write_string, write_u16, write_u32, write_u64, write_u8};
+use model::entity::{EntityID, EntityManager};
+use model::material::MaterialId;
+use model::observer::{EventFilter, SubscriberId, WorldEvent};
+use model::update::BlockPosition;
+use model::world::World;
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use vcpu::cluster::{CpuFault, CpuId};
+
+/// Event log file magic
+pub const MAGIC: &[u8; 4] = b"HVEL";
+/// Event log format version
+pub const VERSION: u16 = 1;
+/// Columnar export file magic
+pub const COLUMNS_MAGIC: &[u8; 4] = b"HVCL";
+
+///
+/// Something that happened during a tick
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Event {
+    BlockChanged { position: BlockPosition, before: MaterialId, after: MaterialId },
+    Spawned(EntityID),
+    Died(EntityID),
+    Fault { cpu: CpuId, owner: Option<EntityID>, code: u16, address: u16 },
+    /// Text passed in by the host, from an entity or from nobody
+    Message { owner: Option<EntityID>, text: String },
+}
+
+impl Event {
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Event::BlockChanged { .. } => "block",
+            Event::Spawned(_) => "spawned",
+            Event::Died(_) => "died",
+            Event::Fault { .. } => "fault",
+            Event::Message { .. } => "message",
+        }
+    }
+}
+
+///
+/// Event stamped with the tick it happened in
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Record {
+    pub tick: u64,
+    pub event: Event,
+}
+
+///
+/// Writes a Simulation's events to a log, see `Simulation::set_recorder`
+///
+pub struct EventRecorder {
+    writer: Box<dyn Write>,
+    subscriber: Option<SubscriberId>,
+    living: HashSet<EntityID>,
+    messages: Vec<Event>,
+    /// Tick of the last record written
+    last: u64,
+    records: u64,
+    error: Option<io::Error>,
+}
+
+impl EventRecorder {
+    /// Recorder writing to `writer`, starting with the log header.
+    pub fn new(mut writer: Box<dyn Write>) -> io::Result<EventRecorder> {
+        writer.write_all(MAGIC)?;
+        write_u16(&mut *writer, VERSION)?;
+        Ok(EventRecorder { writer, subscriber: None, living: HashSet::new(), messages: Vec::new(), last: 0, records: 0, error: None })
+    }
+    /// Start watching `world` and `entities`, taking the entities alive now as already spawned.
+    pub fn attach(&mut self, world: &mut World, entities: &EntityManager, tick: u64) {
+        self.subscriber = Some(world.subscribe(EventFilter { chunks: false, ..EventFilter::all() }));
+        self.living = entities.entities().iter().collect();
+        self.last = tick;
+    }
+    /// Stop watching `world`, flushing the log.
+    pub fn detach(&mut self, world: &mut World) -> io::Result<()> {
+        if let Some(subscriber) = self.subscriber.take() {
+            world.observers_mut().unsubscribe(subscriber);
+        }
+        self.writer.flush()
+    }
+    /// Queue a message to be recorded with the tick it is passed in during.
+    pub fn message(&mut self, owner: Option<EntityID>, text: &str) {
+        self.messages.push(Event::Message { owner, text: text.to_string() });
+    }
+    /// Records written so far.
+    pub fn records(&self) -> u64 { self.records }
+    /// First write that failed, after which nothing more is recorded.
+    pub fn error(&self) -> Option<&io::Error> { self.error.as_ref() }
+    ///
+    /// Record everything that happened in `tick`, called by the Simulation at
+    /// the end of each one.
+    ///
+    pub fn record(&mut self, tick: u64, world: &mut World, entities: &EntityManager, faults: &[CpuFault]) {
+        let mut events = Vec::new();
+        if let Some(subscriber) = self.subscriber {
+            for event in world.drain_events(subscriber) {
+                if let WorldEvent::BlockChanged { position, before, after } = event {
+                    events.push(Event::BlockChanged { position, before: before.material(), after: after.material() });
+                }
+            }
+        }
+        let living: HashSet<EntityID> = entities.entities().iter().collect();
+        let mut spawned: Vec<EntityID> = living.difference(&self.living).cloned().collect();
+        let mut died: Vec<EntityID> = self.living.difference(&living).cloned().collect();
+        spawned.sort();
+        died.sort();
+        events.extend(spawned.into_iter().map(Event::Spawned));
+        events.extend(died.into_iter().map(Event::Died));
+        self.living = living;
+        events.extend(faults.iter().map(|fault| Event::Fault { cpu: fault.cpu, owner: fault.owner, code: fault.fault.code, address: fault.fault.address }));
+        events.append(&mut self.messages);
+        if self.error.is_some() {
+            return;
+        }
+        for event in events {
+            if let Err(error) = write_record(&mut *self.writer, tick - self.last, &event) {
+                self.error = Some(error);
+                return;
+            }
+            self.last = tick;
+            self.records += 1;
+        }
+    }
+}
+
+fn write_owner(writer: &mut dyn Write, owner: Option<EntityID>) -> io::Result<()> {
+    match owner {
+        Some(entity) => {
+            write_u8(writer, 1)?;
+            write_entity(writer, entity)
+        }
+        None => write_u8(writer, 0),
+    }
+}
+
+fn write_entity(writer: &mut dyn Write, entity: EntityID) -> io::Result<()> {
+    write_u32(writer, entity.slot() as u32)?;
+    write_u32(writer, entity.suffix() as u32)
+}
+
+fn read_owner(reader: &mut dyn Read) -> io::Result<Option<EntityID>> {
+    match read_u8(reader)? {
+        0 => Ok(None),
+        1 => Ok(Some(read_entity(reader)?)),
+        _ => Err(invalid_data("bad event owner")),
+    }
+}
+
+fn read_entity(reader: &mut dyn Read) -> io::Result<EntityID> {
+    let slot = read_u32(reader)? as usize;
+    Ok(EntityID::new(slot, read_u32(reader)? as usize))
+}
+
+fn write_record(writer: &mut dyn Write, elapsed: u64, event: &Event) -> io::Result<()> {
+    if elapsed > u32::MAX as u64 {
+        return Err(invalid_data("too many ticks between events"));
+    }
+    write_u32(writer, elapsed as u32)?;
+    match *event {
+        Event::BlockChanged { position: (x, y, z), before, after } => {
+            write_u8(writer, 0)?;
+            write_u64(writer, x)?;
+            write_u16(writer, y as u16)?;
+            write_u64(writer, z)?;
+            write_u16(writer, before.id())?;
+            write_u16(writer, after.id())
+        }
+        Event::Spawned(entity) => {
+            write_u8(writer, 1)?;
+            write_entity(writer, entity)
+        }
+        Event::Died(entity) => {
+            write_u8(writer, 2)?;
+            write_entity(writer, entity)
+        }
+        Event::Fault { cpu, owner, code, address } => {
+            write_u8(writer, 3)?;
+            write_u32(writer, cpu.slot() as u32)?;
+            write_u32(writer, cpu.generation())?;
+            write_owner(writer, owner)?;
+            write_u16(writer, code)?;
+            write_u16(writer, address)
+        }
+        Event::Message { owner, ref text } => {
+            write_u8(writer, 4)?;
+            write_owner(writer, owner)?;
+            write_string(writer, text)
+        }
+    }
+}
+
+///
+/// Read back a whole event log, dropping a last record cut short.
+///
+pub fn read_events(reader: &mut dyn Read) -> io::Result<Vec<Record>> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not an event log"));
+    }
+    if read_u16(reader)? != VERSION {
+        return Err(invalid_data("unsupported event log version"));
+    }
+    let mut records = Vec::new();
+    let mut tick = 0;
+    loop {
+        // A log ends between records, or within one if it was torn by a crash
+        match read_record(reader) {
+            Ok((elapsed, event)) => {
+                tick += elapsed;
+                records.push(Record { tick, event });
+            }
+            Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(records),
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+fn read_record(reader: &mut dyn Read) -> io::Result<(u64, Event)> {
+    let elapsed = read_u32(reader)? as u64;
+    let event = match read_u8(reader)? {
+        0 => {
+            let (x, y, z) = (read_u64(reader)?, read_u16(reader)? as usize, read_u64(reader)?);
+            let before = MaterialId::new(read_u16(reader)?);
+            Event::BlockChanged { position: (x, y, z), before, after: MaterialId::new(read_u16(reader)?) }
+        }
+        1 => Event::Spawned(read_entity(reader)?),
+        2 => Event::Died(read_entity(reader)?),
+        3 => {
+            let slot = read_u32(reader)? as usize;
+            let cpu = CpuId::from_parts(slot, read_u32(reader)?);
+            let owner = read_owner(reader)?;
+            let code = read_u16(reader)?;
+            Event::Fault { cpu, owner, code, address: read_u16(reader)? }
+        }
+        4 => {
+            let owner = read_owner(reader)?;
+            Event::Message { owner, text: read_string(reader)? }
+        }
+        _ => return Err(invalid_data("unknown event kind")),
+    };
+    Ok((elapsed, event))
+}
+
+///
+/// Column of an exported table
+///
+#[derive(Clone, PartialEq, Debug)]
+pub enum Column {
+    Integer(Vec<Option<u64>>),
+    Text(Vec<Option<String>>),
+}
+
+/// Column names of an exported table, in order.
+pub const COLUMNS: [&str; 12] = ["tick", "kind", "x", "y", "z", "entity", "before", "after", "cpu", "code", "address", "text"];
+
+///
+/// Records as a table of the COLUMNS, one row each, with fields an event
+/// doesn't have left empty. Entities and CPUs are numbered by slot, fault
+/// and message owners go in the entity column and materials are named.
+///
+pub fn columns(records: &[Record], materials: &dyn Fn(MaterialId) -> String) -> Vec<Column> {
+    let mut integers: Vec<Vec<Option<u64>>> = (0..8).map(|_| Vec::with_capacity(records.len())).collect();
+    let mut kind = Vec::with_capacity(records.len());
+    let mut text = Vec::with_capacity(records.len());
+    let (mut before, mut after) = (Vec::with_capacity(records.len()), Vec::with_capacity(records.len()));
+    for record in records.iter() {
+        // tick, x, y, z, entity, cpu, code, address
+        let mut row = [Some(record.tick), None, None, None, None, None, None, None];
+        let mut names = (None, None);
+        let mut message = None;
+        match record.event {
+            Event::BlockChanged { position: (x, y, z), before, after } => {
+                row[1..4].copy_from_slice(&[Some(x), Some(y as u64), Some(z)]);
+                names = (Some(materials(before)), Some(materials(after)));
+            }
+            Event::Spawned(entity) | Event::Died(entity) => row[4] = Some(entity.slot() as u64),
+            Event::Fault { cpu, owner, code, address } => {
+                row[4] = owner.map(|owner| owner.slot() as u64);
+                row[5..8].copy_from_slice(&[Some(cpu.slot() as u64), Some(code as u64), Some(address as u64)]);
+            }
+            Event::Message { owner, text: ref body } => {
+                row[4] = owner.map(|owner| owner.slot() as u64);
+                message = Some(body.clone());
+            }
+        }
+        for (column, value) in integers.iter_mut().zip(row.iter()) {
+            column.push(*value);
+        }
+        kind.push(Some(record.event.kind().to_string()));
+        before.push(names.0);
+        after.push(names.1);
+        text.push(message);
+    }
+    let mut integers = integers.into_iter().map(Column::Integer);
+    let mut next = || integers.next().unwrap();
+    let tick = next();
+    let (x, y, z, entity) = (next(), next(), next(), next());
+    let (cpu, code, address) = (next(), next(), next());
+    vec![tick, Column::Text(kind), x, y, z, entity, Column::Text(before), Column::Text(after), cpu, code, address, Column::Text(text)]
+}
+
+/// Field of a CSV row, quoted if it needs to be.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+///
+/// Write records as CSV with a header row of the COLUMNS.
+///
+pub fn export_csv(records: &[Record], materials: &dyn Fn(MaterialId) -> String, writer: &mut dyn Write) -> io::Result<()> {
+    writeln!(writer, "{}", COLUMNS.join(","))?;
+    let table = columns(records, materials);
+    for row in 0..records.len() {
+        let fields: Vec<String> = table.iter().map(|column| match *column {
+            Column::Integer(ref values) => values[row].map(|value| value.to_string()).unwrap_or_default(),
+            Column::Text(ref values) => values[row].as_ref().map(|value| csv_field(value)).unwrap_or_default(),
+        }).collect();
+        writeln!(writer, "{}", fields.join(","))?;
+    }
+    Ok(())
+}
+
+///
+/// Write records column by column, so a reader can load only the columns it
+/// needs:
+///
+/// ```text
+/// header  "HVCL" rows:u64 columns:u16
+/// column  name:string type:u8 nulls:bitmap values
+/// ```
+///
+/// A column's type is 0 for integers, written as u64 each, or 1 for text,
+/// written as strings. The nulls bitmap has a bit per row, set where the
+/// field is empty, least significant bit first, and empty fields are left
+/// out of the values.
+///
+pub fn export_columns(records: &[Record], materials: &dyn Fn(MaterialId) -> String, writer: &mut dyn Write) -> io::Result<()> {
+    writer.write_all(COLUMNS_MAGIC)?;
+    write_u64(writer, records.len() as u64)?;
+    write_u16(writer, COLUMNS.len() as u16)?;
+    for (name, column) in COLUMNS.iter().zip(columns(records, materials)) {
+        write_string(writer, name)?;
+        let mut nulls = vec![0u8; records.len().div_ceil(8)];
+        match column {
+            Column::Integer(values) => {
+                write_u8(writer, 0)?;
+                for (row, value) in values.iter().enumerate() {
+                    if value.is_none() {
+                        nulls[row / 8] |= 1 << (row % 8);
+                    }
+                }
+                writer.write_all(&nulls)?;
+                for value in values.into_iter().flatten() {
+                    write_u64(writer, value)?;
+                }
+            }
+            Column::Text(values) => {
+                write_u8(writer, 1)?;
+                for (row, value) in values.iter().enumerate() {
+                    if value.is_none() {
+                        nulls[row / 8] |= 1 << (row % 8);
+                    }
+                }
+                writer.write_all(&nulls)?;
+                for value in values.into_iter().flatten() {
+                    write_string(writer, &value)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export_columns, export_csv, read_events, Event, EventRecorder, Record};
+    use model::entity::EntityManager;
+    use model::material::MaterialId;
+    use model::world::{Block, Chunk, Vector2, World};
+    use simulation::Simulation;
+    use std::cell::RefCell;
+    use std::io::{self, Cursor, Write};
+    use std::rc::Rc;
+
+    /// Writer whose bytes can still be read once handed to the recorder
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> { self.0.borrow_mut().write(bytes) }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    pub fn test_event_recording() {
+        let mut world = World::new();
+        world.insert_chunk(Vector2::new(0, 0), Box::new(Chunk::new()));
+        let metal = world.materials().id("metal").unwrap();
+        let mut entities = EntityManager::new();
+        let existing = entities.create_entity();
+        let mut simulation = Simulation::new(world, entities);
+        let log = Shared::default();
+        simulation.set_recorder(Some(EventRecorder::new(Box::new(log.clone())).unwrap()));
+
+        simulation.step();
+        simulation.world_mut().set_block(1, 2, 3, Block::new(metal));
+        let drone = simulation.entities_mut().create_entity();
+        simulation.record_message(Some(drone), "hello, \"hive\"");
+        simulation.step();
+        simulation.step();
+        simulation.entities_mut().destroy_entity(existing);
+        simulation.step();
+        let recorder = simulation.set_recorder(None).unwrap();
+        assert_eq!(recorder.records(), 4);
+        assert!(recorder.error().is_none());
+
+        let records = read_events(&mut Cursor::new(log.0.borrow().clone())).unwrap();
+        assert_eq!(records, vec![
+            Record { tick: 2, event: Event::BlockChanged { position: (1, 2, 3), before: MaterialId::new(0), after: metal } },
+            Record { tick: 2, event: Event::Spawned(drone) },
+            Record { tick: 2, event: Event::Message { owner: Some(drone), text: "hello, \"hive\"".to_string() } },
+            Record { tick: 4, event: Event::Died(existing) },
+        ]);
+        let mut torn = log.0.borrow().clone();
+        torn.truncate(torn.len() - 3);
+        assert_eq!(read_events(&mut Cursor::new(torn)).unwrap().len(), 3);
+
+        let materials = simulation.world().materials().clone();
+        let name = |id: MaterialId| materials.get(id).map_or(String::new(), |material| material.name().to_string());
+        let mut csv = Vec::new();
+        export_csv(&records, &name, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "tick,kind,x,y,z,entity,before,after,cpu,code,address,text");
+        assert_eq!(lines[1], "2,block,1,2,3,,air,metal,,,,");
+        assert_eq!(lines[3], format!("2,message,,,,{},,,,,,\"hello, \"\"hive\"\"\"", drone.slot()));
+        let mut columns = Vec::new();
+        export_columns(&records, &name, &mut columns).unwrap();
+        assert_eq!(&columns[..4], b"HVCL");
+        assert_eq!(columns[4], 4);
+    }
+}
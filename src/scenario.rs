@@ -0,0 +1,546 @@
+//!
+//! Scenarios
+//!
+//! A scenario sets up a small world, puts drones running given firmware in
+//! it and runs it headless for up to a tick limit, checking victory and
+//! failure conditions after every tick, so hive behaviours can be regression
+//! tested like any other code. Scenario files are written in the TOML subset
+//! of machine templates, see `vcpu::machine`:
+//!
+//! ```text
+//! name = "dig"
+//! ticks = 600                 # tick limit
+//! size = 2                    # square of Chunks from 0,0, default 1
+//! floor = "rock"              # material of the bottom layer, default none
+//! world = "saves/arena"       # save to start from instead of empty Chunks
+//!
+//! [[structure]]               # structure file pasted with its corner at x, y, z
+//! file = "tower.hvst"
+//! x = 4
+//! y = 1
+//! z = 4
+//!
+//! [[drone]]                   # count drones at x, y, z
+//! machine = "scout.toml"      # machine template, default a bare CPU
+//! firmware = "dig.asm"        # .asm, .hc or an image, default the machine's
+//! source = "SET A, 1"         # or inline assembly
+//! x = 2.5
+//! y = 1
+//! z = 2.5
+//! count = 1
+//!
+//! [[victory]]                 # every one must hold at once to pass
+//! type = "block"
+//! x = 3
+//! y = 1
+//! z = 3
+//! material = "metal"
+//!
+//! [[failure]]                 # any one holding fails the scenario
+//! type = "halted"
+//! drone = 0
+//! ```
+//!
+//! Conditions are one of:
+//!
+//! ---+----------+-------------------------------+------------------------------
+//!  # | TYPE     | KEYS                          | HOLDS WHILE
+//! ---+----------+-------------------------------+------------------------------
+//!  1 | block    | x, y, z, material             | the Block is of the material
+//!  2 | memory   | drone, address, value         | the word of memory equals value
+//!  3 | register | drone, register, value        | the register equals value
+//!  4 | halted   | drone                         | the drone's CPU has halted
+//!  5 | near     | drone, x, y, z, radius        | the drone is within radius
+//! ---+----------+-------------------------------+------------------------------
+//!
+//! Drones are numbered from 0 in the order they are listed, `count` times
+//! each. A scenario with no victory conditions passes by reaching its tick
+//! limit without failing. Files named in a scenario are found relative to it
+//! and a save it starts from is only read, never written.
+//!
+use error::HivemindError;
+use math::Fixed;
+use model::component::Position;
+use model::entity::{EntityID, EntityManager};
+use model::structure::{Placement, Structure};
+use model::update::BlockPosition;
+use model::world::{Block, Chunk, Vector2, World, CHUNK_SIZE};
+use simulation::Simulation;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use vcpu::asm::assemble;
+use vcpu::cluster::CpuComponent;
+use vcpu::cpu::VCPU16;
+use vcpu::hivec;
+use vcpu::machine::{parse_value, strip_comment, MachineTemplate, Value};
+
+/// Registers by index in a register condition
+const REGISTER_NAMES: [&str; 12] = ["A", "B", "C", "X", "Y", "Z", "I", "J", "PC", "SP", "EX", "IA"];
+
+///
+/// Scenario parse or setup failure, at the line of the table or key at fault
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ScenarioError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl ScenarioError {
+    fn new(line: usize, message: &str) -> ScenarioError { ScenarioError { line, message: message.to_string() } }
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "line {}: {}", self.line, self.message) }
+}
+
+///
+/// Structure pasted before the scenario starts
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct Placed {
+    pub file: PathBuf,
+    pub origin: BlockPosition,
+    line: usize,
+}
+
+///
+/// Drones placed before the scenario starts
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct Drones {
+    pub machine: Option<PathBuf>,
+    /// Overrides the machine's firmware
+    pub firmware: Option<PathBuf>,
+    /// Inline assembly, overriding both
+    pub source: Option<String>,
+    pub position: (f64, f64, f64),
+    pub count: u32,
+    line: usize,
+}
+
+///
+/// Victory or failure condition
+///
+#[derive(Clone, PartialEq, Debug)]
+pub enum Condition {
+    Block { position: BlockPosition, material: String },
+    Memory { drone: usize, address: u16, value: u16 },
+    /// Register by index, A to J then PC, SP, EX and IA
+    Register { drone: usize, register: usize, value: u16 },
+    Halted { drone: usize },
+    Near { drone: usize, position: (f64, f64, f64), radius: f64 },
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Condition::Block { position: (x, y, z), ref material } => write!(f, "block {} {} {} is {}", x, y, z, material),
+            Condition::Memory { drone, address, value } => write!(f, "drone {} [0x{:04X}] is {}", drone, address, value),
+            Condition::Register { drone, register, value } => write!(f, "drone {} {} is {}", drone, REGISTER_NAMES[register], value),
+            Condition::Halted { drone } => write!(f, "drone {} halted", drone),
+            Condition::Near { drone, position: (x, y, z), radius } => write!(f, "drone {} within {} of {} {} {}", drone, radius, x, y, z),
+        }
+    }
+}
+
+impl Condition {
+    /// Whether the condition holds in `simulation`, whose drones are `drones`.
+    pub fn holds(&self, simulation: &Simulation, drones: &[EntityID]) -> bool {
+        let cpu = |drone: usize| -> Option<&VCPU16> {
+            let entity = *drones.get(drone)?;
+            simulation.cluster().get(simulation.entities().get_component::<CpuComponent>(entity)?.cpu)
+        };
+        match *self {
+            Condition::Block { position: (x, y, z), ref material } => {
+                let world = simulation.world();
+                world.get_block(x, y, z).is_some_and(|block| world.materials().id(material) == Some(block.material()))
+            }
+            Condition::Memory { drone, address, value } => cpu(drone).is_some_and(|cpu| cpu.get_memory(address) == value),
+            Condition::Register { drone, register, value } => cpu(drone).is_some_and(|cpu| {
+                let getters: [fn(&VCPU16) -> u16; 12] = [
+                    VCPU16::get_a, VCPU16::get_b, VCPU16::get_c, VCPU16::get_x, VCPU16::get_y, VCPU16::get_z, VCPU16::get_i, VCPU16::get_j,
+                    VCPU16::get_pc, VCPU16::get_sp, VCPU16::get_ex, VCPU16::get_ia,
+                ];
+                getters[register](cpu) == value
+            }),
+            Condition::Halted { drone } => cpu(drone).is_some_and(VCPU16::is_halted),
+            Condition::Near { drone, position: (x, y, z), radius } => {
+                let position = drones.get(drone).and_then(|&entity| simulation.entities().get_component::<Position>(entity));
+                position.is_some_and(|position| position.distance(&Position::from_f64(x, y, z)) <= Fixed::from_f64(radius))
+            }
+        }
+    }
+}
+
+///
+/// How a scenario ended
+///
+#[derive(Clone, PartialEq, Debug)]
+pub enum Outcome {
+    /// Every victory condition held, or the tick limit was survived
+    Passed,
+    /// The failure condition held
+    Failed(Condition),
+    /// The tick limit passed before victory
+    TimedOut,
+}
+
+///
+/// Result of running a scenario
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct Report {
+    pub name: String,
+    pub outcome: Outcome,
+    /// Ticks run
+    pub ticks: u64,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool { self.outcome == Outcome::Passed }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.outcome {
+            Outcome::Passed => write!(f, "pass {} in {} ticks", self.name, self.ticks),
+            Outcome::Failed(ref condition) => write!(f, "FAIL {}: {} at tick {}", self.name, condition, self.ticks),
+            Outcome::TimedOut => write!(f, "FAIL {}: no victory in {} ticks", self.name, self.ticks),
+        }
+    }
+}
+
+///
+/// Scenario
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct Scenario {
+    pub name: String,
+    /// Tick limit
+    pub ticks: u64,
+    /// Edge of the square of Chunks from 0,0
+    pub size: u64,
+    pub floor: Option<String>,
+    pub world: Option<PathBuf>,
+    pub structures: Vec<Placed>,
+    pub drones: Vec<Drones>,
+    pub victory: Vec<Condition>,
+    pub failure: Vec<Condition>,
+}
+
+/// Keys of one table and the lines they are on
+struct Table {
+    kind: String,
+    line: usize,
+    keys: HashMap<String, (usize, Value)>,
+}
+
+impl Table {
+    fn take(&mut self, key: &str) -> Option<(usize, Value)> { self.keys.remove(key) }
+    fn text(&mut self, key: &str) -> Result<Option<String>, ScenarioError> {
+        match self.take(key) {
+            Some((_, Value::Text(text))) => Ok(Some(text)),
+            Some((line, _)) => Err(ScenarioError::new(line, &format!("{} needs a string", key))),
+            None => Ok(None),
+        }
+    }
+    fn integer(&mut self, key: &str, max: u64) -> Result<Option<u64>, ScenarioError> {
+        match self.take(key) {
+            Some((_, Value::Integer(value))) if value <= max => Ok(Some(value)),
+            Some((line, _)) => Err(ScenarioError::new(line, &format!("{} needs an integer up to {}", key, max))),
+            None => Ok(None),
+        }
+    }
+    fn float(&mut self, key: &str) -> Result<Option<f64>, ScenarioError> {
+        match self.take(key) {
+            Some((_, Value::Integer(value))) => Ok(Some(value as f64)),
+            Some((_, Value::Float(value))) => Ok(Some(value)),
+            Some((line, _)) => Err(ScenarioError::new(line, &format!("{} needs a number", key))),
+            None => Ok(None),
+        }
+    }
+    fn required<T>(&self, key: &str, value: Option<T>) -> Result<T, ScenarioError> {
+        value.ok_or_else(|| ScenarioError::new(self.line, &format!("{} needs {}", self.kind, key)))
+    }
+    fn block(&mut self) -> Result<BlockPosition, ScenarioError> {
+        let x = self.integer("x", u64::MAX)?;
+        let y = self.integer("y", u16::MAX as u64)?;
+        let z = self.integer("z", u64::MAX)?;
+        Ok((self.required("x", x)?, self.required("y", y)? as usize, self.required("z", z)?))
+    }
+    fn point(&mut self) -> Result<(f64, f64, f64), ScenarioError> {
+        let (x, y, z) = (self.float("x")?, self.float("y")?, self.float("z")?);
+        Ok((self.required("x", x)?, self.required("y", y)?, self.required("z", z)?))
+    }
+    fn drone(&mut self) -> Result<usize, ScenarioError> {
+        let drone = self.integer("drone", u32::MAX as u64)?;
+        Ok(self.required("drone", drone)? as usize)
+    }
+    fn word(&mut self, key: &str) -> Result<u16, ScenarioError> {
+        let value = self.integer(key, u16::MAX as u64)?;
+        Ok(self.required(key, value)? as u16)
+    }
+    /// Refuse keys left over once the table is read.
+    fn finish(self) -> Result<(), ScenarioError> {
+        match self.keys.into_iter().min_by_key(|&(_, (line, _))| line) {
+            Some((key, (line, _))) => Err(ScenarioError::new(line, &format!("unknown key {}", key))),
+            None => Ok(()),
+        }
+    }
+    fn condition(&mut self) -> Result<Condition, ScenarioError> {
+        let kind = self.text("type")?;
+        let condition = match self.required("type", kind)?.as_str() {
+            "block" => {
+                let position = self.block()?;
+                let material = self.text("material")?;
+                Condition::Block { position, material: self.required("material", material)? }
+            }
+            "memory" => Condition::Memory { drone: self.drone()?, address: self.word("address")?, value: self.word("value")? },
+            "register" => {
+                let drone = self.drone()?;
+                let name = self.text("register")?;
+                let name = self.required("register", name)?;
+                let register = REGISTER_NAMES.iter().position(|candidate| candidate.eq_ignore_ascii_case(&name))
+                    .ok_or_else(|| ScenarioError::new(self.line, &format!("unknown register {}", name)))?;
+                Condition::Register { drone, register, value: self.word("value")? }
+            }
+            "halted" => Condition::Halted { drone: self.drone()? },
+            "near" => {
+                let drone = self.drone()?;
+                let position = self.point()?;
+                let radius = self.float("radius")?;
+                Condition::Near { drone, position, radius: self.required("radius", radius)? }
+            }
+            other => return Err(ScenarioError::new(self.line, &format!("unknown condition {}", other))),
+        };
+        Ok(condition)
+    }
+}
+
+impl Scenario {
+    pub fn parse(text: &str) -> Result<Scenario, ScenarioError> {
+        let mut tables = vec![Table { kind: String::new(), line: 0, keys: HashMap::new() }];
+        for (index, line) in text.lines().enumerate() {
+            let number = index + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                match line {
+                    "[[structure]]" | "[[drone]]" | "[[victory]]" | "[[failure]]" => {
+                        tables.push(Table { kind: line[2..line.len() - 2].to_string(), line: number, keys: HashMap::new() })
+                    }
+                    _ => return Err(ScenarioError::new(number, &format!("unknown table {}", line))),
+                }
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(split) => (line[..split].trim(), parse_value(line[split + 1..].trim()).ok_or_else(|| ScenarioError::new(number, "expected a string, number or boolean"))?),
+                None => return Err(ScenarioError::new(number, "expected key = value")),
+            };
+            let table = tables.last_mut().unwrap();
+            if table.keys.insert(key.to_string(), (number, value)).is_some() {
+                return Err(ScenarioError::new(number, &format!("{} is given twice", key)));
+            }
+        }
+
+        let mut tables = tables.into_iter();
+        let mut top = tables.next().unwrap();
+        let name = top.text("name")?.ok_or_else(|| ScenarioError::new(0, "scenario has no name"))?;
+        let ticks = top.integer("ticks", u64::MAX)?.ok_or_else(|| ScenarioError::new(0, "scenario has no tick limit"))?;
+        let mut scenario = Scenario {
+            name,
+            ticks,
+            size: top.integer("size", 64)?.unwrap_or(1),
+            floor: top.text("floor")?,
+            world: top.text("world")?.map(PathBuf::from),
+            structures: Vec::new(),
+            drones: Vec::new(),
+            victory: Vec::new(),
+            failure: Vec::new(),
+        };
+        top.finish()?;
+        for mut table in tables {
+            match table.kind.as_str() {
+                "structure" => {
+                    let file = table.text("file")?;
+                    let file = PathBuf::from(table.required("file", file)?);
+                    scenario.structures.push(Placed { file, origin: table.block()?, line: table.line });
+                }
+                "drone" => {
+                    let machine = table.text("machine")?.map(PathBuf::from);
+                    let firmware = table.text("firmware")?.map(PathBuf::from);
+                    let source = table.text("source")?;
+                    if machine.is_none() && firmware.is_none() && source.is_none() {
+                        return Err(ScenarioError::new(table.line, "drone needs a machine, firmware or source"));
+                    }
+                    let position = table.point()?;
+                    let count = table.integer("count", u32::MAX as u64)?.unwrap_or(1) as u32;
+                    scenario.drones.push(Drones { machine, firmware, source, position, count, line: table.line });
+                }
+                "victory" => scenario.victory.push(table.condition()?),
+                _ => scenario.failure.push(table.condition()?),
+            }
+            table.finish()?;
+        }
+        Ok(scenario)
+    }
+    /// Read a scenario file, the files it names made relative to the working directory.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Scenario, HivemindError> {
+        let path = path.as_ref();
+        let mut scenario = Scenario::parse(&fs::read_to_string(path)?)?;
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        scenario.world = scenario.world.take().map(|world| directory.join(world));
+        for structure in scenario.structures.iter_mut() {
+            structure.file = directory.join(&structure.file);
+        }
+        for drones in scenario.drones.iter_mut() {
+            drones.machine = drones.machine.take().map(|machine| directory.join(machine));
+            drones.firmware = drones.firmware.take().map(|firmware| directory.join(firmware));
+        }
+        Ok(scenario)
+    }
+    /// Drones the scenario starts with.
+    pub fn drone_count(&self) -> usize { self.drones.iter().map(|drones| drones.count as usize).sum() }
+    ///
+    /// Set up the scenario's Simulation, returning it with its drones in
+    /// order.
+    ///
+    pub fn build(&self) -> Result<(Simulation, Vec<EntityID>), HivemindError> {
+        let mut world = match self.world {
+            Some(ref directory) => World::open(directory)?,
+            None => World::new(),
+        };
+        let error = |line: usize, message: String| HivemindError::Scenario(ScenarioError::new(line, &message));
+        let floor = match self.floor {
+            Some(ref name) => Some(world.materials().id(name).ok_or_else(|| error(0, format!("unknown material {}", name)))?),
+            None => None,
+        };
+        for x in 0..self.size {
+            for z in 0..self.size {
+                let position = Vector2::new(x, z);
+                if !world.load_chunk(position)? {
+                    world.insert_chunk(position, Box::new(Chunk::new()));
+                }
+            }
+        }
+        // Nothing the scenario does goes back to its save
+        world.set_storage(None);
+        if let Some(floor) = floor {
+            for x in 0..self.size * CHUNK_SIZE as u64 {
+                for z in 0..self.size * CHUNK_SIZE as u64 {
+                    world.set_block(x, 0, z, Block::new(floor));
+                }
+            }
+        }
+        for placed in self.structures.iter() {
+            let file = File::open(&placed.file).map_err(|failure| error(placed.line, format!("{}: {}", placed.file.display(), failure)))?;
+            let structure = Structure::load(&mut BufReader::new(file))?;
+            structure.paste(&mut world, placed.origin, &Placement::default())?;
+        }
+
+        let mut simulation = Simulation::new(world, EntityManager::new());
+        let mut entities = Vec::with_capacity(self.drone_count());
+        for drones in self.drones.iter() {
+            let template = match drones.machine {
+                Some(ref path) => MachineTemplate::load(path)?,
+                None => MachineTemplate::new("drone"),
+            };
+            let extension = drones.firmware.as_ref().and_then(|path| path.extension()).and_then(|extension| extension.to_str()).map(str::to_lowercase);
+            let image = match (drones.source.as_ref(), drones.firmware.as_ref(), extension.as_deref()) {
+                (Some(source), _, _) => assemble(source).map_err(|failure| error(drones.line, failure.to_string()))?.image,
+                (None, Some(path), Some("asm")) => assemble(&fs::read_to_string(path)?).map_err(|failure| error(drones.line, format!("{}: {}", path.display(), failure)))?.image,
+                (None, Some(path), Some("hc")) => hivec::build(&fs::read_to_string(path)?).map_err(|failure| error(drones.line, format!("{}: {}", path.display(), failure)))?.image,
+                (None, Some(path), _) => MachineTemplate { firmware: Some(path.clone()), ..template.clone() }.read_firmware()?.unwrap(),
+                (None, None, _) => template.read_firmware()?.ok_or_else(|| error(drones.line, format!("machine {} has no firmware", template.name)))?,
+            };
+            for _ in 0..drones.count {
+                let entity = simulation.entities_mut().create_entity();
+                let (x, y, z) = drones.position;
+                simulation.entities_mut().add_component(entity, Position::from_f64(x, y, z));
+                simulation.instantiate(entity, &template, &image)?;
+                entities.push(entity);
+            }
+        }
+        Ok((simulation, entities))
+    }
+    /// Build and run the scenario until it passes, fails or times out.
+    pub fn run(&self) -> Result<Report, HivemindError> {
+        let (mut simulation, drones) = self.build()?;
+        let report = |outcome: Outcome, ticks: u64| Report { name: self.name.clone(), outcome, ticks };
+        for tick in 1..=self.ticks {
+            simulation.step();
+            if let Some(failure) = self.failure.iter().find(|condition| condition.holds(&simulation, &drones)) {
+                return Ok(report(Outcome::Failed(failure.clone()), tick));
+            }
+            if !self.victory.is_empty() && self.victory.iter().all(|condition| condition.holds(&simulation, &drones)) {
+                return Ok(report(Outcome::Passed, tick));
+            }
+        }
+        Ok(report(if self.victory.is_empty() { Outcome::Passed } else { Outcome::TimedOut }, self.ticks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Condition, Outcome, Scenario, ScenarioError};
+
+    const COUNTER: &str = "
+        name = \"counter\"
+        ticks = 50
+        floor = \"rock\"
+
+        [[drone]]
+        source = \"SET A, 0\\n:loop ADD A, 1\\nSET [0x8000], A\\nIFN A, 10\\nSET PC, loop\\n:end SET PC, end\"
+        x = 2.5
+        y = 1
+        z = 2.5
+        count = 2
+
+        [[victory]]
+        type = \"memory\"
+        drone = 1
+        address = 0x8000
+        value = 10
+
+        [[failure]]
+        type = \"register\"
+        drone = 0
+        register = \"B\"
+        value = 0
+    ";
+
+    #[test]
+    pub fn test_scenario() {
+        let scenario = Scenario::parse(COUNTER).unwrap();
+        assert_eq!((scenario.name.as_str(), scenario.ticks, scenario.size, scenario.drone_count()), ("counter", 50, 1, 2));
+        assert_eq!(scenario.victory, vec![Condition::Memory { drone: 1, address: 0x8000, value: 10 }]);
+        let (simulation, drones) = scenario.build().unwrap();
+        assert_eq!(drones.len(), 2);
+        assert_eq!(simulation.world().get_block(5, 0, 5).map(|block| block.material()), simulation.world().materials().id("rock"));
+
+        // B is 0 from the start
+        let report = scenario.run().unwrap();
+        assert_eq!(report.outcome, Outcome::Failed(Condition::Register { drone: 0, register: 1, value: 0 }));
+        assert_eq!(report.to_string(), "FAIL counter: drone 0 B is 0 at tick 1");
+
+        let mut scenario = Scenario { failure: vec![Condition::Halted { drone: 0 }], ..scenario };
+        let report = scenario.run().unwrap();
+        assert!(report.passed() && report.ticks == 1, "{}", report);
+        scenario.victory.push(Condition::Near { drone: 0, position: (2.5, 1.0, 2.5), radius: 0.5 });
+        scenario.victory.push(Condition::Block { position: (0, 0, 0), material: "metal".to_string() });
+        assert_eq!(scenario.run().unwrap().to_string(), "FAIL counter: no victory in 50 ticks");
+
+        let error = |text: &str| Scenario::parse(text).unwrap_err();
+        assert_eq!(error("ticks = 5"), ScenarioError::new(0, "scenario has no name"));
+        assert_eq!(error("name = \"a\"\nticks = 5\nspeed = 2"), ScenarioError::new(3, "unknown key speed"));
+        assert_eq!(error("name = \"a\"\nticks = 5\n[[drone]]\nx = 1"), ScenarioError::new(3, "drone needs a machine, firmware or source"));
+        assert_eq!(error("name = \"a\"\nticks = 5\n[[victory]]\ntype = \"halted\""), ScenarioError::new(3, "victory needs drone"));
+        assert_eq!(error("name = \"a\"\nticks = 5\n[arena]"), ScenarioError::new(3, "unknown table [arena]"));
+    }
+}
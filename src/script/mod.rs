@@ -0,0 +1,532 @@
+//!
+//! Mission Scripting
+//!
+//! A Script is a small interpreted program (see `parser` for the language)
+//! driving a Simulation, so scenarios can be written without recompiling the
+//! crate. Top level statements run once on `start`; after that the host calls
+//! `step` in place of `Simulation::step`, which runs the tick and then the
+//! script's hooks, any of which may be left out:
+//!
+//! ```text
+//! fn on_block_change(x, y, z, material) { }   # once per changed Block
+//! fn on_cpu_halt(entity) { }                  # nil for a CPU without one
+//! fn on_tick(tick) { }                        # last, once per tick
+//! ```
+//!
+//! Builtins reach the World, entities and CPUs:
+//!
+//! ```text
+//! print(values...)          tick()                   str(value)
+//! block(x, y, z)            set_block(x, y, z, material)
+//! spawn(x, y, z)            destroy(entity)          alive(entity)
+//! pos_x(entity)             pos_y(entity)            pos_z(entity)
+//! teleport(entity, x, y, z) cpus()                   attach_cpu(entity, words...)
+//! interrupt(entity, message) halted(entity)          pause_cpu(entity)
+//! resume_cpu(entity)
+//! ```
+//!
+//! Every call into the script runs at most `step_limit` statements and
+//! expressions, so a runaway loop fails the call instead of hanging the tick.
+//!
+
+pub mod parser;
+
+use admin::format_entity;
+use math::Fixed;
+use model::component::Position;
+use model::entity::EntityID;
+use model::world::Block;
+use script::parser::{parse, BinaryOp, Expr, Function, Statement, StatementKind, UnaryOp};
+use simulation::Simulation;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+use std::time::Duration;
+use vcpu::cluster::{CpuComponent, CpuId};
+
+/// Statements and expressions one call into a script may run
+pub const DEFAULT_STEP_LIMIT: usize = 100_000;
+/// Deepest script function recursion
+pub const MAX_CALL_DEPTH: usize = 64;
+
+pub const ON_TICK: &str = "on_tick";
+pub const ON_BLOCK_CHANGE: &str = "on_block_change";
+pub const ON_CPU_HALT: &str = "on_cpu_halt";
+
+///
+/// Script Value
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    Entity(EntityID),
+}
+
+impl Value {
+    /// Everything but nil and false is true.
+    pub fn is_truthy(&self) -> bool { !matches!(*self, Value::Nil | Value::Bool(false)) }
+    fn type_name(&self) -> &'static str {
+        match *self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Str(_) => "string",
+            Value::Entity(_) => "entity",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Str(ref text) => write!(f, "{}", text),
+            Value::Entity(entity) => write!(f, "{}", format_entity(entity)),
+        }
+    }
+}
+
+///
+/// Script Error with the line it happened on
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl ScriptError {
+    pub fn new<S: Into<String>>(line: usize, message: S) -> ScriptError { ScriptError { line, message: message.into() } }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "line {}: {}", self.line, self.message) }
+}
+
+///
+/// Loaded Script and its state
+///
+pub struct Script {
+    statements: Vec<Statement>,
+    functions: HashMap<String, Rc<Function>>,
+    globals: HashMap<String, Value>,
+    output: Vec<String>,
+    step_limit: usize,
+    /// CPUs already reported halted
+    halted: HashSet<CpuId>,
+}
+
+impl Script {
+    ///
+    /// Parse a script, nothing runs until `start`.
+    ///
+    pub fn compile(source: &str) -> Result<Script, ScriptError> {
+        let program = parse(source)?;
+        let mut functions = HashMap::new();
+        for function in program.functions {
+            if functions.contains_key(&function.name) {
+                let line = function.body.first().map_or(0, |statement| statement.line);
+                return Err(ScriptError::new(line, format!("function {} is defined twice", function.name)));
+            }
+            functions.insert(function.name.clone(), Rc::new(function));
+        }
+        Ok(Script {
+            statements: program.statements,
+            functions,
+            globals: HashMap::new(),
+            output: Vec::new(),
+            step_limit: DEFAULT_STEP_LIMIT,
+            halted: HashSet::new(),
+        })
+    }
+    pub fn step_limit(&self) -> usize { self.step_limit }
+    pub fn set_step_limit(&mut self, step_limit: usize) { self.step_limit = step_limit }
+    pub fn has_function(&self, name: &str) -> bool { self.functions.contains_key(name) }
+    pub fn global(&self, name: &str) -> Option<&Value> { self.globals.get(name) }
+    /// Lines printed since the last call.
+    pub fn take_output(&mut self) -> Vec<String> { self.output.split_off(0) }
+    ///
+    /// Run the top level statements, defining the script's globals.
+    ///
+    pub fn start(&mut self, simulation: &mut Simulation) -> Result<(), ScriptError> {
+        let statements = self.statements.split_off(0);
+        let mut interpreter = Interpreter { script: self, simulation, steps: 0, depth: 0 };
+        interpreter.block(&statements, &mut None).map(|_| ())
+    }
+    ///
+    /// Call a script function by name.
+    ///
+    pub fn call(&mut self, simulation: &mut Simulation, name: &str, args: Vec<Value>) -> Result<Value, ScriptError> {
+        let mut interpreter = Interpreter { script: self, simulation, steps: 0, depth: 0 };
+        interpreter.call(0, name, args)
+    }
+    ///
+    /// Run one tick of the Simulation followed by the hooks for it.
+    ///
+    pub fn step(&mut self, simulation: &mut Simulation) -> Result<(), ScriptError> {
+        simulation.step();
+        self.dispatch(simulation)
+    }
+    ///
+    /// Account for elapsed wall clock time like `Simulation::advance`, running
+    /// the hooks after every tick. Returns the number of ticks run.
+    ///
+    pub fn advance(&mut self, simulation: &mut Simulation, elapsed: Duration) -> Result<u32, ScriptError> {
+        let due = simulation.accumulate(elapsed);
+        for _ in 0..due {
+            self.step(simulation)?;
+        }
+        Ok(due)
+    }
+    /// Run the hooks for the tick just completed.
+    fn dispatch(&mut self, simulation: &mut Simulation) -> Result<(), ScriptError> {
+        if self.has_function(ON_BLOCK_CHANGE) {
+            for (x, y, z) in simulation.block_changes().to_vec() {
+                let material = material_at(simulation, x as i64, y as i64, z as i64);
+                self.call(simulation, ON_BLOCK_CHANGE, vec![Value::Int(x as i64), Value::Int(y as i64), Value::Int(z as i64), material])?;
+            }
+        }
+        let halted: Vec<CpuId> = simulation.cluster().ids().into_iter()
+            .filter(|&id| simulation.cluster().get(id).is_some_and(|cpu| cpu.is_halted()))
+            .collect();
+        let newly: Vec<CpuId> = halted.iter().cloned().filter(|id| !self.halted.contains(id)).collect();
+        self.halted = halted.into_iter().collect();
+        if self.has_function(ON_CPU_HALT) {
+            for id in newly {
+                let owner = simulation.cluster().owner(id).map_or(Value::Nil, Value::Entity);
+                self.call(simulation, ON_CPU_HALT, vec![owner])?;
+            }
+        }
+        if self.has_function(ON_TICK) {
+            let tick = simulation.tick() as i64;
+            self.call(simulation, ON_TICK, vec![Value::Int(tick)])?;
+        }
+        Ok(())
+    }
+}
+
+fn material_at(simulation: &Simulation, x: i64, y: i64, z: i64) -> Value {
+    if x < 0 || y < 0 || z < 0 {
+        return Value::Nil;
+    }
+    let world = simulation.world();
+    world.get_block(x as u64, y as usize, z as u64)
+        .and_then(|block| world.materials().get(block.material()))
+        .map_or(Value::Nil, |material| Value::Str(material.name().to_string()))
+}
+
+enum Flow {
+    Next,
+    Return(Value),
+}
+
+/// Local variables of a function call, None at the top level
+type Frame = Option<HashMap<String, Value>>;
+
+struct Interpreter<'a> {
+    script: &'a mut Script,
+    simulation: &'a mut Simulation,
+    steps: usize,
+    depth: usize,
+}
+
+impl<'a> Interpreter<'a> {
+    fn count(&mut self, line: usize) -> Result<(), ScriptError> {
+        self.steps += 1;
+        if self.steps > self.script.step_limit {
+            return Err(ScriptError::new(line, "step limit exceeded"));
+        }
+        Ok(())
+    }
+    fn block(&mut self, statements: &[Statement], frame: &mut Frame) -> Result<Flow, ScriptError> {
+        for statement in statements.iter() {
+            if let Flow::Return(value) = self.statement(statement, frame)? {
+                return Ok(Flow::Return(value));
+            }
+        }
+        Ok(Flow::Next)
+    }
+    fn statement(&mut self, statement: &Statement, frame: &mut Frame) -> Result<Flow, ScriptError> {
+        let line = statement.line;
+        self.count(line)?;
+        match statement.kind {
+            StatementKind::Let(ref name, ref expr) => {
+                let value = self.expr(line, expr, frame)?;
+                match *frame {
+                    Some(ref mut locals) => locals.insert(name.clone(), value),
+                    None => self.script.globals.insert(name.clone(), value),
+                };
+            }
+            StatementKind::Assign(ref name, ref expr) => {
+                let value = self.expr(line, expr, frame)?;
+                let slot = match *frame {
+                    Some(ref mut locals) if locals.contains_key(name) => locals.get_mut(name),
+                    _ => self.script.globals.get_mut(name),
+                };
+                match slot {
+                    Some(slot) => *slot = value,
+                    None => return Err(ScriptError::new(line, format!("undefined variable {}", name))),
+                }
+            }
+            StatementKind::If(ref branches, ref otherwise) => {
+                for (condition, body) in branches.iter() {
+                    if self.expr(line, condition, frame)?.is_truthy() {
+                        return self.block(body, frame);
+                    }
+                }
+                return self.block(otherwise, frame);
+            }
+            StatementKind::While(ref condition, ref body) => {
+                while self.expr(line, condition, frame)?.is_truthy() {
+                    if let Flow::Return(value) = self.block(body, frame)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+            }
+            StatementKind::Return(ref value) => {
+                let value = match *value {
+                    Some(ref expr) => self.expr(line, expr, frame)?,
+                    None => Value::Nil,
+                };
+                return Ok(Flow::Return(value));
+            }
+            StatementKind::Expr(ref expr) => {
+                self.expr(line, expr, frame)?;
+            }
+        }
+        Ok(Flow::Next)
+    }
+    fn expr(&mut self, line: usize, expr: &Expr, frame: &mut Frame) -> Result<Value, ScriptError> {
+        self.count(line)?;
+        match *expr {
+            Expr::Literal(ref value) => Ok(value.clone()),
+            Expr::Variable(ref name) => frame.as_ref().and_then(|locals| locals.get(name))
+                .or_else(|| self.script.globals.get(name))
+                .cloned()
+                .ok_or_else(|| ScriptError::new(line, format!("undefined variable {}", name))),
+            Expr::Unary(op, ref operand) => {
+                let value = self.expr(line, operand, frame)?;
+                match (op, value) {
+                    (UnaryOp::Not, value) => Ok(Value::Bool(!value.is_truthy())),
+                    (UnaryOp::Negate, Value::Int(value)) => Ok(Value::Int(value.wrapping_neg())),
+                    (UnaryOp::Negate, value) => Err(ScriptError::new(line, format!("cannot negate {}", value.type_name()))),
+                }
+            }
+            Expr::Binary(BinaryOp::And, ref left, ref right) => {
+                let left = self.expr(line, left, frame)?;
+                if left.is_truthy() { self.expr(line, right, frame) } else { Ok(left) }
+            }
+            Expr::Binary(BinaryOp::Or, ref left, ref right) => {
+                let left = self.expr(line, left, frame)?;
+                if left.is_truthy() { Ok(left) } else { self.expr(line, right, frame) }
+            }
+            Expr::Binary(op, ref left, ref right) => {
+                let left = self.expr(line, left, frame)?;
+                let right = self.expr(line, right, frame)?;
+                binary(line, op, left, right)
+            }
+            Expr::Call(ref name, ref args) => {
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args.iter() {
+                    values.push(self.expr(line, arg, frame)?);
+                }
+                self.call(line, name, values)
+            }
+        }
+    }
+    fn call(&mut self, line: usize, name: &str, args: Vec<Value>) -> Result<Value, ScriptError> {
+        let function = match self.script.functions.get(name) {
+            Some(function) => function.clone(),
+            None => return builtin(self, line, name, args),
+        };
+        if args.len() != function.params.len() {
+            return Err(ScriptError::new(line, format!("{} takes {} arguments, got {}", name, function.params.len(), args.len())));
+        }
+        if self.depth == MAX_CALL_DEPTH {
+            return Err(ScriptError::new(line, "call depth exceeded"));
+        }
+        self.depth += 1;
+        let mut frame = Some(function.params.iter().cloned().zip(args).collect());
+        let result = self.block(&function.body, &mut frame);
+        self.depth -= 1;
+        Ok(match result? {
+            Flow::Return(value) => value,
+            Flow::Next => Value::Nil,
+        })
+    }
+}
+
+fn binary(line: usize, op: BinaryOp, left: Value, right: Value) -> Result<Value, ScriptError> {
+    Ok(match (op, left, right) {
+        (BinaryOp::Equal, left, right) => Value::Bool(left == right),
+        (BinaryOp::NotEqual, left, right) => Value::Bool(left != right),
+        (BinaryOp::Add, Value::Str(left), right) => Value::Str(format!("{}{}", left, right)),
+        (BinaryOp::Add, left, Value::Str(right)) => Value::Str(format!("{}{}", left, right)),
+        (_, Value::Int(_), Value::Int(0)) if op == BinaryOp::Divide || op == BinaryOp::Remainder => {
+            return Err(ScriptError::new(line, "division by zero"));
+        }
+        (op, Value::Int(left), Value::Int(right)) => match op {
+            BinaryOp::Less => Value::Bool(left < right),
+            BinaryOp::LessEqual => Value::Bool(left <= right),
+            BinaryOp::Greater => Value::Bool(left > right),
+            BinaryOp::GreaterEqual => Value::Bool(left >= right),
+            BinaryOp::Add => Value::Int(left.wrapping_add(right)),
+            BinaryOp::Subtract => Value::Int(left.wrapping_sub(right)),
+            BinaryOp::Multiply => Value::Int(left.wrapping_mul(right)),
+            BinaryOp::Divide => Value::Int(left.wrapping_div(right)),
+            BinaryOp::Remainder => Value::Int(left.wrapping_rem(right)),
+            BinaryOp::And | BinaryOp::Or | BinaryOp::Equal | BinaryOp::NotEqual => unreachable!(),
+        },
+        (op, left, right) => {
+            return Err(ScriptError::new(line, format!("cannot apply {:?} to {} and {}", op, left.type_name(), right.type_name())));
+        }
+    })
+}
+
+fn builtin(interpreter: &mut Interpreter, line: usize, name: &str, args: Vec<Value>) -> Result<Value, ScriptError> {
+    let simulation = &mut *interpreter.simulation;
+    let int = |index: usize| match args.get(index) {
+        Some(&Value::Int(value)) => Ok(value),
+        _ => Err(ScriptError::new(line, format!("{} expects an int as argument {}", name, index + 1))),
+    };
+    let entity = |index: usize| match args.get(index) {
+        Some(&Value::Entity(entity)) => Ok(entity),
+        _ => Err(ScriptError::new(line, format!("{} expects an entity as argument {}", name, index + 1))),
+    };
+    let cpu = |simulation: &Simulation, entity: EntityID| simulation.entities().get_component::<CpuComponent>(entity)
+        .map(|component| component.cpu)
+        .filter(|&cpu| simulation.cluster().contains(cpu));
+    let position = |simulation: &Simulation, entity: EntityID| simulation.entities().get_component::<Position>(entity).map(Position::block);
+    Ok(match name {
+        "print" => {
+            let text: Vec<String> = args.iter().map(Value::to_string).collect();
+            interpreter.script.output.push(text.join(" "));
+            Value::Nil
+        }
+        "str" => Value::Str(args.first().map_or(String::new(), Value::to_string)),
+        "tick" => Value::Int(simulation.tick() as i64),
+        "block" => material_at(simulation, int(0)?, int(1)?, int(2)?),
+        "set_block" => {
+            let (x, y, z) = (int(0)?, int(1)?, int(2)?);
+            let material = match args.get(3) {
+                Some(Value::Str(material)) => material,
+                _ => return Err(ScriptError::new(line, "set_block expects a material name as argument 4")),
+            };
+            let world = simulation.world_mut();
+            let id = world.materials().id(material).ok_or_else(|| ScriptError::new(line, format!("unknown material {}", material)))?;
+            Value::Bool(x >= 0 && y >= 0 && z >= 0 && world.set_block(x as u64, y as usize, z as u64, Block::new(id)))
+        }
+        "spawn" => {
+            let position = Position::new(Fixed::from_int(int(0)?), Fixed::from_int(int(1)?), Fixed::from_int(int(2)?));
+            let entities = simulation.entities_mut();
+            let entity = entities.create_entity();
+            entities.add_component(entity, position);
+            Value::Entity(entity)
+        }
+        "destroy" => Value::Bool(simulation.entities_mut().destroy_entity(entity(0)?)),
+        "alive" => Value::Bool(simulation.entities().is_alive(entity(0)?)),
+        "pos_x" => position(simulation, entity(0)?).map_or(Value::Nil, |(x, _, _)| Value::Int(x)),
+        "pos_y" => position(simulation, entity(0)?).map_or(Value::Nil, |(_, y, _)| Value::Int(y)),
+        "pos_z" => position(simulation, entity(0)?).map_or(Value::Nil, |(_, _, z)| Value::Int(z)),
+        "teleport" => {
+            let target = Position::new(Fixed::from_int(int(1)?), Fixed::from_int(int(2)?), Fixed::from_int(int(3)?));
+            match simulation.entities_mut().get_component_mut::<Position>(entity(0)?) {
+                Some(position) => {
+                    *position = target;
+                    Value::Bool(true)
+                }
+                None => Value::Bool(false),
+            }
+        }
+        "cpus" => Value::Int(simulation.cluster().len() as i64),
+        "attach_cpu" => {
+            let entity = entity(0)?;
+            let mut rom = Vec::new();
+            for index in 1..args.len() {
+                rom.push(int(index)? as u16);
+            }
+            Value::Bool(simulation.attach(entity, &rom).is_ok())
+        }
+        "interrupt" => {
+            let message = int(1)? as u16;
+            match cpu(simulation, entity(0)?) {
+                Some(cpu) => {
+                    simulation.cluster_mut().get_mut(cpu).unwrap().interrupt(message);
+                    Value::Bool(true)
+                }
+                None => Value::Bool(false),
+            }
+        }
+        "halted" => Value::Bool(cpu(simulation, entity(0)?).is_some_and(|cpu| simulation.cluster().get(cpu).unwrap().is_halted())),
+        "pause_cpu" => Value::Bool(cpu(simulation, entity(0)?).is_some_and(|cpu| simulation.cluster_mut().pause(cpu))),
+        "resume_cpu" => Value::Bool(cpu(simulation, entity(0)?).is_some_and(|cpu| simulation.cluster_mut().resume(cpu))),
+        _ => return Err(ScriptError::new(line, format!("unknown function {}", name))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Script, ScriptError, Value};
+    use model::entity::EntityManager;
+    use model::material::Material;
+    use model::world::{Chunk, Vector2, World};
+    use pool::Poolable;
+    use simulation::Simulation;
+
+    const MISSION: &str = r#"
+        let changes = 0;
+        let drone = spawn(4, 1, 4);
+        attach_cpu(drone, 0x8401);
+        set_block(0, 0, 0, "stone");
+
+        fn fib(n) {
+            if n < 2 { return n; }
+            return fib(n - 1) + fib(n - 2);
+        }
+        fn on_block_change(x, y, z, material) {
+            changes = changes + 1;
+            print("block", x, y, z, "is now", material);
+        }
+        fn on_tick(tick) {
+            if tick == 1 {
+                teleport(drone, 9, 1, 9);
+                print("cpu of " + str(drone) + " halted", halted(drone), "at", pos_x(drone));
+            } else if tick == 2 && alive(drone) {
+                print("fib", fib(10));
+                destroy(drone);
+            }
+        }
+    "#;
+
+    #[test]
+    pub fn test_mission_script() {
+        let mut world = World::new();
+        world.materials_mut().register(Material::new("stone", 1.0, 1.0));
+        world.insert_chunk(Vector2::new(0, 0), Chunk::allocate());
+        let mut simulation = Simulation::new(world, EntityManager::new());
+        let mut script = Script::compile(MISSION).unwrap();
+        script.start(&mut simulation).unwrap();
+        assert_eq!(script.global("changes"), Some(&Value::Int(0)));
+        assert_eq!(simulation.cluster().len(), 1);
+
+        script.step(&mut simulation).unwrap();
+        assert_eq!(script.take_output(), vec!["block 0 0 0 is now stone".to_string(), "cpu of 0:0 halted false at 9".to_string()]);
+        assert_eq!(script.global("changes"), Some(&Value::Int(1)));
+        script.step(&mut simulation).unwrap();
+        assert_eq!(script.take_output(), vec!["fib 55".to_string()]);
+        assert!(simulation.entities().entities().is_empty());
+
+        // Errors carry their line, runaway loops are cut off
+        assert_eq!(script.call(&mut simulation, "fib", vec![]).unwrap_err().message, "fib takes 1 arguments, got 0");
+        let mut looping = Script::compile("fn spin() {\n while true { }\n}").unwrap();
+        looping.set_step_limit(1000);
+        assert_eq!(looping.call(&mut simulation, "spin", vec![]), Err(ScriptError::new(2, "step limit exceeded")));
+        let mut broken = Script::compile("let x = 1;\nx = x / 0;").unwrap();
+        assert_eq!(broken.start(&mut simulation).unwrap_err().to_string(), "line 2: division by zero");
+    }
+}
@@ -0,0 +1,407 @@
+///
+/// Script Parser
+///
+/// Turns script source into a Program. The grammar is small and C-like:
+///
+/// ```text
+/// program   = { function | statement }
+/// function  = "fn" name "(" [ name { "," name } ] ")" block
+/// statement = "let" name "=" expr ";" | name "=" expr ";" | expr ";"
+///           | "if" expr block { "else" "if" expr block } [ "else" block ]
+///           | "while" expr block | "return" [ expr ] ";"
+/// block     = "{" { statement } "}"
+/// expr      = or, with || && (== != < <= > >=) (+ -) (* / %) (unary - !)
+///             binding tighter down the list, over integers, strings,
+///             true, false, nil, variables, calls and parentheses
+/// ```
+///
+/// `#` starts a comment running to the end of the line.
+///
+use script::{ScriptError, Value};
+
+const KEYWORDS: [&str; 9] = ["let", "fn", "if", "else", "while", "return", "true", "false", "nil"];
+
+/// Longer symbols first so they win over their prefixes
+const SYMBOLS: [&str; 21] = [
+    "==", "!=", "<=", ">=", "&&", "||",
+    "+", "-", "*", "/", "%", "<", ">", "!", "=", "(", ")", "{", "}", ",", ";",
+];
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Token {
+    Name(String),
+    Keyword(&'static str),
+    Int(i64),
+    Str(String),
+    Symbol(&'static str),
+    End,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Expr {
+    Literal(Value),
+    Variable(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum StatementKind {
+    Let(String, Expr),
+    Assign(String, Expr),
+    /// Condition and body of each branch, then the else body
+    If(Vec<(Expr, Vec<Statement>)>, Vec<Statement>),
+    While(Expr, Vec<Statement>),
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Statement {
+    pub line: usize,
+    pub kind: StatementKind,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<Statement>,
+}
+
+///
+/// Parsed Script
+///
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct Program {
+    /// Top level statements, run once when the script starts
+    pub statements: Vec<Statement>,
+    pub functions: Vec<Function>,
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, ScriptError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(index, c)) = chars.peek() {
+        if c == '\n' {
+            line += 1;
+            chars.next();
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            while chars.peek().is_some_and(|&(_, c)| c != '\n') {
+                chars.next();
+            }
+        } else if c.is_ascii_digit() {
+            let mut digits = String::new();
+            while let Some(&(_, c)) = chars.peek().filter(|&&(_, c)| c.is_ascii_alphanumeric()) {
+                digits.push(c);
+                chars.next();
+            }
+            let value = match digits.strip_prefix("0x") {
+                Some(hex) => i64::from_str_radix(hex, 16),
+                None => digits.parse(),
+            };
+            tokens.push((line, Token::Int(value.map_err(|_| ScriptError::new(line, format!("bad number {}", digits)))?)));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(&(_, c)) = chars.peek().filter(|&&(_, c)| c.is_alphanumeric() || c == '_') {
+                name.push(c);
+                chars.next();
+            }
+            tokens.push((line, match KEYWORDS.iter().find(|&&keyword| keyword == name) {
+                Some(keyword) => Token::Keyword(keyword),
+                None => Token::Name(name),
+            }));
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next().map(|(_, c)| c) {
+                    Some('"') => break,
+                    Some('\\') => text.push(match chars.next().map(|(_, c)| c) {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some(c @ '"') | Some(c @ '\\') => c,
+                        _ => return Err(ScriptError::new(line, "bad escape in string")),
+                    }),
+                    Some('\n') | None => return Err(ScriptError::new(line, "unterminated string")),
+                    Some(c) => text.push(c),
+                }
+            }
+            tokens.push((line, Token::Str(text)));
+        } else {
+            match SYMBOLS.iter().find(|symbol| source[index..].starts_with(**symbol)) {
+                Some(symbol) => {
+                    for _ in 0..symbol.len() {
+                        chars.next();
+                    }
+                    tokens.push((line, Token::Symbol(symbol)));
+                }
+                None => return Err(ScriptError::new(line, format!("unexpected character {:?}", c))),
+            }
+        }
+    }
+    tokens.push((line, Token::End));
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token { &self.tokens[self.position].1 }
+    fn line(&self) -> usize { self.tokens[self.position].0 }
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].1.clone();
+        if token != Token::End {
+            self.position += 1;
+        }
+        token
+    }
+    fn error(&self, expected: &str) -> ScriptError {
+        let found = match *self.peek() {
+            Token::Name(ref name) => name.clone(),
+            Token::Keyword(keyword) | Token::Symbol(keyword) => keyword.to_string(),
+            Token::Int(value) => value.to_string(),
+            Token::Str(ref text) => format!("{:?}", text),
+            Token::End => "end of script".to_string(),
+        };
+        ScriptError::new(self.line(), format!("expected {}, found {}", expected, found))
+    }
+    fn accept(&mut self, token: &Token) -> bool {
+        if self.peek() == token {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+    fn expect_symbol(&mut self, symbol: &'static str) -> Result<(), ScriptError> {
+        if self.accept(&Token::Symbol(symbol)) { Ok(()) } else { Err(self.error(&format!("'{}'", symbol))) }
+    }
+    fn name(&mut self) -> Result<String, ScriptError> {
+        match *self.peek() {
+            Token::Name(_) => match self.next() {
+                Token::Name(name) => Ok(name),
+                _ => unreachable!(),
+            },
+            _ => Err(self.error("a name")),
+        }
+    }
+    fn program(&mut self) -> Result<Program, ScriptError> {
+        let mut program = Program::default();
+        while *self.peek() != Token::End {
+            if self.accept(&Token::Keyword("fn")) {
+                let name = self.name()?;
+                self.expect_symbol("(")?;
+                let mut params = Vec::new();
+                if !self.accept(&Token::Symbol(")")) {
+                    loop {
+                        params.push(self.name()?);
+                        if self.accept(&Token::Symbol(")")) {
+                            break;
+                        }
+                        self.expect_symbol(",")?;
+                    }
+                }
+                let body = self.block()?;
+                program.functions.push(Function { name, params, body });
+            } else {
+                program.statements.push(self.statement()?);
+            }
+        }
+        Ok(program)
+    }
+    fn block(&mut self) -> Result<Vec<Statement>, ScriptError> {
+        self.expect_symbol("{")?;
+        let mut statements = Vec::new();
+        while !self.accept(&Token::Symbol("}")) {
+            if *self.peek() == Token::End {
+                return Err(self.error("'}'"));
+            }
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+    fn statement(&mut self) -> Result<Statement, ScriptError> {
+        let line = self.line();
+        let kind = if self.accept(&Token::Keyword("let")) {
+            let name = self.name()?;
+            self.expect_symbol("=")?;
+            let value = self.expr()?;
+            self.expect_symbol(";")?;
+            StatementKind::Let(name, value)
+        } else if self.accept(&Token::Keyword("if")) {
+            let mut branches = vec![(self.expr()?, self.block()?)];
+            let mut otherwise = Vec::new();
+            while self.accept(&Token::Keyword("else")) {
+                if self.accept(&Token::Keyword("if")) {
+                    branches.push((self.expr()?, self.block()?));
+                } else {
+                    otherwise = self.block()?;
+                    break;
+                }
+            }
+            StatementKind::If(branches, otherwise)
+        } else if self.accept(&Token::Keyword("while")) {
+            StatementKind::While(self.expr()?, self.block()?)
+        } else if self.accept(&Token::Keyword("return")) {
+            let value = if self.accept(&Token::Symbol(";")) { None } else {
+                let value = self.expr()?;
+                self.expect_symbol(";")?;
+                Some(value)
+            };
+            StatementKind::Return(value)
+        } else {
+            let expr = self.expr()?;
+            let kind = match expr {
+                Expr::Variable(name) if self.accept(&Token::Symbol("=")) => StatementKind::Assign(name, self.expr()?),
+                expr => StatementKind::Expr(expr),
+            };
+            self.expect_symbol(";")?;
+            kind
+        };
+        Ok(Statement { line, kind })
+    }
+    fn expr(&mut self) -> Result<Expr, ScriptError> { self.binary(0) }
+    /// Operators of each precedence level, loosest first
+    fn binary(&mut self, level: usize) -> Result<Expr, ScriptError> {
+        const LEVELS: [&[(&str, BinaryOp)]; 5] = [
+            &[("||", BinaryOp::Or)],
+            &[("&&", BinaryOp::And)],
+            &[
+                ("==", BinaryOp::Equal), ("!=", BinaryOp::NotEqual), ("<", BinaryOp::Less),
+                ("<=", BinaryOp::LessEqual), (">", BinaryOp::Greater), (">=", BinaryOp::GreaterEqual),
+            ],
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)],
+            &[("*", BinaryOp::Multiply), ("/", BinaryOp::Divide), ("%", BinaryOp::Remainder)],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        loop {
+            let op = match *self.peek() {
+                Token::Symbol(symbol) => LEVELS[level].iter().find(|&&(text, _)| text == symbol).map(|&(_, op)| op),
+                _ => None,
+            };
+            match op {
+                Some(op) => {
+                    self.next();
+                    left = Expr::Binary(op, Box::new(left), Box::new(self.binary(level + 1)?));
+                }
+                None => return Ok(left),
+            }
+        }
+    }
+    fn unary(&mut self) -> Result<Expr, ScriptError> {
+        if self.accept(&Token::Symbol("-")) {
+            Ok(Expr::Unary(UnaryOp::Negate, Box::new(self.unary()?)))
+        } else if self.accept(&Token::Symbol("!")) {
+            Ok(Expr::Unary(UnaryOp::Not, Box::new(self.unary()?)))
+        } else {
+            self.primary()
+        }
+    }
+    fn primary(&mut self) -> Result<Expr, ScriptError> {
+        let expr = match *self.peek() {
+            Token::Int(value) => Expr::Literal(Value::Int(value)),
+            Token::Str(ref text) => Expr::Literal(Value::Str(text.clone())),
+            Token::Keyword("true") => Expr::Literal(Value::Bool(true)),
+            Token::Keyword("false") => Expr::Literal(Value::Bool(false)),
+            Token::Keyword("nil") => Expr::Literal(Value::Nil),
+            Token::Symbol("(") => {
+                self.next();
+                let expr = self.expr()?;
+                self.expect_symbol(")")?;
+                return Ok(expr);
+            }
+            Token::Name(_) => {
+                let name = self.name()?;
+                if !self.accept(&Token::Symbol("(")) {
+                    return Ok(Expr::Variable(name));
+                }
+                let mut args = Vec::new();
+                if !self.accept(&Token::Symbol(")")) {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.accept(&Token::Symbol(")")) {
+                            break;
+                        }
+                        self.expect_symbol(",")?;
+                    }
+                }
+                return Ok(Expr::Call(name, args));
+            }
+            _ => return Err(self.error("an expression")),
+        };
+        self.next();
+        Ok(expr)
+    }
+}
+
+///
+/// Parse a whole script.
+///
+pub fn parse(source: &str) -> Result<Program, ScriptError> {
+    Parser { tokens: tokenize(source)?, position: 0 }.program()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, BinaryOp, Expr, StatementKind};
+    use script::{ScriptError, Value};
+
+    #[test]
+    pub fn test_parse_script() {
+        let program = parse("let total = 1 + 2 * 3; # comment\nfn on_tick(tick) {\n if tick % 0x10 == 0 { total = total - 1; } else { print(\"a\\\"b\"); }\n}").unwrap();
+        assert_eq!(program.functions[0].params, vec!["tick".to_string()]);
+        match program.statements[0].kind {
+            StatementKind::Let(ref name, Expr::Binary(BinaryOp::Add, ref left, ref right)) => {
+                assert_eq!(name, "total");
+                assert_eq!(**left, Expr::Literal(Value::Int(1)));
+                assert!(matches!(**right, Expr::Binary(BinaryOp::Multiply, _, _)));
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+        match program.functions[0].body[0].kind {
+            StatementKind::If(ref branches, ref otherwise) => {
+                assert_eq!(branches.len(), 1);
+                assert_eq!(otherwise[0].line, 3);
+                assert_eq!(otherwise[0].kind, StatementKind::Expr(Expr::Call("print".to_string(), vec![Expr::Literal(Value::Str("a\"b".to_string()))])));
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(parse("let x = ;").unwrap_err(), ScriptError::new(1, "expected an expression, found ;"));
+        assert_eq!(parse("\nfn f() {").unwrap_err(), ScriptError::new(2, "expected '}', found end of script"));
+        assert!(parse("let s = \"open").is_err());
+    }
+}
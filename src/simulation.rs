@@ -0,0 +1,778 @@
+//!
+//! Simulation Driver
+//!
+//! Owns a World, its entities and their CPUs and advances them together at a
+//! fixed tick rate. Wall clock time is fed in through `advance` and builds up
+//! in an accumulator which is spent one tick interval at a time, so a slow
+//! frame is caught up on rather than stretching the simulation. Catch-up is
+//! capped so a long stall drops time instead of spiralling. The current tick
+//! is kept as a Tick resource for systems which need it, and the time of day
+//! as an Environment resource advanced with it. Systems draw random numbers
+//! from their own streams of the Random resource, see `model::random`.
+//! Work for the hive is posted on the JobBoard resource, whose claims lapse
+//! as ticks pass, see `model::job`.
+//!
+//! Each tick runs, in order: chunk loading, CPUs, physics, portals, power,
+//! pheromones and scheduled Block updates, followed by any added Systems in
+//! the order they were added, and last eviction of Chunks while over the
+//! memory budget, if one is set. Blocks changed since the previous tick, by
+//! the tick or from outside it, are collected at its end.
+//!
+//! Chunks unloaded through the Simulation take the entities standing in them
+//! to storage, see `model::persist`, and bring them back when loaded. Saves
+//! through the Simulation write those entities with their Chunk and leave
+//! them live. An entity running a CPU is stored with its memory image and
+//! boots it again when loaded. Tools
+//! reach entities' Components by name through its ComponentRegistry, see
+//! `model::reflect`.
+//!
+//! A Simulation can be forked to try out what would happen without touching
+//! the live one: the fork shares Chunks and CPU memory pages with it until
+//! either side writes to them, and copies the rest.
+//!
+//! Further Worlds can be added beside the one the Simulation was made with,
+//! sharing its entities, see `model::dimension`. Every World loads chunks,
+//! moves its own entities and runs its pheromones and updates each tick;
+//! added Systems and the Block change list only see the first. Every stage is timed into the
+//! Simulation's Metrics along with the cycles, chunk loads and entities of the
+//! tick. With an EventRecorder set, the events of each tick are written to
+//! its log at the end of the tick, see `recorder`. With a History set,
+//! keyframes are kept as it runs and any recent tick can be rebuilt with
+//! `rewind`, see `history`.
+//!
+
+use budget::{MemoryBudget, MemoryUsage};
+use devices::Bus;
+use error::HivemindError;
+use math::Fixed;
+use metrics::{Metrics, CHUNKS_EVICTED, CHUNKS_LOADED, CPUS_STARVED, CPU_CYCLES, CPU_FAULTS, ENTITIES, MEMORY_BYTES, TICK};
+use model::bounds::signed_chunk_of;
+use model::component::{Position, TargetOf};
+use model::dimension::{self, Portals, WorldId, OVERWORLD};
+use model::entity::{EntityID, EntityManager};
+use history::History;
+use model::environment::Environment;
+use model::job::{self, AssignedTo, JobBoard};
+use model::persist::{ComponentCodecs, StoredEntity};
+use model::physics::{Collision, PhysicsSystem};
+use model::power::{PowerEvent, PowerSystem};
+use model::random::Random;
+use model::reflect::{ComponentRegistry, ReflectError};
+use model::update::{BlockPosition, BlockUpdate};
+use model::world::{Vector2, World};
+use recorder::EventRecorder;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+use vcpu::cluster::{CpuComponent, CpuId, HiveCluster};
+use vcpu::cpu::VCPU16;
+use vcpu::image::Image;
+use vcpu::machine::MachineTemplate;
+
+/// Default ticks per second
+pub const DEFAULT_TICK_RATE: u32 = 20;
+/// Most ticks run by one `advance` at normal speed before the backlog is dropped
+pub const MAX_CATCH_UP: u32 = 10;
+/// Name stored entities carry the CPU they run under, see `HiveCluster::store`
+pub const CPU_COMPONENT: &str = "cpu";
+
+///
+/// Number of the last completed tick, as a resource
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Debug)]
+pub struct Tick(pub u64);
+
+/// Handler for due Block updates
+pub type Updater = Box<dyn FnMut(&mut World, BlockUpdate)>;
+
+///
+/// System run every tick after the built in ones
+///
+pub trait System {
+    fn name(&self) -> &str;
+    fn run(&mut self, world: &mut World, entities: &mut EntityManager);
+}
+
+///
+/// Fixed Rate Simulation
+///
+pub struct Simulation {
+    /// Indexed by WorldId
+    worlds: Vec<World>,
+    world_names: Vec<String>,
+    portals: Portals,
+    entities: EntityManager,
+    cluster: HiveCluster,
+    physics: PhysicsSystem,
+    power: PowerSystem,
+    updater: Option<Updater>,
+    systems: Vec<Box<dyn System>>,
+    tick_rate: u32,
+    /// Tick rate multiplier, 1 for real time
+    speed: u32,
+    paused: bool,
+    accumulator: Duration,
+    collisions: Vec<Collision>,
+    power_events: Vec<PowerEvent>,
+    block_changes: Vec<BlockPosition>,
+    metrics: Metrics,
+    codecs: ComponentCodecs,
+    reflection: ComponentRegistry,
+    memory_budget: Option<MemoryBudget>,
+    recorder: Option<EventRecorder>,
+    history: Option<History>,
+    /// Hash of the entities last written with each loaded Chunk holding any, by World index
+    saved: HashMap<(usize, Vector2<u64>), u64>,
+}
+
+impl Simulation {
+    pub fn new(world: World, entities: EntityManager) -> Simulation {
+        let mut simulation = Simulation {
+            worlds: vec![world],
+            world_names: vec!["overworld".to_string()],
+            portals: Portals::new(),
+            entities,
+            cluster: HiveCluster::new(),
+            physics: PhysicsSystem::new(),
+            power: PowerSystem::new(),
+            updater: None,
+            systems: Vec::new(),
+            tick_rate: DEFAULT_TICK_RATE,
+            speed: 1,
+            paused: false,
+            accumulator: Duration::from_secs(0),
+            collisions: Vec::new(),
+            power_events: Vec::new(),
+            block_changes: Vec::new(),
+            metrics: Metrics::new(),
+            codecs: ComponentCodecs::default(),
+            reflection: ComponentRegistry::default(),
+            memory_budget: None,
+            recorder: None,
+            history: None,
+            saved: HashMap::new(),
+        };
+        simulation.worlds[0].track_changes(true);
+        if simulation.entities.resource::<Tick>().is_none() {
+            simulation.entities.insert_resource(Tick(0));
+        }
+        if simulation.entities.resource::<Environment>().is_none() {
+            simulation.entities.insert_resource(Environment::default());
+        }
+        if simulation.entities.resource::<Random>().is_none() {
+            simulation.entities.insert_resource(Random::default());
+        }
+        if simulation.entities.resource::<JobBoard>().is_none() {
+            simulation.entities.insert_resource(JobBoard::new());
+        }
+        simulation.entities.register_relation::<TargetOf>();
+        simulation.entities.register_relation::<AssignedTo>();
+        simulation.set_tick_rate(DEFAULT_TICK_RATE);
+        simulation
+    }
+    /// The first World, OVERWORLD.
+    pub fn world(&self) -> &World { &self.worlds[0] }
+    pub fn world_mut(&mut self) -> &mut World { &mut self.worlds[0] }
+    /// Host another World beside the others, returning its id.
+    pub fn add_world(&mut self, name: &str, world: World) -> WorldId {
+        assert!(self.worlds.len() <= u16::MAX as usize, "too many Worlds");
+        self.worlds.push(world);
+        self.world_names.push(name.to_string());
+        WorldId(self.worlds.len() as u16 - 1)
+    }
+    pub fn get_world(&self, id: WorldId) -> Option<&World> { self.worlds.get(id.index()) }
+    pub fn get_world_mut(&mut self, id: WorldId) -> Option<&mut World> { self.worlds.get_mut(id.index()) }
+    pub fn world_id(&self, name: &str) -> Option<WorldId> { self.world_names.iter().position(|candidate| candidate == name).map(|index| WorldId(index as u16)) }
+    pub fn world_name(&self, id: WorldId) -> Option<&str> { self.world_names.get(id.index()).map(|name| &name[..]) }
+    pub fn world_count(&self) -> usize { self.worlds.len() }
+    pub fn portals(&self) -> &Portals { &self.portals }
+    pub fn portals_mut(&mut self) -> &mut Portals { &mut self.portals }
+    /// Move an entity to `position` in World `id`. Returns false if either doesn't exist.
+    pub fn transfer(&mut self, entity: EntityID, id: WorldId, position: Position) -> bool {
+        id.index() < self.worlds.len() && dimension::transfer(&mut self.entities, entity, id, position)
+    }
+    ///
+    /// Unload a Chunk of World `id`, storing the entities standing in it.
+    /// Their CPUs are stored with them and released.
+    ///
+    pub fn unload_chunk(&mut self, id: WorldId, position: Vector2<u64>) -> Result<bool, HivemindError> {
+        if id.index() >= self.worlds.len() {
+            return Ok(false);
+        }
+        let residents = self.residents(id).remove(&position).unwrap_or_default();
+        let stored = self.capture(&residents)?;
+        for &entity in residents.iter() {
+            if let Some(cpu) = self.entities.get_component::<CpuComponent>(entity).map(|component| component.cpu) {
+                self.cluster.release(cpu);
+            }
+            self.entities.destroy_entity(entity);
+        }
+        let world = &mut self.worlds[id.index()];
+        // Entities saved with the Chunk which have since left mustn't come back with it
+        if self.saved.remove(&(id.index(), position)).is_some() && stored.is_empty() {
+            world.save_entities(position, &[])?;
+        }
+        world.store_entities(position, stored);
+        world.unload_chunk(position)
+    }
+    ///
+    /// Write a loaded Chunk of World `id` to storage with the entities
+    /// standing in it, which stay live.
+    ///
+    pub fn save_chunk(&mut self, id: WorldId, position: Vector2<u64>) -> Result<(), HivemindError> {
+        if id.index() >= self.worlds.len() {
+            return Ok(());
+        }
+        let residents = self.residents(id).remove(&position).unwrap_or_default();
+        let stored = self.capture(&residents)?;
+        self.worlds[id.index()].save_chunk_with(position, &stored)?;
+        self.note_saved(id.index(), position, &stored);
+        Ok(())
+    }
+    ///
+    /// Write what changed since the last save in every World: Chunks whose
+    /// Blocks changed, with the entities standing in them, and the entities
+    /// alone of Chunks whose residents came, went or changed. Returns how
+    /// many Chunks were written.
+    ///
+    pub fn save_dirty(&mut self) -> Result<usize, HivemindError> {
+        let mut written = 0;
+        for index in 0..self.worlds.len() {
+            let world = &self.worlds[index];
+            self.saved.retain(|&(saved, position), _| saved != index || world.is_chunk_loaded(position));
+            let mut residents = self.residents(WorldId(index as u16));
+            for &(_, position) in self.saved.keys().filter(|&&(saved, _)| saved == index) {
+                residents.entry(position).or_default();
+            }
+            for position in self.worlds[index].dirty_chunks() {
+                residents.entry(position).or_default();
+            }
+            for (position, residents) in residents {
+                let stored = self.capture(&residents)?;
+                let world = &mut self.worlds[index];
+                if world.is_dirty(position) {
+                    world.save_chunk_with(position, &stored)?;
+                } else if self.saved.get(&(index, position)) != hash_of(&stored).as_ref() {
+                    world.save_entities(position, &stored)?;
+                } else {
+                    continue;
+                }
+                self.note_saved(index, position, &stored);
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+    ///
+    /// Full save: everything changed, as `save_dirty`, then every World's
+    /// region files compacted.
+    ///
+    pub fn save(&mut self) -> Result<(), HivemindError> {
+        self.save_dirty()?;
+        for world in self.worlds.iter_mut() {
+            world.compact()?;
+        }
+        Ok(())
+    }
+    fn note_saved(&mut self, index: usize, position: Vector2<u64>, stored: &[StoredEntity]) {
+        match hash_of(stored) {
+            Some(hash) => self.saved.insert((index, position), hash),
+            None => self.saved.remove(&(index, position)),
+        };
+    }
+    /// Entities which can be stored standing in each loaded Chunk of World `id`, in id order.
+    fn residents(&self, id: WorldId) -> BTreeMap<Vector2<u64>, Vec<EntityID>> {
+        let world = &self.worlds[id.index()];
+        let entities = &self.entities;
+        let mut residents: BTreeMap<Vector2<u64>, Vec<EntityID>> = BTreeMap::new();
+        for (entity, at) in entities.iter::<Position>() {
+            let (x, _, z) = at.block();
+            let (chunk, _, _) = signed_chunk_of(x, z);
+            match world.bounds().resolve_chunk(chunk.x, chunk.y) {
+                Some(position) if world.is_chunk_loaded(position)
+                    && dimension::world_of(entities, entity) == id => residents.entry(position).or_default().push(entity),
+                _ => {}
+            }
+        }
+        residents
+    }
+    /// Serialize `residents`, with the CPU each runs under CPU_COMPONENT.
+    fn capture(&mut self, residents: &[EntityID]) -> Result<Vec<StoredEntity>, HivemindError> {
+        let mut stored = Vec::with_capacity(residents.len());
+        for &entity in residents.iter() {
+            let mut entity_stored = self.codecs.capture(&self.entities, entity)?;
+            if let Some(cpu) = self.cluster.store(&self.entities, entity) {
+                entity_stored.components.push((CPU_COMPONENT.to_string(), cpu?));
+            }
+            stored.push(entity_stored);
+        }
+        Ok(stored)
+    }
+    ///
+    /// Load a Chunk of World `id` from storage and spawn the entities stored
+    /// with it, returning them.
+    ///
+    pub fn load_chunk(&mut self, id: WorldId, position: Vector2<u64>) -> Result<Vec<EntityID>, HivemindError> {
+        let world = match self.worlds.get_mut(id.index()) {
+            Some(world) => world,
+            None => return Ok(Vec::new()),
+        };
+        world.load_chunk(position)?;
+        self.spawn_pending(id, position)
+    }
+    ///
+    /// Load every Chunk saved for each World, as `load_chunk`, returning how
+    /// many were loaded.
+    ///
+    pub fn load_saved(&mut self) -> Result<usize, HivemindError> {
+        let mut loaded = 0;
+        for index in 0..self.worlds.len() {
+            let positions = match self.worlds[index].storage() {
+                Some(storage) => storage.chunks()?,
+                None => continue,
+            };
+            for position in positions {
+                self.load_chunk(WorldId(index as u16), position)?;
+                loaded += 1;
+            }
+        }
+        Ok(loaded)
+    }
+    /// Spawn the entities pending in a loaded Chunk.
+    fn spawn_pending(&mut self, id: WorldId, position: Vector2<u64>) -> Result<Vec<EntityID>, HivemindError> {
+        let mut spawned = Vec::new();
+        for stored in self.worlds[id.index()].take_pending_entities(position) {
+            let entity = match self.codecs.restore(&mut self.entities, &stored) {
+                Ok(entity) => entity,
+                Err(error) => return Err(HivemindError::CorruptChunk { position, reason: error.to_string() }),
+            };
+            if let Some(cpu) = stored.get(CPU_COMPONENT) {
+                if let Err(error) = self.cluster.restore(&mut self.entities, entity, cpu) {
+                    self.entities.destroy_entity(entity);
+                    return Err(HivemindError::CorruptChunk { position, reason: error.to_string() });
+                }
+            }
+            spawned.push(entity);
+        }
+        Ok(spawned)
+    }
+    /// Bytes held by the Chunks, CPUs and components of the Simulation.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            chunks: self.worlds.iter().map(|world| world.chunk_bytes()).sum(),
+            cpus: self.cluster.memory_bytes(),
+            components: self.entities.component_bytes(),
+        }
+    }
+    pub fn memory_budget(&self) -> Option<MemoryBudget> { self.memory_budget }
+    /// Evict Chunks after every tick while over `budget`, see `budget`.
+    pub fn set_memory_budget(&mut self, budget: Option<MemoryBudget>) { self.memory_budget = budget }
+    ///
+    /// Unload clean, unoccupied Chunks, least recently used first across
+    /// every World, until within the memory budget. Returns how many went.
+    ///
+    pub fn enforce_memory_budget(&mut self) -> usize {
+        let mut usage = self.memory_usage();
+        let mut excess = match self.memory_budget {
+            Some(budget) => budget.excess(&usage),
+            None => 0,
+        };
+        if excess == 0 {
+            return 0;
+        }
+        let mut occupied = HashSet::new();
+        for (entity, position) in self.entities.iter::<Position>() {
+            let (x, _, z) = position.block();
+            let index = dimension::world_of(&self.entities, entity).index();
+            if let Some(world) = self.worlds.get(index) {
+                let (chunk, _, _) = signed_chunk_of(x, z);
+                if let Some(chunk) = world.bounds().resolve_chunk(chunk.x, chunk.y) {
+                    occupied.insert((index, chunk));
+                }
+            }
+        }
+        let mut candidates: Vec<(usize, Vector2<u64>)> = Vec::new();
+        for (index, world) in self.worlds.iter().enumerate() {
+            candidates.extend(world.eviction_order().into_iter()
+                .filter(|&position| !occupied.contains(&(index, position)) && !self.saved.contains_key(&(index, position)))
+                .map(|position| (index, position)));
+        }
+        let mut evicted = 0;
+        for (index, position) in candidates {
+            if excess == 0 {
+                break;
+            }
+            let before = self.worlds[index].chunk_bytes();
+            if let Ok(true) = self.worlds[index].unload_chunk(position) {
+                evicted += 1;
+                usage.chunks -= before - self.worlds[index].chunk_bytes();
+                excess = self.memory_budget.map_or(0, |budget| budget.excess(&usage));
+            }
+        }
+        evicted
+    }
+    /// Component types stored with unloaded Chunks.
+    pub fn codecs(&self) -> &ComponentCodecs { &self.codecs }
+    pub fn codecs_mut(&mut self) -> &mut ComponentCodecs { &mut self.codecs }
+    pub fn reflection(&self) -> &ComponentRegistry { &self.reflection }
+    pub fn reflection_mut(&mut self) -> &mut ComponentRegistry { &mut self.reflection }
+    /// Set a Component field of an entity from text, see `ComponentRegistry::set_text`.
+    pub fn set_field(&mut self, entity: EntityID, component: &str, field: &str, text: &str) -> Result<(), ReflectError> {
+        self.reflection.set_text(&mut self.entities, entity, component, field, text)
+    }
+    pub fn entities(&self) -> &EntityManager { &self.entities }
+    pub fn entities_mut(&mut self) -> &mut EntityManager { &mut self.entities }
+    pub fn cluster(&self) -> &HiveCluster { &self.cluster }
+    pub fn cluster_mut(&mut self) -> &mut HiveCluster { &mut self.cluster }
+    ///
+    /// Copy of the Simulation to advance on its own, for planners and tests
+    /// exploring outcomes. See `World::fork`, `EntityManager::fork` and
+    /// `HiveCluster::fork` for what is shared and what the fork leaves
+    /// behind. It has no Block updater, Systems or memory budget and starts
+    /// with empty Metrics; it keeps the tick, clock and speed of this one.
+    ///
+    pub fn fork(&self) -> Result<Simulation, HivemindError> {
+        Ok(Simulation {
+            worlds: self.worlds.iter().map(World::fork).collect(),
+            world_names: self.world_names.clone(),
+            portals: self.portals.clone(),
+            entities: self.entities.fork(),
+            cluster: self.cluster.fork()?,
+            physics: self.physics,
+            power: self.power.clone(),
+            updater: None,
+            systems: Vec::new(),
+            tick_rate: self.tick_rate,
+            speed: self.speed,
+            paused: self.paused,
+            accumulator: self.accumulator,
+            collisions: self.collisions.clone(),
+            power_events: self.power_events.clone(),
+            block_changes: self.block_changes.clone(),
+            metrics: Metrics::new(),
+            codecs: self.codecs.clone(),
+            reflection: self.reflection.clone(),
+            memory_budget: None,
+            recorder: None,
+            history: None,
+            saved: HashMap::new(),
+        })
+    }
+    /// Start a CPU embedded in `entity`, see `HiveCluster::attach`.
+    pub fn attach(&mut self, entity: EntityID, rom: &[u16]) -> Result<CpuId, HivemindError> {
+        self.cluster.attach(&mut self.entities, entity, rom)
+    }
+    /// Build a machine in `entity`, see `MachineTemplate::instantiate`.
+    pub fn instantiate(&mut self, entity: EntityID, template: &MachineTemplate, image: &Image) -> Result<CpuId, HivemindError> {
+        template.instantiate(&mut self.cluster, &mut self.entities, entity, image)
+    }
+    /// Call `f` with a CPU and its bus, see `HiveCluster::with_bus`.
+    pub fn with_cpu<R>(&mut self, id: CpuId, f: impl FnOnce(&mut VCPU16, &mut dyn Bus) -> R) -> Option<R> {
+        let world = self.cluster.owner(id).map_or(OVERWORLD, |owner| dimension::world_of(&self.entities, owner));
+        self.cluster.with_bus(id, self.worlds.get_mut(world.index())?, &mut self.entities, f)
+    }
+    pub fn physics(&self) -> &PhysicsSystem { &self.physics }
+    pub fn power(&self) -> &PowerSystem { &self.power }
+    /// Handle due Block updates, without one they are dropped.
+    pub fn set_updater(&mut self, updater: Option<Updater>) { self.updater = updater }
+    ///
+    /// Record each tick's events with `recorder` from the next tick on,
+    /// returning the recorder this replaces with its log flushed.
+    ///
+    pub fn set_recorder(&mut self, recorder: Option<EventRecorder>) -> Option<EventRecorder> {
+        let tick = self.tick();
+        let mut previous = self.recorder.take();
+        if let Some(ref mut previous) = previous {
+            // A failed flush is kept with the first write error, if any
+            let _ = previous.detach(&mut self.worlds[0]);
+        }
+        self.recorder = recorder;
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.attach(&mut self.worlds[0], &self.entities, tick);
+        }
+        previous
+    }
+    pub fn recorder(&self) -> Option<&EventRecorder> { self.recorder.as_ref() }
+    ///
+    /// Keep `history` from now on, starting with a keyframe of the current
+    /// tick, returning the History this replaces.
+    ///
+    pub fn set_history(&mut self, history: Option<History>) -> Option<History> {
+        let mut previous = self.history.take();
+        if let Some(ref mut previous) = previous {
+            previous.detach(&mut self.worlds[0]);
+        }
+        if let Some(mut history) = history {
+            history.attach(&mut self.worlds[0]);
+            history.keyframe(self);
+            self.history = Some(history);
+        }
+        previous
+    }
+    pub fn history(&self) -> Option<&History> { self.history.as_ref() }
+    ///
+    /// Fork of the Simulation as it was at the end of `tick`, rebuilt from its
+    /// History, see `History::rewind`. None if the tick is neither the current
+    /// one nor within the History kept.
+    ///
+    pub fn rewind(&self, tick: u64) -> Result<Option<Simulation>, HivemindError> {
+        match self.history {
+            _ if tick == self.tick() => self.fork().map(Some),
+            Some(ref history) => history.rewind(tick),
+            None => Ok(None),
+        }
+    }
+    /// Record a message from `owner` with the current tick, if recording.
+    pub fn record_message(&mut self, owner: Option<EntityID>, text: &str) {
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.message(owner, text);
+        }
+    }
+    pub fn add_system(&mut self, system: Box<dyn System>) { self.systems.push(system) }
+    /// Names of the added Systems in run order.
+    pub fn systems(&self) -> Vec<&str> { self.systems.iter().map(|system| system.name()).collect() }
+    /// Number of the last completed tick.
+    pub fn tick(&self) -> u64 { self.entities.resource::<Tick>().map_or(0, |tick| tick.0) }
+    pub fn tick_rate(&self) -> u32 { self.tick_rate }
+    /// Change the ticks per second, keeping physics in step.
+    pub fn set_tick_rate(&mut self, tick_rate: u32) {
+        self.tick_rate = tick_rate.max(1);
+        self.physics.timestep = Fixed::ONE / Fixed::from_int(self.tick_rate as i64);
+    }
+    /// Wall clock time per tick at normal speed.
+    pub fn tick_interval(&self) -> Duration { Duration::from_secs(1) / self.tick_rate }
+    pub fn speed(&self) -> u32 { self.speed }
+    /// Run `speed` times faster than real time, 1 for normal speed.
+    pub fn fast_forward(&mut self, speed: u32) { self.speed = speed.max(1) }
+    pub fn is_paused(&self) -> bool { self.paused }
+    /// Stop advancing and forget any time already accumulated.
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.accumulator = Duration::from_secs(0);
+    }
+    pub fn resume(&mut self) { self.paused = false }
+    /// Collisions from the last tick.
+    pub fn collisions(&self) -> &[Collision] { &self.collisions }
+    /// Power changes from the last tick.
+    pub fn power_events(&self) -> &[PowerEvent] { &self.power_events }
+    /// Blocks changed up to the end of the last tick.
+    pub fn block_changes(&self) -> &[BlockPosition] { &self.block_changes }
+    pub fn metrics(&self) -> &Metrics { &self.metrics }
+    pub fn metrics_mut(&mut self) -> &mut Metrics { &mut self.metrics }
+    ///
+    /// Account for `elapsed` wall clock time, running every tick which has
+    /// come due. Returns the number of ticks run.
+    ///
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        let due = self.accumulate(elapsed);
+        for _ in 0..due {
+            self.step();
+        }
+        due
+    }
+    ///
+    /// Account for `elapsed` wall clock time without stepping, returns the
+    /// number of ticks now due for the caller to run.
+    ///
+    pub fn accumulate(&mut self, elapsed: Duration) -> u32 {
+        if self.paused {
+            return 0;
+        }
+        let interval = self.tick_interval();
+        self.accumulator += elapsed * self.speed;
+        let limit = MAX_CATCH_UP * self.speed;
+        let mut due = 0;
+        while self.accumulator >= interval {
+            if due == limit {
+                self.accumulator = Duration::from_secs(0);
+                break;
+            }
+            self.accumulator -= interval;
+            due += 1;
+        }
+        due
+    }
+    ///
+    /// Run exactly one tick, whether or not the simulation is paused.
+    ///
+    pub fn step(&mut self) {
+        let mut history = self.history.take();
+        if let Some(ref mut history) = history {
+            history.before_tick(self);
+        }
+        self.metrics.begin_tick();
+        let started = Instant::now();
+        let mut lap = started;
+        let mut loaded = 0;
+        for index in 0..self.worlds.len() {
+            for position in self.worlds[index].sync_chunks() {
+                // A Chunk whose entities can't be restored still loads, as through `load_chunk`
+                let _ = self.spawn_pending(WorldId(index as u16), position);
+                loaded += 1;
+            }
+        }
+        self.metrics.add(CHUNKS_LOADED, loaded as u64);
+        record(&mut self.metrics, "chunks", &mut lap);
+        self.cluster.tick_worlds(&mut self.worlds, &mut self.entities);
+        self.metrics.add(CPU_CYCLES, self.cluster.cycles());
+        self.metrics.add(CPUS_STARVED, self.cluster.starved().len() as u64);
+        self.metrics.add(CPU_FAULTS, self.cluster.faults().len() as u64);
+        record(&mut self.metrics, "cpus", &mut lap);
+        self.collisions = match self.worlds.len() {
+            1 => self.physics.step(&self.worlds[0], &mut self.entities),
+            _ => {
+                let mut collisions = Vec::new();
+                for (index, world) in self.worlds.iter().enumerate() {
+                    collisions.extend(self.physics.step_in(world, &mut self.entities, WorldId(index as u16)));
+                }
+                collisions
+            }
+        };
+        record(&mut self.metrics, "physics", &mut lap);
+        self.portals.traverse(&mut self.entities);
+        record(&mut self.metrics, "portals", &mut lap);
+        self.power_events = self.power.tick(&mut self.entities);
+        record(&mut self.metrics, "power", &mut lap);
+        for world in self.worlds.iter_mut() {
+            world.tick_pheromones();
+        }
+        record(&mut self.metrics, "pheromones", &mut lap);
+        for world in self.worlds.iter_mut() {
+            match self.updater {
+                Some(ref mut updater) => world.tick_updates(&mut **updater),
+                None => world.tick_updates(&mut |_, _| {}),
+            };
+        }
+        record(&mut self.metrics, "updates", &mut lap);
+        for system in self.systems.iter_mut() {
+            system.run(&mut self.worlds[0], &mut self.entities);
+            record(&mut self.metrics, system.name(), &mut lap);
+        }
+        let evicted = self.enforce_memory_budget();
+        self.metrics.add(CHUNKS_EVICTED, evicted as u64);
+        record(&mut self.metrics, "memory", &mut lap);
+        self.block_changes = self.worlds[0].take_changes();
+        let tick = self.tick() + 1;
+        self.entities.insert_resource(Tick(tick));
+        if let Some(environment) = self.entities.resource_mut::<Environment>() {
+            environment.advance(1);
+        }
+        job::expire(&mut self.entities);
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.record(tick, &mut self.worlds[0], &self.entities, self.cluster.faults());
+        }
+        self.metrics.set_gauge(ENTITIES, self.entities.entities().len() as i64);
+        self.metrics.set_gauge(MEMORY_BYTES, self.memory_usage().total() as i64);
+        self.metrics.record(TICK, started.elapsed());
+        if let Some(mut history) = history {
+            history.after_tick(self);
+            self.history = Some(history);
+        }
+    }
+}
+
+/// Hash of stored entities, None for none at all.
+fn hash_of(stored: &[StoredEntity]) -> Option<u64> {
+    if stored.is_empty() {
+        return None;
+    }
+    let mut hasher = DefaultHasher::new();
+    stored.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// Time since `lap` into the named histogram, starting the next lap.
+fn record(metrics: &mut Metrics, name: &str, lap: &mut Instant) {
+    let now = Instant::now();
+    metrics.record(name, now - *lap);
+    *lap = now;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Simulation, Tick, MAX_CATCH_UP};
+    use math::Fixed;
+    use metrics::{CPU_CYCLES, ENTITIES, TICK};
+    use model::component::{Position, Velocity};
+    use model::entity::EntityManager;
+    use model::world::{Block, Chunk, Vector2, World};
+    use std::time::Duration;
+    use vcpu::cluster::CpuComponent;
+
+    #[test]
+    pub fn test_simulation_clock() {
+        let mut simulation = Simulation::new(World::new(), EntityManager::new());
+        let dust = simulation.entities_mut().create_entity();
+        simulation.entities_mut().add_component(dust, Position::from_f64(0.0, 4.0, 0.0));
+        simulation.entities_mut().add_component(dust, Velocity::from_f64(1.0, 0.0, 0.0));
+        assert_eq!(simulation.tick_interval(), Duration::from_millis(50));
+
+        // Partial intervals carry over to the next advance
+        assert_eq!(simulation.advance(Duration::from_millis(120)), 2);
+        assert_eq!(simulation.advance(Duration::from_millis(30)), 1);
+        assert_eq!(simulation.entities().resource::<Tick>(), Some(&Tick(3)));
+        assert_eq!(simulation.entities().get_component::<Position>(dust).unwrap().x, simulation.physics().timestep * Fixed::from_int(3));
+
+        // Paused time is lost, stepping still works
+        simulation.pause();
+        assert_eq!(simulation.advance(Duration::from_secs(1)), 0);
+        simulation.step();
+        assert_eq!(simulation.tick(), 4);
+        simulation.resume();
+
+        // A long stall is capped, fast forward scales both time and the cap
+        assert_eq!(simulation.advance(Duration::from_secs(10)), MAX_CATCH_UP);
+        assert_eq!(simulation.advance(Duration::from_millis(10)), 0);
+        simulation.fast_forward(4);
+        assert_eq!(simulation.advance(Duration::from_millis(100)), 8);
+        assert_eq!(simulation.tick(), 4 + MAX_CATCH_UP as u64 + 8);
+
+        // Every tick is timed stage by stage
+        assert_eq!(simulation.metrics().histogram(TICK).unwrap().count(), simulation.tick());
+        assert_eq!(simulation.metrics().histogram("physics").unwrap().count(), simulation.tick());
+        assert_eq!(simulation.metrics().gauge(ENTITIES), Some(1));
+        assert_eq!(simulation.metrics().counter(CPU_CYCLES).total, 0);
+    }
+
+    #[test]
+    pub fn test_fork() {
+        let mut simulation = Simulation::new(World::new(), EntityManager::new());
+        simulation.world_mut().insert_chunk(Vector2::new(0, 0), Box::new(Chunk::new()));
+        simulation.world_mut().insert_chunk(Vector2::new(1, 0), Box::new(Chunk::new()));
+        let drone = simulation.entities_mut().create_entity();
+        simulation.entities_mut().add_component(drone, Position::from_f64(1.0, 4.0, 1.0));
+        simulation.entities_mut().add_component(drone, Velocity::from_f64(1.0, 0.0, 0.0));
+        // ADD [0x8000], 1 then SET PC, 0
+        let cpu = simulation.attach(drone, &[0x8BC2, 0x8000, 0x8781]).unwrap();
+        simulation.step();
+
+        // The fork starts where the live Simulation is, sharing its Chunks
+        let mut fork = simulation.fork().unwrap();
+        assert_eq!(fork.tick(), 1);
+        assert_eq!(fork.world().shared_chunks(), 2);
+        assert_eq!(fork.entities().get_component::<Position>(drone), simulation.entities().get_component::<Position>(drone));
+        assert_eq!(fork.entities().get_component::<CpuComponent>(drone).map(|component| component.cpu), Some(cpu));
+        let counter = simulation.cluster().get(cpu).unwrap().get_memory(0x8000);
+        assert_eq!(fork.cluster().get(cpu).unwrap().get_memory(0x8000), counter);
+
+        // and runs on without touching it: a written Chunk is copied, the other stays shared
+        let rock = Block::new(fork.world().materials().id("rock").unwrap());
+        fork.world_mut().set_block(0, 0, 0, rock);
+        fork.entities_mut().destroy_entity(drone);
+        fork.step();
+        fork.step();
+        assert_eq!((fork.world().shared_chunks(), simulation.world().shared_chunks()), (1, 1));
+        assert!(simulation.world().get_block(0, 0, 0).unwrap().is_air());
+        assert!(simulation.entities().is_alive(drone));
+        assert_eq!(simulation.tick(), 1);
+        assert_eq!(simulation.cluster().get(cpu).unwrap().get_memory(0x8000), counter);
+        assert!(fork.cluster().is_empty());
+
+        // The live Simulation carries on as if never forked
+        simulation.step();
+        assert!(simulation.cluster().get(cpu).unwrap().get_memory(0x8000) > counter);
+        drop(fork);
+        assert_eq!(simulation.world().shared_chunks(), 0);
+    }
+}
@@ -0,0 +1,713 @@
+///
+/// VCPU16 Assembler
+///
+/// Assembles source in the usual DCPU-16 syntax into an Image, along with a
+/// SourceMap of its symbols and the source line of each instruction:
+///
+/// ```text
+/// ; comments run to the end of the line
+/// .org 0x0100          ; following words are placed at 0x0100
+/// .entry start         ; PC starts here, else at the first word
+/// :start SET A, 10     ; labels are written :name or name:
+/// loop:  ADD [data + I], 1
+///        IFN I, 4
+///            SET PC, loop
+/// data:  DAT 1, 2, "abc", 'x', data - 1
+/// ```
+///
+/// Operands are registers, PUSH, POP, PEEK, PICK n, SP, PC, EX, `[register]`,
+/// `[register + n]`, `[n]` and expressions of numbers, characters and labels
+/// joined by `+` and `-`. Mnemonics, registers and directives may be in any
+/// case, labels are case sensitive. An `a` operand between -1 and 30 with no
+/// labels in it is packed into the instruction, any other value takes a
+/// NEXT word.
+///
+/// `assemble_object` instead produces a relocatable Object, for the linker
+/// to combine with others.
+///
+use codec::invalid_data;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use vcpu::image::{Image, Segment};
+use vcpu::link::{Object, ObjectSymbol, Relocation, Section, Target};
+
+/// Binary mnemonics and their opcodes
+pub const BINARY_OPS: [(&str, u16); 27] = [
+    ("SET", 0x01), ("ADD", 0x02), ("SUB", 0x03), ("MUL", 0x04), ("MLI", 0x05), ("DIV", 0x06), ("DVI", 0x07),
+    ("MOD", 0x08), ("MDI", 0x09), ("AND", 0x0A), ("BOR", 0x0B), ("XOR", 0x0C), ("SHR", 0x0D), ("ASR", 0x0E),
+    ("SHL", 0x0F), ("IFB", 0x10), ("IFC", 0x11), ("IFE", 0x12), ("IFN", 0x13), ("IFG", 0x14), ("IFA", 0x15),
+    ("IFL", 0x16), ("IFU", 0x17), ("ADX", 0x1A), ("SBX", 0x1B), ("STI", 0x1E), ("STD", 0x1F),
+];
+/// Unary mnemonics and their opcodes
+pub const UNARY_OPS: [(&str, u16); 12] = [
+    ("JSR", 0x01), ("SLP", 0x02), ("INT", 0x08), ("IAG", 0x09), ("IAS", 0x0A), ("RFI", 0x0B),
+    ("IAQ", 0x0C), ("FCG", 0x0D), ("FVS", 0x0E), ("HWN", 0x10), ("HWQ", 0x11), ("HWI", 0x12),
+];
+/// Nullary mnemonics and their opcodes
+pub const NULLARY_OPS: [(&str, u16); 2] = [("NOP", 0x00), ("HIB", 0x01)];
+/// Register names in operand order
+pub const REGISTERS: [&str; 8] = ["A", "B", "C", "X", "Y", "Z", "I", "J"];
+
+///
+/// Assembly Error with the line it happened on
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl AsmError {
+    pub fn new<S: Into<String>>(line: usize, message: S) -> AsmError { AsmError { line, message: message.into() } }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "line {}: {}", self.line, self.message) }
+}
+
+///
+/// Symbols of a program and the source line each instruction came from
+///
+/// Written as a text artifact alongside the image, one entry per line:
+/// `symbol <name> <address>` and `line <address> <line>`, addresses in hex.
+///
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct SourceMap {
+    pub symbols: BTreeMap<String, u16>,
+    /// Source line of the instruction or data starting at each address
+    pub lines: BTreeMap<u16, usize>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap { SourceMap::default() }
+    pub fn symbol(&self, name: &str) -> Option<u16> { self.symbols.get(name).cloned() }
+    pub fn line(&self, address: u16) -> Option<usize> { self.lines.get(&address).cloned() }
+    /// First address assembled from `line`.
+    pub fn address(&self, line: usize) -> Option<u16> {
+        self.lines.iter().find(|&(_, &source)| source == line).map(|(&address, _)| address)
+    }
+    ///
+    /// Nearest symbol at or below `address` and the distance past it. Of
+    /// several symbols at one address the first by name is used.
+    ///
+    pub fn nearest(&self, address: u16) -> Option<(&str, u16)> {
+        self.symbols
+            .iter()
+            .filter(|&(_, &value)| value <= address)
+            .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0)))
+            .map(|(name, &value)| (name.as_str(), address - value))
+    }
+    /// First symbol by name at exactly `address`.
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.symbols.iter().find(|&(_, &value)| value == address).map(|(name, _)| name.as_str())
+    }
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        for (name, address) in self.symbols.iter() {
+            writeln!(writer, "symbol {} 0x{:04X}", name, address)?;
+        }
+        for (address, line) in self.lines.iter() {
+            writeln!(writer, "line 0x{:04X} {}", address, line)?;
+        }
+        Ok(())
+    }
+    pub fn read(reader: &mut dyn Read) -> io::Result<SourceMap> {
+        let mut map = SourceMap::new();
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [] => {}
+                ["symbol", name, address] => {
+                    map.symbols.insert(name.to_string(), number(address).ok_or_else(|| invalid_data("invalid symbol address"))?);
+                }
+                ["line", address, source] => {
+                    let address = number(address).ok_or_else(|| invalid_data("invalid line address"))?;
+                    map.lines.insert(address, source.parse().map_err(|_| invalid_data("invalid line number"))?);
+                }
+                _ => return Err(invalid_data("unknown source map entry")),
+            }
+        }
+        Ok(map)
+    }
+}
+
+///
+/// Assembled program
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Assembly {
+    /// Image with the program's symbols
+    pub image: Image,
+    pub map: SourceMap,
+}
+
+///
+/// Assemble `source`, every error is reported against its line.
+///
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    let module = parse(source, false)?;
+    let symbols: BTreeMap<String, u16> = module.labels.iter().map(|(name, &(_, address))| (name.clone(), address)).collect();
+    let mut image = Image::new();
+    let mut map = SourceMap::new();
+    for &(line, _, address, ref statement) in module.statements.iter() {
+        let words = statement.emit(&mut |expr, _| expr.evaluate(&symbols, line))?;
+        map.lines.insert(address, line);
+        match image.segments.last_mut() {
+            Some(segment) if segment.address as usize + segment.words.len() == address as usize => segment.words.extend(words),
+            _ => image.segments.push(Segment { address, words }),
+        }
+    }
+    image.entry = match module.entry {
+        Some((ref expr, line)) => expr.evaluate(&symbols, line)?,
+        None => image.segments.first().map_or(0, |segment| segment.address),
+    };
+    image.symbols = symbols.clone();
+    map.symbols = symbols;
+    Ok(Assembly { image, map })
+}
+
+///
+/// Assemble `source` into a relocatable Object named `name`, for the linker.
+/// Words are placed in sections instead of at addresses: `.section name`
+/// switches section, `.text` being the first. `.global label` exports a
+/// label to other objects and `.extern label` imports one; `.org` isn't
+/// allowed.
+///
+pub fn assemble_object(name: &str, source: &str) -> Result<Object, AsmError> {
+    let module = parse(source, true)?;
+    let mut object = Object::new(name);
+    object.sections = module.sections.iter().map(|name| Section { name: name.clone(), words: Vec::new(), lines: BTreeMap::new() }).collect();
+    for &(line, section, offset, ref statement) in module.statements.iter() {
+        let mut relocations = Vec::new();
+        let words = statement.emit(&mut |expr, index| {
+            let (value, targets) = module.relocatable(expr, line)?;
+            for (negative, target) in targets {
+                relocations.push(Relocation { section, offset: offset + index, target, negative });
+            }
+            Ok(value)
+        })?;
+        object.relocations.extend(relocations);
+        object.sections[section].lines.insert(offset, line);
+        object.sections[section].words.extend(words);
+    }
+    for (name, &(section, offset)) in module.labels.iter() {
+        object.symbols.insert(name.clone(), ObjectSymbol { section, offset, global: module.globals.contains_key(name) });
+    }
+    if let Some((name, &line)) = module.globals.iter().find(|&(name, _)| !module.labels.contains_key(name)) {
+        return Err(AsmError::new(line, format!("global {} is never defined", name)));
+    }
+    if let Some((ref expr, line)) = module.entry {
+        object.entry = match module.relocatable(expr, line)? {
+            (offset, ref targets) if targets.len() == 1 && !targets[0].0 => match targets[0].1 {
+                Target::Section(section) => Some((section, offset)),
+                Target::Symbol(_) => None,
+            },
+            _ => None,
+        };
+        if object.entry.is_none() {
+            return Err(AsmError::new(line, "entry must be a label in this object"));
+        }
+    }
+    Ok(object)
+}
+
+/// Source parsed and placed, before its labels are resolved
+struct Module {
+    sections: Vec<String>,
+    /// Line, section, address within the section and statement
+    statements: Vec<(usize, usize, u16, Statement)>,
+    /// Section and address of each label
+    labels: BTreeMap<String, (usize, u16)>,
+    /// Exported labels and the line exporting them
+    globals: BTreeMap<String, usize>,
+    externs: BTreeSet<String>,
+    entry: Option<(Expr, usize)>,
+}
+
+impl Module {
+    ///
+    /// Value of `expr` relative to the start of its sections, and the section
+    /// bases and external symbols the linker has to add or subtract. A label
+    /// subtracted from another in its section cancels out.
+    ///
+    fn relocatable(&self, expr: &Expr, line: usize) -> Result<(u16, Vec<(bool, Target)>), AsmError> {
+        let mut value = 0u16;
+        let mut targets: BTreeMap<Target, i32> = BTreeMap::new();
+        for (negative, term) in expr.terms.iter() {
+            let (term, target) = match term {
+                Term::Number(number) => (*number, None),
+                Term::Label(name) => match self.labels.get(name) {
+                    Some(&(section, offset)) => (offset, Some(Target::Section(section))),
+                    None if self.externs.contains(name) => (0, Some(Target::Symbol(name.clone()))),
+                    None => return Err(AsmError::new(line, format!("unknown label {}", name))),
+                },
+            };
+            value = if *negative { value.wrapping_sub(term) } else { value.wrapping_add(term) };
+            if let Some(target) = target {
+                *targets.entry(target).or_insert(0) += if *negative { -1 } else { 1 };
+            }
+        }
+        let mut relocations = Vec::new();
+        for (target, count) in targets {
+            for _ in 0..count.abs() {
+                relocations.push((count < 0, target.clone()));
+            }
+        }
+        Ok((value, relocations))
+    }
+}
+
+/// First pass: parse every line and place it
+fn parse(source: &str, relocatable: bool) -> Result<Module, AsmError> {
+    let mut module = Module {
+        sections: vec![".text".to_string()],
+        statements: Vec::new(),
+        labels: BTreeMap::new(),
+        globals: BTreeMap::new(),
+        externs: BTreeSet::new(),
+        entry: None,
+    };
+    let mut section = 0;
+    let mut addresses = vec![0usize];
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let error = |message: String| AsmError::new(line, message);
+        let (labels, rest) = split_labels(strip_comment(text));
+        for label in labels {
+            if !is_name(label) {
+                return Err(error(format!("invalid label {}", label)));
+            }
+            if module.labels.insert(label.to_string(), (section, addresses[section] as u16)).is_some() || module.externs.contains(label) {
+                return Err(error(format!("label {} is defined twice", label)));
+            }
+        }
+        if rest.is_empty() {
+            continue;
+        }
+        let (mnemonic, operands) = match rest.find(char::is_whitespace) {
+            Some(split) => (&rest[..split], rest[split..].trim()),
+            None => (rest, ""),
+        };
+        let operands = split_operands(operands);
+        let mnemonic = mnemonic.to_uppercase();
+        let statement = match mnemonic.as_str() {
+            ".ORG" if !relocatable => {
+                addresses[section] = constant(&single(&operands, line)?, line)? as usize;
+                continue;
+            }
+            ".ENTRY" => {
+                module.entry = Some((expression(&single(&operands, line)?, line)?, line));
+                continue;
+            }
+            ".SECTION" if relocatable => {
+                let name = single(&operands, line)?;
+                section = match module.sections.iter().position(|section| *section == name) {
+                    Some(section) => section,
+                    None => {
+                        module.sections.push(name);
+                        addresses.push(0);
+                        module.sections.len() - 1
+                    }
+                };
+                continue;
+            }
+            ".GLOBAL" | ".EXTERN" if relocatable => {
+                for name in operands {
+                    if !is_name(&name) {
+                        return Err(error(format!("invalid label {}", name)));
+                    }
+                    if mnemonic == ".GLOBAL" {
+                        module.globals.insert(name, line);
+                    } else if module.labels.contains_key(&name) {
+                        return Err(error(format!("label {} is defined twice", name)));
+                    } else {
+                        module.externs.insert(name);
+                    }
+                }
+                continue;
+            }
+            ".ORG" | ".SECTION" | ".GLOBAL" | ".EXTERN" => {
+                let mode = if relocatable { "objects" } else { "programs, assemble an object to link" };
+                return Err(error(format!("{} isn't allowed in {}", mnemonic.to_lowercase(), mode)));
+            }
+            "DAT" | ".DAT" => {
+                let mut words = Vec::new();
+                for operand in operands.iter() {
+                    match operand.strip_prefix('"').and_then(|text| text.strip_suffix('"')) {
+                        Some(text) => words.extend(text.chars().map(|c| Expr::number(c as u16))),
+                        None => words.push(expression(operand, line)?),
+                    }
+                }
+                Statement::Data(words)
+            }
+            name => instruction(name, &operands, line)?,
+        };
+        let length = statement.length();
+        if addresses[section] + length > 65536 {
+            return Err(error("program runs past the end of memory".to_string()));
+        }
+        module.statements.push((line, section, addresses[section] as u16, statement));
+        addresses[section] += length;
+    }
+    Ok(module)
+}
+
+/// Number in decimal, 0x hex, 0b binary or as a 'c' character.
+pub fn number(text: &str) -> Option<u16> {
+    let text = text.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits.trim()),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        u32::from_str_radix(binary, 2).ok()?
+    } else if digits.len() == 3 && digits.starts_with('\'') && digits.ends_with('\'') {
+        digits.chars().nth(1)? as u32
+    } else {
+        digits.parse::<u32>().ok()?
+    };
+    if value > 0xFFFF {
+        return None;
+    }
+    Some(if negative { (value as u16).wrapping_neg() } else { value as u16 })
+}
+
+/// Sum of numbers and labels
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Expr {
+    /// Each term and whether it is subtracted
+    terms: Vec<(bool, Term)>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Term {
+    Number(u16),
+    Label(String),
+}
+
+impl Expr {
+    fn number(value: u16) -> Expr { Expr { terms: vec![(false, Term::Number(value))] } }
+    fn is_constant(&self) -> bool { self.terms.iter().all(|(_, term)| matches!(term, Term::Number(_))) }
+    fn evaluate(&self, symbols: &BTreeMap<String, u16>, line: usize) -> Result<u16, AsmError> {
+        let mut sum = 0u16;
+        for (negative, term) in self.terms.iter() {
+            let value = match term {
+                Term::Number(value) => *value,
+                Term::Label(name) => *symbols.get(name).ok_or_else(|| AsmError::new(line, format!("unknown label {}", name)))?,
+            };
+            sum = if *negative { sum.wrapping_sub(value) } else { sum.wrapping_add(value) };
+        }
+        Ok(sum)
+    }
+}
+
+/// Operand value and the NEXT word it needs, if any
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Operand {
+    value: u16,
+    next: Option<Expr>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Statement {
+    Nullary(u16),
+    Unary(u16, Operand),
+    Binary(u16, Operand, Operand),
+    Data(Vec<Expr>),
+}
+
+impl Statement {
+    fn length(&self) -> usize {
+        let words = |operand: &Operand| operand.next.is_some() as usize;
+        match self {
+            Statement::Nullary(_) => 1,
+            Statement::Unary(_, a) => 1 + words(a),
+            Statement::Binary(_, b, a) => 1 + words(a) + words(b),
+            Statement::Data(words) => words.len(),
+        }
+    }
+    ///
+    /// Words of the statement, `resolve` giving the value of each expression
+    /// and its index among the words. NEXT words follow in the order they are
+    /// read, a before b.
+    ///
+    fn emit(&self, resolve: &mut dyn FnMut(&Expr, u16) -> Result<u16, AsmError>) -> Result<Vec<u16>, AsmError> {
+        let (word, operands) = match self {
+            Statement::Nullary(opcode) => (opcode << 10, vec![]),
+            Statement::Unary(opcode, a) => (a.value << 10 | opcode << 5, vec![a]),
+            Statement::Binary(opcode, b, a) => (a.value << 10 | b.value << 5 | opcode, vec![a, b]),
+            Statement::Data(words) => return words.iter().enumerate().map(|(index, word)| resolve(word, index as u16)).collect(),
+        };
+        let mut words = vec![word];
+        for next in operands.iter().filter_map(|operand| operand.next.as_ref()) {
+            words.push(resolve(next, words.len() as u16)?);
+        }
+        Ok(words)
+    }
+}
+
+fn instruction(name: &str, operands: &[String], line: usize) -> Result<Statement, AsmError> {
+    let count = |expected: usize| {
+        if operands.len() == expected {
+            Ok(())
+        } else {
+            Err(AsmError::new(line, format!("{} takes {} operands, not {}", name, expected, operands.len())))
+        }
+    };
+    if let Some(&(_, opcode)) = BINARY_OPS.iter().find(|&&(op, _)| op == name) {
+        count(2)?;
+        Ok(Statement::Binary(opcode, operand(&operands[0], false, line)?, operand(&operands[1], true, line)?))
+    } else if let Some(&(_, opcode)) = UNARY_OPS.iter().find(|&&(op, _)| op == name) {
+        count(1)?;
+        Ok(Statement::Unary(opcode, operand(&operands[0], true, line)?))
+    } else if let Some(&(_, opcode)) = NULLARY_OPS.iter().find(|&&(op, _)| op == name) {
+        count(0)?;
+        Ok(Statement::Nullary(opcode))
+    } else {
+        Err(AsmError::new(line, format!("unknown instruction {}", name)))
+    }
+}
+
+/// Operand `text`, `a` for the source operand which may pack small literals.
+fn operand(text: &str, a: bool, line: usize) -> Result<Operand, AsmError> {
+    let upper = text.to_uppercase();
+    let plain = |value: u16| Ok(Operand { value, next: None });
+    if let Some(register) = REGISTERS.iter().position(|&name| name == upper) {
+        return plain(register as u16);
+    }
+    match upper.as_str() {
+        "SP" => return plain(0x1B),
+        "PC" => return plain(0x1C),
+        "EX" => return plain(0x1D),
+        "PEEK" | "[SP]" => return plain(0x19),
+        "POP" | "[SP++]" if a => return plain(0x18),
+        "PUSH" | "[--SP]" if !a => return plain(0x18),
+        "POP" | "[SP++]" | "PUSH" | "[--SP]" => return Err(AsmError::new(line, format!("{} can't be used as this operand", text))),
+        _ => {}
+    }
+    if upper.starts_with("PICK ") {
+        return Ok(Operand { value: 0x1A, next: Some(expression(&text[5..], line)?) });
+    }
+    if let Some(inner) = text.strip_prefix('[').and_then(|text| text.strip_suffix(']')) {
+        let expr = expression(inner, line)?;
+        // A register inside brackets is the base of the address
+        let registers: Vec<usize> = expr.terms.iter().enumerate().filter(|(_, (_, term))| base_register(term).is_some()).map(|(index, _)| index).collect();
+        return match registers.as_slice() {
+            [] => Ok(Operand { value: 0x1E, next: Some(expr) }),
+            &[index] if !expr.terms[index].0 => {
+                let register = base_register(&expr.terms[index].1).unwrap();
+                let mut offset = expr.clone();
+                offset.terms.remove(index);
+                match (register, offset.terms.is_empty()) {
+                    (8, true) => plain(0x19),
+                    (8, false) => Ok(Operand { value: 0x1A, next: Some(offset) }),
+                    (register, true) => plain(0x08 + register),
+                    (register, false) => Ok(Operand { value: 0x10 + register, next: Some(offset) }),
+                }
+            }
+            _ => Err(AsmError::new(line, format!("invalid address {}", text))),
+        };
+    }
+    let expr = expression(text, line)?;
+    if a && expr.is_constant() {
+        let value = expr.evaluate(&BTreeMap::new(), line)?;
+        if value == 0xFFFF || value <= 30 {
+            return plain(value.wrapping_add(0x21) & 0x3F);
+        }
+    }
+    Ok(Operand { value: 0x1F, next: Some(expr) })
+}
+
+/// General register 0-7 or SP as 8, as named by a label term
+fn base_register(term: &Term) -> Option<u16> {
+    match term {
+        Term::Label(name) if name.eq_ignore_ascii_case("SP") => Some(8),
+        Term::Label(name) => REGISTERS.iter().position(|register| register.eq_ignore_ascii_case(name)).map(|index| index as u16),
+        Term::Number(_) => None,
+    }
+}
+
+fn expression(text: &str, line: usize) -> Result<Expr, AsmError> {
+    let mut terms = Vec::new();
+    let mut negative = false;
+    let mut current = String::new();
+    let mut push = |negative: bool, current: &mut String| -> Result<(), AsmError> {
+        let text = current.trim();
+        let term = if let Some(value) = number(text) {
+            Term::Number(value)
+        } else if is_name(text) {
+            Term::Label(text.to_string())
+        } else {
+            return Err(AsmError::new(line, format!("invalid value {}", text)));
+        };
+        terms.push((negative, term));
+        current.clear();
+        Ok(())
+    };
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '\'' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            '+' | '-' if !quoted => {
+                if current.trim().is_empty() {
+                    // Leading sign
+                    negative ^= c == '-';
+                } else {
+                    push(negative, &mut current)?;
+                    negative = c == '-';
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    push(negative, &mut current)?;
+    Ok(Expr { terms })
+}
+
+fn constant(text: &str, line: usize) -> Result<u16, AsmError> {
+    let expr = expression(text, line)?;
+    if !expr.is_constant() {
+        return Err(AsmError::new(line, format!("{} must be a constant", text)));
+    }
+    expr.evaluate(&BTreeMap::new(), line)
+}
+
+fn single(operands: &[String], line: usize) -> Result<String, AsmError> {
+    match operands {
+        [operand] => Ok(operand.clone()),
+        _ => Err(AsmError::new(line, "expected one operand")),
+    }
+}
+
+fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Line up to a `;` outside of quotes
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (index, c) in text.char_indices() {
+        match (c, quote) {
+            ('"', None) | ('\'', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            (';', None) => return text[..index].trim(),
+            _ => {}
+        }
+    }
+    text.trim()
+}
+
+/// Leading `:label` and `label:` definitions and what follows them
+fn split_labels(mut text: &str) -> (Vec<&str>, &str) {
+    let mut labels = Vec::new();
+    loop {
+        let end = text.find(char::is_whitespace).unwrap_or(text.len());
+        let word = &text[..end];
+        if let Some(label) = word.strip_prefix(':').or_else(|| word.strip_suffix(':')) {
+            labels.push(label);
+            text = text[end..].trim_start();
+        } else {
+            return (labels, text);
+        }
+    }
+}
+
+/// Comma separated operands, commas in quotes don't separate
+fn split_operands(text: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    for c in text.chars() {
+        match (c, quote) {
+            ('"', None) | ('\'', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            (',', None) => {
+                operands.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !operands.is_empty() {
+        operands.push(current.trim().to_string());
+    }
+    operands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, AsmError, SourceMap};
+    use std::io::Cursor;
+    use vcpu::cpu::VCPU16;
+
+    const COUNT: &str = "
+        ; Count the words of a table
+        .org 0x0010
+        :start  SET I, 0
+        loop:   IFE [table + I], 0
+                    SET PC, done
+                ADD I, 1
+                SET PC, loop
+        done:   SET A, I
+                HIB
+        table:  DAT 3, 0x1F, 'x', table, 0
+    ";
+
+    #[test]
+    pub fn test_assemble() {
+        let assembly = assemble(COUNT).unwrap();
+        let image = &assembly.image;
+        assert_eq!((image.entry, image.segments.len(), image.segments[0].address), (0x0010, 1, 0x0010));
+        assert_eq!(
+            image.segments[0].words,
+            vec![
+                0x84C1, // SET I, 0
+                0x86D2, 0x001A, // IFE [table + I], 0
+                0x7F81, 0x0018, // SET PC, done
+                0x88C2, // ADD I, 1
+                0x7F81, 0x0011, // SET PC, loop
+                0x1801, // SET A, I
+                0x0400, // HIB
+                3, 0x1F, 'x' as u16, 0x001A, 0,
+            ]
+        );
+        assert_eq!((assembly.map.symbol("loop"), assembly.map.symbol("table")), (Some(0x0011), Some(0x001A)));
+        assert_eq!((assembly.map.line(0x0011), assembly.map.address(8)), (Some(5), Some(0x0016)));
+        assert_eq!(assembly.map.nearest(0x0014), Some(("loop", 3)));
+        assert_eq!(image.symbols, assembly.map.symbols);
+
+        // Runs to the end of the table
+        let mut vcpu = VCPU16::new();
+        vcpu.load_image(image).unwrap();
+        vcpu.run(200);
+        assert!(vcpu.is_hibernating());
+        assert_eq!(vcpu.get_a(), 4);
+
+        // The map survives being written out
+        let mut artifact = Vec::new();
+        assembly.map.write(&mut artifact).unwrap();
+        assert!(String::from_utf8(artifact.clone()).unwrap().starts_with("symbol done 0x0018\n"));
+        assert_eq!(SourceMap::read(&mut Cursor::new(artifact)).unwrap(), assembly.map);
+    }
+
+    #[test]
+    pub fn test_operands() {
+        let words = |source: &str| assemble(source).unwrap().image.to_rom();
+        assert_eq!(words("SET PUSH, POP\nSET PEEK, [SP + 2]\nSET [A - 1], -1\nJSR 31"), vec![0x6301, 0x6B21, 0x0002, 0x8201, 0xFFFF, 0x7C20, 31]);
+        assert_eq!(words("set [0x1000], 'A'\nhwi [b]\nnop\nifg ex, pick 1"), vec![0x7FC1, 0x0041, 0x1000, 0x2640, 0x0000, 0x6BB4, 1]);
+        assert_eq!(assemble("SET A, missing").unwrap_err(), AsmError::new(1, "unknown label missing"));
+        assert_eq!(assemble("x: NOP\nx: NOP").unwrap_err(), AsmError::new(2, "label x is defined twice"));
+        assert_eq!(assemble("SET POP, A").unwrap_err().line, 1);
+        assert_eq!(assemble("\nJSR A, B").unwrap_err(), AsmError::new(2, "JSR takes 1 operands, not 2"));
+        assert!(assemble("FOO A").is_err());
+    }
+}
@@ -0,0 +1,1163 @@
+///
+/// Hive Cluster
+///
+/// Owns every running VCPU16 and ties each one to the entity it is embedded
+/// in. An entity carries a CpuComponent naming its CPU; ticking the cluster
+/// steps each CPU with its WorldInterface routed to that entity, and releases
+/// the CPU back to the pool once the entity is gone. A CPU whose entity is an
+/// unpowered Consumer hibernates, frozen mid-program, until power returns.
+/// Devices from the cluster's DeviceRegistry can be installed on a CPU, after
+/// its WorldInterface on the bus, and plugged or unplugged while it runs.
+/// Segments of the cluster's SharedMemory can be mapped into any number of
+/// CPUs, which see each other's writes from the next tick. Given a
+/// SwapStore, the memory of a CPU held for swap_after ticks is paged out to
+/// it and paged back in when the CPU is next run or borrowed mutably.
+/// A cluster can be forked, its CPUs sharing memory pages with the original
+/// until written.
+///
+/// Each CPU is in one of these PowerStates, starting Running:
+///
+/// ```text
+/// State        Moves to      When
+/// Off          Booting       power_on or reboot
+/// Booting      Running       its host has had BOOT_TICKS ticks of power
+/// Running      Hibernating   its host loses power, or it runs HIB
+/// Hibernating  Running       power returns, or an interrupt after HIB
+/// Running      Halted        a fault with no fault vector
+/// Halted       Booting       reboot
+/// any          Off           power_off
+/// ```
+///
+/// Booting warm resets the CPU, so it runs the program in its memory again
+/// from address 0. A CPU switched off keeps its memory and devices.
+///
+/// Every tick each runnable CPU wants its clock, or less if the BudgetPolicy
+/// ties its cycles to its Consumer's demand, plus whatever it was owed from
+/// earlier ticks. When the cluster's tick limit can't cover every want, each
+/// CPU is granted the same share of its want and the shortfall, up to the
+/// policy's carry limit, is owed to it next tick. Starved CPUs are reported,
+/// as are the faults raised by each CPU during the tick.
+///
+use devices::{Bus, Device, DeviceRegistry, Socket, HOTPLUG_MESSAGE};
+use devices::world::{WorldBus, WorldInterface};
+use error::HivemindError;
+use model::dimension::world_of;
+use model::entity::{EntityID, EntityManager};
+use model::power::Consumer;
+use model::world::World;
+use pool::Pool;
+use std::io;
+use std::slice;
+use vcpu::cpu::{Fault, VCPU16};
+use vcpu::memory::{Firmware, Memory, PAGE_WORDS};
+use vcpu::shared::{Mapping, SegmentId, SharedMemory};
+use vcpu::swap::SwapStore;
+
+/// Cycles a CPU runs per world tick unless configured otherwise
+pub const DEFAULT_CLOCK: u32 = 100;
+/// Powered ticks a CPU spends booting before it runs
+pub const BOOT_TICKS: u32 = 1;
+/// Ticks a CPU is held before its memory is swapped out, a minute at 20 ticks per second
+pub const DEFAULT_SWAP_AFTER: u32 = 1200;
+/// Fault code reported for a CPU whose memory couldn't be swapped back in, it stays held
+pub const FAULT_SWAP_IN: u16 = 0x0100;
+
+///
+/// Power State of a CPU
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PowerState {
+    /// Switched off, holding its memory but not running
+    Off,
+    /// Switched on and counting down its boot
+    Booting,
+    Running,
+    /// Held for lack of power, or waiting on an interrupt after HIB
+    Hibernating,
+    /// Stopped by a fault until rebooted
+    Halted,
+}
+
+///
+/// Cycle Throttling Policy
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BudgetPolicy {
+    /// Most cycles run across the cluster per tick, None for no limit
+    pub tick_limit: Option<u64>,
+    /// Most cycles a starved CPU is owed into later ticks
+    pub max_carry: u32,
+    /// Cycles per unit of Consumer demand, limiting CPUs with a Consumer
+    pub cycles_per_demand: Option<u32>,
+}
+
+impl Default for BudgetPolicy {
+    fn default() -> BudgetPolicy { BudgetPolicy { tick_limit: None, max_carry: DEFAULT_CLOCK, cycles_per_demand: None } }
+}
+
+///
+/// CPU granted fewer cycles than it wanted
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Starvation {
+    pub cpu: CpuId,
+    pub owner: Option<EntityID>,
+    /// Budget plus cycles owed
+    pub wanted: u64,
+    pub granted: u64,
+    /// Owed into the next tick
+    pub carried: u32,
+}
+
+///
+/// Fault raised by a CPU during a tick
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct CpuFault {
+    pub cpu: CpuId,
+    pub owner: Option<EntityID>,
+    pub fault: Fault,
+}
+
+///
+/// CPU Identifier
+///
+/// Slots are reused once a CPU is released, the generation distinguishes the
+/// new occupant of a slot from stale ids held by the previous one.
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct CpuId {
+    slot: usize,
+    generation: u32,
+}
+
+impl CpuId {
+    /// Id from the parts of another, such as one handed across the C API.
+    pub fn from_parts(slot: usize, generation: u32) -> CpuId { CpuId { slot, generation } }
+    pub fn slot(&self) -> usize { self.slot }
+    pub fn generation(&self) -> u32 { self.generation }
+}
+
+///
+/// Embedded CPU Component
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CpuComponent {
+    pub cpu: CpuId,
+    /// Cycles run per tick
+    pub clock: u32,
+    /// The CPU's view of the World, acting through this entity
+    pub interface: WorldInterface,
+}
+
+impl CpuComponent {
+    pub fn new(cpu: CpuId) -> CpuComponent {
+        CpuComponent { cpu, clock: DEFAULT_CLOCK, interface: WorldInterface::new() }
+    }
+}
+
+struct Slot {
+    generation: u32,
+    owner: Option<EntityID>,
+    cpu: Option<Box<VCPU16>>,
+    /// Held for lack of power
+    hibernated: bool,
+    /// Held by an administrator
+    paused: bool,
+    /// Installed after the WorldInterface
+    devices: Vec<Socket>,
+    /// Cycles owed from starved ticks
+    carry: u32,
+    /// Switched off
+    off: bool,
+    /// Powered ticks left before it runs
+    booting: u32,
+    /// Shared memory segments in its address space
+    mappings: Vec<Mapping>,
+    /// Ticks held in a row
+    idle: u32,
+    /// Memory paged out to the SwapStore
+    swapped: bool,
+}
+
+///
+/// Collection of running CPUs
+///
+#[derive(Default)]
+pub struct HiveCluster {
+    pool: Pool<VCPU16>,
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+    registry: DeviceRegistry,
+    /// Executed during the last tick
+    cycles: u64,
+    policy: BudgetPolicy,
+    starved: Vec<Starvation>,
+    faults: Vec<CpuFault>,
+    shared: SharedMemory,
+    swap: Option<Box<dyn SwapStore>>,
+    swap_after: u32,
+    /// Pages a swapped out CPU holds in place of its own
+    blank: Memory,
+}
+
+impl HiveCluster {
+    pub fn new() -> HiveCluster { HiveCluster::with_pool(Pool::new()) }
+    pub fn with_pool(pool: Pool<VCPU16>) -> HiveCluster {
+        HiveCluster { pool, slots: Vec::new(), free: Vec::new(), registry: DeviceRegistry::new(), cycles: 0, policy: BudgetPolicy::default(), starved: Vec::new(), faults: Vec::new(), shared: SharedMemory::new(), swap: None, swap_after: DEFAULT_SWAP_AFTER, blank: Memory::new() }
+    }
+    pub fn pool(&self) -> &Pool<VCPU16> { &self.pool }
+    pub fn device_registry(&self) -> &DeviceRegistry { &self.registry }
+    pub fn device_registry_mut(&mut self) -> &mut DeviceRegistry { &mut self.registry }
+    pub fn shared_memory(&self) -> &SharedMemory { &self.shared }
+    pub fn shared_memory_mut(&mut self) -> &mut SharedMemory { &mut self.shared }
+    /// Number of running CPUs.
+    pub fn len(&self) -> usize { self.slots.len() - self.free.len() }
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+    /// Cycles stepped across every CPU during the last tick.
+    pub fn cycles(&self) -> u64 { self.cycles }
+    pub fn policy(&self) -> BudgetPolicy { self.policy }
+    pub fn set_policy(&mut self, policy: BudgetPolicy) { self.policy = policy }
+    /// CPUs granted less than they wanted during the last tick, in slot order.
+    pub fn starved(&self) -> &[Starvation] { &self.starved }
+    /// Faults raised during the last tick, in slot order.
+    pub fn faults(&self) -> &[CpuFault] { &self.faults }
+    /// Cycles a CPU is owed from starved ticks.
+    pub fn carry(&self, id: CpuId) -> u32 { self.slot(id).map_or(0, |slot| slot.carry) }
+    /// Start a CPU with no owner, loading `rom` at address 0.
+    pub fn spawn(&mut self, rom: &[u16]) -> Result<CpuId, HivemindError> {
+        let mut cpu = self.pool.acquire();
+        if let Err(error) = cpu.load_rom(rom) {
+            self.pool.release(cpu);
+            return Err(error);
+        }
+        Ok(self.start(cpu))
+    }
+    ///
+    /// Start a CPU with no owner, sharing the pages of `firmware` until it
+    /// writes to them.
+    ///
+    pub fn spawn_firmware(&mut self, firmware: &Firmware) -> CpuId {
+        let mut cpu = self.pool.acquire();
+        cpu.load_firmware(firmware);
+        self.start(cpu)
+    }
+    /// Give a loaded CPU a slot.
+    fn start(&mut self, cpu: Box<VCPU16>) -> CpuId {
+        match self.free.pop() {
+            Some(slot) => {
+                let entry = &mut self.slots[slot];
+                entry.cpu = Some(cpu);
+                entry.hibernated = false;
+                entry.paused = false;
+                entry.carry = 0;
+                entry.off = false;
+                entry.booting = 0;
+                entry.mappings.clear();
+                entry.idle = 0;
+                entry.swapped = false;
+                CpuId { slot, generation: entry.generation }
+            }
+            None => {
+                self.slots.push(Slot { generation: 0, owner: None, cpu: Some(cpu), hibernated: false, paused: false, devices: Vec::new(), carry: 0, off: false, booting: 0, mappings: Vec::new(), idle: 0, swapped: false });
+                CpuId { slot: self.slots.len() - 1, generation: 0 }
+            }
+        }
+    }
+    ///
+    /// Start a CPU embedded in `entity`, adding its CpuComponent. Any CPU the
+    /// entity already carried is released.
+    ///
+    pub fn attach(&mut self, entities: &mut EntityManager, entity: EntityID, rom: &[u16]) -> Result<CpuId, HivemindError> {
+        if !entities.is_alive(entity) {
+            return Err(HivemindError::DeadEntity(entity));
+        }
+        let cpu = self.spawn(rom)?;
+        self.embed(entities, entity, cpu);
+        Ok(cpu)
+    }
+    /// `attach` with a CPU sharing the pages of `firmware`.
+    pub fn attach_firmware(&mut self, entities: &mut EntityManager, entity: EntityID, firmware: &Firmware) -> Result<CpuId, HivemindError> {
+        if !entities.is_alive(entity) {
+            return Err(HivemindError::DeadEntity(entity));
+        }
+        let cpu = self.spawn_firmware(firmware);
+        self.embed(entities, entity, cpu);
+        Ok(cpu)
+    }
+    ///
+    /// Serialize the CPU embedded in `entity`: its clock then its memory
+    /// image, see `VCPU16::save_memory`. None if the entity runs no CPU.
+    ///
+    pub fn store(&mut self, entities: &EntityManager, entity: EntityID) -> Option<Result<Vec<u8>, HivemindError>> {
+        let component = entities.get_component::<CpuComponent>(entity).filter(|component| self.contains(component.cpu))?;
+        let mut payload = component.clock.to_le_bytes().to_vec();
+        let result = match self.get_mut(component.cpu) {
+            Some(cpu) => cpu.save_memory(&mut payload),
+            None => Err(HivemindError::from(io::Error::other("cpu memory couldn't be swapped in"))),
+        };
+        Some(result.map(|_| payload))
+    }
+    ///
+    /// Start a CPU embedded in `entity` from a payload written by `store`.
+    /// Only memory is stored, so it boots from address 0.
+    ///
+    pub fn restore(&mut self, entities: &mut EntityManager, entity: EntityID, payload: &[u8]) -> Result<CpuId, HivemindError> {
+        if !entities.is_alive(entity) {
+            return Err(HivemindError::DeadEntity(entity));
+        }
+        if payload.len() < 4 {
+            return Err(HivemindError::from(io::Error::from(io::ErrorKind::UnexpectedEof)));
+        }
+        let mut cpu = self.pool.acquire();
+        if let Err(error) = cpu.load_memory(&mut &payload[4..]) {
+            self.pool.release(cpu);
+            return Err(error);
+        }
+        let cpu = self.start(cpu);
+        self.embed(entities, entity, cpu);
+        if let Some(component) = entities.get_component_mut::<CpuComponent>(entity) {
+            component.clock = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
+        }
+        Ok(cpu)
+    }
+    fn embed(&mut self, entities: &mut EntityManager, entity: EntityID, cpu: CpuId) {
+        self.slots[cpu.slot].owner = Some(entity);
+        if let Some(previous) = entities.add_component(entity, CpuComponent::new(cpu)) {
+            self.release(previous.cpu);
+        }
+    }
+    ///
+    /// Memory pages held by the running CPUs, a page shared between them or
+    /// with a Firmware counted once.
+    ///
+    pub fn memory_pages(&self) -> usize {
+        Memory::distinct_pages(self.slots.iter().filter_map(|slot| slot.cpu.as_ref()).map(|cpu| cpu.memory()))
+    }
+    /// Bytes of memory held by the running CPUs, see memory_pages.
+    pub fn memory_bytes(&self) -> usize { self.memory_pages() * PAGE_WORDS * 2 }
+    pub fn contains(&self, id: CpuId) -> bool { self.slot(id).is_some() }
+    fn slot(&self, id: CpuId) -> Option<&Slot> {
+        self.slots.get(id.slot).filter(|slot| slot.generation == id.generation && slot.cpu.is_some())
+    }
+    ///
+    /// A running CPU. The memory of a swapped out CPU reads as zeros where it
+    /// was paged out, `get_mut` or `swap_in` bring it back.
+    ///
+    pub fn get(&self, id: CpuId) -> Option<&VCPU16> { self.slot(id).and_then(|slot| slot.cpu.as_deref()) }
+    /// A running CPU, swapped in first. None if its memory can't be swapped back in.
+    pub fn get_mut(&mut self, id: CpuId) -> Option<&mut VCPU16> {
+        self.swap_in(id).ok()?;
+        match self.slots.get_mut(id.slot) {
+            Some(slot) if slot.generation == id.generation => slot.cpu.as_deref_mut(),
+            _ => None,
+        }
+    }
+    /// Entity the CPU is embedded in.
+    pub fn owner(&self, id: CpuId) -> Option<EntityID> { self.slot(id).and_then(|slot| slot.owner) }
+    /// CPU is held because its entity is out of power.
+    pub fn is_hibernated(&self, id: CpuId) -> bool { self.slot(id).is_some_and(|slot| slot.hibernated) }
+    /// CPU is held until resumed, whatever its power.
+    pub fn is_paused(&self, id: CpuId) -> bool { self.slot(id).is_some_and(|slot| slot.paused) }
+    /// Hold a CPU mid-program, returns false if it isn't running.
+    pub fn pause(&mut self, id: CpuId) -> bool { self.set_paused(id, true) }
+    pub fn resume(&mut self, id: CpuId) -> bool { self.set_paused(id, false) }
+    fn set_paused(&mut self, id: CpuId, paused: bool) -> bool {
+        if !self.contains(id) {
+            return false;
+        }
+        self.slots[id.slot].paused = paused;
+        true
+    }
+    ///
+    /// Where a CPU is in its power cycle, None if it isn't running. A CPU
+    /// held for lack of power reads as Hibernating whatever its program does.
+    ///
+    pub fn power_state(&self, id: CpuId) -> Option<PowerState> {
+        let slot = self.slot(id)?;
+        let cpu = slot.cpu.as_deref().unwrap();
+        Some(if slot.off {
+            PowerState::Off
+        } else if slot.booting > 0 {
+            PowerState::Booting
+        } else if cpu.is_halted() {
+            PowerState::Halted
+        } else if slot.hibernated || cpu.is_hibernating() {
+            PowerState::Hibernating
+        } else {
+            PowerState::Running
+        })
+    }
+    ///
+    /// Switch a CPU off, keeping its memory and devices. Returns false if it
+    /// isn't running or is already off.
+    ///
+    pub fn power_off(&mut self, id: CpuId) -> bool {
+        if !self.contains(id) || self.slots[id.slot].off {
+            return false;
+        }
+        let slot = &mut self.slots[id.slot];
+        slot.off = true;
+        slot.booting = 0;
+        slot.carry = 0;
+        true
+    }
+    ///
+    /// Switch a CPU on with a warm reset, so it boots the program in its
+    /// memory from address 0 once its host has had BOOT_TICKS of power.
+    /// Returns false if it isn't running or is already on.
+    ///
+    pub fn power_on(&mut self, id: CpuId) -> bool {
+        if !self.contains(id) || !self.slots[id.slot].off {
+            return false;
+        }
+        self.slots[id.slot].off = false;
+        self.boot(id);
+        true
+    }
+    /// Warm reset a CPU that is on, or switch it on, returns false if it isn't running.
+    pub fn reboot(&mut self, id: CpuId) -> bool {
+        if !self.contains(id) {
+            return false;
+        }
+        self.slots[id.slot].off = false;
+        self.boot(id);
+        true
+    }
+    fn boot(&mut self, id: CpuId) {
+        let slot = &mut self.slots[id.slot];
+        slot.cpu.as_deref_mut().unwrap().reset(false);
+        slot.booting = BOOT_TICKS;
+        slot.carry = 0;
+    }
+    /// Add a device to a CPU's bus, returns false if the CPU isn't running.
+    pub fn add_device(&mut self, id: CpuId, device: Box<dyn Device>) -> bool { self.plug(id, device).is_some() }
+    ///
+    /// Plug a device into the first empty socket on a CPU's bus, or a new one
+    /// after the rest, and interrupt the CPU with HOTPLUG_MESSAGE. Returns the
+    /// device's index on the bus, None if the CPU isn't running.
+    ///
+    pub fn plug(&mut self, id: CpuId, device: Box<dyn Device>) -> Option<u16> {
+        if !self.contains(id) {
+            return None;
+        }
+        let slot = &mut self.slots[id.slot];
+        let socket = match slot.devices.iter().position(Option::is_none) {
+            Some(socket) => socket,
+            None => {
+                slot.devices.push(None);
+                slot.devices.len() - 1
+            }
+        };
+        slot.devices[socket] = Some(device);
+        slot.cpu.as_deref_mut().unwrap().interrupt(HOTPLUG_MESSAGE);
+        Some(socket as u16 + slot.owner.map_or(0, |_| 1))
+    }
+    ///
+    /// Unplug the device at `index` on a CPU's bus and interrupt the CPU with
+    /// HOTPLUG_MESSAGE. The devices after it keep their indices, its socket
+    /// stays empty until the next plug unless it was the last. The
+    /// WorldInterface of an embedded CPU can't be unplugged.
+    ///
+    pub fn unplug(&mut self, id: CpuId, index: u16) -> Option<Box<dyn Device>> {
+        if !self.contains(id) {
+            return None;
+        }
+        let slot = &mut self.slots[id.slot];
+        let socket = match slot.owner {
+            Some(_) => (index as usize).checked_sub(1)?,
+            None => index as usize,
+        };
+        let device = slot.devices.get_mut(socket)?.take()?;
+        while slot.devices.last().is_some_and(Option::is_none) {
+            slot.devices.pop();
+        }
+        slot.cpu.as_deref_mut().unwrap().interrupt(HOTPLUG_MESSAGE);
+        Some(device)
+    }
+    /// Add a device from the registry, returns false if the name is unknown or the CPU isn't running.
+    pub fn install(&mut self, id: CpuId, name: &str) -> bool {
+        match self.registry.create(name) {
+            Some(device) => self.add_device(id, device),
+            None => false,
+        }
+    }
+    ///
+    /// Map a shared memory segment into a CPU's memory from `address`, its
+    /// words there replaced by the segment's from the next tick. Returns false
+    /// if the CPU isn't running, or the segment doesn't exist, runs past the
+    /// end of memory or overlaps a segment already mapped.
+    ///
+    pub fn map_segment(&mut self, id: CpuId, segment: SegmentId, address: u16) -> bool {
+        let mapping = Mapping { segment, address };
+        if !self.contains(id) || !self.shared.fits(mapping, &self.slots[id.slot].mappings) {
+            return false;
+        }
+        self.slots[id.slot].mappings.push(mapping);
+        true
+    }
+    /// Stop sharing a segment with a CPU, which keeps its last copy of the words.
+    pub fn unmap_segment(&mut self, id: CpuId, segment: SegmentId) -> bool {
+        if !self.contains(id) {
+            return false;
+        }
+        let mappings = &mut self.slots[id.slot].mappings;
+        let before = mappings.len();
+        mappings.retain(|mapping| mapping.segment != segment);
+        mappings.len() < before
+    }
+    pub fn mappings(&self, id: CpuId) -> &[Mapping] { self.slot(id).map_or(&[], |slot| &slot.mappings[..]) }
+    /// Number of devices installed beyond the WorldInterface.
+    pub fn device_count(&self, id: CpuId) -> usize { self.slot(id).map_or(0, |slot| slot.devices.iter().flatten().count()) }
+    ///
+    /// Swap the memory of CPUs held `after` ticks out to `store`, or stop
+    /// swapping for None. CPUs swapped out to the previous store are swapped
+    /// back in first, and it is kept if one can't be.
+    ///
+    pub fn set_swap(&mut self, store: Option<Box<dyn SwapStore>>, after: u32) -> Result<(), HivemindError> {
+        for id in self.ids() {
+            self.swap_in(id)?;
+        }
+        self.swap = store;
+        self.swap_after = after;
+        Ok(())
+    }
+    /// Ticks a CPU is held before it is swapped out.
+    pub fn swap_after(&self) -> u32 { self.swap_after }
+    /// CPU's memory is paged out to the SwapStore.
+    pub fn is_swapped(&self, id: CpuId) -> bool { self.slot(id).is_some_and(|slot| slot.swapped) }
+    /// Number of CPUs swapped out.
+    pub fn swapped(&self) -> usize { self.slots.iter().filter(|slot| slot.cpu.is_some() && slot.swapped).count() }
+    ///
+    /// Page the memory a CPU holds alone out to the SwapStore, keeping the
+    /// pages it shares. Returns false if it isn't running, is already swapped
+    /// out or there is no store.
+    ///
+    pub fn swap_out(&mut self, id: CpuId) -> Result<bool, HivemindError> {
+        let store = match self.swap.as_mut() {
+            Some(store) if self.slots.get(id.slot).is_some_and(|slot| slot.generation == id.generation && slot.cpu.is_some() && !slot.swapped) => store,
+            _ => return Ok(false),
+        };
+        let slot = &mut self.slots[id.slot];
+        let cpu = slot.cpu.as_deref_mut().unwrap();
+        let mut pages = Vec::new();
+        cpu.page_out(&self.blank, &mut pages)?;
+        if let Err(error) = store.store(id, &pages) {
+            cpu.page_in(&mut &pages[..])?;
+            return Err(error.into());
+        }
+        slot.swapped = true;
+        Ok(true)
+    }
+    ///
+    /// Page a swapped out CPU's memory back in. Returns false if it isn't
+    /// running or isn't swapped out.
+    ///
+    pub fn swap_in(&mut self, id: CpuId) -> Result<bool, HivemindError> {
+        if !self.is_swapped(id) {
+            return Ok(false);
+        }
+        let store = self.swap.as_mut().expect("CPU swapped out without a SwapStore");
+        let pages = store.load(id)?;
+        let slot = &mut self.slots[id.slot];
+        slot.cpu.as_deref_mut().unwrap().page_in(&mut &pages[..])?;
+        slot.swapped = false;
+        store.discard(id);
+        Ok(true)
+    }
+    /// Count a tick held, swapping the CPU out once it has been held swap_after ticks.
+    fn hold(&mut self, id: CpuId) {
+        let slot = &mut self.slots[id.slot];
+        slot.idle = slot.idle.saturating_add(1);
+        if slot.idle >= self.swap_after {
+            // A CPU which can't be swapped out stays in memory
+            let _ = self.swap_out(id);
+        }
+    }
+    /// Swap a CPU in to run it, returns false and reports a fault if it can't be.
+    fn wake(&mut self, id: CpuId, owner: Option<EntityID>) -> bool {
+        self.slots[id.slot].idle = 0;
+        if self.swap_in(id).is_err() {
+            self.faults.push(CpuFault { cpu: id, owner, fault: Fault { code: FAULT_SWAP_IN, address: 0 } });
+            return false;
+        }
+        true
+    }
+    /// Halt a CPU and return it to the pool, returns false if it wasn't running.
+    pub fn release(&mut self, id: CpuId) -> bool {
+        if !self.contains(id) {
+            return false;
+        }
+        if self.slots[id.slot].swapped {
+            if let Some(store) = self.swap.as_mut() {
+                store.discard(id);
+            }
+        }
+        let slot = &mut self.slots[id.slot];
+        let cpu = slot.cpu.take().unwrap();
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.owner = None;
+        slot.devices.clear();
+        self.pool.release(cpu);
+        self.free.push(id.slot);
+        true
+    }
+    ///
+    /// Call `f` with a CPU and the bus it sees during a tick, its
+    /// WorldInterface acting through its owner, so a debugger can step it
+    /// outside of `tick`. The interface's tick isn't begun. Returns None if
+    /// the CPU isn't running, can't be swapped in or its owner no longer
+    /// carries it.
+    ///
+    pub fn with_bus<R>(&mut self, id: CpuId, world: &mut World, entities: &mut EntityManager, f: impl FnOnce(&mut VCPU16, &mut dyn Bus) -> R) -> Option<R> {
+        if !self.contains(id) || self.swap_in(id).is_err() {
+            return None;
+        }
+        let slot = &mut self.slots[id.slot];
+        let cpu = slot.cpu.as_deref_mut().unwrap();
+        let owner = match slot.owner {
+            Some(owner) => owner,
+            None => return Some(f(cpu, &mut slot.devices)),
+        };
+        let mut component = match entities.remove_component::<CpuComponent>(owner) {
+            Some(component) if component.cpu == id => component,
+            other => {
+                if let Some(component) = other {
+                    entities.add_component(owner, component);
+                }
+                return None;
+            }
+        };
+        let result = {
+            let mut bus = WorldBus { interface: &mut component.interface, world, entities, host: owner, devices: &mut slot.devices };
+            f(cpu, &mut bus)
+        };
+        entities.add_component(owner, component);
+        Some(result)
+    }
+    /// Running CPUs in slot order.
+    pub fn ids(&self) -> Vec<CpuId> {
+        self.slots.iter().enumerate()
+            .filter(|&(_, slot)| slot.cpu.is_some())
+            .map(|(index, slot)| CpuId { slot: index, generation: slot.generation })
+            .collect()
+    }
+    ///
+    /// Run every CPU for one world tick, after ticking the devices on its
+    /// bus. Embedded CPUs run their granted cycles with their WorldInterface
+    /// acting through the owning entity;
+    /// CPUs whose owner died or dropped its CpuComponent are released, and
+    /// those which are off, booting, paused or whose owner is an unpowered
+    /// Consumer are skipped. Returns the number of CPUs released.
+    /// CPUs swapped out are swapped in before they run, those whose memory
+    /// can't be are held and reported with a FAULT_SWAP_IN fault.
+    ///
+    pub fn tick(&mut self, world: &mut World, entities: &mut EntityManager) -> usize { self.tick_worlds(slice::from_mut(world), entities) }
+    ///
+    /// Tick as `tick`, each embedded CPU acting on the World its owner lives
+    /// in, indexed by WorldId. CPUs whose owner is in a World not given are
+    /// held like hibernated ones, without being swapped in or granted cycles.
+    ///
+    pub fn tick_worlds(&mut self, worlds: &mut [World], entities: &mut EntityManager) -> usize {
+        let mut released = 0;
+        let mut runnable = Vec::new();
+        self.faults.clear();
+        for id in self.ids() {
+            let held = self.is_paused(id) || self.slots[id.slot].off;
+            let owner = match self.owner(id) {
+                Some(owner) => owner,
+                None if held => {
+                    self.hold(id);
+                    continue;
+                }
+                None if self.slots[id.slot].booting > 0 => {
+                    self.slots[id.slot].booting -= 1;
+                    continue;
+                }
+                None => {
+                    if self.wake(id, None) {
+                        runnable.push((id, None, DEFAULT_CLOCK));
+                    }
+                    continue;
+                }
+            };
+            let component = match entities.get_component::<CpuComponent>(owner) {
+                Some(component) if component.cpu == id => component,
+                _ => {
+                    self.release(id);
+                    released += 1;
+                    continue;
+                }
+            };
+            let consumer = entities.get_component::<Consumer>(owner);
+            let hibernated = consumer.is_some_and(|consumer| !consumer.powered);
+            self.slots[id.slot].hibernated = hibernated;
+            if hibernated || held || world_of(entities, owner).index() >= worlds.len() {
+                self.hold(id);
+                continue;
+            }
+            let slot = &mut self.slots[id.slot];
+            if slot.booting > 0 {
+                slot.booting -= 1;
+                continue;
+            }
+            let budget = match (self.policy.cycles_per_demand, consumer) {
+                (Some(rate), Some(consumer)) => component.clock.min(consumer.demand.saturating_mul(rate)),
+                _ => component.clock,
+            };
+            if self.wake(id, Some(owner)) {
+                runnable.push((id, Some(owner), budget));
+            }
+        }
+        let grants = self.grant(&runnable);
+
+        self.cycles = 0;
+        for (&(id, owner, _), &granted) in runnable.iter().zip(grants.iter()) {
+            let slot = &mut self.slots[id.slot];
+            let cpu = slot.cpu.as_deref_mut().unwrap();
+            for device in slot.devices.iter_mut().flatten() {
+                device.tick(cpu);
+            }
+            self.shared.load(cpu, &slot.mappings);
+            match owner {
+                None => {
+                    cpu.run_with(granted, &mut slot.devices);
+                }
+                Some(owner) => {
+                    // Checked above, and CPUs don't move their hosts between Worlds
+                    let world = &mut worlds[world_of(entities, owner).index()];
+                    // An earlier CPU may have destroyed this one's host, it is released next tick
+                    let mut component = match entities.remove_component::<CpuComponent>(owner) {
+                        Some(component) if component.cpu == id => component,
+                        other => {
+                            if let Some(component) = other {
+                                entities.add_component(owner, component);
+                            }
+                            continue;
+                        }
+                    };
+                    component.interface.begin_tick();
+                    {
+                        let mut bus = WorldBus { interface: &mut component.interface, world, entities, host: owner, devices: &mut slot.devices };
+                        cpu.run_with(granted, &mut bus);
+                    }
+                    entities.add_component(owner, component);
+                }
+            }
+            self.shared.record(cpu, &slot.mappings);
+            self.cycles += granted;
+            for fault in cpu.take_faults() {
+                self.faults.push(CpuFault { cpu: id, owner, fault });
+            }
+        }
+        self.shared.commit();
+        released
+    }
+    ///
+    /// Copy of the cluster to run ahead without touching this one. CPUs share
+    /// their memory pages with the originals until written, those swapped out
+    /// are read back in. Devices which can't fork leave their sockets empty,
+    /// so a fork never plays through the live speakers or writes to the live
+    /// serial lines. The fork starts with an empty DeviceRegistry, a fresh
+    /// pool and no SwapStore.
+    ///
+    pub fn fork(&self) -> Result<HiveCluster, HivemindError> {
+        let mut slots = Vec::with_capacity(self.slots.len());
+        for (index, slot) in self.slots.iter().enumerate() {
+            let mut cpu = slot.cpu.clone();
+            if let (true, Some(cpu), Some(store)) = (slot.swapped, cpu.as_mut(), self.swap.as_ref()) {
+                let pages = store.load(CpuId { slot: index, generation: slot.generation })?;
+                cpu.page_in(&mut &pages[..])?;
+            }
+            slots.push(Slot {
+                generation: slot.generation,
+                owner: slot.owner,
+                cpu,
+                hibernated: slot.hibernated,
+                paused: slot.paused,
+                devices: slot.devices.iter().map(|device| device.as_ref().and_then(|device| device.fork())).collect(),
+                carry: slot.carry,
+                off: slot.off,
+                booting: slot.booting,
+                mappings: slot.mappings.clone(),
+                idle: slot.idle,
+                swapped: false,
+            });
+        }
+        Ok(HiveCluster {
+            pool: Pool::new(),
+            slots,
+            free: self.free.clone(),
+            registry: DeviceRegistry::new(),
+            cycles: self.cycles,
+            policy: self.policy,
+            starved: self.starved.clone(),
+            faults: self.faults.clone(),
+            shared: self.shared.clone(),
+            swap: None,
+            swap_after: self.swap_after,
+            blank: Memory::new(),
+        })
+    }
+    ///
+    /// Share the tick limit among runnable CPUs, updating what each is owed
+    /// and the starvation report. Returns the cycles granted to each.
+    ///
+    fn grant(&mut self, runnable: &[(CpuId, Option<EntityID>, u32)]) -> Vec<u64> {
+        let wants: Vec<u64> = runnable.iter().map(|&(id, _, budget)| budget as u64 + self.slots[id.slot].carry as u64).collect();
+        let total: u64 = wants.iter().sum();
+        let limit = self.policy.tick_limit.unwrap_or(u64::MAX);
+        self.starved.clear();
+        let mut grants = Vec::with_capacity(runnable.len());
+        for (&(id, owner, _), &wanted) in runnable.iter().zip(wants.iter()) {
+            let granted = if total <= limit { wanted } else { (wanted as u128 * limit as u128 / total as u128) as u64 };
+            let carried = (wanted - granted).min(self.policy.max_carry as u64) as u32;
+            self.slots[id.slot].carry = carried;
+            if granted < wanted {
+                self.starved.push(Starvation { cpu: id, owner, wanted, granted, carried });
+            }
+            grants.push(granted);
+        }
+        grants
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BudgetPolicy, CpuComponent, CpuFault, HiveCluster, PowerState};
+    use devices::world::{DEVICE_ID, LOCATE, STATUS_OK};
+    use devices::{Device, DeviceInfo, HOTPLUG_MESSAGE};
+    use error::HivemindError;
+    use model::component::Position;
+    use model::dimension::WorldId;
+    use model::entity::EntityManager;
+    use model::power::Consumer;
+    use model::world::World;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use vcpu::cpu::{Fault, FAULT_INVALID_OPCODE, VCPU16};
+    use vcpu::memory::{Firmware, Memory};
+    use vcpu::shared::ConflictPolicy;
+    use vcpu::swap::DirectorySwap;
+
+    /// HWI 0, followed by NOPs
+    const ROM: [u16; 1] = [0x8640];
+
+    #[test]
+    pub fn test_embedded_cpu_lifecycle() {
+        let mut world = World::new();
+        let mut entities = EntityManager::new();
+        let mut cluster = HiveCluster::new();
+        let drone = entities.create_entity();
+        entities.add_component(drone, Position::from_f64(3.5, 7.0, 9.5));
+        let cpu = cluster.attach(&mut entities, drone, &ROM).unwrap();
+        assert_eq!(entities.get_component::<CpuComponent>(drone).map(|c| c.cpu), Some(cpu));
+        assert_eq!(cluster.owner(cpu), Some(drone));
+        cluster.get_mut(cpu).unwrap().set_a(LOCATE);
+
+        assert_eq!(cluster.tick(&mut world, &mut entities), 0);
+        let state = cluster.get(cpu).unwrap();
+        assert_eq!((state.get_c(), state.get_x(), state.get_y(), state.get_z()), (STATUS_OK, 3, 7, 9));
+
+        // A paused CPU can still be stepped on its own bus
+        assert!(cluster.pause(cpu));
+        let located = cluster.with_bus(cpu, &mut world, &mut entities, |cpu, bus| {
+            cpu.set_pc(0);
+            cpu.set_x(0);
+            cpu.step_with(bus);
+            while cpu.is_busy() {
+                cpu.step_with(bus);
+            }
+            (bus.count(), cpu.get_x())
+        });
+        assert_eq!(located, Some((1, 3)));
+        assert!(cluster.resume(cpu));
+
+        // Once the drone dies its CPU goes back to the pool and the id is stale
+        entities.destroy_entity(drone);
+        assert_eq!(cluster.tick(&mut world, &mut entities), 1);
+        assert!(cluster.is_empty());
+        assert!(cluster.get(cpu).is_none());
+        let other = cluster.spawn(&[]).unwrap();
+        assert_eq!(other.slot(), cpu.slot());
+        assert!(!cluster.contains(cpu));
+        assert_eq!(cluster.pool().stats().reused, 1);
+        assert!(matches!(cluster.attach(&mut entities, drone, &[]), Err(HivemindError::DeadEntity(entity)) if entity == drone));
+
+        // Faults are reported against the CPU and its host
+        let faulty = entities.create_entity();
+        let cpu = cluster.attach(&mut entities, faulty, &[0x0018]).unwrap();
+        cluster.tick(&mut world, &mut entities);
+        let fault = Fault { code: FAULT_INVALID_OPCODE, address: 0 };
+        assert_eq!(cluster.faults(), &[CpuFault { cpu, owner: Some(faulty), fault }]);
+        assert!(cluster.get(cpu).unwrap().is_halted());
+    }
+
+    #[test]
+    pub fn test_unpowered_cpu_hibernates() {
+        let mut world = World::new();
+        let mut entities = EntityManager::new();
+        let mut cluster = HiveCluster::new();
+        let drone = entities.create_entity();
+        entities.add_component(drone, Consumer::new(1));
+        let cpu = cluster.attach(&mut entities, drone, &[]).unwrap();
+        cluster.tick(&mut world, &mut entities);
+        assert_eq!(cluster.get(cpu).unwrap().get_pc(), 100);
+
+        entities.get_component_mut::<Consumer>(drone).unwrap().powered = false;
+        cluster.tick(&mut world, &mut entities);
+        assert!(cluster.is_hibernated(cpu));
+        assert_eq!(cluster.get(cpu).unwrap().get_pc(), 100);
+        assert_eq!(cluster.cycles(), 0);
+
+        entities.get_component_mut::<Consumer>(drone).unwrap().powered = true;
+        cluster.tick(&mut world, &mut entities);
+        assert!(!cluster.is_hibernated(cpu));
+        assert_eq!(cluster.get(cpu).unwrap().get_pc(), 200);
+        assert_eq!(cluster.cycles(), 100);
+    }
+
+    #[test]
+    pub fn test_power_cycle() {
+        let mut world = World::new();
+        let mut entities = EntityManager::new();
+        let mut cluster = HiveCluster::new();
+        let drone = entities.create_entity();
+        entities.add_component(drone, Consumer::new(1));
+        // SET A, 7 then NOPs
+        let cpu = cluster.attach(&mut entities, drone, &[0xA001]).unwrap();
+        assert_eq!(cluster.power_state(cpu), Some(PowerState::Running));
+        assert!(!cluster.power_on(cpu));
+        cluster.tick(&mut world, &mut entities);
+        cluster.get_mut(cpu).unwrap().set_a(0);
+
+        // Off, the CPU keeps its memory and registers but doesn't run
+        assert!(cluster.power_off(cpu));
+        assert!(!cluster.power_off(cpu));
+        cluster.tick(&mut world, &mut entities);
+        assert_eq!(cluster.power_state(cpu), Some(PowerState::Off));
+        assert_eq!((cluster.get(cpu).unwrap().get_pc(), cluster.cycles()), (100, 0));
+
+        // Booting waits for power, then runs the program again from the start
+        entities.get_component_mut::<Consumer>(drone).unwrap().powered = false;
+        assert!(cluster.power_on(cpu));
+        assert_eq!(cluster.get(cpu).unwrap().get_pc(), 0);
+        cluster.tick(&mut world, &mut entities);
+        assert_eq!(cluster.power_state(cpu), Some(PowerState::Booting));
+        entities.get_component_mut::<Consumer>(drone).unwrap().powered = true;
+        cluster.tick(&mut world, &mut entities);
+        assert_eq!((cluster.power_state(cpu), cluster.cycles()), (Some(PowerState::Running), 0));
+        cluster.tick(&mut world, &mut entities);
+        assert_eq!((cluster.get(cpu).unwrap().get_a(), cluster.cycles()), (7, 100));
+
+        // A halted CPU only comes back by rebooting
+        cluster.get_mut(cpu).unwrap().set_memory(0, 0x0018);
+        assert!(cluster.reboot(cpu));
+        cluster.tick(&mut world, &mut entities);
+        cluster.tick(&mut world, &mut entities);
+        assert_eq!(cluster.power_state(cpu), Some(PowerState::Halted));
+        cluster.get_mut(cpu).unwrap().set_memory(0, 0xA001);
+        assert!(cluster.reboot(cpu));
+        assert_eq!(cluster.power_state(cpu), Some(PowerState::Booting));
+
+        assert!(cluster.release(cpu));
+        assert_eq!(cluster.power_state(cpu), None);
+        assert!(!cluster.reboot(cpu));
+    }
+
+    #[test]
+    pub fn test_cycle_budgets() {
+        let mut world = World::new();
+        let mut entities = EntityManager::new();
+        let mut cluster = HiveCluster::new();
+        cluster.set_policy(BudgetPolicy { tick_limit: Some(150), max_carry: 30, cycles_per_demand: Some(100) });
+        let worker = entities.create_entity();
+        let miner = entities.create_entity();
+        entities.add_component(miner, Consumer::new(1));
+        let first = cluster.attach(&mut entities, worker, &[]).unwrap();
+        let second = cluster.attach(&mut entities, miner, &[]).unwrap();
+        // The miner's clock is capped by its power demand
+        entities.get_component_mut::<CpuComponent>(miner).unwrap().clock = 200;
+
+        // Both want 100 of the 150 available, each gets 75 and is owed 25
+        cluster.tick(&mut world, &mut entities);
+        assert_eq!(cluster.cycles(), 150);
+        assert_eq!((cluster.get(first).unwrap().get_pc(), cluster.get(second).unwrap().get_pc()), (75, 75));
+        assert_eq!(cluster.starved().len(), 2);
+        assert_eq!((cluster.starved()[1].owner, cluster.starved()[1].carried), (Some(miner), 25));
+
+        // What is owed is capped by the carry limit
+        cluster.tick(&mut world, &mut entities);
+        assert_eq!(cluster.starved()[0].wanted, 125);
+        assert_eq!(cluster.carry(first), 30);
+
+        // Without a limit the debt is paid off
+        cluster.set_policy(BudgetPolicy::default());
+        cluster.tick(&mut world, &mut entities);
+        assert_eq!(cluster.get(first).unwrap().get_pc(), 150 + 130);
+        assert!(cluster.starved().is_empty());
+        assert_eq!(cluster.carry(first), 0);
+    }
+
+    /// Device identified only by its id
+    struct Module(u32);
+
+    impl Device for Module {
+        fn info(&self) -> DeviceInfo { DeviceInfo { id: self.0, version: 1, manufacturer: 0 } }
+        fn interrupt(&mut self, _cpu: &mut VCPU16) -> u16 { 0 }
+    }
+
+    #[test]
+    pub fn test_hotplug() {
+        let mut world = World::new();
+        let mut entities = EntityManager::new();
+        let mut cluster = HiveCluster::new();
+        let drone = entities.create_entity();
+        let cpu = cluster.attach(&mut entities, drone, &[]).unwrap();
+        cluster.get_mut(cpu).unwrap().set_ia(0x100);
+        let mut ids = |cluster: &mut HiveCluster| {
+            cluster.with_bus(cpu, &mut world, &mut entities, |_, bus| (0..bus.count()).map(|index| bus.info(index).unwrap().id).collect::<Vec<u32>>()).unwrap()
+        };
+        assert_eq!(cluster.plug(cpu, Box::new(Module(1))), Some(1));
+        assert_eq!(cluster.plug(cpu, Box::new(Module(2))), Some(2));
+        assert_eq!(cluster.get(cpu).unwrap().pending_interrupts(), 2);
+
+        // Unplugging leaves the devices after it where they were
+        assert!(cluster.unplug(cpu, 0).is_none());
+        assert_eq!(cluster.unplug(cpu, 1).map(|device| device.info().id), Some(1));
+        assert!(cluster.unplug(cpu, 1).is_none());
+        assert_eq!(ids(&mut cluster), vec![DEVICE_ID, 0, 2]);
+        assert_eq!(cluster.device_count(cpu), 1);
+
+        // The empty socket is filled first, and empty sockets at the end go
+        assert_eq!(cluster.plug(cpu, Box::new(Module(3))), Some(1));
+        assert!(cluster.unplug(cpu, 2).is_some());
+        assert_eq!(ids(&mut cluster), vec![DEVICE_ID, 3]);
+        assert_eq!(cluster.get(cpu).unwrap().pending_interrupts(), 5);
+
+        // Each change reaches the firmware: HIB, handler SET X, A then RFI 0
+        cluster.get_mut(cpu).unwrap().load_rom(&[0x0400]).unwrap();
+        cluster.get_mut(cpu).unwrap().set_memory(0x100, 0x0061);
+        cluster.get_mut(cpu).unwrap().set_memory(0x101, 0x8560);
+        cluster.tick(&mut world, &mut entities);
+        let state = cluster.get(cpu).unwrap();
+        assert_eq!((state.get_x(), state.pending_interrupts()), (HOTPLUG_MESSAGE, 0));
+        assert!(state.is_hibernating());
+        assert!(cluster.unplug(cpu, 1).is_some());
+        assert!(!cluster.get(cpu).unwrap().is_hibernating());
+    }
+
+    #[test]
+    pub fn test_shared_segments() {
+        let mut world = World::new();
+        let mut entities = EntityManager::new();
+        let mut cluster = HiveCluster::new();
+        let board = cluster.shared_memory_mut().create(4, ConflictPolicy::Accumulate);
+        // ADD [0x8000], 1 then HIB
+        let rom = [0x8BC2, 0x8000, 0x0400];
+        let first = cluster.spawn(&rom).unwrap();
+        let second = cluster.spawn(&rom).unwrap();
+        assert!(cluster.map_segment(first, board, 0x8000));
+        assert!(cluster.map_segment(second, board, 0x8000));
+        assert!(!cluster.map_segment(second, board, 0x8002));
+        assert!(!cluster.map_segment(second, board, 0xFFFE));
+
+        // Both counted from the same start, and both counts are kept
+        cluster.tick(&mut world, &mut entities);
+        assert_eq!(cluster.shared_memory().words(board), Some(&[2, 0, 0, 0][..]));
+        cluster.shared_memory_mut().set(board, 1, 9);
+        cluster.get_mut(first).unwrap().reset(false);
+        cluster.tick(&mut world, &mut entities);
+        assert_eq!(cluster.get(first).unwrap().get_memory(0x8001), 9);
+        assert_eq!(cluster.shared_memory().words(board).unwrap()[0], 3);
+
+        assert!(cluster.unmap_segment(first, board));
+        assert!(!cluster.unmap_segment(first, board));
+        assert_eq!(cluster.mappings(second).len(), 1);
+        assert!(cluster.mappings(first).is_empty());
+    }
+
+    #[test]
+    pub fn test_firmware_sharing() {
+        let mut world = World::new();
+        let mut entities = EntityManager::new();
+        let mut cluster = HiveCluster::new();
+        // ADD [0x8000], 1 then SET PC, 0
+        let firmware = Firmware::new(&[0x8BC2, 0x8000, 0x8781]).unwrap();
+        let board = cluster.shared_memory_mut().create(16, ConflictPolicy::LastWins);
+        let drones: Vec<_> = (0..100).map(|_| entities.create_entity()).collect();
+        for &drone in drones.iter() {
+            let cpu = cluster.attach_firmware(&mut entities, drone, &firmware).unwrap();
+            assert!(cluster.map_segment(cpu, board, 0x4000));
+        }
+        // The code page and the zero page are shared by every drone
+        assert_eq!(cluster.memory_pages(), 2);
+
+        // Each drone copies only the page of its counter, however long it runs
+        for _ in 0..3 {
+            cluster.tick(&mut world, &mut entities);
+            assert_eq!(cluster.memory_pages(), 2 + 100);
+        }
+        let cpu = entities.get_component::<CpuComponent>(drones[0]).unwrap().cpu;
+        assert!(cluster.get(cpu).unwrap().get_memory(0x8000) > 0);
+        assert_eq!(Memory::from_firmware(&firmware).get(0x8000), 0);
+    }
+
+    #[test]
+    pub fn test_memory_swap() {
+        let mut world = World::new();
+        let mut entities = EntityManager::new();
+        let mut cluster = HiveCluster::new();
+        let directory = env::temp_dir().join(format!("hivemind-swap-{}", process::id()));
+        cluster.set_swap(Some(Box::new(DirectorySwap::open(&directory).unwrap())), 2).unwrap();
+        let swap_files = || fs::read_dir(&directory).unwrap().count();
+        // ADD [0x8000], 1 then SET PC, 0
+        let firmware = Firmware::new(&[0x8BC2, 0x8000, 0x8781]).unwrap();
+        let drone = entities.create_entity();
+        entities.add_component(drone, Consumer::new(1));
+        let cpu = cluster.attach_firmware(&mut entities, drone, &firmware).unwrap();
+        cluster.tick(&mut world, &mut entities);
+        let count = cluster.get(cpu).unwrap().get_memory(0x8000);
+        assert!(count > 0);
+
+        // Held long enough, the counter's page goes to disk and the firmware's stay
+        entities.get_component_mut::<Consumer>(drone).unwrap().powered = false;
+        cluster.tick(&mut world, &mut entities);
+        assert!(!cluster.is_swapped(cpu));
+        cluster.tick(&mut world, &mut entities);
+        assert!(cluster.is_swapped(cpu));
+        assert_eq!((cluster.swapped(), swap_files()), (1, 1));
+        assert_eq!(cluster.get(cpu).unwrap().get_memory(0x8000), 0);
+        assert_eq!(cluster.get(cpu).unwrap().memory().private_pages(), 0);
+
+        // Power brings it back where it left off
+        entities.get_component_mut::<Consumer>(drone).unwrap().powered = true;
+        cluster.tick(&mut world, &mut entities);
+        assert!(!cluster.is_swapped(cpu));
+        assert!(cluster.get(cpu).unwrap().get_memory(0x8000) > count);
+        assert_eq!(swap_files(), 0);
+
+        // A host in a World not being ticked holds its CPU the same way
+        entities.add_component(drone, WorldId(2));
+        cluster.tick(&mut world, &mut entities);
+        cluster.tick(&mut world, &mut entities);
+        assert!(cluster.is_swapped(cpu) && cluster.cycles() == 0);
+        entities.remove_component::<WorldId>(drone);
+        cluster.tick(&mut world, &mut entities);
+        assert!(!cluster.is_swapped(cpu));
+
+        // Borrowing a swapped out CPU swaps it in, releasing one discards its pages
+        let count = cluster.get(cpu).unwrap().get_memory(0x8000);
+        assert!(cluster.pause(cpu));
+        cluster.tick(&mut world, &mut entities);
+        cluster.tick(&mut world, &mut entities);
+        assert_eq!(cluster.get_mut(cpu).unwrap().get_memory(0x8000), count);
+        assert!(cluster.swap_out(cpu).unwrap());
+        assert!(!cluster.swap_out(cpu).unwrap());
+        assert!(cluster.release(cpu));
+        assert_eq!((cluster.swapped(), swap_files()), (0, 0));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
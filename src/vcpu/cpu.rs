@@ -1,23 +1,183 @@
 /// Modified Implementation of DCPU16
 /// https://gist.github.com/metaphox/3888117
 ///
-use std::io::{Read, Write};
+use codec::invalid_data;
+use devices::{Bus, NoDevices};
+use error::HivemindError;
+use migrate::{Artifact, Migrations};
+use pool::Poolable;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
 use std::mem;
-use std::slice;
+use vcpu::image::Image;
+use vcpu::memory::{Firmware, Memory};
 
 ///
 /// VCPU State Storage
 ///
+/// Cloning shares memory pages copy-on-write, see `Memory`.
+///
+#[derive(Clone)]
 pub struct VCPU16 {
     registers: [u16; 12],
-    memory: [u16; 65536],
+    memory: Memory,
     state: State,
+    interrupts: VecDeque<u16>,
+    queueing: bool,
+    /// Address of the instruction being executed
+    current: u16,
+    /// Code of the last fault, cleared by FCG
+    fault_code: u16,
+    /// Handler for faults, 0 to halt instead
+    fault_vector: u16,
+    /// Faults not yet taken by the host
+    faults: Vec<Fault>,
+    cache: Option<DecodeCache>,
+    #[cfg(feature = "jit")]
+    blocks: Option<BlockCache>,
+    /// Ranges the program may not write or execute
+    regions: Vec<Region>,
+    wake: WakePolicy,
+    /// Cycles spent in the current sleep or hibernation
+    dormant: u32,
+    last_wake: Option<WakeReason>,
+    stats: CycleStats,
+}
+
+/// Interrupts queued beyond this are dropped
+pub const INTERRUPT_QUEUE_LIMIT: usize = 256;
+/// Faults kept beyond this until taken are dropped
+pub const FAULT_LOG_LIMIT: usize = 16;
+
+/// Fault code of an instruction word which doesn't decode
+pub const FAULT_INVALID_OPCODE: u16 = 0x0001;
+/// Fault code of a program write to a read-only Region
+pub const FAULT_WRITE_PROTECTED: u16 = 0x0002;
+/// Fault code of an instruction fetched from a no-execute Region
+pub const FAULT_NO_EXECUTE: u16 = 0x0003;
+
+/// Magic of a memory image, see `save_memory`
+pub const MAGIC: &[u8; 4] = b"HVCS";
+/// Memory image format written by this build, see `migrate`
+pub const VERSION: u16 = 1;
+/// Magic and version ahead of the words of a memory image
+const HEADER_SIZE: usize = 6;
+/// Every word of memory, little-endian
+const IMAGE_SIZE: usize = 2 * 65536;
+
+///
+/// Protected Memory Region
+///
+/// An inclusive range of addresses the program may not write, execute, or
+/// both. Protection only binds the program: the host's `set_memory` and
+/// image loading, and the CPU's own pushes for interrupts and faults, still
+/// write anywhere.
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Region {
+    pub start: u16,
+    pub end: u16,
+    pub read_only: bool,
+    pub no_execute: bool,
+}
+
+impl Region {
+    /// Words from `start` to `end` which can be run but not written, such as firmware.
+    pub fn read_only(start: u16, end: u16) -> Region { Region { start, end, read_only: true, no_execute: false } }
+    /// Words from `start` to `end` which can be written but not run, such as data or the stack.
+    pub fn no_execute(start: u16, end: u16) -> Region { Region { start, end, read_only: false, no_execute: true } }
+    pub fn contains(&self, address: u16) -> bool { self.start <= address && address <= self.end }
+}
+
+///
+/// Interrupts which end a sleep or hibernation early
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Wake {
+    /// Any interrupt, whether or not IA is set to handle it
+    Any,
+    /// Only an interrupt with this message, such as the one a device raises
+    Message(u16),
+    Never,
+}
+
+impl Wake {
+    fn wakes(&self, message: u16) -> bool {
+        match *self {
+            Wake::Any => true,
+            Wake::Message(wanted) => wanted == message,
+            Wake::Never => false,
+        }
+    }
+}
+
+///
+/// What ends SLP and HIB
+///
+/// SLP n ends after n cycles, HIB only when woken. Either may be ended early
+/// by the interrupts its Wake allows, or by the watchdog once the CPU has
+/// been dormant for its cycles. Dormant CPUs run no instructions, though
+/// their devices still tick and may interrupt them.
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct WakePolicy {
+    pub sleep: Wake,
+    pub hibernate: Wake,
+    /// Dormant cycles after which the CPU wakes anyway, None for no limit
+    pub watchdog: Option<u32>,
+}
+
+impl Default for WakePolicy {
+    fn default() -> WakePolicy { WakePolicy { sleep: Wake::Never, hibernate: Wake::Any, watchdog: None } }
+}
 
+///
+/// Why the CPU last woke
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum WakeReason {
+    /// Its SLP ran out
+    Timer,
+    Interrupt(u16),
+    Watchdog,
+}
+
+///
+/// Cycles by what the CPU did with them, for charging energy
+///
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct CycleStats {
+    /// Running or stalled on instructions
+    pub active: u64,
+    pub sleeping: u64,
+    pub hibernating: u64,
+    pub halted: u64,
+    /// Sleeps and hibernations ended
+    pub wakes: u64,
+}
+
+impl CycleStats {
+    pub fn total(&self) -> u64 { self.active + self.sleeping + self.hibernating + self.halted }
+}
+
+///
+/// CPU Fault
+///
+/// Raised when the CPU can't execute an instruction. The fault code is kept
+/// for FCG; with a fault vector set the address of the faulting instruction
+/// is pushed and PC jumps to the vector, otherwise the CPU halts.
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Fault {
+    pub code: u16,
+    /// Address of the faulting instruction
+    pub address: u16,
 }
 
 ///
 /// VCPU Register Index
 ///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 enum Register {
     A = 0x0,
     B = 0x1,
@@ -33,12 +193,16 @@ enum Register {
     IA = 0xB,
 }
 
+/// Registers numbered by operand values 0x00-0x07
+const GENERAL_REGISTERS: [Register; 8] = [Register::A, Register::B, Register::C, Register::X, Register::Y, Register::Z, Register::I, Register::J];
+
 ///
 /// VCPU Operating States
 ///
+#[derive(Clone)]
 enum State {
     Idle,
-    Busy(u16, Instruction),
+    Busy(u16, Pending),
     Sleeping(u16),
     Hibernating,
     Halted,
@@ -47,6 +211,7 @@ enum State {
 ///
 /// Decoded Instruction Value
 ///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 enum Value {
     Register { register: Register, value: u16 },
     Memory { address: u16, value: u16 },
@@ -54,84 +219,408 @@ enum Value {
     None,
 }
 
-struct Decoded<T> {
-    pub result: T,
-    pub time: usize,
+///
+/// Instruction Field an Operand is decoded from
+///
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Operand {
+    /// Source, 6 bits
+    A,
+    /// Destination, 5 bits
+    B,
+}
+
+///
+/// Operand with its NEXT word read, bound to a Value each time it executes
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Mode {
+    Register(Register),
+    /// [register]
+    Indirect(Register),
+    /// [register + NEXT]
+    Offset(Register, u16),
+    Pop,
+    Push,
+    Peek,
+    /// [SP + NEXT]
+    Pick(u16),
+    /// [NEXT]
+    Address(u16),
+    /// NEXT
+    Next(u16),
+    /// Literal within the instruction word
+    Literal(u16),
+    None,
+}
+
+impl Mode {
+    /// NEXT words the Mode was read from
+    fn words(&self) -> u16 {
+        match *self {
+            Mode::Offset(..) | Mode::Pick(_) | Mode::Address(_) | Mode::Next(_) => 1,
+            _ => 0,
+        }
+    }
+}
+
+///
+/// Instruction as read from memory, before its operands are bound
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct Template {
+    op: Op,
+    a: Mode,
+    b: Mode,
+    /// Words including the instruction word
+    length: u16,
+    /// Cycles including those reading NEXT words
+    time: u16,
+}
+
+/// Entries in a decode cache, a power of two
+pub const DECODE_CACHE_ENTRIES: usize = 256;
+
+///
+/// Decode Cache Statistics
+///
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped because memory they were read from was written
+    pub invalidations: u64,
+}
+
+///
+/// Direct mapped cache of Templates by address
+///
+#[derive(Clone)]
+struct DecodeCache {
+    entries: Vec<Option<(u16, Template)>>,
+    stats: CacheStats,
+}
+
+impl DecodeCache {
+    fn new() -> DecodeCache { DecodeCache { entries: vec![None; DECODE_CACHE_ENTRIES], stats: CacheStats::default() } }
+    fn slot(address: u16) -> usize { address as usize & (DECODE_CACHE_ENTRIES - 1) }
+    fn get(&mut self, address: u16) -> Option<Template> {
+        match self.entries[DecodeCache::slot(address)] {
+            Some((tag, template)) if tag == address => {
+                self.stats.hits += 1;
+                Some(template)
+            }
+            _ => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+    fn insert(&mut self, address: u16, template: Template) { self.entries[DecodeCache::slot(address)] = Some((address, template)) }
+    /// Drop any instruction covering `address`, which starts at most two words before it.
+    fn invalidate(&mut self, address: u16) {
+        for offset in 0..3 {
+            let start = address.wrapping_sub(offset);
+            let entry = &mut self.entries[DecodeCache::slot(start)];
+            if matches!(*entry, Some((tag, template)) if tag == start && offset < template.length) {
+                *entry = None;
+                self.stats.invalidations += 1;
+            }
+        }
+    }
+    fn clear(&mut self) {
+        for entry in self.entries.iter_mut() {
+            *entry = None;
+        }
+    }
+}
+
+/// Instructions in a translated block at most
+#[cfg(feature = "jit")]
+pub const BLOCK_LENGTH: usize = 32;
+
+///
+/// Block Translation Statistics
+///
+#[cfg(feature = "jit")]
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub struct BlockStats {
+    /// Blocks decoded from memory
+    pub translated: u64,
+    /// Instructions run from blocks rather than interpreted
+    pub executed: u64,
+    /// Blocks dropped because memory they were read from was written
+    pub invalidations: u64,
+}
+
+///
+/// Templates with the addresses they were read from, in the order they run
+/// when every conditional holds
+///
+#[cfg(feature = "jit")]
+#[derive(Clone)]
+struct Block {
+    instructions: Vec<(u16, Template)>,
+    /// Start and words of each straight run the Templates were read from
+    spans: Vec<(u16, u16)>,
+}
+
+/// Blocks kept by a block cache, a power of two
+#[cfg(feature = "jit")]
+const BLOCK_CACHE_ENTRIES: usize = 256;
+
+///
+/// Direct mapped cache of Blocks by start address, with a bitmap of the
+/// words any of them was read from so most stores are dismissed with one
+/// lookup. The Block running is taken out of the cache, and only put back
+/// if it wasn't written meanwhile.
+///
+#[cfg(feature = "jit")]
+#[derive(Clone)]
+struct BlockCache {
+    entries: Vec<Option<(u16, Block)>>,
+    /// A bit per word of memory, set if a Block was read from it
+    code: Vec<u64>,
+    /// Spans of the Block running, empty if none is
+    running: Vec<(u16, u16)>,
+    /// Set when the Block running is dropped
+    stale: bool,
+    stats: BlockStats,
+}
+
+#[cfg(feature = "jit")]
+impl BlockCache {
+    fn new() -> BlockCache {
+        BlockCache { entries: vec![None; BLOCK_CACHE_ENTRIES], code: vec![0; 65536 / 64], running: Vec::new(), stale: false, stats: BlockStats::default() }
+    }
+    fn slot(address: u16) -> usize { address as usize & (BLOCK_CACHE_ENTRIES - 1) }
+    fn covers(&self, address: u16) -> bool { self.code[address as usize / 64] & 1 << (address % 64) != 0 }
+    fn reads(spans: &[(u16, u16)], address: u16) -> bool { spans.iter().any(|&(start, words)| address.wrapping_sub(start) < words) }
+    /// Note the words read for a Block, so writes to them are caught while it runs.
+    fn mark(code: &mut [u64], spans: &[(u16, u16)]) {
+        for &(start, words) in spans {
+            for offset in 0..words {
+                let address = start.wrapping_add(offset);
+                code[address as usize / 64] |= 1 << (address % 64);
+            }
+        }
+    }
+    fn take(&mut self, start: u16) -> Option<Block> {
+        let entry = &mut self.entries[BlockCache::slot(start)];
+        match entry.take() {
+            Some((tag, block)) if tag == start => Some(block),
+            other => {
+                *entry = other;
+                None
+            }
+        }
+    }
+    fn put(&mut self, start: u16, block: Block) { self.entries[BlockCache::slot(start)] = Some((start, block)) }
+    /// Drop any Block read from `address`.
+    fn invalidate(&mut self, address: u16) {
+        if !self.covers(address) {
+            return;
+        }
+        for entry in self.entries.iter_mut() {
+            if matches!(*entry, Some((_, ref block)) if BlockCache::reads(&block.spans, address)) {
+                *entry = None;
+                self.stats.invalidations += 1;
+            }
+        }
+        if BlockCache::reads(&self.running, address) {
+            self.running.clear();
+            self.stale = true;
+            self.stats.invalidations += 1;
+        }
+        self.code.iter_mut().for_each(|bits| *bits = 0);
+        for (_, block) in self.entries.iter().flatten() {
+            BlockCache::mark(&mut self.code, &block.spans);
+        }
+        BlockCache::mark(&mut self.code, &self.running);
+    }
+    fn clear(&mut self) {
+        self.entries.iter_mut().for_each(|entry| *entry = None);
+        self.code.iter_mut().for_each(|bits| *bits = 0);
+        self.running.clear();
+        self.stale = true;
+    }
 }
 
-enum Instruction {
+///
+/// Operation of an Instruction, decoded apart from its operands
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Op {
     ERR,
     NOP,
     HIB,
-    JSR { left: Value },
-    SLP { left: Value },
-    INT { left: Value },
-    IAG { left: Value },
-    IAS { left: Value },
-    RFI { left: Value },
-    IAQ { left: Value },
-    HWN { left: Value },
-    HWQ { left: Value },
-    HWI { left: Value },
-    SET { left: Value, right: Value },
-    ADD { left: Value, right: Value },
-    SUB { left: Value, right: Value },
-    MUL { left: Value, right: Value },
-    MLI { left: Value, right: Value },
-    DIV { left: Value, right: Value },
-    DVI { left: Value, right: Value },
-    MOD { left: Value, right: Value },
-    MDI { left: Value, right: Value },
-    AND { left: Value, right: Value },
-    BOR { left: Value, right: Value },
-    XOR { left: Value, right: Value },
-    SHR { left: Value, right: Value },
-    ASR { left: Value, right: Value },
-    SHL { left: Value, right: Value },
-    IFB { left: Value, right: Value },
-    IFC { left: Value, right: Value },
-    IFE { left: Value, right: Value },
-    IFN { left: Value, right: Value },
-    IFG { left: Value, right: Value },
-    IFA { left: Value, right: Value },
-    IFL { left: Value, right: Value },
-    IFU { left: Value, right: Value },
-    ADX { left: Value, right: Value },
-    SBX { left: Value, right: Value },
-    STI { left: Value, right: Value },
-    STD { left: Value, right: Value },
+    JSR,
+    SLP,
+    INT,
+    IAG,
+    IAS,
+    RFI,
+    IAQ,
+    FCG,
+    FVS,
+    HWN,
+    HWQ,
+    HWI,
+    SET,
+    ADD,
+    SUB,
+    MUL,
+    MLI,
+    DIV,
+    DVI,
+    MOD,
+    MDI,
+    AND,
+    BOR,
+    XOR,
+    SHR,
+    ASR,
+    SHL,
+    IFB,
+    IFC,
+    IFE,
+    IFN,
+    IFG,
+    IFA,
+    IFL,
+    IFU,
+    ADX,
+    SBX,
+    STI,
+    STD,
 }
 
+///
+/// Instruction with its operands bound, waiting out its cycles
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct Pending {
+    op: Op,
+    /// a
+    left: Value,
+    /// b
+    right: Value,
+}
+
+/// Stall without an operation
+const STALL: Pending = Pending { op: Op::NOP, left: Value::None, right: Value::None };
+
 impl VCPU16 {
     pub fn new() -> VCPU16 {
         VCPU16 {
             registers: [0; 12],
-            memory: [0; 65536],
+            memory: Memory::new(),
             state: State::Idle,
+            interrupts: VecDeque::new(),
+            queueing: false,
+            current: 0,
+            fault_code: 0,
+            fault_vector: 0,
+            faults: Vec::new(),
+            cache: None,
+            #[cfg(feature = "jit")]
+            blocks: None,
+            regions: Vec::new(),
+            wake: WakePolicy::default(),
+            dormant: 0,
+            last_wake: None,
+            stats: CycleStats::default(),
+        }
+    }
+    ///
+    /// Replace memory with an image written by `save_memory`, or a bare
+    /// 128KiB one from before images were versioned. Memory is left
+    /// untouched if the image can't be read in full.
+    ///
+    pub fn load_memory(&mut self, reader: &mut dyn Read) -> Result<(), HivemindError> {
+        let mut header = [0u8; HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + IMAGE_SIZE);
+        if &header[..MAGIC.len()] != MAGIC {
+            // Version 0 images are bare words, so those read were its first
+            bytes.extend_from_slice(MAGIC);
+            bytes.extend_from_slice(&0u16.to_le_bytes());
         }
+        bytes.extend_from_slice(&header);
+        let read = bytes.len();
+        bytes.resize(HEADER_SIZE + IMAGE_SIZE, 0);
+        reader.read_exact(&mut bytes[read..])?;
+        let bytes = Migrations::default().upgrade(Artifact::CpuState, bytes)?;
+        for (address, word) in bytes[HEADER_SIZE..].chunks_exact(2).enumerate() {
+            self.memory.set(address as u16, u16::from_le_bytes([word[0], word[1]]));
+        }
+        self.forget_decoded();
+        Ok(())
+    }
+    pub fn save_memory(&mut self, writer: &mut dyn Write) -> Result<(), HivemindError> {
+        let mut image: Vec<u8> = Vec::with_capacity(HEADER_SIZE + IMAGE_SIZE);
+        image.extend_from_slice(MAGIC);
+        image.extend_from_slice(&VERSION.to_le_bytes());
+        image.extend(self.memory.words().flat_map(|word| word.to_le_bytes()));
+        writer.write_all(&image)?;
+        Ok(())
+    }
+    ///
+    /// Page out the memory this CPU holds alone, see `Memory::page_out`.
+    /// It reads as the pages of `blank` until paged back in.
+    ///
+    pub fn page_out(&mut self, blank: &Memory, writer: &mut dyn Write) -> Result<usize, HivemindError> {
+        let pages = self.memory.page_out(blank, writer)?;
+        self.forget_decoded();
+        Ok(pages)
     }
-    pub fn load_memory(&mut self, reader: &mut Read) {
-        unsafe {
-            let memory_size = mem::size_of_val(&self.memory);
-            let memory_slice = slice::from_raw_parts_mut(
-                &mut self.memory as *mut _ as *mut u8,
-                memory_size,
-            );
-            reader.read_exact(memory_slice).unwrap();
+    /// Restore pages written by `page_out`.
+    pub fn page_in(&mut self, reader: &mut dyn Read) -> Result<usize, HivemindError> {
+        let pages = self.memory.page_in(reader)?;
+        self.forget_decoded();
+        Ok(pages)
+    }
+    ///
+    /// Copy a ROM to the start of memory, leaving the rest as it was.
+    ///
+    pub fn load_rom(&mut self, rom: &[u16]) -> Result<(), HivemindError> {
+        if rom.len() > 65536 {
+            return Err(HivemindError::RomTooLarge(rom.len()));
         }
+        for (address, &word) in rom.iter().enumerate() {
+            self.memory.set(address as u16, word);
+        }
+        self.forget_decoded();
+        Ok(())
     }
-    pub fn save_memory(&mut self, writer: &mut Write) {
-        unsafe {
-            let memory_size = mem::size_of_val(&self.memory);
-            let memory_slice = slice::from_raw_parts_mut(
-                &mut self.memory as *mut _ as *mut u8,
-                memory_size,
-            );
-            writer.write(memory_slice).unwrap();
+    ///
+    /// Copy each segment of `image` to its address and start at its entry
+    /// point, leaving the rest of memory as it was.
+    ///
+    pub fn load_image(&mut self, image: &Image) -> Result<(), HivemindError> {
+        if let Some(segment) = image.segments.iter().find(|segment| segment.address as usize + segment.words.len() > 65536) {
+            return Err(HivemindError::RomTooLarge(segment.address as usize + segment.words.len()));
         }
+        for segment in image.segments.iter() {
+            for (offset, &word) in segment.words.iter().enumerate() {
+                self.memory.set(segment.address.wrapping_add(offset as u16), word);
+            }
+        }
+        self.forget_decoded();
+        self.registers[Register::PC as usize] = image.entry;
+        Ok(())
     }
-    pub fn set_memory(&mut self, address: u16, value: u16) { self.memory[address as usize] = value }
-    pub fn get_memory(&self, address: u16) -> u16 { self.memory[address as usize] }
+    ///
+    /// Replace all of memory with pages shared with `firmware`, each copied
+    /// only when this CPU first writes to it.
+    ///
+    pub fn load_firmware(&mut self, firmware: &Firmware) {
+        self.memory = Memory::from_firmware(firmware);
+        self.forget_decoded();
+    }
+    pub fn memory(&self) -> &Memory { &self.memory }
+    pub fn set_memory(&mut self, address: u16, value: u16) { self.store(address, value) }
+    pub fn get_memory(&self, address: u16) -> u16 { self.memory.get(address) }
     pub fn get_sp(&self) -> u16 { self.registers[Register::SP as usize] }
     pub fn get_pc(&self) -> u16 { self.registers[Register::PC as usize] }
     pub fn get_ex(&self) -> u16 { self.registers[Register::EX as usize] }
@@ -144,17 +633,151 @@ impl VCPU16 {
     pub fn get_z(&self) -> u16 { self.registers[Register::Z as usize] }
     pub fn get_i(&self) -> u16 { self.registers[Register::I as usize] }
     pub fn get_j(&self) -> u16 { self.registers[Register::J as usize] }
+    pub fn set_sp(&mut self, value: u16) { self.registers[Register::SP as usize] = value }
+    pub fn set_pc(&mut self, value: u16) { self.registers[Register::PC as usize] = value }
+    pub fn set_ex(&mut self, value: u16) { self.registers[Register::EX as usize] = value }
+    pub fn set_ia(&mut self, value: u16) { self.registers[Register::IA as usize] = value }
+    pub fn set_a(&mut self, value: u16) { self.registers[Register::A as usize] = value }
+    pub fn set_b(&mut self, value: u16) { self.registers[Register::B as usize] = value }
+    pub fn set_c(&mut self, value: u16) { self.registers[Register::C as usize] = value }
+    pub fn set_x(&mut self, value: u16) { self.registers[Register::X as usize] = value }
+    pub fn set_y(&mut self, value: u16) { self.registers[Register::Y as usize] = value }
+    pub fn set_z(&mut self, value: u16) { self.registers[Register::Z as usize] = value }
+    pub fn set_i(&mut self, value: u16) { self.registers[Register::I as usize] = value }
+    pub fn set_j(&mut self, value: u16) { self.registers[Register::J as usize] = value }
+    /// True while waiting out a multi-cycle instruction or device stall.
+    pub fn is_busy(&self) -> bool { matches!(self.state, State::Busy(_, _)) }
+    pub fn is_sleeping(&self) -> bool { matches!(self.state, State::Sleeping(_)) }
+    pub fn is_hibernating(&self) -> bool { matches!(self.state, State::Hibernating) }
+    pub fn is_halted(&self) -> bool { matches!(self.state, State::Halted) }
+    ///
+    /// Put the CPU back as it is at power on: registers zeroed, PC at 0, no
+    /// queued interrupts or faults and the fault vector cleared. A cold reset
+    /// clears memory as well, a warm one keeps it so the loaded program runs
+    /// again from the start. The decode cache stays enabled but is emptied;
+    /// protection, the wake policy and cycle statistics are kept.
+    ///
+    pub fn reset(&mut self, clear_memory: bool) {
+        self.registers = [0; 12];
+        if clear_memory {
+            self.memory = Memory::new();
+        }
+        self.state = State::Idle;
+        self.interrupts.clear();
+        self.queueing = false;
+        self.current = 0;
+        self.fault_code = 0;
+        self.fault_vector = 0;
+        self.faults.clear();
+        self.dormant = 0;
+        self.last_wake = None;
+        self.forget_decoded();
+    }
+    pub fn wake_policy(&self) -> WakePolicy { self.wake }
+    pub fn set_wake_policy(&mut self, policy: WakePolicy) { self.wake = policy }
+    /// Why the CPU last woke from SLP or HIB, None if it hasn't since reset.
+    pub fn last_wake(&self) -> Option<WakeReason> { self.last_wake }
+    /// Cycles run since created or last cleared, by what they were spent on.
+    pub fn cycle_stats(&self) -> CycleStats { self.stats }
+    pub fn clear_cycle_stats(&mut self) { self.stats = CycleStats::default() }
+    /// End a sleep or hibernation.
+    fn wake(&mut self, reason: WakeReason) {
+        self.state = State::Idle;
+        self.dormant = 0;
+        self.last_wake = Some(reason);
+        self.stats.wakes += 1;
+    }
+    ///
+    /// Raise a hardware or software interrupt. Interrupts are dropped while IA
+    /// is zero or the queue is full; those the WakePolicy allows wake a
+    /// sleeping or hibernating CPU either way.
+    ///
+    pub fn interrupt(&mut self, message: u16) {
+        let wakes = match self.state {
+            State::Sleeping(_) => self.wake.sleep.wakes(message),
+            State::Hibernating => self.wake.hibernate.wakes(message),
+            _ => false,
+        };
+        if wakes {
+            self.wake(WakeReason::Interrupt(message));
+        }
+        if self.registers[Register::IA as usize] != 0 && self.interrupts.len() < INTERRUPT_QUEUE_LIMIT {
+            self.interrupts.push_back(message);
+        }
+    }
+    /// Number of interrupts waiting to be handled.
+    pub fn pending_interrupts(&self) -> usize { self.interrupts.len() }
+    /// Code of the last fault not yet cleared by the program, 0 for none.
+    pub fn fault_code(&self) -> u16 { self.fault_code }
+    pub fn fault_vector(&self) -> u16 { self.fault_vector }
+    pub fn set_fault_vector(&mut self, address: u16) { self.fault_vector = address }
+    /// Faults raised since last taken, oldest first.
+    pub fn take_faults(&mut self) -> Vec<Fault> { mem::take(&mut self.faults) }
+    ///
+    /// Keep decoded instructions by address so loops skip decoding them again,
+    /// a write to memory drops those it overlaps. Off by default.
+    ///
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        if enabled != self.cache.is_some() {
+            self.cache = if enabled { Some(DecodeCache::new()) } else { None };
+        }
+    }
+    pub fn has_decode_cache(&self) -> bool { self.cache.is_some() }
+    /// Hits and misses of the decode cache, None without one.
+    pub fn decode_cache_stats(&self) -> Option<CacheStats> { self.cache.as_ref().map(|cache| cache.stats) }
+    ///
+    /// Run straight-line code from blocks translated ahead of time, falling
+    /// back to interpreting where a block can't be used. A write to memory
+    /// drops the blocks it overlaps. Off by default.
+    ///
+    #[cfg(feature = "jit")]
+    pub fn set_block_cache(&mut self, enabled: bool) {
+        if enabled != self.blocks.is_some() {
+            self.blocks = if enabled { Some(BlockCache::new()) } else { None };
+        }
+    }
+    #[cfg(feature = "jit")]
+    pub fn has_block_cache(&self) -> bool { self.blocks.is_some() }
+    /// Blocks translated and run, None without a block cache.
+    #[cfg(feature = "jit")]
+    pub fn block_cache_stats(&self) -> Option<BlockStats> { self.blocks.as_ref().map(|blocks| blocks.stats) }
+    ///
+    /// Protect a range of memory from the program. Regions may overlap, an
+    /// address is read-only or no-execute if any region covering it is.
+    /// Protection survives `reset`.
+    ///
+    pub fn protect(&mut self, region: Region) { self.regions.push(region) }
+    /// Drop every protected Region.
+    pub fn clear_protection(&mut self) { self.regions.clear() }
+    pub fn regions(&self) -> &[Region] { &self.regions }
+    pub fn is_writable(&self, address: u16) -> bool { !self.regions.iter().any(|region| region.read_only && region.contains(address)) }
+    pub fn is_executable(&self, address: u16) -> bool { !self.regions.iter().any(|region| region.no_execute && region.contains(address)) }
     ///
-    /// Decode Left Value from Instruction Word
-    /// LLLLLL----------
+    /// Raise a fault against the instruction being executed.
     ///
-    /// --- Values: (6 bits) --------------------------------------------------------
+    fn fault(&mut self, code: u16) {
+        let address = self.current;
+        self.fault_code = code;
+        if self.faults.len() < FAULT_LOG_LIMIT {
+            self.faults.push(Fault { code, address });
+        }
+        if self.fault_vector == 0 {
+            self.state = State::Halted;
+        } else {
+            self.push(address);
+            self.registers[Register::PC as usize] = self.fault_vector;
+        }
+    }
+    ///
+    /// Operand Mode of an operand value, a is read before b
+    ///
+    /// --- Values: (6 bits for a, 5 bits for b) -------------------------------------
     ///  C | VALUE     | DESCRIPTION
     /// ---+-----------+----------------------------------------------------------------
     ///  0 | 0x00-0x07 | register (A, B, C, X, Y, Z, I or J, in that order)
     ///  0 | 0x08-0x0f | [register]
     ///  1 | 0x10-0x17 | [register + NEXT]
-    ///  0 |      0x18 | (POP / [SP++])
+    ///  0 |      0x18 | (POP / [SP++]) for a, (PUSH / [--SP]) for b
     ///  0 |      0x19 | [SP] / PEEK
     ///  1 |      0x1A | [SP + NEXT] / PICK n
     ///  0 |      0x1B | SP
@@ -166,562 +789,220 @@ impl VCPU16 {
     /// ---+-----------+----------------------------------------------------------------
     ///
     /// * "NEXT" means "[PC++]". Increases the word length of the instruction by 1.
+    ///   `next` is the word following the operand's place in the instruction.
     /// * By using 0x18, 0x19, 0x1A as PEEK, POP/PUSH, and PICK there's a reverse stack
     ///   starting at memory location 0xFFFF. Example: "SET PUSH, 10", "SET X, POP"
+    /// * Address arithmetic wraps around memory
     /// * Attempting to write to a literal value fails silently
-    fn decode_left(&mut self, instruction_word: u16) -> Decoded<Value> {
-        match (instruction_word & 0xFC00) >> 10 {
-            0x00 => { // A
-                Decoded {
-                    result: Value::Register {
-                        register: Register::A,
-                        value: self.registers[Register::A as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x01 => { // B
-                Decoded {
-                    result: Value::Register {
-                        register: Register::B,
-                        value: self.registers[Register::B as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x02 => { // C
-                Decoded {
-                    result: Value::Register {
-                        register: Register::C,
-                        value: self.registers[Register::C as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x03 => { // X
-                Decoded {
-                    result: Value::Register {
-                        register: Register::X,
-                        value: self.registers[Register::X as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x04 => { // Y
-                Decoded {
-                    result: Value::Register {
-                        register: Register::Y,
-                        value: self.registers[Register::Y as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x05 => { // Z
-                Decoded {
-                    result: Value::Register {
-                        register: Register::Z,
-                        value: self.registers[Register::Z as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x06 => { // I
-                Decoded {
-                    result: Value::Register {
-                        register: Register::I,
-                        value: self.registers[Register::I as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x07 => { // J
-                Decoded {
-                    result: Value::Register {
-                        register: Register::J,
-                        value: self.registers[Register::J as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x08 => {  // [A]
-                let address: u16 = self.registers[Register::A as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x09 => {  // [B]
-                let address: u16 = self.registers[Register::A as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x0A => { // [C]
-                let address: u16 = self.registers[Register::C as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x0B => { // [X]
-                let address: u16 = self.registers[Register::X as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x0C => { // [Y]
-                let address: u16 = self.registers[Register::Y as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x0D => { // [Z]
-                let address: u16 = self.registers[Register::Z as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x0E => { // [I]
-                let address: u16 = self.registers[Register::I as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x0F => { // [J]
-                let address: u16 = self.registers[Register::J as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x10 => { // [A + NEXT]
-                let base: u16 = self.registers[Register::A as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x11 => { // [B + NEXT]
-                let base: u16 = self.registers[Register::B as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x12 => { // [C + NEXT]
-                let base: u16 = self.registers[Register::C as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x13 => { // [X + NEXT]
-                let base: u16 = self.registers[Register::X as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x14 => { // [Y + NEXT]
-                let base: u16 = self.registers[Register::Y as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x15 => { // [Z + NEXT]
-                let base: u16 = self.registers[Register::Z as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x16 => { // [I + NEXT]
-                let base: u16 = self.registers[Register::I as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x17 => { // [J + NEXT]
-                let base: u16 = self.registers[Register::J as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x18 => { // Stack Pop [SP++] (left only)
-                let address: u16 = self.registers[Register::SP as usize];
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::SP as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x19 => { // Stack Peek [SP]
-                let address: u16 = self.registers[Register::SP as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x1A => { // Stack Pick [SP + NEXT]
-                let base: u16 = self.registers[Register::SP as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x1B => { // SP
-                Decoded {
-                    result: Value::Register {
-                        register: Register::SP,
-                        value: self.registers[Register::SP as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x1C => { // PC
-                Decoded {
-                    result: Value::Register {
-                        register: Register::PC,
-                        value: self.registers[Register::PC as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x1D => { // EX
-                Decoded {
-                    result: Value::Register {
-                        register: Register::EX,
-                        value: self.registers[Register::EX as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x1E => { // [NEXT]
-                let next: u16 = self.registers[Register::PC as usize];
-                let address: u16 = self.memory[next as usize];
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x1F => { // NEXT (literal)
-                let next: u16 = self.registers[Register::PC as usize];
-                let value: u16 = self.memory[next as usize];
-                self.registers[Register::PC as usize];
-                Decoded { result: Value::Literal { value }, time: 1 }
-            }
-            0x20 => { Decoded { result: Value::Literal { value: 0xFFFF }, time: 0 } }
-            0x21 => { Decoded { result: Value::Literal { value: 0 }, time: 0 } }
-            0x22 => { Decoded { result: Value::Literal { value: 1 }, time: 0 } }
-            0x23 => { Decoded { result: Value::Literal { value: 2 }, time: 0 } }
-            0x24 => { Decoded { result: Value::Literal { value: 3 }, time: 0 } }
-            0x25 => { Decoded { result: Value::Literal { value: 4 }, time: 0 } }
-            0x26 => { Decoded { result: Value::Literal { value: 5 }, time: 0 } }
-            0x27 => { Decoded { result: Value::Literal { value: 6 }, time: 0 } }
-            0x28 => { Decoded { result: Value::Literal { value: 7 }, time: 0 } }
-            0x29 => { Decoded { result: Value::Literal { value: 8 }, time: 0 } }
-            0x2A => { Decoded { result: Value::Literal { value: 9 }, time: 0 } }
-            0x2B => { Decoded { result: Value::Literal { value: 10 }, time: 0 } }
-            0x2C => { Decoded { result: Value::Literal { value: 11 }, time: 0 } }
-            0x2D => { Decoded { result: Value::Literal { value: 12 }, time: 0 } }
-            0x2E => { Decoded { result: Value::Literal { value: 13 }, time: 0 } }
-            0x2F => { Decoded { result: Value::Literal { value: 14 }, time: 0 } }
-            0x30 => { Decoded { result: Value::Literal { value: 15 }, time: 0 } }
-            0x31 => { Decoded { result: Value::Literal { value: 16 }, time: 0 } }
-            0x32 => { Decoded { result: Value::Literal { value: 17 }, time: 0 } }
-            0x33 => { Decoded { result: Value::Literal { value: 18 }, time: 0 } }
-            0x34 => { Decoded { result: Value::Literal { value: 19 }, time: 0 } }
-            0x35 => { Decoded { result: Value::Literal { value: 20 }, time: 0 } }
-            0x36 => { Decoded { result: Value::Literal { value: 21 }, time: 0 } }
-            0x37 => { Decoded { result: Value::Literal { value: 22 }, time: 0 } }
-            0x38 => { Decoded { result: Value::Literal { value: 23 }, time: 0 } }
-            0x39 => { Decoded { result: Value::Literal { value: 24 }, time: 0 } }
-            0x3A => { Decoded { result: Value::Literal { value: 25 }, time: 0 } }
-            0x3B => { Decoded { result: Value::Literal { value: 26 }, time: 0 } }
-            0x3C => { Decoded { result: Value::Literal { value: 27 }, time: 0 } }
-            0x3D => { Decoded { result: Value::Literal { value: 28 }, time: 0 } }
-            0x3E => { Decoded { result: Value::Literal { value: 29 }, time: 0 } }
-            0x3F => { Decoded { result: Value::Literal { value: 30 }, time: 0 } }
-            _ => { Decoded { result: Value::None, time: 0 } }
+    ///
+    fn mode(operand: u16, side: Operand, next: u16) -> Mode {
+        match operand {
+            0x00..=0x07 => Mode::Register(GENERAL_REGISTERS[operand as usize]),
+            0x08..=0x0F => Mode::Indirect(GENERAL_REGISTERS[operand as usize - 0x08]),
+            0x10..=0x17 => Mode::Offset(GENERAL_REGISTERS[operand as usize - 0x10], next),
+            0x18 => match side {
+                Operand::A => Mode::Pop,
+                Operand::B => Mode::Push,
+            },
+            0x19 => Mode::Peek,
+            0x1A => Mode::Pick(next),
+            0x1B => Mode::Register(Register::SP),
+            0x1C => Mode::Register(Register::PC),
+            0x1D => Mode::Register(Register::EX),
+            0x1E => Mode::Address(next),
+            0x1F => Mode::Next(next),
+            0x20..=0x3F if side == Operand::A => Mode::Literal(operand.wrapping_sub(0x21)),
+            _ => Mode::None,
         }
     }
     ///
-    /// Decode Right Value from Instruction Word
-    /// ------RRRRR-----
-    /// --- Values: (5 bits) --------------------------------------------------------
-    ///  C | VALUE     | DESCRIPTION
-    /// ---+-----------+----------------------------------------------------------------
-    ///  0 | 0x00-0x07 | register (A, B, C, X, Y, Z, I or J, in that order)
-    ///  0 | 0x08-0x0f | [register]
-    ///  1 | 0x10-0x17 | [register + NEXT]
-    ///  0 |      0x18 | (PUSH / [--SP])
-    ///  0 |      0x19 | [SP] / PEEK
-    ///  1 |      0x1a | [SP + NEXT] / PICK next
-    ///  0 |      0x1b | SP
-    ///  0 |      0x1c | PC
-    ///  0 |      0x1d | EX
-    ///  1 |      0x1e | [NEXT]
-    ///  1 |      0x1f | NEXT (literal)
-    /// ---+-----------+----------------------------------------------------------------
+    /// Bind an Operand Mode to its current Value, moving SP for POP and PUSH
     ///
-    /// * "NEXT" means "[PC++]". Increases the word length of the instruction by 1.
-    /// * By using 0x18, 0x19, 0x1a as PEEK, POP/PUSH, and PICK there's a reverse stack
-    ///   starting at memory location 0xffff. Example: "SET PUSH, 10", "SET X, POP"
-    /// * Attempting to write to a literal value fails silently
-    fn decode_right(&mut self, instruction_word: u16) -> Decoded<Value> {
-        match (instruction_word & 0x03E0) >> 5 {
-            0x00 => { // A
-                Decoded {
-                    result: Value::Register {
-                        register: Register::A,
-                        value: self.registers[Register::A as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x01 => { // B
-                Decoded {
-                    result: Value::Register {
-                        register: Register::B,
-                        value: self.registers[Register::B as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x02 => { // C
-                Decoded {
-                    result: Value::Register {
-                        register: Register::C,
-                        value: self.registers[Register::C as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x03 => { // X
-                Decoded {
-                    result: Value::Register {
-                        register: Register::X,
-                        value: self.registers[Register::X as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x04 => { // Y
-                Decoded {
-                    result: Value::Register {
-                        register: Register::Y,
-                        value: self.registers[Register::Y as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x05 => { // Z
-                Decoded {
-                    result: Value::Register {
-                        register: Register::Z,
-                        value: self.registers[Register::Z as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x06 => { // I
-                Decoded {
-                    result: Value::Register {
-                        register: Register::I,
-                        value: self.registers[Register::I as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x07 => { // J
-                Decoded {
-                    result: Value::Register {
-                        register: Register::J,
-                        value: self.registers[Register::J as usize],
-                    },
-                    time: 0,
-                }
-            }
-            0x08 => {  // [A]
-                let address: u16 = self.registers[Register::A as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x09 => {  // [B]
-                let address: u16 = self.registers[Register::A as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x0A => { // [C]
-                let address: u16 = self.registers[Register::C as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x0B => { // [X]
-                let address: u16 = self.registers[Register::X as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x0C => { // [Y]
-                let address: u16 = self.registers[Register::Y as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x0D => { // [Z]
-                let address: u16 = self.registers[Register::Z as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x0E => { // [I]
-                let address: u16 = self.registers[Register::I as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x0F => { // [J]
-                let address: u16 = self.registers[Register::J as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x10 => { // [A + NEXT]
-                let base: u16 = self.registers[Register::A as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x11 => { // [B + NEXT]
-                let base: u16 = self.registers[Register::B as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x12 => { // [C + NEXT]
-                let base: u16 = self.registers[Register::C as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x13 => { // [X + NEXT]
-                let base: u16 = self.registers[Register::X as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x14 => { // [Y + NEXT]
-                let base: u16 = self.registers[Register::Y as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x15 => { // [Z + NEXT]
-                let base: u16 = self.registers[Register::Z as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x16 => { // [I + NEXT]
-                let base: u16 = self.registers[Register::I as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x17 => { // [J + NEXT]
-                let base: u16 = self.registers[Register::J as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
-            }
-            0x18 => { // Stack Push [--SP] (right only)
-                self.registers[Register::SP as usize] -= 1;
-                let address: u16 = self.registers[Register::SP as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
-            }
-            0x19 => { // Stack Peek [SP]
-                let address: u16 = self.registers[Register::SP as usize];
-                let value: u16 = self.memory[address as usize];
-                Decoded { result: Value::Memory { address, value }, time: 0 }
+    #[inline(always)]
+    fn bind(&mut self, mode: Mode) -> Value {
+        match mode {
+            Mode::Register(register) => self.register_value(register),
+            Mode::Indirect(register) => self.memory_value(self.registers[register as usize]),
+            Mode::Offset(register, offset) => self.memory_value(self.registers[register as usize].wrapping_add(offset)),
+            Mode::Pop => {
+                let address = self.registers[Register::SP as usize];
+                self.registers[Register::SP as usize] = address.wrapping_add(1);
+                self.memory_value(address)
+            }
+            Mode::Push => {
+                let address = self.registers[Register::SP as usize].wrapping_sub(1);
+                self.registers[Register::SP as usize] = address;
+                self.memory_value(address)
+            }
+            Mode::Peek => self.memory_value(self.registers[Register::SP as usize]),
+            Mode::Pick(offset) => self.memory_value(self.registers[Register::SP as usize].wrapping_add(offset)),
+            Mode::Address(address) => self.memory_value(address),
+            Mode::Next(value) | Mode::Literal(value) => Value::Literal { value },
+            Mode::None => Value::None,
+        }
+    }
+    fn register_value(&self, register: Register) -> Value { Value::Register { register, value: self.registers[register as usize] } }
+    fn memory_value(&self, address: u16) -> Value { Value::Memory { address, value: self.memory.get(address) } }
+    ///
+    /// Read the instruction at `address` and the NEXT words of its operands.
+    /// Unused opcodes are left without operands so they fault without
+    /// touching the stack. Reading a NEXT word costs a cycle.
+    ///
+    fn read_template(&self, address: u16) -> Template {
+        let word = self.memory.get(address);
+        let (op, cycles) = if word & 0x03FF == 0 {
+            VCPU16::nullary_op((word & 0xFC00) >> 10)
+        } else if word & 0x001F == 0 {
+            VCPU16::unary_op((word & 0x03E0) >> 5)
+        } else {
+            VCPU16::binary_op(word & 0x001F)
+        };
+        if word & 0x03FF == 0 || op == Op::ERR {
+            return Template { op, a: Mode::None, b: Mode::None, length: 1, time: cycles };
+        }
+        let next = |offset: u16| self.memory.get(address.wrapping_add(offset));
+        let a = VCPU16::mode((word & 0xFC00) >> 10, Operand::A, next(1));
+        let b = if word & 0x001F == 0 { Mode::None } else { VCPU16::mode((word & 0x03E0) >> 5, Operand::B, next(1 + a.words())) };
+        let length = 1 + a.words() + b.words();
+        Template { op, a, b, length, time: cycles + length - 1 }
+    }
+    /// Template at `address`, through the decode cache if there is one
+    fn template(&mut self, address: u16) -> Template {
+        if let Some(template) = self.cache.as_mut().and_then(|cache| cache.get(address)) {
+            return template;
+        }
+        let template = self.read_template(address);
+        if let Some(ref mut cache) = self.cache {
+            cache.insert(address, template);
+        }
+        template
+    }
+    /// Write a word of memory, dropping cached instructions it overlaps
+    fn store(&mut self, address: u16, data: u16) {
+        self.memory.set(address, data);
+        if let Some(ref mut cache) = self.cache {
+            cache.invalidate(address);
+        }
+        #[cfg(feature = "jit")]
+        {
+            if let Some(ref mut blocks) = self.blocks {
+                blocks.invalidate(address);
             }
-            0x1A => { // Stack Pick [SP + NEXT]
-                let base: u16 = self.registers[Register::SP as usize];
-                let next: u16 = self.registers[Register::PC as usize];
-                let offset: u16 = self.memory[next as usize];
-                let address: u16 = base + offset;
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
+        }
+    }
+    /// Drop every cached instruction, after memory changed wholesale
+    fn forget_decoded(&mut self) {
+        if let Some(ref mut cache) = self.cache {
+            cache.clear();
+        }
+        #[cfg(feature = "jit")]
+        {
+            if let Some(ref mut blocks) = self.blocks {
+                blocks.clear();
             }
-            0x1B => { // SP
-                Decoded {
-                    result: Value::Register {
-                        register: Register::SP,
-                        value: self.registers[Register::SP as usize],
-                    },
-                    time: 0,
+        }
+    }
+    ///
+    /// Block of the Templates from `start` up to the first which may jump,
+    /// following jumps to constant addresses so a loop is unrolled into it,
+    /// and conditionals as if they held
+    ///
+    #[cfg(feature = "jit")]
+    fn translate(&self, start: u16) -> Block {
+        let (mut instructions, mut spans) = (Vec::new(), Vec::new());
+        let (mut address, mut span) = (start, (start, 0));
+        loop {
+            let template = self.read_template(address);
+            instructions.push((address, template));
+            span.1 += template.length;
+            let writes_pc = match template.op {
+                Op::IAG | Op::FCG | Op::HWN => template.a == Mode::Register(Register::PC),
+                _ => template.b == Mode::Register(Register::PC),
+            };
+            let target = match (template.op, template.a) {
+                (Op::SET, Mode::Literal(target)) | (Op::SET, Mode::Next(target)) if writes_pc => Some(target),
+                _ => None,
+            };
+            // A conditional carries on as if it held, a skip leaves the Block where it runs
+            let jumps = matches!(template.op, Op::ERR | Op::HIB | Op::JSR | Op::SLP | Op::INT | Op::RFI | Op::HWI);
+            let next = address.wrapping_add(template.length);
+            if jumps || (writes_pc && target.is_none()) || instructions.len() == BLOCK_LENGTH {
+                break;
+            }
+            match target {
+                Some(target) => {
+                    spans.push(span);
+                    span = (target, 0);
+                    address = target;
                 }
+                // Straight runs don't wrap around the end of memory
+                None if next <= address => break,
+                None => address = next,
             }
-            0x1C => { // PC
-                Decoded {
-                    result: Value::Register {
-                        register: Register::PC,
-                        value: self.registers[Register::PC as usize],
-                    },
-                    time: 0,
+        }
+        spans.push(span);
+        Block { instructions, spans }
+    }
+    ///
+    /// Run the Block at PC, translating it first if need be, for as long as
+    /// the interpreter would have run the same instructions: stopping where
+    /// PC leaves it, the CPU stops being idle, an interrupt is entered,
+    /// memory it was read from is written, or the next instruction won't
+    /// finish within `cycles`. Returns the cycles spent, 0 to leave the next
+    /// instruction to the interpreter.
+    ///
+    #[cfg(feature = "jit")]
+    fn run_block(&mut self, cycles: u64, bus: &mut dyn Bus) -> u64 {
+        self.dispatch_interrupt();
+        let start = self.registers[Register::PC as usize];
+        let cached = match self.blocks {
+            Some(ref mut blocks) => blocks.take(start),
+            None => return 0,
+        };
+        let translated = cached.is_none();
+        let block = cached.unwrap_or_else(|| self.translate(start));
+        if let Some(ref mut blocks) = self.blocks {
+            if translated {
+                BlockCache::mark(&mut blocks.code, &block.spans);
+                blocks.stats.translated += 1;
+            }
+            blocks.running.clear();
+            blocks.running.extend_from_slice(&block.spans);
+            blocks.stale = false;
+        }
+        let (mut spent, mut executed) = (0, 0);
+        for (index, &(address, template)) in block.instructions.iter().enumerate() {
+            if index > 0 {
+                // What the interpreter would check before the next instruction
+                if spent == cycles || !matches!(self.state, State::Idle) || !self.regions.is_empty() || self.blocks.as_ref().is_none_or(|blocks| blocks.stale) {
+                    break;
                 }
-            }
-            0x1D => { // EX
-                Decoded {
-                    result: Value::Register {
-                        register: Register::EX,
-                        value: self.registers[Register::EX as usize],
-                    },
-                    time: 0,
+                self.dispatch_interrupt();
+                if self.registers[Register::PC as usize] != address {
+                    break;
                 }
             }
-            0x1E => { // [NEXT]
-                let next: u16 = self.registers[Register::PC as usize];
-                let address: u16 = self.memory[next as usize];
-                let value: u16 = self.memory[address as usize];
-                self.registers[Register::PC as usize] += 1;
-                Decoded { result: Value::Memory { address, value }, time: 1 }
+            let time = template.time.max(1) as u64;
+            if spent + time > cycles {
+                break;
             }
-            0x1F => { // NEXT (literal)
-                let next: u16 = self.registers[Register::PC as usize];
-                let value: u16 = self.memory[next as usize];
-                self.registers[Register::PC as usize];
-                Decoded { result: Value::Literal { value }, time: 1 }
+            let (pending, _) = self.bind_template(address, template);
+            spent += time;
+            self.stats.active += time;
+            self.execute(pending, bus);
+            executed += 1;
+        }
+        if let Some(ref mut blocks) = self.blocks {
+            blocks.stats.executed += executed;
+            if !blocks.stale {
+                blocks.running.clear();
+                blocks.put(start, block);
             }
-            _ => Decoded { result: Value::None, time: 0 }
         }
+        spent
     }
+    ///
+    /// Nullary Operation and its cycles
     /// Nullary opcodes always have their lower ten bits unset, have no values and a
     /// six bit opcode. In binary, they have the format: oooooo0000000000
     /// --- Magical opcodes: (5 bits) --------------------------------------------------
@@ -792,15 +1073,15 @@ impl VCPU16 {
     ///  - | 0x3E | -     | Unused
     ///  - | 0x3F | -     | Unused
     /// ---+------+-------+-------------------------------------------------------------
-    fn decode_nullary(&mut self, instruction_word: u16) -> Decoded<Instruction> {
-        match (instruction_word & 0xFC00) >> 10 {
-            0x00 => Decoded { result: Instruction::NOP, time: 0 },
-            0x01 => Decoded { result: Instruction::HIB, time: 0 },
-            _ => Decoded { result: Instruction::ERR, time: 0 },
+    fn nullary_op(opcode: u16) -> (Op, u16) {
+        match opcode {
+            0x00 => (Op::NOP, 0),
+            0x01 => (Op::HIB, 0),
+            _ => (Op::ERR, 0),
         }
     }
     ///
-    /// Decode Unary Instruction
+    /// Unary Operation and its cycles, before reading NEXT words
     /// Unary opcodes always have their lower five bits unset, have one value and a
     /// five bit opcode. In binary, they have the format: aaaaaaooooo00000
     /// The value (L) is in the same six bit format as defined earlier.
@@ -811,7 +1092,7 @@ impl VCPU16 {
     ///  - | 0x00 | n/a   | Reserved for future expansion
     ///  3 | 0x01 | JSR L | pushes the address of the next instruction to the stack,
     ///    |      |       | then sets PC to L
-    ///  - | 0x02 | SLP L | Sleep a cycles
+    ///  1 | 0x02 | SLP L | sleeps for L cycles, or until woken
     ///  - | 0x03 | -     | Unused
     ///  - | 0x04 | -     | Unused
     ///  - | 0x05 | -     | Unused
@@ -825,8 +1106,8 @@ impl VCPU16 {
     ///  2 | 0x0C | IAQ L | if L is nonzero, interrupts will be added to the queue
     ///    |      |       | instead of triggered. if L is zero, interrupts will be
     ///    |      |       | triggered as normal again
-    ///  - | 0x0D | -     | Unused
-    ///  - | 0x0E | -     | Unused
+    ///  1 | 0x0D | FCG L | sets L to the fault code, then clears it
+    ///  1 | 0x0E | FVS L | sets the fault vector to L, 0 halts on a fault
     ///  - | 0x0F | -     | Unused
     ///  2 | 0x10 | HWN L | sets a to number of connected hardware devices
     ///  4 | 0x11 | HWQ L | sets A, B, C, X, Y registers to information about hardware L
@@ -848,27 +1129,25 @@ impl VCPU16 {
     ///  - | 0x1E | -     | Unused
     ///  - | 0x1F | -     | Unused
     /// ---+------+-------+-------------------------------------------------------------
-    fn decode_unary(&mut self, instruction_word: u16) -> Decoded<Instruction> {
-        let (left, ltime) = {
-            let value = self.decode_left(instruction_word);
-            (value.result, value.time)
-        };
-        match (instruction_word & 0x03E0) >> 5 {
-            0x01 => Decoded { result: Instruction::JSR { left }, time: 3 + ltime },
-            0x08 => Decoded { result: Instruction::INT { left }, time: 4 + ltime },
-            0x09 => Decoded { result: Instruction::IAG { left }, time: 1 + ltime },
-            0x0A => Decoded { result: Instruction::IAS { left }, time: 1 + ltime },
-            0x0B => Decoded { result: Instruction::RFI { left }, time: 3 + ltime },
-            0x0C => Decoded { result: Instruction::IAQ { left }, time: 2 + ltime },
-            0x10 => Decoded { result: Instruction::HWN { left }, time: 2 + ltime },
-            0x11 => Decoded { result: Instruction::HWQ { left }, time: 4 + ltime },
-            0x12 => Decoded { result: Instruction::HWI { left }, time: 4 + ltime },
-            _ => Decoded { result: Instruction::ERR, time: 0 },
+    fn unary_op(opcode: u16) -> (Op, u16) {
+        match opcode {
+            0x01 => (Op::JSR, 3),
+            0x02 => (Op::SLP, 1),
+            0x08 => (Op::INT, 4),
+            0x09 => (Op::IAG, 1),
+            0x0A => (Op::IAS, 1),
+            0x0B => (Op::RFI, 3),
+            0x0C => (Op::IAQ, 2),
+            0x0D => (Op::FCG, 1),
+            0x0E => (Op::FVS, 1),
+            0x10 => (Op::HWN, 2),
+            0x11 => (Op::HWQ, 4),
+            0x12 => (Op::HWI, 4),
+            _ => (Op::ERR, 0),
         }
     }
     ///
-    /// Decode Binary Instruction
-    /// Decode Instruction
+    /// Binary Operation and its cycles, before reading NEXT words
     /// --- Binary opcodes (5 bits) ----------------------------------------------------
     ///  C | VAL  | NAME     | DESCRIPTION
     /// ---+------+----------+----------------------------------------------------------
@@ -918,122 +1197,858 @@ impl VCPU16 {
     ///    When they skip an if instruction, they will skip an additional instruction
     ///    at the cost of one extra cycle. This lets you easily chain conditionals.
     ///  * Signed numbers are represented using two's complement.
-    fn decode_binary(&mut self, instruction_word: u16) -> Decoded<Instruction> {
-        let (left, ltime) = {
-            let value = self.decode_left(instruction_word);
-            (value.result, value.time)
-        };
-        let (right, rtime) = {
-            let value = self.decode_left(instruction_word);
-            (value.result, value.time)
-        };
-        let time = ltime + rtime;
-        match (instruction_word & 0x001F) >> 0 {
-            0x01 => Decoded { result: Instruction::SET { left, right }, time: 0 + time },
-            0x02 => Decoded { result: Instruction::ADD { left, right }, time: 2 + time },
-            0x03 => Decoded { result: Instruction::SUB { left, right }, time: 2 + time },
-            0x04 => Decoded { result: Instruction::MUL { left, right }, time: 2 + time },
-            0x05 => Decoded { result: Instruction::MLI { left, right }, time: 2 + time },
-            0x06 => Decoded { result: Instruction::DIV { left, right }, time: 3 + time },
-            0x07 => Decoded { result: Instruction::DVI { left, right }, time: 3 + time },
-            0x08 => Decoded { result: Instruction::MOD { left, right }, time: 3 + time },
-            0x09 => Decoded { result: Instruction::MDI { left, right }, time: 3 + time },
-            0x0A => Decoded { result: Instruction::AND { left, right }, time: 1 + time },
-            0x0B => Decoded { result: Instruction::BOR { left, right }, time: 1 + time },
-            0x0C => Decoded { result: Instruction::XOR { left, right }, time: 1 + time },
-            0x0D => Decoded { result: Instruction::SHR { left, right }, time: 1 + time },
-            0x0E => Decoded { result: Instruction::ASR { left, right }, time: 1 + time },
-            0x0F => Decoded { result: Instruction::SHL { left, right }, time: 1 + time },
-            0x10 => Decoded { result: Instruction::IFB { left, right }, time: 2 + time },
-            0x11 => Decoded { result: Instruction::IFC { left, right }, time: 2 + time },
-            0x12 => Decoded { result: Instruction::IFE { left, right }, time: 2 + time },
-            0x13 => Decoded { result: Instruction::IFN { left, right }, time: 2 + time },
-            0x14 => Decoded { result: Instruction::IFG { left, right }, time: 2 + time },
-            0x15 => Decoded { result: Instruction::IFA { left, right }, time: 2 + time },
-            0x16 => Decoded { result: Instruction::IFL { left, right }, time: 2 + time },
-            0x17 => Decoded { result: Instruction::IFU { left, right }, time: 2 + time },
-            0x18 => Decoded { result: Instruction::ERR, time: 1 },
-            0x19 => Decoded { result: Instruction::ERR, time: 1 },
-            0x1A => Decoded { result: Instruction::ADX { left, right }, time: 3 + time },
-            0x1B => Decoded { result: Instruction::SBX { left, right }, time: 3 + time },
-            0x1C => Decoded { result: Instruction::ERR, time: 1 },
-            0x1D => Decoded { result: Instruction::ERR, time: 1 },
-            0x1E => Decoded { result: Instruction::STI { left, right }, time: 2 + time },
-            0x1F => Decoded { result: Instruction::STD { left, right }, time: 2 + time },
-            _ => Decoded { result: Instruction::ERR, time: 0 }
+    fn binary_op(opcode: u16) -> (Op, u16) {
+        match opcode {
+            0x01 => (Op::SET, 0),
+            0x02 => (Op::ADD, 2),
+            0x03 => (Op::SUB, 2),
+            0x04 => (Op::MUL, 2),
+            0x05 => (Op::MLI, 2),
+            0x06 => (Op::DIV, 3),
+            0x07 => (Op::DVI, 3),
+            0x08 => (Op::MOD, 3),
+            0x09 => (Op::MDI, 3),
+            0x0A => (Op::AND, 1),
+            0x0B => (Op::BOR, 1),
+            0x0C => (Op::XOR, 1),
+            0x0D => (Op::SHR, 1),
+            0x0E => (Op::ASR, 1),
+            0x0F => (Op::SHL, 1),
+            0x10 => (Op::IFB, 2),
+            0x11 => (Op::IFC, 2),
+            0x12 => (Op::IFE, 2),
+            0x13 => (Op::IFN, 2),
+            0x14 => (Op::IFG, 2),
+            0x15 => (Op::IFA, 2),
+            0x16 => (Op::IFL, 2),
+            0x17 => (Op::IFU, 2),
+            0x1A => (Op::ADX, 3),
+            0x1B => (Op::SBX, 3),
+            0x1E => (Op::STI, 2),
+            0x1F => (Op::STD, 2),
+            _ => (Op::ERR, 0),
         }
     }
 
     ///
-    /// Decode Next Instruction
+    /// Decode Next Instruction, returns it with its cycles
     ///
-    fn decode(&mut self) -> Decoded<Instruction> {
-        let address: u16 = self.registers[Register::PC as usize];
-        let instruction_word: u16 = self.memory[address as usize];
-        self.registers[Register::PC as usize] += 1;
-        if instruction_word & 0x03FF == 0 {
-            self.decode_nullary(instruction_word)
-        } else if instruction_word & 0x001F == 0 {
-            self.decode_unary(instruction_word)
-        } else {
-            self.decode_binary(instruction_word)
+    #[inline(always)]
+    fn decode(&mut self) -> (Pending, u16) {
+        let current = self.registers[Register::PC as usize];
+        let template = self.template(current);
+        self.bind_template(current, template)
+    }
+    /// Bind the operands of a Template read at `current`, leaving PC past it
+    #[inline(always)]
+    fn bind_template(&mut self, current: u16, template: Template) -> (Pending, u16) {
+        self.current = current;
+        // a is bound before b's NEXT word is read, as PC sees it
+        self.registers[Register::PC as usize] = current.wrapping_add(1 + template.a.words());
+        let left = self.bind(template.a);
+        self.registers[Register::PC as usize] = current.wrapping_add(template.length);
+        let right = self.bind(template.b);
+        (Pending { op: template.op, left, right }, template.time)
+    }
+
+    /// Current value of a decoded operand
+    #[inline(always)]
+    fn read(&self, value: &Value) -> u16 {
+        match *value {
+            Value::Register { value, .. } | Value::Memory { value, .. } | Value::Literal { value } => value,
+            Value::None => 0,
+        }
+    }
+    /// Store into a decoded operand, writes to literals are ignored
+    #[inline(always)]
+    fn write(&mut self, value: &Value, data: u16) {
+        match *value {
+            Value::Register { register, .. } => self.registers[register as usize] = data,
+            Value::Memory { address, .. } if self.is_writable(address) => self.store(address, data),
+            Value::Memory { .. } => self.fault(FAULT_WRITE_PROTECTED),
+            Value::Literal { .. } | Value::None => {}
+        }
+    }
+    fn push(&mut self, data: u16) {
+        let sp = self.registers[Register::SP as usize].wrapping_sub(1);
+        self.registers[Register::SP as usize] = sp;
+        self.store(sp, data);
+    }
+    fn pop(&mut self) -> u16 {
+        let sp = self.registers[Register::SP as usize];
+        self.registers[Register::SP as usize] = sp.wrapping_add(1);
+        self.memory.get(sp)
+    }
+    /// Skip the next instruction, and any chain of conditionals it starts
+    fn skip(&mut self) {
+        loop {
+            let word = self.memory.get(self.registers[Register::PC as usize]);
+            let length = 1 + operand_words((word & 0xFC00) >> 10) + if word & 0x001F == 0 { 0 } else { operand_words((word & 0x03E0) >> 5) };
+            self.registers[Register::PC as usize] = self.registers[Register::PC as usize].wrapping_add(length);
+            let opcode = word & 0x001F;
+            if !(0x10..=0x17).contains(&opcode) {
+                break;
+            }
+        }
+    }
+    /// Conditional: run the next instruction only if `condition` holds
+    fn branch(&mut self, condition: bool) {
+        if !condition {
+            self.skip();
+        }
+    }
+    /// Enter the interrupt handler if one is pending and queueing is off
+    fn dispatch_interrupt(&mut self) {
+        let handler = self.registers[Register::IA as usize];
+        if self.queueing || handler == 0 {
+            return;
+        }
+        if let Some(message) = self.interrupts.pop_front() {
+            self.queueing = true;
+            let (pc, a) = (self.registers[Register::PC as usize], self.registers[Register::A as usize]);
+            self.push(pc);
+            self.push(a);
+            self.registers[Register::PC as usize] = handler;
+            self.registers[Register::A as usize] = message;
         }
     }
 
     /// Execute Instruction
-    fn execute(&mut self, instruction: Instruction) {
-        match instruction {
-            _ => {
-                //TODO: Stop doing nothing
+    #[inline(always)]
+    fn execute(&mut self, pending: Pending, bus: &mut dyn Bus) {
+        let ex = Register::EX as usize;
+        let Pending { op, left, right } = pending;
+        match op {
+            Op::ERR => self.fault(FAULT_INVALID_OPCODE),
+            Op::NOP => {}
+            Op::HIB => self.state = State::Hibernating,
+            Op::JSR => {
+                let (target, pc) = (self.read(&left), self.registers[Register::PC as usize]);
+                if self.is_writable(self.registers[Register::SP as usize].wrapping_sub(1)) {
+                    self.push(pc);
+                    self.registers[Register::PC as usize] = target;
+                } else {
+                    self.fault(FAULT_WRITE_PROTECTED);
+                }
+            }
+            Op::SLP => {
+                let cycles = self.read(&left);
+                if cycles > 0 {
+                    self.state = State::Sleeping(cycles);
+                }
+            }
+            Op::INT => {
+                let message = self.read(&left);
+                self.interrupt(message);
+            }
+            Op::IAG => {
+                let ia = self.registers[Register::IA as usize];
+                self.write(&left, ia);
+            }
+            Op::IAS => self.registers[Register::IA as usize] = self.read(&left),
+            Op::RFI => {
+                self.queueing = false;
+                self.registers[Register::A as usize] = self.pop();
+                self.registers[Register::PC as usize] = self.pop();
+            }
+            Op::IAQ => self.queueing = self.read(&left) != 0,
+            Op::FCG => {
+                let code = mem::replace(&mut self.fault_code, 0);
+                self.write(&left, code);
+            }
+            Op::FVS => self.fault_vector = self.read(&left),
+            Op::HWN => {
+                let count = bus.count();
+                self.write(&left, count);
+            }
+            Op::HWQ => {
+                let index = self.read(&left);
+                if let Some(info) = bus.info(index) {
+                    self.registers[Register::A as usize] = info.id as u16;
+                    self.registers[Register::B as usize] = (info.id >> 16) as u16;
+                    self.registers[Register::C as usize] = info.version;
+                    self.registers[Register::X as usize] = info.manufacturer as u16;
+                    self.registers[Register::Y as usize] = (info.manufacturer >> 16) as u16;
+                }
+            }
+            Op::HWI => {
+                let index = self.read(&left);
+                let stall = bus.interrupt(index, self);
+                if stall > 0 {
+                    self.state = State::Busy(stall, STALL);
+                }
+            }
+            Op::SET => {
+                let a = self.read(&left);
+                self.write(&right, a);
+            }
+            Op::ADD => {
+                let sum = self.read(&right) as u32 + self.read(&left) as u32;
+                self.write(&right, sum as u16);
+                self.registers[ex] = if sum > 0xFFFF { 0x0001 } else { 0x0000 };
+            }
+            Op::SUB => {
+                let (b, a) = (self.read(&right), self.read(&left));
+                self.write(&right, b.wrapping_sub(a));
+                self.registers[ex] = if a > b { 0xFFFF } else { 0x0000 };
+            }
+            Op::MUL => {
+                let product = self.read(&right) as u32 * self.read(&left) as u32;
+                self.write(&right, product as u16);
+                self.registers[ex] = (product >> 16) as u16;
+            }
+            Op::MLI => {
+                let product = self.read(&right) as i16 as i32 * self.read(&left) as i16 as i32;
+                self.write(&right, product as u16);
+                self.registers[ex] = (product >> 16) as u16;
+            }
+            Op::DIV => {
+                let (b, a) = (self.read(&right) as u32, self.read(&left) as u32);
+                let quotient = b.checked_div(a).unwrap_or(0);
+                self.write(&right, quotient as u16);
+                self.registers[ex] = (b << 16).checked_div(a).unwrap_or(0) as u16;
+            }
+            Op::DVI => {
+                let (b, a) = (self.read(&right) as i16 as i32, self.read(&left) as i16 as i32);
+                if a == 0 {
+                    self.write(&right, 0);
+                    self.registers[ex] = 0;
+                } else {
+                    self.write(&right, b.wrapping_div(a) as u16);
+                    self.registers[ex] = (b << 16).wrapping_div(a) as u16;
+                }
+            }
+            Op::MOD => {
+                let (b, a) = (self.read(&right), self.read(&left));
+                self.write(&right, if a == 0 { 0 } else { b % a });
+            }
+            Op::MDI => {
+                let (b, a) = (self.read(&right) as i16, self.read(&left) as i16);
+                self.write(&right, if a == 0 { 0 } else { b.wrapping_rem(a) as u16 });
+            }
+            Op::AND => {
+                let result = self.read(&right) & self.read(&left);
+                self.write(&right, result);
+            }
+            Op::BOR => {
+                let result = self.read(&right) | self.read(&left);
+                self.write(&right, result);
+            }
+            Op::XOR => {
+                let result = self.read(&right) ^ self.read(&left);
+                self.write(&right, result);
+            }
+            Op::SHR => {
+                let (b, a) = ((self.read(&right) as u64) << 16, self.read(&left) as u32);
+                let shifted = b.checked_shr(a).unwrap_or(0);
+                self.write(&right, (shifted >> 16) as u16);
+                self.registers[ex] = shifted as u16;
+            }
+            Op::ASR => {
+                let (b, a) = ((self.read(&right) as i16 as i64) << 16, self.read(&left).min(63) as u32);
+                let shifted = b >> a;
+                self.write(&right, (shifted >> 16) as u16);
+                self.registers[ex] = shifted as u16;
+            }
+            Op::SHL => {
+                let (b, a) = (self.read(&right) as u64, self.read(&left) as u32);
+                let shifted = b.checked_shl(a).unwrap_or(0);
+                self.write(&right, shifted as u16);
+                self.registers[ex] = (shifted >> 16) as u16;
+            }
+            Op::IFB => {
+                let condition = self.read(&right) & self.read(&left) != 0;
+                self.branch(condition);
+            }
+            Op::IFC => {
+                let condition = self.read(&right) & self.read(&left) == 0;
+                self.branch(condition);
+            }
+            Op::IFE => {
+                let condition = self.read(&right) == self.read(&left);
+                self.branch(condition);
+            }
+            Op::IFN => {
+                let condition = self.read(&right) != self.read(&left);
+                self.branch(condition);
+            }
+            Op::IFG => {
+                let condition = self.read(&right) > self.read(&left);
+                self.branch(condition);
+            }
+            Op::IFA => {
+                let condition = self.read(&right) as i16 > self.read(&left) as i16;
+                self.branch(condition);
+            }
+            Op::IFL => {
+                let condition = self.read(&right) < self.read(&left);
+                self.branch(condition);
+            }
+            Op::IFU => {
+                let condition = (self.read(&right) as i16) < self.read(&left) as i16;
+                self.branch(condition);
+            }
+            Op::ADX => {
+                let sum = self.read(&right) as u32 + self.read(&left) as u32 + self.registers[ex] as u32;
+                self.write(&right, sum as u16);
+                self.registers[ex] = if sum > 0xFFFF { 0x0001 } else { 0x0000 };
+            }
+            Op::SBX => {
+                let result = self.read(&right) as i32 - self.read(&left) as i32 + self.registers[ex] as i16 as i32;
+                self.write(&right, result as u16);
+                self.registers[ex] = if result < 0 { 0xFFFF } else if result > 0xFFFF { 0x0001 } else { 0x0000 };
+            }
+            Op::STI => {
+                let a = self.read(&left);
+                self.write(&right, a);
+                self.registers[Register::I as usize] = self.registers[Register::I as usize].wrapping_add(1);
+                self.registers[Register::J as usize] = self.registers[Register::J as usize].wrapping_add(1);
+            }
+            Op::STD => {
+                let a = self.read(&left);
+                self.write(&right, a);
+                self.registers[Register::I as usize] = self.registers[Register::I as usize].wrapping_sub(1);
+                self.registers[Register::J as usize] = self.registers[Register::J as usize].wrapping_sub(1);
             }
         }
     }
 
-    pub fn step(&mut self) {
-        match &self.state {
-            &State::Idle => {
-                let (ref instruction, cycles) = {
-                    let instruction = self.decode();
-                    (instruction.result, instruction.time)
-                };
+    /// Advance one cycle with no hardware attached.
+    pub fn step(&mut self) { self.run_with(1, &mut NoDevices) }
 
-                self.execute(&instruction);
-            }
-            &State::Busy(time, instruction) => {}
-            &State::Sleeping(time) => {
-                self.state = State::Sleeping(time - 1);
-            }
-            &State::Hibernating => {
-                // Wake up on Interrupt
+    ///
+    /// Advance one cycle. Instructions are decoded on their first cycle and
+    /// take effect on their last, hardware is reached through `bus`.
+    ///
+    pub fn step_with(&mut self, bus: &mut dyn Bus) { self.run_with(1, bus) }
+
+    /// Advance `cycles` cycles with no hardware attached.
+    pub fn run(&mut self, cycles: u64) { self.run_with(cycles, &mut NoDevices) }
+
+    ///
+    /// Advance `cycles` cycles, exactly as that many calls to `step_with`
+    /// would, but waiting out multi-cycle instructions and sleep in one go.
+    ///
+    pub fn run_with(&mut self, cycles: u64, bus: &mut dyn Bus) {
+        let mut cycles = cycles;
+        while cycles > 0 {
+            match self.state {
+                State::Idle => {
+                    #[cfg(feature = "jit")]
+                    {
+                        if self.blocks.is_some() && self.regions.is_empty() {
+                            let spent = self.run_block(cycles, bus);
+                            if spent > 0 {
+                                cycles -= spent;
+                                continue;
+                            }
+                        }
+                    }
+                    self.dispatch_interrupt();
+                    if !self.regions.is_empty() && !self.is_executable(self.registers[Register::PC as usize]) {
+                        // Refused on fetch, taking the cycle the fetch would have
+                        self.current = self.registers[Register::PC as usize];
+                        self.fault(FAULT_NO_EXECUTE);
+                        self.stats.active += 1;
+                        cycles -= 1;
+                        continue;
+                    }
+                    let (pending, time) = self.decode();
+                    let time = time.max(1) as u64;
+                    if time > cycles {
+                        self.state = State::Busy((time - cycles) as u16, pending);
+                        self.stats.active += cycles;
+                        return;
+                    }
+                    cycles -= time;
+                    self.stats.active += time;
+                    self.execute(pending, bus);
+                }
+                State::Busy(ref mut remaining, pending) => {
+                    if *remaining as u64 > cycles {
+                        *remaining -= cycles as u16;
+                        self.stats.active += cycles;
+                        return;
+                    }
+                    cycles -= *remaining as u64;
+                    self.stats.active += *remaining as u64;
+                    self.state = State::Idle;
+                    self.execute(pending, bus);
+                }
+                State::Sleeping(time) => {
+                    let (spent, reason) = self.dormant_for(cycles, Some(time as u64));
+                    self.stats.sleeping += spent;
+                    cycles -= spent;
+                    match reason {
+                        Some(reason) => self.wake(reason),
+                        None => self.state = State::Sleeping(time - spent as u16),
+                    }
+                }
+                State::Hibernating => {
+                    let (spent, reason) = self.dormant_for(cycles, None);
+                    self.stats.hibernating += spent;
+                    cycles -= spent;
+                    match reason {
+                        Some(reason) => self.wake(reason),
+                        None => return,
+                    }
+                }
+                State::Halted => {
+                    self.stats.halted += cycles;
+                    return;
+                }
             }
-            &State::Halted => {}
         }
     }
+    ///
+    /// Spend up to `cycles` dormant, with `timer` cycles left of a sleep.
+    /// Returns the cycles spent and why the CPU wakes, if it does.
+    ///
+    fn dormant_for(&mut self, cycles: u64, timer: Option<u64>) -> (u64, Option<WakeReason>) {
+        let watchdog = self.wake.watchdog.map(|limit| limit.saturating_sub(self.dormant) as u64);
+        let (spent, reason) = match (timer, watchdog) {
+            (Some(timer), Some(watchdog)) if watchdog < timer && watchdog <= cycles => (watchdog, Some(WakeReason::Watchdog)),
+            (Some(timer), _) if timer <= cycles => (timer, Some(WakeReason::Timer)),
+            (None, Some(watchdog)) if watchdog <= cycles => (watchdog, Some(WakeReason::Watchdog)),
+            _ => (cycles, None),
+        };
+        self.dormant = self.dormant.saturating_add(spent as u32);
+        (spent, reason)
+    }
+}
+
+/// Words following the instruction word used by an operand
+fn operand_words(operand: u16) -> u16 {
+    match operand {
+        0x10..=0x17 | 0x1A | 0x1E | 0x1F => 1,
+        _ => 0,
+    }
+}
+
+/// Memory image version 0 to 1: add the header and store words little-endian.
+pub fn upgrade_cpu_state_v0(mut bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+    if bytes.len() != HEADER_SIZE + IMAGE_SIZE {
+        return Err(invalid_data("truncated memory image"));
+    }
+    bytes[MAGIC.len()..HEADER_SIZE].copy_from_slice(&1u16.to_le_bytes());
+    // Version 0 was written in the byte order of the host
+    for word in bytes[HEADER_SIZE..].chunks_exact_mut(2) {
+        let value = u16::from_ne_bytes([word[0], word[1]]);
+        word.copy_from_slice(&value.to_le_bytes());
+    }
+    Ok(bytes)
+}
+
+impl Default for VCPU16 {
+    fn default() -> VCPU16 { VCPU16::new() }
+}
+
+impl Poolable for VCPU16 {
+    fn allocate() -> Box<VCPU16> { Box::new(VCPU16::new()) }
+    fn recycle(&mut self) {
+        self.reset(true);
+        self.cache = None;
+        #[cfg(feature = "jit")]
+        {
+            self.blocks = None;
+        }
+        self.regions.clear();
+        self.wake = WakePolicy::default();
+        self.stats = CycleStats::default();
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::VCPU16;
+    use super::{
+        operand_words, CacheStats, CycleStats, Fault, Operand, Region, Register, Value, Wake, WakePolicy, WakeReason, FAULT_INVALID_OPCODE, FAULT_NO_EXECUTE,
+        FAULT_WRITE_PROTECTED, MAGIC, VCPU16, VERSION,
+    };
+    use devices::{Device, DeviceInfo};
+    use error::HivemindError;
     use rand::{Rng, SeedableRng, XorShiftRng};
     use std::io::Cursor;
 
     #[test]
     pub fn test_save_load_memory() {
         // Create our Memory and external buffers
-        let mut output: [u8; 131072] = [0; 131072];
-        let mut input: [u8; 131072] = [0; 131072];
+        let mut output: [u8; 131078] = [0; 131078];
+        let mut input: [u8; 131078] = [0; 131078];
         let mut vcpu = VCPU16::new();
 
-        // Fill our input Buffer
-        XorShiftRng::from_seed([1; 4]).fill_bytes(&mut input[..]);
+        // Fill our input Buffer behind the header
+        input[..4].copy_from_slice(MAGIC);
+        input[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        XorShiftRng::from_seed([1; 4]).fill_bytes(&mut input[6..]);
 
         // Load our input into Memory
-        vcpu.load_memory(&mut Cursor::new(&mut input[..]));
+        vcpu.load_memory(&mut Cursor::new(&mut input[..])).unwrap();
+        assert_eq!(vcpu.get_memory(0), u16::from_le_bytes([input[6], input[7]]));
 
         // Save our memory to output
-        vcpu.save_memory(&mut Cursor::new(&mut output[..]));
+        vcpu.save_memory(&mut Cursor::new(&mut output[..])).unwrap();
 
         // Compare buffers
         assert_eq!(&input[..], &output[..]);
+
+        // A truncated image, one from a newer build or an oversized ROM is refused without touching memory
+        assert!(matches!(vcpu.load_memory(&mut Cursor::new(&input[..100])), Err(HivemindError::Io(_))));
+        let mut newer = input;
+        newer[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(vcpu.load_memory(&mut Cursor::new(&newer[..])), Err(HivemindError::Io(_))));
+        assert!(matches!(vcpu.load_rom(&vec![0; 65537]), Err(HivemindError::RomTooLarge(65537))));
+        vcpu.save_memory(&mut Cursor::new(&mut output[..])).unwrap();
+        assert_eq!(&input[..], &output[..]);
+
+        // A bare image saved before images were versioned
+        let fixture: &[u8] = include_bytes!("../../fixtures/saves/memory-v0.bin");
+        vcpu.load_memory(&mut &fixture[..]).unwrap();
+        assert_eq!((0..4).map(|address| vcpu.get_memory(address)).collect::<Vec<_>>(), vec![0x7C01, 0x1234, 0xABCD, 0x00FF]);
+        assert_eq!(vcpu.get_memory(0xFFFF), 0xBEEF);
+        vcpu.save_memory(&mut Cursor::new(&mut output[..])).unwrap();
+        assert_eq!(&output[..8], &[b'H', b'V', b'C', b'S', 1, 0, 0x01, 0x7C]);
+    }
+
+    /// Device which stalls for three cycles and reports how often it was called in B
+    struct Counter(u16);
+
+    impl Device for Counter {
+        fn info(&self) -> DeviceInfo { DeviceInfo { id: 0x1234_5678, version: 2, manufacturer: 0x9ABC_DEF0 } }
+        fn interrupt(&mut self, cpu: &mut VCPU16) -> u16 {
+            self.0 += 1;
+            cpu.set_b(self.0);
+            3
+        }
+    }
+
+    #[test]
+    pub fn test_interrupts_and_hardware() {
+        let mut vcpu = VCPU16::new();
+        let mut bus: Vec<Box<dyn Device>> = vec![Box::new(Counter(0))];
+        // IAS 16, INT 5, HWN I, HWQ 0, HWI 0
+        for (address, &word) in [0xC540, 0x9900, 0x1A00, 0x8620, 0x8640].iter().enumerate() {
+            vcpu.set_memory(address as u16, word);
+        }
+        // Handler: IAG J, RFI 0
+        vcpu.set_memory(16, 0x1D20);
+        vcpu.set_memory(17, 0x8560);
+        vcpu.set_a(7);
+        for _ in 0..24 {
+            vcpu.step_with(&mut bus);
+        }
+        // The handler ran with the message in A, then restored A and returned
+        assert_eq!(vcpu.get_j(), 16);
+        assert_eq!(vcpu.get_sp(), 0);
+        assert_eq!(vcpu.get_i(), 1);
+        assert_eq!((vcpu.get_a(), vcpu.get_b(), vcpu.get_c(), vcpu.get_x(), vcpu.get_y()), (0x5678, 1, 2, 0xDEF0, 0x9ABC));
+        assert_eq!(vcpu.pending_interrupts(), 0);
+        assert_eq!(vcpu.get_pc(), 7);
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn test_invalid_opcode_faults() {
+        // FVS 10, an unused binary opcode
+        let mut vcpu = VCPU16::new();
+        vcpu.load_rom(&[0xADC0, 0x0018]).unwrap();
+        // Handler: FCG B
+        vcpu.set_memory(10, 0x05A0);
+        for _ in 0..3 {
+            vcpu.step();
+        }
+        // The handler got the code and the faulting address on the stack
+        assert_eq!((vcpu.get_b(), vcpu.fault_code()), (FAULT_INVALID_OPCODE, 0));
+        assert_eq!(vcpu.get_memory(vcpu.get_sp()), 1);
+        assert_eq!(vcpu.take_faults(), vec![Fault { code: FAULT_INVALID_OPCODE, address: 1 }]);
+        assert!(vcpu.take_faults().is_empty());
+
+        // Without a fault vector the CPU halts
+        let mut vcpu = VCPU16::new();
+        vcpu.load_rom(&[0x0000, 0x0018]).unwrap();
+        for _ in 0..4 {
+            vcpu.step();
+        }
+        assert!(vcpu.is_halted());
+        assert_eq!((vcpu.get_pc(), vcpu.fault_code()), (2, FAULT_INVALID_OPCODE));
+
+        // A warm reset keeps the program and runs it again, a cold one clears it
+        vcpu.set_decode_cache(true);
+        vcpu.reset(false);
+        assert!(!vcpu.is_halted());
+        assert_eq!((vcpu.get_pc(), vcpu.fault_code(), vcpu.get_memory(1)), (0, 0, 0x0018));
+        assert!(vcpu.has_decode_cache());
+        vcpu.reset(true);
+        assert_eq!(vcpu.get_memory(1), 0);
+    }
+
+    #[test]
+    pub fn test_operand_decoding() {
+        let mut vcpu = VCPU16::new();
+        for (index, value) in (0x10..0x18).enumerate() {
+            vcpu.registers[index] = value;
+        }
+        vcpu.set_sp(0x0100);
+        vcpu.set_pc(0x0200);
+        vcpu.set_ex(0x0055);
+        for address in 0..0x0400u16 {
+            vcpu.set_memory(address, address ^ 0xA000);
+        }
+        vcpu.set_memory(0x0200, 0x0002);
+        let mut decode = |operand: u16, side: Operand| {
+            let (pc, sp) = (vcpu.get_pc(), vcpu.get_sp());
+            let (value, time) = decode_operand(&mut vcpu, operand, side);
+            let (advanced, moved) = (vcpu.get_pc().wrapping_sub(pc), vcpu.get_sp().wrapping_sub(sp));
+            vcpu.set_pc(pc);
+            vcpu.set_sp(sp);
+            (value, time, advanced, moved)
+        };
+        let memory = |address: u16| Value::Memory { address, value: address ^ 0xA000 };
+
+        assert_eq!(decode(0x01, Operand::B), (Value::Register { register: Register::B, value: 0x11 }, 0, 0, 0));
+        assert_eq!(decode(0x07, Operand::A), (Value::Register { register: Register::J, value: 0x17 }, 0, 0, 0));
+        assert_eq!(decode(0x09, Operand::A), (memory(0x11), 0, 0, 0));
+        assert_eq!(decode(0x0F, Operand::B), (memory(0x17), 0, 0, 0));
+        assert_eq!(decode(0x10, Operand::A), (memory(0x12), 1, 1, 0));
+        assert_eq!(decode(0x16, Operand::B), (memory(0x18), 1, 1, 0));
+        assert_eq!(decode(0x18, Operand::A), (memory(0x0100), 0, 0, 1));
+        assert_eq!(decode(0x18, Operand::B), (memory(0x00FF), 0, 0, 0xFFFF));
+        assert_eq!(decode(0x19, Operand::A), (memory(0x0100), 0, 0, 0));
+        assert_eq!(decode(0x1A, Operand::B), (memory(0x0102), 1, 1, 0));
+        assert_eq!(decode(0x1B, Operand::A), (Value::Register { register: Register::SP, value: 0x0100 }, 0, 0, 0));
+        assert_eq!(decode(0x1C, Operand::A), (Value::Register { register: Register::PC, value: 0x0200 }, 0, 0, 0));
+        assert_eq!(decode(0x1D, Operand::B), (Value::Register { register: Register::EX, value: 0x0055 }, 0, 0, 0));
+        assert_eq!(decode(0x1E, Operand::A), (memory(0x0002), 1, 1, 0));
+        assert_eq!(decode(0x1F, Operand::B), (Value::Literal { value: 0x0002 }, 1, 1, 0));
+        assert_eq!(decode(0x20, Operand::A), (Value::Literal { value: 0xFFFF }, 0, 0, 0));
+        assert_eq!(decode(0x3F, Operand::A), (Value::Literal { value: 30 }, 0, 0, 0));
+        assert_eq!(decode(0x20, Operand::B), (Value::None, 0, 0, 0));
+
+        // Address arithmetic wraps
+        vcpu.set_j(0xFFFF);
+        vcpu.set_sp(0);
+        assert_eq!(decode_operand(&mut vcpu, 0x17, Operand::A).0, Value::Memory { address: 1, value: 0xA001 });
+        assert_eq!(decode_operand(&mut vcpu, 0x18, Operand::B).0, Value::Memory { address: 0xFFFF, value: 0 });
+    }
+
+    /// Read an operand's NEXT word at PC and bind it, with the cycles it took
+    fn decode_operand(vcpu: &mut VCPU16, operand: u16, side: Operand) -> (Value, u16) {
+        let pc = vcpu.get_pc();
+        let mode = VCPU16::mode(operand, side, vcpu.get_memory(pc));
+        vcpu.set_pc(pc.wrapping_add(operand_words(operand)));
+        (vcpu.bind(mode), mode.words())
+    }
+
+    #[test]
+    pub fn test_binary_operand_order() {
+        // SET B, 0x1234; ADD [0x0300], 5 with a's NEXT word before b's
+        let mut vcpu = VCPU16::new();
+        vcpu.load_rom(&[0x7C21, 0x1234, 0x7FC2, 0x0005, 0x0300]).unwrap();
+        vcpu.set_memory(0x0300, 10);
+        for _ in 0..5 {
+            vcpu.step();
+        }
+        assert_eq!((vcpu.get_b(), vcpu.get_memory(0x0300), vcpu.get_pc()), (0x1234, 15, 5));
+
+        // An unused opcode faults without popping
+        let mut vcpu = VCPU16::new();
+        vcpu.load_rom(&[0x6318]).unwrap();
+        vcpu.step();
+        assert!(vcpu.is_halted());
+        assert_eq!(vcpu.get_sp(), 0);
+    }
+
+    #[test]
+    pub fn test_sleep_and_wake() {
+        // SLP 10, SET A, 1, HIB, SET A, 2
+        let mut vcpu = VCPU16::new();
+        vcpu.load_rom(&[0xAC40, 0x8801, 0x0400, 0x8C01]).unwrap();
+        vcpu.run(6);
+        assert!(vcpu.is_sleeping());
+        // By default interrupts don't cut a sleep short
+        vcpu.interrupt(3);
+        vcpu.run(5);
+        assert_eq!((vcpu.last_wake(), vcpu.get_a()), (Some(WakeReason::Timer), 0));
+        vcpu.run(102);
+        assert!(vcpu.is_hibernating());
+        assert_eq!(vcpu.cycle_stats(), CycleStats { active: 3, sleeping: 10, hibernating: 100, halted: 0, wakes: 1 });
+
+        // Only the message asked for ends hibernation
+        vcpu.set_wake_policy(WakePolicy { sleep: Wake::Any, hibernate: Wake::Message(7), watchdog: Some(50) });
+        vcpu.interrupt(3);
+        assert!(vcpu.is_hibernating());
+        vcpu.interrupt(7);
+        assert_eq!(vcpu.last_wake(), Some(WakeReason::Interrupt(7)));
+        vcpu.run(1);
+        assert_eq!(vcpu.get_a(), 2);
+
+        // The watchdog ends a long sleep, and any interrupt a short one
+        vcpu.reset(false);
+        vcpu.load_rom(&[0x7C40, 1000, 0x0400]).unwrap();
+        vcpu.clear_cycle_stats();
+        vcpu.run(60);
+        assert_eq!(vcpu.last_wake(), Some(WakeReason::Watchdog));
+        assert!(vcpu.is_hibernating());
+        // Woken again after 50 hibernating cycles, then running on
+        vcpu.run(50);
+        assert_eq!(vcpu.cycle_stats(), CycleStats { active: 10, sleeping: 50, hibernating: 50, halted: 0, wakes: 2 });
+        vcpu.reset(false);
+        vcpu.run(10);
+        vcpu.interrupt(9);
+        assert!(!vcpu.is_sleeping());
+        assert_eq!(vcpu.last_wake(), Some(WakeReason::Interrupt(9)));
+    }
+
+    #[test]
+    pub fn test_memory_protection() {
+        // SET [0], 5 ; SET [0x20], 6 ; JSR 0x20 with the stack in firmware
+        let rom = [0x9BC1, 0x0000, 0x9FC1, 0x0020, 0x7C20, 0x0020];
+        let mut vcpu = VCPU16::new();
+        vcpu.load_rom(&rom).unwrap();
+        vcpu.protect(Region::read_only(0, 0x0F));
+        vcpu.protect(Region::no_execute(0x20, 0x2F));
+        vcpu.set_fault_vector(0x10);
+        // Handler: FCG B, HIB
+        vcpu.set_memory(0x10, 0x05A0);
+        vcpu.set_memory(0x11, 0x0400);
+        vcpu.run(10);
+        assert!(vcpu.is_hibernating());
+        // The write was refused and the handler entered with the code
+        assert_eq!((vcpu.get_b(), vcpu.get_memory(0)), (FAULT_WRITE_PROTECTED, 0x9BC1));
+        assert_eq!(vcpu.take_faults(), vec![Fault { code: FAULT_WRITE_PROTECTED, address: 0 }]);
+
+        // Writes outside the regions go through, running data faults on fetch
+        vcpu.reset(false);
+        vcpu.set_pc(2);
+        vcpu.set_sp(0x40);
+        vcpu.run(20);
+        assert_eq!(vcpu.get_memory(0x20), 6);
+        assert_eq!(vcpu.take_faults(), vec![Fault { code: FAULT_NO_EXECUTE, address: 0x20 }]);
+        assert_eq!(vcpu.get_memory(0x3F), 6);
+
+        // A call pushing into firmware faults before jumping
+        vcpu.reset(false);
+        vcpu.set_pc(4);
+        vcpu.set_sp(0x10);
+        vcpu.run(20);
+        assert_eq!(vcpu.take_faults(), vec![Fault { code: FAULT_WRITE_PROTECTED, address: 4 }]);
+        assert_eq!(vcpu.regions().len(), 2);
+
+        // Without protection the program writes over its own code
+        vcpu.clear_protection();
+        vcpu.reset(false);
+        vcpu.run(3);
+        assert_eq!(vcpu.get_memory(0), 5);
+        assert!(vcpu.is_writable(0) && vcpu.is_executable(0x20));
+    }
+
+    #[test]
+    pub fn test_decode_cache() {
+        // ADD A, 1; SET [0], 0x8C02 rewriting the ADD to ADD A, 2; SET PC, 0
+        let rom = [0x8802, 0x7FC1, 0x8C02, 0x0000, 0x8781];
+        let mut plain = VCPU16::new();
+        let mut cached = VCPU16::new();
+        cached.set_decode_cache(true);
+        for vcpu in [&mut plain, &mut cached] {
+            vcpu.load_rom(&rom).unwrap();
+            for _ in 0..200 {
+                vcpu.step();
+            }
+        }
+        assert_eq!((plain.get_a(), cached.get_a()), (79, 79));
+        assert!(plain.decode_cache_stats().is_none());
+        assert_eq!(cached.decode_cache_stats(), Some(CacheStats { hits: 78, misses: 42, invalidations: 40 }));
+
+        // Overwriting a NEXT word drops the instruction it belongs to
+        let mut vcpu = VCPU16::new();
+        vcpu.set_decode_cache(true);
+        vcpu.load_rom(&[0x7C01, 0x1234, 0x8781]).unwrap();
+        vcpu.step();
+        vcpu.step();
+        vcpu.set_memory(1, 0x4321);
+        vcpu.step();
+        vcpu.step();
+        assert_eq!(vcpu.get_a(), 0x4321);
+        assert_eq!(vcpu.decode_cache_stats(), Some(CacheStats { hits: 1, misses: 3, invalidations: 1 }));
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    pub fn test_block_translation() {
+        // The self-modifying loop of test_decode_cache, rewritten under the running block
+        let rom = [0x8802, 0x7FC1, 0x8C02, 0x0000, 0x8781];
+        let mut plain = VCPU16::new();
+        let mut translated = VCPU16::new();
+        translated.set_block_cache(true);
+        for vcpu in [&mut plain, &mut translated] {
+            vcpu.load_rom(&rom).unwrap();
+            vcpu.run(800);
+        }
+        assert_eq!((plain.get_a(), translated.get_a()), (319, 319));
+        let stats = translated.block_cache_stats().unwrap();
+        assert!(stats.executed > 0 && stats.invalidations >= 39);
+
+        // Random code, faults and interrupts included, runs as interpreted in whatever slices it's run
+        let mut executed = 0;
+        for case in 0..64 {
+            let mut rng = XorShiftRng::from_seed([case + 1, 0x5EED, 7, 11]);
+            let mut plain = VCPU16::new();
+            let mut address = 0;
+            while address < 256 {
+                // SET A, 1 and SET PC, NEXT make for longer runs and loops than random words alone,
+                // IAQ 1 and IAQ 0 for interrupts entered within them
+                if rng.gen_weighted_bool(3) {
+                    plain.set_memory(address, 0x8801);
+                } else if rng.gen_weighted_bool(8) {
+                    plain.set_memory(address, if rng.gen() { 0x8980 } else { 0x8580 });
+                } else if rng.gen_weighted_bool(6) {
+                    plain.set_memory(address, 0x7F81);
+                    address += 1;
+                    plain.set_memory(address, rng.gen_range(0, 256));
+                } else {
+                    plain.set_memory(address, rng.gen());
+                }
+                address += 1;
+            }
+            plain.set_fault_vector(rng.gen_range(0, 256));
+            plain.set_ia(rng.gen_range(0, 256));
+            plain.set_sp(0x8000);
+            let mut translated = plain.clone();
+            translated.set_block_cache(true);
+            let mut budget = 4000;
+            while budget > 0 {
+                let cycles = rng.gen_range(1, 64).min(budget);
+                if rng.gen_weighted_bool(8) {
+                    plain.interrupt(case as u16);
+                    translated.interrupt(case as u16);
+                }
+                plain.run(cycles);
+                translated.run(cycles);
+                budget -= cycles;
+                let registers = |vcpu: &VCPU16| (vcpu.registers, vcpu.is_busy(), vcpu.is_halted(), vcpu.fault_code(), vcpu.pending_interrupts(), vcpu.cycle_stats());
+                assert_eq!(registers(&translated), registers(&plain), "case {}", case);
+            }
+            assert!((0..=0xFFFF).all(|address| translated.get_memory(address) == plain.get_memory(address)), "case {}", case);
+            executed += translated.block_cache_stats().unwrap().executed;
+        }
+        assert!(executed > 10_000);
+    }
+
+    #[test]
+    pub fn test_run_matches_steps() {
+        // DIV A, 3; SET B, [0x0100]; ADD A, B; SET PC, 0 as 3, 2, 2 and 1 cycles
+        let rom = [0x9006, 0x7821, 0x0100, 0x0402, 0x8781];
+        let mut stepped = VCPU16::new();
+        let mut run = VCPU16::new();
+        for vcpu in [&mut stepped, &mut run] {
+            vcpu.load_rom(&rom).unwrap();
+            vcpu.set_a(1000);
+            vcpu.set_memory(0x0100, 7);
+        }
+        for &cycles in [1, 2, 3, 5, 8, 13, 21].iter() {
+            for _ in 0..cycles {
+                stepped.step();
+            }
+            run.run(cycles);
+            assert_eq!((run.get_a(), run.get_b(), run.get_pc(), run.is_busy()), (stepped.get_a(), stepped.get_b(), stepped.get_pc(), stepped.is_busy()));
+        }
+    }
+}
@@ -0,0 +1,151 @@
+///
+/// VCPU16 Debugger
+///
+/// Runs a CPU until it reaches a breakpoint, halts or uses up its cycles, and
+/// describes where it is in the terms of its program: breakpoints are set by
+/// label, source line or address, and each traced instruction is shown with
+/// the label it sits under and the source line it was assembled from.
+///
+use devices::Bus;
+use std::collections::BTreeSet;
+use vcpu::asm::{number, SourceMap};
+use vcpu::cpu::VCPU16;
+use vcpu::disasm::disassemble;
+
+///
+/// Why `Debugger::run` returned
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Stop {
+    /// The instruction at this address is about to run
+    Breakpoint(u16),
+    Halted,
+    /// Every cycle asked for was run
+    Finished,
+}
+
+///
+/// Breakpoints and symbols for debugging a CPU
+///
+#[derive(Clone, Debug, Default)]
+pub struct Debugger {
+    map: SourceMap,
+    source: Vec<String>,
+    breakpoints: BTreeSet<u16>,
+}
+
+impl Debugger {
+    pub fn new(map: SourceMap) -> Debugger { Debugger { map, source: Vec::new(), breakpoints: BTreeSet::new() } }
+    pub fn map(&self) -> &SourceMap { &self.map }
+    /// Source the map's lines refer to, for traces to quote.
+    pub fn set_source(&mut self, source: &str) { self.source = source.lines().map(|line| line.trim().to_string()).collect() }
+    ///
+    /// Address of a label, a source line written `:line` or a number.
+    ///
+    pub fn resolve(&self, location: &str) -> Option<u16> {
+        let location = location.trim();
+        match location.strip_prefix(':') {
+            Some(line) => line.parse().ok().and_then(|line| self.map.address(line)),
+            None => self.map.symbol(location).or_else(|| number(location)),
+        }
+    }
+    /// Break before the instruction at `location`, returning its address.
+    pub fn break_at(&mut self, location: &str) -> Option<u16> {
+        let address = self.resolve(location)?;
+        self.breakpoints.insert(address);
+        Some(address)
+    }
+    pub fn clear_breakpoint(&mut self, address: u16) -> bool { self.breakpoints.remove(&address) }
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ { self.breakpoints.iter().cloned() }
+    ///
+    /// Run `cpu` for up to `cycles`, stopping before any instruction with a
+    /// breakpoint other than the one it starts on. Returns why it stopped
+    /// and the cycles run.
+    ///
+    pub fn run(&self, cpu: &mut VCPU16, cycles: u64, bus: &mut dyn Bus) -> (Stop, u64) {
+        let mut ran = 0;
+        loop {
+            if cpu.is_halted() {
+                return (Stop::Halted, ran);
+            }
+            let idle = !cpu.is_busy() && !cpu.is_sleeping();
+            if ran > 0 && idle && self.breakpoints.contains(&cpu.get_pc()) {
+                return (Stop::Breakpoint(cpu.get_pc()), ran);
+            }
+            if ran == cycles {
+                return (Stop::Finished, ran);
+            }
+            cpu.step_with(bus);
+            ran += 1;
+        }
+    }
+    /// `address` as `label+offset`, or in hex when no label precedes it.
+    pub fn locate(&self, address: u16) -> String {
+        match self.map.nearest(address) {
+            Some((label, 0)) => label.to_string(),
+            Some((label, offset)) => format!("{}+{}", label, offset),
+            None => format!("0x{:04X}", address),
+        }
+    }
+    ///
+    /// The instruction at `address` as a trace line: address, location,
+    /// disassembly and the source line it came from.
+    ///
+    pub fn describe(&self, cpu: &VCPU16, address: u16) -> String {
+        let instruction = disassemble(&|address| cpu.get_memory(address), address, Some(&self.map));
+        let mut line = format!("0x{:04X} {:<12} {}", address, self.locate(address), instruction.text);
+        if let Some(number) = self.map.line(address) {
+            match self.source.get(number - 1) {
+                Some(text) => line.push_str(&format!("    ; {}: {}", number, text)),
+                None => line.push_str(&format!("    ; line {}", number)),
+            }
+        }
+        line
+    }
+    /// Trace line for the instruction at PC.
+    pub fn trace(&self, cpu: &VCPU16) -> String { self.describe(cpu, cpu.get_pc()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Debugger, Stop};
+    use devices::NoDevices;
+    use vcpu::asm::assemble;
+    use vcpu::cpu::VCPU16;
+
+    const SOURCE: &str = "
+        start:  SET A, 0
+        loop:   ADD A, 1
+                IFN A, 3
+                    SET PC, loop
+        done:   HIB
+    ";
+
+    #[test]
+    pub fn test_breakpoints() {
+        let assembly = assemble(SOURCE).unwrap();
+        let mut debugger = Debugger::new(assembly.map);
+        debugger.set_source(SOURCE);
+        let mut vcpu = VCPU16::new();
+        vcpu.load_image(&assembly.image).unwrap();
+
+        assert_eq!(debugger.break_at("loop"), Some(1));
+        assert_eq!(debugger.break_at(":6"), Some(5));
+        assert_eq!(debugger.break_at("nowhere"), None);
+        assert_eq!(debugger.resolve("0x0003"), Some(3));
+
+        // Stops each time round the loop, then at done
+        for a in 0..3 {
+            assert_eq!(debugger.run(&mut vcpu, 1000, &mut NoDevices).0, Stop::Breakpoint(1));
+            assert_eq!(vcpu.get_a(), a);
+        }
+        assert_eq!(debugger.trace(&vcpu), "0x0001 loop         ADD A, 1    ; 3: loop:   ADD A, 1");
+        assert_eq!(debugger.describe(&vcpu, 3), "0x0003 loop+2       SET PC, loop    ; 5: SET PC, loop");
+        assert_eq!(debugger.run(&mut vcpu, 1000, &mut NoDevices).0, Stop::Breakpoint(5));
+        assert_eq!(debugger.locate(5), "done");
+
+        assert!(debugger.clear_breakpoint(5));
+        assert_eq!(debugger.run(&mut vcpu, 3, &mut NoDevices), (Stop::Finished, 3));
+        assert!(vcpu.is_hibernating());
+    }
+}
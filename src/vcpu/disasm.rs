@@ -0,0 +1,114 @@
+///
+/// VCPU16 Disassembler
+///
+/// Renders the instruction at an address in the syntax the assembler reads.
+/// Given a SourceMap, NEXT words holding the address of a symbol are shown
+/// as its label. Words that aren't an instruction are shown as `DAT`.
+///
+use vcpu::asm::{SourceMap, BINARY_OPS, NULLARY_OPS, REGISTERS, UNARY_OPS};
+
+///
+/// Disassembled instruction
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Disassembly {
+    pub address: u16,
+    /// Words taken by the instruction and its NEXT words
+    pub length: u16,
+    pub text: String,
+}
+
+///
+/// Disassemble the instruction at `address`, reading memory through `fetch`.
+///
+pub fn disassemble(fetch: &dyn Fn(u16) -> u16, address: u16, map: Option<&SourceMap>) -> Disassembly {
+    let word = fetch(address);
+    let mut length = 1;
+    let mut render = |value: u16, a: bool| {
+        let next = || {
+            let next = fetch(address.wrapping_add(length));
+            length += 1;
+            match map.and_then(|map| map.label(next)) {
+                Some(label) => label.to_string(),
+                None => format!("0x{:04X}", next),
+            }
+        };
+        operand(value, a, next)
+    };
+    let name = |table: &[(&'static str, u16)], opcode: u16| table.iter().find(|&&(_, op)| op == opcode).map(|&(name, _)| name);
+    // a's NEXT word comes before b's
+    let text = if word & 0x03FF == 0 {
+        name(&NULLARY_OPS, word >> 10).map(|name| name.to_string())
+    } else if word & 0x001F == 0 {
+        name(&UNARY_OPS, (word >> 5) & 0x1F).map(|name| format!("{} {}", name, render(word >> 10, true)))
+    } else {
+        name(&BINARY_OPS, word & 0x1F).map(|name| {
+            let a = render(word >> 10, true);
+            let b = render((word >> 5) & 0x1F, false);
+            format!("{} {}, {}", name, b, a)
+        })
+    };
+    match text {
+        Some(text) => Disassembly { address, length, text },
+        None => Disassembly { address, length: 1, text: format!("DAT 0x{:04X}", word) },
+    }
+}
+
+/// Disassemble `count` instructions starting at `address`.
+pub fn disassemble_range(fetch: &dyn Fn(u16) -> u16, address: u16, count: usize, map: Option<&SourceMap>) -> Vec<Disassembly> {
+    let mut address = address;
+    let mut lines = Vec::with_capacity(count);
+    for _ in 0..count {
+        let line = disassemble(fetch, address, map);
+        address = address.wrapping_add(line.length);
+        lines.push(line);
+    }
+    lines
+}
+
+/// Operand `value`, calling `next` for its NEXT word
+fn operand(value: u16, a: bool, next: impl FnOnce() -> String) -> String {
+    match value {
+        0x00..=0x07 => REGISTERS[value as usize].to_string(),
+        0x08..=0x0F => format!("[{}]", REGISTERS[value as usize - 0x08]),
+        0x10..=0x17 => format!("[{} + {}]", REGISTERS[value as usize - 0x10], next()),
+        0x18 if a => "POP".to_string(),
+        0x18 => "PUSH".to_string(),
+        0x19 => "PEEK".to_string(),
+        0x1A => format!("PICK {}", next()),
+        0x1B => "SP".to_string(),
+        0x1C => "PC".to_string(),
+        0x1D => "EX".to_string(),
+        0x1E => format!("[{}]", next()),
+        0x1F => next(),
+        _ => (value.wrapping_sub(0x21) as i16).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, disassemble_range, Disassembly};
+    use vcpu::asm::assemble;
+
+    #[test]
+    pub fn test_disassemble() {
+        let source = "
+            start: SET PUSH, [A + 0x0010]
+                   IFG EX, PICK 0x0002
+                   JSR start
+                   SET [0x1000], -1
+                   HIB
+                   DAT 0x0018
+        ";
+        let assembly = assemble(source).unwrap();
+        let rom = assembly.image.to_rom();
+        let fetch = |address: u16| rom.get(address as usize).cloned().unwrap_or(0);
+        let text: Vec<String> = disassemble_range(&fetch, 0, 6, Some(&assembly.map)).into_iter().map(|line| line.text).collect();
+        assert_eq!(text, vec!["SET PUSH, [A + 0x0010]", "IFG EX, PICK 0x0002", "JSR start", "SET [0x1000], -1", "HIB", "DAT 0x0018"]);
+
+        // Reassembling the text gives the same words
+        let again = assemble(&format!("start: {}", text.join("\n"))).unwrap();
+        assert_eq!(again.image.to_rom(), rom);
+        assert_eq!(disassemble(&fetch, 4, None), Disassembly { address: 4, length: 2, text: "JSR 0x0000".to_string() });
+    }
+}
@@ -0,0 +1,310 @@
+//!
+//! Golden Program Tests
+//!
+//! Small programs, written as raw words with the encoding helpers below, are
+//! run to completion on a fresh VCPU16 and the registers, EX and memory they
+//! leave behind are checked. A program is complete once PC has passed its
+//! last word with no instruction in flight, or the CPU halts. Every program
+//! is also run with the decode cache on, which must not change the outcome.
+//!
+use vcpu::cpu::VCPU16;
+
+/// Steps after which a program is assumed to be stuck
+const MAX_STEPS: usize = 10_000;
+
+// Operand values
+const A: u16 = 0x00;
+const B: u16 = 0x01;
+const C: u16 = 0x02;
+const X: u16 = 0x03;
+const Y: u16 = 0x04;
+const Z: u16 = 0x05;
+const I: u16 = 0x06;
+const J: u16 = 0x07;
+const PUSH_POP: u16 = 0x18;
+const PEEK: u16 = 0x19;
+const PICK: u16 = 0x1A;
+const SP: u16 = 0x1B;
+const PC: u16 = 0x1C;
+const EX: u16 = 0x1D;
+const NEXT_ADDRESS: u16 = 0x1E;
+const NEXT: u16 = 0x1F;
+
+// Binary opcodes
+const SET: u16 = 0x01;
+const ADD: u16 = 0x02;
+const SUB: u16 = 0x03;
+const MUL: u16 = 0x04;
+const MLI: u16 = 0x05;
+const DIV: u16 = 0x06;
+const DVI: u16 = 0x07;
+const MOD: u16 = 0x08;
+const MDI: u16 = 0x09;
+const AND: u16 = 0x0A;
+const BOR: u16 = 0x0B;
+const XOR: u16 = 0x0C;
+const SHR: u16 = 0x0D;
+const ASR: u16 = 0x0E;
+const SHL: u16 = 0x0F;
+const IFB: u16 = 0x10;
+const IFC: u16 = 0x11;
+const IFE: u16 = 0x12;
+const IFN: u16 = 0x13;
+const IFG: u16 = 0x14;
+const IFA: u16 = 0x15;
+const IFL: u16 = 0x16;
+const IFU: u16 = 0x17;
+const ADX: u16 = 0x1A;
+const SBX: u16 = 0x1B;
+const STI: u16 = 0x1E;
+const STD: u16 = 0x1F;
+
+// Unary opcodes
+const JSR: u16 = 0x01;
+const INT: u16 = 0x08;
+const IAG: u16 = 0x09;
+const IAS: u16 = 0x0A;
+const RFI: u16 = 0x0B;
+const IAQ: u16 = 0x0C;
+const HWN: u16 = 0x10;
+const HWQ: u16 = 0x11;
+const HWI: u16 = 0x12;
+
+/// Binary instruction word, `op b, a`
+fn op(opcode: u16, b: u16, a: u16) -> u16 { a << 10 | b << 5 | opcode }
+/// Unary instruction word, `op a`
+fn special(opcode: u16, a: u16) -> u16 { a << 10 | opcode << 5 }
+/// Inline literal operand, -1 to 30
+fn lit(value: i16) -> u16 { (0x21 + value) as u16 }
+/// [register] operand
+fn at(register: u16) -> u16 { 0x08 + register }
+/// [register + NEXT] operand
+fn at_next(register: u16) -> u16 { 0x10 + register }
+
+///
+/// Run `rom` from address 0 after `setup` until it completes, checking the
+/// decode cache makes no difference.
+///
+fn run_with(rom: &[u16], setup: impl Fn(&mut VCPU16)) -> VCPU16 {
+    let cpu = complete(rom, false, &setup);
+    let cached = complete(rom, true, &setup);
+    assert_eq!(state(&cached), state(&cpu), "decode cache changed the outcome");
+    assert!((0..=0xFFFF).all(|address| cached.get_memory(address) == cpu.get_memory(address)), "decode cache changed memory");
+    cpu
+}
+
+fn complete(rom: &[u16], cache: bool, setup: &dyn Fn(&mut VCPU16)) -> VCPU16 {
+    let mut cpu = VCPU16::new();
+    cpu.set_decode_cache(cache);
+    cpu.load_rom(rom).unwrap();
+    setup(&mut cpu);
+    for _ in 0..MAX_STEPS {
+        if cpu.is_halted() || (cpu.get_pc() as usize >= rom.len() && !cpu.is_busy()) {
+            return cpu;
+        }
+        cpu.step();
+    }
+    panic!("program didn't complete within {} steps, pc {:#06x}", MAX_STEPS, cpu.get_pc());
+}
+
+/// Registers, then PC, SP, EX and IA
+fn state(cpu: &VCPU16) -> [u16; 12] {
+    [
+        cpu.get_a(), cpu.get_b(), cpu.get_c(), cpu.get_x(), cpu.get_y(), cpu.get_z(), cpu.get_i(), cpu.get_j(),
+        cpu.get_pc(), cpu.get_sp(), cpu.get_ex(), cpu.get_ia(),
+    ]
+}
+
+fn run(rom: &[u16]) -> VCPU16 { run_with(rom, |_| {}) }
+
+/// A and EX after `op A, B` with A = b and B = a.
+fn compute(opcode: u16, b: u16, a: u16) -> (u16, u16) {
+    let cpu = run_with(&[op(opcode, A, B)], |cpu| {
+        cpu.set_a(b);
+        cpu.set_b(a);
+    });
+    (cpu.get_a(), cpu.get_ex())
+}
+
+/// Whether `op A, B` with A = b and B = a runs the following instruction.
+fn branches(opcode: u16, b: u16, a: u16) -> bool {
+    let cpu = run_with(&[op(opcode, A, B), op(SET, C, lit(1))], |cpu| {
+        cpu.set_a(b);
+        cpu.set_b(a);
+    });
+    cpu.get_c() == 1
+}
+
+#[test]
+pub fn test_golden_addressing_modes() {
+    let cpu = run(&[
+        op(SET, A, lit(5)),
+        op(SET, B, NEXT), 0x1234,
+        op(SET, NEXT_ADDRESS, A), 0x0100,
+        op(SET, C, NEXT_ADDRESS), 0x0100,
+        op(SET, X, NEXT), 0x0100,
+        op(SET, at(X), lit(7)),
+        op(SET, Y, at(X)),
+        op(SET, at_next(X), lit(9)), 2,
+        op(SET, Z, at_next(X)), 2,
+        op(SET, PUSH_POP, lit(11)),
+        op(SET, PUSH_POP, lit(12)),
+        op(SET, I, PEEK),
+        op(SET, J, PICK), 1,
+        op(SET, A, PUSH_POP),
+        op(SET, EX, SP),
+        // Writes to a literal are ignored
+        op(SET, NEXT, A), 3,
+        op(SET, B, PC),
+    ]);
+    assert_eq!((cpu.get_a(), cpu.get_b(), cpu.get_c(), cpu.get_x()), (12, 25, 5, 0x0100));
+    assert_eq!((cpu.get_y(), cpu.get_z(), cpu.get_i(), cpu.get_j()), (7, 9, 12, 11));
+    assert_eq!((cpu.get_sp(), cpu.get_ex(), cpu.get_pc()), (0xFFFF, 0xFFFF, 25));
+    assert_eq!((cpu.get_memory(0x0100), cpu.get_memory(0x0102)), (7, 9));
+    assert_eq!((cpu.get_memory(0xFFFE), cpu.get_memory(0xFFFF)), (12, 11));
+    assert_eq!(run(&[op(SET, A, lit(-1))]).get_a(), 0xFFFF);
+    assert_eq!(run(&[op(SET, A, lit(30))]).get_a(), 30);
+}
+
+#[test]
+pub fn test_golden_arithmetic() {
+    let cases = [
+        ("ADD", ADD, 1, 2, 3, 0),
+        ("ADD overflow", ADD, 0xFFFF, 2, 1, 1),
+        ("SUB", SUB, 5, 3, 2, 0),
+        ("SUB underflow", SUB, 1, 2, 0xFFFF, 0xFFFF),
+        ("MUL", MUL, 0x1000, 0x0100, 0, 0x0010),
+        ("MLI", MLI, 0xFFFF, 2, 0xFFFE, 0xFFFF),
+        ("MLI -32768 * -1", MLI, 0x8000, 0xFFFF, 0x8000, 0),
+        ("MLI -2 * -3", MLI, 0xFFFE, 0xFFFD, 6, 0),
+        ("DIV", DIV, 7, 2, 3, 0x8000),
+        ("DIV by zero", DIV, 5, 0, 0, 0),
+        ("DVI -7 / 2", DVI, 0xFFF9, 2, 0xFFFD, 0x8000),
+        ("DVI 7 / -2", DVI, 7, 0xFFFE, 0xFFFD, 0x8000),
+        ("DVI -32768 / -1", DVI, 0x8000, 0xFFFF, 0x8000, 0),
+        ("DVI by zero", DVI, 7, 0, 0, 0),
+        ("MOD", MOD, 7, 3, 1, 0),
+        ("MOD by zero", MOD, 7, 0, 0, 0),
+        ("MDI -7 % 16", MDI, 0xFFF9, 16, 0xFFF9, 0),
+        ("MDI 7 % -2", MDI, 7, 0xFFFE, 1, 0),
+        ("MDI -32768 % -1", MDI, 0x8000, 0xFFFF, 0, 0),
+        ("MDI by zero", MDI, 0xFFF9, 0, 0, 0),
+    ];
+    for &(name, opcode, b, a, result, ex) in cases.iter() {
+        assert_eq!(compute(opcode, b, a), (result, ex), "{}", name);
+    }
+
+    // ADX and SBX carry EX in
+    let cpu = run(&[op(SET, A, lit(-1)), op(ADD, A, lit(1)), op(ADX, A, lit(2))]);
+    assert_eq!((cpu.get_a(), cpu.get_ex()), (3, 0));
+    let cpu = run(&[op(SET, A, lit(-1)), op(ADD, A, lit(1)), op(SET, A, lit(-1)), op(ADX, A, lit(0))]);
+    assert_eq!((cpu.get_a(), cpu.get_ex()), (0, 1));
+    let cpu = run(&[op(SET, A, lit(0)), op(SUB, A, lit(1)), op(SET, A, lit(5)), op(SBX, A, lit(2))]);
+    assert_eq!((cpu.get_a(), cpu.get_ex()), (2, 0));
+    let cpu = run(&[op(SET, A, lit(1)), op(SBX, A, lit(2))]);
+    assert_eq!((cpu.get_a(), cpu.get_ex()), (0xFFFF, 0xFFFF));
+}
+
+#[test]
+pub fn test_golden_bitwise_and_shifts() {
+    let cases = [
+        ("AND", AND, 0xF0F0, 0xFF00, 0xF000, 0),
+        ("BOR", BOR, 0xF0F0, 0xFF00, 0xFFF0, 0),
+        ("XOR", XOR, 0xF0F0, 0xFF00, 0x0FF0, 0),
+        ("SHR", SHR, 0x8001, 1, 0x4000, 0x8000),
+        ("SHR past the word", SHR, 0xFFFF, 40, 0, 0),
+        ("ASR negative", ASR, 0x8001, 1, 0xC000, 0x8000),
+        ("ASR positive", ASR, 0x4000, 2, 0x1000, 0),
+        ("ASR past the word", ASR, 0xFFFF, 20, 0xFFFF, 0xFFFF),
+        ("ASR huge", ASR, 0x8000, 0xFFFF, 0xFFFF, 0xFFFF),
+        ("SHL", SHL, 0x8001, 1, 0x0002, 0x0001),
+        ("SHL into EX", SHL, 1, 20, 0, 0x0010),
+    ];
+    for &(name, opcode, b, a, result, ex) in cases.iter() {
+        assert_eq!(compute(opcode, b, a), (result, ex), "{}", name);
+    }
+}
+
+#[test]
+pub fn test_golden_branching() {
+    let cases = [
+        ("IFB", IFB, 0x0F, 0x01, true),
+        ("IFB clear", IFB, 0x0F, 0x10, false),
+        ("IFC", IFC, 0x0F, 0x10, true),
+        ("IFC set", IFC, 0x0F, 0x01, false),
+        ("IFE", IFE, 5, 5, true),
+        ("IFE differ", IFE, 5, 6, false),
+        ("IFN", IFN, 5, 6, true),
+        ("IFN same", IFN, 5, 5, false),
+        ("IFG unsigned", IFG, 0xFFFF, 1, true),
+        ("IFG less", IFG, 3, 5, false),
+        ("IFA signed", IFA, 1, 0xFFFF, true),
+        ("IFA negative", IFA, 0xFFFF, 1, false),
+        ("IFL unsigned", IFL, 1, 0xFFFF, true),
+        ("IFL greater", IFL, 5, 3, false),
+        ("IFU signed", IFU, 0xFFFF, 1, true),
+        ("IFU positive", IFU, 1, 0xFFFF, false),
+    ];
+    for &(name, opcode, b, a, taken) in cases.iter() {
+        assert_eq!(branches(opcode, b, a), taken, "{}", name);
+    }
+
+    // A failed test skips a whole chain of conditionals and the NEXT words of what it skips
+    let cpu = run(&[
+        op(IFE, A, lit(1)),
+        op(IFE, A, A),
+        op(SET, C, NEXT), 0x1234,
+        op(SET, X, lit(1)),
+    ]);
+    assert_eq!((cpu.get_c(), cpu.get_x()), (0, 1));
+}
+
+#[test]
+pub fn test_golden_subroutines_and_stack() {
+    let cpu = run(&[
+        special(JSR, lit(3)),
+        op(SET, X, lit(2)),
+        op(SET, PC, lit(5)),
+        op(SET, Y, lit(1)),
+        op(SET, PC, PUSH_POP),
+    ]);
+    assert_eq!((cpu.get_x(), cpu.get_y(), cpu.get_sp(), cpu.get_pc()), (2, 1, 0, 5));
+
+    let cpu = run(&[
+        op(SET, I, lit(16)),
+        op(SET, J, NEXT), 0x0020,
+        op(STI, at(I), lit(5)),
+        op(STD, at(J), lit(6)),
+    ]);
+    assert_eq!((cpu.get_i(), cpu.get_j()), (16, 0x0020));
+    assert_eq!((cpu.get_memory(16), cpu.get_memory(0x0021)), (5, 6));
+}
+
+#[test]
+pub fn test_golden_interrupts_and_hardware() {
+    // The handler at 5 copies the message to X and returns
+    let cpu = run(&[
+        special(IAS, lit(5)),
+        special(INT, lit(3)),
+        special(IAG, Y),
+        op(SET, PC, lit(8)),
+        0x0000,
+        op(SET, X, A),
+        special(RFI, lit(0)),
+        0x0000,
+    ]);
+    assert_eq!((cpu.get_a(), cpu.get_x(), cpu.get_y(), cpu.get_sp()), (0, 3, 5, 0));
+
+    // Queued interrupts wait
+    let cpu = run(&[special(IAS, lit(5)), special(IAQ, lit(1)), special(INT, lit(3))]);
+    assert_eq!(cpu.pending_interrupts(), 1);
+
+    // Without devices HWN counts none and HWQ and HWI do nothing
+    let cpu = run(&[op(SET, A, lit(7)), special(HWN, A), op(SET, B, lit(9)), special(HWQ, lit(0)), special(HWI, lit(0))]);
+    assert_eq!((cpu.get_a(), cpu.get_b()), (0, 9));
+
+    // NOP does nothing, HIB waits for an interrupt
+    let cpu = run(&[0x0000, 0x0400]);
+    assert!(cpu.is_hibernating());
+    assert_eq!(cpu.get_pc(), 2);
+}
@@ -0,0 +1,990 @@
+///
+/// HiveC Compiler
+///
+/// Compiles HiveC, a tiny structured language, into VCPU16 assembly so swarm
+/// logic can be written without hand allocating registers. Every value is an
+/// unsigned 16 bit word:
+///
+/// ```text
+/// program   = { global | extern | function }
+/// global    = "var" name [ "=" constant | "[" number "]" ] ";"
+/// extern    = "extern" "fn" name "(" [ name { "," name } ] ")" ";"
+/// function  = "fn" name "(" [ name { "," name } ] ")" block
+/// statement = "var" name [ "=" expr ] ";" | name "=" expr ";" | expr ";"
+///           | "if" expr block { "else" "if" expr block } [ "else" block ]
+///           | "while" expr block | "break" ";" | "continue" ";"
+///           | "return" [ expr ] ";" | "asm" string ";"
+/// block     = "{" { statement } "}"
+/// expr      = or, with || && | ^ & (== != < <= > >=) (<< >>) (+ -) (* / %)
+///             (unary - ! ~) binding tighter down the list, over numbers,
+///             'c' characters, variables, calls and parentheses
+/// ```
+///
+/// `#` starts a comment running to the end of the line. A global declared
+/// with a size is an array and its name gives its address, read and written
+/// with the `peek(address)` and `poke(address, value)` builtins. Functions
+/// take their arguments on the stack and return in A; the program starts by
+/// calling `main`. An `extern fn` is a routine of another object, such as the
+/// standard library's, taking up to three arguments in A, B and C.
+///
+/// `asm` pastes its string into the output, with `{name}` replaced by the
+/// operand holding a variable. J holds the frame pointer and must be
+/// preserved; A, B and C may be clobbered.
+///
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use vcpu::asm::{assemble_object, Assembly, REGISTERS};
+use vcpu::link::Object;
+use vcpu::stdrom;
+
+const KEYWORDS: [&str; 10] = ["var", "fn", "extern", "if", "else", "while", "break", "continue", "return", "asm"];
+
+/// Longer symbols first so they win over their prefixes
+const SYMBOLS: [&str; 29] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<<", ">>",
+    "+", "-", "*", "/", "%", "&", "|", "^", "~", "<", ">", "!", "=", "(", ")", "{", "}", "[", "]", ",", ";",
+];
+
+/// Names the assembler reads as something other than a label
+const RESERVED: [&str; 7] = ["SP", "PC", "EX", "PUSH", "POP", "PEEK", "PICK"];
+
+///
+/// Compile Error with the HiveC line it happened on
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CompileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl CompileError {
+    pub fn new<S: Into<String>>(line: usize, message: S) -> CompileError { CompileError { line, message: message.into() } }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "line {}: {}", self.line, self.message) }
+}
+
+///
+/// Assembly generated from HiveC
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Compilation {
+    /// Source for `assemble_object`
+    pub assembly: String,
+    /// HiveC line each line of the assembly came from, 0 for none
+    pub lines: Vec<usize>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Token {
+    Name(String),
+    Keyword(&'static str),
+    Int(i64),
+    Str(String),
+    Symbol(&'static str),
+    End,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum UnaryOp {
+    Negate,
+    Not,
+    Complement,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Expr {
+    Int(u16),
+    Variable(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum StatementKind {
+    Var(String, Option<Expr>),
+    Assign(String, Expr),
+    /// Condition and body of each branch, then the else body
+    If(Vec<(Expr, Vec<Statement>)>, Vec<Statement>),
+    While(Expr, Vec<Statement>),
+    Break,
+    Continue,
+    Return(Option<Expr>),
+    Expr(Expr),
+    Asm(String),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Statement {
+    line: usize,
+    kind: StatementKind,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Function {
+    line: usize,
+    name: String,
+    params: Vec<String>,
+    /// None for an extern
+    body: Option<Vec<Statement>>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Global {
+    line: usize,
+    name: String,
+    value: u16,
+    /// Words of an array
+    size: Option<u16>,
+}
+
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+struct Program {
+    globals: Vec<Global>,
+    functions: Vec<Function>,
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, CompileError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(index, c)) = chars.peek() {
+        if c == '\n' {
+            line += 1;
+            chars.next();
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            while chars.peek().is_some_and(|&(_, c)| c != '\n') {
+                chars.next();
+            }
+        } else if c.is_ascii_digit() {
+            let mut digits = String::new();
+            while let Some(&(_, c)) = chars.peek().filter(|&&(_, c)| c.is_ascii_alphanumeric()) {
+                digits.push(c);
+                chars.next();
+            }
+            let value = match digits.strip_prefix("0x") {
+                Some(hex) => i64::from_str_radix(hex, 16),
+                None => digits.parse(),
+            };
+            tokens.push((line, Token::Int(value.map_err(|_| CompileError::new(line, format!("bad number {}", digits)))?)));
+        } else if c == '\'' {
+            chars.next();
+            match (chars.next(), chars.next()) {
+                (Some((_, c)), Some((_, '\''))) => tokens.push((line, Token::Int(c as i64))),
+                _ => return Err(CompileError::new(line, "bad character literal")),
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(&(_, c)) = chars.peek().filter(|&&(_, c)| c.is_ascii_alphanumeric() || c == '_') {
+                name.push(c);
+                chars.next();
+            }
+            tokens.push((line, match KEYWORDS.iter().find(|&&keyword| keyword == name) {
+                Some(keyword) => Token::Keyword(keyword),
+                None => Token::Name(name),
+            }));
+        } else if c == '"' {
+            let start = line;
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next().map(|(_, c)| c) {
+                    Some('"') => break,
+                    Some('\\') => text.push(match chars.next().map(|(_, c)| c) {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some(c @ '"') | Some(c @ '\\') => c,
+                        _ => return Err(CompileError::new(line, "bad escape in string")),
+                    }),
+                    Some('\n') => {
+                        line += 1;
+                        text.push('\n');
+                    }
+                    None => return Err(CompileError::new(start, "unterminated string")),
+                    Some(c) => text.push(c),
+                }
+            }
+            tokens.push((start, Token::Str(text)));
+        } else {
+            match SYMBOLS.iter().find(|symbol| source[index..].starts_with(**symbol)) {
+                Some(symbol) => {
+                    for _ in 0..symbol.len() {
+                        chars.next();
+                    }
+                    tokens.push((line, Token::Symbol(symbol)));
+                }
+                None => return Err(CompileError::new(line, format!("unexpected character {:?}", c))),
+            }
+        }
+    }
+    tokens.push((line, Token::End));
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token { &self.tokens[self.position].1 }
+    fn line(&self) -> usize { self.tokens[self.position].0 }
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].1.clone();
+        if token != Token::End {
+            self.position += 1;
+        }
+        token
+    }
+    fn error(&self, expected: &str) -> CompileError {
+        let found = match *self.peek() {
+            Token::Name(ref name) => name.clone(),
+            Token::Keyword(keyword) | Token::Symbol(keyword) => keyword.to_string(),
+            Token::Int(value) => value.to_string(),
+            Token::Str(ref text) => format!("{:?}", text),
+            Token::End => "end of program".to_string(),
+        };
+        CompileError::new(self.line(), format!("expected {}, found {}", expected, found))
+    }
+    fn accept(&mut self, token: &Token) -> bool {
+        if self.peek() == token {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+    fn expect_symbol(&mut self, symbol: &'static str) -> Result<(), CompileError> {
+        if self.accept(&Token::Symbol(symbol)) { Ok(()) } else { Err(self.error(&format!("'{}'", symbol))) }
+    }
+    fn name(&mut self) -> Result<String, CompileError> {
+        match *self.peek() {
+            Token::Name(_) => match self.next() {
+                Token::Name(name) => Ok(name),
+                _ => unreachable!(),
+            },
+            _ => Err(self.error("a name")),
+        }
+    }
+    /// Name of a global or function, which becomes a label
+    fn label(&mut self) -> Result<String, CompileError> {
+        let line = self.line();
+        let name = self.name()?;
+        let upper = name.to_uppercase();
+        if REGISTERS.contains(&upper.as_str()) || RESERVED.contains(&upper.as_str()) {
+            return Err(CompileError::new(line, format!("{} is the name of a register", name)));
+        }
+        Ok(name)
+    }
+    fn program(&mut self) -> Result<Program, CompileError> {
+        let mut program = Program::default();
+        while *self.peek() != Token::End {
+            let line = self.line();
+            if self.accept(&Token::Keyword("var")) {
+                let name = self.label()?;
+                let (mut value, mut size) = (0, None);
+                if self.accept(&Token::Symbol("=")) {
+                    let expr = self.expr()?;
+                    value = constant(&expr).ok_or_else(|| CompileError::new(line, format!("{} must start as a constant", name)))?;
+                } else if self.accept(&Token::Symbol("[")) {
+                    size = match self.next() {
+                        Token::Int(words) if words > 0 && words <= 0xFFFF => Some(words as u16),
+                        _ => return Err(CompileError::new(line, format!("{} needs a size of 1 to 65535 words", name))),
+                    };
+                    self.expect_symbol("]")?;
+                }
+                self.expect_symbol(";")?;
+                program.globals.push(Global { line, name, value, size });
+            } else {
+                let external = self.accept(&Token::Keyword("extern"));
+                if !self.accept(&Token::Keyword("fn")) {
+                    return Err(self.error(if external { "fn" } else { "var, fn or extern" }));
+                }
+                let name = self.label()?;
+                self.expect_symbol("(")?;
+                let mut params = Vec::new();
+                if !self.accept(&Token::Symbol(")")) {
+                    loop {
+                        params.push(self.name()?);
+                        if self.accept(&Token::Symbol(")")) {
+                            break;
+                        }
+                        self.expect_symbol(",")?;
+                    }
+                }
+                let body = if external {
+                    self.expect_symbol(";")?;
+                    if params.len() > 3 {
+                        return Err(CompileError::new(line, format!("extern {} takes more than 3 arguments", name)));
+                    }
+                    None
+                } else {
+                    Some(self.block()?)
+                };
+                program.functions.push(Function { line, name, params, body });
+            }
+        }
+        Ok(program)
+    }
+    fn block(&mut self) -> Result<Vec<Statement>, CompileError> {
+        self.expect_symbol("{")?;
+        let mut statements = Vec::new();
+        while !self.accept(&Token::Symbol("}")) {
+            if *self.peek() == Token::End {
+                return Err(self.error("'}'"));
+            }
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+    fn statement(&mut self) -> Result<Statement, CompileError> {
+        let line = self.line();
+        let kind = if self.accept(&Token::Keyword("var")) {
+            let name = self.name()?;
+            let value = if self.accept(&Token::Symbol("=")) { Some(self.expr()?) } else { None };
+            self.expect_symbol(";")?;
+            StatementKind::Var(name, value)
+        } else if self.accept(&Token::Keyword("if")) {
+            let mut branches = vec![(self.expr()?, self.block()?)];
+            let mut otherwise = Vec::new();
+            while self.accept(&Token::Keyword("else")) {
+                if self.accept(&Token::Keyword("if")) {
+                    branches.push((self.expr()?, self.block()?));
+                } else {
+                    otherwise = self.block()?;
+                    break;
+                }
+            }
+            StatementKind::If(branches, otherwise)
+        } else if self.accept(&Token::Keyword("while")) {
+            StatementKind::While(self.expr()?, self.block()?)
+        } else if self.accept(&Token::Keyword("break")) {
+            self.expect_symbol(";")?;
+            StatementKind::Break
+        } else if self.accept(&Token::Keyword("continue")) {
+            self.expect_symbol(";")?;
+            StatementKind::Continue
+        } else if self.accept(&Token::Keyword("return")) {
+            let value = if self.accept(&Token::Symbol(";")) { None } else {
+                let value = self.expr()?;
+                self.expect_symbol(";")?;
+                Some(value)
+            };
+            StatementKind::Return(value)
+        } else if self.accept(&Token::Keyword("asm")) {
+            let text = match self.next() {
+                Token::Str(text) => text,
+                _ => return Err(CompileError::new(line, "asm needs a string")),
+            };
+            self.expect_symbol(";")?;
+            StatementKind::Asm(text)
+        } else {
+            let expr = self.expr()?;
+            let kind = match expr {
+                Expr::Variable(name) if self.accept(&Token::Symbol("=")) => StatementKind::Assign(name, self.expr()?),
+                expr => StatementKind::Expr(expr),
+            };
+            self.expect_symbol(";")?;
+            kind
+        };
+        Ok(Statement { line, kind })
+    }
+    fn expr(&mut self) -> Result<Expr, CompileError> { self.binary(0) }
+    /// Operators of each precedence level, loosest first
+    fn binary(&mut self, level: usize) -> Result<Expr, CompileError> {
+        const LEVELS: [&[(&str, BinaryOp)]; 9] = [
+            &[("||", BinaryOp::Or)],
+            &[("&&", BinaryOp::And)],
+            &[("|", BinaryOp::BitOr)],
+            &[("^", BinaryOp::BitXor)],
+            &[("&", BinaryOp::BitAnd)],
+            &[
+                ("==", BinaryOp::Equal), ("!=", BinaryOp::NotEqual), ("<", BinaryOp::Less),
+                ("<=", BinaryOp::LessEqual), (">", BinaryOp::Greater), (">=", BinaryOp::GreaterEqual),
+            ],
+            &[("<<", BinaryOp::ShiftLeft), (">>", BinaryOp::ShiftRight)],
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)],
+            &[("*", BinaryOp::Multiply), ("/", BinaryOp::Divide), ("%", BinaryOp::Remainder)],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        loop {
+            let op = match *self.peek() {
+                Token::Symbol(symbol) => LEVELS[level].iter().find(|&&(text, _)| text == symbol).map(|&(_, op)| op),
+                _ => None,
+            };
+            match op {
+                Some(op) => {
+                    self.next();
+                    left = Expr::Binary(op, Box::new(left), Box::new(self.binary(level + 1)?));
+                }
+                None => return Ok(left),
+            }
+        }
+    }
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        for &(symbol, op) in [("-", UnaryOp::Negate), ("!", UnaryOp::Not), ("~", UnaryOp::Complement)].iter() {
+            if self.accept(&Token::Symbol(symbol)) {
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        self.primary()
+    }
+    fn primary(&mut self) -> Result<Expr, CompileError> {
+        match *self.peek() {
+            Token::Int(value) => {
+                if value > 0xFFFF {
+                    return Err(CompileError::new(self.line(), format!("{} doesn't fit in a word", value)));
+                }
+                self.next();
+                Ok(Expr::Int(value as u16))
+            }
+            Token::Symbol("(") => {
+                self.next();
+                let expr = self.expr()?;
+                self.expect_symbol(")")?;
+                Ok(expr)
+            }
+            Token::Name(_) => {
+                let name = self.name()?;
+                if !self.accept(&Token::Symbol("(")) {
+                    return Ok(Expr::Variable(name));
+                }
+                let mut args = Vec::new();
+                if !self.accept(&Token::Symbol(")")) {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.accept(&Token::Symbol(")")) {
+                            break;
+                        }
+                        self.expect_symbol(",")?;
+                    }
+                }
+                Ok(Expr::Call(name, args))
+            }
+            _ => Err(self.error("an expression")),
+        }
+    }
+}
+
+/// Value of an expression made only of numbers
+fn constant(expr: &Expr) -> Option<u16> {
+    match *expr {
+        Expr::Int(value) => Some(value),
+        Expr::Unary(UnaryOp::Negate, ref value) => constant(value).map(u16::wrapping_neg),
+        Expr::Unary(UnaryOp::Complement, ref value) => constant(value).map(|value| !value),
+        Expr::Binary(op, ref left, ref right) => {
+            let (left, right) = (constant(left)?, constant(right)?);
+            match op {
+                BinaryOp::Add => Some(left.wrapping_add(right)),
+                BinaryOp::Subtract => Some(left.wrapping_sub(right)),
+                BinaryOp::Multiply => Some(left.wrapping_mul(right)),
+                BinaryOp::BitOr => Some(left | right),
+                BinaryOp::BitXor => Some(left ^ right),
+                BinaryOp::BitAnd => Some(left & right),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Mnemonic computing a binary operator into A
+fn arithmetic(op: BinaryOp) -> Option<&'static str> {
+    Some(match op {
+        BinaryOp::BitOr => "BOR",
+        BinaryOp::BitXor => "XOR",
+        BinaryOp::BitAnd => "AND",
+        BinaryOp::ShiftLeft => "SHL",
+        BinaryOp::ShiftRight => "SHR",
+        BinaryOp::Add => "ADD",
+        BinaryOp::Subtract => "SUB",
+        BinaryOp::Multiply => "MUL",
+        BinaryOp::Divide => "DIV",
+        BinaryOp::Remainder => "MOD",
+        _ => return None,
+    })
+}
+
+/// Test run on A for a comparison, and whether it passes when the comparison fails
+fn comparison(op: BinaryOp) -> Option<(&'static str, bool)> {
+    Some(match op {
+        BinaryOp::Equal => ("IFE", false),
+        BinaryOp::NotEqual => ("IFN", false),
+        BinaryOp::Less => ("IFL", false),
+        BinaryOp::Greater => ("IFG", false),
+        BinaryOp::LessEqual => ("IFG", true),
+        BinaryOp::GreaterEqual => ("IFL", true),
+        _ => return None,
+    })
+}
+
+struct Generator<'a> {
+    program: &'a Program,
+    output: Vec<(usize, String)>,
+    line: usize,
+    labels: usize,
+    /// Operand holding each argument and local of the current function
+    locals: HashMap<String, String>,
+    /// Continue and break labels of the loops being generated
+    loops: Vec<(String, String)>,
+    current: String,
+}
+
+impl<'a> Generator<'a> {
+    fn emit<S: Into<String>>(&mut self, text: S) { self.output.push((self.line, text.into())) }
+    fn emit_label(&mut self, label: &str) { self.output.push((self.line, format!("{}:", label))) }
+    fn new_label(&mut self, kind: &str) -> String {
+        self.labels += 1;
+        format!(".{}{}", kind, self.labels)
+    }
+    fn error<S: Into<String>>(&self, message: S) -> CompileError { CompileError::new(self.line, message) }
+    fn global(&self, name: &str) -> Option<&'a Global> { self.program.globals.iter().find(|global| global.name == name) }
+    /// Operand reading a variable, and whether it can be assigned
+    fn variable(&self, name: &str) -> Result<(String, bool), CompileError> {
+        if let Some(operand) = self.locals.get(name) {
+            return Ok((operand.clone(), true));
+        }
+        match self.global(name) {
+            Some(global) if global.size.is_some() => Ok((name.to_string(), false)),
+            Some(_) => Ok((format!("[{}]", name), true)),
+            None => Err(self.error(format!("unknown variable {}", name))),
+        }
+    }
+    /// Operand for an expression needing no code of its own
+    fn operand(&self, expr: &Expr) -> Result<Option<String>, CompileError> {
+        if let Some(value) = constant(expr) {
+            return Ok(Some(value.to_string()));
+        }
+        match *expr {
+            Expr::Variable(ref name) => Ok(Some(self.variable(name)?.0)),
+            _ => Ok(None),
+        }
+    }
+    fn function(&mut self, function: &Function, body: &[Statement]) -> Result<(), CompileError> {
+        self.line = function.line;
+        self.current = function.name.clone();
+        self.locals.clear();
+        let count = function.params.len();
+        for (index, param) in function.params.iter().enumerate() {
+            // The last argument is pushed last, just above the return address
+            let offset = 2 + count - 1 - index;
+            if self.locals.insert(param.clone(), format!("[J + {}]", offset)).is_some() {
+                return Err(self.error(format!("{} has two parameters named {}", function.name, param)));
+            }
+        }
+        let mut names = Vec::new();
+        declared(body, &mut names);
+        for (slot, &(line, name)) in names.iter().enumerate() {
+            if self.locals.insert(name.to_string(), format!("[J - {}]", slot + 1)).is_some() {
+                return Err(CompileError::new(line, format!("{} is declared twice", name)));
+            }
+        }
+        self.emit_label(&function.name);
+        self.emit("SET PUSH, J");
+        self.emit("SET J, SP");
+        if !names.is_empty() {
+            self.emit(format!("SUB SP, {}", names.len()));
+        }
+        self.block(body)?;
+        self.emit("SET A, 0");
+        self.emit_label(&format!(".return_{}", function.name));
+        self.emit("SET SP, J");
+        self.emit("SET J, POP");
+        self.emit("SET PC, POP");
+        Ok(())
+    }
+    fn block(&mut self, statements: &[Statement]) -> Result<(), CompileError> {
+        for statement in statements.iter() {
+            self.line = statement.line;
+            self.statement(&statement.kind)?;
+        }
+        Ok(())
+    }
+    fn statement(&mut self, kind: &StatementKind) -> Result<(), CompileError> {
+        match *kind {
+            StatementKind::Var(ref name, ref value) => {
+                let operand = self.locals[name].clone();
+                match *value {
+                    Some(ref value) => {
+                        self.expr(value)?;
+                        self.emit(format!("SET {}, A", operand));
+                    }
+                    None => self.emit(format!("SET {}, 0", operand)),
+                }
+            }
+            StatementKind::Assign(ref name, ref value) => {
+                let (operand, assignable) = self.variable(name)?;
+                if !assignable {
+                    return Err(self.error(format!("can't assign to array {}", name)));
+                }
+                self.expr(value)?;
+                self.emit(format!("SET {}, A", operand));
+            }
+            StatementKind::If(ref branches, ref otherwise) => {
+                let end = self.new_label("endif");
+                for (condition, body) in branches.iter() {
+                    let next = self.new_label("else");
+                    self.expr(condition)?;
+                    self.emit("IFE A, 0");
+                    self.emit(format!("    SET PC, {}", next));
+                    self.block(body)?;
+                    self.emit(format!("SET PC, {}", end));
+                    self.emit_label(&next);
+                }
+                self.block(otherwise)?;
+                self.emit_label(&end);
+            }
+            StatementKind::While(ref condition, ref body) => {
+                let (top, end) = (self.new_label("while"), self.new_label("wend"));
+                self.emit_label(&top);
+                self.expr(condition)?;
+                self.emit("IFE A, 0");
+                self.emit(format!("    SET PC, {}", end));
+                self.loops.push((top.clone(), end.clone()));
+                self.block(body)?;
+                self.loops.pop();
+                self.emit(format!("SET PC, {}", top));
+                self.emit_label(&end);
+            }
+            StatementKind::Break | StatementKind::Continue => {
+                let target = match (self.loops.last(), kind) {
+                    (Some((_, end)), &StatementKind::Break) => end.clone(),
+                    (Some((top, _)), _) => top.clone(),
+                    (None, _) => return Err(self.error("break and continue must be inside a loop")),
+                };
+                self.emit(format!("SET PC, {}", target));
+            }
+            StatementKind::Return(ref value) => {
+                match *value {
+                    Some(ref value) => self.expr(value)?,
+                    None => self.emit("SET A, 0"),
+                }
+                let function = self.current.clone();
+                self.emit(format!("SET PC, .return_{}", function));
+            }
+            StatementKind::Expr(ref expr) => self.expr(expr)?,
+            StatementKind::Asm(ref text) => {
+                for line in substitute(text, |name| self.variable(name).map(|(operand, _)| operand))?.lines() {
+                    self.emit(line.trim());
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Code leaving the value of `expr` in A
+    fn expr(&mut self, expr: &Expr) -> Result<(), CompileError> {
+        if let Some(operand) = self.operand(expr)? {
+            self.emit(format!("SET A, {}", operand));
+            return Ok(());
+        }
+        match *expr {
+            Expr::Int(_) | Expr::Variable(_) => unreachable!(),
+            Expr::Unary(op, ref value) => {
+                self.expr(value)?;
+                match op {
+                    UnaryOp::Negate => {
+                        self.emit("XOR A, 0xFFFF");
+                        self.emit("ADD A, 1");
+                    }
+                    UnaryOp::Complement => self.emit("XOR A, 0xFFFF"),
+                    UnaryOp::Not => self.test("IFE", "0", false),
+                }
+            }
+            Expr::Binary(op @ BinaryOp::And, ref left, ref right) | Expr::Binary(op @ BinaryOp::Or, ref left, ref right) => {
+                let end = self.new_label("logic");
+                self.expr(left)?;
+                self.emit(if op == BinaryOp::And { "IFE A, 0" } else { "IFN A, 0" });
+                self.emit(format!("    SET PC, {}", end));
+                self.expr(right)?;
+                self.emit_label(&end);
+                self.test("IFN", "0", false);
+            }
+            Expr::Binary(op, ref left, ref right) => {
+                self.expr(left)?;
+                let operand = match self.operand(right)? {
+                    Some(operand) => operand,
+                    None => {
+                        self.emit("SET PUSH, A");
+                        self.expr(right)?;
+                        self.emit("SET B, A");
+                        self.emit("SET A, POP");
+                        "B".to_string()
+                    }
+                };
+                match (arithmetic(op), comparison(op)) {
+                    (Some(mnemonic), _) => self.emit(format!("{} A, {}", mnemonic, operand)),
+                    (_, Some((test, inverted))) => self.test(test, &operand, inverted),
+                    _ => unreachable!(),
+                }
+            }
+            Expr::Call(ref name, ref args) => self.call(name, args)?,
+        }
+        Ok(())
+    }
+    /// Set A to whether `test` of A against `operand` passes, or fails if `inverted`
+    fn test(&mut self, test: &str, operand: &str, inverted: bool) {
+        self.emit(format!("SET C, {}", inverted as u16));
+        self.emit(format!("{} A, {}", test, operand));
+        self.emit(format!("    SET C, {}", !inverted as u16));
+        self.emit("SET A, C");
+    }
+    fn call(&mut self, name: &str, args: &[Expr]) -> Result<(), CompileError> {
+        let arity = match name {
+            "peek" => 1,
+            "poke" => 2,
+            _ => match self.program.functions.iter().find(|function| function.name == name) {
+                Some(function) => function.params.len(),
+                None => return Err(self.error(format!("unknown function {}", name))),
+            },
+        };
+        if args.len() != arity {
+            return Err(self.error(format!("{} takes {} arguments, not {}", name, arity, args.len())));
+        }
+        for arg in args.iter() {
+            self.expr(arg)?;
+            self.emit("SET PUSH, A");
+        }
+        let external = self.program.functions.iter().any(|function| function.name == name && function.body.is_none());
+        match name {
+            "peek" => {
+                self.emit("SET A, POP");
+                self.emit("SET A, [A]");
+            }
+            "poke" => {
+                self.emit("SET A, POP");
+                self.emit("SET B, POP");
+                self.emit("SET [B], A");
+            }
+            _ if external => {
+                for register in ["A", "B", "C"].iter().take(args.len()).rev() {
+                    self.emit(format!("SET {}, POP", register));
+                }
+                self.emit(format!("JSR {}", name));
+            }
+            _ => {
+                self.emit(format!("JSR {}", name));
+                if !args.is_empty() {
+                    self.emit(format!("ADD SP, {}", args.len()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Names declared with var anywhere in a function body, in order
+fn declared<'b>(statements: &'b [Statement], names: &mut Vec<(usize, &'b str)>) {
+    for statement in statements.iter() {
+        match statement.kind {
+            StatementKind::Var(ref name, _) => names.push((statement.line, name)),
+            StatementKind::If(ref branches, ref otherwise) => {
+                for (_, body) in branches.iter() {
+                    declared(body, names);
+                }
+                declared(otherwise, names);
+            }
+            StatementKind::While(_, ref body) => declared(body, names),
+            _ => {}
+        }
+    }
+}
+
+/// `text` with each `{name}` replaced by `operand(name)`
+fn substitute(text: &str, operand: impl Fn(&str) -> Result<String, CompileError>) -> Result<String, CompileError> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        result.push_str(&rest[..start]);
+        result.push_str(&operand(rest[start + 1..end].trim())?);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+///
+/// Compile HiveC into assembly for `assemble_object`.
+///
+pub fn compile(source: &str) -> Result<Compilation, CompileError> {
+    let program = Parser { tokens: tokenize(source)?, position: 0 }.program()?;
+    let mut names: BTreeMap<&str, usize> = BTreeMap::new();
+    for (line, name) in program.globals.iter().map(|global| (global.line, &global.name)).chain(program.functions.iter().map(|function| (function.line, &function.name))) {
+        if ["peek", "poke"].contains(&name.as_str()) || names.insert(name, line).is_some() {
+            return Err(CompileError::new(line, format!("{} is defined twice", name)));
+        }
+    }
+    let mut generator = Generator { program: &program, output: Vec::new(), line: 0, labels: 0, locals: HashMap::new(), loops: Vec::new(), current: String::new() };
+    let externs: Vec<&str> = program.functions.iter().filter(|function| function.body.is_none()).map(|function| function.name.as_str()).collect();
+    if !externs.is_empty() {
+        generator.emit(format!(".extern {}", externs.join(", ")));
+    }
+    for name in names.keys().filter(|name| !externs.contains(name)) {
+        generator.emit(format!(".global {}", name));
+    }
+    if let Some(main) = program.functions.iter().find(|function| function.name == "main" && function.body.is_some()) {
+        generator.line = main.line;
+        generator.emit(".entry .start");
+        generator.emit_label(".start");
+        generator.emit("JSR main");
+        generator.emit_label(".halt");
+        generator.emit("HIB");
+        generator.emit("SET PC, .halt");
+    }
+    for function in program.functions.iter() {
+        if let Some(ref body) = function.body {
+            generator.function(function, body)?;
+        }
+    }
+    if !program.globals.is_empty() {
+        generator.line = 0;
+        generator.emit(".section .data");
+    }
+    for global in program.globals.iter() {
+        generator.line = global.line;
+        let words = match global.size {
+            Some(size) => vec!["0"; size as usize].join(", "),
+            None => global.value.to_string(),
+        };
+        generator.emit(format!("{}: DAT {}", global.name, words));
+    }
+    let (lines, assembly): (Vec<usize>, Vec<String>) = generator.output.into_iter().unzip();
+    Ok(Compilation { assembly: assembly.join("\n"), lines })
+}
+
+///
+/// Compile HiveC into an Object named `name`, whose section lines are those
+/// of the HiveC source and whose symbols are its functions and globals.
+///
+pub fn compile_object(name: &str, source: &str) -> Result<Object, CompileError> {
+    let compilation = compile(source)?;
+    let line = |line: usize| compilation.lines.get(line.wrapping_sub(1)).cloned().unwrap_or(0);
+    let mut object = assemble_object(name, &compilation.assembly).map_err(|error| CompileError::new(line(error.line), error.message))?;
+    // Labels the compiler made up only clutter the SourceMap
+    object.symbols.retain(|name, _| !name.starts_with('.'));
+    for section in object.sections.iter_mut() {
+        section.lines = section.lines.iter().map(|(&offset, &number)| (offset, line(number))).filter(|&(_, number)| number != 0).collect();
+    }
+    Ok(object)
+}
+
+///
+/// Compile HiveC into a program loaded at 0 and linked against the standard
+/// library, whose routines it may declare with `extern fn`.
+///
+pub fn build(source: &str) -> Result<Assembly, CompileError> {
+    let object = compile_object("main", source)?;
+    stdrom::link(&[object], 0).map_err(|error| CompileError::new(0, error.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build, compile, CompileError};
+    use devices::NoDevices;
+    use vcpu::cpu::VCPU16;
+    use vcpu::debugger::{Debugger, Stop};
+
+    const SOURCE: &str = "
+        # Globals start as constants, arrays as zeros
+        var total = -1;
+        var fib = 0;
+        var text[6];
+        var length;
+        var flags;
+        extern fn itoa(value, buffer);
+
+        fn fibonacci(n) {
+            if n < 2 { return n; }
+            return fibonacci(n - 1) + fibonacci(n - 2);
+        }
+
+        fn main() {
+            var i = 0;
+            total = 0;
+            while 1 {
+                i = i + 1;
+                if i % 2 == 0 { continue; } else if i > 9 { break; }
+                total = total + i * i;
+            }
+            fib = fibonacci(10);
+            length = itoa(fib * 2 - 5, text);
+            poke(text + 5, peek(text) + 1);
+            flags = (total >= 165) | (!(fib != 55) << 1) | ((3 <= 2 || -1 > 0) << 2) | ((i == 11 && 0) << 3);
+            asm \"SET {i}, 0x1234\nADD {flags}, {i}\";
+        }
+    ";
+
+    #[test]
+    pub fn test_compile() {
+        let program = build(SOURCE).unwrap();
+        let mut vcpu = VCPU16::new();
+        vcpu.load_image(&program.image).unwrap();
+        vcpu.run(100_000);
+        assert!(vcpu.is_hibernating());
+
+        let read = |name: &str| vcpu.get_memory(program.map.symbol(name).unwrap());
+        // 1 + 9 + 25 + 49 + 81
+        assert_eq!((read("total"), read("fib"), read("length")), (165, 55, 3));
+        let text = program.map.symbol("text").unwrap();
+        let text: Vec<u16> = (text..text + 6).map(|address| vcpu.get_memory(address)).collect();
+        assert_eq!(text, vec!['1' as u16, '0' as u16, '5' as u16, 0, 0, '2' as u16]);
+        assert_eq!(read("flags"), 0x1234 + 0b0111);
+
+        // The debugger follows the HiveC source
+        let mut debugger = Debugger::new(program.map.clone());
+        debugger.set_source(SOURCE);
+        let address = debugger.break_at(":12").unwrap();
+        assert_eq!(debugger.locate(address), "fibonacci+17");
+        let mut vcpu = VCPU16::new();
+        vcpu.load_image(&program.image).unwrap();
+        assert_eq!(debugger.run(&mut vcpu, 100_000, &mut NoDevices).0, Stop::Breakpoint(address));
+        assert!(debugger.trace(&vcpu).ends_with("; 12: return fibonacci(n - 1) + fibonacci(n - 2);"));
+    }
+
+    #[test]
+    pub fn test_compile_errors() {
+        let error = |source: &str| compile(source).unwrap_err();
+        assert_eq!(error("fn main() {\n  x = 1;\n}"), CompileError::new(2, "unknown variable x"));
+        assert_eq!(error("fn f(a) { }\nfn main() { f(); }"), CompileError::new(2, "f takes 1 arguments, not 0"));
+        assert_eq!(error("var count;\nvar count;"), CompileError::new(2, "count is defined twice"));
+        assert_eq!(error("var pc;"), CompileError::new(1, "pc is the name of a register"));
+        assert_eq!(error("fn main() { break; }"), CompileError::new(1, "break and continue must be inside a loop"));
+        assert_eq!(error("var buf[4];\nfn main() { buf = 1; }"), CompileError::new(2, "can't assign to array buf"));
+        assert_eq!(error("fn main() {\nvar i;\nvar i;\n}"), CompileError::new(3, "i is declared twice"));
+        assert_eq!(error("fn main() { 1 + ; }"), CompileError::new(1, "expected an expression, found ;"));
+        assert_eq!(build("fn main() {\n\n  asm \"BAD A\";\n}").unwrap_err(), CompileError::new(3, "unknown instruction BAD"));
+        assert_eq!(build("extern fn missing();\nfn main() { missing(); }").unwrap_err().message, "main refers to undefined symbol missing");
+    }
+}
@@ -0,0 +1,389 @@
+//!
+//! ROM Images
+//!
+//! An Image is what a toolchain hands the hive: segments of words to load at
+//! their addresses, the address to start executing at and the symbols of the
+//! program. Images are read and written in four formats:
+//!
+//! * Raw word dumps, big or little-endian, loaded at address 0
+//! * Intel HEX, each word as two bytes big-endian at twice its address
+//! * The native `.hive` format
+//!
+//! A `.hive` image is little-endian like all persisted data:
+//!
+//! ---+-------+--------------------------------------------------------------
+//!  # | SIZE  | DESCRIPTION
+//! ---+-------+--------------------------------------------------------------
+//!  1 | 4     | Magic "HIVE"
+//!  2 | 2     | Format Version
+//!  3 | 2     | Entry Point
+//!  4 | 2     | Segment Count
+//!  5 | 2     | Symbol Count
+//!  6 | ...   | Segments: (address u16, length u32, words u16 * length)
+//!  7 | ...   | Symbols: (name string, address u16)
+//!  8 | 4     | CRC-32 of everything before it
+//! ---+-------+--------------------------------------------------------------
+//!
+use codec::{invalid_data, read_string, read_u16, read_u32, write_string, write_u16, write_u32};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+
+const MAGIC: &[u8; 4] = b"HIVE";
+const VERSION: u16 = 1;
+/// Words in a CPU's memory
+const WORDS: usize = 65536;
+/// Data bytes per Intel HEX record written
+const HEX_RECORD_BYTES: usize = 16;
+
+///
+/// Image File Format
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Format {
+    RawBigEndian,
+    RawLittleEndian,
+    IntelHex,
+    Hive,
+}
+
+///
+/// Words loaded at an address
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Segment {
+    pub address: u16,
+    pub words: Vec<u16>,
+}
+
+///
+/// Loadable program
+///
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Image {
+    /// Address the PC starts at
+    pub entry: u16,
+    pub segments: Vec<Segment>,
+    pub symbols: BTreeMap<String, u16>,
+}
+
+impl Image {
+    pub fn new() -> Image { Image::default() }
+    /// Image of `rom` loaded at address 0.
+    pub fn from_rom(rom: &[u16]) -> io::Result<Image> {
+        if rom.len() > WORDS {
+            return Err(invalid_data("image is larger than memory"));
+        }
+        Ok(Image { entry: 0, segments: vec![Segment { address: 0, words: rom.to_vec() }], symbols: BTreeMap::new() })
+    }
+    pub fn symbol(&self, name: &str) -> Option<u16> { self.symbols.get(name).cloned() }
+    ///
+    /// Memory from address 0 up to the end of the last segment, gaps between
+    /// segments zeroed.
+    ///
+    pub fn to_rom(&self) -> Vec<u16> {
+        let length = self.segments.iter().map(|segment| segment.address as usize + segment.words.len()).max().unwrap_or(0);
+        let mut rom = vec![0; length];
+        for segment in self.segments.iter() {
+            rom[segment.address as usize..segment.address as usize + segment.words.len()].copy_from_slice(&segment.words);
+        }
+        rom
+    }
+    /// Segments running past the end of memory are refused.
+    fn validate(self) -> io::Result<Image> {
+        if self.segments.iter().any(|segment| segment.address as usize + segment.words.len() > WORDS) {
+            return Err(invalid_data("segment runs past the end of memory"));
+        }
+        Ok(self)
+    }
+    pub fn read(reader: &mut dyn Read, format: Format) -> io::Result<Image> {
+        match format {
+            Format::RawBigEndian => Image::read_raw(reader, u16::from_be_bytes),
+            Format::RawLittleEndian => Image::read_raw(reader, u16::from_le_bytes),
+            Format::IntelHex => Image::read_intel_hex(reader),
+            Format::Hive => Image::read_hive(reader),
+        }
+    }
+    ///
+    /// Raw formats hold only `to_rom`, the entry point and symbols are lost.
+    ///
+    pub fn write(&self, writer: &mut dyn Write, format: Format) -> io::Result<()> {
+        match format {
+            Format::RawBigEndian => writer.write_all(&self.to_rom().iter().flat_map(|word| word.to_be_bytes()).collect::<Vec<u8>>()),
+            Format::RawLittleEndian => writer.write_all(&self.to_rom().iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<u8>>()),
+            Format::IntelHex => self.write_intel_hex(writer),
+            Format::Hive => self.write_hive(writer),
+        }
+    }
+    fn read_raw(reader: &mut dyn Read, word: fn([u8; 2]) -> u16) -> io::Result<Image> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if !bytes.len().is_multiple_of(2) {
+            return Err(invalid_data("raw image has an odd number of bytes"));
+        }
+        Image::from_rom(&bytes.chunks_exact(2).map(|pair| word([pair[0], pair[1]])).collect::<Vec<u16>>())
+    }
+    ///
+    /// Data, end of file, extended segment and linear address and start
+    /// address records are understood. A word with only one of its bytes
+    /// given has the other zeroed.
+    ///
+    fn read_intel_hex(reader: &mut dyn Read) -> io::Result<Image> {
+        let mut words: BTreeMap<u16, u16> = BTreeMap::new();
+        let mut entry = 0;
+        let mut base = 0u32;
+        let mut ended = false;
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if ended {
+                return Err(invalid_data("record after end of file"));
+            }
+            let record = hex_record(line)?;
+            let data = &record[4..record.len() - 1];
+            if data.len() != record[0] as usize {
+                return Err(invalid_data("record length doesn't match its data"));
+            }
+            match record[3] {
+                0x00 => {
+                    let offset = u16::from_be_bytes([record[1], record[2]]) as u32;
+                    for (index, &byte) in data.iter().enumerate() {
+                        let address = base + ((offset + index as u32) & 0xFFFF);
+                        if address as usize >= 2 * WORDS {
+                            return Err(invalid_data("data past the end of memory"));
+                        }
+                        let word = words.entry((address / 2) as u16).or_insert(0);
+                        *word = if address.is_multiple_of(2) { (*word & 0x00FF) | (byte as u16) << 8 } else { (*word & 0xFF00) | byte as u16 };
+                    }
+                }
+                0x01 => ended = true,
+                0x02 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+                0x04 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+                0x03 if data.len() == 4 => {
+                    let (segment, offset) = (u16::from_be_bytes([data[0], data[1]]) as u32, u16::from_be_bytes([data[2], data[3]]) as u32);
+                    entry = (((segment << 4) + offset) / 2) as u16;
+                }
+                0x05 if data.len() == 4 => entry = (u32::from_be_bytes([data[0], data[1], data[2], data[3]]) / 2) as u16,
+                _ => return Err(invalid_data("unsupported record")),
+            }
+        }
+        if !ended {
+            return Err(invalid_data("missing end of file record"));
+        }
+        let mut segments: Vec<Segment> = Vec::new();
+        for (address, word) in words {
+            match segments.last_mut() {
+                Some(segment) if segment.address as usize + segment.words.len() == address as usize => segment.words.push(word),
+                _ => segments.push(Segment { address, words: vec![word] }),
+            }
+        }
+        Ok(Image { entry, segments, symbols: BTreeMap::new() })
+    }
+    /// Symbols aren't representable and are dropped.
+    fn write_intel_hex(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let mut base = 0;
+        for segment in self.segments.iter() {
+            let bytes: Vec<u8> = segment.words.iter().flat_map(|word| word.to_be_bytes()).collect();
+            let mut address = 2 * segment.address as u32;
+            for data in bytes.chunks(HEX_RECORD_BYTES) {
+                // Records may not wrap within a 64KiB bank
+                let split = data.len().min(0x10000 - (address & 0xFFFF) as usize);
+                for data in [&data[..split], &data[split..]] {
+                    if data.is_empty() {
+                        continue;
+                    }
+                    if address >> 16 != base {
+                        base = address >> 16;
+                        write_hex_record(writer, 0x04, 0, &(base as u16).to_be_bytes())?;
+                    }
+                    write_hex_record(writer, 0x00, address as u16, data)?;
+                    address += data.len() as u32;
+                }
+            }
+        }
+        write_hex_record(writer, 0x05, 0, &(2 * self.entry as u32).to_be_bytes())?;
+        write_hex_record(writer, 0x01, 0, &[])
+    }
+    fn read_hive(reader: &mut dyn Read) -> io::Result<Image> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() < 4 || &bytes[..4] != MAGIC {
+            return Err(invalid_data("not a hive image"));
+        }
+        if bytes.len() < 8 {
+            return Err(invalid_data("hive image is truncated"));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 4);
+        if crc32(body) != u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) {
+            return Err(invalid_data("hive image checksum mismatch"));
+        }
+        let mut reader = Cursor::new(&body[4..]);
+        if read_u16(&mut reader)? != VERSION {
+            return Err(invalid_data("unsupported hive image version"));
+        }
+        let entry = read_u16(&mut reader)?;
+        let segment_count = read_u16(&mut reader)?;
+        let symbol_count = read_u16(&mut reader)?;
+        let mut segments = Vec::new();
+        for _ in 0..segment_count {
+            let address = read_u16(&mut reader)?;
+            let length = read_u32(&mut reader)? as usize;
+            if length > WORDS {
+                return Err(invalid_data("segment is larger than memory"));
+            }
+            let mut words = Vec::with_capacity(length);
+            for _ in 0..length {
+                words.push(read_u16(&mut reader)?);
+            }
+            segments.push(Segment { address, words });
+        }
+        let mut symbols = BTreeMap::new();
+        for _ in 0..symbol_count {
+            let name = read_string(&mut reader)?;
+            symbols.insert(name, read_u16(&mut reader)?);
+        }
+        if reader.position() as usize != body.len() - 4 {
+            return Err(invalid_data("trailing data in hive image"));
+        }
+        Image { entry, segments, symbols }.validate()
+    }
+    fn write_hive(&self, writer: &mut dyn Write) -> io::Result<()> {
+        if self.segments.len() > u16::MAX as usize || self.symbols.len() > u16::MAX as usize {
+            return Err(invalid_data("too many segments or symbols"));
+        }
+        let mut body = MAGIC.to_vec();
+        write_u16(&mut body, VERSION)?;
+        write_u16(&mut body, self.entry)?;
+        write_u16(&mut body, self.segments.len() as u16)?;
+        write_u16(&mut body, self.symbols.len() as u16)?;
+        for segment in self.segments.iter() {
+            write_u16(&mut body, segment.address)?;
+            write_u32(&mut body, segment.words.len() as u32)?;
+            for &word in segment.words.iter() {
+                write_u16(&mut body, word)?;
+            }
+        }
+        for (name, &address) in self.symbols.iter() {
+            write_string(&mut body, name)?;
+            write_u16(&mut body, address)?;
+        }
+        let checksum = crc32(&body);
+        writer.write_all(&body)?;
+        write_u32(writer, checksum)
+    }
+}
+
+/// Bytes of a `:`-prefixed record, its checksum verified.
+fn hex_record(line: &str) -> io::Result<Vec<u8>> {
+    let digits = line.strip_prefix(':').ok_or_else(|| invalid_data("record doesn't start with ':'"))?;
+    if !digits.len().is_multiple_of(2) || digits.len() < 10 {
+        return Err(invalid_data("truncated record"));
+    }
+    // Pairs are sliced by byte, which only lines up with characters in ASCII
+    if !digits.is_ascii() {
+        return Err(invalid_data("record isn't hexadecimal"));
+    }
+    let record = (0..digits.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&digits[index..index + 2], 16).map_err(|_| invalid_data("record isn't hexadecimal")))
+        .collect::<io::Result<Vec<u8>>>()?;
+    if record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+        return Err(invalid_data("record checksum mismatch"));
+    }
+    Ok(record)
+}
+
+fn write_hex_record(writer: &mut dyn Write, kind: u8, address: u16, data: &[u8]) -> io::Result<()> {
+    let mut record = vec![data.len() as u8];
+    record.extend_from_slice(&address.to_be_bytes());
+    record.push(kind);
+    record.extend_from_slice(data);
+    let sum = record.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    record.push(sum.wrapping_neg());
+    write!(writer, ":")?;
+    for byte in record {
+        write!(writer, "{:02X}", byte)?;
+    }
+    writeln!(writer)
+}
+
+/// CRC-32 as used by zip and PNG
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, Format, Image, Segment};
+    use std::io::Cursor;
+    use vcpu::cpu::VCPU16;
+
+    fn sample() -> Image {
+        let mut image = Image::new();
+        image.entry = 0x0010;
+        image.segments.push(Segment { address: 0x0010, words: vec![0x7C01, 0x1234, 0x8781] });
+        image.segments.push(Segment { address: 0x7FF0, words: (0..40).collect() });
+        image.symbols.insert("start".to_string(), 0x0010);
+        image.symbols.insert("table".to_string(), 0x7FF0);
+        image
+    }
+
+    fn round_trip(image: &Image, format: Format) -> Image {
+        let mut bytes = Vec::new();
+        image.write(&mut bytes, format).unwrap();
+        Image::read(&mut Cursor::new(bytes), format).unwrap()
+    }
+
+    #[test]
+    pub fn test_formats() {
+        let image = sample();
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(round_trip(&image, Format::Hive), image);
+
+        // Intel HEX keeps the segments and entry point, crossing into the second 64KiB bank
+        let hex = round_trip(&image, Format::IntelHex);
+        assert_eq!((hex.entry, &hex.segments), (image.entry, &image.segments));
+        assert!(hex.symbols.is_empty());
+
+        let mut bytes = Vec::new();
+        Image::from_rom(&[0x7C01, 0x0400]).unwrap().write(&mut bytes, Format::IntelHex).unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(), ":040000007C0104007B\n:0400000500000000F7\n:00000001FF\n");
+
+        // Raw dumps hold memory from address 0
+        for &format in [Format::RawBigEndian, Format::RawLittleEndian].iter() {
+            assert_eq!(round_trip(&image, format).to_rom(), image.to_rom());
+        }
+        let big = Image::read(&mut Cursor::new(vec![0x7C, 0x01, 0x04, 0x00]), Format::RawBigEndian).unwrap();
+        let little = Image::read(&mut Cursor::new(vec![0x01, 0x7C, 0x00, 0x04]), Format::RawLittleEndian).unwrap();
+        assert_eq!((big.to_rom(), little.to_rom()), (vec![0x7C01, 0x0400], vec![0x7C01, 0x0400]));
+
+        let mut vcpu = VCPU16::new();
+        vcpu.load_image(&image).unwrap();
+        assert_eq!((vcpu.get_pc(), vcpu.get_memory(0x0011), vcpu.get_memory(0x8017)), (0x0010, 0x1234, 39));
+    }
+
+    #[test]
+    pub fn test_corrupt_images() {
+        let mut bytes = Vec::new();
+        sample().write(&mut bytes, Format::Hive).unwrap();
+        bytes[12] ^= 1;
+        assert!(Image::read(&mut Cursor::new(bytes), Format::Hive).is_err());
+        assert!(Image::read(&mut Cursor::new(b"HIVE\x01\x00".to_vec()), Format::Hive).is_err());
+        assert!(Image::read(&mut Cursor::new(":0600200\u{3C9}0010002000\n".as_bytes()), Format::IntelHex).is_err());
+
+        for hex in [":0400000001020300F6\n", ":040000007C010400\n:00000001FF\n", ":040000007C010400FF\n:00000001FF\n"].iter() {
+            assert!(Image::read(&mut Cursor::new(hex.as_bytes()), Format::IntelHex).is_err());
+        }
+        assert!(Image::read(&mut Cursor::new(vec![1, 2, 3]), Format::RawBigEndian).is_err());
+        assert!(Image::read(&mut Cursor::new(vec![0; 2 * 65536 + 2]), Format::RawLittleEndian).is_err());
+    }
+}
@@ -0,0 +1,382 @@
+///
+/// Objects and Linker
+///
+/// An Object is assembled source whose words haven't been given addresses
+/// yet: named sections, the labels in them, and relocations marking the words
+/// which hold a section's or another object's address. The linker lays the
+/// sections of every object out one after another, sections of the same name
+/// together in the order they first appear, then patches each relocation
+/// with the address it now refers to. This lets a library of hive routines
+/// be assembled once and shipped apart from the firmware using it.
+///
+/// An object file is little-endian like all persisted data:
+///
+/// ---+-------+--------------------------------------------------------------
+///  # | SIZE  | DESCRIPTION
+/// ---+-------+--------------------------------------------------------------
+///  1 | 4     | Magic "HOBJ"
+///  2 | 2     | Format Version
+///  3 | ...   | Object Name string
+///  4 | ...   | Sections: count u16, then (name string, length u32,
+///    |       | words u16 * length, line count u32, (offset u16, line u32)*)
+///  5 | ...   | Symbols: count u16, then (name string, section u16,
+///    |       | offset u16, global u8)
+///  6 | ...   | Relocations: count u32, then (section u16, offset u16,
+///    |       | negative u8, kind u8, section u16 for kind 0 or name string
+///    |       | for kind 1)
+///  7 | ...   | Entry: present u8, then (section u16, offset u16)
+/// ---+-------+--------------------------------------------------------------
+///
+use codec::{invalid_data, read_string, read_u16, read_u32, read_u8, write_string, write_u16, write_u32, write_u8};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use vcpu::asm::{Assembly, SourceMap};
+use vcpu::image::{Image, Segment};
+
+const MAGIC: &[u8; 4] = b"HOBJ";
+const VERSION: u16 = 1;
+
+///
+/// Words placed together, at an address chosen by the linker
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Section {
+    pub name: String,
+    pub words: Vec<u16>,
+    /// Source line of the instruction or data starting at each offset
+    pub lines: BTreeMap<u16, usize>,
+}
+
+///
+/// Label of an Object
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ObjectSymbol {
+    pub section: usize,
+    pub offset: u16,
+    /// Visible to other objects
+    pub global: bool,
+}
+
+///
+/// Address a relocated word refers to
+///
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Target {
+    /// Start of a section of the same object
+    Section(usize),
+    /// Global symbol of another object
+    Symbol(String),
+}
+
+///
+/// Word whose value the linker adds, or subtracts, a Target's address to
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Relocation {
+    pub section: usize,
+    pub offset: u16,
+    pub target: Target,
+    pub negative: bool,
+}
+
+///
+/// Relocatable assembled module
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Object {
+    pub name: String,
+    pub sections: Vec<Section>,
+    pub symbols: BTreeMap<String, ObjectSymbol>,
+    pub relocations: Vec<Relocation>,
+    /// Section and offset the program starts at
+    pub entry: Option<(usize, u16)>,
+}
+
+///
+/// Linker Error
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LinkError {
+    /// An object refers to a symbol no object exports
+    Undefined { symbol: String, object: String },
+    /// Two objects export the same symbol
+    Duplicate(String),
+    /// The linked program would need this many words from its base
+    TooLarge(usize),
+    /// More than one object declares an entry point
+    MultipleEntries,
+    /// The linked program runs into memory from this address, which is
+    /// already taken
+    Overlap(u16),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LinkError::Undefined { ref symbol, ref object } => write!(f, "{} refers to undefined symbol {}", object, symbol),
+            LinkError::Duplicate(ref symbol) => write!(f, "symbol {} is exported twice", symbol),
+            LinkError::TooLarge(words) => write!(f, "program of {} words is larger than memory", words),
+            LinkError::MultipleEntries => write!(f, "more than one object has an entry point"),
+            LinkError::Overlap(address) => write!(f, "program runs into memory taken from 0x{:04X}", address),
+        }
+    }
+}
+
+impl Object {
+    pub fn new(name: &str) -> Object {
+        Object { name: name.to_string(), sections: Vec::new(), symbols: BTreeMap::new(), relocations: Vec::new(), entry: None }
+    }
+    pub fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        write_u16(writer, VERSION)?;
+        write_string(writer, &self.name)?;
+        write_u16(writer, self.sections.len() as u16)?;
+        for section in self.sections.iter() {
+            write_string(writer, &section.name)?;
+            write_u32(writer, section.words.len() as u32)?;
+            for &word in section.words.iter() {
+                write_u16(writer, word)?;
+            }
+            write_u32(writer, section.lines.len() as u32)?;
+            for (&offset, &line) in section.lines.iter() {
+                write_u16(writer, offset)?;
+                write_u32(writer, line as u32)?;
+            }
+        }
+        write_u16(writer, self.symbols.len() as u16)?;
+        for (name, symbol) in self.symbols.iter() {
+            write_string(writer, name)?;
+            write_u16(writer, symbol.section as u16)?;
+            write_u16(writer, symbol.offset)?;
+            write_u8(writer, symbol.global as u8)?;
+        }
+        write_u32(writer, self.relocations.len() as u32)?;
+        for relocation in self.relocations.iter() {
+            write_u16(writer, relocation.section as u16)?;
+            write_u16(writer, relocation.offset)?;
+            write_u8(writer, relocation.negative as u8)?;
+            match relocation.target {
+                Target::Section(section) => {
+                    write_u8(writer, 0)?;
+                    write_u16(writer, section as u16)?;
+                }
+                Target::Symbol(ref name) => {
+                    write_u8(writer, 1)?;
+                    write_string(writer, name)?;
+                }
+            }
+        }
+        match self.entry {
+            Some((section, offset)) => {
+                write_u8(writer, 1)?;
+                write_u16(writer, section as u16)?;
+                write_u16(writer, offset)
+            }
+            None => write_u8(writer, 0),
+        }
+    }
+    pub fn read(reader: &mut dyn Read) -> io::Result<Object> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not an object file"));
+        }
+        if read_u16(reader)? != VERSION {
+            return Err(invalid_data("unsupported object file version"));
+        }
+        let mut object = Object::new(&read_string(reader)?);
+        for _ in 0..read_u16(reader)? {
+            let name = read_string(reader)?;
+            let length = read_u32(reader)? as usize;
+            if length > 65536 {
+                return Err(invalid_data("section is larger than memory"));
+            }
+            let mut words = Vec::with_capacity(length);
+            for _ in 0..length {
+                words.push(read_u16(reader)?);
+            }
+            let mut lines = BTreeMap::new();
+            for _ in 0..read_u32(reader)? {
+                let offset = read_u16(reader)?;
+                lines.insert(offset, read_u32(reader)? as usize);
+            }
+            object.sections.push(Section { name, words, lines });
+        }
+        let sections = object.sections.len();
+        let section = |index: u16| if (index as usize) < sections { Ok(index as usize) } else { Err(invalid_data("no such section")) };
+        for _ in 0..read_u16(reader)? {
+            let name = read_string(reader)?;
+            let symbol = ObjectSymbol { section: section(read_u16(reader)?)?, offset: read_u16(reader)?, global: read_u8(reader)? != 0 };
+            object.symbols.insert(name, symbol);
+        }
+        for _ in 0..read_u32(reader)? {
+            let (index, offset, negative) = (section(read_u16(reader)?)?, read_u16(reader)?, read_u8(reader)? != 0);
+            let target = match read_u8(reader)? {
+                0 => Target::Section(section(read_u16(reader)?)?),
+                1 => Target::Symbol(read_string(reader)?),
+                _ => return Err(invalid_data("unknown relocation kind")),
+            };
+            if offset as usize >= object.sections[index].words.len() {
+                return Err(invalid_data("relocation is outside its section"));
+            }
+            object.relocations.push(Relocation { section: index, offset, target, negative });
+        }
+        if read_u8(reader)? != 0 {
+            object.entry = Some((section(read_u16(reader)?)?, read_u16(reader)?));
+        }
+        Ok(object)
+    }
+}
+
+///
+/// Link `objects` into a program loaded at `base`. The program starts at
+/// the entry point of the one object declaring it, or else at `base`. Its
+/// SourceMap holds the global symbols by name and the rest as
+/// `object.label`.
+///
+pub fn link(objects: &[Object], base: u16) -> Result<Assembly, LinkError> { link_with(objects, base, &BTreeMap::new()) }
+
+///
+/// Link `objects` as `link` does, against symbols already loaded at fixed
+/// addresses such as a library ROM's. They are in the program's SourceMap
+/// but the words they refer to aren't part of it.
+///
+pub fn link_with(objects: &[Object], base: u16, loaded: &BTreeMap<String, u16>) -> Result<Assembly, LinkError> {
+    // Lay out sections of the same name together, in order of first appearance
+    let mut names: Vec<&str> = Vec::new();
+    for section in objects.iter().flat_map(|object| object.sections.iter()) {
+        if !names.contains(&section.name.as_str()) {
+            names.push(&section.name);
+        }
+    }
+    let mut bases: Vec<Vec<usize>> = objects.iter().map(|object| vec![0; object.sections.len()]).collect();
+    let mut address = base as usize;
+    for name in names {
+        for (index, object) in objects.iter().enumerate() {
+            for (section, placed) in object.sections.iter().enumerate().filter(|(_, section)| section.name == name) {
+                bases[index][section] = address;
+                address += placed.words.len();
+            }
+        }
+    }
+    if address > 65536 {
+        return Err(LinkError::TooLarge(address));
+    }
+
+    let mut map = SourceMap::new();
+    let mut globals: BTreeMap<&str, u16> = loaded.iter().map(|(name, &address)| (name.as_str(), address)).collect();
+    map.symbols.extend(loaded.iter().map(|(name, &address)| (name.clone(), address)));
+    for (index, object) in objects.iter().enumerate() {
+        for (name, symbol) in object.symbols.iter() {
+            let address = (bases[index][symbol.section] + symbol.offset as usize) as u16;
+            if symbol.global {
+                if globals.insert(name, address).is_some() {
+                    return Err(LinkError::Duplicate(name.clone()));
+                }
+                map.symbols.insert(name.clone(), address);
+            } else {
+                map.symbols.insert(format!("{}.{}", object.name, name), address);
+            }
+        }
+    }
+
+    let mut words = vec![0; address - base as usize];
+    for (index, object) in objects.iter().enumerate() {
+        for (section, placed) in object.sections.iter().enumerate() {
+            let start = bases[index][section] - base as usize;
+            words[start..start + placed.words.len()].copy_from_slice(&placed.words);
+            for (&offset, &line) in placed.lines.iter() {
+                map.lines.insert((bases[index][section] + offset as usize) as u16, line);
+            }
+        }
+        for relocation in object.relocations.iter() {
+            let target = match relocation.target {
+                Target::Section(section) => bases[index][section] as u16,
+                Target::Symbol(ref symbol) => *globals
+                    .get(symbol.as_str())
+                    .ok_or_else(|| LinkError::Undefined { symbol: symbol.clone(), object: object.name.clone() })?,
+            };
+            let word = &mut words[bases[index][relocation.section] - base as usize + relocation.offset as usize];
+            *word = if relocation.negative { word.wrapping_sub(target) } else { word.wrapping_add(target) };
+        }
+    }
+
+    let mut entries = objects.iter().enumerate().filter_map(|(index, object)| object.entry.map(|(section, offset)| (bases[index][section] + offset as usize) as u16));
+    let entry = entries.next().unwrap_or(base);
+    if entries.next().is_some() {
+        return Err(LinkError::MultipleEntries);
+    }
+    let mut image = Image::new();
+    image.entry = entry;
+    if !words.is_empty() {
+        image.segments.push(Segment { address: base, words });
+    }
+    image.symbols = map.symbols.clone();
+    Ok(Assembly { image, map })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{link, LinkError, Object};
+    use std::io::Cursor;
+    use vcpu::asm::{assemble_object, AsmError};
+    use vcpu::cpu::VCPU16;
+
+    const MAIN: &str = "
+        .extern fill, buffer
+        .entry start
+        start:  SET A, buffer
+                SET B, 3
+                SET C, 7
+                JSR fill
+                HIB
+    ";
+
+    const LIBRARY: &str = "
+        .global fill, buffer
+        ; Set the B words from A to C
+        fill:   IFE B, 0
+                    SET PC, POP
+                SET [A], C
+                ADD A, 1
+                SUB B, 1
+                SET PC, fill
+        .section .data
+        buffer: DAT 0, 0, 0, 0
+        length: DAT length - buffer
+    ";
+
+    #[test]
+    pub fn test_link() {
+        let main = assemble_object("main", MAIN).unwrap();
+        let library = assemble_object("library", LIBRARY).unwrap();
+        assert_eq!(library.sections.iter().map(|section| section.name.as_str()).collect::<Vec<_>>(), vec![".text", ".data"]);
+
+        // Objects survive being written out
+        let mut bytes = Vec::new();
+        library.write(&mut bytes).unwrap();
+        let library = Object::read(&mut Cursor::new(bytes)).unwrap();
+
+        let program = link(&[main.clone(), library.clone()], 0x0100).unwrap();
+        let (fill, buffer) = (program.map.symbol("fill").unwrap(), program.map.symbol("buffer").unwrap());
+        // main's 7 words, then fill's 7, then the data
+        assert_eq!((program.image.entry, fill, buffer), (0x0100, 0x0107, 0x010E));
+        assert_eq!(program.map.symbol("library.length"), Some(0x0112));
+        assert_eq!(program.map.line(fill), Some(4));
+
+        let mut vcpu = VCPU16::new();
+        vcpu.load_image(&program.image).unwrap();
+        vcpu.set_sp(0x0100);
+        vcpu.run(200);
+        assert!(vcpu.is_hibernating());
+        let memory: Vec<u16> = (buffer..buffer + 5).map(|address| vcpu.get_memory(address)).collect();
+        assert_eq!(memory, vec![7, 7, 7, 0, 4]);
+
+        assert_eq!(link(&[main.clone(), library.clone(), library], 0).unwrap_err(), LinkError::Duplicate("buffer".to_string()));
+        assert_eq!(link(&[main], 0).unwrap_err(), LinkError::Undefined { symbol: "buffer".to_string(), object: "main".to_string() });
+        assert_eq!(assemble_object("bad", ".org 0x100").unwrap_err(), AsmError::new(1, ".org isn't allowed in objects"));
+        assert_eq!(assemble_object("bad", "\n.global missing").unwrap_err(), AsmError::new(2, "global missing is never defined"));
+    }
+}
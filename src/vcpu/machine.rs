@@ -0,0 +1,306 @@
+///
+/// Machine Templates
+///
+/// Describes the hardware of a kind of drone as content: its clock, its
+/// firmware and the devices on its bus, so new hardware needs no code. A
+/// template is written in a small subset of TOML, with a `[[device]]` table
+/// for each device from the cluster's DeviceRegistry in bus order:
+///
+/// ```text
+/// # Scout drone
+/// name = "scout"
+/// clock = 150
+/// decode_cache = true
+/// firmware = "scout.hive"
+///
+/// [[device]]
+/// type = "speaker"
+///
+/// [[device]]
+/// type = "serial"
+/// ```
+///
+/// The WorldInterface is always device 0 of an embedded CPU, so the devices
+/// listed are numbered from 1. Firmware is found relative to the template,
+/// and read as a `.hive`, `.hex` or raw big-endian image by its extension.
+///
+use error::HivemindError;
+use model::entity::{EntityID, EntityManager};
+use std::fmt;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use vcpu::cluster::{CpuComponent, CpuId, HiveCluster, DEFAULT_CLOCK};
+use vcpu::image::{Format, Image};
+
+///
+/// Template parse failure
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MachineError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl MachineError {
+    fn new(line: usize, message: &str) -> MachineError { MachineError { line, message: message.to_string() } }
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "line {}: {}", self.line, self.message) }
+}
+
+///
+/// Hardware of one kind of machine
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MachineTemplate {
+    pub name: String,
+    /// Cycles run per tick
+    pub clock: u32,
+    pub decode_cache: bool,
+    /// Image loaded at start, relative to the template until loaded from a file
+    pub firmware: Option<PathBuf>,
+    /// DeviceRegistry names, in bus order after the WorldInterface
+    pub devices: Vec<String>,
+}
+
+/// Value on the right of `=`, also read by scenario files
+pub enum Value {
+    Text(String),
+    Integer(u64),
+    Float(f64),
+    Boolean(bool),
+}
+
+impl MachineTemplate {
+    pub fn new(name: &str) -> MachineTemplate {
+        MachineTemplate { name: name.to_string(), clock: DEFAULT_CLOCK, decode_cache: false, firmware: None, devices: Vec::new() }
+    }
+    pub fn parse(text: &str) -> Result<MachineTemplate, MachineError> {
+        let mut template = MachineTemplate::new("");
+        let mut named = false;
+        // Line of the device table being read, its type not yet given
+        let mut device: Option<usize> = None;
+        for (index, line) in text.lines().enumerate() {
+            let number = index + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                if line != "[[device]]" {
+                    return Err(MachineError::new(number, &format!("unknown table {}", line)));
+                }
+                if let Some(start) = device {
+                    return Err(MachineError::new(start, "device has no type"));
+                }
+                device = Some(number);
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(split) => (line[..split].trim(), parse_value(line[split + 1..].trim()).ok_or_else(|| MachineError::new(number, "expected a string, integer or boolean"))?),
+                None => return Err(MachineError::new(number, "expected key = value")),
+            };
+            match (device.is_some(), key, value) {
+                (true, "type", Value::Text(name)) => {
+                    template.devices.push(name);
+                    device = None;
+                }
+                (false, "name", Value::Text(name)) => {
+                    template.name = name;
+                    named = true;
+                }
+                (false, "clock", Value::Integer(clock)) if clock <= u32::MAX as u64 => template.clock = clock as u32,
+                (false, "decode_cache", Value::Boolean(enabled)) => template.decode_cache = enabled,
+                (false, "firmware", Value::Text(path)) => template.firmware = Some(PathBuf::from(path)),
+                (_, "type", _) | (false, "name", _) | (false, "clock", _) | (false, "decode_cache", _) | (false, "firmware", _) => {
+                    return Err(MachineError::new(number, &format!("bad value for {}", key)));
+                }
+                _ => return Err(MachineError::new(number, &format!("unknown key {}", key))),
+            }
+        }
+        if let Some(start) = device {
+            return Err(MachineError::new(start, "device has no type"));
+        }
+        if !named {
+            return Err(MachineError::new(0, "machine has no name"));
+        }
+        Ok(template)
+    }
+    /// The template as text `parse` reads back.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("name = {}\nclock = {}\ndecode_cache = {}\n", quote(&self.name), self.clock, self.decode_cache);
+        if let Some(ref firmware) = self.firmware {
+            text.push_str(&format!("firmware = {}\n", quote(&firmware.to_string_lossy())));
+        }
+        for device in self.devices.iter() {
+            text.push_str(&format!("\n[[device]]\ntype = {}\n", quote(device)));
+        }
+        text
+    }
+    /// Read a template file, its firmware path made relative to the working directory.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<MachineTemplate, HivemindError> {
+        let path = path.as_ref();
+        let mut template = MachineTemplate::parse(&fs::read_to_string(path)?)?;
+        if let (Some(firmware), Some(directory)) = (template.firmware.take(), path.parent()) {
+            template.firmware = Some(directory.join(firmware));
+        }
+        Ok(template)
+    }
+    /// Read the firmware image, None if the template has none.
+    pub fn read_firmware(&self) -> Result<Option<Image>, HivemindError> {
+        let path = match self.firmware {
+            Some(ref path) => path,
+            None => return Ok(None),
+        };
+        let format = match path.extension().and_then(|extension| extension.to_str()).map(str::to_lowercase).as_deref() {
+            Some("hive") => Format::Hive,
+            Some("hex") => Format::IntelHex,
+            _ => Format::RawBigEndian,
+        };
+        Ok(Some(Image::read(&mut Cursor::new(fs::read(path)?), format)?))
+    }
+    ///
+    /// Build the machine in `entity`: a CPU running `image` from its entry
+    /// point at the template's clock, with its devices installed. Nothing is
+    /// built if a device isn't in the cluster's registry.
+    ///
+    pub fn instantiate(&self, cluster: &mut HiveCluster, entities: &mut EntityManager, entity: EntityID, image: &Image) -> Result<CpuId, HivemindError> {
+        if let Some(missing) = self.devices.iter().find(|name| !cluster.device_registry().contains(name)) {
+            return Err(HivemindError::UnknownDevice(missing.clone()));
+        }
+        let cpu = cluster.attach(entities, entity, &image.to_rom())?;
+        let state = cluster.get_mut(cpu).unwrap();
+        state.set_pc(image.entry);
+        state.set_decode_cache(self.decode_cache);
+        if let Some(component) = entities.get_component_mut::<CpuComponent>(entity) {
+            component.clock = self.clock;
+        }
+        for name in self.devices.iter() {
+            cluster.install(cpu, name);
+        }
+        Ok(cpu)
+    }
+}
+
+/// Line up to any `#` outside a string
+pub fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, character) in line.char_indices() {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+pub fn parse_value(text: &str) -> Option<Value> {
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        let mut value = String::new();
+        let mut characters = text[1..text.len() - 1].chars();
+        while let Some(character) = characters.next() {
+            value.push(match character {
+                '\\' => match characters.next()? {
+                    'n' => '\n',
+                    't' => '\t',
+                    other @ ('"' | '\\') => other,
+                    _ => return None,
+                },
+                '"' => return None,
+                other => other,
+            });
+        }
+        return Some(Value::Text(value));
+    }
+    match text {
+        "true" => return Some(Value::Boolean(true)),
+        "false" => return Some(Value::Boolean(false)),
+        _ => {}
+    }
+    let digits = text.replace('_', "");
+    match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok().map(Value::Integer),
+        None => digits.parse().ok().map(Value::Integer).or_else(|| digits.parse().ok().filter(|value: &f64| value.is_finite()).map(Value::Float)),
+    }
+}
+
+pub fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for character in text.chars() {
+        match character {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            other => quoted.push(other),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MachineError, MachineTemplate};
+    use devices::speaker::{SoundBuffer, DEVICE_ID};
+    use devices::{Device, Speaker};
+    use error::HivemindError;
+    use model::entity::EntityManager;
+    use std::path::PathBuf;
+    use vcpu::cluster::{CpuComponent, HiveCluster};
+    use vcpu::image::Image;
+
+    const SCOUT: &str = "
+        # Scout drone
+        name = \"scout # 2\"   # comments end lines
+        clock = 0x96
+        decode_cache = true
+        firmware = \"scout.hive\"
+
+        [[device]]
+        type = \"speaker\"
+    ";
+
+    #[test]
+    pub fn test_machine_template() {
+        let template = MachineTemplate::parse(SCOUT).unwrap();
+        assert_eq!(template, MachineTemplate {
+            name: "scout # 2".to_string(),
+            clock: 150,
+            decode_cache: true,
+            firmware: Some(PathBuf::from("scout.hive")),
+            devices: vec!["speaker".to_string()],
+        });
+        assert_eq!(MachineTemplate::parse(&template.to_text()), Ok(template.clone()));
+
+        let error = |text: &str| MachineTemplate::parse(text).unwrap_err();
+        assert_eq!(error("clock = 5"), MachineError::new(0, "machine has no name"));
+        assert_eq!(error("name = \"a\"\nspeed = 5"), MachineError::new(2, "unknown key speed"));
+        assert_eq!(error("name = 5"), MachineError::new(1, "bad value for name"));
+        assert_eq!(error("name = \"a\"\n[[device]]\n[[device]]\ntype = \"x\""), MachineError::new(2, "device has no type"));
+        assert_eq!(error("[cpu]"), MachineError::new(1, "unknown table [cpu]"));
+
+        // Building it needs every device registered
+        let mut cluster = HiveCluster::new();
+        let mut entities = EntityManager::new();
+        let drone = entities.create_entity();
+        let image = Image { entry: 2, ..Image::from_rom(&[0, 0, 0x0400]).unwrap() };
+        assert!(matches!(template.instantiate(&mut cluster, &mut entities, drone, &image), Err(HivemindError::UnknownDevice(ref name)) if name == "speaker"));
+        assert!(cluster.is_empty());
+        let sounds = SoundBuffer::new();
+        cluster.device_registry_mut().register("speaker", Box::new(move || Box::new(Speaker::buffered(&sounds)) as Box<dyn Device>));
+        let cpu = template.instantiate(&mut cluster, &mut entities, drone, &image).unwrap();
+        assert_eq!(entities.get_component::<CpuComponent>(drone).map(|component| component.clock), Some(150));
+        let state = cluster.get(cpu).unwrap();
+        assert_eq!((state.get_pc(), state.has_decode_cache(), cluster.device_count(cpu)), (2, true, 1));
+        let mut world = ::model::world::World::new();
+        let found = cluster.with_bus(cpu, &mut world, &mut entities, |_, bus| bus.info(1).map(|info| info.id));
+        assert_eq!(found, Some(Some(DEVICE_ID)));
+    }
+}
@@ -0,0 +1,176 @@
+//!
+//! Paged CPU Memory
+//!
+//! A CPU's 64K words are held as pages which may be shared: with the other
+//! CPUs started from the same Firmware, or between the untouched pages of a
+//! fresh CPU. A shared page is copied the first time it is written, so a hive
+//! of drones running one program only pays for the pages each drone changes.
+//! The pages a CPU holds alone can be paged out to a writer and back, the
+//! shared ones staying where they are.
+//!
+use codec::{invalid_data, read_u8, write_u8};
+use error::HivemindError;
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+/// Words per page
+pub const PAGE_WORDS: usize = 1024;
+/// Pages in a full 64K word address space
+pub const PAGES: usize = 65536 / PAGE_WORDS;
+
+type Page = [u16; PAGE_WORDS];
+
+///
+/// Read-only memory image shared between CPUs
+///
+#[derive(Clone)]
+pub struct Firmware {
+    pages: [Arc<Page>; PAGES],
+    length: usize,
+}
+
+impl Firmware {
+    ///
+    /// Image of `rom` loaded at address 0, the rest of memory zeroed.
+    ///
+    pub fn new(rom: &[u16]) -> Result<Firmware, HivemindError> {
+        if rom.len() > PAGES * PAGE_WORDS {
+            return Err(HivemindError::RomTooLarge(rom.len()));
+        }
+        let zero = Arc::new([0; PAGE_WORDS]);
+        let pages = std::array::from_fn(|index| {
+            let words = &rom[(index * PAGE_WORDS).min(rom.len())..((index + 1) * PAGE_WORDS).min(rom.len())];
+            if words.iter().all(|&word| word == 0) {
+                zero.clone()
+            } else {
+                let mut page = [0; PAGE_WORDS];
+                page[..words.len()].copy_from_slice(words);
+                Arc::new(page)
+            }
+        });
+        Ok(Firmware { pages, length: rom.len() })
+    }
+    /// Words in the ROM the image was made from.
+    pub fn len(&self) -> usize { self.length }
+    pub fn is_empty(&self) -> bool { self.length == 0 }
+}
+
+///
+/// 64K words of CPU Memory
+///
+/// A clone shares every page with the original until either writes to it.
+///
+#[derive(Clone)]
+pub struct Memory {
+    pages: [Arc<Page>; PAGES],
+}
+
+impl Memory {
+    /// Zeroed memory, every page sharing one zero page until written.
+    pub fn new() -> Memory {
+        let zero = Arc::new([0; PAGE_WORDS]);
+        Memory { pages: std::array::from_fn(|_| zero.clone()) }
+    }
+    /// Memory sharing every page of `firmware`.
+    pub fn from_firmware(firmware: &Firmware) -> Memory { Memory { pages: firmware.pages.clone() } }
+    #[inline(always)]
+    pub fn get(&self, address: u16) -> u16 { self.pages[address as usize / PAGE_WORDS][address as usize % PAGE_WORDS] }
+    /// Write a word, first copying its page if it is shared.
+    #[inline(always)]
+    pub fn set(&mut self, address: u16, value: u16) {
+        Arc::make_mut(&mut self.pages[address as usize / PAGE_WORDS])[address as usize % PAGE_WORDS] = value
+    }
+    /// Pages shared with a Firmware, another CPU or another page.
+    pub fn shared_pages(&self) -> usize { self.pages.iter().filter(|page| Arc::strong_count(page) > 1).count() }
+    /// Pages held by this memory alone.
+    pub fn private_pages(&self) -> usize { PAGES - self.shared_pages() }
+    ///
+    /// Write every page held by this memory alone to `writer`, then replace
+    /// them with the pages of `blank`. Memory is left untouched if writing
+    /// fails. Returns the number of pages written.
+    ///
+    pub fn page_out(&mut self, blank: &Memory, writer: &mut dyn Write) -> io::Result<usize> {
+        let private: Vec<usize> = (0..PAGES).filter(|&index| Arc::strong_count(&self.pages[index]) == 1).collect();
+        write_u8(writer, private.len() as u8)?;
+        for &index in private.iter() {
+            write_u8(writer, index as u8)?;
+            let bytes: Vec<u8> = self.pages[index].iter().flat_map(|word| word.to_le_bytes()).collect();
+            writer.write_all(&bytes)?;
+        }
+        for &index in private.iter() {
+            self.pages[index] = blank.pages[index].clone();
+        }
+        Ok(private.len())
+    }
+    ///
+    /// Restore the pages written by `page_out`, returning how many were read.
+    /// Memory is left untouched if they can't be read in full.
+    ///
+    pub fn page_in(&mut self, reader: &mut dyn Read) -> io::Result<usize> {
+        let count = read_u8(reader)? as usize;
+        let mut pages = Vec::with_capacity(count);
+        for _ in 0..count {
+            let index = read_u8(reader)? as usize;
+            if index >= PAGES {
+                return Err(invalid_data(&format!("page {} out of range", index)));
+            }
+            let mut bytes = vec![0u8; 2 * PAGE_WORDS];
+            reader.read_exact(&mut bytes)?;
+            let mut page = [0; PAGE_WORDS];
+            for (word, bytes) in page.iter_mut().zip(bytes.chunks_exact(2)) {
+                *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+            }
+            pages.push((index, page));
+        }
+        for (index, page) in pages {
+            self.pages[index] = Arc::new(page);
+        }
+        Ok(count)
+    }
+    /// Every word in address order.
+    pub fn words(&self) -> impl Iterator<Item = u16> + '_ { self.pages.iter().flat_map(|page| page.iter().cloned()) }
+    /// Distinct pages across `memories`, each counted once however often it is shared.
+    pub fn distinct_pages<'a>(memories: impl Iterator<Item = &'a Memory>) -> usize {
+        let mut seen = HashSet::new();
+        for memory in memories {
+            for page in memory.pages.iter() {
+                seen.insert(Arc::as_ptr(page));
+            }
+        }
+        seen.len()
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Memory { Memory::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Firmware, Memory, PAGES, PAGE_WORDS};
+    use error::HivemindError;
+
+    #[test]
+    pub fn test_copy_on_write() {
+        let mut rom = vec![0; 3 * PAGE_WORDS];
+        rom[0] = 0x7C01;
+        rom[2 * PAGE_WORDS + 5] = 0x1234;
+        let firmware = Firmware::new(&rom).unwrap();
+        let mut first = Memory::from_firmware(&firmware);
+        let second = Memory::from_firmware(&firmware);
+        assert_eq!((first.get(0), first.get(2 * PAGE_WORDS as u16 + 5)), (0x7C01, 0x1234));
+        // Two code pages and one zero page for the rest
+        assert_eq!(Memory::distinct_pages([&first, &second].iter().cloned()), 3);
+
+        // Writing copies only the page written, the firmware and other CPU keep theirs
+        first.set(1, 0x4321);
+        first.set(2, 0x5678);
+        assert_eq!((first.get(1), second.get(1)), (0x4321, 0));
+        assert_eq!(first.shared_pages(), PAGES - 1);
+        assert_eq!(Memory::distinct_pages([&first, &second].iter().cloned()), 4);
+        assert_eq!(Memory::from_firmware(&firmware).get(1), 0);
+
+        assert!(matches!(Firmware::new(&vec![0; 65537]), Err(HivemindError::RomTooLarge(65537))));
+    }
+}
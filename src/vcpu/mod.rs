@@ -1 +1,20 @@
-pub mod cpu;
\ No newline at end of file
+pub mod asm;
+pub mod cluster;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod hivec;
+pub mod image;
+pub mod link;
+pub mod machine;
+pub mod memory;
+pub mod profiler;
+#[cfg(feature = "reference")]
+pub mod reference;
+pub mod shared;
+pub mod stdrom;
+pub mod swap;
+#[cfg(test)]
+mod golden;
+#[cfg(test)]
+mod properties;
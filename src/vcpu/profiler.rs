@@ -0,0 +1,257 @@
+///
+/// VCPU16 Profiler
+///
+/// Runs a CPU while attributing its cycles to the instruction they were spent
+/// on, either every cycle or one sample every few cycles, so firmware authors
+/// can find what eats their cycle budget. Calls are followed with a shadow
+/// call stack, pushed by each JSR and popped when PC comes back to the
+/// instruction after it with the return address popped, which gives both a
+/// per function report and folded stacks for flamegraph tools:
+///
+/// ```text
+/// start;fibonacci;fibonacci 1260
+/// ```
+///
+/// Cycles of a stalled, sleeping or hibernating CPU go to the instruction
+/// that put it in that state.
+///
+use devices::Bus;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use vcpu::asm::SourceMap;
+use vcpu::cpu::VCPU16;
+
+/// Deepest call stack followed, deeper calls are attributed to their caller
+pub const MAX_DEPTH: usize = 256;
+
+///
+/// How cycles are attributed
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Mode {
+    /// Every cycle
+    Exact,
+    /// One cycle in this many, counted as that many
+    Sampling(u32),
+}
+
+/// Call followed by the shadow stack
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct Frame {
+    /// Address called
+    target: u16,
+    /// Address the call returns to
+    back: u16,
+    /// SP holding the return address
+    sp: u16,
+}
+
+///
+/// Cycle Profiler
+///
+#[derive(Clone, Debug)]
+pub struct Profiler {
+    mode: Mode,
+    /// Cycles by address of the instruction they were spent on
+    addresses: BTreeMap<u16, u64>,
+    /// Cycles by call targets and address
+    stacks: HashMap<(Vec<u16>, u16), u64>,
+    frames: Vec<Frame>,
+    /// Instruction in flight, and the call it makes
+    current: Option<(u16, Option<u16>)>,
+    countdown: u32,
+    total: u64,
+}
+
+impl Profiler {
+    pub fn new(mode: Mode) -> Profiler {
+        let countdown = match mode {
+            Mode::Exact => 1,
+            Mode::Sampling(period) => period.max(1),
+        };
+        Profiler { mode, addresses: BTreeMap::new(), stacks: HashMap::new(), frames: Vec::new(), current: None, countdown, total: 0 }
+    }
+    pub fn mode(&self) -> Mode { self.mode }
+    /// Cycles attributed so far
+    pub fn total(&self) -> u64 { self.total }
+    /// Cycles attributed to the instruction at `address`.
+    pub fn cycles_at(&self, address: u16) -> u64 { self.addresses.get(&address).cloned().unwrap_or(0) }
+    ///
+    /// Run `cpu` for `cycles` on `bus`, attributing each cycle.
+    ///
+    pub fn run(&mut self, cpu: &mut VCPU16, cycles: u64, bus: &mut dyn Bus) {
+        for _ in 0..cycles {
+            if cpu.is_halted() {
+                return;
+            }
+            if !cpu.is_busy() && !cpu.is_sleeping() && !cpu.is_hibernating() {
+                self.boundary(cpu);
+            }
+            self.countdown -= 1;
+            if self.countdown == 0 {
+                let weight = match self.mode {
+                    Mode::Exact => 1,
+                    Mode::Sampling(period) => period.max(1),
+                };
+                self.countdown = weight;
+                self.attribute(cpu.get_pc(), weight as u64);
+            }
+            cpu.step_with(bus);
+        }
+    }
+    /// CPU is about to start the instruction at PC.
+    fn boundary(&mut self, cpu: &VCPU16) {
+        let pc = cpu.get_pc();
+        // The last instruction was a call if PC went to its target
+        if let Some((_, Some(target))) = self.current {
+            if target == pc && self.frames.len() < MAX_DEPTH {
+                self.frames.push(Frame { target, back: cpu.get_memory(cpu.get_sp()), sp: cpu.get_sp() });
+            }
+        }
+        while self.frames.last().is_some_and(|frame| frame.back == pc && frame.sp.wrapping_add(1) == cpu.get_sp()) {
+            self.frames.pop();
+        }
+        let word = cpu.get_memory(pc);
+        // JSR a, whose target is read before the instruction runs
+        let call = if word & 0x03FF == 0x0020 { target(cpu, word >> 10, pc.wrapping_add(1)) } else { None };
+        self.current = Some((pc, call));
+    }
+    fn attribute(&mut self, pc: u16, weight: u64) {
+        let address = self.current.map_or(pc, |(address, _)| address);
+        *self.addresses.entry(address).or_insert(0) += weight;
+        let targets: Vec<u16> = self.frames.iter().map(|frame| frame.target).collect();
+        *self.stacks.entry((targets, address)).or_insert(0) += weight;
+        self.total += weight;
+    }
+    /// Hottest instructions, most cycles first.
+    pub fn hottest(&self, count: usize) -> Vec<(u16, u64)> {
+        let mut addresses: Vec<(u16, u64)> = self.addresses.iter().map(|(&address, &cycles)| (address, cycles)).collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        addresses.truncate(count);
+        addresses
+    }
+    ///
+    /// Cycles spent in each function, named by the label at or before each
+    /// instruction, most cycles first. Instructions before any label are
+    /// named by address.
+    ///
+    pub fn by_symbol(&self, map: &SourceMap) -> Vec<(String, u64)> {
+        let mut symbols: BTreeMap<String, u64> = BTreeMap::new();
+        for (&address, &cycles) in self.addresses.iter() {
+            *symbols.entry(name(map, address)).or_insert(0) += cycles;
+        }
+        let mut symbols: Vec<(String, u64)> = symbols.into_iter().collect();
+        symbols.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        symbols
+    }
+    ///
+    /// Write the cycles of each call stack in the folded format flamegraph
+    /// tools read: frames named by `map` and joined by `;`, then the cycles.
+    ///
+    pub fn write_folded(&self, writer: &mut dyn Write, map: &SourceMap) -> io::Result<()> {
+        let mut folded: BTreeMap<String, u64> = BTreeMap::new();
+        for (&(ref targets, address), &cycles) in self.stacks.iter() {
+            let mut frames: Vec<String> = targets.iter().map(|&target| name(map, target)).collect();
+            let leaf = name(map, address);
+            if frames.last() != Some(&leaf) {
+                frames.push(leaf);
+            }
+            *folded.entry(frames.join(";")).or_insert(0) += cycles;
+        }
+        for (stack, cycles) in folded.iter() {
+            writeln!(writer, "{} {}", stack, cycles)?;
+        }
+        Ok(())
+    }
+}
+
+/// Label at or before `address`, or the address in hex
+fn name(map: &SourceMap, address: u16) -> String {
+    match map.nearest(address) {
+        Some((label, _)) => label.to_string(),
+        None => format!("0x{:04X}", address),
+    }
+}
+
+/// Address a JSR with operand `a`, its NEXT word at `next`, jumps to
+fn target(cpu: &VCPU16, a: u16, next: u16) -> Option<u16> {
+    let register = |index: u16| match index {
+        0 => cpu.get_a(),
+        1 => cpu.get_b(),
+        2 => cpu.get_c(),
+        3 => cpu.get_x(),
+        4 => cpu.get_y(),
+        5 => cpu.get_z(),
+        6 => cpu.get_i(),
+        _ => cpu.get_j(),
+    };
+    Some(match a {
+        0x00..=0x07 => register(a),
+        0x08..=0x0F => cpu.get_memory(register(a - 0x08)),
+        0x10..=0x17 => cpu.get_memory(register(a - 0x10).wrapping_add(cpu.get_memory(next))),
+        0x18 | 0x19 => cpu.get_memory(cpu.get_sp()),
+        0x1A => cpu.get_memory(cpu.get_sp().wrapping_add(cpu.get_memory(next))),
+        0x1B => cpu.get_sp(),
+        0x1C => next,
+        0x1D => cpu.get_ex(),
+        0x1E => cpu.get_memory(cpu.get_memory(next)),
+        0x1F => cpu.get_memory(next),
+        0x20..=0x3F => a.wrapping_sub(0x21),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mode, Profiler};
+    use devices::NoDevices;
+    use vcpu::asm::assemble;
+    use vcpu::cpu::VCPU16;
+
+    const SOURCE: &str = "
+        start:  SET I, 3
+        again:  JSR busy
+                JSR idle
+                SUB I, 1
+                IFN I, 0
+                    SET PC, again
+                HIB
+        busy:   SET J, 10
+        spin:   SUB J, 1
+                IFN J, 0
+                    SET PC, spin
+                SET PC, POP
+        idle:   SET PC, POP
+    ";
+
+    #[test]
+    pub fn test_profile() {
+        let assembly = assemble(SOURCE).unwrap();
+        let mut vcpu = VCPU16::new();
+        vcpu.load_image(&assembly.image).unwrap();
+        let mut profiler = Profiler::new(Mode::Exact);
+        profiler.run(&mut vcpu, 10_000, &mut NoDevices);
+        assert!(vcpu.is_hibernating());
+
+        // Every cycle is accounted for, the idle ones to the HIB
+        let spin = assembly.map.symbol("spin").unwrap();
+        assert_eq!(profiler.total(), 10_000);
+        assert_eq!(profiler.hottest(1)[0], (assembly.map.symbol("busy").unwrap() - 1, 9805));
+        // SUB and IFN take 2 cycles a time, 10 times round for each of 3 calls
+        assert_eq!((profiler.cycles_at(spin), profiler.cycles_at(spin + 1)), (60, 60));
+        let symbols = profiler.by_symbol(&assembly.map);
+        assert_eq!(&symbols[1..3], &[("spin".to_string(), 150), ("busy".to_string(), 3)]);
+
+        let mut folded = Vec::new();
+        profiler.write_folded(&mut folded, &assembly.map).unwrap();
+        assert_eq!(String::from_utf8(folded).unwrap(), "again 9843\nbusy 3\nbusy;spin 150\nidle 3\nstart 1\n");
+
+        // Sampling sees the same hot spot from far fewer samples
+        let mut vcpu = VCPU16::new();
+        vcpu.load_image(&assembly.image).unwrap();
+        let mut sampler = Profiler::new(Mode::Sampling(7));
+        sampler.run(&mut vcpu, 200, &mut NoDevices);
+        assert_eq!(sampler.total(), 196);
+        assert_eq!(sampler.by_symbol(&assembly.map)[0].0, "spin");
+    }
+}
@@ -0,0 +1,391 @@
+//!
+//! Instruction Set Properties
+//!
+//! Random instruction words and register states, checked against invariants
+//! of the instruction set and a reference model of it written from the
+//! opcode tables alone. Each case draws from its own seeded generator, so a
+//! failure names the case to rerun. Programs are only built from binary
+//! instructions and NOPs, the model stops at anything else.
+//!
+use rand::{Rng, SeedableRng, XorShiftRng};
+use vcpu::cpu::VCPU16;
+
+/// Cases per property
+const CASES: u32 = 256;
+/// Instructions per generated program
+const PROGRAM_LENGTH: usize = 4;
+/// Where programs are loaded
+const PROGRAM: u16 = 0x4000;
+/// Window registers are pointed into, filled with random words
+const DATA: u16 = 0x8000;
+const DATA_WORDS: u16 = 128;
+
+// Model register indices
+const SP: usize = 8;
+const PC: usize = 9;
+const EX: usize = 10;
+
+/// Generator for case `case` of a property
+fn generator(case: u32) -> XorShiftRng { XorShiftRng::from_seed([0x5EED, case, 0x1234_5678, 0x9ABC_DEF0]) }
+
+/// Whether an opcode is one of IFB to IFU
+fn is_conditional(opcode: u16) -> bool { (0x10..=0x17).contains(&opcode) }
+/// Whether an opcode is unused
+fn is_unused(opcode: u16) -> bool { matches!(opcode, 0x18 | 0x19 | 0x1C | 0x1D) }
+
+/// NEXT words taken by an operand
+fn next_words(operand: u16) -> u16 { if (0x10..=0x17).contains(&operand) || matches!(operand, 0x1A | 0x1E | 0x1F) { 1 } else { 0 } }
+
+/// Words taken by the instruction starting with `word`
+fn length(word: u16) -> u16 {
+    if word & 0x03FF == 0 {
+        1
+    } else if word & 0x001F == 0 {
+        1 + next_words(word >> 10)
+    } else {
+        1 + next_words(word >> 10) + next_words((word >> 5) & 0x1F)
+    }
+}
+
+/// Random binary instruction with its NEXT words
+fn instruction(rng: &mut XorShiftRng) -> Vec<u16> {
+    let (opcode, b, a) = (rng.gen_range(0x01, 0x20), rng.gen_range(0x00, 0x20), rng.gen_range(0x00, 0x40));
+    let word = a << 10 | b << 5 | opcode;
+    let mut words = vec![word];
+    if !is_unused(opcode) {
+        words.extend((1..length(word)).map(|_| rng.gen::<u16>()));
+    }
+    words
+}
+
+/// Register value, half the time pointing into the data window
+fn register(rng: &mut XorShiftRng) -> u16 { if rng.gen() { rng.gen() } else { DATA + rng.gen_range(0, DATA_WORDS) } }
+
+///
+/// Registers in model order, A to J then SP, PC and EX, with memory set up
+/// around them and a program at PROGRAM.
+///
+fn setup(rng: &mut XorShiftRng, program: &[u16]) -> ([u16; 11], Vec<u16>) {
+    let mut registers = [0u16; 11];
+    for value in registers.iter_mut().take(8) {
+        *value = register(rng);
+    }
+    registers[SP] = DATA + rng.gen_range(16, DATA_WORDS - 16);
+    registers[PC] = PROGRAM;
+    registers[EX] = if rng.gen() { rng.gen() } else { 0 };
+    let mut memory = vec![0u16; 65536];
+    for address in DATA..DATA + DATA_WORDS {
+        memory[address as usize] = rng.gen();
+    }
+    for (offset, &word) in program.iter().enumerate() {
+        memory[PROGRAM as usize + offset] = word;
+    }
+    (registers, memory)
+}
+
+fn load(registers: &[u16; 11], memory: &[u16], cache: bool) -> VCPU16 {
+    let mut cpu = VCPU16::new();
+    cpu.set_decode_cache(cache);
+    for (address, &word) in memory.iter().enumerate() {
+        if word != 0 {
+            cpu.set_memory(address as u16, word);
+        }
+    }
+    cpu.set_a(registers[0]);
+    cpu.set_b(registers[1]);
+    cpu.set_c(registers[2]);
+    cpu.set_x(registers[3]);
+    cpu.set_y(registers[4]);
+    cpu.set_z(registers[5]);
+    cpu.set_i(registers[6]);
+    cpu.set_j(registers[7]);
+    cpu.set_sp(registers[SP]);
+    cpu.set_pc(registers[PC]);
+    cpu.set_ex(registers[EX]);
+    cpu
+}
+
+/// Registers of `cpu` in model order
+fn registers(cpu: &VCPU16) -> [u16; 11] {
+    [
+        cpu.get_a(), cpu.get_b(), cpu.get_c(), cpu.get_x(), cpu.get_y(), cpu.get_z(), cpu.get_i(), cpu.get_j(),
+        cpu.get_sp(), cpu.get_pc(), cpu.get_ex(),
+    ]
+}
+
+/// Run one instruction to completion
+fn execute(cpu: &mut VCPU16) {
+    cpu.step();
+    while cpu.is_busy() {
+        cpu.step();
+    }
+}
+
+/// Where an operand reads and writes
+#[derive(Copy, Clone)]
+enum Location {
+    Register(usize),
+    Memory(u16),
+    Literal(u16),
+}
+
+///
+/// Reference model of the binary instructions
+///
+struct Model {
+    registers: [u16; 11],
+    memory: Vec<u16>,
+    halted: bool,
+}
+
+impl Model {
+    fn fetch(&mut self) -> u16 {
+        let word = self.memory[self.registers[PC] as usize];
+        self.registers[PC] = self.registers[PC].wrapping_add(1);
+        word
+    }
+    /// Location of an operand, reading its NEXT word and moving SP as it goes
+    fn locate(&mut self, operand: u16, is_a: bool) -> Location {
+        match operand {
+            0x00..=0x07 => Location::Register(operand as usize),
+            0x08..=0x0F => Location::Memory(self.registers[operand as usize - 0x08]),
+            0x10..=0x17 => {
+                let offset = self.fetch();
+                Location::Memory(self.registers[operand as usize - 0x10].wrapping_add(offset))
+            }
+            0x18 if is_a => {
+                self.registers[SP] = self.registers[SP].wrapping_add(1);
+                Location::Memory(self.registers[SP].wrapping_sub(1))
+            }
+            0x18 => {
+                self.registers[SP] = self.registers[SP].wrapping_sub(1);
+                Location::Memory(self.registers[SP])
+            }
+            0x19 => Location::Memory(self.registers[SP]),
+            0x1A => {
+                let offset = self.fetch();
+                Location::Memory(self.registers[SP].wrapping_add(offset))
+            }
+            0x1B => Location::Register(SP),
+            0x1C => Location::Register(PC),
+            0x1D => Location::Register(EX),
+            0x1E => Location::Memory(self.fetch()),
+            0x1F => Location::Literal(self.fetch()),
+            _ => Location::Literal((operand as i16 - 0x21) as u16),
+        }
+    }
+    fn read(&self, location: Location) -> u16 {
+        match location {
+            Location::Register(index) => self.registers[index],
+            Location::Memory(address) => self.memory[address as usize],
+            Location::Literal(value) => value,
+        }
+    }
+    fn write(&mut self, location: Location, value: u16) {
+        match location {
+            Location::Register(index) => self.registers[index] = value,
+            Location::Memory(address) => self.memory[address as usize] = value,
+            Location::Literal(_) => {}
+        }
+    }
+    /// Skip the next instruction and the chain of conditionals it starts
+    fn skip(&mut self) {
+        loop {
+            let word = self.memory[self.registers[PC] as usize];
+            self.registers[PC] = self.registers[PC].wrapping_add(length(word));
+            if word & 0x03FF == 0 || !is_conditional(word & 0x1F) {
+                break;
+            }
+        }
+    }
+    /// Run one instruction, false if it isn't modelled
+    fn step(&mut self) -> bool {
+        let word = self.memory[self.registers[PC] as usize];
+        let opcode = word & 0x1F;
+        if word != 0 && opcode == 0 {
+            return false;
+        }
+        self.fetch();
+        if word == 0 {
+            return true;
+        }
+        if is_unused(opcode) {
+            self.halted = true;
+            return true;
+        }
+        // a is located and read before b
+        let a_location = self.locate(word >> 10, true);
+        let a = self.read(a_location);
+        let b_location = self.locate((word >> 5) & 0x1F, false);
+        let b = self.read(b_location);
+        let ex = self.registers[EX];
+        let (signed_b, signed_a) = (b as i16 as i32, a as i16 as i32);
+        let result = match opcode {
+            0x01 => Some((a, None)),
+            0x02 => Some((b.wrapping_add(a), Some((b as u32 + a as u32 >= 0x10000) as u16))),
+            0x03 => Some((b.wrapping_sub(a), Some(if b < a { 0xFFFF } else { 0 }))),
+            0x04 => Some((b.wrapping_mul(a), Some(((b as u32 * a as u32) >> 16) as u16))),
+            0x05 => Some(((signed_b * signed_a) as u16, Some(((signed_b * signed_a) >> 16) as u16))),
+            0x06 if a == 0 => Some((0, Some(0))),
+            0x06 => Some((b / a, Some((((b as u32) << 16) / a as u32) as u16))),
+            0x07 if a == 0 => Some((0, Some(0))),
+            0x07 => Some(((signed_b / signed_a) as u16, Some(((signed_b << 16).wrapping_div(signed_a)) as u16))),
+            0x08 => Some((if a == 0 { 0 } else { b % a }, None)),
+            0x09 => Some((if a == 0 { 0 } else { (signed_b % signed_a) as u16 }, None)),
+            0x0A => Some((b & a, None)),
+            0x0B => Some((b | a, None)),
+            0x0C => Some((b ^ a, None)),
+            0x0D => Some((if a >= 16 { 0 } else { b >> a }, Some(if a >= 32 { 0 } else { (((b as u32) << 16) >> a) as u16 }))),
+            0x0E => Some((((b as i16) >> a.min(15)) as u16, Some(((signed_b << 16) >> a.min(31)) as u16))),
+            0x0F => Some((if a >= 16 { 0 } else { b << a }, Some(if a >= 32 { 0 } else { (((b as u64) << a) >> 16) as u16 }))),
+            0x1A => {
+                let sum = b as u32 + a as u32 + ex as u32;
+                Some((sum as u16, Some((sum > 0xFFFF) as u16)))
+            }
+            0x1B => {
+                let difference = b as i32 - a as i32 + ex as i16 as i32;
+                Some((difference as u16, Some(if difference < 0 { 0xFFFF } else if difference > 0xFFFF { 1 } else { 0 })))
+            }
+            0x1E | 0x1F => Some((a, None)),
+            _ => None,
+        };
+        match result {
+            Some((value, ex)) => {
+                self.write(b_location, value);
+                if let Some(ex) = ex {
+                    self.registers[EX] = ex;
+                }
+                let step = if opcode == 0x1E { 1u16 } else if opcode == 0x1F { 0xFFFF } else { 0 };
+                self.registers[6] = self.registers[6].wrapping_add(step);
+                self.registers[7] = self.registers[7].wrapping_add(step);
+            }
+            None => {
+                if !condition(opcode, b, a) {
+                    self.skip();
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Whether conditional `opcode` runs the next instruction for operands b and a
+fn condition(opcode: u16, b: u16, a: u16) -> bool {
+    match opcode {
+        0x10 => b & a != 0,
+        0x11 => b & a == 0,
+        0x12 => b == a,
+        0x13 => b != a,
+        0x14 => b > a,
+        0x15 => b as i16 > a as i16,
+        0x16 => b < a,
+        0x17 => (b as i16) < a as i16,
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+pub fn test_property_model() {
+    for case in 0..CASES {
+        let mut rng = generator(case);
+        let program: Vec<u16> = (0..PROGRAM_LENGTH).flat_map(|_| instruction(&mut rng)).collect();
+        let (registers_before, memory) = setup(&mut rng, &program);
+        for &cache in [false, true].iter() {
+            let mut model = Model { registers: registers_before, memory: memory.clone(), halted: false };
+            let mut cpu = load(&registers_before, &memory, cache);
+            for index in 0..PROGRAM_LENGTH {
+                let pc = model.registers[PC];
+                if model.halted || !model.step() {
+                    break;
+                }
+                execute(&mut cpu);
+                assert_eq!(registers(&cpu), model.registers, "case {}, instruction {} at {:#06x}, cache {}", case, index, pc, cache);
+                assert_eq!(cpu.is_halted(), model.halted, "case {}, instruction {} at {:#06x}, cache {}", case, index, pc, cache);
+            }
+            let differs = (0..=0xFFFF).find(|&address| cpu.get_memory(address) != model.memory[address as usize]);
+            assert_eq!(differs, None, "case {}, cache {}: memory differs", case, cache);
+        }
+    }
+}
+
+#[test]
+pub fn test_property_pc_advance() {
+    for case in 0..CASES {
+        let mut rng = generator(case);
+        let words = instruction(&mut rng);
+        let (opcode, b) = (words[0] & 0x1F, (words[0] >> 5) & 0x1F);
+        if is_conditional(opcode) || is_unused(opcode) || b == 0x1C {
+            continue;
+        }
+        let (registers_before, memory) = setup(&mut rng, &words);
+        let mut cpu = load(&registers_before, &memory, false);
+        execute(&mut cpu);
+        assert_eq!(cpu.get_pc(), PROGRAM + words.len() as u16, "case {}: {:04x?}", case, words);
+    }
+}
+
+#[test]
+pub fn test_property_literal_writes() {
+    for case in 0..CASES {
+        let mut rng = generator(case);
+        let mut words = instruction(&mut rng);
+        let opcode = words[0] & 0x1F;
+        if is_conditional(opcode) || is_unused(opcode) {
+            continue;
+        }
+        // Rewrite b to a NEXT literal
+        words.truncate(1 + next_words(words[0] >> 10) as usize);
+        words[0] |= 0x1F << 5;
+        words.push(rng.gen());
+        let (registers_before, memory) = setup(&mut rng, &words);
+        let mut cpu = load(&registers_before, &memory, false);
+        execute(&mut cpu);
+        // Only EX, SP for a POP and I and J for STI or STD may change
+        let after = registers(&cpu);
+        let stepped = opcode == 0x1E || opcode == 0x1F;
+        for (index, (&before, &after)) in registers_before.iter().zip(after.iter()).enumerate() {
+            let free = index == EX || index == PC || (index == SP && words[0] >> 10 == 0x18) || (stepped && (index == 6 || index == 7));
+            assert!(free || before == after, "case {}: {:04x?} changed register {}", case, words, index);
+        }
+        let differs = (0..=0xFFFF).find(|&address| cpu.get_memory(address) != memory[address as usize]);
+        assert_eq!(differs, None, "case {}: {:04x?} wrote memory", case, words);
+    }
+}
+
+#[test]
+pub fn test_property_conditional_skip() {
+    for case in 0..CASES {
+        let mut rng = generator(case);
+        // IFx register, register or literal, then a chain of conditionals and one more instruction
+        let (opcode, b, a) = (rng.gen_range(0x10, 0x18), rng.gen_range(0x00, 0x08), rng.gen_range(0x00, 0x40));
+        let mut program = vec![a << 10 | b << 5 | opcode];
+        let a_next = if next_words(a) == 1 { Some(rng.gen::<u16>()) } else { None };
+        program.extend(a_next);
+        let test_length = program.len() as u16;
+        for _ in 0..rng.gen_range(0, 4) {
+            let mut words = instruction(&mut rng);
+            words[0] = (words[0] & !0x1F) | rng.gen_range(0x10, 0x18);
+            words.truncate(1);
+            words.extend((1..length(words[0])).map(|_| rng.gen::<u16>()));
+            program.extend(words);
+        }
+        let mut last = instruction(&mut rng);
+        while is_conditional(last[0] & 0x1F) || is_unused(last[0] & 0x1F) {
+            last = instruction(&mut rng);
+        }
+        program.extend(last);
+        let (registers_before, memory) = setup(&mut rng, &program);
+
+        // a as the model sees it, without side effects of its own on a register b
+        let mut model = Model { registers: registers_before, memory: memory.clone(), halted: false };
+        model.fetch();
+        let a_location = model.locate(a, true);
+        let a_value = model.read(a_location);
+        let b_value = model.read(Location::Register(b as usize));
+        let expected = if condition(opcode, b_value, a_value) { PROGRAM + test_length } else { PROGRAM + program.len() as u16 };
+
+        let mut cpu = load(&registers_before, &memory, false);
+        execute(&mut cpu);
+        assert_eq!(cpu.get_pc(), expected, "case {}: {:04x?}", case, program);
+    }
+}
@@ -0,0 +1,565 @@
+///
+/// Reference Interpreter and Differential Harness
+///
+/// A plain interpreter of the VCPU16 instruction set, written from the
+/// opcode tables and run a whole instruction at a time: no decode cache, no
+/// cycle timing, no protected regions and no hardware. The harness runs a
+/// program on it and on a VCPU16 side by side and compares registers, state
+/// and the memory each instruction wrote after every instruction, reporting
+/// the first place they part ways. Random programs are generated from the
+/// opcodes VCPU16 actually uses rather than those of the DCPU-16 it started
+/// from, with a share of raw words to cover what doesn't decode.
+///
+use rand::Rng;
+use std::collections::VecDeque;
+use std::fmt;
+use vcpu::cpu::{VCPU16, FAULT_INVALID_OPCODE, INTERRUPT_QUEUE_LIMIT};
+use vcpu::disasm::disassemble;
+
+// Register indices, as in `Case::registers`
+const SP: usize = 9;
+const PC: usize = 8;
+const EX: usize = 10;
+const IA: usize = 11;
+const I: usize = 6;
+const J: usize = 7;
+
+/// Names of the registers in `Case::registers` order
+pub const REGISTER_NAMES: [&str; 12] = ["A", "B", "C", "X", "Y", "Z", "I", "J", "PC", "SP", "EX", "IA"];
+
+/// Binary opcodes in use
+const BINARY: [u16; 27] = [
+    0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17,
+    0x1A, 0x1B, 0x1E, 0x1F,
+];
+/// Unary opcodes in use
+const UNARY: [u16; 12] = [0x01, 0x02, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x10, 0x11, 0x12];
+
+/// Words following the instruction word used by an operand
+fn next_words(operand: u16) -> u16 {
+    match operand {
+        0x10..=0x17 | 0x1A | 0x1E | 0x1F => 1,
+        _ => 0,
+    }
+}
+
+///
+/// Starting point of a differential run
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Case {
+    /// Loaded at address 0
+    pub rom: Vec<u16>,
+    /// A, B, C, X, Y, Z, I, J, PC, SP, EX and IA
+    pub registers: [u16; 12],
+    pub fault_vector: u16,
+}
+
+impl Case {
+    ///
+    /// Random program of `length` instructions, mostly valid ones, with
+    /// random registers. Operands are steered towards the program and the
+    /// stack so reads and writes land on words that matter.
+    ///
+    pub fn random<R: Rng>(rng: &mut R, length: usize) -> Case {
+        let mut rom = Vec::with_capacity(length * 3);
+        for _ in 0..length {
+            match rng.gen_range(0, 8) {
+                0 => rom.push(rng.gen()),
+                1 => rom.push(rng.gen_range(0, 2) << 10),
+                2 | 3 => {
+                    let a = rng.gen_range(0x00, 0x40);
+                    rom.push(a << 10 | UNARY[rng.gen_range(0, UNARY.len())] << 5);
+                    rom.extend((0..next_words(a)).map(|_| rng.gen_range(0, 0x40)));
+                }
+                _ => {
+                    let (a, b) = (rng.gen_range(0x00, 0x40), rng.gen_range(0x00, 0x20));
+                    rom.push(a << 10 | b << 5 | BINARY[rng.gen_range(0, BINARY.len())]);
+                    rom.extend((0..next_words(a) + next_words(b)).map(|_| rng.gen_range(0, 0x40)));
+                }
+            }
+        }
+        let mut registers = [0u16; 12];
+        for register in registers.iter_mut().take(8) {
+            *register = if rng.gen() { rng.gen_range(0, 0x40) } else { rng.gen() };
+        }
+        registers[SP] = if rng.gen() { 0 } else { rng.gen() };
+        registers[EX] = if rng.gen() { 0 } else { rng.gen() };
+        registers[IA] = if rng.gen() { 0 } else { rng.gen_range(0, rom.len().max(1) as u16) };
+        let fault_vector = if rng.gen() { 0 } else { rng.gen_range(0, rom.len().max(1) as u16) };
+        Case { rom, registers, fault_vector }
+    }
+    /// VCPU16 set up to run the case.
+    pub fn vcpu(&self) -> VCPU16 {
+        let mut cpu = VCPU16::new();
+        cpu.load_rom(&self.rom).expect("case ROM fits in memory");
+        let r = &self.registers;
+        cpu.set_a(r[0]);
+        cpu.set_b(r[1]);
+        cpu.set_c(r[2]);
+        cpu.set_x(r[3]);
+        cpu.set_y(r[4]);
+        cpu.set_z(r[5]);
+        cpu.set_i(r[6]);
+        cpu.set_j(r[7]);
+        cpu.set_pc(r[PC]);
+        cpu.set_sp(r[SP]);
+        cpu.set_ex(r[EX]);
+        cpu.set_ia(r[IA]);
+        cpu.set_fault_vector(self.fault_vector);
+        cpu
+    }
+    /// Reference interpreter set up to run the case.
+    pub fn reference(&self) -> Reference {
+        let mut memory = vec![0; 65536];
+        memory[..self.rom.len()].copy_from_slice(&self.rom);
+        Reference {
+            registers: self.registers,
+            memory,
+            interrupts: VecDeque::new(),
+            queueing: false,
+            fault_code: 0,
+            fault_vector: self.fault_vector,
+            state: Status::Running,
+            written: Vec::new(),
+        }
+    }
+}
+
+///
+/// Whether a CPU is still running instructions
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Status {
+    Running,
+    Hibernating,
+    Halted,
+}
+
+/// Where an operand reads and writes
+#[derive(Copy, Clone)]
+enum Location {
+    Register(usize),
+    Memory(u16),
+    Literal(u16),
+    None,
+}
+
+///
+/// Reference VCPU16 Interpreter
+///
+/// SLP runs as a NOP, the harness waits out a VCPU16's sleep instead.
+///
+pub struct Reference {
+    registers: [u16; 12],
+    memory: Vec<u16>,
+    interrupts: VecDeque<u16>,
+    queueing: bool,
+    fault_code: u16,
+    fault_vector: u16,
+    state: Status,
+    /// Addresses written by the last instruction
+    written: Vec<u16>,
+}
+
+impl Reference {
+    pub fn registers(&self) -> [u16; 12] { self.registers }
+    pub fn memory(&self, address: u16) -> u16 { self.memory[address as usize] }
+    pub fn status(&self) -> Status { self.state }
+    pub fn fault_code(&self) -> u16 { self.fault_code }
+    pub fn pending_interrupts(&self) -> usize { self.interrupts.len() }
+    /// Addresses written by the last instruction, in order.
+    pub fn written(&self) -> &[u16] { &self.written }
+
+    fn fetch(&mut self) -> u16 {
+        let word = self.memory[self.registers[PC] as usize];
+        self.registers[PC] = self.registers[PC].wrapping_add(1);
+        word
+    }
+    fn store(&mut self, address: u16, value: u16) {
+        self.memory[address as usize] = value;
+        self.written.push(address);
+    }
+    fn push(&mut self, value: u16) {
+        self.registers[SP] = self.registers[SP].wrapping_sub(1);
+        let sp = self.registers[SP];
+        self.store(sp, value);
+    }
+    fn pop(&mut self) -> u16 {
+        let value = self.memory[self.registers[SP] as usize];
+        self.registers[SP] = self.registers[SP].wrapping_add(1);
+        value
+    }
+    /// Location of an operand, reading its NEXT word and moving SP as it goes
+    fn locate(&mut self, operand: u16, is_a: bool) -> Location {
+        match operand {
+            0x00..=0x07 => Location::Register(operand as usize),
+            0x08..=0x0F => Location::Memory(self.registers[operand as usize - 0x08]),
+            0x10..=0x17 => {
+                let offset = self.fetch();
+                Location::Memory(self.registers[operand as usize - 0x10].wrapping_add(offset))
+            }
+            0x18 if is_a => {
+                self.registers[SP] = self.registers[SP].wrapping_add(1);
+                Location::Memory(self.registers[SP].wrapping_sub(1))
+            }
+            0x18 => {
+                self.registers[SP] = self.registers[SP].wrapping_sub(1);
+                Location::Memory(self.registers[SP])
+            }
+            0x19 => Location::Memory(self.registers[SP]),
+            0x1A => {
+                let offset = self.fetch();
+                Location::Memory(self.registers[SP].wrapping_add(offset))
+            }
+            0x1B => Location::Register(SP),
+            0x1C => Location::Register(PC),
+            0x1D => Location::Register(EX),
+            0x1E => Location::Memory(self.fetch()),
+            0x1F => Location::Literal(self.fetch()),
+            0x20..=0x3F if is_a => Location::Literal(operand.wrapping_sub(0x21)),
+            _ => Location::None,
+        }
+    }
+    fn read(&self, location: Location) -> u16 {
+        match location {
+            Location::Register(index) => self.registers[index],
+            Location::Memory(address) => self.memory[address as usize],
+            Location::Literal(value) => value,
+            Location::None => 0,
+        }
+    }
+    fn write(&mut self, location: Location, value: u16) {
+        match location {
+            Location::Register(index) => self.registers[index] = value,
+            Location::Memory(address) => self.store(address, value),
+            Location::Literal(_) | Location::None => {}
+        }
+    }
+    /// Skip the next instruction, and the chain of conditionals it starts
+    fn skip(&mut self) {
+        loop {
+            let word = self.memory[self.registers[PC] as usize];
+            let opcode = word & 0x1F;
+            let b = if opcode == 0 { 0 } else { next_words((word >> 5) & 0x1F) };
+            self.registers[PC] = self.registers[PC].wrapping_add(1 + next_words(word >> 10) + b);
+            if !(0x10..=0x17).contains(&opcode) {
+                break;
+            }
+        }
+    }
+    /// Invalid instruction at `address`
+    fn fault(&mut self, address: u16) {
+        self.fault_code = FAULT_INVALID_OPCODE;
+        if self.fault_vector == 0 {
+            self.state = Status::Halted;
+        } else {
+            self.push(address);
+            self.registers[PC] = self.fault_vector;
+        }
+    }
+    /// Raise an interrupt, dropped while IA is zero or the queue is full.
+    fn interrupt(&mut self, message: u16) {
+        if self.registers[IA] != 0 && self.interrupts.len() < INTERRUPT_QUEUE_LIMIT {
+            self.interrupts.push_back(message);
+        }
+    }
+
+    ///
+    /// Enter a pending interrupt's handler if there is one, then run one
+    /// instruction. Does nothing once hibernating or halted.
+    ///
+    pub fn step(&mut self) {
+        self.written.clear();
+        if self.state != Status::Running {
+            return;
+        }
+        if !self.queueing && self.registers[IA] != 0 {
+            if let Some(message) = self.interrupts.pop_front() {
+                self.queueing = true;
+                let (pc, a) = (self.registers[PC], self.registers[0]);
+                self.push(pc);
+                self.push(a);
+                self.registers[PC] = self.registers[IA];
+                self.registers[0] = message;
+            }
+        }
+        let address = self.registers[PC];
+        let word = self.fetch();
+        if word & 0x03FF == 0 {
+            match word >> 10 {
+                0x00 => {}
+                0x01 => self.state = Status::Hibernating,
+                _ => self.fault(address),
+            }
+        } else if word & 0x001F == 0 {
+            self.unary((word >> 5) & 0x1F, word >> 10, address);
+        } else {
+            self.binary(word & 0x1F, (word >> 5) & 0x1F, word >> 10, address);
+        }
+    }
+
+    fn unary(&mut self, opcode: u16, a: u16, address: u16) {
+        if !UNARY.contains(&opcode) {
+            return self.fault(address);
+        }
+        let location = self.locate(a, true);
+        let value = self.read(location);
+        match opcode {
+            // JSR
+            0x01 => {
+                let pc = self.registers[PC];
+                self.push(pc);
+                self.registers[PC] = value;
+            }
+            // SLP
+            0x02 => {}
+            // INT
+            0x08 => self.interrupt(value),
+            // IAG
+            0x09 => {
+                let ia = self.registers[IA];
+                self.write(location, ia);
+            }
+            // IAS
+            0x0A => self.registers[IA] = value,
+            // RFI
+            0x0B => {
+                self.queueing = false;
+                self.registers[0] = self.pop();
+                self.registers[PC] = self.pop();
+            }
+            // IAQ
+            0x0C => self.queueing = value != 0,
+            // FCG
+            0x0D => {
+                let code = self.fault_code;
+                self.fault_code = 0;
+                self.write(location, code);
+            }
+            // FVS
+            0x0E => self.fault_vector = value,
+            // HWN, no hardware attached
+            0x10 => self.write(location, 0),
+            // HWQ and HWI, of hardware that isn't there
+            _ => {}
+        }
+    }
+
+    fn binary(&mut self, opcode: u16, b: u16, a: u16, address: u16) {
+        if !BINARY.contains(&opcode) {
+            return self.fault(address);
+        }
+        // a is located and read before b
+        let a_location = self.locate(a, true);
+        let a = self.read(a_location);
+        let b_location = self.locate(b, false);
+        let b = self.read(b_location);
+        let ex = self.registers[EX];
+        let (signed_b, signed_a) = (b as i16 as i32, a as i16 as i32);
+        let (value, ex) = match opcode {
+            // SET, STI and STD
+            0x01 | 0x1E | 0x1F => (a, None),
+            // ADD
+            0x02 => (b.wrapping_add(a), Some((b as u32 + a as u32 > 0xFFFF) as u16)),
+            // SUB
+            0x03 => (b.wrapping_sub(a), Some(if a > b { 0xFFFF } else { 0 })),
+            // MUL
+            0x04 => (b.wrapping_mul(a), Some(((b as u32 * a as u32) >> 16) as u16)),
+            // MLI
+            0x05 => ((signed_b * signed_a) as u16, Some(((signed_b * signed_a) >> 16) as u16)),
+            // DIV and DVI by zero
+            0x06 | 0x07 if a == 0 => (0, Some(0)),
+            // DIV
+            0x06 => (b / a, Some((((b as u32) << 16) / a as u32) as u16)),
+            // DVI
+            0x07 => (signed_b.wrapping_div(signed_a) as u16, Some((signed_b << 16).wrapping_div(signed_a) as u16)),
+            // MOD and MDI by zero
+            0x08 | 0x09 if a == 0 => (0, None),
+            // MOD
+            0x08 => (b % a, None),
+            // MDI
+            0x09 => ((signed_b % signed_a) as u16, None),
+            // AND, BOR and XOR
+            0x0A => (b & a, None),
+            0x0B => (b | a, None),
+            0x0C => (b ^ a, None),
+            // SHR
+            0x0D => (if a >= 16 { 0 } else { b >> a }, Some(if a >= 32 { 0 } else { (((b as u32) << 16) >> a) as u16 })),
+            // ASR
+            0x0E => (((b as i16) >> a.min(15)) as u16, Some(((signed_b << 16) >> a.min(31)) as u16)),
+            // SHL
+            0x0F => (if a >= 16 { 0 } else { b << a }, Some(if a >= 32 { 0 } else { (((b as u64) << a) >> 16) as u16 })),
+            // IFB to IFU
+            0x10..=0x17 => {
+                let condition = match opcode {
+                    0x10 => b & a != 0,
+                    0x11 => b & a == 0,
+                    0x12 => b == a,
+                    0x13 => b != a,
+                    0x14 => b > a,
+                    0x15 => signed_b > signed_a,
+                    0x16 => b < a,
+                    _ => signed_b < signed_a,
+                };
+                if !condition {
+                    self.skip();
+                }
+                return;
+            }
+            // ADX
+            0x1A => {
+                let sum = b as u32 + a as u32 + ex as u32;
+                (sum as u16, Some((sum > 0xFFFF) as u16))
+            }
+            // SBX, EX taken as signed
+            _ => {
+                let difference = b as i32 - a as i32 + ex as i16 as i32;
+                (difference as u16, Some(if difference < 0 { 0xFFFF } else if difference > 0xFFFF { 1 } else { 0 }))
+            }
+        };
+        self.write(b_location, value);
+        if let Some(ex) = ex {
+            self.registers[EX] = ex;
+        }
+        let step = match opcode {
+            0x1E => 1,
+            0x1F => 0xFFFF,
+            _ => 0,
+        };
+        self.registers[I] = self.registers[I].wrapping_add(step);
+        self.registers[J] = self.registers[J].wrapping_add(step);
+    }
+}
+
+/// Registers of a VCPU16 in `Case::registers` order
+fn vcpu_registers(cpu: &VCPU16) -> [u16; 12] {
+    [
+        cpu.get_a(), cpu.get_b(), cpu.get_c(), cpu.get_x(), cpu.get_y(), cpu.get_z(), cpu.get_i(), cpu.get_j(),
+        cpu.get_pc(), cpu.get_sp(), cpu.get_ex(), cpu.get_ia(),
+    ]
+}
+
+fn vcpu_status(cpu: &VCPU16) -> Status {
+    if cpu.is_halted() {
+        Status::Halted
+    } else if cpu.is_hibernating() {
+        Status::Hibernating
+    } else {
+        Status::Running
+    }
+}
+
+///
+/// Where a VCPU16 first left the reference
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Divergence {
+    /// Instructions run before the one which diverged
+    pub step: usize,
+    /// Address of that instruction
+    pub address: u16,
+    /// The instruction, disassembled
+    pub instruction: String,
+    /// What differs, such as a register name or `[0x1234]`
+    pub field: String,
+    pub reference: u16,
+    pub vcpu: u16,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "step {} at 0x{:04X} `{}`: {} is 0x{:04X} on the reference, 0x{:04X} on the VCPU16",
+            self.step, self.address, self.instruction, self.field, self.reference, self.vcpu
+        )
+    }
+}
+
+///
+/// Run a case on both for up to `steps` instructions, or until the
+/// reference hibernates or halts. Returns the instructions run, or the
+/// first divergence. Memory as a whole is compared once at the end.
+///
+pub fn compare(case: &Case, steps: usize) -> Result<usize, Divergence> { compare_with(case.vcpu(), case.reference(), steps) }
+
+/// `compare` with a VCPU16 and Reference set up by the caller.
+pub fn compare_with(mut cpu: VCPU16, mut reference: Reference, steps: usize) -> Result<usize, Divergence> {
+    let mut address = reference.registers[PC];
+    let mut instruction = String::new();
+    for step in 0..steps {
+        if reference.status() != Status::Running {
+            return Ok(step);
+        }
+        address = reference.registers[PC];
+        instruction = disassemble(&|address| reference.memory(address), address, None).text;
+        let diverged = |field: String, reference: u16, vcpu: u16| Divergence { step, address, instruction: instruction.clone(), field, reference, vcpu };
+        reference.step();
+        cpu.step();
+        while cpu.is_busy() || cpu.is_sleeping() {
+            cpu.step();
+        }
+        let (expected, actual) = (reference.registers(), vcpu_registers(&cpu));
+        if let Some(index) = (0..12).find(|&index| expected[index] != actual[index]) {
+            return Err(diverged(REGISTER_NAMES[index].to_string(), expected[index], actual[index]));
+        }
+        if reference.status() != vcpu_status(&cpu) {
+            return Err(diverged("status".to_string(), reference.status() as u16, vcpu_status(&cpu) as u16));
+        }
+        if reference.fault_code() != cpu.fault_code() {
+            return Err(diverged("fault code".to_string(), reference.fault_code(), cpu.fault_code()));
+        }
+        if reference.pending_interrupts() != cpu.pending_interrupts() {
+            return Err(diverged("pending interrupts".to_string(), reference.pending_interrupts() as u16, cpu.pending_interrupts() as u16));
+        }
+        if let Some(&written) = reference.written().iter().find(|&&written| reference.memory(written) != cpu.get_memory(written)) {
+            return Err(diverged(format!("[0x{:04X}]", written), reference.memory(written), cpu.get_memory(written)));
+        }
+    }
+    if let Some(word) = (0..=0xFFFF).find(|&word| reference.memory(word) != cpu.get_memory(word)) {
+        return Err(Divergence {
+            step: steps,
+            address,
+            instruction,
+            field: format!("[0x{:04X}]", word),
+            reference: reference.memory(word),
+            vcpu: cpu.get_memory(word),
+        });
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, compare_with, Case, Status};
+    use rand::{SeedableRng, XorShiftRng};
+    use vcpu::cpu::Region;
+
+    #[test]
+    pub fn test_reference_agrees() {
+        let mut rng = XorShiftRng::from_seed([0x2873, 1, 2, 3]);
+        for program in 0..500 {
+            let case = Case::random(&mut rng, 24);
+            if let Err(divergence) = compare(&case, 64) {
+                panic!("program {} diverged, {}\n{:?}", program, divergence, case);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_reference_pinpoints() {
+        // SET A, 1; SET [0x0100], A; ADD A, 2
+        let case = Case { rom: vec![0x8801, 0x03C1, 0x0100, 0x8C02], registers: [0; 12], fault_vector: 0 };
+        assert_eq!(compare(&case, 3), Ok(3));
+        assert_eq!(case.reference().status(), Status::Running);
+
+        // A VCPU16 which refuses the write halts where the reference doesn't
+        let mut cpu = case.vcpu();
+        cpu.protect(Region::read_only(0x0100, 0x0100));
+        let divergence = compare_with(cpu, case.reference(), 3).unwrap_err();
+        assert_eq!((divergence.step, divergence.address), (1, 0x0001));
+        assert_eq!(divergence.instruction, "SET [0x0100], A");
+        assert_eq!(divergence.field, "status");
+    }
+}
@@ -0,0 +1,187 @@
+///
+/// Shared Memory Segments
+///
+/// Blocks of words several CPUs map into their address spaces, a blackboard
+/// for a hive to coordinate through without the round trips of a radio.
+/// Segments change once per tick: every CPU starts the tick seeing the
+/// segment as the last tick left it, and the words each one writes are
+/// merged after they have all run. Each word is written whole, and words
+/// written by more than one CPU in a tick are settled by the segment's
+/// ConflictPolicy, in the order the CPUs ran.
+///
+use std::collections::BTreeMap;
+use vcpu::cpu::VCPU16;
+
+///
+/// How words written by several CPUs in one tick are settled
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ConflictPolicy {
+    /// The first CPU to run keeps its write
+    FirstWins,
+    /// The last CPU to run keeps its write
+    LastWins,
+    /// Every CPU's change to the word is added up, so counters can be shared
+    Accumulate,
+}
+
+///
+/// Shared Memory Segment Identifier
+///
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct SegmentId(usize);
+
+impl SegmentId {
+    pub fn index(&self) -> usize { self.0 }
+}
+
+///
+/// Segment mapped into a CPU's memory
+///
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Mapping {
+    pub segment: SegmentId,
+    /// Address of the segment's first word
+    pub address: u16,
+}
+
+#[derive(Clone)]
+struct Segment {
+    words: Vec<u16>,
+    policy: ConflictPolicy,
+    /// Writes of this tick by offset: the word, or the change for Accumulate
+    writes: BTreeMap<usize, u16>,
+}
+
+///
+/// Every Segment of a cluster
+///
+#[derive(Clone, Default)]
+pub struct SharedMemory {
+    segments: Vec<Segment>,
+}
+
+impl SharedMemory {
+    pub fn new() -> SharedMemory { SharedMemory::default() }
+    /// New segment of `length` zeroed words.
+    pub fn create(&mut self, length: u16, policy: ConflictPolicy) -> SegmentId {
+        self.segments.push(Segment { words: vec![0; length as usize], policy, writes: BTreeMap::new() });
+        SegmentId(self.segments.len() - 1)
+    }
+    pub fn len(&self) -> usize { self.segments.len() }
+    pub fn is_empty(&self) -> bool { self.segments.is_empty() }
+    /// Words of a segment as of the last commit.
+    pub fn words(&self, id: SegmentId) -> Option<&[u16]> { self.segments.get(id.0).map(|segment| &segment.words[..]) }
+    pub fn policy(&self, id: SegmentId) -> Option<ConflictPolicy> { self.segments.get(id.0).map(|segment| segment.policy) }
+    /// Write a word from the host, seen by CPUs from their next tick. Returns false if it is out of range.
+    pub fn set(&mut self, id: SegmentId, offset: u16, value: u16) -> bool {
+        match self.segments.get_mut(id.0).and_then(|segment| segment.words.get_mut(offset as usize)) {
+            Some(word) => {
+                *word = value;
+                true
+            }
+            None => false,
+        }
+    }
+    ///
+    /// Whether `mapping` fits in the address space without overlapping any
+    /// of `others`.
+    ///
+    pub fn fits(&self, mapping: Mapping, others: &[Mapping]) -> bool {
+        let end = |mapping: &Mapping| self.segments.get(mapping.segment.0).map(|segment| mapping.address as usize + segment.words.len());
+        match end(&mapping) {
+            Some(stop) if stop <= 0x10000 => others.iter().all(|other| end(other).is_some_and(|other_stop| stop <= other.address as usize || other_stop <= mapping.address as usize)),
+            _ => false,
+        }
+    }
+    ///
+    /// Copy the mapped segments into `cpu`'s memory before it runs. Only
+    /// words which differ are written, so pages the CPU shares with its
+    /// Firmware or a fork stay shared and its decode cache stays warm.
+    ///
+    pub fn load(&self, cpu: &mut VCPU16, mappings: &[Mapping]) {
+        for mapping in mappings {
+            for (offset, &word) in self.segments[mapping.segment.0].words.iter().enumerate() {
+                let address = mapping.address.wrapping_add(offset as u16);
+                if cpu.get_memory(address) != word {
+                    cpu.set_memory(address, word);
+                }
+            }
+        }
+    }
+    /// Note the words `cpu` changed in its mapped segments after it ran.
+    pub fn record(&mut self, cpu: &VCPU16, mappings: &[Mapping]) {
+        for mapping in mappings {
+            let segment = &mut self.segments[mapping.segment.0];
+            for (offset, &old) in segment.words.iter().enumerate() {
+                let new = cpu.get_memory(mapping.address.wrapping_add(offset as u16));
+                if new == old {
+                    continue;
+                }
+                match segment.policy {
+                    ConflictPolicy::FirstWins => {
+                        segment.writes.entry(offset).or_insert(new);
+                    }
+                    ConflictPolicy::LastWins => {
+                        segment.writes.insert(offset, new);
+                    }
+                    ConflictPolicy::Accumulate => {
+                        let change = segment.writes.entry(offset).or_insert(0);
+                        *change = change.wrapping_add(new.wrapping_sub(old));
+                    }
+                }
+            }
+        }
+    }
+    /// Apply the writes recorded this tick.
+    pub fn commit(&mut self) {
+        for segment in self.segments.iter_mut() {
+            let policy = segment.policy;
+            for (offset, value) in std::mem::take(&mut segment.writes) {
+                let word = &mut segment.words[offset];
+                *word = if policy == ConflictPolicy::Accumulate { word.wrapping_add(value) } else { value };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConflictPolicy, Mapping, SharedMemory};
+    use vcpu::cpu::VCPU16;
+
+    #[test]
+    pub fn test_conflicts() {
+        let mut shared = SharedMemory::new();
+        let first = shared.create(2, ConflictPolicy::FirstWins);
+        let last = shared.create(2, ConflictPolicy::LastWins);
+        let total = shared.create(1, ConflictPolicy::Accumulate);
+        let mappings = [Mapping { segment: first, address: 0x100 }, Mapping { segment: last, address: 0x200 }, Mapping { segment: total, address: 0x300 }];
+        assert!(shared.set(total, 0, 10));
+        assert!(!shared.set(total, 1, 10));
+
+        let mut cpus = [VCPU16::new(), VCPU16::new()];
+        for (index, cpu) in cpus.iter_mut().enumerate() {
+            shared.load(cpu, &mappings);
+            assert_eq!(cpu.get_memory(0x300), 10);
+            cpu.set_memory(0x100, index as u16 + 1);
+            cpu.set_memory(0x200, index as u16 + 1);
+            cpu.set_memory(0x300, 10 + index as u16 + 1);
+        }
+        // Each CPU writes on its own copy until the tick is over
+        cpus[1].set_memory(0x101, 7);
+        for cpu in cpus.iter() {
+            shared.record(cpu, &mappings);
+        }
+        assert_eq!(shared.words(first), Some(&[0, 0][..]));
+        shared.commit();
+        assert_eq!(shared.words(first), Some(&[1, 7][..]));
+        assert_eq!(shared.words(last), Some(&[2, 0][..]));
+        assert_eq!(shared.words(total), Some(&[13][..]));
+
+        // Mappings must fit in memory and apart from each other
+        assert!(shared.fits(Mapping { segment: first, address: 0xFFFE }, &mappings[1..]));
+        assert!(!shared.fits(Mapping { segment: first, address: 0xFFFF }, &[]));
+        assert!(!shared.fits(Mapping { segment: first, address: 0x1FF }, &mappings[1..]));
+    }
+}
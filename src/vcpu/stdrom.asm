@@ -0,0 +1,180 @@
+; Hivemind Standard Firmware Library
+;
+; Routines are called with JSR, take their arguments in A, B and C and
+; return in A unless noted. Registers other than those listed as clobbered
+; are preserved. The interrupt table and pheromone scratch space live in the
+; .data section, so sniff isn't safe to call from an interrupt handler.
+
+        .global memcpy, memset, itoa
+        .global int_init, int_set
+        .global hw_find, world_init, smell, mark, sniff
+
+; Copy C words from B to A. Clobbers C.
+memcpy: IFE C, 0
+            SET PC, POP
+        SET PUSH, I
+        SET PUSH, J
+        SET I, A
+        SET J, B
+        ADD C, A
+memcpy_loop:
+        STI [I], [J]
+        IFN I, C
+            SET PC, memcpy_loop
+        SET J, POP
+        SET I, POP
+        SET PC, POP
+
+; Set C words from A to B. Clobbers A, C.
+memset: IFE C, 0
+            SET PC, POP
+        ADD C, A
+memset_loop:
+        SET [A], B
+        ADD A, 1
+        IFN A, C
+            SET PC, memset_loop
+        SET PC, POP
+
+; Write A in decimal to B, one character per word. Returns the number of
+; characters in A. Clobbers B, EX.
+itoa:   SET PUSH, X
+        SET PUSH, Y
+        SET X, 0
+itoa_digit:
+        SET Y, A
+        MOD Y, 10
+        ADD Y, '0'
+        SET PUSH, Y
+        ADD X, 1
+        DIV A, 10
+        IFN A, 0
+            SET PC, itoa_digit
+        SET A, X
+itoa_store:
+        SET [B], POP
+        ADD B, 1
+        SUB X, 1
+        IFN X, 0
+            SET PC, itoa_store
+        SET Y, POP
+        SET X, POP
+        SET PC, POP
+
+; Route interrupts through the handler table. A handler is called with JSR
+; and the message in A, messages without one are dropped.
+int_init:
+        IAS int_dispatch
+        SET PC, POP
+
+; Handle interrupt message A, 0 to 15, with the routine at B, 0 for none.
+int_set:
+        IFG A, 15
+            SET PC, POP
+        SET [int_handlers + A], B
+        SET PC, POP
+
+int_dispatch:
+        IFG A, 15
+            RFI 0
+        IFE [int_handlers + A], 0
+            RFI 0
+        JSR [int_handlers + A]
+        RFI 0
+
+; Index of the device whose id is A (low word) and B (high word), 0xFFFF
+; if there is none. Clobbers B, C, X, Y.
+hw_find:
+        SET PUSH, I
+        SET PUSH, J
+        SET PUSH, Z
+        SET Z, A
+        SET J, B
+        HWN I
+hw_find_next:
+        IFE I, 0
+            SET PC, hw_find_none
+        SUB I, 1
+        HWQ I
+        IFE A, Z
+            IFE B, J
+                SET PC, hw_find_found
+        SET PC, hw_find_next
+hw_find_none:
+        SET I, 0xFFFF
+hw_find_found:
+        SET A, I
+        SET Z, POP
+        SET J, POP
+        SET I, POP
+        SET PC, POP
+
+; Find the World Interface for the pheromone routines, returning its index
+; as hw_find does. Clobbers B, C, X, Y.
+world_init:
+        SET A, 0x4946
+        SET B, 0x4857
+        JSR hw_find
+        SET [world_device], A
+        SET PC, POP
+
+; Strength of pheromone channel B at offset X, Y, Z returned in B, with the
+; World Interface's status in C. Clobbers A.
+smell:  SET A, 6
+        HWI [world_device]
+        SET PC, POP
+
+; Deposit I strength of pheromone channel B at offset X, Y, Z, with the
+; status in C. Clobbers A.
+mark:   SET A, 7
+        HWI [world_device]
+        SET PC, POP
+
+; Offset of the strongest of the six Blocks beside the host for pheromone
+; channel B, returned in X, Y, Z with its strength in B, 0 if none of them
+; has any. Clobbers A, C.
+sniff:  SET PUSH, I
+        SET PUSH, J
+        SET I, sniff_offsets
+        SET J, B
+        SET A, sniff_best
+        SET B, 0
+        SET C, 4
+        JSR memset
+sniff_next:
+        SET X, [I]
+        SET Y, [I + 1]
+        SET Z, [I + 2]
+        SET B, J
+        JSR smell
+        IFN C, 0
+            SET PC, sniff_skip
+        IFG B, [sniff_best]
+            JSR sniff_keep
+sniff_skip:
+        ADD I, 3
+        IFN I, sniff_offsets + 18
+            SET PC, sniff_next
+        SET B, [sniff_best]
+        SET X, [sniff_best + 1]
+        SET Y, [sniff_best + 2]
+        SET Z, [sniff_best + 3]
+        SET J, POP
+        SET I, POP
+        SET PC, POP
+sniff_keep:
+        SET [sniff_best], B
+        SET [sniff_best + 1], X
+        SET [sniff_best + 2], Y
+        SET [sniff_best + 3], Z
+        SET PC, POP
+
+        .section .data
+int_handlers:
+        DAT 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+world_device:
+        DAT 0xFFFF
+sniff_offsets:
+        DAT 1, 0, 0, -1, 0, 0, 0, 1, 0, 0, -1, 0, 0, 0, 1, 0, 0, -1
+sniff_best:
+        DAT 0, 0, 0, 0
@@ -0,0 +1,187 @@
+///
+/// Standard Firmware Library
+///
+/// Routines most hive programs need, shipped as assembly source and linked
+/// at a fixed address at the top of memory so firmware can call them without
+/// carrying its own copies: memcpy, memset and itoa, an interrupt dispatcher
+/// with a table of handlers, device discovery by id, and wrappers for the
+/// World Interface's pheromone commands. `stdrom.asm` documents how each
+/// routine is called.
+///
+/// Programs declare the routines they use with `.extern` and are linked
+/// against the library with `link`. Loading the library with `load` leaves
+/// the rest of memory alone, so it can go in after any image.
+///
+use std::collections::BTreeMap;
+use vcpu::asm::{assemble_object, Assembly};
+use vcpu::cpu::VCPU16;
+use vcpu::link::{self, LinkError, Object};
+
+/// Source of the library
+pub const SOURCE: &str = include_str!("stdrom.asm");
+/// Address the library is linked at
+pub const BASE: u16 = 0xF000;
+
+/// The library assembled, ready to link.
+pub fn object() -> Object { assemble_object("stdrom", SOURCE).expect("stdrom.asm assembles") }
+
+/// The library linked at BASE on its own.
+pub fn assembly() -> Assembly { link::link(&[object()], BASE).expect("stdrom links") }
+
+///
+/// Write the library into `cpu`'s memory at BASE, leaving its registers and
+/// the memory below BASE as they are.
+///
+pub fn load(cpu: &mut VCPU16) {
+    for segment in assembly().image.segments.iter() {
+        for (offset, &word) in segment.words.iter().enumerate() {
+            cpu.set_memory(segment.address.wrapping_add(offset as u16), word);
+        }
+    }
+}
+
+///
+/// Link `objects` at `base` against the library, giving one image holding
+/// both. The program must end before BASE.
+///
+pub fn link(objects: &[Object], base: u16) -> Result<Assembly, LinkError> {
+    let library = assembly();
+    let globals: BTreeMap<String, u16> = object()
+        .symbols
+        .iter()
+        .filter(|(_, symbol)| symbol.global)
+        .filter_map(|(name, _)| library.map.symbol(name).map(|address| (name.clone(), address)))
+        .collect();
+    let mut program = link::link_with(objects, base, &globals)?;
+    let end = program.image.segments.iter().map(|segment| segment.address as usize + segment.words.len()).max().unwrap_or(0);
+    if end > BASE as usize {
+        return Err(LinkError::Overlap(BASE));
+    }
+    program.image.segments.extend(library.image.segments);
+    for (name, address) in library.map.symbols {
+        program.map.symbols.entry(name).or_insert(address);
+    }
+    program.image.symbols = program.map.symbols.clone();
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{link, load, object, BASE};
+    use model::component::Position;
+    use model::entity::EntityManager;
+    use model::pheromone::Channel;
+    use model::world::{Chunk, Vector2, World};
+    use pool::Poolable;
+    use vcpu::asm::assemble_object;
+    use vcpu::cluster::HiveCluster;
+    use vcpu::cpu::VCPU16;
+    use vcpu::link::LinkError;
+
+    const MAIN: &str = "
+        .extern memcpy, memset, itoa, int_init, int_set, hw_find
+        .entry start
+        start:  SET A, copy
+                SET B, source
+                SET C, 3
+                JSR memcpy
+                SET A, fill
+                SET B, 9
+                SET C, 2
+                JSR memset
+                SET A, 40213
+                SET B, text
+                JSR itoa
+                SET [length], A
+                JSR int_init
+                SET A, 5
+                SET B, handler
+                JSR int_set
+                INT 5
+                SET A, 0x4946
+                SET B, 0x4857
+                JSR hw_find
+                SET [found], A
+                HIB
+        handler: SET [seen], A
+                SET PC, POP
+        .section .data
+        source: DAT 1, 2, 3
+        copy:   DAT 0, 0, 0
+        fill:   DAT 0, 0, 0
+        text:   DAT 0, 0, 0, 0, 0, 0
+        length: DAT 0
+        seen:   DAT 0
+        found:  DAT 0
+    ";
+
+    #[test]
+    pub fn test_library() {
+        let main = assemble_object("main", MAIN).unwrap();
+        let program = link(std::slice::from_ref(&main), 0).unwrap();
+        assert_eq!(program.map.symbol("memcpy"), Some(BASE));
+        let mut vcpu = VCPU16::new();
+        vcpu.load_image(&program.image).unwrap();
+        vcpu.run(2000);
+        assert!(vcpu.is_hibernating());
+
+        let read = |name: &str, count: u16| {
+            let address = program.map.symbol(&format!("main.{}", name)).unwrap();
+            (address..address + count).map(|address| vcpu.get_memory(address)).collect::<Vec<u16>>()
+        };
+        assert_eq!(read("copy", 3), vec![1, 2, 3]);
+        assert_eq!(read("fill", 3), vec![9, 9, 0]);
+        assert_eq!(String::from_utf16(&read("text", 5)).unwrap(), "40213");
+        assert_eq!(read("length", 1), vec![5]);
+        assert_eq!(read("seen", 1), vec![5]);
+        assert_eq!(read("found", 1), vec![0xFFFF]);
+
+        // Loading the library alone leaves the program and registers alone
+        let mut other = VCPU16::new();
+        other.set_memory(0, 0x1234);
+        load(&mut other);
+        assert_eq!((other.get_memory(0), other.get_memory(BASE), other.get_pc()), (0x1234, vcpu.get_memory(BASE), 0));
+
+        assert_eq!(link(&[main.clone(), object()], 0).unwrap_err(), LinkError::Duplicate("hw_find".to_string()));
+        assert_eq!(link(&[main], BASE - 10).unwrap_err(), LinkError::Overlap(BASE));
+    }
+
+    #[test]
+    pub fn test_sniff() {
+        let mut world = World::new();
+        world.insert_chunk(Vector2::new(0, 0), Chunk::allocate());
+        let trail = world.pheromones_mut().register(Channel::new("trail", 0.1, 0.0));
+        world.pheromones_mut().set(trail, 5, 3, 5, 12.0);
+        world.pheromones_mut().set(trail, 5, 2, 4, 30.0);
+        let mut entities = EntityManager::new();
+        let drone = entities.create_entity();
+        entities.add_component(drone, Position::from_f64(5.5, 2.0, 5.5));
+
+        let source = format!(
+            "
+            .extern world_init, sniff
+            JSR world_init
+            SET B, {}
+            JSR sniff
+            HIB
+        ",
+            trail.id()
+        );
+        let program = link(&[assemble_object("main", &source).unwrap()], 0).unwrap();
+        let mut cluster = HiveCluster::new();
+        let cpu = cluster.attach(&mut entities, drone, &program.image.to_rom()).unwrap();
+        for _ in 0..20 {
+            cluster.tick(&mut world, &mut entities);
+        }
+        let state = cluster.get(cpu).unwrap();
+        assert!(state.is_hibernating());
+        assert_eq!((state.get_b(), state.get_x(), state.get_y(), state.get_z()), (30, 0, 0, 0xFFFF));
+        assert_eq!(state.get_memory(program.map.symbol("stdrom.world_device").unwrap()), 0);
+
+        // Without a World Interface the routines find no device
+        let mut vcpu = VCPU16::new();
+        vcpu.load_image(&program.image).unwrap();
+        vcpu.run(2000);
+        assert_eq!(vcpu.get_memory(program.map.symbol("stdrom.world_device").unwrap()), 0xFFFF);
+    }
+}
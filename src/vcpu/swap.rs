@@ -0,0 +1,72 @@
+///
+/// CPU Memory Swap
+///
+/// Backing stores for the memory of CPUs a HiveCluster has paged out. A CPU
+/// held for long enough, off, paused or without power, hands the pages it
+/// holds alone to the cluster's SwapStore and keeps only those it shares,
+/// so a world of mostly idle drones costs little more than their firmware.
+/// Its pages come back as it wakes. Swapped pages only live as long as the
+/// cluster, they aren't part of a save.
+///
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use vcpu::cluster::CpuId;
+
+///
+/// Store of paged out CPU memory, by CPU
+///
+pub trait SwapStore {
+    /// Keep the pages of `cpu`, replacing any kept before.
+    fn store(&mut self, cpu: CpuId, pages: &[u8]) -> io::Result<()>;
+    /// Pages kept for `cpu`.
+    fn load(&self, cpu: CpuId) -> io::Result<Vec<u8>>;
+    /// Forget the pages of `cpu`, if any.
+    fn discard(&mut self, cpu: CpuId);
+}
+
+///
+/// Swap File per CPU in a Directory
+///
+/// Files left behind are removed when the store is dropped.
+///
+pub struct DirectorySwap {
+    directory: PathBuf,
+    files: HashSet<CpuId>,
+}
+
+impl DirectorySwap {
+    /// Swap into `directory`, creating it if needed.
+    pub fn open<P: AsRef<Path>>(directory: P) -> io::Result<DirectorySwap> {
+        fs::create_dir_all(directory.as_ref())?;
+        Ok(DirectorySwap { directory: directory.as_ref().to_path_buf(), files: HashSet::new() })
+    }
+    pub fn directory(&self) -> &Path { &self.directory }
+    /// Number of CPUs with pages in the store.
+    pub fn len(&self) -> usize { self.files.len() }
+    pub fn is_empty(&self) -> bool { self.files.is_empty() }
+    fn path(&self, cpu: CpuId) -> PathBuf { self.directory.join(format!("cpu-{}-{}.swap", cpu.slot(), cpu.generation())) }
+}
+
+impl SwapStore for DirectorySwap {
+    fn store(&mut self, cpu: CpuId, pages: &[u8]) -> io::Result<()> {
+        fs::write(self.path(cpu), pages)?;
+        self.files.insert(cpu);
+        Ok(())
+    }
+    fn load(&self, cpu: CpuId) -> io::Result<Vec<u8>> { fs::read(self.path(cpu)) }
+    fn discard(&mut self, cpu: CpuId) {
+        if self.files.remove(&cpu) {
+            let _ = fs::remove_file(self.path(cpu));
+        }
+    }
+}
+
+impl Drop for DirectorySwap {
+    fn drop(&mut self) {
+        for cpu in self.files.iter() {
+            let _ = fs::remove_file(self.path(*cpu));
+        }
+    }
+}
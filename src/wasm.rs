@@ -0,0 +1,232 @@
+//!
+//! Browser Playground Bindings
+//!
+//! The surface a browser firmware playground is built on, enabled by the
+//! `wasm` feature. Everything crosses the boundary as numbers, strings and
+//! word arrays, which JavaScript sees as Uint16Arrays, and errors as their
+//! message. Entities are handed out as indices into the Sandbox's drones.
+//!
+//! A Playground is a lone VCPU16 with devices whose HWI is answered by a
+//! callback: it is given the registers A to J, may change them, and returns
+//! the cycles to stall. A Sandbox is a minimal Simulation with a single
+//! Chunk of flat world for drones to run in.
+//!
+use devices::{Bus, Device, DeviceInfo};
+use math::Fixed;
+use model::component::Position;
+use model::entity::{EntityID, EntityManager};
+use model::material::MaterialId;
+use model::world::{Block, Chunk, Vector2, World, CHUNK_SIZE};
+use simulation::Simulation;
+use vcpu::asm::assemble;
+use vcpu::cluster::CpuComponent;
+use vcpu::cpu::VCPU16;
+use vcpu::disasm::disassemble_range;
+
+/// HWI callback, given A to J and returning the cycles to stall
+pub type Callback = Box<dyn FnMut(&mut [u16; 8]) -> u16>;
+
+///
+/// Device answering HWI with a Callback
+///
+pub struct CallbackDevice {
+    info: DeviceInfo,
+    callback: Callback,
+}
+
+impl CallbackDevice {
+    pub fn new(info: DeviceInfo, callback: Callback) -> CallbackDevice { CallbackDevice { info, callback } }
+}
+
+impl Device for CallbackDevice {
+    fn info(&self) -> DeviceInfo { self.info }
+    fn interrupt(&mut self, cpu: &mut VCPU16) -> u16 {
+        let mut registers = [cpu.get_a(), cpu.get_b(), cpu.get_c(), cpu.get_x(), cpu.get_y(), cpu.get_z(), cpu.get_i(), cpu.get_j()];
+        let stall = (self.callback)(&mut registers);
+        cpu.set_a(registers[0]);
+        cpu.set_b(registers[1]);
+        cpu.set_c(registers[2]);
+        cpu.set_x(registers[3]);
+        cpu.set_y(registers[4]);
+        cpu.set_z(registers[5]);
+        cpu.set_i(registers[6]);
+        cpu.set_j(registers[7]);
+        stall
+    }
+}
+
+///
+/// Lone VCPU16 with its devices
+///
+#[derive(Default)]
+pub struct Playground {
+    cpu: VCPU16,
+    devices: Vec<Box<dyn Device>>,
+}
+
+impl Playground {
+    pub fn new() -> Playground { Playground::default() }
+    ///
+    /// Assemble `source` and load it after a reset, returning the words
+    /// loaded. Memory is cleared, devices stay attached.
+    ///
+    pub fn assemble(&mut self, source: &str) -> Result<usize, String> {
+        let assembly = assemble(source).map_err(|error| error.to_string())?;
+        self.cpu.reset(true);
+        self.cpu.load_image(&assembly.image).map_err(|error| error.to_string())?;
+        Ok(assembly.image.segments.iter().map(|segment| segment.words.len()).sum())
+    }
+    /// Reset and load `rom` at address 0.
+    pub fn load(&mut self, rom: &[u16]) -> Result<(), String> {
+        self.cpu.reset(true);
+        self.cpu.load_rom(rom).map_err(|error| error.to_string())
+    }
+    /// `length` words of memory from `address`, wrapping past the end.
+    pub fn memory(&self, address: u16, length: usize) -> Vec<u16> {
+        (0..length).map(|offset| self.cpu.get_memory(address.wrapping_add(offset as u16))).collect()
+    }
+    pub fn write_memory(&mut self, address: u16, words: &[u16]) {
+        for (offset, &word) in words.iter().enumerate() {
+            self.cpu.set_memory(address.wrapping_add(offset as u16), word);
+        }
+    }
+    /// A, B, C, X, Y, Z, I, J, PC, SP, EX and IA.
+    pub fn registers(&self) -> Vec<u16> {
+        let cpu = &self.cpu;
+        vec![
+            cpu.get_a(), cpu.get_b(), cpu.get_c(), cpu.get_x(), cpu.get_y(), cpu.get_z(), cpu.get_i(), cpu.get_j(),
+            cpu.get_pc(), cpu.get_sp(), cpu.get_ex(), cpu.get_ia(),
+        ]
+    }
+    /// Set a register by its index in `registers`, false if there is none.
+    pub fn set_register(&mut self, index: usize, value: u16) -> bool {
+        let setters: [fn(&mut VCPU16, u16); 12] = [
+            VCPU16::set_a, VCPU16::set_b, VCPU16::set_c, VCPU16::set_x, VCPU16::set_y, VCPU16::set_z, VCPU16::set_i, VCPU16::set_j,
+            VCPU16::set_pc, VCPU16::set_sp, VCPU16::set_ex, VCPU16::set_ia,
+        ];
+        match setters.get(index) {
+            Some(set) => {
+                set(&mut self.cpu, value);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Attach a device answering HWI with `callback`, returning its index.
+    pub fn add_device(&mut self, id: u32, version: u16, manufacturer: u32, callback: Callback) -> u16 {
+        self.devices.push(Box::new(CallbackDevice::new(DeviceInfo { id, version, manufacturer }, callback)));
+        self.devices.len() as u16 - 1
+    }
+    /// Raise an interrupt, as a device would.
+    pub fn interrupt(&mut self, message: u16) { self.cpu.interrupt(message) }
+    pub fn step(&mut self) { self.cpu.step_with(&mut self.devices) }
+    pub fn run(&mut self, cycles: u32) { self.cpu.run_with(cycles as u64, &mut self.devices) }
+    pub fn is_halted(&self) -> bool { self.cpu.is_halted() }
+    /// `count` instructions from `address` as assembly text.
+    pub fn disassemble(&self, address: u16, count: usize) -> Vec<String> {
+        disassemble_range(&|address| self.cpu.get_memory(address), address, count, None).into_iter().map(|line| line.text).collect()
+    }
+    pub fn cpu(&self) -> &VCPU16 { &self.cpu }
+    pub fn cpu_mut(&mut self) -> &mut VCPU16 { &mut self.cpu }
+}
+
+///
+/// Minimal Simulation: one flat Chunk and the drones running in it
+///
+pub struct Sandbox {
+    simulation: Simulation,
+    drones: Vec<EntityID>,
+}
+
+impl Sandbox {
+    /// Chunk (0, 0) with its bottom layer of `floor`, by material name.
+    pub fn new(floor: &str) -> Result<Sandbox, String> {
+        let mut world = World::new();
+        let material = world.materials().id(floor).ok_or_else(|| format!("unknown material {}", floor))?;
+        world.insert_chunk(Vector2::new(0, 0), Box::new(Chunk::new()));
+        for x in 0..CHUNK_SIZE as u64 {
+            for z in 0..CHUNK_SIZE as u64 {
+                world.set_block(x, 0, z, Block::new(material));
+            }
+        }
+        Ok(Sandbox { simulation: Simulation::new(world, EntityManager::new()), drones: Vec::new() })
+    }
+    /// Place a drone running `rom`, returning its index.
+    pub fn spawn(&mut self, x: f64, y: f64, z: f64, rom: &[u16]) -> Result<usize, String> {
+        let entity = self.simulation.entities_mut().create_entity();
+        self.simulation.entities_mut().add_component(entity, Position::from_f64(x, y, z));
+        if let Err(error) = self.simulation.attach(entity, rom) {
+            self.simulation.entities_mut().destroy_entity(entity);
+            return Err(error.to_string());
+        }
+        self.drones.push(entity);
+        Ok(self.drones.len() - 1)
+    }
+    pub fn drones(&self) -> usize { self.drones.len() }
+    /// x, y and z of a drone, empty if it has no position.
+    pub fn position(&self, drone: usize) -> Vec<f64> {
+        let position = self.drones.get(drone).and_then(|&entity| self.simulation.entities().get_component::<Position>(entity));
+        position.map_or_else(Vec::new, |position| [position.x, position.y, position.z].iter().map(|value: &Fixed| value.to_f64()).collect())
+    }
+    /// Material id of a block, None outside the Chunk.
+    pub fn block(&self, x: u64, y: usize, z: u64) -> Option<u16> { self.simulation.world().get_block(x, y, z).map(|block| block.material().id()) }
+    pub fn set_block(&mut self, x: u64, y: usize, z: u64, material: u16) -> bool {
+        self.simulation.world_mut().set_block(x, y, z, Block::new(MaterialId::new(material)))
+    }
+    /// Run `ticks` Simulation ticks, returning the tick reached.
+    pub fn step(&mut self, ticks: u32) -> u64 {
+        for _ in 0..ticks {
+            self.simulation.step();
+        }
+        self.simulation.tick()
+    }
+    /// Call `f` with a drone's CPU and its bus.
+    pub fn with_cpu<R>(&mut self, drone: usize, f: impl FnOnce(&mut VCPU16, &mut dyn Bus) -> R) -> Option<R> {
+        let entity = *self.drones.get(drone)?;
+        let id = self.simulation.entities().get_component::<CpuComponent>(entity)?.cpu;
+        self.simulation.with_cpu(id, f)
+    }
+    pub fn simulation(&self) -> &Simulation { &self.simulation }
+    pub fn simulation_mut(&mut self) -> &mut Simulation { &mut self.simulation }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Playground, Sandbox};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    pub fn test_wasm_playground() {
+        let mut playground = Playground::new();
+        assert!(playground.assemble("FOO A").unwrap_err().starts_with("line 1"));
+        assert_eq!(playground.assemble("SET A, 3\nHWI 0\nSET B, A\n:end SET PC, end").unwrap(), 5);
+
+        // The callback sees and changes registers
+        let calls = Rc::new(Cell::new(0));
+        let seen = calls.clone();
+        assert_eq!(playground.add_device(0x1234, 1, 0, Box::new(move |registers| {
+            seen.set(seen.get() + 1);
+            registers[0] *= 2;
+            0
+        })), 0);
+        playground.run(20);
+        assert_eq!(calls.get(), 1);
+        assert_eq!(&playground.registers()[..2], &[6, 6]);
+        assert_eq!(playground.disassemble(0, 2), vec!["SET A, 3", "HWI 0"]);
+
+        playground.write_memory(0xFFFF, &[7, 8]);
+        assert_eq!(playground.memory(0xFFFF, 2), vec![7, 8]);
+        assert!(playground.set_register(11, 0x40) && !playground.set_register(12, 0));
+        assert_eq!(playground.registers()[11], 0x40);
+
+        // Drones run in the sandbox
+        let mut sandbox = Sandbox::new("rock").unwrap();
+        assert!(Sandbox::new("cheese").is_err());
+        let drone = sandbox.spawn(1.5, 1.0, 1.5, &[0x8401, 0x8802, 0x8B81]).unwrap();
+        assert_eq!(sandbox.step(3), 3);
+        assert!(sandbox.with_cpu(drone, |cpu, _| cpu.get_a()).unwrap() > 0);
+        assert_eq!(sandbox.position(drone).len(), 3);
+        assert!(sandbox.block(0, 0, 0).unwrap() != 0 && sandbox.block(0, 1, 0) == Some(0));
+    }
+}